@@ -0,0 +1,567 @@
+// cargo bench [-- <filter>]: instruction decoding per encoding, deposit/withdraw against states
+// of growing size, whole process_instruction calls, the state's map lookups and balance account
+// updates, all on host accounts with the runtime syscalls stubbed as in tests/processor.rs
+// criterion isn't a dependency, each benchmark runs batches of calls for about SAMPLE_TIME and
+// reports the min, median and max time per call over the batches
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hello_world::ContractInstruction::{
+    AdminAddSupportedToken, AdminSetTokenFlags, BatchDeposit, GetBalance, InitializeConfig,
+    InitializeState, ListSupportedTokens, Multicall, UserDeposit, UserTransfer, UserWithdraw,
+};
+use hello_world::{
+    deserialize_instruction, find_balance_address, find_receipts_address, find_vault_address,
+    find_vault_authority, load_state, process_instruction, BalanceAccount, BalanceAccountData,
+    ContractInstruction, ContractState, RateBuckets, ReceiptsHeader, TokenMetadata, TokenType,
+    BALANCE_ACCOUNT_SIZE, BALANCE_ACCOUNT_TAG, INSTRUCTION_VERSION_JSON, RECEIPTS_ACCOUNT_MAX_SIZE,
+    RECEIPTS_ACCOUNT_TAG, RECEIPTS_HEADER_LEN, STATE_ACCOUNT_SIZE, STATE_DISCRIMINATOR,
+    STATE_HEADER_LEN, STATE_SEED, STATE_VERSION,
+};
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::Clock;
+use solana_program::entrypoint::{ProgramResult, SUCCESS};
+use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+use solana_program::program_utils::limited_deserialize;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_program::system_instruction::SystemInstruction;
+use solana_program::system_program;
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+const SAMPLES: u32 = 10;
+const SAMPLE_TIME: Duration = Duration::from_millis(200);
+const ADMIN: Pubkey = Pubkey::new_from_array([7; 32]);
+
+thread_local! {
+    static PROGRAM_ID: Cell<Pubkey> = const { Cell::new(Pubkey::new_from_array([0; 32])) };
+}
+
+// logs and events are dropped, CPIs into spl_token and the system program run in-process
+struct BenchSyscallStubs;
+
+impl SyscallStubs for BenchSyscallStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    fn sol_set_return_data(&self, _data: &[u8]) {}
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { std::ptr::write(var_addr as *mut Rent, Rent::default()) };
+        SUCCESS
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { std::ptr::write(var_addr as *mut Clock, Clock::default()) };
+        SUCCESS
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let program_id = PROGRAM_ID.with(|p| p.get());
+        let pda_signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &program_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut accounts = Vec::new();
+        for meta in &instruction.accounts {
+            let mut info = account_infos
+                .iter()
+                .find(|a| a.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            info.is_signer |= pda_signers.contains(info.key);
+            accounts.push(info);
+        }
+        if instruction.program_id == spl_token::id() {
+            return spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &accounts,
+                &instruction.data,
+            );
+        }
+        match limited_deserialize(&instruction.data, 1024) {
+            Ok(SystemInstruction::CreateAccount {
+                lamports, owner, ..
+            }) => {
+                **accounts[1].lamports.borrow_mut() = lamports;
+                accounts[1].assign(&owner);
+                Ok(())
+            }
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+fn install_syscall_stubs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        set_syscall_stubs(Box::new(BenchSyscallStubs));
+    });
+}
+
+fn new_program_id() -> Pubkey {
+    install_syscall_stubs();
+    let program_id = Pubkey::new_unique();
+    PROGRAM_ID.with(|p| p.set(program_id));
+    program_id
+}
+
+// backing storage is leaked, a benchmark run only makes a few accounts
+fn new_account(key: Pubkey, signer: bool, data_len: usize, owner: Pubkey) -> AccountInfo<'static> {
+    AccountInfo::new(
+        Box::leak(Box::new(key)),
+        signer,
+        true,
+        Box::leak(Box::new(0)),
+        Box::leak(vec![0u8; data_len].into_boxed_slice()),
+        Box::leak(Box::new(owner)),
+        false,
+        0,
+    )
+}
+
+fn new_token_account(
+    key: Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> AccountInfo<'static> {
+    let account = new_account(key, false, TokenAccount::LEN, spl_token::id());
+    let token_account = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    token_account.pack_into_slice(&mut account.data.borrow_mut());
+    account
+}
+
+fn token() -> TokenType {
+    TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_from_array([1; 32]),
+    }
+}
+
+fn metadata() -> TokenMetadata {
+    TokenMetadata {
+        name: "USD Coin".to_string(),
+        decimals: 9,
+        metadata_uri: Some("https://example.com/usdc.json".to_string()),
+    }
+}
+
+// a listed token with one user depositing into and withdrawing from it
+struct Fixture {
+    program_id: Pubkey,
+    state: AccountInfo<'static>,
+    user: Pubkey,
+    // [state, user, user token account, vault, vault authority, token program, balance, system]
+    accounts: Vec<AccountInfo<'static>>,
+    nonce: u64,
+}
+
+impl Fixture {
+    // the state carries the per-user entries of holders other depositors, the nonce, referrer
+    // and rate window every depositor leaves behind, the account is as large as they need,
+    // beyond what a real state account can hold past a few hundred holders
+    // the rate limit is off, the measured user deposits far more often than it allows
+    fn new(holders: usize) -> Self {
+        let program_id = new_program_id();
+        let (state_key, _) = Pubkey::find_program_address(&[STATE_SEED], &program_id);
+        let data_len = STATE_ACCOUNT_SIZE.max(STATE_HEADER_LEN + 1024 + holders * 128);
+        let state = new_account(state_key, false, data_len, program_id);
+        **state.lamports.borrow_mut() = Rent::default().minimum_balance(data_len);
+        let admin = new_account(ADMIN, true, 0, Pubkey::default());
+        run(
+            &program_id,
+            std::slice::from_ref(&state),
+            &InitializeState.pack(),
+        );
+        run(
+            &program_id,
+            &[state.clone(), admin.clone()],
+            &InitializeConfig { admin: ADMIN }.pack(),
+        );
+
+        let token = token();
+        let mint = new_account(token.mint, false, Mint::LEN, spl_token::id());
+        Mint {
+            mint_authority: COption::None,
+            supply: 0,
+            decimals: 9,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut mint.data.borrow_mut());
+        let (vault_authority, _) = find_vault_authority(&program_id, &token.mint);
+        let vault = new_token_account(
+            find_vault_address(&program_id, &token.mint),
+            &token.mint,
+            &vault_authority,
+            0,
+        );
+        let add = AdminAddSupportedToken {
+            token: token.clone(),
+            metadata: metadata(),
+        };
+        run(
+            &program_id,
+            &[state.clone(), admin, mint, vault.clone()],
+            &add.pack(),
+        );
+
+        let mut contract_state = load_state(&state).unwrap();
+        for i in 0..holders {
+            let mut key = [0; 32];
+            key[..8].copy_from_slice(&(i as u64 + 1).to_le_bytes());
+            contract_state.nonces.insert(Pubkey::new_from_array(key), 1);
+            contract_state
+                .referrers
+                .insert(Pubkey::new_from_array(key), None);
+            contract_state.rate_buckets.insert(
+                Pubkey::new_from_array(key),
+                RateBuckets {
+                    bucket_start: 0,
+                    count: 1,
+                    previous_count: 0,
+                },
+            );
+        }
+        contract_state.rate_limit_ops = 0;
+        write_state(&state, &contract_state);
+
+        let user = Pubkey::new_unique();
+        let (balance, _) = find_balance_address(&program_id, &token.mint, &user);
+        // full already, so the measured deposits only overwrite its oldest receipt
+        let (receipts_key, bump) = find_receipts_address(&program_id, &user);
+        let receipts = new_account(receipts_key, false, RECEIPTS_ACCOUNT_MAX_SIZE, program_id);
+        receipts.data.borrow_mut()[..RECEIPTS_HEADER_LEN].copy_from_slice(bytemuck::bytes_of(
+            &ReceiptsHeader {
+                tag: RECEIPTS_ACCOUNT_TAG,
+                user,
+                bump,
+                next: [0; 2],
+            },
+        ));
+        let accounts = vec![
+            state.clone(),
+            new_account(user, true, 0, Pubkey::default()),
+            new_token_account(Pubkey::new_unique(), &token.mint, &user, u64::MAX / 2),
+            vault,
+            new_account(vault_authority, false, 0, Pubkey::default()),
+            new_account(spl_token::id(), false, 0, Pubkey::default()),
+            new_account(balance, false, BALANCE_ACCOUNT_SIZE, system_program::id()),
+            new_account(system_program::id(), false, 0, Pubkey::default()),
+            receipts,
+        ];
+        let mut fixture = Fixture {
+            program_id,
+            state,
+            user,
+            accounts,
+            nonce: 0,
+        };
+        // creates the balance account, so the measured deposits only update it
+        let data = fixture.deposit(1_000_000_000).pack();
+        fixture.process(&data).unwrap();
+        fixture
+    }
+
+    fn deposit(&mut self, amount: u64) -> ContractInstruction {
+        self.nonce += 1;
+        UserDeposit {
+            token: token(),
+            user: self.user,
+            amount,
+            nonce: self.nonce - 1,
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        }
+    }
+
+    fn withdraw(&mut self, amount: u64) -> ContractInstruction {
+        self.nonce += 1;
+        UserWithdraw {
+            token: token(),
+            user: self.user,
+            amount,
+            nonce: self.nonce - 1,
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        }
+    }
+
+    fn process(&self, data: &[u8]) -> ProgramResult {
+        process_instruction(&self.program_id, &self.accounts, data)
+    }
+}
+
+fn run(program_id: &Pubkey, accounts: &[AccountInfo<'static>], data: &[u8]) {
+    process_instruction(program_id, accounts, data).unwrap();
+}
+
+// the layout store_state writes
+fn write_state(account: &AccountInfo, state: &ContractState) {
+    let payload = borsh::to_vec(state).unwrap();
+    let mut data = account.data.borrow_mut();
+    data[0] = STATE_VERSION;
+    data[1..9].copy_from_slice(&STATE_DISCRIMINATOR);
+    data[9..STATE_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    data[STATE_HEADER_LEN..STATE_HEADER_LEN + payload.len()].copy_from_slice(&payload);
+}
+
+fn pack_json(instruction: &ContractInstruction) -> Vec<u8> {
+    let mut data = vec![INSTRUCTION_VERSION_JSON];
+    data.extend(serde_json::to_vec(instruction).unwrap());
+    data
+}
+
+struct Bencher {
+    filter: Option<String>,
+}
+
+impl Bencher {
+    fn bench<T>(&self, name: &str, mut f: impl FnMut() -> T) {
+        if let Some(filter) = &self.filter {
+            if !name.contains(filter.as_str()) {
+                return;
+            }
+        }
+        // the warm-up call also sizes the batches
+        let start = Instant::now();
+        black_box(f());
+        let once = start.elapsed().max(Duration::from_nanos(1));
+        let batch = (SAMPLE_TIME.as_nanos() / once.as_nanos()).clamp(1, 1_000_000) as u32;
+        let mut per_call: Vec<Duration> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..batch {
+                    black_box(f());
+                }
+                start.elapsed() / batch
+            })
+            .collect();
+        per_call.sort();
+        println!(
+            "{:<48} time: [{:?} {:?} {:?}]",
+            name,
+            per_call[0],
+            per_call[per_call.len() / 2],
+            per_call[per_call.len() - 1]
+        );
+    }
+}
+
+// one of each payload shape clients send, from a bare query to a full multicall
+fn sample_instructions() -> Vec<(&'static str, ContractInstruction)> {
+    let user = Pubkey::new_unique();
+    let deposit = |nonce| UserDeposit {
+        token: token(),
+        user,
+        amount: 1_000_000,
+        nonce,
+        lock_until: Some(1_700_000_000),
+        referrer: Some(Pubkey::new_unique()),
+        beneficiary: None,
+        memo: Some("invoice 17".to_string()),
+    };
+    vec![
+        (
+            "get_balance",
+            GetBalance {
+                token: token(),
+                user,
+            },
+        ),
+        (
+            "list_supported_tokens",
+            ListSupportedTokens {
+                offset: 0,
+                limit: 32,
+            },
+        ),
+        (
+            "set_token_flags",
+            AdminSetTokenFlags {
+                token: token(),
+                deposits_enabled: true,
+                withdrawals_enabled: false,
+            },
+        ),
+        (
+            "add_supported_token",
+            AdminAddSupportedToken {
+                token: token(),
+                metadata: metadata(),
+            },
+        ),
+        ("deposit", deposit(0)),
+        (
+            "withdraw_eth_signed",
+            UserWithdraw {
+                token: token(),
+                user,
+                amount: 1_000_000,
+                nonce: 3,
+                eth_signature: Some(vec![0xab; 65]),
+                recipient: Some(Pubkey::new_unique()),
+                memo: None,
+            },
+        ),
+        (
+            "transfer",
+            UserTransfer {
+                token: token(),
+                from: user,
+                to: Pubkey::new_unique(),
+                amount: 5,
+            },
+        ),
+        (
+            "batch_deposit_8",
+            BatchDeposit {
+                user,
+                items: (0..8).map(|i| (token(), 100 + i)).collect(),
+            },
+        ),
+        (
+            // the most of them that fit MAX_INSTRUCTION_DATA_LEN as JSON
+            "multicall_3_deposits",
+            Multicall {
+                calls: (0..3).map(deposit).collect(),
+            },
+        ),
+    ]
+}
+
+fn main() {
+    // decoding logs the instruction too
+    install_syscall_stubs();
+    let bencher = Bencher {
+        filter: std::env::args().skip(1).find(|arg| !arg.starts_with("--")),
+    };
+
+    // (a) decoding, the JSON layout against the borsh, Anchor and compact ones
+    for (name, instruction) in sample_instructions() {
+        let mut encodings = vec![
+            ("json", pack_json(&instruction)),
+            ("borsh", instruction.pack()),
+            ("anchor", instruction.pack_anchor()),
+        ];
+        encodings.extend(instruction.pack_compact().map(|data| ("compact", data)));
+        for (encoding, data) in encodings {
+            let bench_name = format!("decode/{}/{} ({} bytes)", name, encoding, data.len());
+            bencher.bench(&bench_name, || {
+                deserialize_instruction(black_box(&data)).unwrap()
+            });
+        }
+    }
+
+    // (b) deposit and withdraw, whose cost grows with the state they decode and store again
+    for holders in [10, 10_000, 1_000_000] {
+        let mut fixture = Fixture::new(holders);
+        bencher.bench(&format!("handler/deposit/{}_holders", holders), || {
+            let data = fixture.deposit(1).pack();
+            fixture.process(&data).unwrap()
+        });
+        bencher.bench(&format!("handler/withdraw/{}_holders", holders), || {
+            let data = fixture.withdraw(1).pack();
+            fixture.process(&data).unwrap()
+        });
+    }
+
+    // (c) whole calls at a small state, the encoding of the data included
+    let mut fixture = Fixture::new(10);
+    bencher.bench("process_instruction/deposit/json", || {
+        let data = pack_json(&fixture.deposit(1));
+        fixture.process(&data).unwrap()
+    });
+    bencher.bench("process_instruction/deposit/borsh", || {
+        let data = fixture.deposit(1).pack();
+        fixture.process(&data).unwrap()
+    });
+    let query = GetBalance {
+        token: token(),
+        user: fixture.user,
+    };
+    let (json, borsh) = (pack_json(&query), query.pack());
+    bencher.bench("process_instruction/get_balance/json", || {
+        fixture.process(&json).unwrap()
+    });
+    bencher.bench("process_instruction/get_balance/borsh", || {
+        fixture.process(&borsh).unwrap()
+    });
+    let state = fixture.state.clone();
+    bencher.bench("process_instruction/load_state/10_holders", || {
+        load_state(&state).unwrap()
+    });
+
+    // (d) the state's BTreeMaps against the HashMaps they replaced, at 10k users: a lookup of
+    // each user, and encoding the map, which borsh sorts first for a HashMap
+    let nonces: BTreeMap<Pubkey, u64> = (0..10_000u64).map(|i| (Pubkey::new_unique(), i)).collect();
+    let hashed: HashMap<Pubkey, u64> = nonces.iter().map(|(k, v)| (*k, *v)).collect();
+    let users: Vec<Pubkey> = hashed.keys().copied().collect();
+    bencher.bench("state/lookup_10000_users/btree_map", || {
+        users
+            .iter()
+            .filter_map(|user| nonces.get(user))
+            .sum::<u64>()
+    });
+    bencher.bench("state/lookup_10000_users/hash_map", || {
+        users
+            .iter()
+            .filter_map(|user| hashed.get(user))
+            .sum::<u64>()
+    });
+    bencher.bench("state/encode_10000_users/btree_map", || {
+        borsh::to_vec(&nonces).unwrap()
+    });
+    bencher.bench("state/encode_10000_users/hash_map", || {
+        borsh::to_vec(&hashed).unwrap()
+    });
+
+    // (e) crediting a balance account, decoded and encoded again against changed in place
+    let mut data = vec![BALANCE_ACCOUNT_TAG];
+    BalanceAccount {
+        mint: token().mint,
+        user: fixture.user,
+        bump: 255,
+        epoch: 0,
+        amount: 0,
+        flags: 0,
+        last_update_slot: 0,
+        nonce: 0,
+        ..Default::default()
+    }
+    .serialize(&mut data)
+    .unwrap();
+    bencher.bench("balance_account/credit/borsh", || {
+        let mut balance = BalanceAccount::try_from_slice(&black_box(&data)[1..]).unwrap();
+        balance.amount += 1;
+        balance.serialize(&mut &mut data[1..]).unwrap();
+    });
+    bencher.bench("balance_account/credit/in_place", || {
+        let balance = BalanceAccountData::load_mut(black_box(&mut data)).unwrap();
+        balance.set_amount(balance.amount() + 1);
+    });
+}
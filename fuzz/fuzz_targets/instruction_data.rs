@@ -0,0 +1,14 @@
+// arbitrary bytes as instruction data, the first byte picks the signing user
+#![no_main]
+
+use hello_world_fuzz::World;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    let Some((&signer, data)) = input.split_first() else {
+        return;
+    };
+    let world = World::get();
+    let _ = world.execute(signer as usize, data);
+    world.check_invariants();
+});
@@ -0,0 +1,306 @@
+// sequences of well-formed instructions on the world's token, the fuzzer picks the users and
+// pushes the amounts, fees and limits to their extremes, the invariants hold after every step
+#![no_main]
+
+use arbitrary::Arbitrary;
+use hello_world::ContractInstruction;
+use hello_world_fuzz::{World, USERS};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Deposit {
+        user: u8,
+        amount: u64,
+    },
+    Withdraw {
+        user: u8,
+        amount: u64,
+    },
+    WithdrawAll {
+        user: u8,
+    },
+    RequestWithdraw {
+        user: u8,
+        amount: u64,
+    },
+    ClaimWithdraw {
+        user: u8,
+    },
+    CancelWithdrawRequest {
+        user: u8,
+    },
+    Transfer {
+        from: u8,
+        to: u8,
+        amount: u64,
+    },
+    Approve {
+        owner: u8,
+        spender: u8,
+        amount: u64,
+    },
+    TransferFrom {
+        owner: u8,
+        spender: u8,
+        to: u8,
+        amount: u64,
+    },
+    BatchDeposit {
+        user: u8,
+        amount: Option<u64>,
+    },
+    BatchWithdraw {
+        user: u8,
+        amount: Option<u64>,
+    },
+    SetTreasury {
+        user: u8,
+    },
+    WithdrawTreasury {
+        amount: u64,
+    },
+    SetWithdrawalFee {
+        fee_bps: u16,
+    },
+    SetDepositFee {
+        fee_bps: u16,
+    },
+    SetFeeExempt {
+        user: u8,
+        exempt: bool,
+    },
+    SetDepositCap {
+        cap: Option<u64>,
+    },
+    SetMaxUserBalance {
+        max_user_balance: Option<u64>,
+    },
+    SetMinDeposit {
+        min_deposit: u64,
+    },
+    SetDailyWithdrawalLimit {
+        limit: u64,
+    },
+    SetWithdrawDelay {
+        delay_slots: u64,
+    },
+    SetWithdrawDelegate {
+        user: u8,
+        delegate: Option<u8>,
+    },
+    Freeze {
+        user: u8,
+    },
+    Unfreeze {
+        user: u8,
+    },
+    SetBlocked {
+        user: u8,
+        blocked: bool,
+    },
+    Pause {
+        withdrawals_only: bool,
+    },
+    Unpause,
+    ForceDeleteToken,
+    AdvanceSlots {
+        slots: u64,
+    },
+}
+
+// (signing user, instruction), None for steps that aren't instructions
+fn instruction(world: &World, op: &Op) -> Option<(usize, ContractInstruction)> {
+    let user = |index: &u8| world.users[*index as usize % USERS];
+    let token = world.token.clone();
+    let admin = |instruction| Some((0, instruction));
+    match op {
+        Op::Deposit { user: u, amount } => Some((
+            *u as usize,
+            ContractInstruction::UserDeposit {
+                token,
+                user: user(u),
+                amount: *amount,
+                nonce: world.nonce(*u as usize),
+                lock_until: None,
+                referrer: None,
+                beneficiary: None,
+                memo: None,
+            },
+        )),
+        Op::Withdraw { user: u, amount } => Some((
+            *u as usize,
+            ContractInstruction::UserWithdraw {
+                token,
+                user: user(u),
+                amount: *amount,
+                nonce: world.nonce(*u as usize),
+                eth_signature: None,
+                recipient: None,
+                memo: None,
+            },
+        )),
+        Op::WithdrawAll { user: u } => Some((
+            *u as usize,
+            ContractInstruction::UserWithdrawAll {
+                token,
+                user: user(u),
+            },
+        )),
+        Op::RequestWithdraw { user: u, amount } => Some((
+            *u as usize,
+            ContractInstruction::UserRequestWithdraw {
+                token,
+                user: user(u),
+                amount: *amount,
+            },
+        )),
+        Op::ClaimWithdraw { user: u } => Some((
+            *u as usize,
+            ContractInstruction::UserClaimWithdraw {
+                token,
+                user: user(u),
+            },
+        )),
+        Op::CancelWithdrawRequest { user: u } => {
+            admin(ContractInstruction::AdminCancelWithdrawRequest {
+                token,
+                user: user(u),
+            })
+        }
+        Op::Transfer { from, to, amount } => Some((
+            *from as usize,
+            ContractInstruction::UserTransfer {
+                token,
+                from: user(from),
+                to: user(to),
+                amount: *amount,
+            },
+        )),
+        Op::Approve {
+            owner,
+            spender,
+            amount,
+        } => Some((
+            *owner as usize,
+            ContractInstruction::UserApprove {
+                token,
+                owner: user(owner),
+                spender: user(spender),
+                amount: *amount,
+            },
+        )),
+        Op::TransferFrom {
+            owner,
+            spender,
+            to,
+            amount,
+        } => Some((
+            *spender as usize,
+            ContractInstruction::TransferFrom {
+                token,
+                owner: user(owner),
+                spender: user(spender),
+                to: user(to),
+                amount: *amount,
+            },
+        )),
+        // the world has one token, so a batch has at most one item
+        Op::BatchDeposit { user: u, amount } => Some((
+            *u as usize,
+            ContractInstruction::BatchDeposit {
+                user: user(u),
+                items: amount.iter().map(|a| (token.clone(), *a)).collect(),
+            },
+        )),
+        Op::BatchWithdraw { user: u, amount } => Some((
+            *u as usize,
+            ContractInstruction::BatchWithdraw {
+                user: user(u),
+                items: amount.iter().map(|a| (token.clone(), *a)).collect(),
+            },
+        )),
+        Op::SetTreasury { user: u } => {
+            admin(ContractInstruction::AdminSetTreasury { treasury: user(u) })
+        }
+        Op::WithdrawTreasury { amount } => admin(ContractInstruction::AdminWithdrawTreasury {
+            token,
+            amount: *amount,
+        }),
+        Op::SetWithdrawalFee { fee_bps } => admin(ContractInstruction::AdminSetWithdrawalFee {
+            token,
+            fee_bps: *fee_bps,
+        }),
+        Op::SetDepositFee { fee_bps } => admin(ContractInstruction::AdminSetDepositFee {
+            token,
+            fee_bps: *fee_bps,
+        }),
+        Op::SetFeeExempt { user: u, exempt } => admin(ContractInstruction::AdminSetFeeExempt {
+            user: user(u),
+            exempt: *exempt,
+        }),
+        Op::SetDepositCap { cap } => {
+            admin(ContractInstruction::AdminSetDepositCap { token, cap: *cap })
+        }
+        Op::SetMaxUserBalance { max_user_balance } => {
+            admin(ContractInstruction::AdminSetMaxUserBalance {
+                token,
+                max_user_balance: *max_user_balance,
+            })
+        }
+        Op::SetMinDeposit { min_deposit } => admin(ContractInstruction::AdminSetMinDeposit {
+            token,
+            min_deposit: *min_deposit,
+        }),
+        Op::SetDailyWithdrawalLimit { limit } => {
+            admin(ContractInstruction::AdminSetDailyWithdrawalLimit {
+                token,
+                limit: *limit,
+            })
+        }
+        Op::SetWithdrawDelay { delay_slots } => admin(ContractInstruction::AdminSetWithdrawDelay {
+            delay_slots: *delay_slots,
+        }),
+        Op::SetWithdrawDelegate { user: u, delegate } => Some((
+            *u as usize,
+            ContractInstruction::UserSetWithdrawDelegate {
+                user: user(u),
+                delegate: delegate.as_ref().map(user),
+            },
+        )),
+        Op::Freeze { user: u } => admin(ContractInstruction::AdminFreezeUser {
+            token,
+            user: user(u),
+        }),
+        Op::Unfreeze { user: u } => admin(ContractInstruction::AdminUnfreezeUser {
+            token,
+            user: user(u),
+        }),
+        Op::SetBlocked { user: u, blocked } => admin(ContractInstruction::AdminSetBlocked {
+            user: user(u),
+            blocked: *blocked,
+        }),
+        Op::Pause { withdrawals_only } => admin(ContractInstruction::AdminPause {
+            withdrawals_only: *withdrawals_only,
+        }),
+        Op::Unpause => admin(ContractInstruction::AdminUnpause),
+        Op::ForceDeleteToken => admin(ContractInstruction::AdminForceDeleteToken {
+            token,
+            confirm: true,
+        }),
+        Op::AdvanceSlots { slots } => {
+            world.advance_slots(*slots);
+            None
+        }
+    }
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let world = World::get();
+    for op in ops.iter().take(64) {
+        if let Some((signer, instruction)) = instruction(world, op) {
+            let _ = world.execute(signer, &instruction.pack());
+        }
+        world.check_invariants();
+    }
+});
@@ -0,0 +1,423 @@
+// host harness shared by the fuzz targets: one program with an initialized state, a listed
+// token and a few funded users, the runtime syscalls the program makes are stubbed
+use std::cell::{Cell, RefCell};
+use std::sync::Once;
+
+use hello_world::{
+    find_balance_address, find_receipts_address, find_vault_address, find_vault_authority,
+    load_balances, load_state, process_instruction, ContractInstruction, ReceiptsHeader,
+    TokenMetadata, TokenType, BALANCE_ACCOUNT_SIZE, RECEIPTS_ACCOUNT_MAX_SIZE, RECEIPTS_ACCOUNT_TAG,
+    RECEIPTS_HEADER_LEN, STATE_ACCOUNT_SIZE, STATE_SEED,
+};
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::Clock;
+use solana_program::entrypoint::{ProgramResult, SUCCESS};
+use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+use solana_program::program_utils::limited_deserialize;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_program::system_instruction::SystemInstruction;
+use solana_program::system_program;
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+// users[0] is the admin
+pub const USERS: usize = 3;
+// what every user holds in its token account before anything runs
+pub const INITIAL_TOKENS: u64 = 1_000_000_000;
+
+const PROGRAM_ID: Pubkey = Pubkey::new_from_array([1; 32]);
+
+thread_local! {
+    static SLOT: Cell<u64> = const { Cell::new(0) };
+}
+
+struct FuzzSyscallStubs;
+
+impl SyscallStubs for FuzzSyscallStubs {
+    // logging every instruction slows fuzzing down to a crawl
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_log_data(&self, _fields: &[&[u8]]) {}
+
+    fn sol_set_return_data(&self, _data: &[u8]) {}
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { std::ptr::write(var_addr as *mut Rent, Rent::default()) };
+        SUCCESS
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            slot: SLOT.with(|s| s.get()),
+            ..Clock::default()
+        };
+        unsafe { std::ptr::write(var_addr as *mut Clock, clock) };
+        SUCCESS
+    }
+
+    // the SPL token and system program CPIs run in-process
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let pda_signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &PROGRAM_ID))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut cpi_accounts = Vec::new();
+        for meta in &instruction.accounts {
+            let mut info = account_infos
+                .iter()
+                .find(|a| a.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            info.is_signer |= pda_signers.contains(info.key);
+            if meta.is_signer && !info.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            cpi_accounts.push(info);
+        }
+        if instruction.program_id == spl_token::id() {
+            return spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &cpi_accounts,
+                &instruction.data,
+            );
+        }
+        if instruction.program_id != system_program::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        // accounts are allocated up front, creating one only funds and assigns it
+        match limited_deserialize(&instruction.data, 1024) {
+            Ok(SystemInstruction::CreateAccount {
+                lamports,
+                space,
+                owner,
+            }) => {
+                let account = &cpi_accounts[1];
+                if account.lamports() > 0 || account.data_len() != space as usize {
+                    return Err(ProgramError::AccountAlreadyInitialized);
+                }
+                let from = &cpi_accounts[0];
+                let remaining = from
+                    .lamports()
+                    .checked_sub(lamports)
+                    .ok_or(ProgramError::InsufficientFunds)?;
+                **from.lamports.borrow_mut() = remaining;
+                **account.lamports.borrow_mut() = lamports;
+                account.assign(&owner);
+                Ok(())
+            }
+            Ok(SystemInstruction::Transfer { lamports }) => {
+                let (from, to) = (&cpi_accounts[0], &cpi_accounts[1]);
+                let remaining = from
+                    .lamports()
+                    .checked_sub(lamports)
+                    .ok_or(ProgramError::InsufficientFunds)?;
+                **from.lamports.borrow_mut() = remaining;
+                **to.lamports.borrow_mut() += lamports;
+                Ok(())
+            }
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+}
+
+// AccountInfo borrows everything it points at, the world lives for the whole fuzzing run
+fn new_account(
+    key: Pubkey,
+    is_signer: bool,
+    data_len: usize,
+    owner: Pubkey,
+) -> AccountInfo<'static> {
+    AccountInfo::new(
+        Box::leak(Box::new(key)),
+        is_signer,
+        true,
+        Box::leak(Box::new(0)),
+        Box::leak(vec![0u8; data_len].into_boxed_slice()),
+        Box::leak(Box::new(owner)),
+        false,
+        0,
+    )
+}
+
+fn new_token_account(
+    key: Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> AccountInfo<'static> {
+    let account = new_account(key, false, TokenAccount::LEN, spl_token::id());
+    let token_account = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    token_account.pack_into_slice(&mut account.data.borrow_mut());
+    account
+}
+
+// lamports, data and owner of every account, to roll back a failed instruction like the
+// runtime does
+type Snapshot = Vec<(u64, Vec<u8>, Pubkey)>;
+
+pub struct World {
+    pub token: TokenType,
+    pub users: [Pubkey; USERS],
+    state: AccountInfo<'static>,
+    // per user: the user as signer and its token account
+    signers: Vec<AccountInfo<'static>>,
+    token_accounts: Vec<AccountInfo<'static>>,
+    vault: AccountInfo<'static>,
+    vault_authority: AccountInfo<'static>,
+    token_program: AccountInfo<'static>,
+    system_program: AccountInfo<'static>,
+    balances: Vec<AccountInfo<'static>>,
+    receipts: Vec<AccountInfo<'static>>,
+    initial: RefCell<Snapshot>,
+}
+
+impl World {
+    // the world of this thread, set up on first use and reset to its initial state after
+    pub fn get() -> &'static World {
+        thread_local! {
+            static WORLD: &'static World = Box::leak(Box::new(World::new()));
+        }
+        let world = WORLD.with(|world| *world);
+        world.reset();
+        world
+    }
+
+    fn new() -> World {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            set_syscall_stubs(Box::new(FuzzSyscallStubs));
+        });
+        let token = TokenType {
+            symbol: "usdc".to_string(),
+            mint: Pubkey::new_from_array([2; 32]),
+        };
+        let users = [0u8, 1, 2].map(|i| Pubkey::new_from_array([10 + i; 32]));
+        let (state_key, _) = Pubkey::find_program_address(&[STATE_SEED], &PROGRAM_ID);
+        let state = new_account(state_key, false, STATE_ACCOUNT_SIZE, PROGRAM_ID);
+        **state.lamports.borrow_mut() = Rent::default().minimum_balance(STATE_ACCOUNT_SIZE);
+        let signers: Vec<_> = users
+            .iter()
+            .map(|user| {
+                let signer = new_account(*user, true, 0, system_program::id());
+                **signer.lamports.borrow_mut() = 1_000_000_000_000;
+                signer
+            })
+            .collect();
+        let token_accounts = users
+            .iter()
+            .map(|user| new_token_account(Pubkey::new_unique(), &token.mint, user, INITIAL_TOKENS))
+            .collect();
+        let mint = new_account(token.mint, false, Mint::LEN, spl_token::id());
+        Mint {
+            mint_authority: COption::None,
+            supply: INITIAL_TOKENS * USERS as u64,
+            decimals: 6,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        }
+        .pack_into_slice(&mut mint.data.borrow_mut());
+        let (vault_authority_key, _) = find_vault_authority(&PROGRAM_ID, &token.mint);
+        let vault = new_token_account(
+            find_vault_address(&PROGRAM_ID, &token.mint),
+            &token.mint,
+            &vault_authority_key,
+            0,
+        );
+        let balances = users
+            .iter()
+            .map(|user| {
+                let (address, _) = find_balance_address(&PROGRAM_ID, &token.mint, user);
+                new_account(address, false, BALANCE_ACCOUNT_SIZE, system_program::id())
+            })
+            .collect();
+        // created at full size, so deposits never have to grow them
+        let receipts = users
+            .iter()
+            .map(|user| {
+                let (address, bump) = find_receipts_address(&PROGRAM_ID, user);
+                let account = new_account(address, false, RECEIPTS_ACCOUNT_MAX_SIZE, PROGRAM_ID);
+                account.data.borrow_mut()[..RECEIPTS_HEADER_LEN].copy_from_slice(
+                    bytemuck::bytes_of(&ReceiptsHeader {
+                        tag: RECEIPTS_ACCOUNT_TAG,
+                        user: *user,
+                        bump,
+                        next: [0; 2],
+                    }),
+                );
+                account
+            })
+            .collect();
+        let world = World {
+            token,
+            users,
+            state,
+            signers,
+            token_accounts,
+            vault,
+            vault_authority: new_account(vault_authority_key, false, 0, Pubkey::default()),
+            token_program: new_account(spl_token::id(), false, 0, Pubkey::default()),
+            system_program: new_account(system_program::id(), false, 0, Pubkey::default()),
+            balances,
+            receipts,
+            initial: RefCell::new(Vec::new()),
+        };
+
+        let admin = users[0];
+        let setup = [
+            (
+                vec![world.state.clone()],
+                ContractInstruction::InitializeState,
+            ),
+            (
+                vec![world.state.clone(), world.signers[0].clone()],
+                ContractInstruction::InitializeConfig { admin },
+            ),
+            (
+                vec![
+                    world.state.clone(),
+                    world.signers[0].clone(),
+                    mint,
+                    world.vault.clone(),
+                ],
+                ContractInstruction::AdminAddSupportedToken {
+                    token: world.token.clone(),
+                    metadata: TokenMetadata {
+                        name: "Fuzz".to_string(),
+                        decimals: 6,
+                        metadata_uri: None,
+                    },
+                },
+            ),
+        ];
+        for (accounts, instruction) in setup {
+            process_instruction(&PROGRAM_ID, &accounts, &instruction.pack()).unwrap();
+        }
+        *world.initial.borrow_mut() = world.snapshot();
+        world
+    }
+
+    fn all_accounts(&self) -> Vec<&AccountInfo<'static>> {
+        let mut accounts = vec![&self.state, &self.vault];
+        accounts.extend(&self.signers);
+        accounts.extend(&self.token_accounts);
+        accounts.extend(&self.balances);
+        accounts.extend(&self.receipts);
+        accounts
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.all_accounts()
+            .into_iter()
+            .map(|account| {
+                (
+                    account.lamports(),
+                    account.data.borrow().to_vec(),
+                    *account.owner,
+                )
+            })
+            .collect()
+    }
+
+    fn restore(&self, snapshot: &Snapshot) {
+        for (account, (lamports, data, owner)) in self.all_accounts().into_iter().zip(snapshot) {
+            **account.lamports.borrow_mut() = *lamports;
+            account.data.borrow_mut().copy_from_slice(data);
+            account.assign(owner);
+        }
+    }
+
+    fn reset(&self) {
+        self.restore(&self.initial.borrow());
+        SLOT.with(|s| s.set(0));
+    }
+
+    // the nonce user's next deposit or withdraw must carry
+    pub fn nonce(&self, user: usize) -> u64 {
+        let state = load_state(&self.state).expect("state account corrupted");
+        state
+            .nonces
+            .get(&self.users[user % USERS])
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn advance_slots(&self, slots: u64) {
+        SLOT.with(|s| s.set(s.get().saturating_add(slots)));
+    }
+
+    // [state, signer, signer's token account, vault, vault_authority, token_program], the
+    // balance accounts of every user, the system program and the receipts accounts of every
+    // user, enough for every instruction
+    // on the world's token; a failed instruction leaves no trace, as on chain
+    pub fn execute(&self, signer: usize, data: &[u8]) -> ProgramResult {
+        let signer = signer % USERS;
+        let mut accounts = vec![
+            self.state.clone(),
+            self.signers[signer].clone(),
+            self.token_accounts[signer].clone(),
+            self.vault.clone(),
+            self.vault_authority.clone(),
+            self.token_program.clone(),
+        ];
+        accounts.extend(self.balances.iter().cloned());
+        accounts.push(self.system_program.clone());
+        accounts.extend(self.receipts.iter().cloned());
+        let before = self.snapshot();
+        let result = process_instruction(&PROGRAM_ID, &accounts, data);
+        if result.is_err() {
+            self.restore(&before);
+        }
+        result
+    }
+
+    // the state account still decodes and the ledger is fully backed by the vault
+    pub fn check_invariants(&self) {
+        let mut state = load_state(&self.state).expect("state account corrupted");
+        load_balances(&PROGRAM_ID, &self.balances, &mut state).expect("balance account corrupted");
+        // total_deposited keeps counting what pending withdraw requests locked
+        let mut ledger = 0;
+        for entry in state.all_token_balances.values() {
+            let balances: u128 = entry.balances.values().map(|&b| u128::from(b)).sum();
+            let pending: u128 = entry
+                .withdraw_requests
+                .values()
+                .map(|request| u128::from(request.amount))
+                .sum();
+            assert_eq!(balances + pending, u128::from(entry.total_deposited));
+            ledger += balances + pending;
+        }
+        let vault = TokenAccount::unpack(&self.vault.data.borrow())
+            .unwrap()
+            .amount;
+        assert!(
+            ledger <= u128::from(vault),
+            "ledger {} exceeds vault {}",
+            ledger,
+            vault
+        );
+        let tokens: u128 = self
+            .token_accounts
+            .iter()
+            .map(|account| u128::from(TokenAccount::unpack(&account.data.borrow()).unwrap().amount))
+            .sum();
+        assert_eq!(
+            tokens + u128::from(vault),
+            u128::from(INITIAL_TOKENS) * USERS as u128
+        );
+    }
+}
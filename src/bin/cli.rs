@@ -0,0 +1,23 @@
+// admin and user operations from the command line, see src/cli.rs
+use hello_world::cli::{format_text, parse, run};
+
+fn main() {
+    let (config, command) = match parse(std::env::args_os()) {
+        Ok(parsed) => parsed,
+        Err(e) => match e.downcast::<clap::Error>() {
+            Ok(e) => e.exit(),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(2);
+            }
+        },
+    };
+    match run(&config, &command) {
+        Ok(output) if config.json => println!("{}", output),
+        Ok(output) => println!("{}", format_text(&output)),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
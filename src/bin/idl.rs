@@ -0,0 +1,7 @@
+// prints the IDL, `cargo run --features idl --bin idl > idl/solana_test.json` refreshes the checked-in copy
+fn main() {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&hello_world::idl::generate_idl()).unwrap()
+    );
+}
@@ -0,0 +1,640 @@
+// command line front end of the client builders, the `cli` binary is a thin wrapper
+// parsing and instruction building don't touch the network so they can be tested offline, run
+// fetches the state and the mints the instructions need, then sends them in one transaction
+// amounts are in the token's base units, tokens are named by symbol or mint
+use std::error::Error;
+use std::ffi::OsString;
+
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{read_keypair_file, Signer};
+use solana_sdk::transaction::Transaction;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+
+use crate::client::{
+    add_supported_token_ix, decode_balance, decode_state_account, delete_supported_token_ix,
+    deposit_ix, execute_action_ix, find_state_address, initialize_config_ix, initialize_state_ix,
+    queue_action_ix, with_fee_balance, with_token_2022, withdraw_ix,
+};
+use crate::state::{
+    find_balance_address, find_vault_authority, required_account_size, ContractState,
+    TokenMetadata, TokenType,
+};
+
+pub type CliResult<T> = Result<T, Box<dyn Error>>;
+
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8899";
+
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    pub url: String,
+    pub keypair: String,
+    pub program_id: Pubkey,
+    pub json: bool,
+}
+
+// tokens other than AddToken's are a symbol or a mint, looked up in the registry
+#[derive(Debug, PartialEq)]
+pub enum CliCommand {
+    // InitializeState then InitializeConfig, admin defaults to the keypair
+    Init {
+        admin: Option<Pubkey>,
+    },
+    AddToken {
+        mint: Pubkey,
+        symbol: String,
+        name: String,
+        decimals: u8,
+        metadata_uri: Option<String>,
+    },
+    // the deletion is timelocked, queued for queue_eta_slot if given and run otherwise
+    DeleteToken {
+        token: String,
+        queue_eta_slot: Option<u64>,
+    },
+    // token_account defaults to the keypair's associated token account, the keypair for SOL
+    Deposit {
+        token: String,
+        amount: u64,
+        token_account: Option<Pubkey>,
+    },
+    Withdraw {
+        token: String,
+        amount: u64,
+        token_account: Option<Pubkey>,
+    },
+    // user defaults to the keypair
+    Balance {
+        token: String,
+        user: Option<Pubkey>,
+    },
+    ListTokens,
+    // required_account_size of the state, computed offline
+    AccountSize {
+        max_tokens: usize,
+        users: usize,
+    },
+}
+
+fn token_arg() -> Arg<'static> {
+    Arg::new("token")
+        .required(true)
+        .help("symbol or mint of a supported token")
+}
+
+fn amount_arg() -> Arg<'static> {
+    Arg::new("amount")
+        .required(true)
+        .help("amount in the token's base units")
+}
+
+fn token_account_arg() -> Arg<'static> {
+    Arg::new("token-account")
+        .long("token-account")
+        .takes_value(true)
+        .help("token account to use instead of the associated one")
+}
+
+pub fn app() -> Command<'static> {
+    Command::new("solana_test")
+        .about("admin and user operations of the custody program")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .global(true)
+                .takes_value(true)
+                .default_value(DEFAULT_URL)
+                .help("JSON RPC URL of the cluster"),
+        )
+        .arg(
+            Arg::new("keypair")
+                .long("keypair")
+                .global(true)
+                .takes_value(true)
+                .help("signer and fee payer, ~/.config/solana/id.json by default"),
+        )
+        .arg(
+            Arg::new("program-id")
+                .long("program-id")
+                .global(true)
+                .takes_value(true)
+                .help("address the program is deployed at, required"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .help("print JSON instead of text"),
+        )
+        .subcommand(
+            Command::new("init")
+                .about("create the state account and set the admin")
+                .arg(
+                    Arg::new("admin")
+                        .long("admin")
+                        .takes_value(true)
+                        .help("admin key, the keypair by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("add-token")
+                .about("list a token, creating its vault")
+                .arg(Arg::new("mint").required(true))
+                .arg(Arg::new("symbol").required(true))
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .takes_value(true)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("decimals")
+                        .long("decimals")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(Arg::new("uri").long("uri").takes_value(true)),
+        )
+        .subcommand(
+            Command::new("delete-token")
+                .about("delist a token with an empty vault, once queued with --queue")
+                .arg(token_arg())
+                .arg(
+                    Arg::new("queue")
+                        .long("queue")
+                        .takes_value(true)
+                        .value_name("ETA_SLOT")
+                        .help("queue the deletion to run from this slot on"),
+                ),
+        )
+        .subcommand(
+            Command::new("deposit")
+                .about("deposit into the keypair's balance")
+                .arg(token_arg())
+                .arg(amount_arg())
+                .arg(token_account_arg()),
+        )
+        .subcommand(
+            Command::new("withdraw")
+                .about("withdraw from the keypair's balance")
+                .arg(token_arg())
+                .arg(amount_arg())
+                .arg(token_account_arg()),
+        )
+        .subcommand(
+            Command::new("balance")
+                .about("show a user's balance of a token")
+                .arg(token_arg())
+                .arg(
+                    Arg::new("user")
+                        .long("user")
+                        .takes_value(true)
+                        .help("user to show, the keypair by default"),
+                ),
+        )
+        .subcommand(Command::new("list-tokens").about("show the supported tokens"))
+        .subcommand(
+            Command::new("account-size")
+                .about("show how large the state account has to be")
+                .arg(
+                    Arg::new("max-tokens")
+                        .long("max-tokens")
+                        .takes_value(true)
+                        .default_value("64"),
+                )
+                .arg(
+                    Arg::new("users")
+                        .long("users")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("number of users expected"),
+                ),
+        )
+}
+
+fn value<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> CliResult<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    matches
+        .value_of(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| format!("invalid {} {}: {}", name, value, e).into())
+        })
+        .transpose()
+}
+
+fn required<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> CliResult<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value(matches, name)?.ok_or_else(|| format!("missing {}", name).into())
+}
+
+fn default_keypair() -> String {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    format!("{}/.config/solana/id.json", home)
+}
+
+// args include the binary name, as std::env::args_os
+pub fn parse<I, T>(args: I) -> CliResult<(Config, CliCommand)>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = app().try_get_matches_from(args)?;
+    let config = Config {
+        url: required(&matches, "url")?,
+        keypair: value(&matches, "keypair")?.unwrap_or_else(default_keypair),
+        program_id: required(&matches, "program-id")?,
+        json: matches.is_present("json"),
+    };
+    let command = match matches.subcommand() {
+        Some(("init", m)) => CliCommand::Init {
+            admin: value(m, "admin")?,
+        },
+        Some(("add-token", m)) => CliCommand::AddToken {
+            mint: required(m, "mint")?,
+            symbol: required(m, "symbol")?,
+            name: required(m, "name")?,
+            decimals: required(m, "decimals")?,
+            metadata_uri: value(m, "uri")?,
+        },
+        Some(("delete-token", m)) => CliCommand::DeleteToken {
+            token: required(m, "token")?,
+            queue_eta_slot: value(m, "queue")?,
+        },
+        Some(("deposit", m)) => CliCommand::Deposit {
+            token: required(m, "token")?,
+            amount: required(m, "amount")?,
+            token_account: value(m, "token-account")?,
+        },
+        Some(("withdraw", m)) => CliCommand::Withdraw {
+            token: required(m, "token")?,
+            amount: required(m, "amount")?,
+            token_account: value(m, "token-account")?,
+        },
+        Some(("balance", m)) => CliCommand::Balance {
+            token: required(m, "token")?,
+            user: value(m, "user")?,
+        },
+        Some(("list-tokens", _)) => CliCommand::ListTokens,
+        Some(("account-size", m)) => CliCommand::AccountSize {
+            max_tokens: required(m, "max-tokens")?,
+            users: required(m, "users")?,
+        },
+        _ => unreachable!("subcommand_required"),
+    };
+    Ok((config, command))
+}
+
+// the registry entry named by symbol or mint
+pub fn resolve_token(state: &ContractState, token: &str) -> CliResult<TokenType> {
+    let mint = token.parse::<Pubkey>().ok();
+    state
+        .all_token_balances
+        .keys()
+        .find(|t| Some(t.mint) == mint || t.symbol == token)
+        .cloned()
+        .ok_or_else(|| format!("token {} is not supported", token).into())
+}
+
+// what the instructions of a command need to know about the chain
+pub struct ChainData {
+    // None before init
+    pub state: Option<ContractState>,
+    // owner of the command's mint, spl_token or spl_token_2022
+    pub token_program: Pubkey,
+    // data of the signer's balance account of a deposit's or withdrawal's token, None if there
+    // is none, it holds the nonce
+    pub balance: Option<Vec<u8>>,
+}
+
+// the nonce signer's deposit or withdrawal has to carry
+fn nonce(state: &ContractState, signer: &Pubkey, chain: &ChainData) -> u64 {
+    state.balance_nonce(signer, chain.balance.as_deref().unwrap_or_default())
+}
+
+fn require_state(chain: &ChainData) -> CliResult<&ContractState> {
+    chain
+        .state
+        .as_ref()
+        .ok_or_else(|| "the state account is not initialized".into())
+}
+
+fn with_token_program(
+    instruction: Instruction,
+    token: &TokenType,
+    chain: &ChainData,
+) -> Instruction {
+    if !token.is_native() && chain.token_program == spl_token_2022::id() {
+        with_token_2022(instruction, &token.mint)
+    } else {
+        instruction
+    }
+}
+
+fn user_token_account(
+    token: &TokenType,
+    signer: &Pubkey,
+    token_account: &Option<Pubkey>,
+    chain: &ChainData,
+) -> Pubkey {
+    match token_account {
+        Some(account) => *account,
+        None if token.is_native() => *signer,
+        None => {
+            get_associated_token_address_with_program_id(signer, &token.mint, &chain.token_program)
+        }
+    }
+}
+
+// instructions of a command signed by signer, empty for the read-only ones
+pub fn build_instructions(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    command: &CliCommand,
+    chain: &ChainData,
+) -> CliResult<Vec<Instruction>> {
+    let instructions = match command {
+        CliCommand::Init { admin } => vec![
+            initialize_state_ix(program_id, signer),
+            initialize_config_ix(program_id, signer, admin.as_ref().unwrap_or(signer)),
+        ],
+        CliCommand::AddToken {
+            mint,
+            symbol,
+            name,
+            decimals,
+            metadata_uri,
+        } => {
+            let token = TokenType {
+                symbol: symbol.clone(),
+                mint: *mint,
+            };
+            let metadata = TokenMetadata {
+                name: name.clone(),
+                decimals: *decimals,
+                metadata_uri: metadata_uri.clone(),
+            };
+            let add = add_supported_token_ix(program_id, signer, &token, &metadata);
+            if token.is_native() {
+                vec![add]
+            } else {
+                let (vault_authority, _) = find_vault_authority(program_id, mint);
+                vec![
+                    create_associated_token_account_idempotent(
+                        signer,
+                        &vault_authority,
+                        mint,
+                        &chain.token_program,
+                    ),
+                    with_token_program(add, &token, chain),
+                ]
+            }
+        }
+        CliCommand::DeleteToken {
+            token,
+            queue_eta_slot,
+        } => {
+            let token = resolve_token(require_state(chain)?, token)?;
+            let delete = with_token_program(
+                delete_supported_token_ix(program_id, signer, &token),
+                &token,
+                chain,
+            );
+            match queue_eta_slot {
+                Some(eta_slot) => vec![queue_action_ix(program_id, signer, &delete, *eta_slot)],
+                None => vec![execute_action_ix(program_id, delete)],
+            }
+        }
+        CliCommand::Deposit {
+            token,
+            amount,
+            token_account,
+        } => {
+            let state = require_state(chain)?;
+            let token = resolve_token(state, token)?;
+            let nonce = nonce(state, signer, chain);
+            let deposit = deposit_ix(
+                program_id,
+                &token,
+                signer,
+                &user_token_account(&token, signer, token_account, chain),
+                *amount,
+                nonce,
+                None,
+                None,
+                None,
+                None,
+            );
+            let bps = state.all_token_balances[&token].deposit_fee_bps;
+            let deposit = with_fee_balance(deposit, state, &token, signer, bps);
+            vec![with_token_program(deposit, &token, chain)]
+        }
+        CliCommand::Withdraw {
+            token,
+            amount,
+            token_account,
+        } => {
+            let state = require_state(chain)?;
+            let token = resolve_token(state, token)?;
+            let nonce = nonce(state, signer, chain);
+            let withdraw = withdraw_ix(
+                program_id,
+                &token,
+                signer,
+                signer,
+                &user_token_account(&token, signer, token_account, chain),
+                *amount,
+                nonce,
+                None,
+                None,
+                None,
+            );
+            let bps = state.all_token_balances[&token].withdrawal_fee_bps;
+            let withdraw = with_fee_balance(withdraw, state, &token, signer, bps);
+            vec![with_token_program(withdraw, &token, chain)]
+        }
+        CliCommand::Balance { .. } | CliCommand::ListTokens | CliCommand::AccountSize { .. } => {
+            Vec::new()
+        }
+    };
+    Ok(instructions)
+}
+
+// the mint a command's instructions move, for looking up its token program
+fn command_mint(command: &CliCommand, state: Option<&ContractState>) -> CliResult<Option<Pubkey>> {
+    let token = match command {
+        CliCommand::AddToken { mint, .. } => return Ok(Some(*mint)),
+        CliCommand::DeleteToken { token, .. }
+        | CliCommand::Deposit { token, .. }
+        | CliCommand::Withdraw { token, .. } => token,
+        _ => return Ok(None),
+    };
+    match state {
+        Some(state) => Ok(Some(resolve_token(state, token)?.mint)),
+        None => Ok(None),
+    }
+}
+
+fn fetch_data(rpc: &RpcClient, address: &Pubkey) -> CliResult<Option<Vec<u8>>> {
+    let account = rpc
+        .get_account_with_commitment(address, rpc.commitment())?
+        .value;
+    Ok(account.map(|account| account.data))
+}
+
+fn fetch_state(rpc: &RpcClient, program_id: &Pubkey) -> CliResult<Option<ContractState>> {
+    let Some(data) = fetch_data(rpc, &find_state_address(program_id))? else {
+        return Ok(None);
+    };
+    Ok(Some(decode_state_account(program_id, &data)?))
+}
+
+fn list_tokens(state: &ContractState) -> Value {
+    let mut tokens: Vec<_> = state.all_token_balances.iter().collect();
+    tokens.sort_by(|a, b| a.0.symbol.cmp(&b.0.symbol));
+    let tokens: Vec<Value> = tokens
+        .into_iter()
+        .map(|(token, entry)| {
+            let metadata = state
+                .token_metadata
+                .get(&token.mint)
+                .cloned()
+                .unwrap_or_default();
+            json!({
+                "symbol": token.symbol,
+                "mint": token.mint.to_string(),
+                "name": metadata.name,
+                "decimals": metadata.decimals,
+                "total_deposited": entry.total_deposited,
+                "deposits_enabled": entry.deposits_enabled,
+                "withdrawals_enabled": entry.withdrawals_enabled,
+            })
+        })
+        .collect();
+    json!(tokens)
+}
+
+// text form of run's output
+pub fn format_text(output: &Value) -> String {
+    if let Some(signature) = output.get("signature") {
+        return format!("signature {}", signature.as_str().unwrap_or_default());
+    }
+    if let Some(bytes) = output.get("bytes") {
+        return format!(
+            "{} bytes for {} tokens and {} users",
+            bytes, output["max_tokens"], output["users"]
+        );
+    }
+    if let Some(amount) = output.get("amount") {
+        return format!(
+            "{} {} of {}",
+            amount,
+            output["symbol"].as_str().unwrap_or_default(),
+            output["user"].as_str().unwrap_or_default()
+        );
+    }
+    output
+        .as_array()
+        .map(|tokens| {
+            tokens
+                .iter()
+                .map(|t| {
+                    format!(
+                        "{:<12} {} decimals {} total {}{}{}",
+                        t["symbol"].as_str().unwrap_or_default(),
+                        t["mint"].as_str().unwrap_or_default(),
+                        t["decimals"],
+                        t["total_deposited"],
+                        if t["deposits_enabled"] == json!(true) {
+                            ""
+                        } else {
+                            " deposits disabled"
+                        },
+                        if t["withdrawals_enabled"] == json!(true) {
+                            ""
+                        } else {
+                            " withdrawals disabled"
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .unwrap_or_default()
+}
+
+// run a command against config.url, its output is what --json prints
+pub fn run(config: &Config, command: &CliCommand) -> CliResult<Value> {
+    if let CliCommand::AccountSize { max_tokens, users } = command {
+        return Ok(json!({
+            "max_tokens": max_tokens,
+            "users": users,
+            "bytes": required_account_size(*max_tokens, *users),
+        }));
+    }
+    let rpc = RpcClient::new_with_commitment(config.url.clone(), CommitmentConfig::confirmed());
+    let keypair = read_keypair_file(&config.keypair)
+        .map_err(|e| format!("can't read keypair {}: {}", config.keypair, e))?;
+    let signer = keypair.pubkey();
+    let state = fetch_state(&rpc, &config.program_id)?;
+
+    match command {
+        CliCommand::Balance { token, user } => {
+            let state = state.ok_or("the state account is not initialized")?;
+            let token = resolve_token(&state, token)?;
+            let user = user.unwrap_or(signer);
+            let (address, _) = find_balance_address(&config.program_id, &token.mint, &user);
+            let data = fetch_data(&rpc, &address)?;
+            let amount = decode_balance(&config.program_id, state, &token, &user, data.as_deref())?;
+            return Ok(json!({
+                "symbol": token.symbol,
+                "mint": token.mint.to_string(),
+                "user": user.to_string(),
+                "amount": amount,
+            }));
+        }
+        CliCommand::ListTokens => {
+            let state = state.ok_or("the state account is not initialized")?;
+            return Ok(list_tokens(&state));
+        }
+        _ => {}
+    }
+
+    let mint = command_mint(command, state.as_ref())?;
+    let token_program = match mint {
+        Some(mint) if mint != spl_token::native_mint::id() => rpc.get_account(&mint)?.owner,
+        _ => spl_token::id(),
+    };
+    let balance = match (command, mint) {
+        (CliCommand::Deposit { .. } | CliCommand::Withdraw { .. }, Some(mint)) => {
+            let (address, _) = find_balance_address(&config.program_id, &mint, &signer);
+            fetch_data(&rpc, &address)?
+        }
+        _ => None,
+    };
+    let chain = ChainData {
+        state,
+        token_program,
+        balance,
+    };
+    let instructions = build_instructions(&config.program_id, &signer, command, &chain)?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&signer),
+        &[&keypair],
+        rpc.get_latest_blockhash()?,
+    );
+    let signature = rpc.send_and_confirm_transaction(&transaction)?;
+    Ok(json!({ "signature": signature.to_string() }))
+}
@@ -0,0 +1,1927 @@
+// off-chain builders of every ContractInstruction, data packed with ContractInstruction::pack
+// and accounts laid out as instruction.rs documents them
+// builders of instructions that change balances include the balance accounts of the users
+// involved and the system program, add the treasury's with with_treasury_balance when the
+// token charges a fee or sweeps dust, and pass them through with_token_2022 for a Token-2022 mint
+use std::io::{self, Write};
+
+use borsh::BorshDeserialize;
+use serde::{Deserialize, Serialize};
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+
+use crate::instruction::ContractInstruction;
+use crate::state::{
+    amount_for_shares, find_balance_address, find_receipts_address, find_vault_address,
+    find_vault_address_with_program_id, find_vault_authority, load_balances, load_state,
+    ContractState, TokenMetadata, TokenType, STATE_SEED,
+};
+
+// address of the state PDA
+pub fn find_state_address(program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[STATE_SEED], program_id).0
+}
+
+// append the treasury's balance account of mint, needed when a fee is credited to it
+pub fn with_treasury_balance(
+    mut instruction: Instruction,
+    mint: &Pubkey,
+    treasury: &Pubkey,
+) -> Instruction {
+    let (balance, _) = find_balance_address(&instruction.program_id, mint, treasury);
+    instruction.accounts.push(AccountMeta::new(balance, false));
+    instruction
+}
+
+// append user's receipts account, needed by every deposit credited to user
+pub fn with_receipts_account(mut instruction: Instruction, user: &Pubkey) -> Instruction {
+    let (receipts, _) = find_receipts_address(&instruction.program_id, user);
+    instruction.accounts.push(AccountMeta::new(receipts, false));
+    instruction
+}
+
+// append the price account feed, needed by deposits of a token with a price feed
+pub fn with_price_feed(mut instruction: Instruction, feed: &Pubkey) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(*feed, false));
+    instruction
+}
+
+// with_treasury_balance if user is charged a fee of bps on token by state
+pub fn with_fee_balance(
+    instruction: Instruction,
+    state: &ContractState,
+    token: &TokenType,
+    user: &Pubkey,
+    bps: u16,
+) -> Instruction {
+    match state.treasury {
+        Some(treasury) if bps > 0 && !state.fee_exempt.contains(user) => {
+            with_treasury_balance(instruction, &token.mint, &treasury)
+        }
+        _ => instruction,
+    }
+}
+
+// ContractState out of the fetched data of the state account
+pub fn decode_state_account(
+    program_id: &Pubkey,
+    data: &[u8],
+) -> Result<ContractState, ProgramError> {
+    let key = find_state_address(program_id);
+    let (mut lamports, mut data) = (0, data.to_vec());
+    let account = AccountInfo::new(
+        &key,
+        false,
+        false,
+        &mut lamports,
+        &mut data,
+        program_id,
+        false,
+        0,
+    );
+    load_state(&account)
+}
+
+// user's balance of a supported token as the program sees it, data is the fetched balance
+// account's or None if there is none, one written under an earlier listing of the token is 0
+pub fn decode_balance(
+    program_id: &Pubkey,
+    mut state: ContractState,
+    token: &TokenType,
+    user: &Pubkey,
+    data: Option<&[u8]>,
+) -> Result<u64, ProgramError> {
+    if let Some(data) = data {
+        let (key, _) = find_balance_address(program_id, &token.mint, user);
+        let (mut lamports, mut data) = (0, data.to_vec());
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            program_id,
+            false,
+            0,
+        );
+        load_balances(program_id, std::slice::from_ref(&account), &mut state)?;
+    }
+    let entry = state
+        .all_token_balances
+        .get(token)
+        .ok_or(ProgramError::InvalidArgument)?;
+    Ok(entry.balances.get(user).copied().unwrap_or(0))
+}
+
+// one user's balance of one token, as exported by decode_balances
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BalanceRow {
+    pub token_symbol: String,
+    #[serde(with = "pubkey_serde")]
+    pub mint: Pubkey,
+    #[serde(with = "pubkey_serde")]
+    pub user: Pubkey,
+    // in token units, for a share-mode token what shares are worth, rounded down
+    pub balance: u64,
+    // the shares held of a share-mode token, None for any other
+    pub shares: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct BalanceExport {
+    // ordered by mint, then user
+    pub rows: Vec<BalanceRow>,
+    // balance accounts that couldn't be decoded, their balances are missing from rows
+    pub failed: Vec<(Pubkey, ProgramError)>,
+}
+
+impl BalanceExport {
+    // a header line then one line per row, symbols are alphanumeric and keys base58, so no
+    // field needs quoting, shares is empty for a token that isn't in share mode
+    pub fn to_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "token,mint,user,balance,shares")?;
+        for row in &self.rows {
+            let shares = row
+                .shares
+                .map(|shares| shares.to_string())
+                .unwrap_or_default();
+            writeln!(
+                writer,
+                "{},{},{},{},{}",
+                row.token_symbol, row.mint, row.user, row.balance, shares
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// every balance of every listed token, out of the fetched data of the state account and of
+// balance accounts given as (address, data), the balances still inline in the state included
+// balances of a share-mode token are in units, with the shares next to them
+// a balance account that fails to decode is reported in failed and the others still count,
+// ones written under an earlier listing of their token or for an unlisted one are 0 and left
+// out like the program leaves them out
+pub fn decode_balances(
+    program_id: &Pubkey,
+    state_data: &[u8],
+    balance_accounts: &[(Pubkey, Vec<u8>)],
+) -> Result<BalanceExport, ProgramError> {
+    let mut state = decode_state_account(program_id, state_data)?;
+    let mut failed = Vec::new();
+    for (address, data) in balance_accounts {
+        let (mut lamports, mut data) = (0, data.clone());
+        let account = AccountInfo::new(
+            address,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            program_id,
+            false,
+            0,
+        );
+        if let Err(error) = load_balances(program_id, std::slice::from_ref(&account), &mut state) {
+            failed.push((*address, error));
+        }
+    }
+    let rows = state
+        .all_token_balances
+        .iter()
+        .flat_map(|(token, entry)| {
+            let share_supply = state.share_supplies.get(&token.mint).copied();
+            entry.balances.iter().map(move |(user, balance)| {
+                let (balance, shares) = match share_supply {
+                    // shares are part of the supply, so what they are worth fits a u64
+                    Some(supply) => (
+                        amount_for_shares(*balance, supply, entry.total_deposited).unwrap_or(0),
+                        Some(*balance),
+                    ),
+                    None => (*balance, None),
+                };
+                BalanceRow {
+                    token_symbol: token.symbol.clone(),
+                    mint: token.mint,
+                    user: *user,
+                    balance,
+                    shares,
+                }
+            })
+        })
+        .collect();
+    Ok(BalanceExport { rows, failed })
+}
+
+// a Pubkey as its base58 string, serde's default is its 32 bytes
+mod pubkey_serde {
+    use std::str::FromStr;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use solana_program::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(key: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(key)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        Pubkey::from_str(&key).map_err(D::Error::custom)
+    }
+}
+
+// append the SPL Memo program so a deposit's or withdrawal's memo is logged by it as well
+pub fn with_memo_program(mut instruction: Instruction) -> Instruction {
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(spl_memo::id(), false));
+    instruction
+}
+
+// point an instruction built for mint at its Token-2022 vault and program, the mint follows
+// the program, the token accounts passed to the builder must be Token-2022 accounts too
+pub fn with_token_2022(mut instruction: Instruction, mint: &Pubkey) -> Instruction {
+    let program_id = instruction.program_id;
+    let legacy_vault = find_vault_address(&program_id, mint);
+    let vault = find_vault_address_with_program_id(&program_id, mint, &spl_token_2022::id());
+    let mut accounts = Vec::with_capacity(instruction.accounts.len() + 1);
+    // the token program comes two after the vault, see token_transfer_accounts
+    let mut token_program = None;
+    for (index, mut account) in instruction.accounts.into_iter().enumerate() {
+        if account.pubkey == legacy_vault {
+            account.pubkey = vault;
+            token_program = Some(index + 2);
+        }
+        let is_token_program = token_program == Some(index) && account.pubkey == spl_token::id();
+        if is_token_program {
+            account.pubkey = spl_token_2022::id();
+        }
+        accounts.push(account);
+        if is_token_program {
+            accounts.push(AccountMeta::new_readonly(*mint, false));
+        }
+    }
+    instruction.accounts = accounts;
+    instruction
+}
+
+// the instruction another builder packed, read without deserialize_instruction's logging so
+// it also works off-chain next to a solana-program-test bank
+fn unpack_built(instruction: &Instruction) -> ContractInstruction {
+    // the builders' data always decodes
+    ContractInstruction::try_from_slice(&instruction.data[1..]).unwrap()
+}
+
+fn build(
+    program_id: &Pubkey,
+    instruction: ContractInstruction,
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction::new_with_bytes(*program_id, &instruction.pack(), accounts)
+}
+
+// [state, authority] for instructions that only touch the state account
+fn authority_accounts(program_id: &Pubkey, authority: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new_readonly(*authority, true),
+    ]
+}
+
+// user_token_account, vault, vault_authority and token_program of one token, native SOL goes
+// through the vault PDA and the system program
+fn token_transfer_accounts(
+    program_id: &Pubkey,
+    token: &TokenType,
+    token_account: &Pubkey,
+) -> Vec<AccountMeta> {
+    if token.is_native() {
+        let (vault, _) = find_vault_authority(program_id, &token.mint);
+        return vec![
+            AccountMeta::new(*token_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ];
+    }
+    let (vault_authority, _) = find_vault_authority(program_id, &token.mint);
+    vec![
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(find_vault_address(program_id, &token.mint), false),
+        AccountMeta::new_readonly(vault_authority, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]
+}
+
+// the balance accounts of every (mint, user) followed by the system program
+fn push_balance_accounts(
+    accounts: &mut Vec<AccountMeta>,
+    program_id: &Pubkey,
+    balances: &[(&Pubkey, &Pubkey)],
+) {
+    for (mint, user) in balances {
+        let (balance, _) = find_balance_address(program_id, mint, user);
+        accounts.push(AccountMeta::new(balance, false));
+    }
+    accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+}
+
+// [state, signer] followed by UserDeposit's token accounts and user's balance account, signer
+// pays the balance account's rent
+fn token_instruction(
+    program_id: &Pubkey,
+    instruction: ContractInstruction,
+    signer: AccountMeta,
+    token: &TokenType,
+    user: &Pubkey,
+    token_account: &Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        signer,
+    ];
+    accounts.extend(token_transfer_accounts(program_id, token, token_account));
+    push_balance_accounts(&mut accounts, program_id, &[(&token.mint, user)]);
+    build(program_id, instruction, accounts)
+}
+
+pub fn initialize_state_ix(program_id: &Pubkey, payer: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::InitializeState,
+        vec![
+            AccountMeta::new(find_state_address(program_id), false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+pub fn migrate_state_ix(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::MigrateState,
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn initialize_config_ix(
+    program_id: &Pubkey,
+    initializer: &Pubkey,
+    admin: &Pubkey,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::InitializeConfig { admin: *admin },
+        authority_accounts(program_id, initializer),
+    )
+}
+
+// the SPL vault has to exist already, native SOL's vault is funded by admin
+pub fn add_supported_token_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    metadata: &TokenMetadata,
+) -> Instruction {
+    let state = AccountMeta::new(find_state_address(program_id), false);
+    let accounts = if token.is_native() {
+        let (vault, _) = find_vault_authority(program_id, &token.mint);
+        vec![
+            state,
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ]
+    } else {
+        // admin funds the state account's growth when the new entry doesn't fit it
+        vec![
+            state,
+            AccountMeta::new(*admin, true),
+            AccountMeta::new_readonly(token.mint, false),
+            AccountMeta::new_readonly(find_vault_address(program_id, &token.mint), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ]
+    };
+    build(
+        program_id,
+        ContractInstruction::AdminAddSupportedToken {
+            token: token.clone(),
+            metadata: metadata.clone(),
+        },
+        accounts,
+    )
+}
+
+// add_supported_token_ix's accounts for every token, listed with default metadata
+pub fn add_supported_tokens_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    tokens: &[TokenType],
+) -> Instruction {
+    let mut accounts = authority_accounts(program_id, admin);
+    accounts[1] = AccountMeta::new(*admin, true);
+    for token in tokens {
+        if token.is_native() {
+            let (vault, _) = find_vault_authority(program_id, &token.mint);
+            accounts.push(AccountMeta::new(vault, false));
+            accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        } else {
+            accounts.push(AccountMeta::new_readonly(token.mint, false));
+            accounts.push(AccountMeta::new_readonly(
+                find_vault_address(program_id, &token.mint),
+                false,
+            ));
+        }
+    }
+    accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    build(
+        program_id,
+        ContractInstruction::AdminAddSupportedTokens {
+            tokens: tokens.to_vec(),
+        },
+        accounts,
+    )
+}
+
+pub fn delete_supported_token_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+) -> Instruction {
+    let vault = if token.is_native() {
+        find_vault_authority(program_id, &token.mint).0
+    } else {
+        find_vault_address(program_id, &token.mint)
+    };
+    let mut accounts = authority_accounts(program_id, admin);
+    accounts.push(AccountMeta::new_readonly(vault, false));
+    build(
+        program_id,
+        ContractInstruction::AdminDeleteSupportedToken {
+            token: token.clone(),
+        },
+        accounts,
+    )
+}
+
+pub fn force_delete_token_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    confirm: bool,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminForceDeleteToken {
+            token: token.clone(),
+            confirm,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// user_token_account is user itself for native SOL
+// user funds the deposit, beneficiary (user if None) is credited and gets the balance account,
+// nonce is the one of user's balance account, see ContractState::balance_nonce
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    user_token_account: &Pubkey,
+    amount: u64,
+    nonce: u64,
+    lock_until: Option<i64>,
+    referrer: Option<Pubkey>,
+    beneficiary: Option<Pubkey>,
+    memo: Option<String>,
+) -> Instruction {
+    let mut instruction = token_instruction(
+        program_id,
+        ContractInstruction::UserDeposit {
+            token: token.clone(),
+            user: *user,
+            amount,
+            nonce,
+            lock_until,
+            referrer,
+            beneficiary,
+            memo,
+        },
+        AccountMeta::new(*user, true),
+        token,
+        beneficiary.as_ref().unwrap_or(user),
+        user_token_account,
+    );
+    // user's own balance account holds the nonce, it goes before the system program
+    if beneficiary.is_some_and(|beneficiary| beneficiary != *user) {
+        let (balance, _) = find_balance_address(program_id, &token.mint, user);
+        let system_program = instruction.accounts.len() - 1;
+        instruction
+            .accounts
+            .insert(system_program, AccountMeta::new(balance, false));
+    }
+    with_receipts_account(instruction, beneficiary.as_ref().unwrap_or(user))
+}
+
+// authority is user or its delegate, with eth_signature it is whoever relays the instruction
+// recipient, if set, must own recipient_token_account
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    authority: &Pubkey,
+    user: &Pubkey,
+    recipient_token_account: &Pubkey,
+    amount: u64,
+    nonce: u64,
+    eth_signature: Option<Vec<u8>>,
+    recipient: Option<Pubkey>,
+    memo: Option<String>,
+) -> Instruction {
+    token_instruction(
+        program_id,
+        ContractInstruction::UserWithdraw {
+            token: token.clone(),
+            user: *user,
+            amount,
+            nonce,
+            eth_signature,
+            recipient,
+            memo,
+        },
+        AccountMeta::new(*authority, true),
+        token,
+        user,
+        recipient_token_account,
+    )
+}
+
+// the closed balance account's rent goes to authority
+pub fn withdraw_all_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    authority: &Pubkey,
+    user: &Pubkey,
+    recipient_token_account: &Pubkey,
+) -> Instruction {
+    token_instruction(
+        program_id,
+        ContractInstruction::UserWithdrawAll {
+            token: token.clone(),
+            user: *user,
+        },
+        AccountMeta::new(*authority, true),
+        token,
+        user,
+        recipient_token_account,
+    )
+}
+
+pub fn request_withdraw_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*user, true),
+    ];
+    push_balance_accounts(&mut accounts, program_id, &[(&token.mint, user)]);
+    build(
+        program_id,
+        ContractInstruction::UserRequestWithdraw {
+            token: token.clone(),
+            user: *user,
+            amount,
+        },
+        accounts,
+    )
+}
+
+pub fn claim_withdraw_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    authority: &Pubkey,
+    user: &Pubkey,
+    recipient_token_account: &Pubkey,
+) -> Instruction {
+    token_instruction(
+        program_id,
+        ContractInstruction::UserClaimWithdraw {
+            token: token.clone(),
+            user: *user,
+        },
+        AccountMeta::new(*authority, true),
+        token,
+        user,
+        recipient_token_account,
+    )
+}
+
+// user's balance account is passed for the balance check
+pub fn enqueue_withdraw_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*user, true),
+    ];
+    push_balance_accounts(&mut accounts, program_id, &[(&token.mint, user)]);
+    build(
+        program_id,
+        ContractInstruction::UserEnqueueWithdraw {
+            token: token.clone(),
+            user: *user,
+            amount,
+        },
+        accounts,
+    )
+}
+
+pub fn cancel_queued_withdraw_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::UserCancelQueuedWithdraw {
+            token: token.clone(),
+            user: *user,
+        },
+        authority_accounts(program_id, user),
+    )
+}
+
+// entries are (user, token account receiving their payout) of the front of token's queue in
+// order, all of them are processed at most, the users get the rent of the balance accounts
+// closed and payer pays for the treasury's, see with_treasury_balance
+pub fn process_withdraw_queue_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    token: &TokenType,
+    entries: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*payer, true),
+    ];
+    for (_, token_account) in entries {
+        accounts.extend(token_transfer_accounts(program_id, token, token_account));
+    }
+    let balances: Vec<_> = entries
+        .iter()
+        .map(|(user, _)| (&token.mint, user))
+        .collect();
+    push_balance_accounts(&mut accounts, program_id, &balances);
+    accounts.extend(
+        entries
+            .iter()
+            .map(|(user, _)| AccountMeta::new(*user, false)),
+    );
+    build(
+        program_id,
+        ContractInstruction::ProcessWithdrawQueue {
+            token: token.clone(),
+            // more entries than fit a u8 wouldn't fit a transaction either
+            max_items: entries.len() as u8,
+        },
+        accounts,
+    )
+}
+
+// admin pays for user's balance account if it was closed in the meantime
+pub fn cancel_withdraw_request_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*admin, true),
+    ];
+    push_balance_accounts(&mut accounts, program_id, &[(&token.mint, user)]);
+    build(
+        program_id,
+        ContractInstruction::AdminCancelWithdrawRequest {
+            token: token.clone(),
+            user: *user,
+        },
+        accounts,
+    )
+}
+
+pub fn set_withdraw_delay_ix(program_id: &Pubkey, admin: &Pubkey, delay_slots: u64) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetWithdrawDelay { delay_slots },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_rate_limit_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    max_ops: u32,
+    window_slots: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetRateLimit {
+            max_ops,
+            window_slots,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// u128::MAX for no cap
+pub fn set_global_cap_ix(program_id: &Pubkey, admin: &Pubkey, cap: u128) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetGlobalCap { cap },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_treasury_ix(program_id: &Pubkey, admin: &Pubkey, treasury: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetTreasury {
+            treasury: *treasury,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_withdrawal_fee_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    fee_bps: u16,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetWithdrawalFee {
+            token: token.clone(),
+            fee_bps,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_deposit_fee_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    fee_bps: u16,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetDepositFee {
+            token: token.clone(),
+            fee_bps,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_fee_exempt_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    user: &Pubkey,
+    exempt: bool,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetFeeExempt {
+            user: *user,
+            exempt,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn withdraw_treasury_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    admin_token_account: &Pubkey,
+    token: &TokenType,
+    treasury: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    token_instruction(
+        program_id,
+        ContractInstruction::AdminWithdrawTreasury {
+            token: token.clone(),
+            amount,
+        },
+        AccountMeta::new(*admin, true),
+        token,
+        treasury,
+        admin_token_account,
+    )
+}
+
+pub fn set_withdraw_delegate_ix(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    delegate: Option<Pubkey>,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::UserSetWithdrawDelegate {
+            user: *user,
+            delegate,
+        },
+        authority_accounts(program_id, user),
+    )
+}
+
+pub fn freeze_user_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminFreezeUser {
+            token: token.clone(),
+            user: *user,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn unfreeze_user_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminUnfreezeUser {
+            token: token.clone(),
+            user: *user,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_blocked_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    user: &Pubkey,
+    blocked: bool,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetBlocked {
+            user: *user,
+            blocked,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_operator_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    operator: Option<Pubkey>,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetOperator { operator },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn propose_new_admin_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    new_admin: &Pubkey,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminProposeNewAdmin {
+            new_admin: *new_admin,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn cancel_proposed_admin_ix(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminCancelProposedAdmin,
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn accept_admin_ix(program_id: &Pubkey, pending_admin: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AcceptAdmin,
+        authority_accounts(program_id, pending_admin),
+    )
+}
+
+pub fn pause_ix(program_id: &Pubkey, admin: &Pubkey, withdrawals_only: bool) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminPause { withdrawals_only },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn unpause_ix(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminUnpause,
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_token_flags_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    deposits_enabled: bool,
+    withdrawals_enabled: bool,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetTokenFlags {
+            token: token.clone(),
+            deposits_enabled,
+            withdrawals_enabled,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_max_tokens_ix(program_id: &Pubkey, admin: &Pubkey, max_tokens: u32) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetMaxTokens { max_tokens },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_deposit_cap_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    cap: Option<u64>,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetDepositCap {
+            token: token.clone(),
+            cap,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// Pubkey::default() as feed removes the token's feed
+pub fn set_price_feed_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    feed: &Pubkey,
+    usd_cap: u64,
+    max_age_slots: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetPriceFeed {
+            token: token.clone(),
+            feed: *feed,
+            usd_cap,
+            max_age_slots,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// a numerator of 0 removes the rate
+pub fn set_swap_rate_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    from: &TokenType,
+    to: &TokenType,
+    numerator: u64,
+    denominator: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetSwapRate {
+            from: from.clone(),
+            to: to.clone(),
+            numerator,
+            denominator,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_max_user_balance_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    max_user_balance: Option<u64>,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetMaxUserBalance {
+            token: token.clone(),
+            max_user_balance,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_min_deposit_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    min_deposit: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetMinDeposit {
+            token: token.clone(),
+            min_deposit,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_daily_withdrawal_limit_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    limit: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetDailyWithdrawalLimit {
+            token: token.clone(),
+            limit,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// [state, user], the token accounts of every item, then the user's balance accounts
+fn batch_instruction(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    items: &[(TokenType, u64, Pubkey)],
+    instruction: ContractInstruction,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*user, true),
+    ];
+    for (token, _, token_account) in items {
+        accounts.extend(token_transfer_accounts(program_id, token, token_account));
+    }
+    let balances: Vec<_> = items
+        .iter()
+        .map(|(token, ..)| (&token.mint, user))
+        .collect();
+    push_balance_accounts(&mut accounts, program_id, &balances);
+    build(program_id, instruction, accounts)
+}
+
+// items are (token, amount, user's token account)
+pub fn batch_deposit_ix(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    items: &[(TokenType, u64, Pubkey)],
+) -> Instruction {
+    let instruction = ContractInstruction::BatchDeposit {
+        user: *user,
+        items: items
+            .iter()
+            .map(|(token, amount, _)| (token.clone(), *amount))
+            .collect(),
+    };
+    with_receipts_account(
+        batch_instruction(program_id, user, items, instruction),
+        user,
+    )
+}
+
+// items as for batch_deposit_ix, with the token accounts receiving the payouts
+pub fn batch_withdraw_ix(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    items: &[(TokenType, u64, Pubkey)],
+) -> Instruction {
+    let instruction = ContractInstruction::BatchWithdraw {
+        user: *user,
+        items: items
+            .iter()
+            .map(|(token, amount, _)| (token.clone(), *amount))
+            .collect(),
+    };
+    batch_instruction(program_id, user, items, instruction)
+}
+
+// from pays for to's balance account
+pub fn transfer_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    from: &Pubkey,
+    to: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*from, true),
+    ];
+    push_balance_accounts(
+        &mut accounts,
+        program_id,
+        &[(&token.mint, from), (&token.mint, to)],
+    );
+    build(
+        program_id,
+        ContractInstruction::UserTransfer {
+            token: token.clone(),
+            from: *from,
+            to: *to,
+            amount,
+        },
+        accounts,
+    )
+}
+
+// treasury is the state's, whose balances take the other side of the swap
+pub fn swap_ix(
+    program_id: &Pubkey,
+    from: &TokenType,
+    to: &TokenType,
+    user: &Pubkey,
+    treasury: &Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*user, true),
+    ];
+    push_balance_accounts(
+        &mut accounts,
+        program_id,
+        &[
+            (&from.mint, user),
+            (&from.mint, treasury),
+            (&to.mint, user),
+            (&to.mint, treasury),
+        ],
+    );
+    build(
+        program_id,
+        ContractInstruction::UserSwap {
+            from: from.clone(),
+            to: to.clone(),
+            user: *user,
+            amount_in,
+            min_amount_out,
+        },
+        accounts,
+    )
+}
+
+pub fn approve_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    owner: &Pubkey,
+    spender: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::UserApprove {
+            token: token.clone(),
+            owner: *owner,
+            spender: *spender,
+            amount,
+        },
+        authority_accounts(program_id, owner),
+    )
+}
+
+// spender pays for to's balance account
+pub fn transfer_from_ix(
+    program_id: &Pubkey,
+    token: &TokenType,
+    owner: &Pubkey,
+    spender: &Pubkey,
+    to: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*spender, true),
+    ];
+    push_balance_accounts(
+        &mut accounts,
+        program_id,
+        &[(&token.mint, owner), (&token.mint, to)],
+    );
+    build(
+        program_id,
+        ContractInstruction::TransferFrom {
+            token: token.clone(),
+            owner: *owner,
+            spender: *spender,
+            to: *to,
+            amount,
+        },
+        accounts,
+    )
+}
+
+pub fn get_balance_ix(program_id: &Pubkey, token: &TokenType, user: &Pubkey) -> Instruction {
+    let (balance, _) = find_balance_address(program_id, &token.mint, user);
+    build(
+        program_id,
+        ContractInstruction::GetBalance {
+            token: token.clone(),
+            user: *user,
+        },
+        vec![
+            AccountMeta::new_readonly(find_state_address(program_id), false),
+            AccountMeta::new_readonly(balance, false),
+        ],
+    )
+}
+
+pub fn link_eth_address_ix(program_id: &Pubkey, user: &Pubkey, signature: Vec<u8>) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::LinkEthAddress {
+            user: *user,
+            signature,
+        },
+        authority_accounts(program_id, user),
+    )
+}
+
+pub fn get_sequence_ix(program_id: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::GetSequence,
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+pub fn get_nonce_ix(program_id: &Pubkey, token: &TokenType, user: &Pubkey) -> Instruction {
+    let (balance, _) = find_balance_address(program_id, &token.mint, user);
+    build(
+        program_id,
+        ContractInstruction::GetNonce {
+            token: token.clone(),
+            user: *user,
+        },
+        vec![
+            AccountMeta::new_readonly(find_state_address(program_id), false),
+            AccountMeta::new_readonly(balance, false),
+        ],
+    )
+}
+
+pub fn list_supported_tokens_ix(program_id: &Pubkey, offset: u32, limit: u32) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::ListSupportedTokens { offset, limit },
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+pub fn get_global_stats_ix(program_id: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::GetGlobalStats,
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+pub fn get_user_stats_ix(program_id: &Pubkey, token: &TokenType, user: &Pubkey) -> Instruction {
+    let (balance, _) = find_balance_address(program_id, &token.mint, user);
+    build(
+        program_id,
+        ContractInstruction::GetUserStats {
+            token: token.clone(),
+            user: *user,
+        },
+        vec![
+            AccountMeta::new_readonly(find_state_address(program_id), false),
+            AccountMeta::new_readonly(balance, false),
+        ],
+    )
+}
+
+pub fn get_receipt_ix(program_id: &Pubkey, user: &Pubkey, receipt_id: u64) -> Instruction {
+    let (receipts, _) = find_receipts_address(program_id, user);
+    build(
+        program_id,
+        ContractInstruction::GetReceipt {
+            user: *user,
+            receipt_id,
+        },
+        vec![
+            AccountMeta::new_readonly(find_state_address(program_id), false),
+            AccountMeta::new_readonly(receipts, false),
+        ],
+    )
+}
+
+pub fn get_queue_position_ix(program_id: &Pubkey, token: &TokenType, user: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::GetQueuePosition {
+            token: token.clone(),
+            user: *user,
+        },
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+pub fn is_token_supported_ix(program_id: &Pubkey, token: &TokenType) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::IsTokenSupported {
+            token: token.clone(),
+        },
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+pub fn get_token_stats_ix(program_id: &Pubkey, token: &TokenType) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::GetTokenStats {
+            token: token.clone(),
+        },
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+// the mint is passed so its decimals can be checked, native SOL has none
+pub fn update_token_metadata_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    metadata: &TokenMetadata,
+) -> Instruction {
+    let mut accounts = authority_accounts(program_id, admin);
+    if !token.is_native() {
+        accounts.push(AccountMeta::new_readonly(token.mint, false));
+    }
+    build(
+        program_id,
+        ContractInstruction::AdminUpdateTokenMetadata {
+            token: token.clone(),
+            metadata: metadata.clone(),
+        },
+        accounts,
+    )
+}
+
+pub fn set_display_precision_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    display_precision: Option<u8>,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetDisplayPrecision {
+            token: token.clone(),
+            display_precision,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_reward_rate_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    points_per_day: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetRewardRate {
+            token: token.clone(),
+            points_per_day,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// mints are the rewarded tokens user holds, the points accrued on them are claimed as well
+pub fn claim_rewards_ix(program_id: &Pubkey, user: &Pubkey, mints: &[Pubkey]) -> Instruction {
+    let mut accounts = authority_accounts(program_id, user);
+    accounts.extend(reward_balance_accounts(program_id, user, mints));
+    build(
+        program_id,
+        ContractInstruction::ClaimRewards { user: *user },
+        accounts,
+    )
+}
+
+// mints as for claim_rewards_ix
+pub fn get_reward_points_ix(program_id: &Pubkey, user: &Pubkey, mints: &[Pubkey]) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(
+        find_state_address(program_id),
+        false,
+    )];
+    accounts.extend(reward_balance_accounts(program_id, user, mints));
+    build(
+        program_id,
+        ContractInstruction::GetRewardPoints { user: *user },
+        accounts,
+    )
+}
+
+// settling points only reads the balances
+fn reward_balance_accounts<'a>(
+    program_id: &'a Pubkey,
+    user: &'a Pubkey,
+    mints: &'a [Pubkey],
+) -> impl Iterator<Item = AccountMeta> + 'a {
+    mints.iter().map(|mint| {
+        let (balance, _) = find_balance_address(program_id, mint, user);
+        AccountMeta::new_readonly(balance, false)
+    })
+}
+
+// admin_token_account is the admin itself for native SOL
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_vested_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    admin_token_account: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+    start_ts: i64,
+    duration_secs: u64,
+) -> Instruction {
+    let instruction = token_instruction(
+        program_id,
+        ContractInstruction::AdminDepositVested {
+            token: token.clone(),
+            user: *user,
+            amount,
+            start_ts,
+            duration_secs,
+        },
+        AccountMeta::new(*admin, true),
+        token,
+        user,
+        admin_token_account,
+    );
+    with_receipts_account(instruction, user)
+}
+
+pub fn get_referral_stats_ix(program_id: &Pubkey, referrer: &Pubkey) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::GetReferralStats {
+            referrer: *referrer,
+        },
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+// balances are the (mint, user) pairs whose balances go into the snapshot
+pub fn snapshot_state_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    destination: &Pubkey,
+    balances: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = authority_accounts(program_id, admin);
+    accounts.push(AccountMeta::new(*destination, false));
+    accounts.extend(balances.iter().map(|(mint, user)| {
+        let (balance, _) = find_balance_address(program_id, mint, user);
+        AccountMeta::new_readonly(balance, false)
+    }));
+    build(
+        program_id,
+        ContractInstruction::AdminSnapshotState,
+        accounts,
+    )
+}
+
+pub fn get_audit_log_ix(program_id: &Pubkey, offset: u32, limit: u32) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::GetAuditLog { offset, limit },
+        vec![AccountMeta::new_readonly(
+            find_state_address(program_id),
+            false,
+        )],
+    )
+}
+
+// signer is user or the admin, it gets the balance account's rent
+pub fn close_user_balance_ix(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+) -> Instruction {
+    let (balance, _) = find_balance_address(program_id, &token.mint, user);
+    let accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*signer, true),
+        AccountMeta::new(balance, false),
+    ];
+    build(
+        program_id,
+        ContractInstruction::CloseUserBalance {
+            token: token.clone(),
+            user: *user,
+        },
+        accounts,
+    )
+}
+
+// holders are the users with a balance of token, every one of them has to be passed
+pub fn emergency_sweep_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    destination: &Pubkey,
+    holders: &[Pubkey],
+) -> Instruction {
+    let mut accounts = authority_accounts(program_id, admin);
+    accounts.extend(token_transfer_accounts(program_id, token, destination));
+    accounts.extend(holders.iter().map(|user| {
+        let (balance, _) = find_balance_address(program_id, &token.mint, user);
+        AccountMeta::new(balance, false)
+    }));
+    build(
+        program_id,
+        ContractInstruction::AdminEmergencySweep {
+            token: token.clone(),
+            destination: *destination,
+        },
+        accounts,
+    )
+}
+
+pub fn rename_token_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    new_symbol: &str,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminRenameToken {
+            old: token.clone(),
+            new_symbol: new_symbol.to_string(),
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// admin_token_account pays what total adds to the token's airdrop
+pub fn set_merkle_root_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    admin_token_account: &Pubkey,
+    token: &TokenType,
+    root: [u8; 32],
+    total: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*admin, true),
+    ];
+    accounts.extend(token_transfer_accounts(
+        program_id,
+        token,
+        admin_token_account,
+    ));
+    build(
+        program_id,
+        ContractInstruction::AdminSetMerkleRoot {
+            token: token.clone(),
+            root,
+            total,
+        },
+        accounts,
+    )
+}
+
+// token must have no balances yet
+pub fn enable_share_mode_ix(program_id: &Pubkey, admin: &Pubkey, token: &TokenType) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminEnableShareMode {
+            token: token.clone(),
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// amount comes out of admin_token_account
+pub fn donate_yield_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    admin_token_account: &Pubkey,
+    token: &TokenType,
+    amount: u64,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*admin, true),
+    ];
+    accounts.extend(token_transfer_accounts(
+        program_id,
+        token,
+        admin_token_account,
+    ));
+    build(
+        program_id,
+        ContractInstruction::AdminDonateYield {
+            token: token.clone(),
+            amount,
+        },
+        accounts,
+    )
+}
+
+// at most user's balance is slashed, the treasury's balance account is created if needed
+pub fn slash_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    treasury: &Pubkey,
+    amount: u64,
+    reason_code: u16,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*admin, true),
+    ];
+    push_balance_accounts(
+        &mut accounts,
+        program_id,
+        &[(&token.mint, user), (&token.mint, treasury)],
+    );
+    build(
+        program_id,
+        ContractInstruction::AdminSlash {
+            token: token.clone(),
+            user: *user,
+            amount,
+            reason_code,
+        },
+        accounts,
+    )
+}
+
+// payer covers the rent of user's balance account if it has none yet
+pub fn claim_airdrop_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+    proof: &[[u8; 32]],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*payer, true),
+    ];
+    push_balance_accounts(&mut accounts, program_id, &[(&token.mint, user)]);
+    build(
+        program_id,
+        ContractInstruction::ClaimAirdrop {
+            token: token.clone(),
+            user: *user,
+            amount,
+            proof: proof.to_vec(),
+        },
+        accounts,
+    )
+}
+
+// signature is the admin's over voucher_message, the transaction needs an ed25519 precompile
+// instruction verifying it ahead of this one, payer covers the rent of user's balance account
+// if it has none yet
+#[allow(clippy::too_many_arguments)]
+pub fn redeem_voucher_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+    voucher_id: u64,
+    expiry: i64,
+    signature: [u8; 64],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(find_state_address(program_id), false),
+        AccountMeta::new(*payer, true),
+    ];
+    push_balance_accounts(
+        &mut accounts,
+        program_id,
+        &[(&token.mint, admin), (&token.mint, user)],
+    );
+    accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    build(
+        program_id,
+        ContractInstruction::RedeemVoucher {
+            token: token.clone(),
+            user: *user,
+            amount,
+            voucher_id,
+            expiry,
+            signature,
+        },
+        accounts,
+    )
+}
+
+// queue action, built by another builder, to run from eta_slot on, authority is the one that
+// may run it
+pub fn queue_action_ix(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    action: &Instruction,
+    eta_slot: u64,
+) -> Instruction {
+    let action = unpack_built(action);
+    build(
+        program_id,
+        ContractInstruction::AdminQueueAction {
+            action: Box::new(action),
+            eta_slot,
+        },
+        authority_accounts(program_id, authority),
+    )
+}
+
+// run the queued action, built by another builder, with its own accounts
+pub fn execute_action_ix(program_id: &Pubkey, action: Instruction) -> Instruction {
+    let instruction = unpack_built(&action);
+    build(
+        program_id,
+        ContractInstruction::AdminExecuteAction {
+            action: Box::new(instruction),
+        },
+        action.accounts,
+    )
+}
+
+pub fn set_timelock_delay_ix(program_id: &Pubkey, admin: &Pubkey, delay_slots: u64) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetTimelockDelay { delay_slots },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_circuit_breaker_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    threshold_bps: u16,
+    window_slots: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetCircuitBreaker {
+            token: token.clone(),
+            threshold_bps,
+            window_slots,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn reset_breaker_ix(program_id: &Pubkey, admin: &Pubkey, token: &TokenType) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminResetBreaker {
+            token: token.clone(),
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_dust_threshold_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    threshold: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetDustThreshold {
+            token: token.clone(),
+            threshold,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_withdraw_cooldown_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    withdraw_cooldown_slots: u64,
+    transfers_reset: bool,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetWithdrawCooldown {
+            token: token.clone(),
+            withdraw_cooldown_slots,
+            transfers_reset,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+pub fn set_min_withdraw_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    token: &TokenType,
+    min_withdraw: u64,
+) -> Instruction {
+    build(
+        program_id,
+        ContractInstruction::AdminSetMinWithdraw {
+            token: token.clone(),
+            min_withdraw,
+        },
+        authority_accounts(program_id, admin),
+    )
+}
+
+// one instruction running calls, built by the other builders, in order
+pub fn multicall_ix(program_id: &Pubkey, calls: &[Instruction]) -> Instruction {
+    let instructions = calls.iter().map(unpack_built).collect();
+    build(
+        program_id,
+        ContractInstruction::Multicall {
+            calls: instructions,
+        },
+        calls
+            .iter()
+            .flat_map(|call| call.accounts.iter().cloned())
+            .collect(),
+    )
+}
@@ -0,0 +1,243 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use solana_program::{decode_error::DecodeError, program_error::ProgramError};
+use thiserror::Error;
+
+// Errors returned by the contract as ProgramError::Custom(code)
+// the numeric codes are part of the interface, never renumber existing variants
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Error, FromPrimitive)]
+pub enum ContractError {
+    #[error("token already added")]
+    TokenAlreadyExists = 0,
+    #[error("deleting a token that was never added")]
+    TokenNotFound = 1,
+    #[error("depositing a token that is not supported")]
+    TokenNotSupportedForDeposit = 2,
+    #[error("withdrawing a token that is not supported")]
+    TokenNotSupportedForWithdraw = 3,
+    #[error("querying a token that is not supported")]
+    TokenNotSupportedForQuery = 4,
+    #[error("token account holds another mint")]
+    TokenAccountMintMismatch = 5,
+    #[error("vault is not controlled by the vault authority PDA")]
+    InvalidVaultAuthority = 6,
+    #[error("config already initialized")]
+    ConfigAlreadyInitialized = 7,
+    #[error("config not initialized")]
+    ConfigNotInitialized = 8,
+    #[error("no admin proposed")]
+    NoPendingAdmin = 9,
+    #[error("balance overflow")]
+    BalanceOverflow = 10,
+    #[error("users still hold balances of the token")]
+    TokenHasOutstandingBalances = 11,
+    #[error("force delete not confirmed")]
+    ForceDeleteNotConfirmed = 12,
+    #[error("contract is paused")]
+    ContractPaused = 13,
+    #[error("deposits of the token are disabled")]
+    DepositsDisabled = 14,
+    #[error("withdrawals of the token are disabled")]
+    WithdrawalsDisabled = 15,
+    #[error("amount must be greater than zero")]
+    ZeroAmount = 16,
+    #[error("transfer to self")]
+    SelfTransfer = 17,
+    #[error("too many items in batch")]
+    BatchTooLarge = 18,
+    #[error("mint account is not the token's mint")]
+    MintAccountMismatch = 19,
+    #[error("token symbol must be 1 to 12 ASCII letters or digits")]
+    InvalidTokenSymbol = 20,
+    #[error("maximum number of supported tokens reached")]
+    TokenLimitReached = 21,
+    #[error("deposit would exceed the token's deposit cap")]
+    DepositCapExceeded = 22,
+    #[error("credit would exceed the token's per-user balance cap")]
+    UserBalanceCapExceeded = 23,
+    #[error("deposit is below the token's minimum")]
+    DepositBelowMinimum = 24,
+    #[error("withdrawal exceeds the remaining daily allowance")]
+    DailyLimitExceeded = 25,
+    #[error("a withdraw request is already pending")]
+    WithdrawRequestPending = 26,
+    #[error("no withdraw request pending")]
+    NoWithdrawRequest = 27,
+    #[error("withdraw request is still timelocked")]
+    WithdrawRequestLocked = 28,
+    #[error("fee above MAX_FEE_BPS")]
+    FeeTooHigh = 29,
+    #[error("treasury not configured")]
+    TreasuryNotSet = 30,
+    #[error("spender has no allowance")]
+    NoAllowance = 31,
+    #[error("amount exceeds the remaining allowance")]
+    AllowanceExceeded = 32,
+    #[error("delegated and Ethereum-signed withdrawals can only pay out to the user's own token account")]
+    DelegateRecipientNotUser = 33,
+    #[error("user's balance of the token is frozen")]
+    AccountFrozen = 34,
+    #[error("user is blocked")]
+    UserBlocked = 35,
+    #[error("the admin can't be blocked")]
+    CannotBlockAdmin = 36,
+    #[error("the operator can't perform admin-only instructions")]
+    InsufficientAuthority = 37,
+    #[error("nonce doesn't match the user's current nonce")]
+    InvalidNonce = 38,
+    #[error("state account schema version isn't supported, run MigrateState")]
+    UnsupportedStateVersion = 39,
+    #[error("state account is already at the current schema version")]
+    StateAlreadyCurrent = 40,
+    #[error("malformed, high-s or unrecoverable secp256k1 signature")]
+    InvalidEthSignature = 41,
+    #[error("the signing Ethereum address isn't linked to the user")]
+    EthAddressNotLinked = 42,
+    #[error("Ethereum address is linked to another user")]
+    EthAddressAlreadyLinked = 43,
+    #[error("balance account isn't the PDA of the mint and user it holds")]
+    InvalidBalanceAccount = 44,
+    #[error("a balance account the instruction credits wasn't passed")]
+    MissingBalanceAccount = 45,
+    #[error("vault isn't the vault authority's associated token account of the mint")]
+    InvalidVaultAccount = 46,
+    #[error("token's vault still holds tokens")]
+    VaultNotEmpty = 47,
+    #[error("the SOL symbol and the native mint are reserved for TokenType::native")]
+    ReservedToken = 48,
+    #[error("account isn't owned by the program expected to own it")]
+    WrongOwner = 49,
+    #[error("account holds too few lamports to be rent-exempt")]
+    NotRentExempt = 50,
+    #[error("account isn't the PDA derived for it")]
+    InvalidPda = 51,
+    #[error("program account isn't the expected program")]
+    InvalidProgramId = 52,
+    #[error("token name is longer than MAX_TOKEN_NAME_LEN bytes")]
+    TokenNameTooLong = 53,
+    #[error("token decimals exceed MAX_DECIMALS")]
+    InvalidDecimals = 54,
+    #[error("metadata URI is longer than MAX_METADATA_URI_LEN bytes")]
+    MetadataUriTooLong = 55,
+    #[error("token decimals differ from the mint's")]
+    DecimalsMismatch = 56,
+    #[error("display precision exceeds the token's decimals")]
+    InvalidDisplayPrecision = 57,
+    #[error("deposit isn't a multiple of the smallest amount the token displays")]
+    DustDeposit = 58,
+    #[error("lock_until isn't in the future")]
+    InvalidLockTime = 59,
+    #[error("too many locked lots of one token")]
+    TooManyLockedLots = 60,
+    #[error("balance is locked")]
+    BalanceLocked = 61,
+    #[error("too many vesting schedules of one token")]
+    TooManyVestingSchedules = 62,
+    #[error("a user can't refer itself")]
+    SelfReferral = 63,
+    #[error("snapshot doesn't fit in the destination account")]
+    SnapshotBufferTooSmall = 64,
+    #[error("snapshot destination is the state or a balance account")]
+    InvalidSnapshotAccount = 65,
+    #[error("balance must be zero to be closed")]
+    BalanceNotZero = 66,
+    #[error("only allowed while the contract is paused")]
+    ContractNotPaused = 67,
+    #[error("balance accounts passed don't cover the token's deposits")]
+    SweepIncomplete = 68,
+    #[error("mint has a Token-2022 extension the vault can't support")]
+    UnsupportedMintExtension = 69,
+    #[error("payout account isn't owned by the withdrawal's recipient")]
+    RecipientMismatch = 70,
+    #[error("memo is longer than 64 bytes")]
+    MemoTooLong = 71,
+    #[error("instruction can't be a call of a multicall")]
+    CallNotBatchable = 72,
+    #[error("state account can't grow to fit the state")]
+    StateAccountFull = 73,
+    #[error("account isn't a writable state account of this program")]
+    InvalidStateAccount = 74,
+    #[error("mint is already listed under another symbol")]
+    MintAlreadyRegistered = 75,
+    #[error("token has no airdrop")]
+    NoAirdrop = 76,
+    #[error("merkle proof doesn't lead to the airdrop's root")]
+    InvalidMerkleProof = 77,
+    #[error("airdrop already claimed")]
+    AirdropAlreadyClaimed = 78,
+    #[error("claim exceeds what is left of the airdrop's total")]
+    AirdropExhausted = 79,
+    #[error("less than the airdrop's total reached the vault")]
+    AirdropUnderfunded = 80,
+    #[error("the voucher has expired")]
+    VoucherExpired = 81,
+    #[error("the voucher was already redeemed")]
+    VoucherAlreadyRedeemed = 82,
+    #[error("the voucher isn't signed by the admin")]
+    InvalidVoucherSignature = 83,
+    #[error("too many operations in the current rate limit window")]
+    RateLimited = 84,
+    #[error("deposit would exceed the global cap")]
+    GlobalCapExceeded = 85,
+    #[error("price feed account is not a Pyth price currently trading")]
+    InvalidPriceFeed = 86,
+    #[error("price was published too many slots ago")]
+    StalePrice = 87,
+    #[error("deposit would exceed the token's USD cap")]
+    UsdCapExceeded = 88,
+    #[error("no swap rate is set between these tokens")]
+    SwapRateNotSet = 89,
+    #[error("swap would return less than min_amount_out")]
+    SlippageExceeded = 90,
+    #[error("amount is worth no shares, or the shares are worth nothing")]
+    ZeroShares = 91,
+    #[error("instruction is timelocked, queue it with AdminQueueAction")]
+    TimelockRequired = 92,
+    #[error("action was not queued")]
+    ActionNotQueued = 93,
+    #[error("action's timelock has not elapsed yet")]
+    TimelockNotElapsed = 94,
+    #[error("queued action expired")]
+    ActionExpired = 95,
+    #[error("action is already queued")]
+    ActionAlreadyQueued = 96,
+    #[error("token's circuit breaker tripped, only AdminResetBreaker re-enables withdrawals")]
+    CircuitBreakerTripped = 97,
+    #[error("user deposited too recently to withdraw")]
+    CooldownActive = 98,
+    #[error("withdrawal is below the token's minimum and not the whole balance")]
+    WithdrawBelowMinimum = 99,
+    #[error("no such receipt, or it was evicted")]
+    ReceiptNotFound = 100,
+    #[error("the token's withdrawal queue is full")]
+    WithdrawQueueFull = 101,
+    #[error("the user already has a withdrawal queued for the token")]
+    WithdrawAlreadyQueued = 102,
+    #[error("the user has no withdrawal queued for the token")]
+    NotInWithdrawQueue = 103,
+    #[error("the batch lists the same symbol or mint twice")]
+    DuplicateTokenInBatch = 104,
+    #[error("the receipts account of a user the instruction issues a receipt to wasn't passed")]
+    MissingReceiptsAccount = 105,
+}
+
+impl From<ContractError> for ProgramError {
+    fn from(e: ContractError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for ContractError {
+    fn type_of() -> &'static str {
+        "ContractError"
+    }
+}
+
+// decode the code of a ProgramError::Custom, e.g. from transaction logs
+impl TryFrom<u32> for ContractError {
+    type Error = ProgramError;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        ContractError::from_u32(code).ok_or(ProgramError::InvalidArgument)
+    }
+}
@@ -0,0 +1,202 @@
+// events logged with sol_log_data for off-chain indexers
+// every event is logged as two data fields: its NAME and its borsh encoding
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{log::sol_log_data, pubkey::Pubkey};
+
+pub trait Event: BorshSerialize + BorshDeserialize {
+    const NAME: &'static str;
+
+    fn emit(&self) {
+        // plain structs always serialize
+        sol_log_data(&[Self::NAME.as_bytes(), &borsh::to_vec(self).unwrap()]);
+    }
+
+    // the event held by a log's data fields, None for another event or garbage
+    fn decode(fields: &[&[u8]]) -> Option<Self> {
+        match fields {
+            [name, data] if *name == Self::NAME.as_bytes() => Self::try_from_slice(data).ok(),
+            _ => None,
+        }
+    }
+}
+
+// amount left payer's token account and was credited to user, new_balance is the user's
+// ledger balance after the deposit fee, referrer is the one credited with the amount's volume,
+// memo is the deposit's as given, the slots are the balance's after the deposit, receipt_id
+// is the id of the deposit's receipt
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct DepositEvent {
+    pub token_symbol: String,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub sequence: u64,
+    pub referrer: Option<Pubkey>,
+    pub payer: Pubkey,
+    pub memo: Option<String>,
+    pub last_deposit_slot: u64,
+    pub last_withdraw_slot: u64,
+    pub receipt_id: u64,
+}
+
+impl Event for DepositEvent {
+    const NAME: &'static str = "DepositEvent";
+}
+
+// amount was debited from the user's ledger balance, the payout is amount minus the fee
+// and went to recipient, the owner of the payout account, memo is the withdrawal's as given
+// dust_swept is what was left at or below the token's dust threshold and went to the treasury
+// the slots are the balance's after the withdrawal
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct WithdrawEvent {
+    pub token_symbol: String,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub sequence: u64,
+    pub recipient: Pubkey,
+    pub memo: Option<String>,
+    pub dust_swept: u64,
+    pub last_deposit_slot: u64,
+    pub last_withdraw_slot: u64,
+}
+
+impl Event for WithdrawEvent {
+    const NAME: &'static str = "WithdrawEvent";
+}
+
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct TokenAddedEvent {
+    pub token_symbol: String,
+    pub mint: Pubkey,
+    pub sequence: u64,
+}
+
+impl Event for TokenAddedEvent {
+    const NAME: &'static str = "TokenAddedEvent";
+}
+
+// the token listed as old_symbol is listed as new_symbol from now on
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct TokenRenamedEvent {
+    pub old_symbol: String,
+    pub new_symbol: String,
+    pub mint: Pubkey,
+    pub sequence: u64,
+}
+
+impl Event for TokenRenamedEvent {
+    const NAME: &'static str = "TokenRenamedEvent";
+}
+
+// also emitted by a force delete
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct TokenRemovedEvent {
+    pub token_symbol: String,
+    pub mint: Pubkey,
+    pub sequence: u64,
+}
+
+impl Event for TokenRemovedEvent {
+    const NAME: &'static str = "TokenRemovedEvent";
+}
+
+// amount of the token's airdrop was credited to user, new_balance is the user's balance after it
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct AirdropClaimedEvent {
+    pub token_symbol: String,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub sequence: u64,
+}
+
+impl Event for AirdropClaimedEvent {
+    const NAME: &'static str = "AirdropClaimedEvent";
+}
+
+// amount of the token moved from the admin's balance to user's by the voucher voucher_id,
+// new_balance is the user's balance after it
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct VoucherRedeemedEvent {
+    pub token_symbol: String,
+    pub user: Pubkey,
+    pub voucher_id: u64,
+    pub amount: u64,
+    pub new_balance: u64,
+    pub sequence: u64,
+}
+
+impl Event for VoucherRedeemedEvent {
+    const NAME: &'static str = "VoucherRedeemedEvent";
+}
+
+// points were zeroed, the payout is made off-chain
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct RewardsClaimedEvent {
+    pub user: Pubkey,
+    pub points: u64,
+    pub sequence: u64,
+}
+
+impl Event for RewardsClaimedEvent {
+    const NAME: &'static str = "RewardsClaimedEvent";
+}
+
+// amount of a user's balance, pending withdraw request included, AdminEmergencySweep moved
+// to destination
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct SweepEvent {
+    pub token_symbol: String,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub destination: Pubkey,
+    pub sequence: u64,
+}
+
+impl Event for SweepEvent {
+    const NAME: &'static str = "SweepEvent";
+}
+
+// amount of user's balance AdminSlash moved to the treasury for reason_code, user_balance and
+// treasury_balance are their balances after it
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct SlashEvent {
+    pub token_symbol: String,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub reason_code: u16,
+    pub user_balance: u64,
+    pub treasury_balance: u64,
+    pub sequence: u64,
+}
+
+impl Event for SlashEvent {
+    const NAME: &'static str = "SlashEvent";
+}
+
+// withdrawn of total_deposited, counting withdrawn, left within the window starting at
+// window_start_slot, more than the token's circuit breaker allows, its withdrawals are disabled
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct CircuitBreakerTrippedEvent {
+    pub token_symbol: String,
+    pub withdrawn: u64,
+    pub total_deposited: u64,
+    pub window_start_slot: u64,
+    pub sequence: u64,
+}
+
+impl Event for CircuitBreakerTrippedEvent {
+    const NAME: &'static str = "CircuitBreakerTrippedEvent";
+}
@@ -0,0 +1,196 @@
+// machine-readable description of the program for indexers and codegen: every instruction with
+// its discriminators and arguments, the types they use, the events and the error codes
+// derived from the borsh schemas, so it can't describe an encoding the program doesn't use,
+// idl/solana_test.json is its checked-in output (see tests/idl.rs)
+// accounts aren't listed, they are only documented on ContractInstruction and in client.rs
+use std::collections::BTreeMap;
+
+use borsh::schema::{Declaration, Definition, Fields};
+use borsh::BorshSchema;
+use num_traits::FromPrimitive;
+use serde_json::{json, Value};
+
+use crate::error::ContractError;
+use crate::events::{
+    AirdropClaimedEvent, CircuitBreakerTrippedEvent, DepositEvent, Event, RewardsClaimedEvent,
+    SlashEvent, SweepEvent, TokenAddedEvent, TokenRemovedEvent, TokenRenamedEvent,
+    VoucherRedeemedEvent, WithdrawEvent,
+};
+use crate::instruction::{
+    ContractInstruction, INSTRUCTION_DISCRIMINATORS, INSTRUCTION_VERSION_BORSH,
+};
+
+pub fn generate_idl() -> Value {
+    let mut definitions = BTreeMap::new();
+    ContractInstruction::add_definitions_recursively(&mut definitions);
+    let mut types = BTreeMap::new();
+
+    let Some(Definition::Enum { variants, .. }) =
+        definitions.get(&ContractInstruction::declaration())
+    else {
+        unreachable!("ContractInstruction is an enum");
+    };
+    let instructions: Vec<Value> = variants
+        .iter()
+        .map(|(tag, name, declaration)| {
+            let (snake_case, discriminator) = INSTRUCTION_DISCRIMINATORS[*tag as usize];
+            json!({
+                "name": name,
+                "snake_case": snake_case,
+                "tag": tag,
+                "discriminator": discriminator,
+                "args": fields(declaration, &definitions, &mut types),
+            })
+        })
+        .collect();
+
+    let events = vec![
+        event::<DepositEvent>(&mut types),
+        event::<WithdrawEvent>(&mut types),
+        event::<TokenAddedEvent>(&mut types),
+        event::<TokenRemovedEvent>(&mut types),
+        event::<TokenRenamedEvent>(&mut types),
+        event::<RewardsClaimedEvent>(&mut types),
+        event::<AirdropClaimedEvent>(&mut types),
+        event::<VoucherRedeemedEvent>(&mut types),
+        event::<SweepEvent>(&mut types),
+        event::<SlashEvent>(&mut types),
+        event::<CircuitBreakerTrippedEvent>(&mut types),
+    ];
+
+    // codes are dense from 0, see test_contract_error_codes
+    let errors: Vec<Value> = (0..)
+        .map_while(ContractError::from_u32)
+        .map(|error| {
+            json!({
+                "code": error as u32,
+                "name": format!("{:?}", error),
+                "msg": error.to_string(),
+            })
+        })
+        .collect();
+
+    json!({
+        "name": "solana_test",
+        "version": env!("CARGO_PKG_VERSION"),
+        "instruction_version": INSTRUCTION_VERSION_BORSH,
+        "instructions": instructions,
+        "types": types,
+        "events": events,
+        "errors": errors,
+    })
+}
+
+fn event<E: Event + BorshSchema>(types: &mut BTreeMap<String, Value>) -> Value {
+    let mut definitions = BTreeMap::new();
+    E::add_definitions_recursively(&mut definitions);
+    json!({
+        "name": E::NAME,
+        "fields": fields(&E::declaration(), &definitions, types),
+    })
+}
+
+// [{name, type}] of a struct's fields, empty for a unit variant
+fn fields(
+    declaration: &Declaration,
+    definitions: &BTreeMap<Declaration, Definition>,
+    types: &mut BTreeMap<String, Value>,
+) -> Vec<Value> {
+    match definitions.get(declaration) {
+        Some(Definition::Struct {
+            fields: Fields::NamedFields(fields),
+        }) => fields
+            .iter()
+            .map(|(name, declaration)| {
+                json!({ "name": name, "type": idl_type(declaration, definitions, types) })
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// the IDL type of a declaration, structs and enums are named and described once in types
+fn idl_type(
+    declaration: &Declaration,
+    definitions: &BTreeMap<Declaration, Definition>,
+    types: &mut BTreeMap<String, Value>,
+) -> Value {
+    match declaration.as_str() {
+        "Pubkey" => return json!("pubkey"),
+        "String" => return json!("string"),
+        _ => {}
+    }
+    // its variants are the instructions, and a type refering to itself is described already
+    if *declaration == ContractInstruction::declaration() || types.contains_key(declaration) {
+        return json!({ "defined": declaration });
+    }
+    match definitions.get(declaration) {
+        Some(Definition::Primitive(_)) | None => json!(declaration),
+        Some(Definition::Sequence {
+            length_width: 0,
+            length_range,
+            elements,
+        }) => json!({ "array": [idl_type(elements, definitions, types), length_range.end()] }),
+        Some(Definition::Sequence { elements, .. }) => {
+            json!({ "vec": idl_type(elements, definitions, types) })
+        }
+        Some(Definition::Tuple { elements }) => json!({
+            "tuple": elements
+                .iter()
+                .map(|element| idl_type(element, definitions, types))
+                .collect::<Vec<_>>(),
+        }),
+        Some(Definition::Enum { variants, .. }) if declaration.starts_with("Option<") => {
+            json!({ "option": idl_type(&variants[1].2, definitions, types) })
+        }
+        Some(Definition::Enum { variants, .. }) => {
+            types.insert(declaration.clone(), Value::Null);
+            let variants: Vec<Value> = variants
+                .iter()
+                .map(|(_, name, variant)| {
+                    json!({ "name": name, "fields": fields(variant, definitions, types) })
+                })
+                .collect();
+            types.insert(
+                declaration.clone(),
+                json!({ "kind": "enum", "variants": variants }),
+            );
+            json!({ "defined": declaration })
+        }
+        Some(Definition::Struct { .. }) => {
+            types.insert(declaration.clone(), Value::Null);
+            let fields = fields(declaration, definitions, types);
+            types.insert(
+                declaration.clone(),
+                json!({ "kind": "struct", "fields": fields }),
+            );
+            json!({ "defined": declaration })
+        }
+    }
+}
+
+// schema of Multicall's calls, a Vec<ContractInstruction> without descending into
+// ContractInstruction again, which the derived schema would do forever
+// its variants are the instructions, the IDL doesn't repeat them as a type
+pub(crate) fn calls_declaration() -> Declaration {
+    "Vec<ContractInstruction>".to_string()
+}
+
+// the action of AdminQueueAction and AdminExecuteAction, ContractInstruction itself
+pub(crate) fn action_declaration() -> Declaration {
+    ContractInstruction::declaration()
+}
+
+// defined along with the enum it is in
+pub(crate) fn add_action_definitions(_definitions: &mut BTreeMap<Declaration, Definition>) {}
+
+pub(crate) fn add_calls_definitions(definitions: &mut BTreeMap<Declaration, Definition>) {
+    definitions.insert(
+        calls_declaration(),
+        Definition::Sequence {
+            length_width: 4,
+            length_range: 0..=u32::MAX as u64,
+            elements: ContractInstruction::declaration(),
+        },
+    );
+}
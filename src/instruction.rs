@@ -0,0 +1,2302 @@
+// instructions the program accepts and how their data is encoded
+use borsh::{BorshDeserialize, BorshSerialize};
+use serde::{Deserialize, Serialize};
+use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::state::{AuditEntry, TokenMetadata, TokenType, MAX_SYMBOL_LEN};
+
+// Define the instructions that the contract can accept
+// UserDeposit and UserWithdraw signed off-chain can be relayed, see ed25519_signers, the nonce
+// they consume keeps the signed payload from being replayed
+// every (user, token) balance an instruction reads or changes, the treasury's included when a
+// fee is credited, needs its balance account (see find_balance_address) after the listed
+// accounts, plus the system program when one has to be created, accounts[1] pays its rent
+// every deposit likewise needs the receipts account (see find_receipts_address) of the user it
+// credits, accounts[1] paying for it to be created and to grow by a receipt
+// the same goes for the state account, grown by up to 10 KiB an instruction when the state
+// written back doesn't fit it, otherwise the instruction fails with StateAccountFull
+// tokens of a Token-2022 mint pass that program as token_program, followed by the mint, and
+// their vault is the one of find_vault_address_with_program_id, transfer fees are never
+// credited, a withdrawal pays out the amount debited minus the fee
+#[derive(Clone, Serialize, Deserialize, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub enum ContractInstruction {
+    // create the state PDA (if needed) and write an empty ContractState into it
+    InitializeState,
+    // upgrade the state account one schema version towards STATE_VERSION
+    // accounts: [state, admin]
+    MigrateState,
+    // one-time setup of the admin allowed to manage tokens
+    // accounts: [state, initializer]
+    InitializeConfig {
+        admin: Pubkey,
+    },
+    // symbol and mint must both be unused, accounts: [state, admin, mint, vault_token_account]
+    // the vault has to exist already, it is the associated token account of the vault
+    // authority (see find_vault_address)
+    // TokenType::native() takes [state, admin, vault, system_program] instead, the vault PDA is
+    // made rent-exempt and taken over, admin pays
+    // metadata must pass validate_token_metadata and carry the mint's decimals
+    // the system program may follow, the state account is grown when the new entry doesn't
+    // fit, admin paying the extra rent
+    AdminAddSupportedToken {
+        token: TokenType,
+        metadata: TokenMetadata,
+    },
+    // fails while any user still holds a balance or the vault isn't empty
+    // accounts: [state, admin, vault_token_account]
+    AdminDeleteSupportedToken {
+        token: TokenType,
+    },
+    // delete even with outstanding balances, destroying them, confirm must be true
+    // accounts: [state, admin]
+    AdminForceDeleteToken {
+        token: TokenType,
+        confirm: bool,
+    },
+    // nonce must equal the one of user's balance account of token (see GetNonce), it is bumped
+    // on success
+    // with lock_until set the amount credited stays locked until that unix time, no instruction
+    // can take the balance below what is still locked
+    // the user's first deposit binds referrer for good, later ones are credited to it whatever
+    // they name
+    // with beneficiary set, user still signs, funds the deposit and uses up its nonce, but the
+    // credit, lock and referral go to beneficiary, which signs nothing and may be new, and
+    // beneficiary's balance account is passed before user's, neither of them may be blocked
+    // memo, at most MAX_MEMO_LEN bytes, is copied into the DepositEvent and also logged by the
+    // SPL Memo program when its account is passed after the others
+    // accounts: [state, user, user_token_account, vault_token_account, vault_authority, token_program]
+    // for native SOL: [state, user, user, vault, vault, system_program]
+    UserDeposit {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+        nonce: u64,
+        lock_until: Option<i64>,
+        referrer: Option<Pubkey>,
+        beneficiary: Option<Pubkey>,
+        memo: Option<String>,
+    },
+    // nonce as for UserDeposit, accounts: same as UserDeposit, native SOL is paid out to the
+    // system account in place of user_token_account, never below the vault's rent-exempt minimum
+    // with eth_signature set, user authorizes it with a linked Ethereum key (see LinkEthAddress)
+    // instead of signing, the 65-byte [r, s, v] signature is over keccak256 of eth_withdraw_message
+    // and the payout must go to a token account owned by user
+    // with recipient set, the payout must go to a token account it owns (its system account for
+    // SOL), user's ledger balance is debited all the same and recipient's is never credited
+    // memo as for UserDeposit, eth_signature doesn't cover it
+    UserWithdraw {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+        nonce: u64,
+        eth_signature: Option<Vec<u8>>,
+        recipient: Option<Pubkey>,
+        memo: Option<String>,
+    },
+    // withdraw the user's whole liquid balance and close the user's balance account unless some
+    // of it is still locked, rent goes back to accounts[1], a zero balance succeeds
+    // accounts: same as UserDeposit
+    UserWithdrawAll {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // lock amount out of the available balance, claimable after withdraw_delay_slots
+    // accounts: [state, user]
+    UserRequestWithdraw {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+    },
+    // complete the pending request, accounts: same as UserDeposit
+    UserClaimWithdraw {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // return a pending request's amount to the user's balance, accounts: [state, admin]
+    AdminCancelWithdrawRequest {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // accounts: [state, admin]
+    AdminSetWithdrawDelay {
+        delay_slots: u64,
+    },
+    // accounts: [state, admin]
+    AdminSetTreasury {
+        treasury: Pubkey,
+    },
+    // fee in basis points taken from every withdrawal, accounts: [state, admin]
+    AdminSetWithdrawalFee {
+        token: TokenType,
+        fee_bps: u16,
+    },
+    // fee in basis points taken from every deposit, accounts: [state, admin]
+    AdminSetDepositFee {
+        token: TokenType,
+        fee_bps: u16,
+    },
+    // exempt users from deposit and withdrawal fees of all tokens, accounts: [state, admin]
+    AdminSetFeeExempt {
+        user: Pubkey,
+        exempt: bool,
+    },
+    // pay out accumulated fees
+    // accounts: [state, admin, admin_token_account, vault_token_account, vault_authority, token_program]
+    AdminWithdrawTreasury {
+        token: TokenType,
+        amount: u64,
+    },
+    // let delegate sign UserWithdraw, UserWithdrawAll and UserClaimWithdraw for user, None
+    // revokes, accounts: [state, user]
+    UserSetWithdrawDelegate {
+        user: Pubkey,
+        delegate: Option<Pubkey>,
+    },
+    // block withdrawals and outgoing transfers of user's balance, also allowed before user
+    // holds anything, accounts: [state, admin]
+    AdminFreezeUser {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // accounts: [state, admin]
+    AdminUnfreezeUser {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // the admin itself can't be blocked, accounts: [state, admin]
+    AdminSetBlocked {
+        user: Pubkey,
+        blocked: bool,
+    },
+    // set or clear (None) the operator, accounts: [state, admin]
+    AdminSetOperator {
+        operator: Option<Pubkey>,
+    },
+    // first step of admin rotation, accounts: [state, admin]
+    AdminProposeNewAdmin {
+        new_admin: Pubkey,
+    },
+    // drop the pending proposal, accounts: [state, admin]
+    AdminCancelProposedAdmin,
+    // second step of admin rotation, accounts: [state, pending_admin]
+    AcceptAdmin,
+    // halt deposits, and withdrawals too unless withdrawals_only, accounts: [state, admin]
+    AdminPause {
+        withdrawals_only: bool,
+    },
+    // accounts: [state, admin]
+    AdminUnpause,
+    // per-token switches, independent of the global pause, accounts: [state, admin]
+    AdminSetTokenFlags {
+        token: TokenType,
+        deposits_enabled: bool,
+        withdrawals_enabled: bool,
+    },
+    // lowering it below the current count only blocks further adds, accounts: [state, admin]
+    AdminSetMaxTokens {
+        max_tokens: u32,
+    },
+    // a cap below the current total keeps existing balances but blocks new deposits
+    // accounts: [state, admin]
+    AdminSetDepositCap {
+        token: TokenType,
+        cap: Option<u64>,
+    },
+    // balances already above a lowered ceiling are kept, only new credits are refused
+    // accounts: [state, admin]
+    AdminSetMaxUserBalance {
+        token: TokenType,
+        max_user_balance: Option<u64>,
+    },
+    // accounts: [state, admin]
+    AdminSetMinDeposit {
+        token: TokenType,
+        min_deposit: u64,
+    },
+    // 0 removes the limit, accounts: [state, admin]
+    AdminSetDailyWithdrawalLimit {
+        token: TokenType,
+        limit: u64,
+    },
+    // deposit several tokens at once, all or nothing, at most MAX_BATCH_ITEMS items
+    // accounts: [state, user] followed by UserDeposit's token accounts for every item
+    BatchDeposit {
+        user: Pubkey,
+        items: Vec<(TokenType, u64)>,
+    },
+    // mirror of BatchDeposit, accounts: same as BatchDeposit
+    BatchWithdraw {
+        user: Pubkey,
+        items: Vec<(TokenType, u64)>,
+    },
+    // move internal balance between two users without touching the vault
+    // accounts: [state, from]
+    UserTransfer {
+        token: TokenType,
+        from: Pubkey,
+        to: Pubkey,
+        amount: u64,
+    },
+    // let spender move up to amount of owner's balance, 0 revokes, accounts: [state, owner]
+    UserApprove {
+        token: TokenType,
+        owner: Pubkey,
+        spender: Pubkey,
+        amount: u64,
+    },
+    // internal transfer out of owner's balance against an allowance, accounts: [state, spender]
+    TransferFrom {
+        token: TokenType,
+        owner: Pubkey,
+        spender: Pubkey,
+        to: Pubkey,
+        amount: u64,
+    },
+    // query user's balance of token, a borsh UserBalance via return data
+    // accounts: [state, balance account]
+    GetBalance {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // map the Ethereum address recovered from signature to user, signature is over
+    // keccak256 of eth_link_message, accounts: [state, user]
+    LinkEthAddress {
+        user: Pubkey,
+        signature: Vec<u8>,
+    },
+    // query the sequence number of the last state change, u64 LE via return data
+    GetSequence,
+    // query the nonce user's next UserDeposit/UserWithdraw of token must carry, u64 LE via
+    // return data, accounts: [state, balance account]
+    GetNonce {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // page of the supported tokens sorted by symbol, a borsh TokenPage via return data
+    // limit is capped at MAX_TOKENS_PER_PAGE and the page ends early if the return data would
+    // overflow, an offset past the end gives an empty page, accounts: [state]
+    ListSupportedTokens {
+        offset: u32,
+        limit: u32,
+    },
+    // aggregates of one token, a borsh TokenStats via return data, accounts: [state]
+    GetTokenStats {
+        token: TokenType,
+    },
+    // replace a listed token's metadata, checked as for AdminAddSupportedToken
+    // accounts: [state, admin, mint], native SOL takes [state, admin]
+    AdminUpdateTokenMetadata {
+        token: TokenType,
+        metadata: TokenMetadata,
+    },
+    // at most the token's decimals, None lifts the rule, accounts: [state, admin]
+    AdminSetDisplayPrecision {
+        token: TokenType,
+        display_precision: Option<u8>,
+    },
+    // points per whole token held for a day from now on, 0 stops the accrual, points earned so
+    // far are kept, accounts: [state, admin]
+    // points are settled for every balance account an instruction is passed, at the balance
+    // it had since they were last settled
+    AdminSetRewardRate {
+        token: TokenType,
+        points_per_day: u64,
+    },
+    // zero user's points, the payout happens off-chain from the RewardsClaimedEvent
+    // accounts: [state, user], followed by the user's balance accounts of the rewarded tokens
+    // to claim the points accrued on them up to now
+    ClaimRewards {
+        user: Pubkey,
+    },
+    // user's points as a u64 LE via return data, accounts: [state], followed by balance
+    // accounts as for ClaimRewards to include the points accrued on them up to now
+    GetRewardPoints {
+        user: Pubkey,
+    },
+    // deposit amount from the admin's token account for user, vesting linearly from start_ts
+    // over duration_secs, the unvested part is locked as with UserDeposit's lock_until
+    // accounts: same as AdminWithdrawTreasury, followed by user's balance account and the
+    // system program
+    AdminDepositVested {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+        start_ts: i64,
+        duration_secs: u64,
+    },
+    // volume referrer's referees deposited, a borsh ReferralStats via return data
+    // accounts: [state]
+    GetReferralStats {
+        referrer: Pubkey,
+    },
+    // write the state to destination, a program owned account other than the state and the
+    // balance accounts, laid out as SNAPSHOT_HEADER_LEN describes, see decode_snapshot
+    // the balances exported are those of the balance accounts passed
+    // accounts: [state, admin, destination], followed by balance accounts
+    AdminSnapshotState,
+    // page of the audit log newest first, a borsh AuditPage via return data, limit is capped
+    // at MAX_AUDIT_ENTRIES_PER_PAGE, an offset past the end gives an empty page
+    // accounts: [state]
+    GetAuditLog {
+        offset: u32,
+        limit: u32,
+    },
+    // drop user's zero balance of token and close its balance account, the rent goes to the
+    // signer, user or the admin, a balance withdrawn in full is closed by the withdrawal
+    // accounts: [state, signer, user's balance account]
+    CloseUserBalance {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // while paused, move token's whole vault to the destination token account and zero every
+    // balance and withdraw request of it, a SweepEvent per user records what they lost
+    // accounts: same as AdminWithdrawTreasury with destination as the admin's token account,
+    // followed by the balance accounts of every user holding the token
+    AdminEmergencySweep {
+        token: TokenType,
+        destination: Pubkey,
+    },
+    // run up to 8 calls in order against the same state, it's written back only if every one of
+    // them succeeded, each call is authorized and takes a sequence number as it would alone
+    // queries, InitializeState, MigrateState and Multicall itself can't be called
+    // accounts: the accounts of every call one after another, each starting with the state
+    // account, a new balance account is paid for by the first call's accounts[1]
+    Multicall {
+        #[cfg_attr(
+            feature = "idl",
+            borsh(schema(with_funcs(
+                declaration = "crate::idl::calls_declaration",
+                definitions = "crate::idl::add_calls_definitions"
+            )))
+        )]
+        calls: Vec<ContractInstruction>,
+    },
+    // list old under new_symbol, checked as for AdminAddSupportedToken, its balances, settings
+    // and allowances are kept, accounts: [state, admin]
+    AdminRenameToken {
+        old: TokenType,
+        new_symbol: String,
+    },
+    // start token's airdrop of total, moved from the admin's token account into the vault, a
+    // claim is credited once its proof leads to root, see verify_merkle_proof
+    // the token's earlier airdrop is replaced, what is left of it counts towards total
+    // accounts: same as AdminWithdrawTreasury
+    AdminSetMerkleRoot {
+        token: TokenType,
+        root: [u8; 32],
+        total: u64,
+    },
+    // credit user amount of token's airdrop, proof holds the siblings from
+    // airdrop_leaf(user, amount) up to the root, anyone may submit it
+    // accounts: [state, payer], followed by user's balance account and the system program
+    ClaimAirdrop {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    },
+    // move amount of token from the admin's balance to user's, signature is the admin's
+    // ed25519 signature over voucher_message, verified by an ed25519 precompile instruction
+    // earlier in the transaction, anyone may submit it until expiry (unix timestamp) and
+    // voucher_id can be redeemed once
+    // accounts: [state, payer], followed by the admin's and user's balance accounts, the
+    // system program and the instructions sysvar
+    RedeemVoucher {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+        voucher_id: u64,
+        expiry: i64,
+        #[serde(with = "signature_serde")]
+        signature: [u8; 64],
+    },
+    // let each user make at most max_ops deposits, withdrawals and transfers in any window of
+    // window_slots slots, 0 max_ops for no limit, the admin and the treasury are never limited,
+    // accounts: [state, admin]
+    AdminSetRateLimit {
+        max_ops: u32,
+        window_slots: u64,
+    },
+    // deposits taking the sum of every token's total_deposited above cap fail, u128::MAX for
+    // no cap, a cap below the current sum only blocks new deposits, accounts: [state, admin]
+    AdminSetGlobalCap {
+        cap: u128,
+    },
+    // totals across every token, a borsh GlobalStats via return data, accounts: [state]
+    GetGlobalStats,
+    // deposits of token then need the Pyth price account feed appended to their accounts, and
+    // fail once its total_deposited is worth more than usd_cap whole US dollars at a price
+    // published at most max_age_slots ago, the default pubkey as feed removes it
+    // accounts: [state, admin]
+    AdminSetPriceFeed {
+        token: TokenType,
+        feed: Pubkey,
+        usd_cap: u64,
+        max_age_slots: u64,
+    },
+    // users swap amount_in of from for amount_in * numerator / denominator of to, rounded
+    // down, a numerator of 0 removes the rate, accounts: [state, admin]
+    AdminSetSwapRate {
+        from: TokenType,
+        to: TokenType,
+        numerator: u64,
+        denominator: u64,
+    },
+    // swap amount_in of user's from balance for to at the posted rate, the treasury's balances
+    // take the other side, fails if that buys less than min_amount_out
+    // both amounts are balances, so shares of a share-mode token, the rate applies to what
+    // they are worth
+    // accounts: [state, user, user's from balance, treasury's from balance, user's to balance,
+    // treasury's to balance, system program]
+    UserSwap {
+        from: TokenType,
+        to: TokenType,
+        user: Pubkey,
+        amount_in: u64,
+        min_amount_out: u64,
+    },
+    // from now on token's balances are shares of its total_deposited, deposits mint them with
+    // shares_for_amount and withdrawals burn them for amount_for_shares, the token must have no
+    // balances, accounts: [state, admin]
+    AdminEnableShareMode {
+        token: TokenType,
+    },
+    // move amount from the admin's token account into the vault of a share-mode token, its
+    // holders' shares are worth that much more, accounts: same as AdminWithdrawTreasury
+    AdminDonateYield {
+        token: TokenType,
+        amount: u64,
+    },
+    // penalize user by moving amount of their balance, at most all of it, to the treasury's,
+    // refused while user's withdraw request is more than would be left, a SlashEvent records
+    // reason_code, amount is in token units, shares worth it for a share-mode token
+    // accounts: [state, admin, user's balance, treasury's balance, system program]
+    AdminSlash {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+        reason_code: u16,
+    },
+    // queue action, one of the timelocked instructions, to run from eta_slot on, which has to
+    // be timelock_delay_slots away at least, signed by whoever may run the action
+    // accounts: [state, admin or operator]
+    AdminQueueAction {
+        #[cfg_attr(
+            feature = "idl",
+            borsh(schema(with_funcs(
+                declaration = "crate::idl::action_declaration",
+                definitions = "crate::idl::add_action_definitions"
+            )))
+        )]
+        action: Box<ContractInstruction>,
+        eta_slot: u64,
+    },
+    // run a queued action once its eta is reached and before it expired, the timelocked
+    // instructions AdminDeleteSupportedToken, AdminForceDeleteToken, AdminSetWithdrawalFee,
+    // AdminSetDepositFee, AdminProposeNewAdmin and AdminSetTimelockDelay only run this way
+    // accounts: the action's own
+    AdminExecuteAction {
+        #[cfg_attr(
+            feature = "idl",
+            borsh(schema(with_funcs(
+                declaration = "crate::idl::action_declaration",
+                definitions = "crate::idl::add_action_definitions"
+            )))
+        )]
+        action: Box<ContractInstruction>,
+    },
+    // at least MIN_TIMELOCK_DELAY_SLOTS, accounts: [state, admin]
+    AdminSetTimelockDelay {
+        delay_slots: u64,
+    },
+    // disable token's withdrawals once more than threshold_bps of its total_deposited is
+    // withdrawn within window_slots, a threshold or window of 0 removes the breaker
+    // accounts: [state, admin]
+    AdminSetCircuitBreaker {
+        token: TokenType,
+        threshold_bps: u16,
+        window_slots: u64,
+    },
+    // re-enable withdrawals of a token whose circuit breaker tripped, its window starts over
+    // accounts: [state, admin]
+    AdminResetBreaker {
+        token: TokenType,
+    },
+    // a withdrawal leaving a balance of threshold or less sweeps it to the treasury, 0 for
+    // none, needs the treasury set, accounts: [state, admin]
+    AdminSetDustThreshold {
+        token: TokenType,
+        threshold: u64,
+    },
+    // whether token is listed, a borsh Option<SupportedToken> via return data, so a single 0
+    // for an unknown token, which isn't an error, accounts: [state]
+    IsTokenSupported {
+        token: TokenType,
+    },
+    // user's deposit and withdrawal counts and volumes of token, a borsh UserStats via return
+    // data, all 0 for a user without any, accounts: [state, user's balance account]
+    GetUserStats {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // a user can't withdraw token within withdraw_cooldown_slots of their last deposit, 0 for
+    // no cooldown, with transfers_reset a transfer received counts as a deposit too
+    // accounts: [state, admin]
+    AdminSetWithdrawCooldown {
+        token: TokenType,
+        withdraw_cooldown_slots: u64,
+        transfers_reset: bool,
+    },
+    // withdrawals of token below min_withdraw fail unless they take the whole balance, 0 for
+    // no minimum, accounts: [state, admin]
+    AdminSetMinWithdraw {
+        token: TokenType,
+        min_withdraw: u64,
+    },
+    // user's deposit receipt receipt_id, a borsh DepositReceipt via return data, only the
+    // latest MAX_RECEIPTS_PER_USER are kept, accounts: [state, user's receipts account]
+    GetReceipt {
+        user: Pubkey,
+        receipt_id: u64,
+    },
+    // queue a withdrawal of amount for ProcessWithdrawQueue, for when the vault or the circuit
+    // breaker can't pay it out now, one per user and token, accounts: [state, user]
+    UserEnqueueWithdraw {
+        token: TokenType,
+        user: Pubkey,
+        amount: u64,
+    },
+    // drop user's queued withdrawal of token, accounts: [state, user]
+    UserCancelQueuedWithdraw {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // pay out the withdrawals at the front of token's queue in order, stops at max_items or
+    // the first one the vault can't pay, one still cooling down, over its daily limit or
+    // locked keeps its place, one whose user closed their associated token account moves to
+    // the back, one that can't run for another reason is dropped
+    // anyone can run it, accounts: [state, payer] then UserDeposit's token accounts for each
+    // of the first min(max_items, queue length) entries, paying out to its user, then their
+    // balance accounts, the system program and the users, who get the rent of the balance
+    // accounts closed
+    ProcessWithdrawQueue {
+        token: TokenType,
+        max_items: u8,
+    },
+    // user's place in token's withdrawal queue, a borsh Option<u32> via return data, 0 for
+    // the front, accounts: [state]
+    GetQueuePosition {
+        token: TokenType,
+        user: Pubkey,
+    },
+    // list several tokens at once, all or nothing, at most MAX_BATCH_ITEMS, each checked as
+    // for AdminAddSupportedToken and against the others, named after its symbol with its
+    // mint's decimals (see AdminUpdateTokenMetadata)
+    // accounts: [state, admin] followed by AdminAddSupportedToken's accounts after the admin
+    // for every token, then the system program for the state account's growth
+    AdminAddSupportedTokens {
+        tokens: Vec<TokenType>,
+    },
+}
+
+// serde only goes up to [u8; 32], a signature is the same array of numbers as a Vec<u8>
+mod signature_serde {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        signature: &[u8; 64],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(signature)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 64], D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"64 bytes"))
+    }
+}
+
+// longest memo of a UserDeposit or UserWithdraw, in bytes
+pub const MAX_MEMO_LEN: usize = 64;
+
+// most tokens a ListSupportedTokens page holds
+pub const MAX_TOKENS_PER_PAGE: u32 = 32;
+
+// return data of ListSupportedTokens, continue at offset + tokens.len() while it's below total
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct TokenPage {
+    pub tokens: Vec<(TokenType, TokenMetadata)>,
+    // number of supported tokens
+    pub total: u32,
+}
+
+// most entries a GetAuditLog page holds, a full page fits in the return data
+pub const MAX_AUDIT_ENTRIES_PER_PAGE: u32 = 16;
+
+// return data of GetAuditLog, continue at offset + entries.len() while it's below total
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    // number of entries in the log
+    pub total: u32,
+}
+
+// return data of GetTokenStats
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct TokenStats {
+    // every balance and pending withdraw request together
+    pub total_deposited: u64,
+    // users with a nonzero balance
+    pub holder_count: u64,
+    pub metadata: TokenMetadata,
+}
+
+// return data of IsTokenSupported for a listed token
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct SupportedToken {
+    pub mint: Pubkey,
+    pub deposits_enabled: bool,
+    pub withdrawals_enabled: bool,
+}
+
+// return data of GetGlobalStats
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct GlobalStats {
+    // sum of every token's total_deposited
+    pub total_deposited: u128,
+    pub cap: u128,
+    pub token_count: u32,
+    // sum of every token's holder count, a user holding two tokens counts twice
+    pub holder_count: u64,
+}
+
+// return data of GetBalance
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct UserBalance {
+    // withdrawable now
+    pub liquid: u64,
+    // still locked or not vested yet, see UserDeposit and AdminDepositVested
+    pub locked: u64,
+    // 0 for never
+    pub last_deposit_slot: u64,
+    pub last_withdraw_slot: u64,
+}
+
+// return data of GetReferralStats
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct ReferralStats {
+    pub volume: u64,
+    // users bound to the referrer
+    pub referees: u64,
+}
+
+// domain tag of eth_link_message
+const ETH_LINK_TAG: &[u8] = b"link";
+// domain tag of voucher_message
+const VOUCHER_TAG: &[u8] = b"voucher";
+
+// first byte of the instruction data selects how the rest is encoded, any other first byte
+// starts an Anchor-style discriminator (see INSTRUCTION_DISCRIMINATORS)
+pub const INSTRUCTION_VERSION_JSON: u8 = 0;
+pub const INSTRUCTION_VERSION_BORSH: u8 = 1;
+// see ContractInstruction::pack_compact
+pub const INSTRUCTION_VERSION_COMPACT: u8 = 2;
+
+// (snake_case name, the first 8 bytes of sha256("global:<name>")) of every variant, indexed by
+// ContractInstruction::tag, Anchor-style instruction data is the discriminator followed by the
+// variant's borsh fields, none of them starts with a version byte
+// a variant appended later whose discriminator does has to take another name
+pub const INSTRUCTION_DISCRIMINATORS: [(&str, [u8; 8]); 86] = [
+    (
+        "initialize_state",
+        [0xbe, 0xab, 0xe0, 0xdb, 0xd9, 0x48, 0xc7, 0xb0],
+    ),
+    (
+        "migrate_state",
+        [0x22, 0xbd, 0xe2, 0xde, 0xda, 0x9c, 0x13, 0xd5],
+    ),
+    (
+        "initialize_config",
+        [0xd0, 0x7f, 0x15, 0x01, 0xc2, 0xbe, 0xc4, 0x46],
+    ),
+    (
+        "admin_add_supported_token",
+        [0x9e, 0x5d, 0x06, 0x7b, 0x70, 0xed, 0x8d, 0x97],
+    ),
+    (
+        "admin_delete_supported_token",
+        [0x17, 0x71, 0xb9, 0x24, 0x74, 0xf4, 0x4a, 0x96],
+    ),
+    (
+        "admin_force_delete_token",
+        [0x06, 0xea, 0x49, 0x07, 0x8b, 0x8f, 0x62, 0x33],
+    ),
+    (
+        "user_deposit",
+        [0xba, 0xc6, 0x8c, 0xe9, 0x81, 0x27, 0x62, 0x99],
+    ),
+    (
+        "user_withdraw",
+        [0x35, 0xfe, 0x1a, 0xf2, 0x77, 0xed, 0x49, 0x21],
+    ),
+    (
+        "user_withdraw_all",
+        [0xd8, 0xf4, 0x00, 0x1a, 0x89, 0x86, 0x0e, 0x6f],
+    ),
+    (
+        "user_request_withdraw",
+        [0xb1, 0x54, 0xe9, 0xf4, 0xf3, 0x22, 0xcd, 0xe7],
+    ),
+    (
+        "user_claim_withdraw",
+        [0x3d, 0xf9, 0xd7, 0xc5, 0x85, 0x0e, 0x29, 0x80],
+    ),
+    (
+        "admin_cancel_withdraw_request",
+        [0x98, 0x48, 0x8f, 0x65, 0x7b, 0x5f, 0xda, 0x57],
+    ),
+    (
+        "admin_set_withdraw_delay",
+        [0xd0, 0x78, 0x14, 0x46, 0x83, 0x06, 0xe1, 0xfb],
+    ),
+    (
+        "admin_set_treasury",
+        [0xae, 0xfb, 0xef, 0x41, 0xf1, 0xa2, 0x88, 0xbb],
+    ),
+    (
+        "admin_set_withdrawal_fee",
+        [0x81, 0xca, 0x90, 0xc9, 0xfb, 0x4d, 0x3f, 0x78],
+    ),
+    (
+        "admin_set_deposit_fee",
+        [0x06, 0xda, 0x3b, 0xfc, 0xa9, 0xd7, 0x9a, 0x3f],
+    ),
+    (
+        "admin_set_fee_exempt",
+        [0x75, 0xa1, 0x2c, 0x08, 0x05, 0x58, 0xa2, 0x71],
+    ),
+    (
+        "admin_withdraw_treasury",
+        [0x37, 0xcc, 0x3b, 0x9c, 0xf6, 0xcd, 0x99, 0x10],
+    ),
+    (
+        "user_set_withdraw_delegate",
+        [0x6b, 0x73, 0xb3, 0xc9, 0x1c, 0x8e, 0x7b, 0x70],
+    ),
+    (
+        "admin_freeze_user",
+        [0x6b, 0xbc, 0x82, 0x4b, 0x77, 0x33, 0x7e, 0xfa],
+    ),
+    (
+        "admin_unfreeze_user",
+        [0xcd, 0xab, 0xfc, 0x91, 0xdf, 0xd5, 0xce, 0xf3],
+    ),
+    (
+        "admin_set_blocked",
+        [0xb9, 0x56, 0xec, 0x50, 0xba, 0x3f, 0xe1, 0x66],
+    ),
+    (
+        "admin_set_operator",
+        [0x7c, 0xb1, 0x2a, 0x2a, 0x4a, 0xa9, 0x28, 0x18],
+    ),
+    (
+        "admin_propose_new_admin",
+        [0xb6, 0x3f, 0xad, 0xee, 0x1b, 0x7a, 0xb2, 0x53],
+    ),
+    (
+        "admin_cancel_proposed_admin",
+        [0x1a, 0x63, 0x5b, 0x95, 0xb8, 0x44, 0xa3, 0x9d],
+    ),
+    (
+        "accept_admin",
+        [0x70, 0x2a, 0x2d, 0x5a, 0x74, 0xb5, 0x0d, 0xaa],
+    ),
+    (
+        "admin_pause",
+        [0x0d, 0x6d, 0xf0, 0x81, 0x56, 0xf5, 0xb6, 0x2d],
+    ),
+    (
+        "admin_unpause",
+        [0x77, 0x97, 0xff, 0x68, 0x8f, 0x77, 0x42, 0xa4],
+    ),
+    (
+        "admin_set_token_flags",
+        [0xfc, 0xa9, 0x14, 0x1b, 0xa9, 0xd9, 0x69, 0x56],
+    ),
+    (
+        "admin_set_max_tokens",
+        [0x22, 0xda, 0xb9, 0x80, 0x47, 0x8b, 0x6e, 0x45],
+    ),
+    (
+        "admin_set_deposit_cap",
+        [0xf6, 0x73, 0x12, 0x18, 0x1f, 0x45, 0x60, 0xc5],
+    ),
+    (
+        "admin_set_max_user_balance",
+        [0x4a, 0x4c, 0xb9, 0x98, 0xd0, 0x21, 0xdc, 0x6f],
+    ),
+    (
+        "admin_set_min_deposit",
+        [0x19, 0x36, 0xf1, 0x82, 0x1b, 0x01, 0x2a, 0xd0],
+    ),
+    (
+        "admin_set_daily_withdrawal_limit",
+        [0x73, 0x07, 0xcc, 0x67, 0xca, 0xad, 0xbe, 0xac],
+    ),
+    (
+        "batch_deposit",
+        [0xdc, 0x8a, 0x15, 0x16, 0x61, 0x99, 0xd0, 0xda],
+    ),
+    (
+        "batch_withdraw",
+        [0x25, 0x4c, 0x95, 0x47, 0x5e, 0x24, 0xf5, 0xc3],
+    ),
+    (
+        "user_transfer",
+        [0x8f, 0xb2, 0x00, 0x05, 0x72, 0xc9, 0x0e, 0x3c],
+    ),
+    (
+        "user_approve",
+        [0x57, 0x9f, 0xaa, 0x1e, 0xc3, 0x56, 0xf5, 0x00],
+    ),
+    (
+        "transfer_from",
+        [0xe6, 0xff, 0x82, 0x07, 0xdc, 0xf7, 0x7a, 0x00],
+    ),
+    (
+        "get_balance",
+        [0x05, 0xad, 0xb4, 0x97, 0xf3, 0x51, 0xe9, 0x37],
+    ),
+    (
+        "link_eth_address",
+        [0x03, 0x63, 0x13, 0xec, 0xa3, 0xe3, 0x48, 0xf8],
+    ),
+    (
+        "get_sequence",
+        [0x8c, 0x00, 0x7b, 0xfa, 0x59, 0xa6, 0xb1, 0x66],
+    ),
+    (
+        "get_nonce",
+        [0x84, 0x81, 0xee, 0x4c, 0x76, 0x0a, 0xd4, 0x07],
+    ),
+    (
+        "list_supported_tokens",
+        [0xe5, 0xec, 0xad, 0x60, 0x85, 0xbd, 0x1c, 0x3a],
+    ),
+    (
+        "get_token_stats",
+        [0x75, 0xa8, 0xb4, 0x8c, 0xd6, 0xba, 0xa7, 0x4d],
+    ),
+    (
+        "admin_update_token_metadata",
+        [0x6e, 0x90, 0x9a, 0x35, 0xf5, 0x07, 0xb3, 0x36],
+    ),
+    (
+        "admin_set_display_precision",
+        [0x06, 0x91, 0x12, 0x06, 0xc3, 0x9c, 0x44, 0x22],
+    ),
+    (
+        "admin_set_reward_rate",
+        [0xd6, 0xcd, 0x98, 0x7a, 0xe3, 0x4f, 0x6e, 0x11],
+    ),
+    (
+        "claim_rewards",
+        [0x04, 0x90, 0x84, 0x47, 0x74, 0x17, 0x97, 0x50],
+    ),
+    (
+        "get_reward_points",
+        [0x53, 0x1b, 0xd1, 0xc7, 0xf3, 0xf1, 0xfd, 0x29],
+    ),
+    (
+        "admin_deposit_vested",
+        [0x17, 0x0e, 0x2c, 0x4f, 0x7f, 0x4d, 0xeb, 0x08],
+    ),
+    (
+        "get_referral_stats",
+        [0xaf, 0x84, 0x1c, 0xea, 0x31, 0xda, 0x4f, 0xff],
+    ),
+    (
+        "admin_snapshot_state",
+        [0x9e, 0xec, 0x0e, 0xbf, 0x58, 0x50, 0x78, 0x05],
+    ),
+    (
+        "get_audit_log",
+        [0x60, 0x52, 0xe5, 0x9c, 0x01, 0x10, 0xb2, 0xa2],
+    ),
+    (
+        "close_user_balance",
+        [0x03, 0xc1, 0x01, 0x7f, 0x71, 0xfc, 0x79, 0xc4],
+    ),
+    (
+        "admin_emergency_sweep",
+        [0x6e, 0xce, 0xb0, 0x5c, 0x15, 0x72, 0xab, 0x39],
+    ),
+    (
+        "multicall",
+        [0xba, 0x66, 0x96, 0x02, 0xad, 0x37, 0x93, 0x2c],
+    ),
+    (
+        "admin_rename_token",
+        [0x3c, 0x51, 0x26, 0x0b, 0x51, 0xa7, 0x1e, 0xa5],
+    ),
+    (
+        "admin_set_merkle_root",
+        [0x20, 0x8b, 0x38, 0x86, 0xd0, 0x6a, 0xdd, 0x7e],
+    ),
+    (
+        "claim_airdrop",
+        [0x89, 0x32, 0x7a, 0x6f, 0x59, 0xfe, 0x08, 0x14],
+    ),
+    (
+        "redeem_voucher",
+        [0x32, 0xdb, 0x08, 0x7f, 0x2d, 0x60, 0xa1, 0x5c],
+    ),
+    (
+        "admin_set_rate_limit",
+        [0x5d, 0x60, 0xd3, 0x70, 0xf6, 0x85, 0xff, 0xfd],
+    ),
+    (
+        "admin_set_global_cap",
+        [0xc6, 0xe4, 0x46, 0xfb, 0xcd, 0x76, 0x20, 0x4a],
+    ),
+    (
+        "get_global_stats",
+        [0x5d, 0x78, 0xec, 0xec, 0xaf, 0xc8, 0x40, 0xf5],
+    ),
+    (
+        "admin_set_price_feed",
+        [0xce, 0x00, 0x1d, 0xc2, 0x32, 0x98, 0x2c, 0xf0],
+    ),
+    (
+        "admin_set_swap_rate",
+        [0xd4, 0x26, 0x53, 0xd9, 0xfb, 0x52, 0xf3, 0xc8],
+    ),
+    (
+        "user_swap",
+        [0x78, 0xb9, 0x68, 0x7e, 0x58, 0x66, 0x5e, 0x06],
+    ),
+    (
+        "admin_enable_share_mode",
+        [0x2e, 0xdb, 0x4b, 0x50, 0x07, 0x35, 0xa5, 0x53],
+    ),
+    (
+        "admin_donate_yield",
+        [0x4a, 0xee, 0x42, 0xda, 0xcf, 0x0e, 0x3c, 0xec],
+    ),
+    (
+        "admin_slash",
+        [0x3d, 0xa5, 0xce, 0xd7, 0xbf, 0xfe, 0x8c, 0x5a],
+    ),
+    (
+        "admin_queue_action",
+        [0xc6, 0xc2, 0x7e, 0x26, 0xaf, 0xcb, 0xe7, 0xac],
+    ),
+    (
+        "admin_execute_action",
+        [0x71, 0xc5, 0x9c, 0xf7, 0x8b, 0x5e, 0x03, 0x3f],
+    ),
+    (
+        "admin_set_timelock_delay",
+        [0xea, 0xf7, 0xf5, 0x49, 0x4b, 0xb1, 0x65, 0xf5],
+    ),
+    (
+        "admin_set_circuit_breaker",
+        [0xac, 0x0e, 0x5a, 0xfc, 0x40, 0x03, 0x85, 0xbc],
+    ),
+    (
+        "admin_reset_breaker",
+        [0xe4, 0xc5, 0xab, 0x48, 0x40, 0x49, 0x64, 0x55],
+    ),
+    (
+        "admin_set_dust_threshold",
+        [0x37, 0x0b, 0xe0, 0xac, 0xf7, 0xef, 0xd7, 0xfb],
+    ),
+    (
+        "is_token_supported",
+        [0x51, 0x5f, 0x4c, 0x7e, 0xd4, 0x4b, 0xe0, 0x85],
+    ),
+    (
+        "get_user_stats",
+        [0x26, 0x37, 0x32, 0x84, 0x73, 0x7f, 0x32, 0x20],
+    ),
+    (
+        "admin_set_withdraw_cooldown",
+        [0xdd, 0x9e, 0x6a, 0x6a, 0x8a, 0xd5, 0x18, 0xf1],
+    ),
+    (
+        "admin_set_min_withdraw",
+        [0x9f, 0x15, 0x36, 0xd7, 0x08, 0x75, 0xf3, 0xf5],
+    ),
+    (
+        "get_receipt",
+        [0x32, 0xaf, 0x32, 0x95, 0xbc, 0x87, 0x2a, 0x2b],
+    ),
+    (
+        "user_enqueue_withdraw",
+        [0x40, 0x7c, 0xd0, 0x7f, 0xec, 0xb2, 0x95, 0x58],
+    ),
+    (
+        "user_cancel_queued_withdraw",
+        [0x0e, 0x9e, 0xa3, 0x2f, 0x89, 0xa0, 0x73, 0xe1],
+    ),
+    (
+        "process_withdraw_queue",
+        [0x17, 0x64, 0x3d, 0xf1, 0x86, 0xbe, 0x30, 0x35],
+    ),
+    (
+        "get_queue_position",
+        [0x1d, 0x5e, 0x72, 0x58, 0xf3, 0x04, 0xa2, 0xcb],
+    ),
+    (
+        "admin_add_supported_tokens",
+        [0xa5, 0x90, 0x1c, 0xc3, 0x49, 0x73, 0x48, 0xc2],
+    ),
+];
+// longer data can't fit in a transaction (PACKET_DATA_SIZE), it is refused before parsing
+pub const MAX_INSTRUCTION_DATA_LEN: usize = 1232;
+
+impl ContractInstruction {
+    // instruction data in the current encoding, INSTRUCTION_VERSION_BORSH then the borsh payload
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![INSTRUCTION_VERSION_BORSH];
+        // writing into a Vec can't fail
+        data.extend(borsh::to_vec(self).unwrap());
+        data
+    }
+
+    // Anchor-style instruction data, the discriminator then the variant's borsh fields
+    pub fn pack_anchor(&self) -> Vec<u8> {
+        // writing into a Vec can't fail
+        let borsh = borsh::to_vec(self).unwrap();
+        [&self.discriminator()[..], &borsh[1..]].concat()
+    }
+
+    pub fn discriminator(&self) -> [u8; 8] {
+        INSTRUCTION_DISCRIMINATORS[self.tag() as usize].1
+    }
+
+    // borsh variant index, what the audit log records the instruction as
+    pub fn tag(&self) -> u8 {
+        // writing into a Vec can't fail, a variant always encodes its index first
+        borsh::to_vec(self).unwrap()[0]
+    }
+
+    // instruction data in the compact layout for transactions short on space, None if a field
+    // doesn't fit it: INSTRUCTION_VERSION_COMPACT, the tag, then the fields in order
+    // - Pubkey: 32 bytes, u64 and i64: 8, u32: 4, u16: 2, u8: 1, all little-endian, bool: 1
+    //   byte, 0 or 1
+    // - TokenType: its symbol, a u8 length then at most MAX_SYMBOL_LEN bytes, the mint is left
+    //   out and looked up among the listed tokens when the instruction runs, decoded tokens
+    //   carry Pubkey::default() until then (see listed_tokens_mut), only the symbols of
+    //   AdminAddSupportedToken and AdminAddSupportedTokens are followed by the mint
+    // - String and Vec<u8>: a u8 length then the bytes, TokenMetadata: name then decimals
+    // - [u8; 32]: 32 bytes
+    // - Vec<TokenType>, Vec<(TokenType, u64)> and Vec<[u8; 32]>: a u8 count then the items
+    // - Multicall: a u8 count then every call's tag and fields behind a u16 length
+    // - the action of AdminQueueAction and AdminExecuteAction: its tag and fields
+    // - Option fields aren't in their place, a byte after the others flags which are present,
+    //   bit i for the variant's i-th one, and their values follow it in order
+    // a UserDeposit without options takes 52 bytes plus its symbol, 56 for "usdc"
+    pub fn pack_compact(&self) -> Option<Vec<u8>> {
+        let mut writer = CompactWriter(vec![INSTRUCTION_VERSION_COMPACT]);
+        self.write_compact(&mut writer)?;
+        Some(writer.0)
+    }
+
+    // decode pack_compact's layout, data starts with INSTRUCTION_VERSION_COMPACT
+    pub fn unpack_compact(data: &[u8]) -> Result<ContractInstruction, ProgramError> {
+        let mut reader = match data.split_first() {
+            Some((&INSTRUCTION_VERSION_COMPACT, payload)) => CompactReader::new(payload),
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        let instruction = reader.instruction().and_then(|instruction| {
+            reader.finish()?;
+            Ok(instruction)
+        });
+        instruction.map_err(|e| {
+            msg!("invalid instruction data: {}", e);
+            ProgramError::InvalidInstructionData
+        })
+    }
+
+    // the tokens the instruction names that have to be listed already, the program looks the
+    // ones with the default mint up by symbol before running it, whatever the encoding
+    pub fn listed_tokens_mut(&mut self) -> Vec<&mut TokenType> {
+        use ContractInstruction::*;
+        match self {
+            AdminDeleteSupportedToken { token }
+            | AdminForceDeleteToken { token, .. }
+            | UserDeposit { token, .. }
+            | UserWithdraw { token, .. }
+            | UserWithdrawAll { token, .. }
+            | UserRequestWithdraw { token, .. }
+            | UserClaimWithdraw { token, .. }
+            | AdminCancelWithdrawRequest { token, .. }
+            | AdminSetWithdrawalFee { token, .. }
+            | AdminSetDepositFee { token, .. }
+            | AdminWithdrawTreasury { token, .. }
+            | AdminFreezeUser { token, .. }
+            | AdminUnfreezeUser { token, .. }
+            | AdminSetTokenFlags { token, .. }
+            | AdminSetDepositCap { token, .. }
+            | AdminSetMaxUserBalance { token, .. }
+            | AdminSetMinDeposit { token, .. }
+            | AdminSetDailyWithdrawalLimit { token, .. }
+            | UserTransfer { token, .. }
+            | UserApprove { token, .. }
+            | TransferFrom { token, .. }
+            | GetBalance { token, .. }
+            | GetNonce { token, .. }
+            | GetUserStats { token, .. }
+            | GetTokenStats { token }
+            | IsTokenSupported { token }
+            | AdminUpdateTokenMetadata { token, .. }
+            | AdminSetDisplayPrecision { token, .. }
+            | AdminSetRewardRate { token, .. }
+            | AdminDepositVested { token, .. }
+            | CloseUserBalance { token, .. }
+            | AdminEmergencySweep { token, .. }
+            | AdminRenameToken { old: token, .. }
+            | AdminSetMerkleRoot { token, .. }
+            | ClaimAirdrop { token, .. }
+            | RedeemVoucher { token, .. }
+            | AdminSetPriceFeed { token, .. }
+            | AdminEnableShareMode { token }
+            | AdminDonateYield { token, .. }
+            | AdminSlash { token, .. }
+            | AdminSetCircuitBreaker { token, .. }
+            | AdminResetBreaker { token }
+            | AdminSetDustThreshold { token, .. }
+            | AdminSetWithdrawCooldown { token, .. }
+            | AdminSetMinWithdraw { token, .. }
+            | UserEnqueueWithdraw { token, .. }
+            | UserCancelQueuedWithdraw { token, .. }
+            | ProcessWithdrawQueue { token, .. }
+            | GetQueuePosition { token, .. } => vec![token],
+            BatchDeposit { items, .. } | BatchWithdraw { items, .. } => {
+                items.iter_mut().map(|(token, _)| token).collect()
+            }
+            AdminSetSwapRate { from, to, .. } | UserSwap { from, to, .. } => vec![from, to],
+            Multicall { calls } => calls
+                .iter_mut()
+                .flat_map(|call| call.listed_tokens_mut())
+                .collect(),
+            AdminQueueAction { action, .. } | AdminExecuteAction { action } => {
+                action.listed_tokens_mut()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // the tag and fields of pack_compact
+    fn write_compact(&self, w: &mut CompactWriter) -> Option<()> {
+        use ContractInstruction::*;
+        w.u8(self.tag());
+        match self {
+            InitializeState
+            | MigrateState
+            | AdminCancelProposedAdmin
+            | AcceptAdmin
+            | AdminUnpause
+            | GetSequence
+            | AdminSnapshotState
+            | GetGlobalStats => {}
+            InitializeConfig { admin: user }
+            | AdminSetTreasury { treasury: user }
+            | AdminProposeNewAdmin { new_admin: user }
+            | ClaimRewards { user }
+            | GetRewardPoints { user }
+            | GetReferralStats { referrer: user } => w.pubkey(user),
+            AdminAddSupportedToken { token, metadata } => {
+                w.symbol(token)?;
+                w.pubkey(&token.mint);
+                w.metadata(metadata)?;
+            }
+            AdminUpdateTokenMetadata { token, metadata } => {
+                w.symbol(token)?;
+                w.metadata(metadata)?;
+            }
+            AdminDeleteSupportedToken { token }
+            | GetTokenStats { token }
+            | IsTokenSupported { token }
+            | AdminEnableShareMode { token }
+            | AdminResetBreaker { token } => w.symbol(token)?,
+            AdminForceDeleteToken { token, confirm } => {
+                w.symbol(token)?;
+                w.bool(*confirm);
+            }
+            UserDeposit {
+                token,
+                user,
+                amount,
+                nonce,
+                lock_until,
+                referrer,
+                beneficiary,
+                memo,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+                w.u64(*nonce);
+                w.flags(&[
+                    lock_until.is_some(),
+                    referrer.is_some(),
+                    beneficiary.is_some(),
+                    memo.is_some(),
+                ]);
+                if let Some(lock_until) = lock_until {
+                    w.i64(*lock_until);
+                }
+                if let Some(referrer) = referrer {
+                    w.pubkey(referrer);
+                }
+                if let Some(beneficiary) = beneficiary {
+                    w.pubkey(beneficiary);
+                }
+                if let Some(memo) = memo {
+                    w.bytes(memo.as_bytes())?;
+                }
+            }
+            UserWithdraw {
+                token,
+                user,
+                amount,
+                nonce,
+                eth_signature,
+                recipient,
+                memo,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+                w.u64(*nonce);
+                w.flags(&[eth_signature.is_some(), recipient.is_some(), memo.is_some()]);
+                if let Some(eth_signature) = eth_signature {
+                    w.bytes(eth_signature)?;
+                }
+                if let Some(recipient) = recipient {
+                    w.pubkey(recipient);
+                }
+                if let Some(memo) = memo {
+                    w.bytes(memo.as_bytes())?;
+                }
+            }
+            UserWithdrawAll { token, user }
+            | UserClaimWithdraw { token, user }
+            | AdminCancelWithdrawRequest { token, user }
+            | AdminFreezeUser { token, user }
+            | AdminUnfreezeUser { token, user }
+            | GetBalance { token, user }
+            | GetNonce { token, user }
+            | UserCancelQueuedWithdraw { token, user }
+            | GetQueuePosition { token, user }
+            | GetUserStats { token, user }
+            | CloseUserBalance { token, user }
+            | AdminEmergencySweep {
+                token,
+                destination: user,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+            }
+            UserRequestWithdraw {
+                token,
+                user,
+                amount,
+            }
+            | UserEnqueueWithdraw {
+                token,
+                user,
+                amount,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+            }
+            AdminSetWithdrawDelay { delay_slots } => w.u64(*delay_slots),
+            AdminSetWithdrawalFee { token, fee_bps } | AdminSetDepositFee { token, fee_bps } => {
+                w.symbol(token)?;
+                w.u16(*fee_bps);
+            }
+            AdminSetFeeExempt { user, exempt: flag }
+            | AdminSetBlocked {
+                user,
+                blocked: flag,
+            } => {
+                w.pubkey(user);
+                w.bool(*flag);
+            }
+            AdminWithdrawTreasury { token, amount }
+            | AdminSetMinDeposit {
+                token,
+                min_deposit: amount,
+            }
+            | AdminSetDailyWithdrawalLimit {
+                token,
+                limit: amount,
+            }
+            | AdminSetRewardRate {
+                token,
+                points_per_day: amount,
+            }
+            | AdminSetDustThreshold {
+                token,
+                threshold: amount,
+            }
+            | AdminSetMinWithdraw {
+                token,
+                min_withdraw: amount,
+            } => {
+                w.symbol(token)?;
+                w.u64(*amount);
+            }
+            UserSetWithdrawDelegate { user, delegate } => {
+                w.pubkey(user);
+                w.flags(&[delegate.is_some()]);
+                if let Some(delegate) = delegate {
+                    w.pubkey(delegate);
+                }
+            }
+            AdminSetOperator { operator } => {
+                w.flags(&[operator.is_some()]);
+                if let Some(operator) = operator {
+                    w.pubkey(operator);
+                }
+            }
+            AdminPause { withdrawals_only } => w.bool(*withdrawals_only),
+            AdminSetTokenFlags {
+                token,
+                deposits_enabled,
+                withdrawals_enabled,
+            } => {
+                w.symbol(token)?;
+                w.bool(*deposits_enabled);
+                w.bool(*withdrawals_enabled);
+            }
+            AdminSetMaxTokens { max_tokens } => w.u32(*max_tokens),
+            AdminSetDepositCap { token, cap: amount }
+            | AdminSetMaxUserBalance {
+                token,
+                max_user_balance: amount,
+            } => {
+                w.symbol(token)?;
+                w.flags(&[amount.is_some()]);
+                if let Some(amount) = amount {
+                    w.u64(*amount);
+                }
+            }
+            BatchDeposit { user, items } | BatchWithdraw { user, items } => {
+                w.pubkey(user);
+                w.u8(u8::try_from(items.len()).ok()?);
+                for (token, amount) in items {
+                    w.symbol(token)?;
+                    w.u64(*amount);
+                }
+            }
+            UserTransfer {
+                token,
+                from,
+                to,
+                amount,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(from);
+                w.pubkey(to);
+                w.u64(*amount);
+            }
+            UserApprove {
+                token,
+                owner,
+                spender,
+                amount,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(owner);
+                w.pubkey(spender);
+                w.u64(*amount);
+            }
+            TransferFrom {
+                token,
+                owner,
+                spender,
+                to,
+                amount,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(owner);
+                w.pubkey(spender);
+                w.pubkey(to);
+                w.u64(*amount);
+            }
+            LinkEthAddress { user, signature } => {
+                w.pubkey(user);
+                w.bytes(signature)?;
+            }
+            ListSupportedTokens { offset, limit } | GetAuditLog { offset, limit } => {
+                w.u32(*offset);
+                w.u32(*limit);
+            }
+            AdminSetDisplayPrecision {
+                token,
+                display_precision,
+            } => {
+                w.symbol(token)?;
+                w.flags(&[display_precision.is_some()]);
+                if let Some(display_precision) = display_precision {
+                    w.u8(*display_precision);
+                }
+            }
+            AdminDepositVested {
+                token,
+                user,
+                amount,
+                start_ts,
+                duration_secs,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+                w.i64(*start_ts);
+                w.u64(*duration_secs);
+            }
+            Multicall { calls } => {
+                w.u8(u8::try_from(calls.len()).ok()?);
+                for call in calls {
+                    let mut call_writer = CompactWriter(Vec::new());
+                    call.write_compact(&mut call_writer)?;
+                    w.u16(u16::try_from(call_writer.0.len()).ok()?);
+                    w.0.extend(call_writer.0);
+                }
+            }
+            AdminRenameToken { old, new_symbol } => {
+                w.symbol(old)?;
+                if new_symbol.len() > MAX_SYMBOL_LEN {
+                    return None;
+                }
+                w.bytes(new_symbol.as_bytes())?;
+            }
+            AdminSetMerkleRoot { token, root, total } => {
+                w.symbol(token)?;
+                w.0.extend(root);
+                w.u64(*total);
+            }
+            ClaimAirdrop {
+                token,
+                user,
+                amount,
+                proof,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+                w.u8(u8::try_from(proof.len()).ok()?);
+                for node in proof {
+                    w.0.extend(node);
+                }
+            }
+            RedeemVoucher {
+                token,
+                user,
+                amount,
+                voucher_id,
+                expiry,
+                signature,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+                w.u64(*voucher_id);
+                w.i64(*expiry);
+                w.0.extend(signature);
+            }
+            AdminSetRateLimit {
+                max_ops,
+                window_slots,
+            } => {
+                w.u32(*max_ops);
+                w.u64(*window_slots);
+            }
+            AdminSetGlobalCap { cap } => w.0.extend(cap.to_le_bytes()),
+            AdminSetPriceFeed {
+                token,
+                feed,
+                usd_cap,
+                max_age_slots,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(feed);
+                w.u64(*usd_cap);
+                w.u64(*max_age_slots);
+            }
+            AdminSetSwapRate {
+                from,
+                to,
+                numerator,
+                denominator,
+            } => {
+                w.symbol(from)?;
+                w.symbol(to)?;
+                w.u64(*numerator);
+                w.u64(*denominator);
+            }
+            UserSwap {
+                from,
+                to,
+                user,
+                amount_in,
+                min_amount_out,
+            } => {
+                w.symbol(from)?;
+                w.symbol(to)?;
+                w.pubkey(user);
+                w.u64(*amount_in);
+                w.u64(*min_amount_out);
+            }
+            AdminDonateYield { token, amount } => {
+                w.symbol(token)?;
+                w.u64(*amount);
+            }
+            AdminSlash {
+                token,
+                user,
+                amount,
+                reason_code,
+            } => {
+                w.symbol(token)?;
+                w.pubkey(user);
+                w.u64(*amount);
+                w.u16(*reason_code);
+            }
+            AdminQueueAction { action, eta_slot } => {
+                action.write_compact(w)?;
+                w.u64(*eta_slot);
+            }
+            AdminExecuteAction { action } => action.write_compact(w)?,
+            AdminSetTimelockDelay { delay_slots } => w.u64(*delay_slots),
+            AdminSetCircuitBreaker {
+                token,
+                threshold_bps,
+                window_slots,
+            } => {
+                w.symbol(token)?;
+                w.u16(*threshold_bps);
+                w.u64(*window_slots);
+            }
+            AdminSetWithdrawCooldown {
+                token,
+                withdraw_cooldown_slots,
+                transfers_reset,
+            } => {
+                w.symbol(token)?;
+                w.u64(*withdraw_cooldown_slots);
+                w.bool(*transfers_reset);
+            }
+            GetReceipt { user, receipt_id } => {
+                w.pubkey(user);
+                w.u64(*receipt_id);
+            }
+            ProcessWithdrawQueue { token, max_items } => {
+                w.symbol(token)?;
+                w.u8(*max_items);
+            }
+            AdminAddSupportedTokens { tokens } => {
+                w.u8(u8::try_from(tokens.len()).ok()?);
+                for token in tokens {
+                    w.symbol(token)?;
+                    w.pubkey(&token.mint);
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+// see ContractInstruction::pack_compact
+struct CompactWriter(Vec<u8>);
+
+impl CompactWriter {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.0.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn pubkey(&mut self, value: &Pubkey) {
+        self.0.extend(value.as_ref());
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> Option<()> {
+        self.u8(u8::try_from(value.len()).ok()?);
+        self.0.extend(value);
+        Some(())
+    }
+
+    fn symbol(&mut self, token: &TokenType) -> Option<()> {
+        if token.symbol.len() > MAX_SYMBOL_LEN {
+            return None;
+        }
+        self.bytes(token.symbol.as_bytes())
+    }
+
+    fn metadata(&mut self, metadata: &TokenMetadata) -> Option<()> {
+        self.bytes(metadata.name.as_bytes())?;
+        self.u8(metadata.decimals);
+        self.flags(&[metadata.metadata_uri.is_some()]);
+        if let Some(metadata_uri) = &metadata.metadata_uri {
+            self.bytes(metadata_uri.as_bytes())?;
+        }
+        Some(())
+    }
+
+    fn flags(&mut self, present: &[bool]) {
+        let flags = present
+            .iter()
+            .enumerate()
+            .fold(0, |flags, (i, present)| flags | (*present as u8) << i);
+        self.u8(flags);
+    }
+}
+
+type CompactResult<T> = Result<T, &'static str>;
+
+// see ContractInstruction::pack_compact, anything but its canonical output is refused
+struct CompactReader<'a> {
+    data: &'a [u8],
+    // presence of the option fields not read yet, the next one in the lowest bit
+    flags: u8,
+}
+
+impl<'a> CompactReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        CompactReader { data, flags: 0 }
+    }
+
+    fn finish(&self) -> CompactResult<()> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err("trailing bytes")
+        }
+    }
+
+    fn take(&mut self, len: usize) -> CompactResult<&'a [u8]> {
+        if self.data.len() < len {
+            return Err("truncated");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> CompactResult<[u8; N]> {
+        // take returns exactly N bytes
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> CompactResult<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn bool(&mut self) -> CompactResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("invalid bool"),
+        }
+    }
+
+    fn u16(&mut self) -> CompactResult<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> CompactResult<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> CompactResult<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> CompactResult<i64> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn pubkey(&mut self) -> CompactResult<Pubkey> {
+        Ok(Pubkey::new_from_array(self.array()?))
+    }
+
+    fn bytes(&mut self) -> CompactResult<Vec<u8>> {
+        let len = self.u8()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> CompactResult<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| "invalid utf-8")
+    }
+
+    fn symbol(&mut self) -> CompactResult<TokenType> {
+        let symbol = self.string()?;
+        if symbol.len() > MAX_SYMBOL_LEN {
+            return Err("symbol too long");
+        }
+        Ok(TokenType {
+            symbol,
+            mint: Pubkey::default(),
+        })
+    }
+
+    fn metadata(&mut self) -> CompactResult<TokenMetadata> {
+        let name = self.string()?;
+        let decimals = self.u8()?;
+        self.flags(1)?;
+        Ok(TokenMetadata {
+            name,
+            decimals,
+            metadata_uri: self.option(Self::string)?,
+        })
+    }
+
+    // count option fields follow
+    fn flags(&mut self, count: u32) -> CompactResult<()> {
+        let flags = self.u8()?;
+        if flags >> count != 0 {
+            return Err("unknown option flags");
+        }
+        self.flags = flags;
+        Ok(())
+    }
+
+    fn option<T>(&mut self, read: fn(&mut Self) -> CompactResult<T>) -> CompactResult<Option<T>> {
+        let present = self.flags & 1 == 1;
+        self.flags >>= 1;
+        if present {
+            read(self).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // the tag and fields of ContractInstruction::write_compact, options after the other fields
+    fn instruction(&mut self) -> CompactResult<ContractInstruction> {
+        use ContractInstruction::*;
+        let tag = self.u8()?;
+        let Some((name, _)) = INSTRUCTION_DISCRIMINATORS.get(tag as usize) else {
+            return Err("unknown instruction tag");
+        };
+        Ok(match *name {
+            "initialize_state" => InitializeState,
+            "migrate_state" => MigrateState,
+            "initialize_config" => InitializeConfig {
+                admin: self.pubkey()?,
+            },
+            "admin_add_supported_token" => {
+                let symbol = self.symbol()?.symbol;
+                AdminAddSupportedToken {
+                    token: TokenType {
+                        symbol,
+                        mint: self.pubkey()?,
+                    },
+                    metadata: self.metadata()?,
+                }
+            }
+            "admin_delete_supported_token" => AdminDeleteSupportedToken {
+                token: self.symbol()?,
+            },
+            "admin_force_delete_token" => AdminForceDeleteToken {
+                token: self.symbol()?,
+                confirm: self.bool()?,
+            },
+            "user_deposit" => UserDeposit {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+                nonce: self.u64()?,
+                lock_until: {
+                    self.flags(4)?;
+                    self.option(Self::i64)?
+                },
+                referrer: self.option(Self::pubkey)?,
+                beneficiary: self.option(Self::pubkey)?,
+                memo: self.option(Self::string)?,
+            },
+            "user_withdraw" => UserWithdraw {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+                nonce: self.u64()?,
+                eth_signature: {
+                    self.flags(3)?;
+                    self.option(Self::bytes)?
+                },
+                recipient: self.option(Self::pubkey)?,
+                memo: self.option(Self::string)?,
+            },
+            "user_withdraw_all" => UserWithdrawAll {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "user_request_withdraw" => UserRequestWithdraw {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+            },
+            "user_claim_withdraw" => UserClaimWithdraw {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_cancel_withdraw_request" => AdminCancelWithdrawRequest {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_set_withdraw_delay" => AdminSetWithdrawDelay {
+                delay_slots: self.u64()?,
+            },
+            "admin_set_treasury" => AdminSetTreasury {
+                treasury: self.pubkey()?,
+            },
+            "admin_set_withdrawal_fee" => AdminSetWithdrawalFee {
+                token: self.symbol()?,
+                fee_bps: self.u16()?,
+            },
+            "admin_set_deposit_fee" => AdminSetDepositFee {
+                token: self.symbol()?,
+                fee_bps: self.u16()?,
+            },
+            "admin_set_fee_exempt" => AdminSetFeeExempt {
+                user: self.pubkey()?,
+                exempt: self.bool()?,
+            },
+            "admin_withdraw_treasury" => AdminWithdrawTreasury {
+                token: self.symbol()?,
+                amount: self.u64()?,
+            },
+            "user_set_withdraw_delegate" => UserSetWithdrawDelegate {
+                user: self.pubkey()?,
+                delegate: {
+                    self.flags(1)?;
+                    self.option(Self::pubkey)?
+                },
+            },
+            "admin_freeze_user" => AdminFreezeUser {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_unfreeze_user" => AdminUnfreezeUser {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_set_blocked" => AdminSetBlocked {
+                user: self.pubkey()?,
+                blocked: self.bool()?,
+            },
+            "admin_set_operator" => AdminSetOperator {
+                operator: {
+                    self.flags(1)?;
+                    self.option(Self::pubkey)?
+                },
+            },
+            "admin_propose_new_admin" => AdminProposeNewAdmin {
+                new_admin: self.pubkey()?,
+            },
+            "admin_cancel_proposed_admin" => AdminCancelProposedAdmin,
+            "accept_admin" => AcceptAdmin,
+            "admin_pause" => AdminPause {
+                withdrawals_only: self.bool()?,
+            },
+            "admin_unpause" => AdminUnpause,
+            "admin_set_token_flags" => AdminSetTokenFlags {
+                token: self.symbol()?,
+                deposits_enabled: self.bool()?,
+                withdrawals_enabled: self.bool()?,
+            },
+            "admin_set_max_tokens" => AdminSetMaxTokens {
+                max_tokens: self.u32()?,
+            },
+            "admin_set_deposit_cap" => AdminSetDepositCap {
+                token: self.symbol()?,
+                cap: {
+                    self.flags(1)?;
+                    self.option(Self::u64)?
+                },
+            },
+            "admin_set_max_user_balance" => AdminSetMaxUserBalance {
+                token: self.symbol()?,
+                max_user_balance: {
+                    self.flags(1)?;
+                    self.option(Self::u64)?
+                },
+            },
+            "admin_set_min_deposit" => AdminSetMinDeposit {
+                token: self.symbol()?,
+                min_deposit: self.u64()?,
+            },
+            "admin_set_daily_withdrawal_limit" => AdminSetDailyWithdrawalLimit {
+                token: self.symbol()?,
+                limit: self.u64()?,
+            },
+            "batch_deposit" => BatchDeposit {
+                user: self.pubkey()?,
+                items: self.items()?,
+            },
+            "batch_withdraw" => BatchWithdraw {
+                user: self.pubkey()?,
+                items: self.items()?,
+            },
+            "user_transfer" => UserTransfer {
+                token: self.symbol()?,
+                from: self.pubkey()?,
+                to: self.pubkey()?,
+                amount: self.u64()?,
+            },
+            "user_approve" => UserApprove {
+                token: self.symbol()?,
+                owner: self.pubkey()?,
+                spender: self.pubkey()?,
+                amount: self.u64()?,
+            },
+            "transfer_from" => TransferFrom {
+                token: self.symbol()?,
+                owner: self.pubkey()?,
+                spender: self.pubkey()?,
+                to: self.pubkey()?,
+                amount: self.u64()?,
+            },
+            "get_balance" => GetBalance {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "link_eth_address" => LinkEthAddress {
+                user: self.pubkey()?,
+                signature: self.bytes()?,
+            },
+            "get_sequence" => GetSequence,
+            "get_nonce" => GetNonce {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "list_supported_tokens" => ListSupportedTokens {
+                offset: self.u32()?,
+                limit: self.u32()?,
+            },
+            "get_token_stats" => GetTokenStats {
+                token: self.symbol()?,
+            },
+            "admin_update_token_metadata" => AdminUpdateTokenMetadata {
+                token: self.symbol()?,
+                metadata: self.metadata()?,
+            },
+            "admin_set_display_precision" => AdminSetDisplayPrecision {
+                token: self.symbol()?,
+                display_precision: {
+                    self.flags(1)?;
+                    self.option(Self::u8)?
+                },
+            },
+            "admin_set_reward_rate" => AdminSetRewardRate {
+                token: self.symbol()?,
+                points_per_day: self.u64()?,
+            },
+            "claim_rewards" => ClaimRewards {
+                user: self.pubkey()?,
+            },
+            "get_reward_points" => GetRewardPoints {
+                user: self.pubkey()?,
+            },
+            "admin_deposit_vested" => AdminDepositVested {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+                start_ts: self.i64()?,
+                duration_secs: self.u64()?,
+            },
+            "get_referral_stats" => GetReferralStats {
+                referrer: self.pubkey()?,
+            },
+            "admin_snapshot_state" => AdminSnapshotState,
+            "get_audit_log" => GetAuditLog {
+                offset: self.u32()?,
+                limit: self.u32()?,
+            },
+            "close_user_balance" => CloseUserBalance {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_emergency_sweep" => AdminEmergencySweep {
+                token: self.symbol()?,
+                destination: self.pubkey()?,
+            },
+            "multicall" => {
+                let count = self.u8()?;
+                let mut calls = Vec::new();
+                for _ in 0..count {
+                    let len = self.u16()? as usize;
+                    let mut call = CompactReader::new(self.take(len)?);
+                    calls.push(call.instruction()?);
+                    call.finish()?;
+                }
+                Multicall { calls }
+            }
+            "admin_rename_token" => AdminRenameToken {
+                old: self.symbol()?,
+                new_symbol: self.symbol()?.symbol,
+            },
+            "admin_set_merkle_root" => AdminSetMerkleRoot {
+                token: self.symbol()?,
+                root: self.array()?,
+                total: self.u64()?,
+            },
+            "claim_airdrop" => ClaimAirdrop {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+                proof: {
+                    let count = self.u8()?;
+                    (0..count)
+                        .map(|_| self.array())
+                        .collect::<CompactResult<_>>()?
+                },
+            },
+            "redeem_voucher" => RedeemVoucher {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+                voucher_id: self.u64()?,
+                expiry: self.i64()?,
+                signature: self.array()?,
+            },
+            "admin_set_rate_limit" => AdminSetRateLimit {
+                max_ops: self.u32()?,
+                window_slots: self.u64()?,
+            },
+            "admin_set_global_cap" => AdminSetGlobalCap {
+                cap: u128::from_le_bytes(self.array()?),
+            },
+            "get_global_stats" => GetGlobalStats,
+            "admin_set_price_feed" => AdminSetPriceFeed {
+                token: self.symbol()?,
+                feed: self.pubkey()?,
+                usd_cap: self.u64()?,
+                max_age_slots: self.u64()?,
+            },
+            "admin_set_swap_rate" => AdminSetSwapRate {
+                from: self.symbol()?,
+                to: self.symbol()?,
+                numerator: self.u64()?,
+                denominator: self.u64()?,
+            },
+            "user_swap" => UserSwap {
+                from: self.symbol()?,
+                to: self.symbol()?,
+                user: self.pubkey()?,
+                amount_in: self.u64()?,
+                min_amount_out: self.u64()?,
+            },
+            "admin_enable_share_mode" => AdminEnableShareMode {
+                token: self.symbol()?,
+            },
+            "admin_donate_yield" => AdminDonateYield {
+                token: self.symbol()?,
+                amount: self.u64()?,
+            },
+            "admin_slash" => AdminSlash {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+                reason_code: self.u16()?,
+            },
+            "admin_queue_action" => AdminQueueAction {
+                action: Box::new(self.instruction()?),
+                eta_slot: self.u64()?,
+            },
+            "admin_execute_action" => AdminExecuteAction {
+                action: Box::new(self.instruction()?),
+            },
+            "admin_set_timelock_delay" => AdminSetTimelockDelay {
+                delay_slots: self.u64()?,
+            },
+            "admin_set_circuit_breaker" => AdminSetCircuitBreaker {
+                token: self.symbol()?,
+                threshold_bps: self.u16()?,
+                window_slots: self.u64()?,
+            },
+            "admin_reset_breaker" => AdminResetBreaker {
+                token: self.symbol()?,
+            },
+            "admin_set_dust_threshold" => AdminSetDustThreshold {
+                token: self.symbol()?,
+                threshold: self.u64()?,
+            },
+            "is_token_supported" => IsTokenSupported {
+                token: self.symbol()?,
+            },
+            "get_user_stats" => GetUserStats {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_set_withdraw_cooldown" => AdminSetWithdrawCooldown {
+                token: self.symbol()?,
+                withdraw_cooldown_slots: self.u64()?,
+                transfers_reset: self.bool()?,
+            },
+            "admin_set_min_withdraw" => AdminSetMinWithdraw {
+                token: self.symbol()?,
+                min_withdraw: self.u64()?,
+            },
+            "get_receipt" => GetReceipt {
+                user: self.pubkey()?,
+                receipt_id: self.u64()?,
+            },
+            "user_enqueue_withdraw" => UserEnqueueWithdraw {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+                amount: self.u64()?,
+            },
+            "user_cancel_queued_withdraw" => UserCancelQueuedWithdraw {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "process_withdraw_queue" => ProcessWithdrawQueue {
+                token: self.symbol()?,
+                max_items: self.u8()?,
+            },
+            "get_queue_position" => GetQueuePosition {
+                token: self.symbol()?,
+                user: self.pubkey()?,
+            },
+            "admin_add_supported_tokens" => {
+                let count = self.u8()?;
+                AdminAddSupportedTokens {
+                    tokens: (0..count)
+                        .map(|_| {
+                            Ok(TokenType {
+                                symbol: self.symbol()?.symbol,
+                                mint: self.pubkey()?,
+                            })
+                        })
+                        .collect::<CompactResult<_>>()?,
+                }
+            }
+            _ => return Err("unknown instruction tag"),
+        })
+    }
+
+    fn items(&mut self) -> CompactResult<Vec<(TokenType, u64)>> {
+        let count = self.u8()?;
+        (0..count)
+            .map(|_| Ok((self.symbol()?, self.u64()?)))
+            .collect()
+    }
+}
+
+// decode instruction data of any encoding, malformed data is an error, never a panic
+pub fn deserialize_instruction(data: &[u8]) -> Result<ContractInstruction, ProgramError> {
+    if data.len() > MAX_INSTRUCTION_DATA_LEN {
+        msg!("instruction data too long: {} bytes", data.len());
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let (version, payload) = data
+        .split_first()
+        .ok_or(ProgramError::InvalidInstructionData)?;
+    let instruction = match *version {
+        // original layout, kept for old clients
+        INSTRUCTION_VERSION_JSON => serde_json::from_slice(payload).map_err(|e| {
+            msg!("invalid instruction data: {}", e);
+            ProgramError::InvalidInstructionData
+        })?,
+        INSTRUCTION_VERSION_BORSH => ContractInstruction::try_from_slice(payload).map_err(|e| {
+            msg!("invalid instruction data: {}", e);
+            ProgramError::InvalidInstructionData
+        })?,
+        INSTRUCTION_VERSION_COMPACT => ContractInstruction::unpack_compact(data)?,
+        _ => decode_anchor(data)?,
+    };
+    Ok(instruction)
+}
+
+// Anchor-style data, the discriminator selects the variant and its fields follow in borsh
+fn decode_anchor(data: &[u8]) -> Result<ContractInstruction, ProgramError> {
+    let discriminator = data.get(..8).unwrap_or(data);
+    let Some(tag) = INSTRUCTION_DISCRIMINATORS
+        .iter()
+        .position(|(_, known)| &known[..] == discriminator)
+    else {
+        // neither encoding
+        msg!("unsupported instruction version {}", data[0]);
+        return Err(ProgramError::InvalidInstructionData);
+    };
+    // the legacy payload is the same fields behind the variant index
+    let payload = [&[tag as u8][..], &data[8..]].concat();
+    ContractInstruction::try_from_slice(&payload).map_err(|e| {
+        msg!("invalid instruction data: {}", e);
+        ProgramError::InvalidInstructionData
+    })
+}
+
+// what a user's Ethereum key signs to authorize UserWithdraw:
+// program_id || token mint || user || amount LE || nonce LE
+pub fn eth_withdraw_message(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+    nonce: u64,
+) -> Vec<u8> {
+    [
+        program_id.as_ref(),
+        token.mint.as_ref(),
+        user.as_ref(),
+        &amount.to_le_bytes(),
+        &nonce.to_le_bytes(),
+    ]
+    .concat()
+}
+
+// what an Ethereum key signs to be linked to user: program_id || "link" || user
+pub fn eth_link_message(program_id: &Pubkey, user: &Pubkey) -> Vec<u8> {
+    [program_id.as_ref(), ETH_LINK_TAG, user.as_ref()].concat()
+}
+
+// what the admin signs to issue a RedeemVoucher: program_id || "voucher" || token mint ||
+// user || amount LE || voucher_id LE || expiry LE
+pub fn voucher_message(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    amount: u64,
+    voucher_id: u64,
+    expiry: i64,
+) -> Vec<u8> {
+    [
+        program_id.as_ref(),
+        VOUCHER_TAG,
+        token.mint.as_ref(),
+        user.as_ref(),
+        &amount.to_le_bytes(),
+        &voucher_id.to_le_bytes(),
+        &expiry.to_le_bytes(),
+    ]
+    .concat()
+}
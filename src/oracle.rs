@@ -0,0 +1,75 @@
+// prices read from Pyth price accounts for the USD deposit caps, see AdminSetPriceFeed
+// only the fields those need are read, at their offsets in pyth-client's v2 price account
+// (pc_price_t), the aggregate is the price used
+
+// first field of every Pyth account
+pub const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+pub const PYTH_VERSION: u32 = 2;
+// account type of a price account
+pub const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+// status of an aggregate that is currently trading, any other is no price
+pub const PYTH_STATUS_TRADING: u32 = 1;
+
+// byte offsets of the fields read, each little endian
+pub const PYTH_MAGIC_OFFSET: usize = 0;
+pub const PYTH_VERSION_OFFSET: usize = 4;
+pub const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+// i32
+pub const PYTH_EXPONENT_OFFSET: usize = 20;
+// aggregate price: i64
+pub const PYTH_PRICE_OFFSET: usize = 208;
+// aggregate status: u32
+pub const PYTH_STATUS_OFFSET: usize = 224;
+// slot the aggregate was published in: u64
+pub const PYTH_PUBLISH_SLOT_OFFSET: usize = 232;
+// shortest data holding every field read
+pub const PYTH_PRICE_ACCOUNT_MIN_LEN: usize = PYTH_PUBLISH_SLOT_OFFSET + 8;
+
+// price * 10^exponent US dollars for one whole token
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Price {
+    pub price: u64,
+    pub exponent: i32,
+    pub publish_slot: u64,
+}
+
+impl Price {
+    // the aggregate of a Pyth price account, None unless it is one and trading at a positive
+    // price
+    pub fn from_pyth(data: &[u8]) -> Option<Price> {
+        let field = |offset: usize| -> [u8; 4] { data[offset..offset + 4].try_into().unwrap() };
+        if data.len() < PYTH_PRICE_ACCOUNT_MIN_LEN
+            || u32::from_le_bytes(field(PYTH_MAGIC_OFFSET)) != PYTH_MAGIC
+            || u32::from_le_bytes(field(PYTH_VERSION_OFFSET)) != PYTH_VERSION
+            || u32::from_le_bytes(field(PYTH_ACCOUNT_TYPE_OFFSET)) != PYTH_ACCOUNT_TYPE_PRICE
+            || u32::from_le_bytes(field(PYTH_STATUS_OFFSET)) != PYTH_STATUS_TRADING
+        {
+            return None;
+        }
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        let price = read_u64(PYTH_PRICE_OFFSET) as i64;
+        Some(Price {
+            price: u64::try_from(price).ok().filter(|&price| price > 0)?,
+            exponent: i32::from_le_bytes(field(PYTH_EXPONENT_OFFSET)),
+            publish_slot: read_u64(PYTH_PUBLISH_SLOT_OFFSET),
+        })
+    }
+
+    // whole US dollars amount base units of a token with decimals are worth, rounded down,
+    // None when that doesn't fit a u128
+    pub fn usd_value(&self, amount: u64, decimals: u8) -> Option<u128> {
+        let value = u128::from(amount) * u128::from(self.price);
+        let exponent = self.exponent - i32::from(decimals);
+        if exponent >= 0 {
+            value.checked_mul(10u128.checked_pow(exponent as u32)?)
+        } else {
+            // a divisor past u128::MAX leaves nothing of the value
+            Some(
+                10u128
+                    .checked_pow(exponent.unsigned_abs())
+                    .map_or(0, |divisor| value / divisor),
+            )
+        }
+    }
+}
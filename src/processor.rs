@@ -0,0 +1,5314 @@
+// instruction handlers, process_instruction dispatches to them
+use std::collections::{BTreeSet, HashMap};
+
+use borsh::BorshDeserialize;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    ed25519_program,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
+    hash::hash,
+    instruction::Instruction,
+    keccak, msg,
+    program::{invoke, invoke_signed, set_return_data, MAX_RETURN_DATA},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    secp256k1_recover::secp256k1_recover,
+    system_instruction::{self, MAX_PERMITTED_DATA_LENGTH},
+    system_program,
+    sysvar::{clock::Clock, instructions, Sysvar},
+};
+
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::extension::{
+    transfer_fee::TransferFeeConfig, BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+};
+use spl_token_2022::state::{Account, Mint};
+
+use crate::error::ContractError;
+use crate::events::{
+    AirdropClaimedEvent, CircuitBreakerTrippedEvent, DepositEvent, Event, RewardsClaimedEvent,
+    SlashEvent, SweepEvent, TokenAddedEvent, TokenRemovedEvent, TokenRenamedEvent,
+    VoucherRedeemedEvent, WithdrawEvent,
+};
+use crate::instruction::{
+    deserialize_instruction, eth_link_message, eth_withdraw_message, voucher_message, AuditPage,
+    ContractInstruction, GlobalStats, ReferralStats, SupportedToken, TokenPage, TokenStats,
+    UserBalance, MAX_AUDIT_ENTRIES_PER_PAGE, MAX_MEMO_LEN, MAX_TOKENS_PER_PAGE,
+};
+use crate::oracle::Price;
+use crate::state::{
+    airdrop_leaf, amount_for_shares, canonical_symbol, find_balance_address, find_receipts_address,
+    find_vault_address, find_vault_address_with_program_id, find_vault_authority, load_balances,
+    load_state, load_versioned_payload, migrate_payload, read_receipts, shares_for_amount,
+    store_state, token_symbol_hash, validate_token_metadata, validate_token_symbol,
+    verify_merkle_proof, write_balance_account, write_state_payload, Airdrop, AuditEntry,
+    BalanceAccount, BalanceAccountData, BalanceAccounts, BalanceSlots, CircuitBreaker,
+    ContractState, DepositReceipt, LockedLot, PriceFeed, QueuedAction, QueuedWithdraw, RateBuckets,
+    ReceiptsHeader, RewardPool, SwapRate, TokenEntry, TokenMetadata, TokenType, UserStats,
+    VestingSchedule, WithdrawCooldown, WithdrawRequest, AUDIT_LOG_CAPACITY, BALANCE_ACCOUNT_SIZE,
+    BALANCE_ACCOUNT_TAG, BALANCE_ACCOUNT_V1_SIZE, BALANCE_SEED, MAX_LOCKED_LOTS,
+    MAX_RECEIPTS_PER_USER, MAX_VESTING_SCHEDULES, MAX_WITHDRAW_QUEUE_LEN, MIN_TIMELOCK_DELAY_SLOTS,
+    NATIVE_SOL_SYMBOL, RECEIPTS_ACCOUNT_TAG, RECEIPTS_HEADER_LEN, RECEIPTS_SEED,
+    SNAPSHOT_HEADER_LEN, STATE_ACCOUNT_SIZE, STATE_HEADER_LEN, STATE_SEED, STATE_UNINITIALIZED,
+    STATE_VERSION, VAULT_SEED,
+};
+
+// accounts needed to move SPL tokens between a user and the program's vault
+// for native SOL user_token_account is the user's system account, the vault is also its own
+// authority and token_program is the system program
+struct TokenTransferAccounts<'a, 'b> {
+    user_token_account: &'a AccountInfo<'b>,
+    vault_token_account: &'a AccountInfo<'b>,
+    // PDA [VAULT_SEED, mint] owning the vault, signs withdrawals
+    vault_authority: &'a AccountInfo<'b>,
+    token_program: &'a AccountInfo<'b>,
+    // only passed after the Token-2022 program, its transfers need the mint
+    mint: Option<&'a AccountInfo<'b>>,
+}
+
+// Token-2022 mint extensions a vault can live with, the others let tokens move or change
+// outside of deposits and withdrawals
+const SUPPORTED_MINT_EXTENSIONS: &[ExtensionType] = &[
+    ExtensionType::TransferFeeConfig,
+    ExtensionType::MintCloseAuthority,
+    ExtensionType::InterestBearingConfig,
+    ExtensionType::MetadataPointer,
+    ExtensionType::TokenMetadata,
+];
+
+// half the secp256k1 group order, big endian, the largest s of a low-s signature
+const SECP256K1_HALF_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+// highest fee in basis points, 10%
+const MAX_FEE_BPS: u16 = 1000;
+// length of the daily withdrawal limit window
+const WITHDRAWAL_WINDOW_SECS: i64 = 24 * 60 * 60;
+// bounds the compute used by a single batch instruction
+const MAX_BATCH_ITEMS: usize = 16;
+// most calls a Multicall runs
+const MAX_MULTICALL_CALLS: usize = 8;
+
+// program entrypoint's implementation
+// accounts[0] is always the state PDA, accounts[1] is the signer (admin or user) of the instruction
+// read-only queries only take the state account
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let instruction = deserialize_instruction(instruction_data)?;
+    let signers = if is_relayable(&instruction) {
+        ed25519_signers(program_id, accounts, instruction_data)?
+    } else {
+        Vec::new()
+    };
+    let accounts: Vec<AccountInfo> = accounts
+        .iter()
+        .map(|account| {
+            let mut account = account.clone();
+            account.is_signer |= signers.contains(account.key);
+            account
+        })
+        .collect();
+    let account_info_iter = &mut accounts.iter();
+    let state_account = next_account_info(account_info_iter)?;
+
+    if let ContractInstruction::InitializeState = instruction {
+        return initialize_state(program_id, state_account, account_info_iter);
+    }
+    check_state_account(program_id, state_account, !is_query(&instruction))?;
+    // the only instruction that runs against an older state version
+    if let ContractInstruction::MigrateState = instruction {
+        let signer = next_account_info(account_info_iter)?;
+        return migrate_state(state_account, signer);
+    }
+
+    let mut state = load_state(state_account)?;
+    let mut instruction = instruction;
+    resolve_token_symbols(&mut instruction, &state);
+    let balance_accounts = load_balances(program_id, &accounts[1..], &mut state)?;
+    let balances_before = balance_snapshot(&state);
+    let first_receipt_id = state.next_receipt_id;
+    // before anything changes the balances they were held at
+    accrue_rewards(&mut state)?;
+
+    check_not_timelocked(&instruction)?;
+    let mut audit_entries = Vec::new();
+    if let ContractInstruction::Multicall { calls } = instruction {
+        multicall(
+            program_id,
+            calls,
+            &accounts,
+            &balance_accounts,
+            &mut state,
+            &mut audit_entries,
+        )?;
+    } else if is_query(&instruction) {
+        // queries leave the state as it was
+        return execute(
+            program_id,
+            instruction,
+            &accounts,
+            &balance_accounts,
+            &mut state,
+            &mut audit_entries,
+        );
+    } else {
+        execute(
+            program_id,
+            instruction,
+            &accounts,
+            &balance_accounts,
+            &mut state,
+            &mut audit_entries,
+        )?;
+    }
+
+    check_locked_balances(&balances_before, &mut state)?;
+    for entry in audit_entries {
+        push_audit_entry(entry, &mut state);
+    }
+    update_holder_counts(&balances_before, &mut state);
+    prune_reward_indexes(&balances_before, &mut state);
+    store_balances(
+        program_id,
+        &accounts,
+        &balance_accounts,
+        &balances_before,
+        &mut state,
+    )?;
+    store_receipts(program_id, &accounts, first_receipt_id, &mut state)?;
+    grow_state_account(state_account, &accounts, &state)?;
+    store_state(state_account, &state)
+}
+
+// realloc the state account when state no longer fits it, accounts[1] tops its lamports up to
+// the rent of the new size through the system program, which must then be passed too
+// an instruction can add at most MAX_PERMITTED_DATA_INCREASE bytes to the length the account
+// had when it started
+fn grow_state_account<'a>(
+    state_account: &AccountInfo<'a>,
+    accounts: &[AccountInfo<'a>],
+    state: &ContractState,
+) -> ProgramResult {
+    let len = STATE_HEADER_LEN + state.serialized_size();
+    if len <= state_account.data_len() {
+        return Ok(());
+    }
+    // the runtime serializes the length the account started with right before its key
+    let original_len = unsafe { state_account.original_data_len() };
+    let max_len =
+        (original_len + MAX_PERMITTED_DATA_INCREASE).min(MAX_PERMITTED_DATA_LENGTH as usize);
+    if len > max_len {
+        msg!(
+            "state needs {} bytes, the account can grow to {}",
+            len,
+            max_len
+        );
+        return Err(ContractError::StateAccountFull.into());
+    }
+    let Some(system_program) = accounts
+        .iter()
+        .find(|account| account.key == &system_program::ID)
+    else {
+        msg!(
+            "state needs {} bytes, pass the system program to grow it",
+            len
+        );
+        return Err(ContractError::StateAccountFull.into());
+    };
+    let payer = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if !payer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let shortfall = Rent::get()?
+        .minimum_balance(len)
+        .saturating_sub(state_account.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, state_account.key, shortfall),
+            &[payer.clone(), state_account.clone(), system_program.clone()],
+        )?;
+    }
+    state_account.realloc(len, true)
+}
+
+// run calls in order against the same state, any failing call fails the multicall and with it
+// every call before it, each call's accounts start with the state account as they would on
+// their own, so they can simply be concatenated
+fn multicall<'a, 'b>(
+    program_id: &Pubkey,
+    calls: Vec<ContractInstruction>,
+    accounts: &'a [AccountInfo<'b>],
+    balance_accounts: &BalanceAccounts<'a, 'b>,
+    state: &mut ContractState,
+    audit_entries: &mut Vec<AuditEntry>,
+) -> ProgramResult {
+    if calls.len() > MAX_MULTICALL_CALLS {
+        return Err(ContractError::BatchTooLarge.into());
+    }
+    let state_key = accounts[0].key;
+    let mut starts: Vec<usize> = accounts
+        .iter()
+        .enumerate()
+        .filter(|(_, account)| account.key == state_key)
+        .map(|(index, _)| index)
+        .collect();
+    if starts.len() != calls.len() {
+        msg!(
+            "multicall of {} calls got accounts for {}",
+            calls.len(),
+            starts.len()
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    starts.push(accounts.len());
+    for (index, call) in calls.into_iter().enumerate() {
+        // queries would overwrite each other's return data
+        if matches!(
+            call,
+            ContractInstruction::InitializeState
+                | ContractInstruction::MigrateState
+                | ContractInstruction::Multicall { .. }
+        ) || is_query(&call)
+        {
+            msg!("multicall call {} can't be batched", index);
+            return Err(ContractError::CallNotBatchable.into());
+        }
+        check_not_timelocked(&call)?;
+        let call_accounts = &accounts[starts[index]..starts[index + 1]];
+        execute(
+            program_id,
+            call,
+            call_accounts,
+            balance_accounts,
+            state,
+            audit_entries,
+        )
+        .inspect_err(|_| {
+            msg!("multicall call {} failed", index);
+        })?;
+    }
+    Ok(())
+}
+
+// instructions users get timelock_delay_slots to react to, they only run as the action of
+// AdminExecuteAction, pausing stays immediate
+fn is_timelocked(instruction: &ContractInstruction) -> bool {
+    matches!(
+        instruction,
+        ContractInstruction::AdminDeleteSupportedToken { .. }
+            | ContractInstruction::AdminForceDeleteToken { .. }
+            | ContractInstruction::AdminSetWithdrawalFee { .. }
+            | ContractInstruction::AdminSetDepositFee { .. }
+            | ContractInstruction::AdminProposeNewAdmin { .. }
+            | ContractInstruction::AdminSetTimelockDelay { .. }
+    )
+}
+
+fn check_not_timelocked(instruction: &ContractInstruction) -> ProgramResult {
+    if is_timelocked(instruction) {
+        msg!("instruction {} has to be queued first", instruction.tag());
+        return Err(ContractError::TimelockRequired.into());
+    }
+    Ok(())
+}
+
+// read-only instructions, they return data and don't write the state back
+fn is_query(instruction: &ContractInstruction) -> bool {
+    matches!(
+        instruction,
+        ContractInstruction::GetBalance { .. }
+            | ContractInstruction::GetNonce { .. }
+            | ContractInstruction::GetSequence
+            | ContractInstruction::ListSupportedTokens { .. }
+            | ContractInstruction::GetTokenStats { .. }
+            | ContractInstruction::IsTokenSupported { .. }
+            | ContractInstruction::GetUserStats { .. }
+            | ContractInstruction::GetReceipt { .. }
+            | ContractInstruction::GetQueuePosition { .. }
+            | ContractInstruction::GetRewardPoints { .. }
+            | ContractInstruction::GetReferralStats { .. }
+            | ContractInstruction::GetAuditLog { .. }
+            | ContractInstruction::GetGlobalStats
+    )
+}
+
+// authorize and run a single instruction against state, accounts starting with the state account
+// the audit entry of a state change is queued in audit_entries
+fn execute<'a, 'b>(
+    program_id: &Pubkey,
+    instruction: ContractInstruction,
+    accounts: &'a [AccountInfo<'b>],
+    balance_accounts: &BalanceAccounts<'a, 'b>,
+    state: &mut ContractState,
+    audit_entries: &mut Vec<AuditEntry>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let state_account = next_account_info(account_info_iter)?;
+
+    // admin and operator instructions all take their authority as accounts[1]
+    if let Some(role) = required_role(&instruction) {
+        let signer = next_account_info(account_info_iter)?;
+        verify_role(signer, role, state)?;
+    }
+
+    // every instruction writing the state takes the next number, a failed one is discarded
+    // along with the rest of its changes
+    if !is_query(&instruction) {
+        state.sequence = state
+            .sequence
+            .checked_add(1)
+            .ok_or(ContractError::BalanceOverflow)?;
+        // logged once the instruction succeeded, a failed one isn't written back
+        let (token, amount) = audit_subject(&instruction);
+        audit_entries.push(AuditEntry {
+            sequence: state.sequence,
+            instruction_tag: instruction.tag(),
+            actor: accounts
+                .get(1)
+                .map(|account| *account.key)
+                .unwrap_or_default(),
+            token_symbol_hash: token.map_or(0, |token| token_symbol_hash(&token.symbol)),
+            amount,
+            slot: Clock::get()?.slot,
+        });
+    }
+
+    let rate_limited = rate_limited_user(&instruction);
+    match instruction {
+        ContractInstruction::InitializeState
+        | ContractInstruction::MigrateState
+        | ContractInstruction::Multicall { .. } => unreachable!(),
+        ContractInstruction::InitializeConfig { admin } => {
+            let signer = next_account_info(account_info_iter)?;
+            initialize_config(admin, signer, state)?;
+        }
+        ContractInstruction::AdminAddSupportedToken { token, metadata } => {
+            check_add_token(
+                program_id,
+                token,
+                Some(metadata),
+                accounts,
+                account_info_iter,
+                state,
+            )?;
+        }
+        ContractInstruction::AdminAddSupportedTokens { tokens } => {
+            check_add_tokens(program_id, tokens, accounts, account_info_iter, state)?;
+        }
+        ContractInstruction::AdminDeleteSupportedToken { token } => {
+            let vault_account = next_account_info(account_info_iter)?;
+            let vault_token = token.clone();
+            check_delete_token(token, false, state)?;
+            check_vault_empty(program_id, &vault_token, vault_account)?;
+        }
+        ContractInstruction::AdminForceDeleteToken { token, confirm } => {
+            if !confirm {
+                return Err(ContractError::ForceDeleteNotConfirmed.into());
+            }
+            check_delete_token(token, true, state)?;
+        }
+        ContractInstruction::UserDeposit {
+            token,
+            user,
+            amount,
+            nonce,
+            lock_until,
+            referrer,
+            beneficiary,
+            memo,
+        } => {
+            check_memo(&memo)?;
+            let mint = token.mint;
+            check_nonce(program_id, &mint, &user, nonce, accounts, state)?;
+            let signer = next_account_info(account_info_iter)?;
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            user_deposit_token(
+                program_id,
+                token,
+                user,
+                amount,
+                lock_until,
+                referrer,
+                beneficiary,
+                memo.clone(),
+                signer,
+                &transfer_accounts,
+                state,
+            )?;
+            check_usd_cap(&mint, accounts, state)?;
+            forward_memo(&memo, accounts)?;
+            // like every other state change the bump is dropped if the instruction fails
+            bump_nonce(program_id, &mint, &user, accounts, state)?;
+        }
+        ContractInstruction::UserWithdraw {
+            token,
+            user,
+            amount,
+            nonce,
+            eth_signature,
+            recipient,
+            memo,
+        } => {
+            check_memo(&memo)?;
+            let mint = token.mint;
+            check_nonce(program_id, &mint, &user, nonce, accounts, state)?;
+            let signer = next_account_info(account_info_iter)?;
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            let mut signer = signer.clone();
+            if let Some(signature) = eth_signature {
+                let message = eth_withdraw_message(program_id, &token, &user, amount, nonce);
+                verify_eth_withdraw(
+                    &message,
+                    &signature,
+                    &user,
+                    &signer,
+                    &transfer_accounts,
+                    state,
+                )?;
+                signer.is_signer = true;
+            }
+            user_withdraw_token(
+                program_id,
+                token,
+                user,
+                amount,
+                recipient,
+                memo.clone(),
+                &signer,
+                &transfer_accounts,
+                state,
+            )?;
+            forward_memo(&memo, accounts)?;
+            // like every other state change the bump is dropped if the instruction fails
+            bump_nonce(program_id, &mint, &user, accounts, state)?;
+        }
+        ContractInstruction::UserWithdrawAll { token, user } => {
+            let signer = next_account_info(account_info_iter)?;
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            user_withdraw_all(program_id, token, user, signer, &transfer_accounts, state)?;
+        }
+        ContractInstruction::CloseUserBalance { token, user } => {
+            let signer = next_account_info(account_info_iter)?;
+            close_user_balance(program_id, token, user, signer, balance_accounts, state)?;
+        }
+        ContractInstruction::UserRequestWithdraw {
+            token,
+            user,
+            amount,
+        } => {
+            let signer = next_account_info(account_info_iter)?;
+            user_request_withdraw(token, user, amount, signer, state)?;
+        }
+        ContractInstruction::UserClaimWithdraw { token, user } => {
+            let signer = next_account_info(account_info_iter)?;
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            user_claim_withdraw(program_id, token, user, signer, &transfer_accounts, state)?;
+        }
+        ContractInstruction::UserEnqueueWithdraw {
+            token,
+            user,
+            amount,
+        } => {
+            let signer = next_account_info(account_info_iter)?;
+            user_enqueue_withdraw(token, user, amount, signer, state)?;
+        }
+        ContractInstruction::UserCancelQueuedWithdraw { token, user } => {
+            let signer = next_account_info(account_info_iter)?;
+            cancel_queued_withdraw(token, user, signer, state)?;
+        }
+        ContractInstruction::ProcessWithdrawQueue { token, max_items } => {
+            // the payer, it pays for the treasury's balance account
+            next_account_info(account_info_iter)?;
+            if usize::from(max_items) > MAX_BATCH_ITEMS {
+                return Err(ContractError::BatchTooLarge.into());
+            }
+            let queued = state
+                .withdraw_queues
+                .get(&token.mint)
+                .map_or(0, |queue| queue.len())
+                .min(usize::from(max_items));
+            let transfer_accounts = (0..queued)
+                .map(|_| next_token_transfer_accounts(account_info_iter))
+                .collect::<Result<Vec<_>, _>>()?;
+            process_withdraw_queue(program_id, token, &transfer_accounts, accounts, state)?;
+        }
+        ContractInstruction::AdminCancelWithdrawRequest { token, user } => {
+            cancel_withdraw_request(token, user, state)?;
+        }
+        ContractInstruction::AdminSetWithdrawDelay { delay_slots } => {
+            set_withdraw_delay(delay_slots, state)?;
+        }
+        ContractInstruction::AdminSetTreasury { treasury } => {
+            set_treasury(treasury, state)?;
+        }
+        ContractInstruction::AdminSetWithdrawalFee { token, fee_bps } => {
+            set_withdrawal_fee(token, fee_bps, state)?;
+        }
+        ContractInstruction::AdminSetDepositFee { token, fee_bps } => {
+            set_deposit_fee(token, fee_bps, state)?;
+        }
+        ContractInstruction::AdminSetFeeExempt { user, exempt } => {
+            set_fee_exempt(user, exempt, state)?;
+        }
+        ContractInstruction::AdminDepositVested {
+            token,
+            user,
+            amount,
+            start_ts,
+            duration_secs,
+        } => {
+            // accounts[1] is the admin that verify_role checked
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            let schedule = VestingSchedule {
+                amount,
+                start_ts,
+                duration_secs,
+            };
+            admin_deposit_vested(
+                program_id,
+                token,
+                user,
+                schedule,
+                &accounts[1],
+                &transfer_accounts,
+                state,
+            )?;
+        }
+        ContractInstruction::AdminSnapshotState => {
+            let destination = next_account_info(account_info_iter)?;
+            snapshot_state(program_id, state_account, destination, state)?;
+        }
+        ContractInstruction::AdminWithdrawTreasury { token, amount } => {
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            withdraw_treasury(program_id, token, amount, &transfer_accounts, state)?;
+        }
+        ContractInstruction::AdminEmergencySweep { token, destination } => {
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            emergency_sweep(program_id, token, destination, &transfer_accounts, state)?;
+        }
+        ContractInstruction::AdminRenameToken { old, new_symbol } => {
+            rename_token(old, new_symbol, state)?;
+        }
+        ContractInstruction::AdminSetMerkleRoot { token, root, total } => {
+            // accounts[1] is the admin that verify_role checked
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            set_merkle_root(
+                program_id,
+                token,
+                root,
+                total,
+                &accounts[1],
+                &transfer_accounts,
+                state,
+            )?;
+        }
+        ContractInstruction::ClaimAirdrop {
+            token,
+            user,
+            amount,
+            proof,
+        } => {
+            claim_airdrop(token, user, amount, &proof, state)?;
+        }
+        ContractInstruction::RedeemVoucher {
+            token,
+            user,
+            amount,
+            voucher_id,
+            expiry,
+            signature,
+        } => {
+            let voucher = voucher_message(program_id, &token, &user, amount, voucher_id, expiry);
+            let signed = match accounts
+                .iter()
+                .find(|account| instructions::check_id(account.key))
+            {
+                Some(sysvar) => ed25519_signatures(sysvar, &voucher)?,
+                None => Vec::new(),
+            };
+            redeem_voucher(
+                token, user, amount, voucher_id, expiry, &signature, &signed, state,
+            )?;
+        }
+        ContractInstruction::UserSetWithdrawDelegate { user, delegate } => {
+            let signer = next_account_info(account_info_iter)?;
+            set_withdraw_delegate(user, delegate, signer, state)?;
+        }
+        ContractInstruction::AdminFreezeUser { token, user } => {
+            set_user_frozen(token, user, true, state)?;
+        }
+        ContractInstruction::AdminUnfreezeUser { token, user } => {
+            set_user_frozen(token, user, false, state)?;
+        }
+        ContractInstruction::AdminSetBlocked { user, blocked } => {
+            set_blocked(user, blocked, state)?;
+        }
+        ContractInstruction::AdminSetOperator { operator } => {
+            set_operator(operator, state)?;
+        }
+        ContractInstruction::AdminProposeNewAdmin { new_admin } => {
+            propose_new_admin(Some(new_admin), state)?;
+        }
+        ContractInstruction::AdminCancelProposedAdmin => {
+            propose_new_admin(None, state)?;
+        }
+        ContractInstruction::AcceptAdmin => {
+            let signer = next_account_info(account_info_iter)?;
+            accept_admin(signer, state)?;
+        }
+        ContractInstruction::AdminPause { withdrawals_only } => {
+            set_paused(true, withdrawals_only, state)?;
+        }
+        ContractInstruction::AdminUnpause => {
+            set_paused(false, false, state)?;
+        }
+        ContractInstruction::AdminSetTokenFlags {
+            token,
+            deposits_enabled,
+            withdrawals_enabled,
+        } => {
+            set_token_flags(token, deposits_enabled, withdrawals_enabled, state)?;
+        }
+        ContractInstruction::AdminSetMaxTokens { max_tokens } => {
+            set_max_tokens(max_tokens, state)?;
+        }
+        ContractInstruction::AdminSetDepositCap { token, cap } => {
+            set_deposit_cap(token, cap, state)?;
+        }
+        ContractInstruction::AdminSetMaxUserBalance {
+            token,
+            max_user_balance,
+        } => {
+            set_max_user_balance(token, max_user_balance, state)?;
+        }
+        ContractInstruction::AdminSetMinDeposit { token, min_deposit } => {
+            set_min_deposit(token, min_deposit, state)?;
+        }
+        ContractInstruction::AdminSetDailyWithdrawalLimit { token, limit } => {
+            set_daily_withdrawal_limit(token, limit, state)?;
+        }
+        ContractInstruction::BatchDeposit { user, items } => {
+            let signer = next_account_info(account_info_iter)?;
+            if items.len() > MAX_BATCH_ITEMS {
+                return Err(ContractError::BatchTooLarge.into());
+            }
+            let transfer_accounts = items
+                .iter()
+                .map(|_| next_token_transfer_accounts(account_info_iter))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mints: Vec<Pubkey> = items.iter().map(|(token, _)| token.mint).collect();
+            user_batch_deposit(program_id, user, items, signer, &transfer_accounts, state)?;
+            for mint in &mints {
+                check_usd_cap(mint, accounts, state)?;
+            }
+        }
+        ContractInstruction::BatchWithdraw { user, items } => {
+            let signer = next_account_info(account_info_iter)?;
+            if items.len() > MAX_BATCH_ITEMS {
+                return Err(ContractError::BatchTooLarge.into());
+            }
+            let transfer_accounts = items
+                .iter()
+                .map(|_| next_token_transfer_accounts(account_info_iter))
+                .collect::<Result<Vec<_>, _>>()?;
+            user_batch_withdraw(program_id, user, items, signer, &transfer_accounts, state)?;
+        }
+        ContractInstruction::UserTransfer {
+            token,
+            from,
+            to,
+            amount,
+        } => {
+            let signer = next_account_info(account_info_iter)?;
+            user_transfer_token(token, from, to, amount, signer, state)?;
+        }
+        ContractInstruction::UserApprove {
+            token,
+            owner,
+            spender,
+            amount,
+        } => {
+            let signer = next_account_info(account_info_iter)?;
+            user_approve(token, owner, spender, amount, signer, state)?;
+        }
+        ContractInstruction::TransferFrom {
+            token,
+            owner,
+            spender,
+            to,
+            amount,
+        } => {
+            let signer = next_account_info(account_info_iter)?;
+            transfer_from(token, owner, spender, to, amount, signer, state)?;
+        }
+        ContractInstruction::GetBalance { token, user } => {
+            // read-only query, state is not written back
+            return get_balance(token, user, state);
+        }
+        ContractInstruction::LinkEthAddress { user, signature } => {
+            let signer = next_account_info(account_info_iter)?;
+            link_eth_address(program_id, user, &signature, signer, state)?;
+        }
+        ContractInstruction::GetSequence => {
+            // read-only query, state is not written back
+            set_return_data(&state.sequence.to_le_bytes());
+            return Ok(());
+        }
+        ContractInstruction::GetNonce { token, user } => {
+            // read-only query, state is not written back
+            let nonce = current_nonce(program_id, &token.mint, &user, accounts, state)?;
+            set_return_data(&nonce.to_le_bytes());
+            return Ok(());
+        }
+        ContractInstruction::ListSupportedTokens { offset, limit } => {
+            // read-only query, state is not written back
+            return list_supported_tokens(offset, limit, state);
+        }
+        ContractInstruction::GetTokenStats { token } => {
+            // read-only query, state is not written back
+            return get_token_stats(token, state);
+        }
+        ContractInstruction::IsTokenSupported { token } => {
+            // read-only query, state is not written back
+            return is_token_supported(&token, state);
+        }
+        ContractInstruction::GetUserStats { token, user } => {
+            // read-only query, state is not written back
+            return get_user_stats(&token, &user, state);
+        }
+        ContractInstruction::GetReceipt { user, receipt_id } => {
+            // read-only query, state is not written back
+            return get_receipt(program_id, accounts, &user, receipt_id, state);
+        }
+        ContractInstruction::GetQueuePosition { token, user } => {
+            // read-only query, state is not written back
+            return get_queue_position(&token, &user, state);
+        }
+        ContractInstruction::GetAuditLog { offset, limit } => {
+            // read-only query, state is not written back
+            return get_audit_log(offset, limit, state);
+        }
+        ContractInstruction::AdminUpdateTokenMetadata { token, metadata } => {
+            let decimals = if token.is_native() {
+                spl_token::native_mint::DECIMALS
+            } else {
+                let mint_account = next_account_info(account_info_iter)?;
+                load_mint(mint_account, &token.mint)?.decimals
+            };
+            update_token_metadata(token, metadata, decimals, state)?;
+        }
+        ContractInstruction::AdminSetDisplayPrecision {
+            token,
+            display_precision,
+        } => {
+            set_display_precision(token, display_precision, state)?;
+        }
+        ContractInstruction::AdminSetRewardRate {
+            token,
+            points_per_day,
+        } => {
+            set_reward_rate(token, points_per_day, state)?;
+        }
+        ContractInstruction::ClaimRewards { user } => {
+            let signer = next_account_info(account_info_iter)?;
+            claim_rewards(user, signer, state)?;
+        }
+        ContractInstruction::GetRewardPoints { user } => {
+            // read-only query, state is not written back
+            let points = state.reward_points.get(&user).copied().unwrap_or(0);
+            set_return_data(&points.to_le_bytes());
+            return Ok(());
+        }
+        ContractInstruction::GetReferralStats { referrer } => {
+            // read-only query, state is not written back
+            let stats = ReferralStats {
+                volume: state.referral_volume.get(&referrer).copied().unwrap_or(0),
+                referees: state
+                    .referrers
+                    .values()
+                    .filter(|bound| **bound == Some(referrer))
+                    .count() as u64,
+            };
+            // serializing into a Vec can't fail
+            set_return_data(&borsh::to_vec(&stats).unwrap());
+            return Ok(());
+        }
+        ContractInstruction::AdminSetRateLimit {
+            max_ops,
+            window_slots,
+        } => {
+            set_rate_limit(max_ops, window_slots, state)?;
+        }
+        ContractInstruction::AdminSetGlobalCap { cap } => {
+            state.global_deposit_cap = cap;
+        }
+        ContractInstruction::AdminSetPriceFeed {
+            token,
+            feed,
+            usd_cap,
+            max_age_slots,
+        } => {
+            set_price_feed(token, feed, usd_cap, max_age_slots, state)?;
+        }
+        ContractInstruction::AdminSetSwapRate {
+            from,
+            to,
+            numerator,
+            denominator,
+        } => {
+            set_swap_rate(from, to, numerator, denominator, state)?;
+        }
+        ContractInstruction::AdminEnableShareMode { token } => {
+            enable_share_mode(token, state)?;
+        }
+        ContractInstruction::AdminDonateYield { token, amount } => {
+            // accounts[1] is the admin that verify_role checked
+            let transfer_accounts = next_token_transfer_accounts(account_info_iter)?;
+            donate_yield(
+                program_id,
+                token,
+                amount,
+                &accounts[1],
+                &transfer_accounts,
+                state,
+            )?;
+        }
+        ContractInstruction::AdminSlash {
+            token,
+            user,
+            amount,
+            reason_code,
+        } => {
+            slash(token, user, amount, reason_code, state)?;
+        }
+        ContractInstruction::AdminQueueAction { action, eta_slot } => {
+            queue_action(&action, eta_slot, state)?;
+        }
+        ContractInstruction::AdminExecuteAction { action } => {
+            take_queued_action(&action, state)?;
+            // authorized and logged as itself, it takes the same accounts
+            execute(
+                program_id,
+                *action,
+                accounts,
+                balance_accounts,
+                state,
+                audit_entries,
+            )?;
+        }
+        ContractInstruction::AdminSetTimelockDelay { delay_slots } => {
+            set_timelock_delay(delay_slots, state)?;
+        }
+        ContractInstruction::AdminSetCircuitBreaker {
+            token,
+            threshold_bps,
+            window_slots,
+        } => {
+            set_circuit_breaker(token, threshold_bps, window_slots, state)?;
+        }
+        ContractInstruction::AdminResetBreaker { token } => {
+            reset_breaker(token, state)?;
+        }
+        ContractInstruction::AdminSetDustThreshold { token, threshold } => {
+            set_dust_threshold(token, threshold, state)?;
+        }
+        ContractInstruction::AdminSetWithdrawCooldown {
+            token,
+            withdraw_cooldown_slots,
+            transfers_reset,
+        } => {
+            set_withdraw_cooldown(token, withdraw_cooldown_slots, transfers_reset, state)?;
+        }
+        ContractInstruction::AdminSetMinWithdraw {
+            token,
+            min_withdraw,
+        } => {
+            set_min_withdraw(token, min_withdraw, state)?;
+        }
+        ContractInstruction::UserSwap {
+            from,
+            to,
+            user,
+            amount_in,
+            min_amount_out,
+        } => {
+            let signer = next_account_info(account_info_iter)?;
+            user_swap(from, to, user, amount_in, min_amount_out, signer, state)?;
+        }
+        ContractInstruction::GetGlobalStats => {
+            // read-only query, state is not written back
+            return get_global_stats(state);
+        }
+    }
+    // counted once the operation itself succeeded, so its own errors come first
+    if let Some(user) = rate_limited {
+        count_operation(user, state)?;
+    }
+    Ok(())
+}
+
+// count the balances that went from zero to nonzero or back, absent counts as zero
+fn update_holder_counts(before: &HashMap<(Pubkey, Pubkey), u64>, state: &mut ContractState) {
+    for (token, entry) in &state.all_token_balances {
+        let was_held = |user: &Pubkey| before.get(&(token.mint, *user)).is_some_and(|&b| b > 0);
+        let is_held = |user: &Pubkey| entry.balances.get(user).is_some_and(|&b| b > 0);
+        let gained = entry
+            .balances
+            .keys()
+            .filter(|user| is_held(user) && !was_held(user))
+            .count() as u64;
+        let lost = before
+            .keys()
+            .filter(|(mint, user)| mint == &token.mint && was_held(user) && !is_held(user))
+            .count() as u64;
+        if gained == lost {
+            continue;
+        }
+        let count = state.holder_counts.entry(token.mint).or_insert(0);
+        // saturating, holders of listings from before v6 were never counted
+        *count = count.saturating_add(gained).saturating_sub(lost);
+    }
+}
+
+// every (mint, user) balance the state knows about
+fn balance_snapshot(state: &ContractState) -> HashMap<(Pubkey, Pubkey), u64> {
+    state
+        .all_token_balances
+        .iter()
+        .flat_map(|(token, entry)| {
+            entry
+                .balances
+                .iter()
+                .map(|(user, amount)| ((token.mint, *user), *amount))
+        })
+        .collect()
+}
+
+// write the balances the instruction changed into their balance accounts along with their
+// stats and slots, creating missing ones and closing those whose balance was dropped, then
+// take them out of the state, balances of older layouts stay inline until they change
+// every account is located before the first one is touched
+fn store_balances<'a, 'b>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'b>],
+    loaded: &BalanceAccounts<'a, 'b>,
+    before: &HashMap<(Pubkey, Pubkey), u64>,
+    state: &mut ContractState,
+) -> ProgramResult {
+    let mut closes = Vec::new();
+    // (account, whether it has to be created first, contents)
+    let mut writes = Vec::new();
+    for (token, entry) in state.all_token_balances.iter_mut() {
+        let epoch = state.token_epochs.get(&token.mint).copied().unwrap_or(0);
+        for (&(mint, user), account) in loaded {
+            if mint == token.mint
+                && before.contains_key(&(mint, user))
+                && !entry.balances.contains_key(&user)
+            {
+                closes.push(*account);
+            }
+        }
+        for (user, amount) in &entry.balances {
+            let key = (token.mint, *user);
+            let stats = state.user_stats.get(&key).copied().unwrap_or_default();
+            let slots = state.balance_slots.get(&key).copied().unwrap_or_default();
+            if before.get(&key) == Some(amount) {
+                let Some(account) = loaded.get(&key) else {
+                    continue;
+                };
+                let stored = BalanceAccountData::read(&account.try_borrow_data()?)?;
+                if stored.stats() == stats && stored.slots() == slots {
+                    continue;
+                }
+            }
+            let (address, bump) = find_balance_address(program_id, &token.mint, user);
+            let (account, create) = match loaded.get(&key) {
+                Some(account) => (*account, false),
+                // absent means 0, no account needed for it
+                None if *amount == 0 => continue,
+                None => {
+                    let Some(account) = accounts.iter().find(|account| account.key == &address)
+                    else {
+                        msg!("missing balance account {} of user {}", address, user);
+                        return Err(ContractError::MissingBalanceAccount.into());
+                    };
+                    // bump_nonce may have created it already
+                    (account, account.owner != program_id)
+                }
+            };
+            let balance = BalanceAccount {
+                mint: token.mint,
+                user: *user,
+                bump,
+                epoch,
+                amount: *amount,
+                flags: 0,
+                last_update_slot: 0,
+                nonce: 0,
+                stats,
+                slots,
+            };
+            writes.push((account, create, balance));
+        }
+        entry.balances.retain(|user, amount| {
+            !loaded.contains_key(&(token.mint, *user))
+                && before.get(&(token.mint, *user)) == Some(amount)
+        });
+    }
+    // written, closed or loaded, whichever way they are in their accounts now
+    let stored = |key: &(Pubkey, Pubkey)| {
+        loaded.contains_key(key)
+            || writes
+                .iter()
+                .any(|(_, _, balance)| (balance.mint, balance.user) == *key)
+    };
+    state.user_stats.retain(|key, _| !stored(key));
+    state.balance_slots.retain(|key, _| !stored(key));
+
+    // closed accounts refund their user when it is passed writable, accounts[1] otherwise,
+    // created ones are paid by accounts[1]
+    let signer = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys);
+    for account in closes {
+        // an account created again at the address starts past the nonce it was at
+        let (user, nonce) = {
+            let data = account.try_borrow_data()?;
+            let user = BalanceAccountData::read(&data)?.user;
+            (user, state.balance_nonce(&user, &data))
+        };
+        state.nonce_floor = state.nonce_floor.max(nonce);
+        let recipient = match accounts
+            .iter()
+            .find(|account| account.key == &user && account.is_writable)
+        {
+            Some(owner) => owner,
+            None => signer.clone()?,
+        };
+        close_balance_account(account, recipient)?;
+    }
+    let slot = Clock::get()?.slot;
+    for (account, create, mut balance) in writes {
+        balance.last_update_slot = slot;
+        if create {
+            balance.nonce = state.first_nonce(&balance.user);
+            create_balance_account(program_id, accounts, signer.clone()?, account, &balance)?;
+        } else {
+            upgrade_balance_account(account, accounts, state)?;
+        }
+        write_balance_account(account, &balance)?;
+    }
+    Ok(())
+}
+
+// move the receipts of first_id on, the ones the instruction issued, out of the state into
+// their users' receipts accounts, each grows by a receipt until it holds
+// MAX_RECEIPTS_PER_USER, then the oldest is overwritten, accounts[1] pays for creating and
+// growing them
+fn store_receipts<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    first_id: u64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    for (user, receipts) in state.receipts.iter_mut() {
+        let issued = receipts.partition_point(|receipt| receipt.receipt_id < first_id);
+        if issued == receipts.len() {
+            continue;
+        }
+        let (address, bump) = find_receipts_address(program_id, user);
+        let Some(account) = accounts.iter().find(|account| account.key == &address) else {
+            msg!("missing receipts account {} of user {}", address, user);
+            return Err(ContractError::MissingReceiptsAccount.into());
+        };
+        let payer = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let system_program = accounts
+            .iter()
+            .find(|account| account.key == &system_program::ID)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let rent = Rent::get()?;
+        if account.owner != program_id {
+            invoke_signed(
+                &system_instruction::create_account(
+                    payer.key,
+                    account.key,
+                    rent.minimum_balance(RECEIPTS_HEADER_LEN),
+                    RECEIPTS_HEADER_LEN as u64,
+                    program_id,
+                ),
+                &[payer.clone(), account.clone(), system_program.clone()],
+                &[&[RECEIPTS_SEED, user.as_ref(), &[bump]]],
+            )?;
+            let mut data = account.try_borrow_mut_data()?;
+            *bytemuck::from_bytes_mut(&mut data[..RECEIPTS_HEADER_LEN]) = ReceiptsHeader {
+                tag: RECEIPTS_ACCOUNT_TAG,
+                user: *user,
+                bump,
+                next: [0; 2],
+            };
+        }
+        let new = receipts.split_off(issued);
+        let held = account.data_len().saturating_sub(RECEIPTS_HEADER_LEN) / DepositReceipt::LEN;
+        let len = RECEIPTS_HEADER_LEN
+            + (held + new.len()).min(MAX_RECEIPTS_PER_USER) * DepositReceipt::LEN;
+        if len > account.data_len() {
+            let shortfall = rent.minimum_balance(len).saturating_sub(account.lamports());
+            if shortfall > 0 {
+                invoke(
+                    &system_instruction::transfer(payer.key, account.key, shortfall),
+                    &[payer.clone(), account.clone(), system_program.clone()],
+                )?;
+            }
+            account.realloc(len, true)?;
+        }
+        let mut data = account.try_borrow_mut_data()?;
+        let (header, ring) = data.split_at_mut(RECEIPTS_HEADER_LEN);
+        let header = ReceiptsHeader::load_mut(header)?;
+        let mut next = header.next();
+        for receipt in &new {
+            let mut slot = &mut ring[next * DepositReceipt::LEN..(next + 1) * DepositReceipt::LEN];
+            // a receipt always fits its slot
+            borsh::to_writer(&mut slot, receipt).unwrap();
+            next = (next + 1) % MAX_RECEIPTS_PER_USER;
+        }
+        header.set_next(next);
+    }
+    // the ones left are from before receipts accounts
+    state.receipts.retain(|_, receipts| !receipts.is_empty());
+    Ok(())
+}
+
+// bring a balance account of an older, shorter layout to BALANCE_ACCOUNT_SIZE, the fields it
+// lacked 0 but for a missing nonce, first_nonce as that is the one its user was at, accounts[1]
+// tops its lamports up to the rent of the new size through the system program
+fn upgrade_balance_account<'a>(
+    account: &AccountInfo<'a>,
+    accounts: &[AccountInfo<'a>],
+    state: &ContractState,
+) -> ProgramResult {
+    if account.data_len() >= BALANCE_ACCOUNT_SIZE {
+        return Ok(());
+    }
+    let system_program = accounts
+        .iter()
+        .find(|account| account.key == &system_program::ID)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let payer = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let shortfall = Rent::get()?
+        .minimum_balance(BALANCE_ACCOUNT_SIZE)
+        .saturating_sub(account.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, shortfall),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    let len = account.data_len();
+    account.realloc(BALANCE_ACCOUNT_SIZE, true)?;
+    if len < BALANCE_ACCOUNT_V1_SIZE {
+        let mut data = account.try_borrow_mut_data()?;
+        let balance = BalanceAccountData::load_mut(&mut data)?;
+        balance.set_nonce(state.first_nonce(&balance.user));
+    }
+    Ok(())
+}
+
+fn create_balance_account<'a>(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    balance: &BalanceAccount,
+) -> ProgramResult {
+    let system_program = accounts
+        .iter()
+        .find(|account| account.key == &system_program::ID)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let rent = Rent::get()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            account.key,
+            rent.minimum_balance(BALANCE_ACCOUNT_SIZE),
+            BALANCE_ACCOUNT_SIZE as u64,
+            program_id,
+        ),
+        &[payer.clone(), account.clone(), system_program.clone()],
+        &[&[
+            BALANCE_SEED,
+            balance.mint.as_ref(),
+            balance.user.as_ref(),
+            &[balance.bump],
+        ]],
+    )
+}
+
+// hand the rent to recipient and give the account back to the system program
+fn close_balance_account(account: &AccountInfo, recipient: &AccountInfo) -> ProgramResult {
+    let lamports = account.lamports();
+    **recipient.try_borrow_mut_lamports()? = recipient
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ContractError::BalanceOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.try_borrow_mut_data()?.fill(0);
+    account.assign(&system_program::ID);
+    Ok(())
+}
+
+// ed25519 precompile data: [signature count: u8][padding: u8] then one
+// [signature, pubkey, message offsets and instruction indexes: 7 x u16 LE] per signature
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_LEN: usize = 14;
+// instruction index meaning "the ed25519 instruction itself"
+const ED25519_CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+// whether a payload signed off-chain is accepted for instruction, only ones carrying a nonce
+// they consume are, a signature over any other payload could be replayed in a new transaction
+fn is_relayable(instruction: &ContractInstruction) -> bool {
+    matches!(
+        instruction,
+        ContractInstruction::UserDeposit { .. } | ContractInstruction::UserWithdraw { .. }
+    )
+}
+
+// keys that signed program_id || instruction_data through an ed25519 precompile instruction
+// earlier in this transaction, they count as signers so a relayer can submit a user's payload
+// the instructions sysvar is found by key among the accounts, no sysvar means no such keys
+// deposits still need a real signature, the SPL transfer is authorized by the user
+// only consulted for instructions is_relayable accepts
+fn ed25519_signers(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> Result<Vec<Pubkey>, ProgramError> {
+    let Some(sysvar) = accounts
+        .iter()
+        .find(|account| instructions::check_id(account.key))
+    else {
+        return Ok(Vec::new());
+    };
+    let message = [program_id.as_ref(), instruction_data].concat();
+    let signatures = ed25519_signatures(sysvar, &message)?;
+    Ok(signatures.into_iter().map(|(key, _)| key).collect())
+}
+
+// a key that signed through the ed25519 precompile and its signature, None when the
+// precompile read it from another instruction
+type Ed25519Signature = (Pubkey, Option<[u8; 64]>);
+
+// every ed25519 precompile signature over message earlier in this transaction
+fn ed25519_signatures(
+    sysvar: &AccountInfo,
+    message: &[u8],
+) -> Result<Vec<Ed25519Signature>, ProgramError> {
+    let mut signatures = Vec::new();
+    let current = instructions::load_current_index_checked(sysvar)?;
+    for index in 0..current {
+        let instruction = instructions::load_instruction_at_checked(index.into(), sysvar)?;
+        if ed25519_program::check_id(&instruction.program_id) {
+            signatures.extend(ed25519_signed_keys(&instruction.data, message));
+        }
+    }
+    Ok(signatures)
+}
+
+// keys and signatures of the precompile instruction's signatures over message, the runtime
+// already verified them, only offsets pointing into the precompile instruction itself are read
+fn ed25519_signed_keys(data: &[u8], message: &[u8]) -> Vec<Ed25519Signature> {
+    let count = data.first().copied().unwrap_or(0) as usize;
+    (0..count)
+        .filter_map(|i| {
+            let start = ED25519_OFFSETS_START + i * ED25519_OFFSETS_LEN;
+            let offsets = data.get(start..start + ED25519_OFFSETS_LEN)?;
+            let field = |n: usize| u16::from_le_bytes([offsets[2 * n], offsets[2 * n + 1]]);
+            let (signature_offset, signature_index) = (field(0) as usize, field(1));
+            let (pubkey_offset, pubkey_index) = (field(2) as usize, field(3));
+            let (message_offset, message_size, message_index) =
+                (field(4) as usize, field(5) as usize, field(6));
+            if pubkey_index != ED25519_CURRENT_INSTRUCTION
+                || message_index != ED25519_CURRENT_INSTRUCTION
+            {
+                return None;
+            }
+            let pubkey = data.get(pubkey_offset..pubkey_offset + 32)?;
+            let signed = data.get(message_offset..message_offset + message_size)?;
+            let signature = (signature_index == ED25519_CURRENT_INSTRUCTION)
+                .then(|| data.get(signature_offset..signature_offset + 64))
+                .flatten()
+                .map(|signature| signature.try_into().unwrap());
+            (signed == message).then(|| (Pubkey::try_from(pubkey).unwrap(), signature))
+        })
+        .collect()
+}
+
+// create the state PDA if it does not exist yet, then write an empty state into it
+// accounts after the state account: [payer, system_program], only needed when the PDA has to be created
+fn initialize_state<'a, 'b>(
+    program_id: &Pubkey,
+    state_account: &'a AccountInfo<'b>,
+    account_info_iter: &mut impl Iterator<Item = &'a AccountInfo<'b>>,
+) -> ProgramResult {
+    let (state_pubkey, bump) = Pubkey::find_program_address(&[STATE_SEED], program_id);
+    if state_account.key != &state_pubkey {
+        return Err(ContractError::InvalidPda.into());
+    }
+
+    if state_account.data_is_empty() {
+        let payer = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+        check_program_id(system_program, &system_program::id())?;
+        let rent = Rent::get()?;
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                state_account.key,
+                rent.minimum_balance(STATE_ACCOUNT_SIZE),
+                STATE_ACCOUNT_SIZE as u64,
+                program_id,
+            ),
+            &[payer.clone(), state_account.clone(), system_program.clone()],
+            &[&[STATE_SEED, &[bump]]],
+        )?;
+    }
+    check_state_account(program_id, state_account, true)?;
+
+    let version = state_account.try_borrow_data()?.first().copied();
+    if version.is_some_and(|version| version != STATE_UNINITIALIZED) {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    store_state(state_account, &ContractState::default())
+}
+
+// the state account must be the program's own rent-exempt state PDA, an account carrying a
+// copy of the state anywhere else is refused, as is one the instruction can't write when it
+// changes the state, load_state then checks its discriminator
+fn check_state_account(
+    program_id: &Pubkey,
+    state_account: &AccountInfo,
+    writable: bool,
+) -> ProgramResult {
+    let (state_pubkey, _) = Pubkey::find_program_address(&[STATE_SEED], program_id);
+    if state_account.key != &state_pubkey {
+        return Err(ContractError::InvalidPda.into());
+    }
+    if state_account.owner != program_id {
+        msg!("state account is owned by {}", state_account.owner);
+        return Err(ContractError::InvalidStateAccount.into());
+    }
+    if writable && !state_account.is_writable {
+        msg!("state account isn't writable");
+        return Err(ContractError::InvalidStateAccount.into());
+    }
+    if !Rent::get()?.is_exempt(state_account.lamports(), state_account.data_len()) {
+        return Err(ContractError::NotRentExempt.into());
+    }
+    Ok(())
+}
+
+fn check_owner(account: &AccountInfo, owner: &Pubkey) -> ProgramResult {
+    if account.owner != owner {
+        return Err(ContractError::WrongOwner.into());
+    }
+    Ok(())
+}
+
+// program accounts passed in for CPIs must be the real programs
+fn check_program_id(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    if account.key != program_id {
+        return Err(ContractError::InvalidProgramId.into());
+    }
+    Ok(())
+}
+
+// upgrade the state account by one schema version, signed by the admin recorded in it
+fn migrate_state(state_account: &AccountInfo, signer: &AccountInfo) -> ProgramResult {
+    let (version, payload) = load_versioned_payload(state_account)?;
+    if version == STATE_VERSION {
+        return Err(ContractError::StateAlreadyCurrent.into());
+    }
+    if version > STATE_VERSION {
+        msg!("state version {} is newer than this program", version);
+        return Err(ContractError::UnsupportedStateVersion.into());
+    }
+    let payload = migrate_payload(version, payload)?;
+
+    // older layouts can't be decoded, so the admin is read from a fully migrated copy
+    let mut latest = payload.clone();
+    for step in version + 1..STATE_VERSION {
+        latest = migrate_payload(step, latest)?;
+    }
+    let state =
+        ContractState::try_from_slice(&latest).map_err(|_| ProgramError::InvalidAccountData)?;
+    verify_role(signer, Role::Admin, &state)?;
+
+    write_state_payload(
+        &mut state_account.try_borrow_mut_data()?,
+        version + 1,
+        &payload,
+    )
+}
+
+// export state into destination, stamped with the schema version and the time
+fn snapshot_state(
+    program_id: &Pubkey,
+    state_account: &AccountInfo,
+    destination: &AccountInfo,
+    state: &ContractState,
+) -> ProgramResult {
+    check_owner(destination, program_id)?;
+    let mut data = destination.try_borrow_mut_data()?;
+    if destination.key == state_account.key || data.first() == Some(&BALANCE_ACCOUNT_TAG) {
+        return Err(ContractError::InvalidSnapshotAccount.into());
+    }
+    let payload = borsh::to_vec(state).map_err(|_| ProgramError::InvalidAccountData)?;
+    if data.len() < SNAPSHOT_HEADER_LEN + payload.len() {
+        msg!(
+            "snapshot takes {} bytes, the account has {}",
+            SNAPSHOT_HEADER_LEN + payload.len(),
+            data.len()
+        );
+        return Err(ContractError::SnapshotBufferTooSmall.into());
+    }
+    data[0] = STATE_VERSION;
+    data[1..9].copy_from_slice(&Clock::get()?.unix_timestamp.to_le_bytes());
+    data[9..SNAPSHOT_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    data[SNAPSHOT_HEADER_LEN..SNAPSHOT_HEADER_LEN + payload.len()].copy_from_slice(&payload);
+    Ok(())
+}
+
+// record the admin, can only be done once
+fn initialize_config(
+    admin: Pubkey,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !signer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if state.admin.is_some() {
+        return Err(ContractError::ConfigAlreadyInitialized.into());
+    }
+    state.admin = Some(admin);
+    Ok(())
+}
+
+// set (or clear with None) the pending admin, a new proposal replaces the previous one
+fn propose_new_admin(
+    new_admin: Option<Pubkey>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if new_admin.is_none() && state.pending_admin.is_none() {
+        return Err(ContractError::NoPendingAdmin.into());
+    }
+    state.pending_admin = new_admin;
+    Ok(())
+}
+
+// pending admin takes over once it signs
+fn accept_admin(signer: &AccountInfo, state: &mut ContractState) -> Result<(), ProgramError> {
+    let pending_admin = state.pending_admin.ok_or(ContractError::NoPendingAdmin)?;
+    if !verify_signature(signer, &pending_admin) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    state.admin = Some(pending_admin);
+    state.pending_admin = None;
+    // the admin is never blocked
+    state.blocked.remove(&pending_admin);
+    Ok(())
+}
+
+// pause or unpause user deposits/withdrawals, token management is not affected
+fn set_paused(
+    paused: bool,
+    withdrawals_only: bool,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    state.paused = paused;
+    state.withdrawals_only = paused && withdrawals_only;
+    Ok(())
+}
+
+fn set_max_tokens(max_tokens: u32, state: &mut ContractState) -> Result<(), ProgramError> {
+    state.max_tokens = max_tokens;
+    Ok(())
+}
+
+// the new limit applies to the operations already counted, unless the window length changes,
+// which starts every count over as the buckets no longer line up
+fn set_rate_limit(
+    max_ops: u32,
+    window_slots: u64,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if max_ops > 0 && window_slots == 0 {
+        msg!("a rate limit needs a window of at least one slot");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if max_ops == 0 || window_slots != state.rate_limit_window_slots {
+        state.rate_buckets.clear();
+        state.rate_windows.clear();
+    }
+    state.rate_limit_ops = max_ops;
+    state.rate_limit_window_slots = window_slots;
+    Ok(())
+}
+
+// the user whose rate limit instruction counts against, see AdminSetRateLimit
+fn rate_limited_user(instruction: &ContractInstruction) -> Option<Pubkey> {
+    match instruction {
+        ContractInstruction::UserDeposit { user, .. }
+        | ContractInstruction::UserWithdraw { user, .. }
+        | ContractInstruction::UserWithdrawAll { user, .. }
+        | ContractInstruction::UserEnqueueWithdraw { user, .. }
+        | ContractInstruction::BatchDeposit { user, .. }
+        | ContractInstruction::BatchWithdraw { user, .. }
+        | ContractInstruction::UserTransfer { from: user, .. }
+        | ContractInstruction::TransferFrom { spender: user, .. }
+        | ContractInstruction::UserSwap { user, .. } => Some(*user),
+        _ => None,
+    }
+}
+
+// count one more operation in user's sliding window of the last rate_limit_window_slots slots.
+// slots are cut into buckets of the window's length and the previous bucket's operations count
+// for the share of it the window still covers, rounded up, so a burst at the end of one bucket
+// still counts at the start of the next
+fn count_operation(user: Pubkey, state: &mut ContractState) -> ProgramResult {
+    let max_ops = state.rate_limit_ops;
+    if max_ops == 0 || state.admin == Some(user) || state.treasury == Some(user) {
+        return Ok(());
+    }
+    let slot = Clock::get()?.slot;
+    let window_slots = state.rate_limit_window_slots;
+    let bucket_start = slot - slot % window_slots;
+    // buckets the window has moved past count for nobody
+    state
+        .rate_buckets
+        .retain(|_, buckets| buckets.bucket_start.saturating_add(window_slots) >= bucket_start);
+    state
+        .rate_windows
+        .retain(|_, window| window.start_slot.saturating_add(window_slots) > slot);
+    let legacy = state
+        .rate_windows
+        .remove(&user)
+        .map_or(0, |window| window.count);
+    let buckets = state.rate_buckets.entry(user).or_insert(RateBuckets {
+        bucket_start,
+        count: 0,
+        previous_count: 0,
+    });
+    if buckets.bucket_start != bucket_start {
+        let previous_count = if buckets.bucket_start.saturating_add(window_slots) == bucket_start {
+            buckets.count
+        } else {
+            0
+        };
+        *buckets = RateBuckets {
+            bucket_start,
+            count: 0,
+            previous_count,
+        };
+    }
+    buckets.count = buckets.count.saturating_add(legacy);
+    let carried = (u128::from(buckets.previous_count)
+        * u128::from(window_slots - (slot - bucket_start)))
+    .div_ceil(u128::from(window_slots));
+    let counted = carried + u128::from(buckets.count);
+    if counted >= u128::from(max_ops) {
+        msg!(
+            "{} operations in the last {} slots, the limit is {}",
+            counted,
+            window_slots,
+            max_ops
+        );
+        return Err(ContractError::RateLimited.into());
+    }
+    buckets.count += 1;
+    Ok(())
+}
+
+// add newly supported token, without metadata it is named after its symbol and takes its
+// mint's decimals
+fn check_add_token<'a, 'b>(
+    program_id: &Pubkey,
+    token: TokenType,
+    metadata: Option<TokenMetadata>,
+    accounts: &[AccountInfo<'b>],
+    account_info_iter: &mut impl Iterator<Item = &'a AccountInfo<'b>>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError>
+where
+    'b: 'a,
+{
+    if let Some(metadata) = &metadata {
+        validate_token_metadata(metadata)?;
+    }
+    let token = check_new_token(&token, state)?;
+    if state.all_token_balances.len() >= state.max_tokens as usize {
+        return Err(ContractError::TokenLimitReached.into());
+    }
+    let with_decimals = |decimals: u8| {
+        let metadata = metadata.unwrap_or_else(|| TokenMetadata {
+            name: token.symbol.clone(),
+            decimals,
+            metadata_uri: None,
+        });
+        check_decimals(&metadata, decimals).map(|_| metadata)
+    };
+    let metadata = if token.is_native() {
+        let payer = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let system_program = next_account_info(account_info_iter)?;
+        let metadata = with_decimals(spl_token::native_mint::DECIMALS)?;
+        init_native_vault(program_id, payer, vault_account, system_program)?;
+        metadata
+    } else {
+        let mint_account = next_account_info(account_info_iter)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let metadata = with_decimals(load_mint(mint_account, &token.mint)?.decimals)?;
+        check_mint_extensions(mint_account)?;
+        // the vault's address depends on the mint's token program, which pins it from now on
+        check_vault_account(program_id, vault_account, &token.mint, mint_account.owner)?;
+        metadata
+    };
+
+    TokenAddedEvent {
+        token_symbol: token.symbol.clone(),
+        mint: token.mint,
+        sequence: state.sequence,
+    }
+    .emit();
+    state.token_epochs.insert(token.mint, state.sequence);
+    state.token_metadata.insert(token.mint, metadata);
+    state.all_token_balances.insert(token, TokenEntry::new());
+    Ok(())
+}
+
+// token in the canonical form it is stored in, if neither its symbol nor its mint is listed
+fn check_new_token(token: &TokenType, state: &ContractState) -> Result<TokenType, ProgramError> {
+    validate_token_symbol(&token.symbol)?;
+    // "sol" and "SOL" are the same symbol, store the canonical form
+    let token = TokenType {
+        symbol: canonical_symbol(&token.symbol),
+        mint: token.mint,
+    };
+    if (token.symbol == NATIVE_SOL_SYMBOL) != token.is_native() {
+        return Err(ContractError::ReservedToken.into());
+    }
+    // the registry is keyed by mint, so it is the index of listed mints as well, one vault
+    // never backs two entries
+    if let Some((existing, _)) = state.all_token_balances.get_key_value(&token) {
+        msg!("mint {} is listed as {}", token.mint, existing.symbol);
+        return Err(ContractError::MintAlreadyRegistered.into());
+    }
+    if state
+        .all_token_balances
+        .keys()
+        .any(|existing| existing.symbol == token.symbol)
+    {
+        return Err(ContractError::TokenAlreadyExists.into());
+    }
+    Ok(token)
+}
+
+// list every token of a batch, each one is checked against the others and the listed ones
+// before the first is added
+fn check_add_tokens<'a, 'b>(
+    program_id: &Pubkey,
+    tokens: Vec<TokenType>,
+    accounts: &[AccountInfo<'b>],
+    account_info_iter: &mut impl Iterator<Item = &'a AccountInfo<'b>>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError>
+where
+    'b: 'a,
+{
+    if tokens.len() > MAX_BATCH_ITEMS {
+        return Err(ContractError::BatchTooLarge.into());
+    }
+    let mut checked: Vec<TokenType> = Vec::with_capacity(tokens.len());
+    for (index, token) in tokens.iter().enumerate() {
+        validate_token_symbol(&token.symbol)?;
+        let symbol = canonical_symbol(&token.symbol);
+        if checked
+            .iter()
+            .any(|other| other.mint == token.mint || other.symbol == symbol)
+        {
+            msg!("batch item {} repeats {} or its mint", index, token.symbol);
+            return Err(ContractError::DuplicateTokenInBatch.into());
+        }
+        checked.push(check_new_token(token, state).inspect_err(|_| {
+            msg!("batch item {} is listed already", index);
+        })?);
+    }
+    if state.all_token_balances.len() + checked.len() > state.max_tokens as usize {
+        return Err(ContractError::TokenLimitReached.into());
+    }
+    for token in checked {
+        check_add_token(program_id, token, None, accounts, account_info_iter, state)?;
+    }
+    Ok(())
+}
+
+// mint_account must be the real SPL or Token-2022 mint of mint
+fn load_mint(mint_account: &AccountInfo, mint: &Pubkey) -> Result<Mint, ProgramError> {
+    if mint_account.key != mint {
+        return Err(ContractError::MintAccountMismatch.into());
+    }
+    check_token_program_owner(mint_account)?;
+    Ok(StateWithExtensions::<Mint>::unpack(&mint_account.try_borrow_data()?)?.base)
+}
+
+fn check_mint_extensions(mint_account: &AccountInfo) -> ProgramResult {
+    if mint_account.owner != &spl_token_2022::id() {
+        return Ok(());
+    }
+    let data = mint_account.try_borrow_data()?;
+    let extensions = StateWithExtensions::<Mint>::unpack(&data)?.get_extension_types()?;
+    if let Some(extension) = extensions
+        .iter()
+        .find(|extension| !SUPPORTED_MINT_EXTENSIONS.contains(extension))
+    {
+        msg!("unsupported mint extension {:?}", extension);
+        return Err(ContractError::UnsupportedMintExtension.into());
+    }
+    Ok(())
+}
+
+// what the transfer fee extension of a Token-2022 mint keeps back of amount, 0 for other mints
+fn transfer_fee(
+    transfer_accounts: &TokenTransferAccounts,
+    amount: u64,
+) -> Result<u64, ProgramError> {
+    let Some(mint_account) = transfer_accounts.mint else {
+        return Ok(0);
+    };
+    let data = mint_account.try_borrow_data()?;
+    let mint = StateWithExtensions::<Mint>::unpack(&data)?;
+    let Ok(config) = mint.get_extension::<TransferFeeConfig>() else {
+        return Ok(0);
+    };
+    config
+        .calculate_epoch_fee(Clock::get()?.epoch, amount)
+        .ok_or(ContractError::BalanceOverflow.into())
+}
+
+// registered decimals have to be the mint's, or every amount shown would be off
+fn check_decimals(metadata: &TokenMetadata, decimals: u8) -> ProgramResult {
+    if metadata.decimals != decimals {
+        msg!("mint has {} decimals, not {}", decimals, metadata.decimals);
+        return Err(ContractError::DecimalsMismatch.into());
+    }
+    Ok(())
+}
+
+// top the SOL vault PDA up to rent exemption and take ownership of it, lamports sent to the
+// address beforehand are kept, a vault left by an earlier add is reused
+fn init_native_vault<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+) -> ProgramResult {
+    let mint = spl_token::native_mint::id();
+    let (vault, bump) = find_vault_authority(program_id, &mint);
+    if vault_account.key != &vault {
+        return Err(ContractError::InvalidVaultAccount.into());
+    }
+    check_program_id(system_program, &system_program::id())?;
+    if vault_account.owner == program_id {
+        return Ok(());
+    }
+    let shortfall = Rent::get()?
+        .minimum_balance(0)
+        .saturating_sub(vault_account.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, vault_account.key, shortfall),
+            &[payer.clone(), vault_account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::assign(vault_account.key, program_id),
+        &[vault_account.clone(), system_program.clone()],
+        &[&[VAULT_SEED, mint.as_ref(), &[bump]]],
+    )
+}
+
+// tokens sent to the vault outside of deposits would be stranded by deleting the token
+fn check_vault_empty(
+    program_id: &Pubkey,
+    token: &TokenType,
+    vault_account: &AccountInfo,
+) -> ProgramResult {
+    if token.is_native() {
+        check_native_vault(program_id, vault_account)?;
+    } else {
+        check_vault_account(program_id, vault_account, &token.mint, vault_account.owner)?;
+    }
+    if vault_balance(vault_account, &token.mint)? > 0 {
+        return Err(ContractError::VaultNotEmpty.into());
+    }
+    Ok(())
+}
+
+// delete supported token
+// without force, refuse while users still hold balances of token
+fn check_delete_token(
+    token: TokenType,
+    force: bool,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    // balances of users not passed to the instruction aren't loaded, total_deposited covers
+    // every balance and pending request
+    let total = entry.total_deposited;
+    if total > 0 {
+        if !force {
+            return Err(ContractError::TokenHasOutstandingBalances.into());
+        }
+        msg!(
+            "force deleted token {}, destroyed total balance {}",
+            token.symbol,
+            total
+        );
+    }
+    if let Some((token, _)) = state.all_token_balances.remove_entry(&token) {
+        // what a forced delete destroyed leaves the global total too
+        state.global_total_deposited = state
+            .global_total_deposited
+            .checked_sub(u128::from(total))
+            .ok_or(ProgramError::InvalidAccountData)?;
+        state.token_epochs.remove(&token.mint);
+        state.holder_counts.remove(&token.mint);
+        state.token_metadata.remove(&token.mint);
+        state.display_precisions.remove(&token.mint);
+        state.reward_pools.remove(&token.mint);
+        state
+            .reward_indexes
+            .retain(|(mint, _), _| mint != &token.mint);
+        state.locked_lots.retain(|(mint, _), _| mint != &token.mint);
+        state.user_stats.retain(|(mint, _), _| mint != &token.mint);
+        state
+            .balance_slots
+            .retain(|(mint, _), _| mint != &token.mint);
+        state
+            .vesting_schedules
+            .retain(|(mint, _), _| mint != &token.mint);
+        state.airdrops.remove(&token.mint);
+        state.price_feeds.remove(&token.mint);
+        state
+            .swap_rates
+            .retain(|(from, to), _| from != &token.mint && to != &token.mint);
+        state.share_supplies.remove(&token.mint);
+        state.circuit_breakers.remove(&token.mint);
+        state.dust_thresholds.remove(&token.mint);
+        state.withdraw_cooldowns.remove(&token.mint);
+        state.min_withdrawals.remove(&token.mint);
+        state.withdraw_queues.remove(&token.mint);
+        TokenRemovedEvent {
+            token_symbol: token.symbol,
+            mint: token.mint,
+            sequence: state.sequence,
+        }
+        .emit();
+    }
+    Ok(())
+}
+
+// re-key token's entry and allowances under new_symbol, everything else is keyed by mint
+fn rename_token(
+    token: TokenType,
+    new_symbol: String,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    validate_token_symbol(&new_symbol)?;
+    let new_symbol = canonical_symbol(&new_symbol);
+    if (new_symbol == NATIVE_SOL_SYMBOL) != token.is_native() {
+        return Err(ContractError::ReservedToken.into());
+    }
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if state
+        .all_token_balances
+        .keys()
+        .any(|existing| existing.symbol == new_symbol && existing != &token)
+    {
+        return Err(ContractError::TokenAlreadyExists.into());
+    }
+    // checked above, nothing fails from here on so the entry is never left out
+    let (old, entry) = state.all_token_balances.remove_entry(&token).unwrap();
+    let renamed = TokenType {
+        symbol: new_symbol,
+        mint: old.mint,
+    };
+    state.all_token_balances.insert(renamed.clone(), entry);
+    // the map keys compare by mint too, so the stale symbols have to be taken out and put back
+    let allowances: Vec<_> = state
+        .allowances
+        .keys()
+        .filter(|(allowed, _, _)| allowed == &renamed)
+        .cloned()
+        .collect();
+    for key in allowances {
+        if let Some(amount) = state.allowances.remove(&key) {
+            let (_, owner, spender) = key;
+            state
+                .allowances
+                .insert((renamed.clone(), owner, spender), amount);
+        }
+    }
+    TokenRenamedEvent {
+        old_symbol: old.symbol,
+        new_symbol: renamed.symbol,
+        mint: renamed.mint,
+        sequence: state.sequence,
+    }
+    .emit();
+    Ok(())
+}
+
+// fund token's airdrop from the admin's token account, what is left of the airdrop it replaces
+// is already in the vault and counts towards total
+fn set_merkle_root<'a>(
+    program_id: &Pubkey,
+    token: TokenType,
+    root: [u8; 32],
+    total: u64,
+    admin: &AccountInfo<'a>,
+    transfer_accounts: &TokenTransferAccounts<'_, 'a>,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if state.share_supplies.contains_key(&token.mint) {
+        msg!("airdrops of a share-mode token would be claimed as shares");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    let unclaimed = state
+        .airdrops
+        .get(&token.mint)
+        .map_or(0, Airdrop::unclaimed);
+    let Some(amount) = total.checked_sub(unclaimed) else {
+        msg!(
+            "{} of the earlier airdrop is unclaimed, more than the total {}",
+            unclaimed,
+            total
+        );
+        return Err(ProgramError::InvalidArgument);
+    };
+    check_token_transfer_accounts(program_id, transfer_accounts, &token.mint)?;
+    // claims are paid in full, a Token-2022 transfer fee would leave the vault short
+    if transfer_fee(transfer_accounts, amount)? > 0 {
+        return Err(ContractError::AirdropUnderfunded.into());
+    }
+    let new_total = entry
+        .total_deposited
+        .checked_add(amount)
+        .ok_or(ContractError::BalanceOverflow)?;
+    if entry.deposit_cap.is_some_and(|cap| new_total > cap) {
+        return Err(ContractError::DepositCapExceeded.into());
+    }
+    let global_total = raise_global_total(
+        state.global_total_deposited,
+        state.global_deposit_cap,
+        amount,
+    )?;
+    entry.total_deposited = new_total;
+    state.global_total_deposited = global_total;
+    state.airdrops.insert(
+        token.mint,
+        Airdrop {
+            root,
+            total,
+            claimed: 0,
+            claimed_leaves: BTreeSet::new(),
+        },
+    );
+    if amount == 0 {
+        return Ok(());
+    }
+    transfer_to_vault(amount, admin, transfer_accounts)
+}
+
+// credit user's leaf of token's airdrop, its amount is in the vault and total_deposited already
+fn claim_airdrop(
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    proof: &[[u8; 32]],
+    state: &mut ContractState,
+) -> ProgramResult {
+    check_not_blocked(state, &[user])?;
+    if state.paused {
+        return Err(ContractError::ContractPaused.into());
+    }
+    let token_symbol = stored_symbol(&token, state);
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    let airdrop = state
+        .airdrops
+        .get_mut(&token.mint)
+        .ok_or(ContractError::NoAirdrop)?;
+    let leaf = airdrop_leaf(&user, amount);
+    if !verify_merkle_proof(leaf, proof, &airdrop.root) {
+        return Err(ContractError::InvalidMerkleProof.into());
+    }
+    if airdrop.claimed_leaves.contains(&leaf) {
+        return Err(ContractError::AirdropAlreadyClaimed.into());
+    }
+    if amount > airdrop.unclaimed() {
+        msg!(
+            "{} of the airdrop's {} is left",
+            airdrop.unclaimed(),
+            airdrop.total
+        );
+        return Err(ContractError::AirdropExhausted.into());
+    }
+    let new_balance = entry
+        .balances
+        .get(&user)
+        .copied()
+        .unwrap_or(0)
+        .checked_add(amount)
+        .ok_or(ContractError::BalanceOverflow)?;
+    if entry.max_user_balance.is_some_and(|max| new_balance > max) {
+        return Err(ContractError::UserBalanceCapExceeded.into());
+    }
+    entry.balances.insert(user, new_balance);
+    airdrop.claimed += amount;
+    airdrop.claimed_leaves.insert(leaf);
+    AirdropClaimedEvent {
+        token_symbol,
+        user,
+        amount,
+        new_balance,
+        sequence: state.sequence,
+    }
+    .emit();
+    Ok(())
+}
+
+// move amount from the admin's balance to user's once signed holds the admin's key with
+// signature, the verified ed25519 signatures over the voucher's message
+#[allow(clippy::too_many_arguments)]
+fn redeem_voucher(
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    voucher_id: u64,
+    expiry: i64,
+    signature: &[u8; 64],
+    signed: &[Ed25519Signature],
+    state: &mut ContractState,
+) -> ProgramResult {
+    let admin = state.admin.ok_or(ContractError::ConfigNotInitialized)?;
+    check_not_blocked(state, &[user])?;
+    if state.paused {
+        return Err(ContractError::ContractPaused.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    if now > expiry {
+        return Err(ContractError::VoucherExpired.into());
+    }
+    // a voucher replayed after its expiry fails above, it needn't be remembered anymore
+    state
+        .redeemed_vouchers
+        .retain(|_, redeemed_expiry| *redeemed_expiry >= now);
+    if state.redeemed_vouchers.contains_key(&voucher_id) {
+        return Err(ContractError::VoucherAlreadyRedeemed.into());
+    }
+    if !signed
+        .iter()
+        .any(|(key, verified)| *key == admin && verified.as_ref() == Some(signature))
+    {
+        return Err(ContractError::InvalidVoucherSignature.into());
+    }
+    move_balance(&token, admin, user, amount, state)?;
+    state.redeemed_vouchers.insert(voucher_id, expiry);
+    VoucherRedeemedEvent {
+        token_symbol: stored_symbol(&token, state),
+        user,
+        voucher_id,
+        amount,
+        new_balance: state.all_token_balances[&token].balances[&user],
+        sequence: state.sequence,
+    }
+    .emit();
+    Ok(())
+}
+
+fn set_deposit_cap(
+    token: TokenType,
+    cap: Option<u64>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.deposit_cap = cap;
+    Ok(())
+}
+
+// the default pubkey as feed removes the token's feed
+fn set_price_feed(
+    token: TokenType,
+    feed: Pubkey,
+    usd_cap: u64,
+    max_age_slots: u64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if feed == Pubkey::default() {
+        state.price_feeds.remove(&token.mint);
+    } else {
+        state.price_feeds.insert(
+            token.mint,
+            PriceFeed {
+                feed,
+                usd_cap,
+                max_age_slots,
+            },
+        );
+    }
+    Ok(())
+}
+
+// a numerator of 0 removes the rate from from to to, the other direction is its own rate
+fn set_swap_rate(
+    from: TokenType,
+    to: TokenType,
+    numerator: u64,
+    denominator: u64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.all_token_balances.contains_key(&from) || !state.all_token_balances.contains_key(&to)
+    {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if from == to || denominator == 0 {
+        msg!("a swap rate is between two tokens and has a denominator");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if numerator == 0 {
+        state.swap_rates.remove(&(from.mint, to.mint));
+    } else {
+        state.swap_rates.insert(
+            (from.mint, to.mint),
+            SwapRate {
+                numerator,
+                denominator,
+            },
+        );
+    }
+    Ok(())
+}
+
+// only while token has no balances, they would be read as shares otherwise
+fn enable_share_mode(token: TokenType, state: &mut ContractState) -> ProgramResult {
+    let entry = state
+        .all_token_balances
+        .get(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    if entry.total_deposited > 0 || !entry.withdraw_requests.is_empty() {
+        return Err(ContractError::TokenHasOutstandingBalances.into());
+    }
+    state.share_supplies.entry(token.mint).or_insert(0);
+    Ok(())
+}
+
+// raise the total_deposited of a share-mode token by what reaches the vault, its shares stay
+fn donate_yield<'a>(
+    program_id: &Pubkey,
+    token: TokenType,
+    amount: u64,
+    admin: &AccountInfo<'a>,
+    transfer_accounts: &TokenTransferAccounts<'_, 'a>,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if amount == 0 {
+        return Err(ContractError::ZeroAmount.into());
+    }
+    // without shares the yield would go to whoever deposits first
+    if state
+        .share_supplies
+        .get(&token.mint)
+        .is_none_or(|supply| *supply == 0)
+    {
+        msg!("token {} has no shares to donate to", token.symbol);
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_token_transfer_accounts(program_id, transfer_accounts, &token.mint)?;
+    let received = amount.saturating_sub(transfer_fee(transfer_accounts, amount)?);
+    let global_total = raise_global_total(
+        state.global_total_deposited,
+        state.global_deposit_cap,
+        received,
+    )?;
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.total_deposited = entry
+        .total_deposited
+        .checked_add(received)
+        .ok_or(ContractError::BalanceOverflow)?;
+    state.global_total_deposited = global_total;
+    transfer_to_vault(amount, admin, transfer_accounts)
+}
+
+// identifies an action across AdminQueueAction and AdminExecuteAction
+fn action_hash(action: &ContractInstruction) -> [u8; 32] {
+    // serializing into a Vec can't fail
+    hash(&borsh::to_vec(action).unwrap()).to_bytes()
+}
+
+fn queue_action(
+    action: &ContractInstruction,
+    eta_slot: u64,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !is_timelocked(action) {
+        msg!("instruction {} isn't timelocked", action.tag());
+        return Err(ProgramError::InvalidArgument);
+    }
+    let slot = Clock::get()?.slot;
+    let earliest = slot.saturating_add(state.timelock_delay_slots);
+    if eta_slot < earliest {
+        msg!("eta slot {} is before slot {}", eta_slot, earliest);
+        return Err(ProgramError::InvalidArgument);
+    }
+    // expired actions can't run anymore, dropping them keeps the queue short
+    state
+        .queued_actions
+        .retain(|_, queued| !queued.expired(slot));
+    let action_hash = action_hash(action);
+    if state.queued_actions.contains_key(&action_hash) {
+        return Err(ContractError::ActionAlreadyQueued.into());
+    }
+    state.queued_actions.insert(
+        action_hash,
+        QueuedAction {
+            queued_slot: slot,
+            eta_slot,
+        },
+    );
+    msg!(
+        "queued instruction {} to run from slot {}",
+        action.tag(),
+        eta_slot
+    );
+    Ok(())
+}
+
+// dequeue action if it may run now, the delay counts as it is now and not as it was queued
+fn take_queued_action(
+    action: &ContractInstruction,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let slot = Clock::get()?.slot;
+    let action_hash = action_hash(action);
+    let queued = state
+        .queued_actions
+        .get(&action_hash)
+        .copied()
+        .ok_or(ContractError::ActionNotQueued)?;
+    if queued.expired(slot) {
+        return Err(ContractError::ActionExpired.into());
+    }
+    let ready = queued.eta_slot.max(
+        queued
+            .queued_slot
+            .saturating_add(state.timelock_delay_slots),
+    );
+    if slot < ready {
+        msg!("action can run from slot {}", ready);
+        return Err(ContractError::TimelockNotElapsed.into());
+    }
+    state.queued_actions.remove(&action_hash);
+    Ok(())
+}
+
+fn set_timelock_delay(delay_slots: u64, state: &mut ContractState) -> Result<(), ProgramError> {
+    if delay_slots < MIN_TIMELOCK_DELAY_SLOTS {
+        msg!(
+            "timelock delay {} is below {}",
+            delay_slots,
+            MIN_TIMELOCK_DELAY_SLOTS
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    state.timelock_delay_slots = delay_slots;
+    Ok(())
+}
+
+// the treasury is where dust goes, so it has to be set first
+fn set_dust_threshold(
+    token: TokenType,
+    threshold: u64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if threshold == 0 {
+        state.dust_thresholds.remove(&token.mint);
+    } else {
+        if state.treasury.is_none() {
+            return Err(ContractError::TreasuryNotSet.into());
+        }
+        state.dust_thresholds.insert(token.mint, threshold);
+    }
+    Ok(())
+}
+
+fn set_min_withdraw(
+    token: TokenType,
+    min_withdraw: u64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if min_withdraw == 0 {
+        state.min_withdrawals.remove(&token.mint);
+    } else {
+        state.min_withdrawals.insert(token.mint, min_withdraw);
+    }
+    Ok(())
+}
+
+fn set_withdraw_cooldown(
+    token: TokenType,
+    withdraw_cooldown_slots: u64,
+    transfers_reset: bool,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if withdraw_cooldown_slots == 0 {
+        state.withdraw_cooldowns.remove(&token.mint);
+    } else {
+        state.withdraw_cooldowns.insert(
+            token.mint,
+            WithdrawCooldown {
+                withdraw_cooldown_slots,
+                transfers_reset,
+            },
+        );
+    }
+    Ok(())
+}
+
+// a tripped breaker stays, disabled or not, until AdminResetBreaker
+fn set_circuit_breaker(
+    token: TokenType,
+    threshold_bps: u16,
+    window_slots: u64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if threshold_bps > 10_000 {
+        msg!("threshold {} bps is above 10000", threshold_bps);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let tripped = state
+        .circuit_breakers
+        .get(&token.mint)
+        .is_some_and(|breaker| breaker.tripped);
+    if threshold_bps == 0 || window_slots == 0 {
+        if tripped {
+            return Err(ContractError::CircuitBreakerTripped.into());
+        }
+        state.circuit_breakers.remove(&token.mint);
+    } else {
+        state.circuit_breakers.insert(
+            token.mint,
+            CircuitBreaker {
+                threshold_bps,
+                window_slots,
+                window_start_slot: Clock::get()?.slot,
+                withdrawn: 0,
+                tripped,
+            },
+        );
+    }
+    Ok(())
+}
+
+fn reset_breaker(token: TokenType, state: &mut ContractState) -> ProgramResult {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    let breaker = state
+        .circuit_breakers
+        .get_mut(&token.mint)
+        .filter(|breaker| breaker.tripped)
+        .ok_or_else(|| {
+            msg!("circuit breaker of {} has not tripped", token.symbol);
+            ProgramError::InvalidArgument
+        })?;
+    breaker.tripped = false;
+    breaker.window_start_slot = Clock::get()?.slot;
+    breaker.withdrawn = 0;
+    entry.withdrawals_enabled = true;
+    Ok(())
+}
+
+// breaker after payout left the vault of a token holding total_deposited after it, tripped
+// once what left within the window is more than its threshold of what was there
+fn count_breaker_withdrawal(
+    mut breaker: CircuitBreaker,
+    payout: u64,
+    total_deposited: u64,
+    slot: u64,
+) -> CircuitBreaker {
+    if slot.saturating_sub(breaker.window_start_slot) >= breaker.window_slots {
+        breaker.window_start_slot = slot;
+        breaker.withdrawn = 0;
+    }
+    breaker.withdrawn = breaker.withdrawn.saturating_add(payout);
+    let base = u128::from(total_deposited) + u128::from(breaker.withdrawn);
+    if u128::from(breaker.withdrawn) * 10_000 > base * u128::from(breaker.threshold_bps) {
+        breaker.tripped = true;
+    }
+    breaker
+}
+
+// a slash is a penalty, it goes through while paused and for a frozen user
+// amount is in token units, of a share-mode token the shares worth it are taken, rounded down
+fn slash(
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    reason_code: u16,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if amount == 0 {
+        return Err(ContractError::ZeroAmount.into());
+    }
+    let treasury = state.treasury.ok_or(ContractError::TreasuryNotSet)?;
+    if user == treasury {
+        return Err(ContractError::SelfTransfer.into());
+    }
+    // more shares than there are take the whole balance
+    let amount = ledger_for_units(&token, amount, state).unwrap_or(u64::MAX);
+    if amount == 0 {
+        return Err(ContractError::ZeroShares.into());
+    }
+    let token_symbol = stored_symbol(&token, state);
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    let balance = entry.balances.get(&user).copied().unwrap_or(0);
+    let slashed = amount.min(balance);
+    if slashed == 0 {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    let user_balance = balance - slashed;
+    if let Some(request) = entry.withdraw_requests.get(&user) {
+        if request.amount > user_balance {
+            msg!(
+                "withdraw request of {} is more than the {} left",
+                request.amount,
+                user_balance
+            );
+            return Err(ContractError::WithdrawRequestPending.into());
+        }
+    }
+    // the treasury has no balance cap
+    let treasury_balance = entry
+        .balances
+        .get(&treasury)
+        .copied()
+        .unwrap_or(0)
+        .checked_add(slashed)
+        .ok_or(ContractError::BalanceOverflow)?;
+    // keep the state small, a balance slashed in full is dropped along with its account
+    if user_balance == 0 {
+        entry.balances.remove(&user);
+    } else {
+        entry.balances.insert(user, user_balance);
+    }
+    entry.balances.insert(treasury, treasury_balance);
+    msg!(
+        "slashed {} of token {} from {}, reason {}",
+        slashed,
+        token.symbol,
+        user,
+        reason_code
+    );
+    SlashEvent {
+        token_symbol,
+        user,
+        amount: slashed,
+        reason_code,
+        user_balance,
+        treasury_balance,
+        sequence: state.sequence,
+    }
+    .emit();
+    Ok(())
+}
+
+fn set_max_user_balance(
+    token: TokenType,
+    max_user_balance: Option<u64>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.max_user_balance = max_user_balance;
+    Ok(())
+}
+
+fn set_min_deposit(
+    token: TokenType,
+    min_deposit: u64,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.min_deposit = min_deposit;
+    Ok(())
+}
+
+fn set_daily_withdrawal_limit(
+    token: TokenType,
+    limit: u64,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.daily_withdrawal_limit = limit;
+    if limit == 0 {
+        entry.withdrawal_windows.clear();
+    }
+    Ok(())
+}
+
+fn set_operator(operator: Option<Pubkey>, state: &mut ContractState) -> Result<(), ProgramError> {
+    state.operator = operator;
+    Ok(())
+}
+
+fn set_treasury(treasury: Pubkey, state: &mut ContractState) -> Result<(), ProgramError> {
+    state.treasury = Some(treasury);
+    Ok(())
+}
+
+fn set_withdrawal_fee(
+    token: TokenType,
+    fee_bps: u16,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if fee_bps > MAX_FEE_BPS {
+        return Err(ContractError::FeeTooHigh.into());
+    }
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.withdrawal_fee_bps = fee_bps;
+    Ok(())
+}
+
+fn set_deposit_fee(
+    token: TokenType,
+    fee_bps: u16,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if fee_bps > MAX_FEE_BPS {
+        return Err(ContractError::FeeTooHigh.into());
+    }
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    entry.deposit_fee_bps = fee_bps;
+    Ok(())
+}
+
+fn set_fee_exempt(
+    user: Pubkey,
+    exempt: bool,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if exempt {
+        state.fee_exempt.insert(user);
+    } else {
+        state.fee_exempt.remove(&user);
+    }
+    Ok(())
+}
+
+// admin pulls amount of the treasury's balance into its own token account, no fee is taken
+fn withdraw_treasury(
+    program_id: &Pubkey,
+    token: TokenType,
+    amount: u64,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let treasury = state.treasury.ok_or(ContractError::TreasuryNotSet)?;
+    let (mint, payout) = debit_withdraw(
+        program_id,
+        &token,
+        treasury,
+        amount,
+        None,
+        transfer_accounts,
+        state,
+    )?;
+    transfer_from_vault(program_id, &mint, payout, transfer_accounts)
+}
+
+// move the whole vault to destination and zero every balance and withdraw request of token,
+// each user's loss is logged for reimbursement off-chain
+// every balance has to be passed, with the unclaimed airdrop they must add up to total_deposited
+fn emergency_sweep(
+    program_id: &Pubkey,
+    token: TokenType,
+    destination: Pubkey,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if !state.paused {
+        return Err(ContractError::ContractNotPaused.into());
+    }
+    if transfer_accounts.user_token_account.key != &destination {
+        msg!("destination account {} not passed", destination);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mint = token.mint;
+    check_token_transfer_accounts(program_id, transfer_accounts, &mint)?;
+    let token_symbol = stored_symbol(&token, state);
+    let unclaimed = state.airdrops.get(&mint).map_or(0, Airdrop::unclaimed);
+    let share_supply = state.share_supplies.get(&mint).copied();
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+
+    // what every user is owed, a pending withdraw request included
+    let mut swept: HashMap<Pubkey, u64> = HashMap::new();
+    for (user, amount) in entry
+        .balances
+        .iter()
+        .map(|(user, amount)| (user, *amount))
+        .chain(
+            entry
+                .withdraw_requests
+                .iter()
+                .map(|(user, request)| (user, request.amount)),
+        )
+    {
+        let owed = swept.entry(*user).or_insert(0);
+        *owed = owed
+            .checked_add(amount)
+            .ok_or(ContractError::BalanceOverflow)?;
+    }
+    let total = swept
+        .values()
+        .try_fold(0u64, |sum, amount| sum.checked_add(*amount))
+        .ok_or(ContractError::BalanceOverflow)?;
+    // the unclaimed airdrop is in total_deposited but in no balance, a share-mode token's
+    // balances add up to its shares
+    let owed = share_supply.unwrap_or(entry.total_deposited.saturating_sub(unclaimed));
+    if total != owed {
+        msg!(
+            "balances passed add up to {} of {}, pass every balance account",
+            total,
+            owed
+        );
+        return Err(ContractError::SweepIncomplete.into());
+    }
+
+    for amount in entry.balances.values_mut() {
+        *amount = 0;
+    }
+    entry.withdraw_requests.clear();
+    state.global_total_deposited = state
+        .global_total_deposited
+        .checked_sub(u128::from(entry.total_deposited))
+        .ok_or(ProgramError::InvalidAccountData)?;
+    entry.total_deposited = 0;
+    if let Some(supply) = state.share_supplies.get_mut(&mint) {
+        *supply = 0;
+    }
+    state
+        .locked_lots
+        .retain(|(lot_mint, _), _| lot_mint != &mint);
+    state
+        .vesting_schedules
+        .retain(|(schedule_mint, _), _| schedule_mint != &mint);
+    state.airdrops.remove(&mint);
+    let mut users: Vec<(Pubkey, u64)> = swept.into_iter().filter(|(_, a)| *a > 0).collect();
+    users.sort();
+    for (user, amount) in users {
+        SweepEvent {
+            token_symbol: token_symbol.clone(),
+            user,
+            amount,
+            destination,
+            sequence: state.sequence,
+        }
+        .emit();
+    }
+
+    // donations and dust go too, the vault is left empty
+    let payout = vault_balance(transfer_accounts.vault_token_account, &mint)?;
+    msg!(
+        "swept {} of token {} to {}",
+        payout,
+        token.symbol,
+        destination
+    );
+    if payout == 0 {
+        return Ok(());
+    }
+    transfer_from_vault(program_id, &mint, payout, transfer_accounts)
+}
+
+fn set_blocked(user: Pubkey, blocked: bool, state: &mut ContractState) -> Result<(), ProgramError> {
+    if blocked {
+        // blocking the admin would lock everyone out of unblocking
+        if state.admin == Some(user) {
+            return Err(ContractError::CannotBlockAdmin.into());
+        }
+        state.blocked.insert(user);
+    } else {
+        state.blocked.remove(&user);
+    }
+    Ok(())
+}
+
+// freezing twice or unfreezing a user that isn't frozen is a no-op
+fn set_user_frozen(
+    token: TokenType,
+    user: Pubkey,
+    frozen: bool,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    if frozen {
+        entry.frozen.insert(user);
+    } else {
+        entry.frozen.remove(&user);
+    }
+    Ok(())
+}
+
+// enable/disable deposits and withdrawals of a single token
+fn set_token_flags(
+    token: TokenType,
+    deposits_enabled: bool,
+    withdrawals_enabled: bool,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    let tripped = state
+        .circuit_breakers
+        .get(&token.mint)
+        .is_some_and(|breaker| breaker.tripped);
+    if withdrawals_enabled && tripped {
+        return Err(ContractError::CircuitBreakerTripped.into());
+    }
+    entry.deposits_enabled = deposits_enabled;
+    entry.withdrawals_enabled = withdrawals_enabled;
+    Ok(())
+}
+
+// decimals are the mint's
+fn update_token_metadata(
+    token: TokenType,
+    metadata: TokenMetadata,
+    decimals: u8,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    validate_token_metadata(&metadata)?;
+    check_decimals(&metadata, decimals)?;
+    state.token_metadata.insert(token.mint, metadata);
+    Ok(())
+}
+
+fn set_display_precision(
+    token: TokenType,
+    display_precision: Option<u8>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    let Some(display_precision) = display_precision else {
+        state.display_precisions.remove(&token.mint);
+        return Ok(());
+    };
+    if display_precision > token_metadata(&token.mint, state).decimals {
+        return Err(ContractError::InvalidDisplayPrecision.into());
+    }
+    state
+        .display_precisions
+        .insert(token.mint, display_precision);
+    Ok(())
+}
+
+// smallest amount of mint UIs show, 1 without a display precision
+fn dust_unit(mint: &Pubkey, state: &ContractState) -> u64 {
+    match state.display_precisions.get(mint) {
+        // decimals are at most MAX_DECIMALS, so the power fits
+        Some(precision) => 10u64.pow(u32::from(
+            token_metadata(mint, state)
+                .decimals
+                .saturating_sub(*precision),
+        )),
+        None => 1,
+    }
+}
+
+// settles the points accrued so far before the rate changes, a new pool starts at index 0
+fn set_reward_rate(
+    token: TokenType,
+    points_per_day: u64,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    let pool = state.reward_pools.entry(token.mint).or_insert(RewardPool {
+        points_per_day: 0,
+        index: 0,
+        updated_at: now,
+    });
+    update_reward_pool(pool, now);
+    pool.points_per_day = points_per_day;
+    Ok(())
+}
+
+fn claim_rewards(
+    user: Pubkey,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    let points = state.reward_points.remove(&user).unwrap_or(0);
+    RewardsClaimedEvent {
+        user,
+        points,
+        sequence: state.sequence,
+    }
+    .emit();
+    Ok(())
+}
+
+fn update_reward_pool(pool: &mut RewardPool, now: i64) {
+    if now <= pool.updated_at {
+        return;
+    }
+    let elapsed = u128::from(now.abs_diff(pool.updated_at));
+    // a u64 rate times an i64 duration can't overflow, the sum saturates after eons
+    pool.index = pool
+        .index
+        .saturating_add(u128::from(pool.points_per_day) * elapsed);
+    pool.updated_at = now;
+}
+
+// credit every balance in the state with the points it earned since its last settlement
+fn accrue_rewards(state: &mut ContractState) -> ProgramResult {
+    if state.reward_pools.is_empty() {
+        return Ok(());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    for (token, entry) in &state.all_token_balances {
+        let Some(pool) = state.reward_pools.get_mut(&token.mint) else {
+            continue;
+        };
+        update_reward_pool(pool, now);
+        let decimals = state
+            .token_metadata
+            .get(&token.mint)
+            .map_or(0, |metadata| metadata.decimals);
+        // at most 10^18 * 86400 < 2^77, as reward_points_due needs
+        let per_unit = 10u128.pow(u32::from(decimals)) * 86_400;
+        for (user, &balance) in &entry.balances {
+            let index = state.reward_indexes.entry((token.mint, *user)).or_insert(0);
+            let due = reward_points_due(balance, pool.index.saturating_sub(*index), per_unit);
+            *index = pool.index;
+            if due > 0 {
+                let points = state.reward_points.entry(*user).or_insert(0);
+                *points = points.saturating_add(due);
+            }
+        }
+    }
+    Ok(())
+}
+
+// balance * index_delta / per_unit rounded down, saturating at u64::MAX, exact although the
+// product can take up to 2^64 * 2^127, per_unit must be below 2^96
+fn reward_points_due(balance: u64, index_delta: u128, per_unit: u128) -> u64 {
+    let (quotient, remainder) = (index_delta / per_unit, index_delta % per_unit);
+    let Some(whole) = u128::from(balance).checked_mul(quotient) else {
+        return u64::MAX;
+    };
+    // balance * remainder / per_unit in 32-bit halves of balance, every product stays below
+    // 2^32 * per_unit
+    let (high, low) = (u128::from(balance >> 32), u128::from(balance & 0xffff_ffff));
+    let partial = high * remainder;
+    let fraction =
+        ((partial / per_unit) << 32) + (((partial % per_unit) << 32) + low * remainder) / per_unit;
+    u64::try_from(whole.saturating_add(fraction)).unwrap_or(u64::MAX)
+}
+
+// holders without a balance accrue nothing, their next deposit starts from the current index
+// every balance accrue_rewards gave an index to is in before, absent from the state means 0
+fn prune_reward_indexes(before: &HashMap<(Pubkey, Pubkey), u64>, state: &mut ContractState) {
+    for (mint, user) in before.keys() {
+        let is_held = state
+            .all_token_balances
+            .iter()
+            .find(|(token, _)| &token.mint == mint)
+            .and_then(|(_, entry)| entry.balances.get(user))
+            .is_some_and(|&balance| balance > 0);
+        if !is_held {
+            state.reward_indexes.remove(&(*mint, *user));
+        }
+    }
+}
+
+// user deposit token
+#[allow(clippy::too_many_arguments)]
+fn user_deposit_token<'a>(
+    program_id: &Pubkey,
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    lock_until: Option<i64>,
+    referrer: Option<Pubkey>,
+    beneficiary: Option<Pubkey>,
+    memo: Option<String>,
+    signer: &AccountInfo<'a>,
+    transfer_accounts: &TokenTransferAccounts<'_, 'a>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // user pays, beneficiary's balance is the one credited and capped
+    let beneficiary = beneficiary.unwrap_or(user);
+    check_not_blocked(state, &[user, beneficiary])?;
+    if state.paused {
+        return Err(ContractError::ContractPaused.into());
+    }
+
+    // the credit only lands in the state account if the transfer below succeeded
+    let referrer = track_referral(beneficiary, referrer, amount, state)?;
+    let credited = credit_deposit(
+        program_id,
+        &token,
+        beneficiary,
+        user,
+        amount,
+        referrer,
+        memo,
+        transfer_accounts,
+        state,
+    )?;
+    if let Some(lock_until) = lock_until {
+        lock_deposit(token.mint, beneficiary, credited, lock_until, state)?;
+    }
+    transfer_to_vault(amount, signer, transfer_accounts)
+}
+
+// bind user to referrer on its first deposit and credit amount to whoever it is bound to,
+// returns that referrer
+fn track_referral(
+    user: Pubkey,
+    referrer: Option<Pubkey>,
+    amount: u64,
+    state: &mut ContractState,
+) -> Result<Option<Pubkey>, ProgramError> {
+    if referrer == Some(user) {
+        return Err(ContractError::SelfReferral.into());
+    }
+    let bound = *state.referrers.entry(user).or_insert(referrer);
+    if let Some(bound) = bound {
+        let volume = state.referral_volume.entry(bound).or_insert(0);
+        *volume = volume.saturating_add(amount);
+    }
+    Ok(bound)
+}
+
+// lock amount of user's balance until lock_until, lots locked until the same time are merged
+fn lock_deposit(
+    mint: Pubkey,
+    user: Pubkey,
+    amount: u64,
+    lock_until: i64,
+    state: &mut ContractState,
+) -> ProgramResult {
+    let now = Clock::get()?.unix_timestamp;
+    if lock_until <= now {
+        msg!("lock_until {} isn't after now, {}", lock_until, now);
+        return Err(ContractError::InvalidLockTime.into());
+    }
+    let lots = state.locked_lots.entry((mint, user)).or_default();
+    lots.retain(|lot| lot.unlock_at > now);
+    if let Some(lot) = lots.iter_mut().find(|lot| lot.unlock_at == lock_until) {
+        // can't overflow, the lot is part of a balance that didn't
+        lot.amount += amount;
+        return Ok(());
+    }
+    if lots.len() >= MAX_LOCKED_LOTS {
+        return Err(ContractError::TooManyLockedLots.into());
+    }
+    lots.push(LockedLot {
+        amount,
+        unlock_at: lock_until,
+    });
+    Ok(())
+}
+
+// fund user's balance from the admin's token account, the credited amount vests on schedule
+fn admin_deposit_vested<'a>(
+    program_id: &Pubkey,
+    token: TokenType,
+    user: Pubkey,
+    schedule: VestingSchedule,
+    admin: &AccountInfo<'a>,
+    transfer_accounts: &TokenTransferAccounts<'_, 'a>,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    check_not_blocked(state, &[user])?;
+    if state.paused {
+        return Err(ContractError::ContractPaused.into());
+    }
+
+    let amount = schedule.amount;
+    // the user didn't deposit this itself, so no referrer is credited
+    let credited = credit_deposit(
+        program_id,
+        &token,
+        user,
+        *admin.key,
+        amount,
+        None,
+        None,
+        transfer_accounts,
+        state,
+    )?;
+    let now = Clock::get()?.unix_timestamp;
+    let schedules = state
+        .vesting_schedules
+        .entry((token.mint, user))
+        .or_default();
+    schedules.retain(|schedule| schedule.unvested(now) > 0);
+    if schedules.len() >= MAX_VESTING_SCHEDULES {
+        return Err(ContractError::TooManyVestingSchedules.into());
+    }
+    schedules.push(VestingSchedule {
+        amount: credited,
+        ..schedule
+    });
+    transfer_to_vault(amount, admin, transfer_accounts)
+}
+
+// what of user's balance of mint is still locked or unvested
+fn locked_amount(mint: &Pubkey, user: &Pubkey, state: &ContractState) -> Result<u64, ProgramError> {
+    let key = (*mint, *user);
+    if !state.locked_lots.contains_key(&key) && !state.vesting_schedules.contains_key(&key) {
+        return Ok(0);
+    }
+    Ok(locked_at(&key, Clock::get()?.unix_timestamp, state))
+}
+
+// never more than the balance, check_locked_balances keeps it covered
+fn locked_at(key: &(Pubkey, Pubkey), now: i64, state: &ContractState) -> u64 {
+    let locked: u64 = state
+        .locked_lots
+        .get(key)
+        .into_iter()
+        .flatten()
+        .filter(|lot| lot.unlock_at > now)
+        .map(|lot| lot.amount)
+        .sum();
+    let unvested: u64 = state
+        .vesting_schedules
+        .get(key)
+        .into_iter()
+        .flatten()
+        .map(|schedule| schedule.unvested(now))
+        .sum();
+    locked.saturating_add(unvested)
+}
+
+// drops the lots that unlocked and the schedules that vested, then every balance the
+// instruction could have changed must still cover what is locked or unvested, absent from the
+// state means 0 if it was in before
+fn check_locked_balances(
+    before: &HashMap<(Pubkey, Pubkey), u64>,
+    state: &mut ContractState,
+) -> ProgramResult {
+    if state.locked_lots.is_empty() && state.vesting_schedules.is_empty() {
+        return Ok(());
+    }
+    let now = Clock::get()?.unix_timestamp;
+    state.locked_lots.retain(|_, lots| {
+        lots.retain(|lot| lot.unlock_at > now);
+        !lots.is_empty()
+    });
+    state.vesting_schedules.retain(|_, schedules| {
+        schedules.retain(|schedule| schedule.unvested(now) > 0);
+        !schedules.is_empty()
+    });
+    let keys = state
+        .locked_lots
+        .keys()
+        .chain(state.vesting_schedules.keys());
+    for key @ (mint, user) in keys {
+        let balance = state
+            .all_token_balances
+            .iter()
+            .find(|(token, _)| &token.mint == mint)
+            .and_then(|(_, entry)| entry.balances.get(user));
+        let balance = match balance {
+            Some(&balance) => balance,
+            None if before.contains_key(key) => 0,
+            // not loaded, so not changed either
+            None => continue,
+        };
+        let locked = locked_at(key, now, state);
+        if balance < locked {
+            msg!("{} of user {}'s balance is locked", locked, user);
+            return Err(ContractError::BalanceLocked.into());
+        }
+    }
+    Ok(())
+}
+
+// every item is validated and credited before the first transfer, one bad item aborts the batch
+fn user_batch_deposit<'a>(
+    program_id: &Pubkey,
+    user: Pubkey,
+    items: Vec<(TokenType, u64)>,
+    signer: &AccountInfo<'a>,
+    transfer_accounts: &[TokenTransferAccounts<'_, 'a>],
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    if state.paused {
+        return Err(ContractError::ContractPaused.into());
+    }
+
+    for (index, ((token, amount), accounts)) in items.iter().zip(transfer_accounts).enumerate() {
+        let referrer = track_referral(user, None, *amount, state)?;
+        credit_deposit(
+            program_id, token, user, user, *amount, referrer, None, accounts, state,
+        )
+        .inspect_err(|_| {
+            msg!("batch deposit item {} failed", index);
+        })?;
+    }
+    for ((_, amount), accounts) in items.iter().zip(transfer_accounts) {
+        transfer_to_vault(*amount, signer, accounts)?;
+    }
+    Ok(())
+}
+
+// ledger side of a deposit, checks token and accounts then credits user amount minus fee
+// and the fee to the treasury, returns what the user was credited, payer funded it
+#[allow(clippy::too_many_arguments)]
+fn credit_deposit(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: Pubkey,
+    payer: Pubkey,
+    amount: u64,
+    referrer: Option<Pubkey>,
+    memo: Option<String>,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> Result<u64, ProgramError> {
+    let treasury = state.treasury;
+    let exempt = is_fee_exempt(state, &user);
+    let dust_unit = dust_unit(&token.mint, state);
+    let entry = state
+        .all_token_balances
+        .get_mut(token)
+        .ok_or(ContractError::TokenNotSupportedForDeposit)?;
+    if !entry.deposits_enabled {
+        return Err(ContractError::DepositsDisabled.into());
+    }
+    if amount < entry.min_deposit {
+        msg!(
+            "minimum deposit of token {} is {}",
+            token.symbol,
+            entry.min_deposit
+        );
+        return Err(ContractError::DepositBelowMinimum.into());
+    }
+    if !amount.is_multiple_of(dust_unit) {
+        msg!(
+            "deposits of token {} must be multiples of {}",
+            token.symbol,
+            dust_unit
+        );
+        return Err(ContractError::DustDeposit.into());
+    }
+
+    check_token_transfer_accounts(program_id, transfer_accounts, &token.mint)?;
+    // a Token-2022 transfer fee never reaches the vault, only what does is credited
+    let received = amount.saturating_sub(transfer_fee(transfer_accounts, amount)?);
+    // a share-mode token credits the shares received buys, the rest stays with its holders
+    let share_supply = state.share_supplies.get(&token.mint).copied();
+    let credit = match share_supply {
+        Some(supply) => to_shares(received, supply, entry.total_deposited)?,
+        None => received,
+    };
+    let fee = if exempt {
+        0
+    } else {
+        fee_amount(credit, entry.deposit_fee_bps)
+    };
+    let balance = entry.balances.get(&user).copied().unwrap_or(0);
+    let new_balance = balance
+        .checked_add(credit - fee)
+        .ok_or(ContractError::BalanceOverflow)?;
+    let treasury_credit = credit_fee(entry, treasury, fee)?;
+    // the whole amount received enters the vault, fee included
+    let new_total = entry
+        .total_deposited
+        .checked_add(received)
+        .ok_or(ContractError::BalanceOverflow)?;
+    if entry.deposit_cap.is_some_and(|cap| new_total > cap) {
+        return Err(ContractError::DepositCapExceeded.into());
+    }
+    let new_value = balance_value(
+        new_balance,
+        share_supply.map(|supply| supply.saturating_add(credit)),
+        new_total,
+    );
+    if entry.max_user_balance.is_some_and(|max| new_value > max) {
+        return Err(ContractError::UserBalanceCapExceeded.into());
+    }
+    let global_total = raise_global_total(
+        state.global_total_deposited,
+        state.global_deposit_cap,
+        received,
+    )?;
+    entry.balances.insert(user, new_balance);
+    if let Some((treasury, treasury_balance)) = treasury_credit {
+        entry.balances.insert(treasury, treasury_balance);
+    }
+    entry.total_deposited = new_total;
+    state.global_total_deposited = global_total;
+    if let Some(supply) = share_supply {
+        // can't overflow, the shares minted are at most the new balance
+        state.share_supplies.insert(token.mint, supply + credit);
+    }
+    let stats = state.user_stats.entry((token.mint, user)).or_default();
+    stats.deposit_count = stats.deposit_count.saturating_add(1);
+    stats.lifetime_deposited = stats.lifetime_deposited.saturating_add(u128::from(amount));
+    let slots = state.balance_slots.entry((token.mint, user)).or_default();
+    slots.last_deposit_slot = Clock::get()?.slot;
+    let slots = *slots;
+    let receipt_id = issue_receipt(&token.mint, user, amount, slots.last_deposit_slot, state);
+    // events of a failed instruction are discarded along with it
+    DepositEvent {
+        token_symbol: stored_symbol(token, state),
+        user,
+        amount,
+        new_balance,
+        sequence: state.sequence,
+        referrer,
+        payer,
+        memo,
+        last_deposit_slot: slots.last_deposit_slot,
+        last_withdraw_slot: slots.last_withdraw_slot,
+        receipt_id,
+    }
+    .emit();
+    Ok(credit - fee)
+}
+
+// record a deposit in user's receipts, store_receipts moves it into their receipts account, its
+// id is also the return data, so a batch returns its last deposit's and the ones before it
+// have the ids right below
+fn issue_receipt(
+    mint: &Pubkey,
+    user: Pubkey,
+    amount: u64,
+    slot: u64,
+    state: &mut ContractState,
+) -> u64 {
+    let receipt_id = state.next_receipt_id;
+    // a u64 of deposits can't run out
+    state.next_receipt_id += 1;
+    state
+        .receipts
+        .entry(user)
+        .or_default()
+        .push(DepositReceipt {
+            receipt_id,
+            mint: *mint,
+            user,
+            amount,
+            slot,
+        });
+    set_return_data(&receipt_id.to_le_bytes());
+    receipt_id
+}
+
+// what balance of a token is worth in token units, the unit its limits are set in, a share-mode
+// balance worth more than a u64 is past any of them
+fn balance_value(balance: u64, share_supply: Option<u64>, total_deposited: u64) -> u64 {
+    match share_supply {
+        Some(supply) => amount_for_shares(balance, supply, total_deposited).unwrap_or(u64::MAX),
+        None => balance,
+    }
+}
+
+// shares_for_amount for a deposit, which has to mint at least one
+fn to_shares(amount: u64, total_shares: u64, total_assets: u64) -> Result<u64, ProgramError> {
+    let shares = shares_for_amount(amount, total_shares, total_assets)
+        .ok_or(ContractError::BalanceOverflow)?;
+    if shares == 0 {
+        msg!(
+            "{} is worth no shares, {} shares hold {}",
+            amount,
+            total_shares,
+            total_assets
+        );
+        return Err(ContractError::ZeroShares.into());
+    }
+    Ok(shares)
+}
+
+// a deposit of mint, already credited, fails if its total_deposited is now worth more than the
+// USD cap of its price feed, the feed account is found among the instruction's accounts
+fn check_usd_cap(mint: &Pubkey, accounts: &[AccountInfo], state: &ContractState) -> ProgramResult {
+    let Some(price_feed) = state.price_feeds.get(mint) else {
+        return Ok(());
+    };
+    let Some(feed) = accounts
+        .iter()
+        .find(|account| account.key == &price_feed.feed)
+    else {
+        msg!("price feed {} missing", price_feed.feed);
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let price =
+        Price::from_pyth(&feed.try_borrow_data()?).ok_or(ContractError::InvalidPriceFeed)?;
+    let slot = Clock::get()?.slot;
+    if slot.saturating_sub(price.publish_slot) > price_feed.max_age_slots {
+        msg!(
+            "price published in slot {}, more than {} slots ago",
+            price.publish_slot,
+            price_feed.max_age_slots
+        );
+        return Err(ContractError::StalePrice.into());
+    }
+    let total = state
+        .all_token_balances
+        .iter()
+        .find(|(token, _)| &token.mint == mint)
+        .map_or(0, |(_, entry)| entry.total_deposited);
+    let value = price.usd_value(total, token_metadata(mint, state).decimals);
+    if value.is_none_or(|value| value > u128::from(price_feed.usd_cap)) {
+        msg!("total deposited is capped at {} USD", price_feed.usd_cap);
+        return Err(ContractError::UsdCapExceeded.into());
+    }
+    Ok(())
+}
+
+// global_total_deposited once amount more of a token entered the vault, at most cap
+fn raise_global_total(total: u128, cap: u128, amount: u64) -> Result<u128, ProgramError> {
+    let total = total
+        .checked_add(u128::from(amount))
+        .ok_or(ContractError::BalanceOverflow)?;
+    if total > cap {
+        msg!("global cap is {}", cap);
+        return Err(ContractError::GlobalCapExceeded.into());
+    }
+    Ok(total)
+}
+
+fn check_memo(memo: &Option<String>) -> ProgramResult {
+    if memo.as_ref().is_some_and(|memo| memo.len() > MAX_MEMO_LEN) {
+        msg!("memos are at most {} bytes", MAX_MEMO_LEN);
+        return Err(ContractError::MemoTooLong.into());
+    }
+    Ok(())
+}
+
+// log memo through the SPL Memo program too if its account was passed, it signs for no one
+fn forward_memo(memo: &Option<String>, accounts: &[AccountInfo]) -> ProgramResult {
+    let (Some(memo), Some(memo_program)) = (
+        memo,
+        accounts
+            .iter()
+            .find(|account| account.key == &spl_memo::id()),
+    ) else {
+        return Ok(());
+    };
+    invoke(
+        &spl_memo::build_memo(memo.as_bytes(), &[]),
+        std::slice::from_ref(memo_program),
+    )
+}
+
+// symbol as stored when token was added, token may carry any casing of it
+// tokens of compact instructions come without their mint, see ContractInstruction::pack_compact,
+// one whose symbol isn't listed keeps the default mint and so matches no token
+fn resolve_token_symbols(instruction: &mut ContractInstruction, state: &ContractState) {
+    for token in instruction.listed_tokens_mut() {
+        if token.mint != Pubkey::default() {
+            continue;
+        }
+        let symbol = canonical_symbol(&token.symbol);
+        if let Some(listed) = state
+            .all_token_balances
+            .keys()
+            .find(|listed| listed.symbol == symbol)
+        {
+            token.mint = listed.mint;
+        }
+    }
+}
+
+fn stored_symbol(token: &TokenType, state: &ContractState) -> String {
+    state
+        .all_token_balances
+        .get_key_value(token)
+        .map_or_else(|| token.symbol.clone(), |(token, _)| token.symbol.clone())
+}
+
+// move amount from the user's token account into the vault, signed by the user
+fn transfer_to_vault<'a>(
+    amount: u64,
+    signer: &AccountInfo<'a>,
+    transfer_accounts: &TokenTransferAccounts<'_, 'a>,
+) -> ProgramResult {
+    if is_native_transfer(transfer_accounts) {
+        return invoke(
+            &system_instruction::transfer(
+                transfer_accounts.user_token_account.key,
+                transfer_accounts.vault_token_account.key,
+                amount,
+            ),
+            &[
+                transfer_accounts.user_token_account.clone(),
+                transfer_accounts.vault_token_account.clone(),
+                transfer_accounts.token_program.clone(),
+            ],
+        );
+    }
+    let (instruction, accounts) = token_transfer(
+        transfer_accounts.user_token_account,
+        transfer_accounts.vault_token_account,
+        signer,
+        amount,
+        transfer_accounts,
+    )?;
+    invoke(&instruction, &accounts)
+}
+
+// SPL transfer of amount from source to destination authorized by authority, and the accounts
+// it takes, Token-2022 mints go through transfer_checked, which their transfer fees require
+fn token_transfer<'a>(
+    source: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    amount: u64,
+    transfer_accounts: &TokenTransferAccounts<'_, 'a>,
+) -> Result<(Instruction, Vec<AccountInfo<'a>>), ProgramError> {
+    let token_program = transfer_accounts.token_program;
+    let Some(mint) = transfer_accounts.mint else {
+        let instruction = spl_token::instruction::transfer(
+            token_program.key,
+            source.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+        )?;
+        let accounts = [source, destination, authority, token_program];
+        return Ok((instruction, accounts.map(AccountInfo::clone).to_vec()));
+    };
+    let decimals = load_mint(mint, mint.key)?.decimals;
+    let instruction = spl_token_2022::instruction::transfer_checked(
+        token_program.key,
+        source.key,
+        mint.key,
+        destination.key,
+        authority.key,
+        &[],
+        amount,
+        decimals,
+    )?;
+    let accounts = [source, mint, destination, authority, token_program];
+    Ok((instruction, accounts.map(AccountInfo::clone).to_vec()))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn user_withdraw_token(
+    program_id: &Pubkey,
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    recipient: Option<Pubkey>,
+    memo: Option<String>,
+    signer: &AccountInfo,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    verify_withdraw_authority(signer, &user, transfer_accounts, state)?;
+    check_not_blocked(state, &[user])?;
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+    // a delegate or an eth signature already pins the payout to user
+    if let Some(recipient) = recipient {
+        if payout_owner(transfer_accounts)? != recipient {
+            msg!("payout account isn't owned by recipient {}", recipient);
+            return Err(ContractError::RecipientMismatch.into());
+        }
+    }
+
+    // the debit only lands in the state account if the transfer below succeeded
+    let (mint, payout) = debit_withdraw(
+        program_id,
+        &token,
+        user,
+        amount,
+        memo,
+        transfer_accounts,
+        state,
+    )?;
+    transfer_from_vault(program_id, &mint, payout, transfer_accounts)
+}
+
+// drop user's zero balance of token, store_balances then closes its balance account
+// signer must be user or the admin
+fn close_user_balance(
+    program_id: &Pubkey,
+    token: TokenType,
+    user: Pubkey,
+    signer: &AccountInfo,
+    balance_accounts: &BalanceAccounts,
+    state: &mut ContractState,
+) -> ProgramResult {
+    let is_admin = state
+        .admin
+        .is_some_and(|admin| verify_signature(signer, &admin));
+    if !is_admin {
+        if !verify_signature(signer, &user) {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        check_not_blocked(state, &[user])?;
+    }
+    if !balance_accounts.contains_key(&(token.mint, user)) {
+        let (address, _) = find_balance_address(program_id, &token.mint, &user);
+        msg!("missing balance account {} of user {}", address, user);
+        return Err(ContractError::MissingBalanceAccount.into());
+    }
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotSupportedForWithdraw)?;
+    if entry
+        .balances
+        .get(&user)
+        .is_some_and(|&balance| balance > 0)
+    {
+        return Err(ContractError::BalanceNotZero.into());
+    }
+    entry.balances.remove(&user);
+    // there is nothing left to keep the activity for
+    state.user_stats.remove(&(token.mint, user));
+    state.balance_slots.remove(&(token.mint, user));
+    Ok(())
+}
+
+// withdraw whatever the user holds and isn't locked, the amount withdrawn is logged
+fn user_withdraw_all(
+    program_id: &Pubkey,
+    token: TokenType,
+    user: Pubkey,
+    signer: &AccountInfo,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    verify_withdraw_authority(signer, &user, transfer_accounts, state)?;
+    check_not_blocked(state, &[user])?;
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+
+    let balance = state
+        .all_token_balances
+        .get(&token)
+        .ok_or(ContractError::TokenNotSupportedForWithdraw)?
+        .balances
+        .get(&user)
+        .copied()
+        .unwrap_or(0);
+    let amount = balance.saturating_sub(locked_amount(&token.mint, &user, state)?);
+    let (mint, payout) = debit_withdraw(
+        program_id,
+        &token,
+        user,
+        amount,
+        None,
+        transfer_accounts,
+        state,
+    )?;
+    msg!("withdrew all {} of token {}", amount, token.symbol);
+    if payout == 0 {
+        return Ok(());
+    }
+    transfer_from_vault(program_id, &mint, payout, transfer_accounts)
+}
+
+// only the user itself can pick its delegate
+fn set_withdraw_delegate(
+    user: Pubkey,
+    delegate: Option<Pubkey>,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    match delegate {
+        Some(delegate) => state.withdraw_delegates.insert(user, delegate),
+        None => state.withdraw_delegates.remove(&user),
+    };
+    Ok(())
+}
+
+// signer must be user, or user's delegate paying out to a token account owned by user
+fn verify_withdraw_authority(
+    signer: &AccountInfo,
+    user: &Pubkey,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &ContractState,
+) -> Result<(), ProgramError> {
+    if verify_signature(signer, user) {
+        return Ok(());
+    }
+    let delegate = state
+        .withdraw_delegates
+        .get(user)
+        .ok_or(ProgramError::MissingRequiredSignature)?;
+    if !verify_signature(signer, delegate) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_recipient_is_user(transfer_accounts, user)
+}
+
+fn check_recipient_is_user(
+    transfer_accounts: &TokenTransferAccounts,
+    user: &Pubkey,
+) -> Result<(), ProgramError> {
+    if &payout_owner(transfer_accounts)? != user {
+        return Err(ContractError::DelegateRecipientNotUser.into());
+    }
+    Ok(())
+}
+
+// whoever a withdrawal's payout ends up with
+fn payout_owner(transfer_accounts: &TokenTransferAccounts) -> Result<Pubkey, ProgramError> {
+    // SOL is paid out to the recipient's system account itself
+    if is_native_transfer(transfer_accounts) {
+        Ok(*transfer_accounts.user_token_account.key)
+    } else {
+        Ok(unpack_token_account(transfer_accounts.user_token_account)?.owner)
+    }
+}
+
+// Ethereum address whose key produced signature ([r, s, v]) over keccak256(message)
+fn recover_eth_address(message: &[u8], signature: &[u8]) -> Result<[u8; 20], ProgramError> {
+    let (rs, v) = match signature {
+        [rs @ .., v] if rs.len() == 64 => (rs, *v),
+        _ => return Err(ContractError::InvalidEthSignature.into()),
+    };
+    // Ethereum wallets add 27 to the recovery id
+    let recovery_id = match v {
+        0 | 1 => v,
+        27 | 28 => v - 27,
+        _ => return Err(ContractError::InvalidEthSignature.into()),
+    };
+    // (r, n - s) is an equally valid signature, only the low-s form is accepted
+    if rs[32..] > SECP256K1_HALF_ORDER[..] {
+        return Err(ContractError::InvalidEthSignature.into());
+    }
+    let pubkey = secp256k1_recover(&keccak::hash(message).0, recovery_id, rs)
+        .map_err(|_| ContractError::InvalidEthSignature)?;
+    let pubkey_hash = keccak::hash(&pubkey.0).0;
+    Ok(pubkey_hash[12..].try_into().unwrap())
+}
+
+// signature must come from an Ethereum key linked to user, who is signer's key,
+// and the payout must stay with user
+fn verify_eth_withdraw(
+    message: &[u8],
+    signature: &[u8],
+    user: &Pubkey,
+    signer: &AccountInfo,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &ContractState,
+) -> Result<(), ProgramError> {
+    if signer.key != user {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let address = recover_eth_address(message, signature)?;
+    if state.eth_links.get(&address) != Some(user) {
+        return Err(ContractError::EthAddressNotLinked.into());
+    }
+    check_recipient_is_user(transfer_accounts, user)
+}
+
+// an address already linked to someone else can't be taken over
+fn link_eth_address(
+    program_id: &Pubkey,
+    user: Pubkey,
+    signature: &[u8],
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    let address = recover_eth_address(&eth_link_message(program_id, &user), signature)?;
+    if state
+        .eth_links
+        .get(&address)
+        .is_some_and(|linked| linked != &user)
+    {
+        return Err(ContractError::EthAddressAlreadyLinked.into());
+    }
+    state.eth_links.insert(address, user);
+    Ok(())
+}
+
+// move amount from the available balance into a pending request
+fn user_request_withdraw(
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+    if amount == 0 {
+        return Err(ContractError::ZeroAmount.into());
+    }
+
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotSupportedForWithdraw)?;
+    if !entry.withdrawals_enabled {
+        return Err(ContractError::WithdrawalsDisabled.into());
+    }
+    if entry.frozen.contains(&user) {
+        return Err(ContractError::AccountFrozen.into());
+    }
+    if entry.withdraw_requests.contains_key(&user) {
+        return Err(ContractError::WithdrawRequestPending.into());
+    }
+    let balance = entry.balances.get(&user).copied().unwrap_or(0);
+    let new_balance = balance
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+
+    let clock = Clock::get()?;
+    entry.balances.insert(user, new_balance);
+    entry.withdraw_requests.insert(
+        user,
+        WithdrawRequest {
+            amount,
+            requested_slot: clock.slot,
+            requested_at: clock.unix_timestamp,
+        },
+    );
+    Ok(())
+}
+
+// once the delay passed, unlock the request and withdraw it like a normal withdrawal
+fn user_claim_withdraw(
+    program_id: &Pubkey,
+    token: TokenType,
+    user: Pubkey,
+    signer: &AccountInfo,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    verify_withdraw_authority(signer, &user, transfer_accounts, state)?;
+    check_not_blocked(state, &[user])?;
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+
+    let delay_slots = state.withdraw_delay_slots;
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotSupportedForWithdraw)?;
+    let request = entry
+        .withdraw_requests
+        .get(&user)
+        .ok_or(ContractError::NoWithdrawRequest)?;
+    if Clock::get()?.slot < request.requested_slot.saturating_add(delay_slots) {
+        return Err(ContractError::WithdrawRequestLocked.into());
+    }
+    let amount = request.amount;
+    unlock_withdraw_request(entry, user)?;
+
+    let (mint, payout) = debit_withdraw(
+        program_id,
+        &token,
+        user,
+        amount,
+        None,
+        transfer_accounts,
+        state,
+    )?;
+    transfer_from_vault(program_id, &mint, payout, transfer_accounts)
+}
+
+// also applies to requests already pending
+fn set_withdraw_delay(delay_slots: u64, state: &mut ContractState) -> Result<(), ProgramError> {
+    state.withdraw_delay_slots = delay_slots;
+    Ok(())
+}
+
+// admin escape hatch, e.g. when the user's key is compromised
+fn cancel_withdraw_request(
+    token: TokenType,
+    user: Pubkey,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get_mut(&token)
+        .ok_or(ContractError::TokenNotFound)?;
+    unlock_withdraw_request(entry, user)
+}
+
+// drop user's pending request and put its amount back into the available balance
+fn unlock_withdraw_request(entry: &mut TokenEntry, user: Pubkey) -> Result<(), ProgramError> {
+    let request = entry
+        .withdraw_requests
+        .remove(&user)
+        .ok_or(ContractError::NoWithdrawRequest)?;
+    let balance = entry.balances.entry(user).or_insert(0);
+    *balance = balance
+        .checked_add(request.amount)
+        .ok_or(ContractError::BalanceOverflow)?;
+    Ok(())
+}
+
+// queue amount of user's balance for ProcessWithdrawQueue, the checks user_request_withdraw
+// makes up front are made here too, the rest when it runs
+fn user_enqueue_withdraw(
+    token: TokenType,
+    user: Pubkey,
+    amount: u64,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+    if amount == 0 {
+        return Err(ContractError::ZeroAmount.into());
+    }
+
+    // disabled withdrawals are no reason not to queue, they wait for them to be enabled again
+    let entry = state
+        .all_token_balances
+        .get(&token)
+        .ok_or(ContractError::TokenNotSupportedForWithdraw)?;
+    if entry.frozen.contains(&user) {
+        return Err(ContractError::AccountFrozen.into());
+    }
+    if entry.balances.get(&user).copied().unwrap_or(0) < amount {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    let queue = state.withdraw_queues.entry(token.mint).or_default();
+    if queue.iter().any(|queued| queued.user == user) {
+        return Err(ContractError::WithdrawAlreadyQueued.into());
+    }
+    if queue.len() >= MAX_WITHDRAW_QUEUE_LEN {
+        return Err(ContractError::WithdrawQueueFull.into());
+    }
+    queue.push_back(QueuedWithdraw {
+        user,
+        amount,
+        queued_slot: Clock::get()?.slot,
+    });
+    Ok(())
+}
+
+fn cancel_queued_withdraw(
+    token: TokenType,
+    user: Pubkey,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let queue = state
+        .withdraw_queues
+        .get_mut(&token.mint)
+        .ok_or(ContractError::NotInWithdrawQueue)?;
+    let position = queue
+        .iter()
+        .position(|queued| queued.user == user)
+        .ok_or(ContractError::NotInWithdrawQueue)?;
+    queue.remove(position);
+    if queue.is_empty() {
+        state.withdraw_queues.remove(&token.mint);
+    }
+    Ok(())
+}
+
+// withdraw the entries at the front of token's queue, one per transfer_accounts in queue order,
+// each paid out before the next is debited so the vault check sees what is left
+// the vault falling short or withdrawals getting disabled, the breaker tripping included,
+// stops it with the entry kept in place. an entry that can only run later keeps its place and
+// the next one runs, see is_retryable, rate limits were counted when it was queued. one whose
+// user closed their associated token account moves to the back of the queue, so it doesn't
+// hold up the ones behind it until they open it again or cancel, one failing any other way is
+// dropped
+fn process_withdraw_queue(
+    program_id: &Pubkey,
+    token: TokenType,
+    transfer_accounts: &[TokenTransferAccounts],
+    accounts: &[AccountInfo],
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotSupportedForWithdraw.into());
+    }
+    // entries before it were kept
+    let mut position = 0;
+    for item_accounts in transfer_accounts {
+        let Some(queued) = state
+            .withdraw_queues
+            .get(&token.mint)
+            .and_then(|queue| queue.get(position))
+            .copied()
+        else {
+            break;
+        };
+        if is_closed_payout_account(&token.mint, &queued.user, item_accounts) {
+            msg!(
+                "token account of queued user {} is closed, moved to the back",
+                queued.user
+            );
+            if let Some(queue) = state.withdraw_queues.get_mut(&token.mint) {
+                queue.remove(position);
+                queue.push_back(queued);
+            }
+            continue;
+        }
+        if payout_owner(item_accounts)? != queued.user {
+            msg!("payout account isn't owned by queued user {}", queued.user);
+            return Err(ContractError::RecipientMismatch.into());
+        }
+        // a balance account left out would read as 0 and get the entry dropped
+        let (address, _) = find_balance_address(program_id, &token.mint, &queued.user);
+        if !accounts.iter().any(|account| account.key == &address) {
+            msg!(
+                "missing balance account {} of user {}",
+                address,
+                queued.user
+            );
+            return Err(ContractError::MissingBalanceAccount.into());
+        }
+        // it takes the rent of the balance account a full withdrawal closes
+        if !accounts
+            .iter()
+            .any(|account| account.key == &queued.user && account.is_writable)
+        {
+            msg!("missing account of queued user {}", queued.user);
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let Some(entry) = state.all_token_balances.get(&token) else {
+            break;
+        };
+        if !entry.withdrawals_enabled {
+            msg!("withdrawals of {} are disabled", token.symbol);
+            break;
+        }
+        let balance = entry.balances.get(&queued.user).copied().unwrap_or(0);
+
+        let withdrawn = check_not_blocked(state, &[queued.user]).and_then(|_| {
+            debit_withdraw(
+                program_id,
+                &token,
+                queued.user,
+                queued.amount,
+                None,
+                item_accounts,
+                state,
+            )
+        });
+        match withdrawn {
+            Ok((mint, payout)) => {
+                remove_queued_withdraw(&mint, position, state);
+                transfer_from_vault(program_id, &mint, payout, item_accounts)?;
+            }
+            // user holds the amount, so it's the vault that is short
+            Err(ProgramError::InsufficientFunds) if balance >= queued.amount => {
+                msg!(
+                    "vault can't pay out {} to user {}",
+                    queued.amount,
+                    queued.user
+                );
+                break;
+            }
+            // debit_withdraw changes nothing when it fails
+            Err(error) if is_retryable(&error) => {
+                msg!("queued withdrawal of user {} kept: {}", queued.user, error);
+                position += 1;
+            }
+            Err(error) => {
+                msg!(
+                    "queued withdrawal of user {} dropped: {}",
+                    queued.user,
+                    error
+                );
+                remove_queued_withdraw(&token.mint, position, state);
+            }
+        }
+    }
+    Ok(())
+}
+
+// failures of a queued withdrawal that pass with time
+fn is_retryable(error: &ProgramError) -> bool {
+    [
+        ContractError::CooldownActive,
+        ContractError::DailyLimitExceeded,
+        ContractError::BalanceLocked,
+    ]
+    .into_iter()
+    .any(|retryable| *error == retryable.into())
+}
+
+// the payout account is user's associated token account of mint and it has been closed, SOL
+// is paid out to the user's own account, which can't be
+fn is_closed_payout_account(
+    mint: &Pubkey,
+    user: &Pubkey,
+    transfer_accounts: &TokenTransferAccounts,
+) -> bool {
+    let token_program = transfer_accounts.token_program.key;
+    (token_program == &spl_token::id() || token_program == &spl_token_2022::id())
+        && transfer_accounts.user_token_account.data_is_empty()
+        && transfer_accounts.user_token_account.key
+            == &get_associated_token_address_with_program_id(user, mint, token_program)
+}
+
+fn remove_queued_withdraw(mint: &Pubkey, position: usize, state: &mut ContractState) {
+    if let Some(queue) = state.withdraw_queues.get_mut(mint) {
+        queue.remove(position);
+        if queue.is_empty() {
+            state.withdraw_queues.remove(mint);
+        }
+    }
+}
+
+// every balance is checked and debited before the first transfer, one bad item aborts the batch
+fn user_batch_withdraw(
+    program_id: &Pubkey,
+    user: Pubkey,
+    items: Vec<(TokenType, u64)>,
+    signer: &AccountInfo,
+    transfer_accounts: &[TokenTransferAccounts],
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    if state.paused && !state.withdrawals_only {
+        return Err(ContractError::ContractPaused.into());
+    }
+
+    let mut payouts = Vec::with_capacity(items.len());
+    for (index, ((token, amount), accounts)) in items.iter().zip(transfer_accounts).enumerate() {
+        let payout = debit_withdraw(program_id, token, user, *amount, None, accounts, state)
+            .inspect_err(|_| {
+                msg!("batch withdraw item {} failed", index);
+            })?;
+        payouts.push(payout);
+    }
+    for ((mint, payout), accounts) in payouts.iter().zip(transfer_accounts) {
+        transfer_from_vault(program_id, mint, *payout, accounts)?;
+    }
+    Ok(())
+}
+
+// ledger side of a withdrawal, checks token, accounts and funds then debits user the full
+// amount and credits the fee to the treasury
+// returns the mint of token and the payout, amount minus fee
+fn debit_withdraw(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: Pubkey,
+    amount: u64,
+    memo: Option<String>,
+    transfer_accounts: &TokenTransferAccounts,
+    state: &mut ContractState,
+) -> Result<(Pubkey, u64), ProgramError> {
+    let treasury = state.treasury;
+    let exempt = is_fee_exempt(state, &user);
+    let locked = locked_amount(&token.mint, &user, state)?;
+    let share_supply = state.share_supplies.get(&token.mint).copied();
+    let breaker = state.circuit_breakers.get(&token.mint).copied();
+    let dust_threshold = state.dust_thresholds.get(&token.mint).copied().unwrap_or(0);
+    let cooldown = state.withdraw_cooldowns.get(&token.mint).copied();
+    let min_withdraw = state.min_withdrawals.get(&token.mint).copied().unwrap_or(0);
+    let last_deposit_slot = state
+        .balance_slots
+        .get(&(token.mint, user))
+        .map(|slots| slots.last_deposit_slot);
+    let entry = state
+        .all_token_balances
+        .get_mut(token)
+        .ok_or(ContractError::TokenNotSupportedForWithdraw)?;
+    if !entry.withdrawals_enabled {
+        return Err(ContractError::WithdrawalsDisabled.into());
+    }
+    if entry.frozen.contains(&user) {
+        return Err(ContractError::AccountFrozen.into());
+    }
+    // a balance that never took a deposit has nothing to cool down from
+    if let (Some(cooldown), Some(last_deposit_slot)) = (cooldown, last_deposit_slot) {
+        let ends = last_deposit_slot.saturating_add(cooldown.withdraw_cooldown_slots);
+        let slot = Clock::get()?.slot;
+        if slot < ends {
+            msg!(
+                "user {} can withdraw {} in {} slots",
+                user,
+                token.symbol,
+                ends - slot
+            );
+            return Err(ContractError::CooldownActive.into());
+        }
+    }
+    let mint = token.mint;
+    check_token_transfer_accounts(program_id, transfer_accounts, &mint)?;
+    let recipient = payout_owner(transfer_accounts)?;
+    // the limits are in token units, shares withdrawn count at what they are worth
+    let amount_value = balance_value(amount, share_supply, entry.total_deposited);
+
+    // a window starts with the first withdrawal after the previous one expired
+    let window = if entry.daily_withdrawal_limit > 0 {
+        let now = Clock::get()?.unix_timestamp;
+        let (window_start, withdrawn) = match entry.withdrawal_windows.get(&user) {
+            Some(&(start, withdrawn)) if now.saturating_sub(start) < WITHDRAWAL_WINDOW_SECS => {
+                (start, withdrawn)
+            }
+            _ => (now, 0),
+        };
+        let withdrawn = withdrawn
+            .checked_add(amount_value)
+            .filter(|withdrawn| *withdrawn <= entry.daily_withdrawal_limit)
+            .ok_or(ContractError::DailyLimitExceeded)?;
+        Some((window_start, withdrawn))
+    } else {
+        None
+    };
+
+    let left = entry
+        .balances
+        .get(&user)
+        .copied()
+        .unwrap_or(0)
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    // check_locked_balances would catch it too, but only after the payout
+    if left < locked {
+        msg!("{} of user {}'s balance is locked", locked, user);
+        return Err(ContractError::BalanceLocked.into());
+    }
+    // a leftover too small to keep goes to the treasury, unless some of it is locked or it
+    // already is the treasury's, the threshold is in token units so leftover shares count at
+    // what they are worth
+    let left_value = match share_supply {
+        Some(supply) => amount_for_shares(left, supply, entry.total_deposited).unwrap_or(0),
+        None => left,
+    };
+    let dust_swept = if left_value <= dust_threshold && locked == 0 && treasury != Some(user) {
+        left
+    } else {
+        0
+    };
+    let new_balance = left - dust_swept;
+    // the whole balance can always go, so no one is left stuck below the minimum, dust swept
+    // along with it included
+    if amount_value < min_withdraw && new_balance > 0 {
+        msg!(
+            "minimum withdrawal of token {} is {}",
+            token.symbol,
+            min_withdraw
+        );
+        return Err(ContractError::WithdrawBelowMinimum.into());
+    }
+    let fee = if exempt {
+        0
+    } else {
+        fee_amount(amount, entry.withdrawal_fee_bps)
+    };
+    // the fee stays in the vault, so do fee and total_deposited
+    let burned = amount - fee;
+    // a share-mode token pays out what the shares burned are worth, rounded down
+    let payout = match share_supply {
+        Some(supply) => amount_for_shares(burned, supply, entry.total_deposited)
+            .filter(|payout| *payout > 0)
+            .ok_or(ContractError::ZeroShares)?,
+        None => burned,
+    };
+    // the dust stays in the vault like the fee
+    let treasury_credit = credit_fee(entry, treasury, fee + dust_swept)?;
+    // the ledger can't be paid out if the vault itself is short
+    if vault_balance(transfer_accounts.vault_token_account, &mint)? < payout {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    // total_deposited covers every balance, falling short means the state is corrupt
+    let new_total = entry
+        .total_deposited
+        .checked_sub(payout)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    let global_total = state
+        .global_total_deposited
+        .checked_sub(u128::from(payout))
+        .ok_or(ProgramError::InvalidAccountData)?;
+    // keep the state small, a balance withdrawn in full is dropped along with its account
+    if new_balance == 0 {
+        entry.balances.remove(&user);
+    } else {
+        entry.balances.insert(user, new_balance);
+    }
+    if let Some((treasury, treasury_balance)) = treasury_credit {
+        entry.balances.insert(treasury, treasury_balance);
+    }
+    entry.total_deposited = new_total;
+    state.global_total_deposited = global_total;
+    if let Some(supply) = share_supply {
+        // burned is part of a balance, so of the supply
+        state.share_supplies.insert(mint, supply - burned);
+    }
+    if let Some(window) = window {
+        entry.withdrawal_windows.insert(user, window);
+    }
+    let stats = state.user_stats.entry((mint, user)).or_default();
+    stats.withdraw_count = stats.withdraw_count.saturating_add(1);
+    stats.lifetime_withdrawn = stats
+        .lifetime_withdrawn
+        .saturating_add(u128::from(amount_value));
+    let slots = state.balance_slots.entry((mint, user)).or_default();
+    slots.last_withdraw_slot = Clock::get()?.slot;
+    let slots = *slots;
+    // the withdrawal tripping the breaker still goes through, the ones after it don't
+    let breaker = match breaker {
+        Some(breaker) => {
+            let slot = Clock::get()?.slot;
+            let breaker = count_breaker_withdrawal(breaker, payout, new_total, slot);
+            state.circuit_breakers.insert(mint, breaker);
+            Some(breaker).filter(|breaker| breaker.tripped)
+        }
+        None => None,
+    };
+    if breaker.is_some() {
+        entry.withdrawals_enabled = false;
+    }
+    WithdrawEvent {
+        token_symbol: stored_symbol(token, state),
+        user,
+        amount,
+        new_balance,
+        sequence: state.sequence,
+        recipient,
+        memo,
+        dust_swept,
+        last_deposit_slot: slots.last_deposit_slot,
+        last_withdraw_slot: slots.last_withdraw_slot,
+    }
+    .emit();
+    if let Some(breaker) = breaker {
+        msg!("circuit breaker of {} tripped", token.symbol);
+        CircuitBreakerTrippedEvent {
+            token_symbol: stored_symbol(token, state),
+            withdrawn: breaker.withdrawn,
+            total_deposited: new_total,
+            window_start_slot: breaker.window_start_slot,
+            sequence: state.sequence,
+        }
+        .emit();
+    }
+    Ok((mint, payout))
+}
+
+// treasury's balance after receiving fee, None when there is no fee to credit
+fn credit_fee(
+    entry: &TokenEntry,
+    treasury: Option<Pubkey>,
+    fee: u64,
+) -> Result<Option<(Pubkey, u64)>, ProgramError> {
+    if fee == 0 {
+        return Ok(None);
+    }
+    let treasury = treasury.ok_or(ContractError::TreasuryNotSet)?;
+    let treasury_balance = entry
+        .balances
+        .get(&treasury)
+        .copied()
+        .unwrap_or(0)
+        .checked_add(fee)
+        .ok_or(ContractError::BalanceOverflow)?;
+    Ok(Some((treasury, treasury_balance)))
+}
+
+// the treasury never pays fees to itself
+fn is_fee_exempt(state: &ContractState, user: &Pubkey) -> bool {
+    state.treasury.as_ref() == Some(user) || state.fee_exempt.contains(user)
+}
+
+// floor of amount * fee_bps / 10_000, never more than amount as fee_bps <= MAX_FEE_BPS
+fn fee_amount(amount: u64, fee_bps: u16) -> u64 {
+    (amount as u128 * fee_bps as u128 / 10_000) as u64
+}
+
+// move amount from the vault back to the user's token account, signed by the vault PDA
+// the program owns the SOL vault and moves its lamports itself
+fn transfer_from_vault(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+    transfer_accounts: &TokenTransferAccounts,
+) -> ProgramResult {
+    if is_native_transfer(transfer_accounts) {
+        let vault = transfer_accounts.vault_token_account;
+        if vault_balance(vault, mint)? < amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+        let recipient = transfer_accounts.user_token_account;
+        let received = recipient
+            .lamports()
+            .checked_add(amount)
+            .ok_or(ContractError::BalanceOverflow)?;
+        **vault.try_borrow_mut_lamports()? -= amount;
+        **recipient.try_borrow_mut_lamports()? = received;
+        return Ok(());
+    }
+    // the ledger is debited the whole amount, it's what leaves the vault
+    let fee = transfer_fee(transfer_accounts, amount)?;
+    if fee > 0 {
+        msg!("transfer fee of {} withheld from the payout", fee);
+    }
+    let (_, bump) = find_vault_authority(program_id, mint);
+    let (instruction, accounts) = token_transfer(
+        transfer_accounts.vault_token_account,
+        transfer_accounts.user_token_account,
+        transfer_accounts.vault_authority,
+        amount,
+        transfer_accounts,
+    )?;
+    invoke_signed(
+        &instruction,
+        &accounts,
+        &[&[VAULT_SEED, mint.as_ref(), &[bump]]],
+    )
+}
+
+// internal ledger transfer, zero amounts and transfers to self are rejected
+fn user_transfer_token(
+    token: TokenType,
+    from: Pubkey,
+    to: Pubkey,
+    amount: u64,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &from) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[from, to])?;
+    move_balance(&token, from, to, amount, state)
+}
+
+// user pays amount_in of from into the treasury's balance and gets what that buys of to at the
+// posted rate, rounded down, out of the treasury's balance, totals and vaults stay as they are
+fn user_swap(
+    from: TokenType,
+    to: TokenType,
+    user: Pubkey,
+    amount_in: u64,
+    min_amount_out: u64,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &user) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[user])?;
+    if !state.all_token_balances.contains_key(&from) || !state.all_token_balances.contains_key(&to)
+    {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    let rate = state
+        .swap_rates
+        .get(&(from.mint, to.mint))
+        .copied()
+        .ok_or(ContractError::SwapRateNotSet)?;
+    let treasury = state.treasury.ok_or(ContractError::TreasuryNotSet)?;
+    // the rate is between token units, the balances of a share-mode token are shares
+    let units_in = units_for_ledger(&from, amount_in, state)?;
+    // below u64::MAX * numerator / denominator, too much only if the rate raises the amount
+    let units_out = u64::try_from(
+        u128::from(units_in) * u128::from(rate.numerator) / u128::from(rate.denominator),
+    )
+    .map_err(|_| ContractError::BalanceOverflow)?;
+    let amount_out = ledger_for_units(&to, units_out, state)?;
+    if amount_out < min_amount_out {
+        msg!(
+            "{} in buys {}, less than {}",
+            amount_in,
+            amount_out,
+            min_amount_out
+        );
+        return Err(ContractError::SlippageExceeded.into());
+    }
+    move_balance(&from, user, treasury, amount_in, state)?;
+    move_balance(&to, treasury, user, amount_out, state)
+}
+
+// what amount of token's balances is worth in token units, rounded down, more shares than
+// there are is more than any balance holds
+fn units_for_ledger(
+    token: &TokenType,
+    amount: u64,
+    state: &ContractState,
+) -> Result<u64, ProgramError> {
+    let Some(supply) = state.share_supplies.get(&token.mint).copied() else {
+        return Ok(amount);
+    };
+    let total_deposited = state
+        .all_token_balances
+        .get(token)
+        .map_or(0, |entry| entry.total_deposited);
+    amount_for_shares(amount, supply, total_deposited).ok_or(ProgramError::InsufficientFunds)
+}
+
+// the balance worth units of token, shares rounded down for a share-mode one
+fn ledger_for_units(
+    token: &TokenType,
+    units: u64,
+    state: &ContractState,
+) -> Result<u64, ProgramError> {
+    let Some(supply) = state.share_supplies.get(&token.mint).copied() else {
+        return Ok(units);
+    };
+    let total_deposited = state
+        .all_token_balances
+        .get(token)
+        .map_or(0, |entry| entry.total_deposited);
+    shares_for_amount(units, supply, total_deposited).ok_or(ProgramError::InsufficientFunds)
+}
+
+// set the allowance of spender, approving again replaces the previous amount
+fn user_approve(
+    token: TokenType,
+    owner: Pubkey,
+    spender: Pubkey,
+    amount: u64,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &owner) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[owner, spender])?;
+    if !state.all_token_balances.contains_key(&token) {
+        return Err(ContractError::TokenNotFound.into());
+    }
+    if amount == 0 {
+        state.allowances.remove(&(token, owner, spender));
+    } else {
+        state.allowances.insert((token, owner, spender), amount);
+    }
+    Ok(())
+}
+
+// spender moves owner's balance to `to`, using up allowance
+fn transfer_from(
+    token: TokenType,
+    owner: Pubkey,
+    spender: Pubkey,
+    to: Pubkey,
+    amount: u64,
+    signer: &AccountInfo,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if !verify_signature(signer, &spender) {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_not_blocked(state, &[owner, spender, to])?;
+    let key = (token, owner, spender);
+    let allowance = *state
+        .allowances
+        .get(&key)
+        .ok_or(ContractError::NoAllowance)?;
+    let remaining = allowance
+        .checked_sub(amount)
+        .ok_or(ContractError::AllowanceExceeded)?;
+    move_balance(&key.0, owner, to, amount, state)?;
+    if remaining == 0 {
+        state.allowances.remove(&key);
+    } else {
+        state.allowances.insert(key, remaining);
+    }
+    Ok(())
+}
+
+// move internal balance from one user to another, shared by all transfer paths
+fn move_balance(
+    token: &TokenType,
+    from: Pubkey,
+    to: Pubkey,
+    amount: u64,
+    state: &mut ContractState,
+) -> Result<(), ProgramError> {
+    if state.paused {
+        return Err(ContractError::ContractPaused.into());
+    }
+    if amount == 0 {
+        return Err(ContractError::ZeroAmount.into());
+    }
+    if from == to {
+        return Err(ContractError::SelfTransfer.into());
+    }
+
+    let share_supply = state.share_supplies.get(&token.mint).copied();
+    let entry = state
+        .all_token_balances
+        .get_mut(token)
+        .ok_or(ContractError::TokenNotFound)?;
+    if entry.frozen.contains(&from) {
+        return Err(ContractError::AccountFrozen.into());
+    }
+    let from_balance = entry
+        .balances
+        .get(&from)
+        .copied()
+        .unwrap_or(0)
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    let to_balance = entry
+        .balances
+        .get(&to)
+        .copied()
+        .unwrap_or(0)
+        .checked_add(amount)
+        .ok_or(ContractError::BalanceOverflow)?;
+    let to_value = balance_value(to_balance, share_supply, entry.total_deposited);
+    if entry.max_user_balance.is_some_and(|max| to_value > max) {
+        return Err(ContractError::UserBalanceCapExceeded.into());
+    }
+    entry.balances.insert(from, from_balance);
+    entry.balances.insert(to, to_balance);
+    if state
+        .withdraw_cooldowns
+        .get(&token.mint)
+        .is_some_and(|cooldown| cooldown.transfers_reset)
+    {
+        let slots = state.balance_slots.entry((token.mint, to)).or_default();
+        slots.last_deposit_slot = Clock::get()?.slot;
+    }
+    Ok(())
+}
+
+// the nonce of user's balance account of mint, looked up by address so one created earlier in
+// the same Multicall counts, an account not passed reads as not created yet
+fn current_nonce(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    user: &Pubkey,
+    accounts: &[AccountInfo],
+    state: &ContractState,
+) -> Result<u64, ProgramError> {
+    let (address, _) = find_balance_address(program_id, mint, user);
+    match accounts.iter().find(|account| account.key == &address) {
+        Some(account) if account.owner == program_id => {
+            Ok(state.balance_nonce(user, &account.try_borrow_data()?))
+        }
+        _ => Ok(state.first_nonce(user)),
+    }
+}
+
+// reject a replayed or out-of-order payload
+fn check_nonce(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    user: &Pubkey,
+    nonce: u64,
+    accounts: &[AccountInfo],
+    state: &ContractState,
+) -> ProgramResult {
+    let expected = current_nonce(program_id, mint, user, accounts, state)?;
+    if nonce != expected {
+        msg!("expected nonce {}, got {}", expected, nonce);
+        return Err(ContractError::InvalidNonce.into());
+    }
+    Ok(())
+}
+
+// advance the nonce of user's balance account of mint in place, an account that doesn't exist
+// yet is created with a 0 balance first, accounts[1] paying its rent, so the nonce outlives a
+// deposit credited to someone else
+fn bump_nonce<'a>(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    user: &Pubkey,
+    accounts: &[AccountInfo<'a>],
+    state: &ContractState,
+) -> ProgramResult {
+    let (address, bump) = find_balance_address(program_id, mint, user);
+    let Some(account) = accounts.iter().find(|account| account.key == &address) else {
+        msg!("missing balance account {} of user {}", address, user);
+        return Err(ContractError::MissingBalanceAccount.into());
+    };
+    let slot = Clock::get()?.slot;
+    if account.owner != program_id {
+        let payer = accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let balance = BalanceAccount {
+            mint: *mint,
+            user: *user,
+            bump,
+            epoch: state.token_epochs.get(mint).copied().unwrap_or(0),
+            amount: 0,
+            flags: 0,
+            last_update_slot: slot,
+            nonce: state.first_nonce(user),
+            stats: UserStats::default(),
+            slots: BalanceSlots::default(),
+        };
+        create_balance_account(program_id, accounts, payer, account, &balance)?;
+        write_balance_account(account, &balance)?;
+    }
+    upgrade_balance_account(account, accounts, state)?;
+    let mut data = account.try_borrow_mut_data()?;
+    let balance = BalanceAccountData::load_mut(&mut data)?;
+    balance.set_nonce(balance.nonce() + 1);
+    balance.set_last_update_slot(slot);
+    Ok(())
+}
+
+// publish user's balance of token via return data, user never deposited counts as 0
+fn get_balance(token: TokenType, user: Pubkey, state: &ContractState) -> Result<(), ProgramError> {
+    let entry = state
+        .all_token_balances
+        .get(&token)
+        .ok_or(ContractError::TokenNotSupportedForQuery)?;
+    let balance = entry.balances.get(&user).copied().unwrap_or(0);
+    // a balance account that wasn't passed reads as 0
+    let locked = locked_amount(&token.mint, &user, state)?.min(balance);
+    let slots = state
+        .balance_slots
+        .get(&(token.mint, user))
+        .copied()
+        .unwrap_or_default();
+    let balance = UserBalance {
+        liquid: balance - locked,
+        locked,
+        last_deposit_slot: slots.last_deposit_slot,
+        last_withdraw_slot: slots.last_withdraw_slot,
+    };
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(&balance).unwrap());
+    Ok(())
+}
+
+fn list_supported_tokens(offset: u32, limit: u32, state: &ContractState) -> ProgramResult {
+    // symbols are unique, so the order and with it the pages are stable
+    let mut tokens: Vec<&TokenType> = state.all_token_balances.keys().collect();
+    tokens.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+    let mut page = TokenPage {
+        tokens: Vec::new(),
+        total: tokens.len() as u32,
+    };
+    // serializing into a Vec can't fail
+    let mut len = borsh::object_length(&page).unwrap();
+    for token in tokens
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_TOKENS_PER_PAGE) as usize)
+    {
+        let listing = (token.clone(), token_metadata(&token.mint, state));
+        len += borsh::object_length(&listing).unwrap();
+        if len > MAX_RETURN_DATA {
+            break;
+        }
+        page.tokens.push(listing);
+    }
+    set_return_data(&borsh::to_vec(&page).unwrap());
+    Ok(())
+}
+
+// tokens listed before v7 have the default metadata until it's updated
+fn token_metadata(mint: &Pubkey, state: &ContractState) -> TokenMetadata {
+    state.token_metadata.get(mint).cloned().unwrap_or_default()
+}
+
+fn get_global_stats(state: &ContractState) -> ProgramResult {
+    let stats = GlobalStats {
+        total_deposited: state.global_total_deposited,
+        cap: state.global_deposit_cap,
+        token_count: state.all_token_balances.len() as u32,
+        holder_count: state.holder_counts.values().sum(),
+    };
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(&stats).unwrap());
+    Ok(())
+}
+
+fn get_token_stats(token: TokenType, state: &ContractState) -> ProgramResult {
+    let entry = state
+        .all_token_balances
+        .get(&token)
+        .ok_or(ContractError::TokenNotSupportedForQuery)?;
+    let stats = TokenStats {
+        total_deposited: entry.total_deposited,
+        holder_count: state.holder_counts.get(&token.mint).copied().unwrap_or(0),
+        metadata: token_metadata(&token.mint, state),
+    };
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(&stats).unwrap());
+    Ok(())
+}
+
+fn is_token_supported(token: &TokenType, state: &ContractState) -> ProgramResult {
+    let supported = state
+        .all_token_balances
+        .get(token)
+        .map(|entry| SupportedToken {
+            mint: token.mint,
+            deposits_enabled: entry.deposits_enabled,
+            withdrawals_enabled: entry.withdrawals_enabled,
+        });
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(&supported).unwrap());
+    Ok(())
+}
+
+fn get_user_stats(token: &TokenType, user: &Pubkey, state: &ContractState) -> ProgramResult {
+    if !state.all_token_balances.contains_key(token) {
+        return Err(ContractError::TokenNotSupportedForQuery.into());
+    }
+    let stats = state
+        .user_stats
+        .get(&(token.mint, *user))
+        .copied()
+        .unwrap_or_default();
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(&stats).unwrap());
+    Ok(())
+}
+
+// looked up in user's receipts account if it was passed, then among the receipts kept in the
+// state before there were receipts accounts
+fn get_receipt(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: &Pubkey,
+    receipt_id: u64,
+    state: &ContractState,
+) -> ProgramResult {
+    let (address, _) = find_receipts_address(program_id, user);
+    let mut receipts = match accounts.iter().find(|account| account.key == &address) {
+        Some(account) if account.owner == program_id => read_receipts(&account.try_borrow_data()?)?,
+        _ => Vec::new(),
+    };
+    receipts.extend(state.receipts.get(user).into_iter().flatten());
+    let receipt = receipts
+        .iter()
+        .find(|receipt| receipt.receipt_id == receipt_id)
+        .ok_or(ContractError::ReceiptNotFound)?;
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(receipt).unwrap());
+    Ok(())
+}
+
+fn get_queue_position(token: &TokenType, user: &Pubkey, state: &ContractState) -> ProgramResult {
+    if !state.all_token_balances.contains_key(token) {
+        return Err(ContractError::TokenNotSupportedForQuery.into());
+    }
+    let position = state.withdraw_queues.get(&token.mint).and_then(|queue| {
+        queue
+            .iter()
+            .position(|queued| &queued.user == user)
+            .map(|position| position as u32)
+    });
+    // serializing into a Vec can't fail
+    set_return_data(&borsh::to_vec(&position).unwrap());
+    Ok(())
+}
+
+fn next_token_transfer_accounts<'a, 'b>(
+    account_info_iter: &mut impl Iterator<Item = &'a AccountInfo<'b>>,
+) -> Result<TokenTransferAccounts<'a, 'b>, ProgramError> {
+    let user_token_account = next_account_info(account_info_iter)?;
+    let vault_token_account = next_account_info(account_info_iter)?;
+    let vault_authority = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let mint = if token_program.key == &spl_token_2022::id() {
+        Some(next_account_info(account_info_iter)?)
+    } else {
+        None
+    };
+    Ok(TokenTransferAccounts {
+        user_token_account,
+        vault_token_account,
+        vault_authority,
+        token_program,
+        mint,
+    })
+}
+
+// user's token account and vault must both hold mint, and the vault must belong to the vault PDA
+fn check_token_transfer_accounts(
+    program_id: &Pubkey,
+    transfer_accounts: &TokenTransferAccounts,
+    mint: &Pubkey,
+) -> Result<(), ProgramError> {
+    if mint == &spl_token::native_mint::id() {
+        check_program_id(transfer_accounts.token_program, &system_program::id())?;
+        check_native_vault(program_id, transfer_accounts.vault_token_account)?;
+        if transfer_accounts.vault_authority.key != transfer_accounts.vault_token_account.key {
+            return Err(ContractError::InvalidVaultAuthority.into());
+        }
+        return Ok(());
+    }
+    let token_program = transfer_accounts.token_program.key;
+    if token_program == &spl_token_2022::id() {
+        if transfer_accounts.mint.map(|account| account.key) != Some(mint) {
+            return Err(ContractError::MintAccountMismatch.into());
+        }
+    } else {
+        check_program_id(transfer_accounts.token_program, &spl_token::id())?;
+    }
+    check_owner(transfer_accounts.user_token_account, token_program)?;
+    check_owner(transfer_accounts.vault_token_account, token_program)?;
+    let user_token_account = unpack_token_account(transfer_accounts.user_token_account)?;
+    let vault_token_account = unpack_token_account(transfer_accounts.vault_token_account)?;
+    if &user_token_account.mint != mint || &vault_token_account.mint != mint {
+        return Err(ContractError::TokenAccountMintMismatch.into());
+    }
+    let (vault_authority, _) = find_vault_authority(program_id, mint);
+    if vault_token_account.owner != vault_authority
+        || transfer_accounts.vault_authority.key != &vault_authority
+    {
+        return Err(ContractError::InvalidVaultAuthority.into());
+    }
+    if transfer_accounts.vault_token_account.key
+        != &find_vault_address_with_program_id(program_id, mint, token_program)
+    {
+        return Err(ContractError::InvalidVaultAccount.into());
+    }
+    Ok(())
+}
+
+// vault_account must be the vault of mint, a token account of token_program held by the vault
+// authority
+fn check_vault_account(
+    program_id: &Pubkey,
+    vault_account: &AccountInfo,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> ProgramResult {
+    if vault_account.key != &find_vault_address_with_program_id(program_id, mint, token_program) {
+        return Err(ContractError::InvalidVaultAccount.into());
+    }
+    check_owner(vault_account, token_program)?;
+    let vault = unpack_token_account(vault_account)?;
+    if &vault.mint != mint {
+        return Err(ContractError::TokenAccountMintMismatch.into());
+    }
+    if vault.owner != find_vault_authority(program_id, mint).0 {
+        return Err(ContractError::InvalidVaultAuthority.into());
+    }
+    Ok(())
+}
+
+// the SOL vault is the program-owned vault authority PDA of the native mint
+fn check_native_vault(program_id: &Pubkey, vault_account: &AccountInfo) -> ProgramResult {
+    let mint = spl_token::native_mint::id();
+    if vault_account.key != &find_vault_address(program_id, &mint) {
+        return Err(ContractError::InvalidVaultAccount.into());
+    }
+    check_owner(vault_account, program_id)
+}
+
+// transfers of the accounts checked against the native mint go through the system program
+fn is_native_transfer(transfer_accounts: &TokenTransferAccounts) -> bool {
+    transfer_accounts.token_program.key == &system_program::id()
+}
+
+// what the vault of mint can pay out, the SOL vault keeps its rent-exempt minimum
+fn vault_balance(vault_account: &AccountInfo, mint: &Pubkey) -> Result<u64, ProgramError> {
+    if mint == &spl_token::native_mint::id() {
+        let rent_floor = Rent::get()?.minimum_balance(vault_account.data_len());
+        return Ok(vault_account.lamports().saturating_sub(rent_floor));
+    }
+    Ok(unpack_token_account(vault_account)?.amount)
+}
+
+// a token account of either token program, Token-2022 ones may carry extensions
+fn unpack_token_account(account: &AccountInfo) -> Result<Account, ProgramError> {
+    check_token_program_owner(account)?;
+    Ok(StateWithExtensions::<Account>::unpack(&account.try_borrow_data()?)?.base)
+}
+
+fn check_token_program_owner(account: &AccountInfo) -> ProgramResult {
+    if account.owner != &spl_token_2022::id() {
+        check_owner(account, &spl_token::id())?;
+    }
+    Ok(())
+}
+
+// who may sign an instruction besides the user it acts for
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Role {
+    // admin only
+    Admin,
+    // operator or admin
+    Operator,
+}
+
+// permission matrix, None for instructions not gated by a role
+fn required_role(instruction: &ContractInstruction) -> Option<Role> {
+    match instruction {
+        // whoever may run the action queues it, and is checked again when it runs
+        ContractInstruction::AdminQueueAction { action, .. }
+        | ContractInstruction::AdminExecuteAction { action } => required_role(action),
+        ContractInstruction::AdminAddSupportedToken { .. }
+        | ContractInstruction::AdminAddSupportedTokens { .. }
+        | ContractInstruction::AdminDeleteSupportedToken { .. }
+        | ContractInstruction::AdminForceDeleteToken { .. }
+        | ContractInstruction::AdminSetOperator { .. }
+        | ContractInstruction::AdminProposeNewAdmin { .. }
+        | ContractInstruction::AdminCancelProposedAdmin
+        | ContractInstruction::AdminSetMaxTokens { .. }
+        | ContractInstruction::AdminSetRateLimit { .. }
+        | ContractInstruction::AdminSetGlobalCap { .. }
+        | ContractInstruction::AdminSetPriceFeed { .. }
+        | ContractInstruction::AdminSetSwapRate { .. }
+        | ContractInstruction::AdminEnableShareMode { .. }
+        | ContractInstruction::AdminDonateYield { .. }
+        | ContractInstruction::AdminSlash { .. }
+        | ContractInstruction::AdminSetTimelockDelay { .. }
+        | ContractInstruction::AdminSetCircuitBreaker { .. }
+        | ContractInstruction::AdminResetBreaker { .. }
+        | ContractInstruction::AdminSetDustThreshold { .. }
+        | ContractInstruction::AdminSetWithdrawCooldown { .. }
+        | ContractInstruction::AdminSetMinWithdraw { .. }
+        | ContractInstruction::AdminSetTreasury { .. }
+        | ContractInstruction::AdminWithdrawTreasury { .. }
+        | ContractInstruction::AdminEmergencySweep { .. }
+        | ContractInstruction::AdminDepositVested { .. }
+        | ContractInstruction::AdminSnapshotState
+        | ContractInstruction::AdminSetWithdrawDelay { .. }
+        | ContractInstruction::AdminUpdateTokenMetadata { .. }
+        | ContractInstruction::AdminRenameToken { .. }
+        | ContractInstruction::AdminSetMerkleRoot { .. } => Some(Role::Admin),
+        ContractInstruction::AdminPause { .. }
+        | ContractInstruction::AdminUnpause
+        | ContractInstruction::AdminSetTokenFlags { .. }
+        | ContractInstruction::AdminSetDepositCap { .. }
+        | ContractInstruction::AdminSetMaxUserBalance { .. }
+        | ContractInstruction::AdminSetMinDeposit { .. }
+        | ContractInstruction::AdminSetDisplayPrecision { .. }
+        | ContractInstruction::AdminSetRewardRate { .. }
+        | ContractInstruction::AdminSetDailyWithdrawalLimit { .. }
+        | ContractInstruction::AdminSetWithdrawalFee { .. }
+        | ContractInstruction::AdminSetDepositFee { .. }
+        | ContractInstruction::AdminSetFeeExempt { .. }
+        | ContractInstruction::AdminFreezeUser { .. }
+        | ContractInstruction::AdminUnfreezeUser { .. }
+        | ContractInstruction::AdminSetBlocked { .. }
+        | ContractInstruction::AdminCancelWithdrawRequest { .. } => Some(Role::Operator),
+        // MigrateState checks the admin itself, the state can't be loaded before migrating
+        ContractInstruction::InitializeState
+        | ContractInstruction::MigrateState
+        | ContractInstruction::InitializeConfig { .. }
+        | ContractInstruction::AcceptAdmin
+        | ContractInstruction::UserDeposit { .. }
+        | ContractInstruction::UserWithdraw { .. }
+        | ContractInstruction::UserWithdrawAll { .. }
+        | ContractInstruction::UserRequestWithdraw { .. }
+        | ContractInstruction::UserClaimWithdraw { .. }
+        | ContractInstruction::UserSetWithdrawDelegate { .. }
+        | ContractInstruction::CloseUserBalance { .. }
+        | ContractInstruction::BatchDeposit { .. }
+        | ContractInstruction::BatchWithdraw { .. }
+        | ContractInstruction::UserTransfer { .. }
+        | ContractInstruction::UserApprove { .. }
+        | ContractInstruction::TransferFrom { .. }
+        | ContractInstruction::GetBalance { .. }
+        | ContractInstruction::LinkEthAddress { .. }
+        | ContractInstruction::GetNonce { .. }
+        | ContractInstruction::GetSequence
+        | ContractInstruction::ListSupportedTokens { .. }
+        | ContractInstruction::GetTokenStats { .. }
+        | ContractInstruction::IsTokenSupported { .. }
+        | ContractInstruction::GetUserStats { .. }
+        | ContractInstruction::GetReceipt { .. }
+        | ContractInstruction::UserEnqueueWithdraw { .. }
+        | ContractInstruction::UserCancelQueuedWithdraw { .. }
+        | ContractInstruction::ProcessWithdrawQueue { .. }
+        | ContractInstruction::GetQueuePosition { .. }
+        | ContractInstruction::ClaimRewards { .. }
+        | ContractInstruction::GetRewardPoints { .. }
+        | ContractInstruction::GetReferralStats { .. }
+        | ContractInstruction::GetAuditLog { .. }
+        | ContractInstruction::ClaimAirdrop { .. }
+        | ContractInstruction::RedeemVoucher { .. }
+        | ContractInstruction::GetGlobalStats
+        | ContractInstruction::UserSwap { .. }
+        | ContractInstruction::Multicall { .. } => None,
+    }
+}
+
+// token and amount the audit log records for instruction, a setting's new value counts as
+// its amount, a batch of several tokens records none and the sum of its amounts
+fn audit_subject(instruction: &ContractInstruction) -> (Option<&TokenType>, u64) {
+    match instruction {
+        ContractInstruction::UserDeposit { token, amount, .. }
+        | ContractInstruction::UserWithdraw { token, amount, .. }
+        | ContractInstruction::UserRequestWithdraw { token, amount, .. }
+        | ContractInstruction::UserEnqueueWithdraw { token, amount, .. }
+        | ContractInstruction::AdminWithdrawTreasury { token, amount }
+        | ContractInstruction::AdminDepositVested { token, amount, .. }
+        | ContractInstruction::UserTransfer { token, amount, .. }
+        | ContractInstruction::UserApprove { token, amount, .. }
+        | ContractInstruction::TransferFrom { token, amount, .. }
+        | ContractInstruction::ClaimAirdrop { token, amount, .. }
+        | ContractInstruction::RedeemVoucher { token, amount, .. }
+        | ContractInstruction::AdminDonateYield { token, amount }
+        | ContractInstruction::AdminSlash { token, amount, .. } => (Some(token), *amount),
+        ContractInstruction::AdminSetMerkleRoot { token, total, .. } => (Some(token), *total),
+        ContractInstruction::AdminSetWithdrawalFee { token, fee_bps }
+        | ContractInstruction::AdminSetDepositFee { token, fee_bps } => {
+            (Some(token), u64::from(*fee_bps))
+        }
+        ContractInstruction::AdminSetDepositCap { token, cap: value }
+        | ContractInstruction::AdminSetMaxUserBalance {
+            token,
+            max_user_balance: value,
+        } => (Some(token), value.unwrap_or(0)),
+        ContractInstruction::AdminSetMinDeposit {
+            token,
+            min_deposit: value,
+        }
+        | ContractInstruction::AdminSetDailyWithdrawalLimit {
+            token,
+            limit: value,
+        }
+        | ContractInstruction::AdminSetRewardRate {
+            token,
+            points_per_day: value,
+        }
+        | ContractInstruction::AdminSetDustThreshold {
+            token,
+            threshold: value,
+        }
+        | ContractInstruction::AdminSetWithdrawCooldown {
+            token,
+            withdraw_cooldown_slots: value,
+            ..
+        }
+        | ContractInstruction::AdminSetMinWithdraw {
+            token,
+            min_withdraw: value,
+        } => (Some(token), *value),
+        ContractInstruction::ProcessWithdrawQueue { token, max_items } => {
+            (Some(token), u64::from(*max_items))
+        }
+        ContractInstruction::AdminSetWithdrawDelay { delay_slots }
+        | ContractInstruction::AdminSetTimelockDelay { delay_slots } => (None, *delay_slots),
+        // the action is logged again as itself once it runs
+        ContractInstruction::AdminQueueAction { eta_slot, .. } => (None, *eta_slot),
+        ContractInstruction::AdminSetMaxTokens { max_tokens } => (None, u64::from(*max_tokens)),
+        ContractInstruction::AdminAddSupportedTokens { tokens } => (None, tokens.len() as u64),
+        ContractInstruction::AdminSetRateLimit { max_ops, .. } => (None, u64::from(*max_ops)),
+        ContractInstruction::AdminSetGlobalCap { cap } => {
+            (None, u64::try_from(*cap).unwrap_or(u64::MAX))
+        }
+        ContractInstruction::AdminSetPriceFeed { token, usd_cap, .. } => (Some(token), *usd_cap),
+        ContractInstruction::AdminSetCircuitBreaker {
+            token,
+            threshold_bps,
+            ..
+        } => (Some(token), u64::from(*threshold_bps)),
+        // a rate and a swap record the token paid in
+        ContractInstruction::AdminSetSwapRate {
+            from, numerator, ..
+        } => (Some(from), *numerator),
+        ContractInstruction::UserSwap {
+            from, amount_in, ..
+        } => (Some(from), *amount_in),
+        ContractInstruction::BatchDeposit { items, .. }
+        | ContractInstruction::BatchWithdraw { items, .. } => {
+            let amount = items
+                .iter()
+                .fold(0u64, |sum, (_, amount)| sum.saturating_add(*amount));
+            let token = items.first().map(|(token, _)| token);
+            match token {
+                Some(first) if items.iter().all(|(token, _)| token == first) => (token, amount),
+                _ => (None, amount),
+            }
+        }
+        ContractInstruction::AdminAddSupportedToken { token, .. }
+        | ContractInstruction::AdminDeleteSupportedToken { token }
+        | ContractInstruction::AdminForceDeleteToken { token, .. }
+        | ContractInstruction::UserWithdrawAll { token, .. }
+        | ContractInstruction::CloseUserBalance { token, .. }
+        | ContractInstruction::UserClaimWithdraw { token, .. }
+        | ContractInstruction::AdminCancelWithdrawRequest { token, .. }
+        | ContractInstruction::AdminFreezeUser { token, .. }
+        | ContractInstruction::AdminUnfreezeUser { token, .. }
+        | ContractInstruction::AdminSetTokenFlags { token, .. }
+        | ContractInstruction::AdminEmergencySweep { token, .. }
+        | ContractInstruction::AdminUpdateTokenMetadata { token, .. }
+        | ContractInstruction::AdminRenameToken { old: token, .. }
+        | ContractInstruction::AdminSetDisplayPrecision { token, .. }
+        | ContractInstruction::AdminEnableShareMode { token }
+        | ContractInstruction::AdminResetBreaker { token }
+        | ContractInstruction::UserCancelQueuedWithdraw { token, .. } => (Some(token), 0),
+        _ => (None, 0),
+    }
+}
+
+// append entry, dropping the oldest once the log holds AUDIT_LOG_CAPACITY entries
+fn push_audit_entry(entry: AuditEntry, state: &mut ContractState) {
+    if state.audit_log.len() >= AUDIT_LOG_CAPACITY {
+        state.audit_log.pop_front();
+    }
+    state.audit_log.push_back(entry);
+}
+
+fn get_audit_log(offset: u32, limit: u32, state: &ContractState) -> ProgramResult {
+    let page = AuditPage {
+        entries: state
+            .audit_log
+            .iter()
+            .rev()
+            .skip(offset as usize)
+            .take(limit.min(MAX_AUDIT_ENTRIES_PER_PAGE) as usize)
+            .cloned()
+            .collect(),
+        total: state.audit_log.len() as u32,
+    };
+    // serializing into a Vec can't fail, a full page is below MAX_RETURN_DATA
+    set_return_data(&borsh::to_vec(&page).unwrap());
+    Ok(())
+}
+
+// signer must be the admin, or the operator where role allows it
+fn verify_role(
+    signer: &AccountInfo,
+    role: Role,
+    state: &ContractState,
+) -> Result<(), ProgramError> {
+    let admin = state.admin.ok_or(ContractError::ConfigNotInitialized)?;
+    if verify_signature(signer, &admin) {
+        return Ok(());
+    }
+    let is_operator = state
+        .operator
+        .is_some_and(|operator| verify_signature(signer, &operator));
+    match role {
+        Role::Operator if is_operator => Ok(()),
+        Role::Admin if is_operator => Err(ContractError::InsufficientAuthority.into()),
+        _ => Err(ProgramError::MissingRequiredSignature),
+    }
+}
+
+// every user instruction checks the keys it touches before changing anything
+fn check_not_blocked(state: &ContractState, users: &[Pubkey]) -> Result<(), ProgramError> {
+    if users.iter().any(|user| state.blocked.contains(user)) {
+        return Err(ContractError::UserBlocked.into());
+    }
+    Ok(())
+}
+
+// the expected key must be passed as an account and have signed the transaction
+// is_signer also covers keys that signed the payload off-chain, see ed25519_signers
+fn verify_signature(signer: &AccountInfo, expected: &Pubkey) -> bool {
+    signer.key == expected && signer.is_signer
+}
@@ -0,0 +1,478 @@
+// async client of a deployed program: builds an instruction with the client builders, signs it
+// with a fresh blockhash, sends and confirms it, and hands a failure of the program back as
+// its ContractError
+// a transaction whose blockhash expired before it was confirmed is signed again with a new one
+// and resent, the nonce UserDeposit and UserWithdraw carry makes a resend of one that did land
+// fail with InvalidNonce instead of applying twice
+use std::time::Duration;
+
+use async_trait::async_trait;
+use num_traits::FromPrimitive;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_client::rpc_request::{RpcError, MAX_MULTIPLE_ACCOUNTS};
+use solana_program::hash::Hash;
+use solana_program::instruction::{Instruction, InstructionError};
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use thiserror::Error;
+
+use crate::client::{
+    add_supported_token_ix, decode_balance, decode_balances, decode_state_account, deposit_ix,
+    find_state_address, with_fee_balance, with_token_2022, withdraw_ix, BalanceExport,
+};
+use crate::error::ContractError;
+use crate::state::{
+    find_balance_address, find_vault_authority, ContractState, TokenMetadata, TokenType,
+    BALANCE_ACCOUNT_TAG,
+};
+
+// what ContractClient needs of a cluster, RpcClient implements it
+#[async_trait]
+pub trait Cluster: Send + Sync {
+    async fn latest_blockhash(&self) -> Result<Hash, ClientError>;
+    async fn send_and_confirm(&self, transaction: &Transaction) -> Result<Signature, ClientError>;
+    // None if there is no account at address
+    async fn account(&self, address: &Pubkey) -> Result<Option<Account>, ClientError>;
+    // addresses of the accounts owned by program_id whose data starts with tag
+    async fn program_account_keys(
+        &self,
+        program_id: &Pubkey,
+        tag: u8,
+    ) -> Result<Vec<Pubkey>, ClientError>;
+    // in the order of addresses, at most MAX_MULTIPLE_ACCOUNTS of them
+    async fn accounts(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError>;
+}
+
+#[async_trait]
+impl Cluster for RpcClient {
+    async fn latest_blockhash(&self) -> Result<Hash, ClientError> {
+        self.get_latest_blockhash().await
+    }
+
+    async fn send_and_confirm(&self, transaction: &Transaction) -> Result<Signature, ClientError> {
+        self.send_and_confirm_transaction(transaction).await
+    }
+
+    async fn account(&self, address: &Pubkey) -> Result<Option<Account>, ClientError> {
+        Ok(self
+            .get_account_with_commitment(address, self.commitment())
+            .await?
+            .value)
+    }
+
+    // the data is sliced away, only the keys are used
+    async fn program_account_keys(
+        &self,
+        program_id: &Pubkey,
+        tag: u8,
+    ) -> Result<Vec<Pubkey>, ClientError> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                0,
+                vec![tag],
+            ))]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice: Some(UiDataSliceConfig {
+                    offset: 0,
+                    length: 0,
+                }),
+                commitment: Some(self.commitment()),
+                min_context_slot: None,
+            },
+            with_context: None,
+        };
+        Ok(self
+            .get_program_accounts_with_config(program_id, config)
+            .await?
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect())
+    }
+
+    async fn accounts(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        Ok(self
+            .get_multiple_accounts_with_commitment(addresses, self.commitment())
+            .await?
+            .value)
+    }
+}
+
+// attempts after the first wait backoff, multiplied by backoff_multiplier after each of them
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub backoff_multiplier: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(500),
+            backoff_multiplier: 2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // wait before attempt, which counts from 0, or None once they are used up
+    pub fn backoff_before(&self, attempt: u32) -> Option<Duration> {
+        match attempt {
+            0 => Some(Duration::ZERO),
+            _ if attempt >= self.max_attempts => None,
+            _ => Some(self.backoff * self.backoff_multiplier.saturating_pow(attempt - 1)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ContractClientConfig {
+    pub commitment: CommitmentConfig,
+    // of each RPC request
+    pub request_timeout: Duration,
+    // how long a sent transaction may stay unseen before it counts as expired
+    pub confirm_timeout: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl Default for ContractClientConfig {
+    fn default() -> Self {
+        ContractClientConfig {
+            commitment: CommitmentConfig::confirmed(),
+            request_timeout: Duration::from_secs(30),
+            confirm_timeout: Duration::from_secs(5),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ContractClientError {
+    // an instruction of the program failed with one of its own codes
+    #[error("contract error: {0}")]
+    Contract(ContractError),
+    // it failed with any other error, e.g. a token program's during a CPI
+    #[error("instruction {index} failed: {error}")]
+    Instruction { index: u8, error: InstructionError },
+    #[error("transaction expired {attempts} times")]
+    Expired { attempts: u32 },
+    #[error("{0}")]
+    Rpc(Box<ClientError>),
+    // an account the client read couldn't be decoded, or the token isn't supported
+    #[error("{0}")]
+    Program(#[from] ProgramError),
+}
+
+impl From<ClientError> for ContractClientError {
+    fn from(error: ClientError) -> Self {
+        ContractClientError::Rpc(Box::new(error))
+    }
+}
+
+// whether error means the transaction's blockhash expired before it was confirmed, so it is
+// worth signing again with a new one
+pub fn is_blockhash_expired(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::RpcError(RpcError::ForUser(message)) => {
+            message.starts_with("unable to confirm transaction")
+        }
+        _ => matches!(
+            error.get_transaction_error(),
+            Some(TransactionError::BlockhashNotFound)
+        ),
+    }
+}
+
+// the typed error of a failed transaction, custom codes of instructions calling program_id are
+// its ContractErrors
+pub fn decode_error(
+    program_id: &Pubkey,
+    transaction: &Transaction,
+    error: ClientError,
+) -> ContractClientError {
+    let Some(TransactionError::InstructionError(index, error)) = error.get_transaction_error()
+    else {
+        return error.into();
+    };
+    let program = transaction
+        .message
+        .instructions
+        .get(index as usize)
+        .and_then(|ix| {
+            transaction
+                .message
+                .account_keys
+                .get(ix.program_id_index as usize)
+        });
+    if let InstructionError::Custom(code) = error {
+        if program == Some(program_id) {
+            if let Some(error) = ContractError::from_u32(code) {
+                return ContractClientError::Contract(error);
+            }
+        }
+    }
+    ContractClientError::Instruction { index, error }
+}
+
+pub struct ContractClient<C = RpcClient> {
+    cluster: C,
+    program_id: Pubkey,
+    payer: Keypair,
+    config: ContractClientConfig,
+}
+
+impl ContractClient<RpcClient> {
+    pub fn new(
+        url: String,
+        program_id: Pubkey,
+        payer: Keypair,
+        config: ContractClientConfig,
+    ) -> Self {
+        let rpc = RpcClient::new_with_timeouts_and_commitment(
+            url,
+            config.request_timeout,
+            config.commitment,
+            config.confirm_timeout,
+        );
+        Self::with_cluster(rpc, program_id, payer, config)
+    }
+}
+
+impl<C: Cluster> ContractClient<C> {
+    pub fn with_cluster(
+        cluster: C,
+        program_id: Pubkey,
+        payer: Keypair,
+        config: ContractClientConfig,
+    ) -> Self {
+        ContractClient {
+            cluster,
+            program_id,
+            payer,
+            config,
+        }
+    }
+
+    pub fn cluster(&self) -> &C {
+        &self.cluster
+    }
+
+    pub fn payer(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+
+    // sign instructions as the payer and send them as one transaction, following the retry
+    // policy while it expires
+    pub async fn send(
+        &self,
+        instructions: &[Instruction],
+    ) -> Result<Signature, ContractClientError> {
+        let mut attempt = 0;
+        while let Some(backoff) = self.config.retry.backoff_before(attempt) {
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+            let transaction = Transaction::new_signed_with_payer(
+                instructions,
+                Some(&self.payer.pubkey()),
+                &[&self.payer],
+                self.cluster.latest_blockhash().await?,
+            );
+            match self.cluster.send_and_confirm(&transaction).await {
+                Ok(signature) => return Ok(signature),
+                Err(error) if is_blockhash_expired(&error) => continue,
+                Err(error) => return Err(decode_error(&self.program_id, &transaction, error)),
+            }
+        }
+        Err(ContractClientError::Expired { attempts: attempt })
+    }
+
+    pub async fn state(&self) -> Result<ContractState, ContractClientError> {
+        let account = self
+            .cluster
+            .account(&find_state_address(&self.program_id))
+            .await?
+            .ok_or(ProgramError::UninitializedAccount)?;
+        Ok(decode_state_account(&self.program_id, &account.data)?)
+    }
+
+    // spl_token or spl_token_2022, whichever owns the mint
+    async fn token_program(&self, token: &TokenType) -> Result<Pubkey, ContractClientError> {
+        if token.is_native() {
+            return Ok(spl_token::id());
+        }
+        let mint = self
+            .cluster
+            .account(&token.mint)
+            .await?
+            .ok_or(ProgramError::InvalidArgument)?;
+        Ok(mint.owner)
+    }
+
+    async fn for_token_program(
+        &self,
+        instruction: Instruction,
+        token: &TokenType,
+    ) -> Result<Instruction, ContractClientError> {
+        if self.token_program(token).await? == spl_token_2022::id() {
+            Ok(with_token_2022(instruction, &token.mint))
+        } else {
+            Ok(instruction)
+        }
+    }
+
+    // as the admin, creating the vault of an SPL token first
+    pub async fn add_token(
+        &self,
+        token: &TokenType,
+        metadata: &TokenMetadata,
+    ) -> Result<Signature, ContractClientError> {
+        let admin = self.payer();
+        let add = add_supported_token_ix(&self.program_id, &admin, token, metadata);
+        if token.is_native() {
+            return self.send(&[add]).await;
+        }
+        let (vault_authority, _) = find_vault_authority(&self.program_id, &token.mint);
+        let create_vault = create_associated_token_account_idempotent(
+            &admin,
+            &vault_authority,
+            &token.mint,
+            &self.token_program(token).await?,
+        );
+        let add = self.for_token_program(add, token).await?;
+        self.send(&[create_vault, add]).await
+    }
+
+    // from the payer's token account, the payer itself for native SOL
+    pub async fn deposit(
+        &self,
+        token: &TokenType,
+        user_token_account: &Pubkey,
+        amount: u64,
+    ) -> Result<Signature, ContractClientError> {
+        let state = self.state().await?;
+        let user = self.payer();
+        let entry = state
+            .all_token_balances
+            .get(token)
+            .ok_or(ContractClientError::Contract(
+                ContractError::TokenNotSupportedForDeposit,
+            ))?;
+        let nonce = self.nonce(&state, token, &user).await?;
+        let deposit = deposit_ix(
+            &self.program_id,
+            token,
+            &user,
+            user_token_account,
+            amount,
+            nonce,
+            None,
+            None,
+            None,
+            None,
+        );
+        let deposit = with_fee_balance(deposit, &state, token, &user, entry.deposit_fee_bps);
+        let deposit = self.for_token_program(deposit, token).await?;
+        self.send(&[deposit]).await
+    }
+
+    // the payer's balance to its recipient_token_account
+    pub async fn withdraw(
+        &self,
+        token: &TokenType,
+        recipient_token_account: &Pubkey,
+        amount: u64,
+    ) -> Result<Signature, ContractClientError> {
+        let state = self.state().await?;
+        let user = self.payer();
+        let entry = state
+            .all_token_balances
+            .get(token)
+            .ok_or(ContractClientError::Contract(
+                ContractError::TokenNotSupportedForWithdraw,
+            ))?;
+        let nonce = self.nonce(&state, token, &user).await?;
+        let withdraw = withdraw_ix(
+            &self.program_id,
+            token,
+            &user,
+            &user,
+            recipient_token_account,
+            amount,
+            nonce,
+            None,
+            None,
+            None,
+        );
+        let withdraw = with_fee_balance(withdraw, &state, token, &user, entry.withdrawal_fee_bps);
+        let withdraw = self.for_token_program(withdraw, token).await?;
+        self.send(&[withdraw]).await
+    }
+
+    // the nonce user's next deposit or withdrawal of token must carry, kept in its balance account
+    async fn nonce(
+        &self,
+        state: &ContractState,
+        token: &TokenType,
+        user: &Pubkey,
+    ) -> Result<u64, ContractClientError> {
+        let (address, _) = find_balance_address(&self.program_id, &token.mint, user);
+        let account = self.cluster.account(&address).await?;
+        let data = account
+            .as_ref()
+            .map_or(&[][..], |account| account.data.as_slice());
+        Ok(state.balance_nonce(user, data))
+    }
+
+    pub async fn get_balance(
+        &self,
+        token: &TokenType,
+        user: &Pubkey,
+    ) -> Result<u64, ContractClientError> {
+        let state = self.state().await?;
+        let (address, _) = find_balance_address(&self.program_id, &token.mint, user);
+        let account = self.cluster.account(&address).await?;
+        let data = account.as_ref().map(|account| account.data.as_slice());
+        Ok(decode_balance(&self.program_id, state, token, user, data)?)
+    }
+
+    // every user's balance of every listed token, see decode_balances
+    // the balance accounts are fetched MAX_MULTIPLE_ACCOUNTS at a time, one closed between the
+    // listing and its fetch is skipped
+    pub async fn export_balances(&self) -> Result<BalanceExport, ContractClientError> {
+        let state = self
+            .cluster
+            .account(&find_state_address(&self.program_id))
+            .await?
+            .ok_or(ProgramError::UninitializedAccount)?;
+        let keys = self
+            .cluster
+            .program_account_keys(&self.program_id, BALANCE_ACCOUNT_TAG)
+            .await?;
+        let mut balance_accounts = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let accounts = self.cluster.accounts(chunk).await?;
+            balance_accounts.extend(
+                chunk
+                    .iter()
+                    .zip(accounts)
+                    .filter_map(|(address, account)| Some((*address, account?.data))),
+            );
+        }
+        Ok(decode_balances(
+            &self.program_id,
+            &state.data,
+            &balance_accounts,
+        )?)
+    }
+}
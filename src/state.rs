@@ -0,0 +1,1589 @@
+// account layouts: the state PDA, the per-user balance PDAs and the PDAs clients derive
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    hash::hashv,
+    keccak, msg,
+    program_error::ProgramError,
+    pubkey::{Pubkey, PUBKEY_BYTES},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+
+use crate::error::ContractError;
+
+// tokens are identified by mint, symbol is only a display label
+#[derive(Clone, Serialize, Deserialize, BorshSerialize, BorshDeserialize, Debug)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct TokenType {
+    pub symbol: String,
+    pub mint: Pubkey,
+}
+
+impl PartialEq for TokenType {
+    fn eq(&self, other: &Self) -> bool {
+        self.mint == other.mint
+    }
+}
+
+impl Eq for TokenType {}
+
+impl std::hash::Hash for TokenType {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.mint.hash(state);
+    }
+}
+
+impl PartialOrd for TokenType {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TokenType {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.mint.cmp(&other.mint)
+    }
+}
+
+// symbol of native SOL, no SPL token may use it
+pub const NATIVE_SOL_SYMBOL: &str = "SOL";
+
+impl TokenType {
+    // reserved token for native SOL, keyed by the native mint, its balances are lamports held
+    // directly by its vault PDA
+    pub fn native() -> Self {
+        TokenType {
+            symbol: NATIVE_SOL_SYMBOL.to_string(),
+            mint: spl_token::native_mint::id(),
+        }
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.mint == spl_token::native_mint::id()
+    }
+
+    // encoded with a symbol of MAX_SYMBOL_LEN bytes
+    pub const MAX_LEN: usize = 4 + MAX_SYMBOL_LEN + PUBKEY_BYTES;
+
+    pub fn serialized_size(&self) -> usize {
+        4 + self.symbol.len() + PUBKEY_BYTES
+    }
+}
+
+// display data of a supported token for UIs, the program itself never reads it
+#[derive(
+    Clone, Default, Serialize, Deserialize, BorshSerialize, BorshDeserialize, PartialEq, Debug,
+)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct TokenMetadata {
+    // at most MAX_TOKEN_NAME_LEN bytes
+    pub name: String,
+    // at most MAX_DECIMALS and the mint's own, native SOL has 9
+    pub decimals: u8,
+    // at most MAX_METADATA_URI_LEN bytes
+    pub metadata_uri: Option<String>,
+}
+
+impl TokenMetadata {
+    // encoded with the longest name and URI
+    pub const MAX_LEN: usize = 4 + MAX_TOKEN_NAME_LEN + 1 + 1 + 4 + MAX_METADATA_URI_LEN;
+
+    pub fn serialized_size(&self) -> usize {
+        4 + self.name.len() + 1 + 1 + self.metadata_uri.as_ref().map_or(0, |uri| 4 + uri.len())
+    }
+}
+
+// registry record of a supported token
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct TokenEntry {
+    pub deposits_enabled: bool,
+    pub withdrawals_enabled: bool,
+    // None means unlimited
+    pub deposit_cap: Option<u64>,
+    // sum of all balances and the unclaimed part of the token's airdrop, checked against
+    // deposit_cap
+    pub total_deposited: u64,
+    // ceiling on any single user's balance, None means unlimited
+    pub max_user_balance: Option<u64>,
+    // smallest amount a single deposit may have, withdrawals are not limited
+    pub min_deposit: u64,
+    // most a user may withdraw per WITHDRAWAL_WINDOW_SECS, 0 means no limit
+    pub daily_withdrawal_limit: u64,
+    // user -> (window_start_unix, withdrawn_in_window), only tracked while a limit is set
+    pub withdrawal_windows: BTreeMap<Pubkey, (i64, u64)>,
+    // share of every withdrawal credited to the treasury, at most MAX_FEE_BPS
+    pub withdrawal_fee_bps: u16,
+    // share of every deposit credited to the treasury, at most MAX_FEE_BPS
+    pub deposit_fee_bps: u16,
+    // at most one pending request per user, its amount is no longer in balances
+    pub withdraw_requests: BTreeMap<Pubkey, WithdrawRequest>,
+    // users that can still receive but not move their balance out
+    pub frozen: BTreeSet<Pubkey>,
+    // only filled in while an instruction runs, balances live in their own balance accounts
+    // the state account keeps the entries of older layouts until they are next touched
+    pub balances: BTreeMap<Pubkey, u64>,
+}
+
+// first phase of a timelocked withdrawal
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct WithdrawRequest {
+    pub amount: u64,
+    pub requested_slot: u64,
+    pub requested_at: i64,
+}
+
+impl WithdrawRequest {
+    pub const LEN: usize = 8 + 8 + 8;
+}
+
+// loyalty points paid on one token's balances
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct RewardPool {
+    // points per whole token held for a day
+    pub points_per_day: u64,
+    // points_per_day integrated over the seconds since the pool was created, a holder is
+    // owed balance * (index - their index) / (10^decimals * 86400)
+    pub index: u128,
+    // unix time index was brought up to
+    pub updated_at: i64,
+}
+
+impl RewardPool {
+    pub const LEN: usize = 8 + 16 + 8;
+}
+
+// a user's deposits, withdrawals and transfers counted since start_slot, the fixed windows of
+// states before v32, see rate_buckets
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct RateWindow {
+    pub start_slot: u64,
+    pub count: u32,
+}
+
+impl RateWindow {
+    pub const LEN: usize = 8 + 4;
+}
+
+// a user's deposits, withdrawals and transfers counted in the bucket of rate_limit_window_slots
+// slots starting at bucket_start and in the bucket before it, see AdminSetRateLimit
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct RateBuckets {
+    pub bucket_start: u64,
+    pub count: u32,
+    pub previous_count: u32,
+}
+
+impl RateBuckets {
+    pub const LEN: usize = 8 + 4 + 4;
+}
+
+// price account deposits of a token are valued with and their USD cap, see AdminSetPriceFeed
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct PriceFeed {
+    pub feed: Pubkey,
+    // whole US dollars total_deposited may be worth at most
+    pub usd_cap: u64,
+    // a price published more slots ago than this is refused
+    pub max_age_slots: u64,
+}
+
+impl PriceFeed {
+    pub const LEN: usize = PUBKEY_BYTES + 8 + 8;
+}
+
+// amount_in of one token buys amount_in * numerator / denominator of another, see UserSwap
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct SwapRate {
+    pub numerator: u64,
+    // never 0
+    pub denominator: u64,
+}
+
+impl SwapRate {
+    pub const LEN: usize = 8 + 8;
+}
+
+// a token's withdrawals disable themselves once more than threshold_bps of total_deposited,
+// counting what left since, is withdrawn within window_slots, see AdminSetCircuitBreaker
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct CircuitBreaker {
+    pub threshold_bps: u16,
+    pub window_slots: u64,
+    // the current window starts with the first withdrawal after the previous one expired
+    pub window_start_slot: u64,
+    // paid out of the vault within the window
+    pub withdrawn: u64,
+    // withdrawals stay disabled until AdminResetBreaker
+    pub tripped: bool,
+}
+
+impl CircuitBreaker {
+    pub const LEN: usize = 2 + 8 + 8 + 8 + 1;
+}
+
+// how active a user has been with one token since its balance was first credited or last
+// closed, also the return data of GetUserStats
+#[derive(Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct UserStats {
+    pub deposit_count: u32,
+    pub withdraw_count: u32,
+    // sums of the deposits' and withdrawals' amounts, fees included
+    pub lifetime_deposited: u128,
+    pub lifetime_withdrawn: u128,
+}
+
+impl UserStats {
+    pub const LEN: usize = 4 + 4 + 16 + 16;
+}
+
+// slots a user's balance of one token last took a deposit and a withdrawal in, 0 for never
+#[derive(Clone, Copy, Default, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct BalanceSlots {
+    pub last_deposit_slot: u64,
+    pub last_withdraw_slot: u64,
+}
+
+impl BalanceSlots {
+    pub const LEN: usize = 8 + 8;
+}
+
+// record of one deposit, kept as it was made, also the return data of GetReceipt
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct DepositReceipt {
+    pub receipt_id: u64,
+    pub mint: Pubkey,
+    pub user: Pubkey,
+    // as deposited, before fees
+    pub amount: u64,
+    pub slot: u64,
+}
+
+impl DepositReceipt {
+    pub const LEN: usize = 8 + PUBKEY_BYTES + PUBKEY_BYTES + 8 + 8;
+}
+
+// most receipts kept per user, a deposit past it evicts the oldest
+pub const MAX_RECEIPTS_PER_USER: usize = 256;
+
+// start of the [RECEIPTS_SEED, user] PDA, followed by up to MAX_RECEIPTS_PER_USER borsh
+// DepositReceipts, a ring that is written from the start and, once full, over the oldest
+// receipts are kept in their own account rather than the state, which every instruction loads
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq, Debug)]
+pub struct ReceiptsHeader {
+    pub tag: u8,
+    pub user: Pubkey,
+    pub bump: u8,
+    // see next()
+    pub next: [u8; 2],
+}
+
+impl ReceiptsHeader {
+    // the header at the start of data, which has to carry RECEIPTS_ACCOUNT_TAG
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        let data = data
+            .get_mut(..RECEIPTS_HEADER_LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let header: &mut Self = bytemuck::from_bytes_mut(data);
+        if header.tag != RECEIPTS_ACCOUNT_TAG {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(header)
+    }
+
+    // index the next receipt is written at, the oldest one once the ring is full
+    pub fn next(&self) -> usize {
+        u16::from_le_bytes(self.next) as usize
+    }
+
+    pub fn set_next(&mut self, next: usize) {
+        self.next = (next as u16).to_le_bytes();
+    }
+}
+
+// the receipts of a receipts account's data, oldest first
+pub fn read_receipts(data: &[u8]) -> Result<Vec<DepositReceipt>, ProgramError> {
+    let header = data
+        .get(..RECEIPTS_HEADER_LEN)
+        .map(bytemuck::from_bytes::<ReceiptsHeader>)
+        .filter(|header| header.tag == RECEIPTS_ACCOUNT_TAG)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    let mut receipts = data[RECEIPTS_HEADER_LEN..]
+        .chunks_exact(DepositReceipt::LEN)
+        .map(DepositReceipt::try_from_slice)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    if receipts.len() == MAX_RECEIPTS_PER_USER {
+        receipts.rotate_left(header.next());
+    }
+    Ok(receipts)
+}
+
+// a withdrawal waiting in its token's queue for ProcessWithdrawQueue, the balance isn't
+// reserved for it and is only checked when it runs
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct QueuedWithdraw {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub queued_slot: u64,
+}
+
+impl QueuedWithdraw {
+    pub const LEN: usize = PUBKEY_BYTES + 8 + 8;
+}
+
+// most withdrawals one token's queue holds, a user has at most one in it
+pub const MAX_WITHDRAW_QUEUE_LEN: usize = 16;
+
+// a user can't withdraw a token within withdraw_cooldown_slots of their last deposit of it
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct WithdrawCooldown {
+    pub withdraw_cooldown_slots: u64,
+    // whether a transfer received starts the cooldown over like a deposit
+    pub transfers_reset: bool,
+}
+
+impl WithdrawCooldown {
+    pub const LEN: usize = 8 + 1;
+}
+
+// an action AdminQueueAction queued, see AdminExecuteAction
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct QueuedAction {
+    pub queued_slot: u64,
+    // first slot it can run in
+    pub eta_slot: u64,
+}
+
+impl QueuedAction {
+    pub const LEN: usize = 8 + 8;
+
+    // past the grace period after its eta it can no longer run
+    pub fn expired(&self, slot: u64) -> bool {
+        slot > self.eta_slot.saturating_add(TIMELOCK_GRACE_SLOTS)
+    }
+}
+
+// part of a balance deposited with a lock, not withdrawable before unlock_at
+#[derive(Clone, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct LockedLot {
+    pub amount: u64,
+    // unix time the lot becomes liquid at
+    pub unlock_at: i64,
+}
+
+impl LockedLot {
+    pub const LEN: usize = 8 + 8;
+}
+
+// most lots a user can have locked in one token at a time
+pub const MAX_LOCKED_LOTS: usize = 8;
+
+// part of a balance deposited by the admin that vests linearly over
+// [start_ts, start_ts + duration_secs]
+#[derive(Clone, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct VestingSchedule {
+    pub amount: u64,
+    pub start_ts: i64,
+    pub duration_secs: u64,
+}
+
+// most schedules a user can have vesting in one token at a time
+pub const MAX_VESTING_SCHEDULES: usize = 8;
+
+impl VestingSchedule {
+    pub const LEN: usize = 8 + 8 + 8;
+
+    // the part not vested at now, the vested part is rounded down so this one is rounded up
+    pub fn unvested(&self, now: i64) -> u64 {
+        if now <= self.start_ts {
+            return self.amount;
+        }
+        let elapsed = now.abs_diff(self.start_ts);
+        if elapsed >= self.duration_secs {
+            return 0;
+        }
+        // below amount, elapsed is below duration_secs
+        let vested = u128::from(self.amount) * u128::from(elapsed) / u128::from(self.duration_secs);
+        self.amount - vested as u64
+    }
+}
+
+// one successful state-changing instruction, every field fixed width
+#[derive(Clone, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct AuditEntry {
+    // the state's sequence the instruction took
+    pub sequence: u64,
+    // first byte of the instruction's borsh encoding, see ContractInstruction::tag
+    pub instruction_tag: u8,
+    // accounts[1] of the instruction, its signer
+    pub actor: Pubkey,
+    // token_symbol_hash of the token it was about, 0 if none
+    pub token_symbol_hash: u32,
+    // amount it moved or set, 0 if none
+    pub amount: u64,
+    pub slot: u64,
+}
+
+impl AuditEntry {
+    pub const LEN: usize = 8 + 1 + PUBKEY_BYTES + 4 + 8 + 8;
+}
+
+// merkle airdrop of one token, funded in full when its root was set
+#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct Airdrop {
+    // root of the tree over airdrop_leaf of every (user, amount) to credit
+    pub root: [u8; 32],
+    pub total: u64,
+    // sum of the amounts claimed, never more than total
+    pub claimed: u64,
+    // airdrop_leaf of every claim made, a leaf is credited once
+    pub claimed_leaves: BTreeSet<[u8; 32]>,
+}
+
+impl Airdrop {
+    // encoded without claimed leaves
+    pub const BASE_LEN: usize = 32 + 8 + 8 + 4;
+
+    pub fn serialized_size(&self) -> usize {
+        Self::BASE_LEN + self.claimed_leaves.len() * 32
+    }
+
+    // what the vault still holds for claims
+    pub fn unclaimed(&self) -> u64 {
+        self.total - self.claimed
+    }
+}
+
+// leaf of user's amount in an airdrop tree, sha256(user || amount as u64 LE)
+pub fn airdrop_leaf(user: &Pubkey, amount: u64) -> [u8; 32] {
+    hashv(&[user.as_ref(), &amount.to_le_bytes()]).to_bytes()
+}
+
+// parent of two nodes of an airdrop tree, sha256 of the smaller one followed by the other, so
+// proofs carry no sides, public so clients can build the tree
+pub fn merkle_parent(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (left, right) = if a <= b { (a, b) } else { (b, a) };
+    hashv(&[left, right]).to_bytes()
+}
+
+// whether proof, the siblings from leaf up, leads from leaf to root
+pub fn verify_merkle_proof(leaf: [u8; 32], proof: &[[u8; 32]], root: &[u8; 32]) -> bool {
+    proof
+        .iter()
+        .fold(leaf, |node, sibling| merkle_parent(&node, sibling))
+        == *root
+}
+
+// shares amount of a share-mode token buys out of total_assets (its total_deposited) and the
+// total_shares in balances, rounded down, the first deposit gets one share per unit
+// None if they don't fit a u64, or total_assets is 0 while there are shares
+pub fn shares_for_amount(amount: u64, total_shares: u64, total_assets: u64) -> Option<u64> {
+    if total_shares == 0 {
+        return Some(amount);
+    }
+    let shares = u128::from(amount) * u128::from(total_shares);
+    u64::try_from(shares.checked_div(u128::from(total_assets))?).ok()
+}
+
+// what shares of a share-mode token are worth, rounded down, public so clients can show the
+// balances of one, None if total_shares is 0
+pub fn amount_for_shares(shares: u64, total_shares: u64, total_assets: u64) -> Option<u64> {
+    let amount = u128::from(shares) * u128::from(total_assets);
+    u64::try_from(amount.checked_div(u128::from(total_shares))?).ok()
+}
+
+// entries kept in the audit log, the oldest is dropped for a new one once it's full
+// the log is length-prefixed, so a log kept under a smaller capacity loads as it is and grows
+pub const AUDIT_LOG_CAPACITY: usize = 128;
+
+// first 4 bytes of keccak256 of the canonical symbol, little-endian
+pub fn token_symbol_hash(symbol: &str) -> u32 {
+    let hash = keccak::hash(canonical_symbol(symbol).as_bytes()).0;
+    u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]])
+}
+
+impl TokenEntry {
+    pub(crate) fn new() -> Self {
+        TokenEntry {
+            deposits_enabled: true,
+            withdrawals_enabled: true,
+            deposit_cap: None,
+            total_deposited: 0,
+            max_user_balance: None,
+            min_deposit: 0,
+            daily_withdrawal_limit: 0,
+            withdrawal_windows: BTreeMap::new(),
+            withdrawal_fee_bps: 0,
+            deposit_fee_bps: 0,
+            withdraw_requests: BTreeMap::new(),
+            frozen: BTreeSet::new(),
+            balances: BTreeMap::new(),
+        }
+    }
+
+    // encoded with deposit_cap and max_user_balance set and no user in any of its maps
+    pub const BASE_LEN: usize = 1 + 1 + 9 + 8 + 9 + 8 + 8 + 4 + 2 + 2 + 4 + 4 + 4;
+
+    pub fn serialized_size(&self) -> usize {
+        let unset_caps = [self.deposit_cap, self.max_user_balance]
+            .iter()
+            .filter(|cap| cap.is_none())
+            .count();
+        Self::BASE_LEN - unset_caps * 8
+            + self.withdrawal_windows.len() * (PUBKEY_BYTES + 8 + 8)
+            + self.withdraw_requests.len() * (PUBKEY_BYTES + WithdrawRequest::LEN)
+            + self.frozen.len() * PUBKEY_BYTES
+            + self.balances.len() * (PUBKEY_BYTES + 8)
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct ContractState {
+    pub all_token_balances: BTreeMap<TokenType, TokenEntry>,
+    // set once by InitializeConfig, None until then
+    pub admin: Option<Pubkey>,
+    // proposed by the admin, becomes admin once it signs AcceptAdmin
+    pub pending_admin: Option<Pubkey>,
+    // day-to-day key allowed the Role::Operator subset of admin instructions
+    pub operator: Option<Pubkey>,
+    // emergency stop of user deposits and withdrawals
+    pub paused: bool,
+    // while paused, still let users withdraw
+    pub withdrawals_only: bool,
+    // cap on supported tokens, the whole registry has to fit into the state account
+    pub max_tokens: u32,
+    // slots between UserRequestWithdraw and UserClaimWithdraw
+    pub withdraw_delay_slots: u64,
+    // fees are credited to this key's balance of the token, it pays no fees itself
+    pub treasury: Option<Pubkey>,
+    // users paying neither deposit nor withdrawal fees
+    pub fee_exempt: BTreeSet<Pubkey>,
+    // (token, owner, spender) -> amount spender may still move out of owner's balance
+    pub allowances: BTreeMap<(TokenType, Pubkey, Pubkey), u64>,
+    // user -> key that may withdraw to the user's own token accounts
+    pub withdraw_delegates: BTreeMap<Pubkey, Pubkey>,
+    // users locked out of every user instruction, their balances stay untouched
+    pub blocked: BTreeSet<Pubkey>,
+    // user -> next nonce of the user's UserDeposit/UserWithdraw from before nonces moved into
+    // balance accounts, only read since, see first_nonce
+    pub nonces: BTreeMap<Pubkey, u64>,
+    // Ethereum address -> user it may authorize withdrawals for
+    pub eth_links: BTreeMap<[u8; 20], Pubkey>,
+    // number of the last successful state-changing instruction, carried by its events
+    pub sequence: u64,
+    // mint -> sequence of the AdminAddSupportedToken that listed it, balance accounts written
+    // under another epoch belong to an earlier, deleted listing of the mint
+    pub token_epochs: BTreeMap<Pubkey, u64>,
+    // mint -> number of users holding a nonzero balance of it
+    pub holder_counts: BTreeMap<Pubkey, u64>,
+    // mint -> metadata it was listed or last updated with
+    pub token_metadata: BTreeMap<Pubkey, TokenMetadata>,
+    // mint -> decimals shown by UIs, deposits must be whole multiples of the smallest amount
+    // shown, 10^(decimals - display_precision), absent means no rule
+    pub display_precisions: BTreeMap<Pubkey, u8>,
+    // mint -> its reward pool, only for tokens an operator set a reward rate for
+    pub reward_pools: BTreeMap<Pubkey, RewardPool>,
+    // user -> points accrued and not claimed yet
+    pub reward_points: BTreeMap<Pubkey, u64>,
+    // (mint, user) -> pool index the user's points are accrued up to, only kept for nonzero
+    // balances, an absent one is 0, which covers balances older than the pool
+    pub reward_indexes: BTreeMap<(Pubkey, Pubkey), u128>,
+    // (mint, user) -> locked parts of the user's balance, the balance still includes them, a
+    // lot is dropped once it unlocks
+    pub locked_lots: BTreeMap<(Pubkey, Pubkey), Vec<LockedLot>>,
+    // (mint, user) -> vesting parts of the user's balance, the balance still includes them, a
+    // schedule is dropped once it fully vested
+    pub vesting_schedules: BTreeMap<(Pubkey, Pubkey), Vec<VestingSchedule>>,
+    // user -> referrer named by the user's first deposit, None if it named none, never changes
+    pub referrers: BTreeMap<Pubkey, Option<Pubkey>>,
+    // referrer -> sum of the amounts its referees deposited since they were bound
+    pub referral_volume: BTreeMap<Pubkey, u64>,
+    // the last AUDIT_LOG_CAPACITY successful state-changing instructions, oldest first
+    pub audit_log: VecDeque<AuditEntry>,
+    // mint -> its latest airdrop, see AdminSetMerkleRoot
+    pub airdrops: BTreeMap<Pubkey, Airdrop>,
+    // voucher_id -> expiry of every redeemed voucher, see RedeemVoucher, dropped once expired
+    // as the voucher can't be redeemed anymore
+    pub redeemed_vouchers: BTreeMap<u64, i64>,
+    // most deposits, withdrawals and transfers a user makes per rate_limit_window_slots, 0 for
+    // no limit, the admin and the treasury have none
+    pub rate_limit_ops: u32,
+    pub rate_limit_window_slots: u64,
+    // user -> its window from before v32, only the ones still running are kept and they count
+    // into the user's rate_buckets on their next operation
+    pub rate_windows: BTreeMap<Pubkey, RateWindow>,
+    // sum of every listed token's total_deposited
+    pub global_total_deposited: u128,
+    // deposits taking global_total_deposited above it fail, u128::MAX for none
+    pub global_deposit_cap: u128,
+    // mint -> its price feed, tokens without one only have their unit caps
+    pub price_feeds: BTreeMap<Pubkey, PriceFeed>,
+    // (from mint, to mint) -> rate users swap at, one direction each
+    pub swap_rates: BTreeMap<(Pubkey, Pubkey), SwapRate>,
+    // mint -> total_shares of a token in share mode, whose balances are shares of its
+    // total_deposited, see AdminEnableShareMode
+    pub share_supplies: BTreeMap<Pubkey, u64>,
+    // slots an action has to wait between AdminQueueAction and AdminExecuteAction, at least
+    // MIN_TIMELOCK_DELAY_SLOTS
+    pub timelock_delay_slots: u64,
+    // sha256 of a queued action's borsh encoding -> when it was queued and can run
+    pub queued_actions: BTreeMap<[u8; 32], QueuedAction>,
+    // mint -> its withdrawal circuit breaker, tokens without one have none
+    pub circuit_breakers: BTreeMap<Pubkey, CircuitBreaker>,
+    // mint -> balance a withdrawal may leave behind at most before the rest is swept to the
+    // treasury, tokens without one keep any balance, see AdminSetDustThreshold
+    pub dust_thresholds: BTreeMap<Pubkey, u64>,
+    // (mint, user) -> user's activity with the token, reset by CloseUserBalance, kept in the
+    // balance account, this only holds the ones of balances still inline and, while an
+    // instruction runs, the ones of the balance accounts passed
+    pub user_stats: BTreeMap<(Pubkey, Pubkey), UserStats>,
+    // (mint, user) -> when the balance last changed, dropped with it by CloseUserBalance, kept
+    // like user_stats
+    pub balance_slots: BTreeMap<(Pubkey, Pubkey), BalanceSlots>,
+    // mint -> its withdrawal cooldown, tokens without one have none
+    pub withdraw_cooldowns: BTreeMap<Pubkey, WithdrawCooldown>,
+    // mint -> smallest withdrawal of the token short of a whole balance, see AdminSetMinWithdraw
+    pub min_withdrawals: BTreeMap<Pubkey, u64>,
+    // id of the next deposit's receipt, ids are never reused
+    pub next_receipt_id: u64,
+    // user -> receipts of their deposits of any token from before receipts moved into receipts
+    // accounts, oldest first, only read now, and while an instruction runs the ones it issued
+    pub receipts: BTreeMap<Pubkey, Vec<DepositReceipt>>,
+    // mint -> queued withdrawals of the token, first in first out, an empty queue is dropped
+    pub withdraw_queues: BTreeMap<Pubkey, VecDeque<QueuedWithdraw>>,
+    // highest nonce a closed balance account was at, see first_nonce
+    pub nonce_floor: u64,
+    // user -> its operations in the current and the previous bucket, dropped once neither is
+    // inside the window anymore
+    pub rate_buckets: BTreeMap<Pubkey, RateBuckets>,
+}
+
+impl Default for ContractState {
+    fn default() -> Self {
+        ContractState {
+            all_token_balances: BTreeMap::new(),
+            admin: None,
+            pending_admin: None,
+            operator: None,
+            paused: false,
+            withdrawals_only: false,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            withdraw_delay_slots: DEFAULT_WITHDRAW_DELAY_SLOTS,
+            treasury: None,
+            fee_exempt: BTreeSet::new(),
+            allowances: BTreeMap::new(),
+            withdraw_delegates: BTreeMap::new(),
+            blocked: BTreeSet::new(),
+            nonces: BTreeMap::new(),
+            eth_links: BTreeMap::new(),
+            sequence: 0,
+            token_epochs: BTreeMap::new(),
+            holder_counts: BTreeMap::new(),
+            token_metadata: BTreeMap::new(),
+            display_precisions: BTreeMap::new(),
+            reward_pools: BTreeMap::new(),
+            reward_points: BTreeMap::new(),
+            reward_indexes: BTreeMap::new(),
+            locked_lots: BTreeMap::new(),
+            vesting_schedules: BTreeMap::new(),
+            referrers: BTreeMap::new(),
+            referral_volume: BTreeMap::new(),
+            audit_log: VecDeque::new(),
+            airdrops: BTreeMap::new(),
+            redeemed_vouchers: BTreeMap::new(),
+            rate_limit_ops: DEFAULT_RATE_LIMIT_OPS,
+            rate_limit_window_slots: DEFAULT_RATE_LIMIT_WINDOW_SLOTS,
+            rate_windows: BTreeMap::new(),
+            global_total_deposited: 0,
+            global_deposit_cap: u128::MAX,
+            price_feeds: BTreeMap::new(),
+            swap_rates: BTreeMap::new(),
+            share_supplies: BTreeMap::new(),
+            timelock_delay_slots: DEFAULT_TIMELOCK_DELAY_SLOTS,
+            queued_actions: BTreeMap::new(),
+            circuit_breakers: BTreeMap::new(),
+            dust_thresholds: BTreeMap::new(),
+            user_stats: BTreeMap::new(),
+            balance_slots: BTreeMap::new(),
+            withdraw_cooldowns: BTreeMap::new(),
+            min_withdrawals: BTreeMap::new(),
+            next_receipt_id: 0,
+            receipts: BTreeMap::new(),
+            withdraw_queues: BTreeMap::new(),
+            nonce_floor: 0,
+            rate_buckets: BTreeMap::new(),
+        }
+    }
+}
+
+impl ContractState {
+    // encoded with admin, pending_admin, operator and treasury set and every collection empty
+    pub const BASE_LEN: usize = 4
+        + 3 * (1 + PUBKEY_BYTES)
+        + 1
+        + 1
+        + 4
+        + 8
+        + (1 + PUBKEY_BYTES)
+        + 6 * 4
+        + 8
+        + 4
+        + 8
+        + 27 * 4
+        + 2 * 16
+        + 8
+        + 8
+        + 4
+        + 8;
+
+    // what borsh::to_vec(self) would return the length of, without encoding it
+    pub fn serialized_size(&self) -> usize {
+        let option_len = |key: &Option<Pubkey>| 1 + key.map_or(0, |_| PUBKEY_BYTES);
+        let pair = 2 * PUBKEY_BYTES;
+        Self::BASE_LEN
+            + self
+                .all_token_balances
+                .iter()
+                .map(|(token, entry)| token.serialized_size() + entry.serialized_size())
+                .sum::<usize>()
+            - [self.admin, self.pending_admin, self.operator, self.treasury]
+                .iter()
+                .filter(|key| key.is_none())
+                .count()
+                * PUBKEY_BYTES
+            + self.fee_exempt.len() * PUBKEY_BYTES
+            + self
+                .allowances
+                .keys()
+                .map(|(token, _, _)| token.serialized_size() + pair + 8)
+                .sum::<usize>()
+            + self.withdraw_delegates.len() * pair
+            + self.blocked.len() * PUBKEY_BYTES
+            + self.nonces.len() * (PUBKEY_BYTES + 8)
+            + self.eth_links.len() * (20 + PUBKEY_BYTES)
+            + self.token_epochs.len() * (PUBKEY_BYTES + 8)
+            + self.holder_counts.len() * (PUBKEY_BYTES + 8)
+            + self
+                .token_metadata
+                .values()
+                .map(|metadata| PUBKEY_BYTES + metadata.serialized_size())
+                .sum::<usize>()
+            + self.display_precisions.len() * (PUBKEY_BYTES + 1)
+            + self.reward_pools.len() * (PUBKEY_BYTES + RewardPool::LEN)
+            + self.reward_points.len() * (PUBKEY_BYTES + 8)
+            + self.reward_indexes.len() * (pair + 16)
+            + self
+                .locked_lots
+                .values()
+                .map(|lots| pair + 4 + lots.len() * LockedLot::LEN)
+                .sum::<usize>()
+            + self
+                .vesting_schedules
+                .values()
+                .map(|schedules| pair + 4 + schedules.len() * VestingSchedule::LEN)
+                .sum::<usize>()
+            + self
+                .referrers
+                .values()
+                .map(|referrer| PUBKEY_BYTES + option_len(referrer))
+                .sum::<usize>()
+            + self.referral_volume.len() * (PUBKEY_BYTES + 8)
+            + self.audit_log.len() * AuditEntry::LEN
+            + self
+                .airdrops
+                .values()
+                .map(|airdrop| PUBKEY_BYTES + airdrop.serialized_size())
+                .sum::<usize>()
+            + self.redeemed_vouchers.len() * (8 + 8)
+            + self.rate_windows.len() * (PUBKEY_BYTES + RateWindow::LEN)
+            + self.price_feeds.len() * (PUBKEY_BYTES + PriceFeed::LEN)
+            + self.swap_rates.len() * (pair + SwapRate::LEN)
+            + self.share_supplies.len() * (PUBKEY_BYTES + 8)
+            + self.queued_actions.len() * (32 + QueuedAction::LEN)
+            + self.circuit_breakers.len() * (PUBKEY_BYTES + CircuitBreaker::LEN)
+            + self.dust_thresholds.len() * (PUBKEY_BYTES + 8)
+            + self.user_stats.len() * (pair + UserStats::LEN)
+            + self.balance_slots.len() * (pair + BalanceSlots::LEN)
+            + self.withdraw_cooldowns.len() * (PUBKEY_BYTES + WithdrawCooldown::LEN)
+            + self.min_withdrawals.len() * (PUBKEY_BYTES + 8)
+            + self
+                .receipts
+                .values()
+                .map(|receipts| PUBKEY_BYTES + 4 + receipts.len() * DepositReceipt::LEN)
+                .sum::<usize>()
+            + self
+                .withdraw_queues
+                .values()
+                .map(|queue| PUBKEY_BYTES + 4 + queue.len() * QueuedWithdraw::LEN)
+                .sum::<usize>()
+            + self.rate_buckets.len() * (PUBKEY_BYTES + RateBuckets::LEN)
+    }
+
+    // the nonce a balance account of user starts at, past every nonce user signed before nonces
+    // moved into balance accounts and every nonce of a closed balance account, so a balance
+    // account created again at the same address can't take an old payload
+    pub fn first_nonce(&self, user: &Pubkey) -> u64 {
+        let legacy = self.nonces.get(user).copied().unwrap_or(0);
+        legacy.max(self.nonce_floor)
+    }
+
+    // the nonce the next UserDeposit or UserWithdraw of user on the balance account holding data
+    // must carry, data is empty if the account doesn't exist
+    pub fn balance_nonce(&self, user: &Pubkey, data: &[u8]) -> u64 {
+        match BalanceAccountData::read(data) {
+            Ok(balance) if data.len() >= BALANCE_ACCOUNT_V1_SIZE => balance.nonce(),
+            // not created yet, or of the layout before balance accounts had a nonce
+            _ => self.first_nonce(user),
+        }
+    }
+}
+
+// state bytes of one listed token at most: its registry entry with the longest symbol and both
+// caps set, its epoch, holder count, longest metadata, display precision and reward pool
+const TOKEN_STATE_LEN: usize = TokenType::MAX_LEN
+    + TokenEntry::BASE_LEN
+    + 2 * (PUBKEY_BYTES + 8)
+    + PUBKEY_BYTES
+    + TokenMetadata::MAX_LEN
+    + PUBKEY_BYTES
+    + 1
+    + PUBKEY_BYTES
+    + RewardPool::LEN;
+// state bytes of a typical user: its referrer, referral volume and reward points
+const USER_STATE_LEN: usize = 2 * (PUBKEY_BYTES + 8) + PUBKEY_BYTES + 1 + PUBKEY_BYTES;
+
+// data length a state account needs for max_tokens tokens and about max_users_hint users,
+// with a full audit log, public so clients and the CLI can size the account up front
+// users' locks, vesting schedules, allowances, pending requests and per-token records, and
+// frozen, blocked or fee-exempt users aren't counted, leave room for the ones expected
+pub fn required_account_size(max_tokens: usize, max_users_hint: usize) -> usize {
+    STATE_HEADER_LEN
+        + ContractState::BASE_LEN
+        + AUDIT_LOG_CAPACITY * AuditEntry::LEN
+        + max_tokens * TOKEN_STATE_LEN
+        + max_users_hint * USER_STATE_LEN
+}
+
+// contents of the [BALANCE_SEED, mint, user] PDA holding user's balance of one token
+#[derive(Default, BorshSerialize, BorshDeserialize, PartialEq, Debug)]
+pub struct BalanceAccount {
+    pub mint: Pubkey,
+    pub user: Pubkey,
+    pub bump: u8,
+    // token_epochs entry of mint when last written
+    pub epoch: u64,
+    pub amount: u64,
+    // no flag is defined yet, always 0
+    pub flags: u8,
+    // slot of the last instruction that wrote the account
+    pub last_update_slot: u64,
+    // the nonce user's next UserDeposit or UserWithdraw of mint must carry
+    pub nonce: u64,
+    // user's activity with mint since the account was created
+    pub stats: UserStats,
+    pub slots: BalanceSlots,
+}
+
+impl BalanceAccount {
+    pub const LEN: usize =
+        PUBKEY_BYTES + PUBKEY_BYTES + 1 + 8 + 8 + 1 + 8 + 8 + UserStats::LEN + BalanceSlots::LEN;
+}
+
+// a balance account's data as it lies in the account, the tag then BalanceAccount's fields at
+// fixed offsets, the same bytes borsh writes, so the program can read and change a balance in
+// place instead of decoding and encoding it
+// the u64s are little-endian byte arrays, which leaves no padding and any alignment works
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq, Debug)]
+pub struct BalanceAccountData {
+    pub tag: u8,
+    pub mint: Pubkey,
+    pub user: Pubkey,
+    pub bump: u8,
+    // see epoch()
+    pub epoch: [u8; 8],
+    // see amount()
+    pub amount: [u8; 8],
+    pub flags: u8,
+    // see last_update_slot()
+    pub last_update_slot: [u8; 8],
+    // see nonce()
+    pub nonce: [u8; 8],
+    // see stats()
+    pub deposit_count: [u8; 4],
+    pub withdraw_count: [u8; 4],
+    pub lifetime_deposited: [u8; 16],
+    pub lifetime_withdrawn: [u8; 16],
+    // see slots()
+    pub last_deposit_slot: [u8; 8],
+    pub last_withdraw_slot: [u8; 8],
+}
+
+impl BalanceAccountData {
+    // the balance account at the start of data, which has to carry BALANCE_ACCOUNT_TAG
+    pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+        let data = data
+            .get(..BALANCE_ACCOUNT_SIZE)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let balance: &Self = bytemuck::from_bytes(data);
+        if balance.tag != BALANCE_ACCOUNT_TAG {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(balance)
+    }
+
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        let data = data
+            .get_mut(..BALANCE_ACCOUNT_SIZE)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let balance: &mut Self = bytemuck::from_bytes_mut(data);
+        if balance.tag != BALANCE_ACCOUNT_TAG {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(balance)
+    }
+
+    // a copy of the balance account at the start of data, one of an older, shorter layout reads
+    // the fields it lacks as 0
+    pub fn read(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < BALANCE_ACCOUNT_V0_SIZE || data[0] != BALANCE_ACCOUNT_TAG {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let len = data.len().min(BALANCE_ACCOUNT_SIZE);
+        let mut balance = Self::zeroed();
+        bytemuck::bytes_of_mut(&mut balance)[..len].copy_from_slice(&data[..len]);
+        Ok(balance)
+    }
+
+    pub fn epoch(&self) -> u64 {
+        u64::from_le_bytes(self.epoch)
+    }
+
+    pub fn set_epoch(&mut self, epoch: u64) {
+        self.epoch = epoch.to_le_bytes();
+    }
+
+    pub fn amount(&self) -> u64 {
+        u64::from_le_bytes(self.amount)
+    }
+
+    pub fn set_amount(&mut self, amount: u64) {
+        self.amount = amount.to_le_bytes();
+    }
+
+    pub fn last_update_slot(&self) -> u64 {
+        u64::from_le_bytes(self.last_update_slot)
+    }
+
+    pub fn set_last_update_slot(&mut self, slot: u64) {
+        self.last_update_slot = slot.to_le_bytes();
+    }
+
+    pub fn nonce(&self) -> u64 {
+        u64::from_le_bytes(self.nonce)
+    }
+
+    pub fn set_nonce(&mut self, nonce: u64) {
+        self.nonce = nonce.to_le_bytes();
+    }
+
+    pub fn stats(&self) -> UserStats {
+        UserStats {
+            deposit_count: u32::from_le_bytes(self.deposit_count),
+            withdraw_count: u32::from_le_bytes(self.withdraw_count),
+            lifetime_deposited: u128::from_le_bytes(self.lifetime_deposited),
+            lifetime_withdrawn: u128::from_le_bytes(self.lifetime_withdrawn),
+        }
+    }
+
+    pub fn set_stats(&mut self, stats: &UserStats) {
+        self.deposit_count = stats.deposit_count.to_le_bytes();
+        self.withdraw_count = stats.withdraw_count.to_le_bytes();
+        self.lifetime_deposited = stats.lifetime_deposited.to_le_bytes();
+        self.lifetime_withdrawn = stats.lifetime_withdrawn.to_le_bytes();
+    }
+
+    pub fn slots(&self) -> BalanceSlots {
+        BalanceSlots {
+            last_deposit_slot: u64::from_le_bytes(self.last_deposit_slot),
+            last_withdraw_slot: u64::from_le_bytes(self.last_withdraw_slot),
+        }
+    }
+
+    pub fn set_slots(&mut self, slots: &BalanceSlots) {
+        self.last_deposit_slot = slots.last_deposit_slot.to_le_bytes();
+        self.last_withdraw_slot = slots.last_withdraw_slot.to_le_bytes();
+    }
+}
+
+impl From<&BalanceAccount> for BalanceAccountData {
+    fn from(balance: &BalanceAccount) -> Self {
+        BalanceAccountData {
+            tag: BALANCE_ACCOUNT_TAG,
+            mint: balance.mint,
+            user: balance.user,
+            bump: balance.bump,
+            epoch: balance.epoch.to_le_bytes(),
+            amount: balance.amount.to_le_bytes(),
+            flags: balance.flags,
+            last_update_slot: balance.last_update_slot.to_le_bytes(),
+            nonce: balance.nonce.to_le_bytes(),
+            deposit_count: balance.stats.deposit_count.to_le_bytes(),
+            withdraw_count: balance.stats.withdraw_count.to_le_bytes(),
+            lifetime_deposited: balance.stats.lifetime_deposited.to_le_bytes(),
+            lifetime_withdrawn: balance.stats.lifetime_withdrawn.to_le_bytes(),
+            last_deposit_slot: balance.slots.last_deposit_slot.to_le_bytes(),
+            last_withdraw_slot: balance.slots.last_withdraw_slot.to_le_bytes(),
+        }
+    }
+}
+
+impl From<&BalanceAccountData> for BalanceAccount {
+    fn from(balance: &BalanceAccountData) -> Self {
+        BalanceAccount {
+            mint: balance.mint,
+            user: balance.user,
+            bump: balance.bump,
+            epoch: balance.epoch(),
+            amount: balance.amount(),
+            flags: balance.flags,
+            last_update_slot: balance.last_update_slot(),
+            nonce: balance.nonce(),
+            stats: balance.stats(),
+            slots: balance.slots(),
+        }
+    }
+}
+
+// balance accounts passed to an instruction, by (mint, user)
+pub type BalanceAccounts<'a, 'b> = HashMap<(Pubkey, Pubkey), &'a AccountInfo<'b>>;
+
+// seed of the PDA holding ContractState
+pub const STATE_SEED: &[u8] = b"state";
+// seed of the PDA owning a token's vault, [VAULT_SEED, mint]
+pub const VAULT_SEED: &[u8] = b"vault";
+// seed of the PDA holding one user's balance of one token, [BALANCE_SEED, mint, user]
+pub const BALANCE_SEED: &[u8] = b"balance";
+// first byte of a balance account, then the borsh BalanceAccount
+pub const BALANCE_ACCOUNT_TAG: u8 = 0xba;
+pub const BALANCE_ACCOUNT_SIZE: usize = 1 + BalanceAccount::LEN;
+// size of the balance accounts written before flags, last_update_slot and nonce were added,
+// the program brings one to BALANCE_ACCOUNT_SIZE the first time it writes it
+pub const BALANCE_ACCOUNT_V0_SIZE: usize = 82;
+// size of the balance accounts written before stats and slots moved in from the state
+pub const BALANCE_ACCOUNT_V1_SIZE: usize = 99;
+// seed of the PDA keeping one user's latest deposit receipts, [RECEIPTS_SEED, user]
+pub const RECEIPTS_SEED: &[u8] = b"receipts";
+// first byte of a receipts account
+pub const RECEIPTS_ACCOUNT_TAG: u8 = 0xbc;
+pub const RECEIPTS_HEADER_LEN: usize = std::mem::size_of::<ReceiptsHeader>();
+// a receipts account holding MAX_RECEIPTS_PER_USER receipts, it grows one receipt at a time
+pub const RECEIPTS_ACCOUNT_MAX_SIZE: usize =
+    RECEIPTS_HEADER_LEN + MAX_RECEIPTS_PER_USER * DepositReceipt::LEN;
+// space allocated for the state account on InitializeState
+pub const STATE_ACCOUNT_SIZE: usize = 10 * 1024;
+// state account layout: [schema version: u8][STATE_DISCRIMINATOR][payload len: u32 LE]
+// [borsh ContractState]
+// version 0 means uninitialized, version 1 is what used to be the initialized flag, versions
+// before STATE_DISCRIMINATOR_VERSION have no discriminator
+pub const STATE_HEADER_LEN: usize = 1 + 8 + 4;
+pub(crate) const STATE_UNINITIALIZED: u8 = 0;
+// schema this program reads and writes, older accounts go through MigrateState
+pub const STATE_VERSION: u8 = 32;
+// the first 8 bytes of sha256("account:ContractState"), as Anchor derives an account's, so an
+// account of another layout is never read as the state
+pub const STATE_DISCRIMINATOR: [u8; 8] = [190, 138, 10, 223, 189, 116, 222, 115];
+pub const STATE_DISCRIMINATOR_VERSION: u8 = 14;
+// AdminSnapshotState layout: [schema version: u8][unix timestamp: i64 LE][payload len: u32 LE]
+// [borsh ContractState]
+pub const SNAPSHOT_HEADER_LEN: usize = 1 + 8 + 4;
+
+// longest token symbol accepted, in bytes
+pub const MAX_SYMBOL_LEN: usize = 12;
+// longest token name accepted, in bytes
+pub const MAX_TOKEN_NAME_LEN: usize = 32;
+// most decimals a token may have, 10^18 still fits in a u64
+pub const MAX_DECIMALS: u8 = 18;
+// longest metadata_uri accepted, in bytes
+pub const MAX_METADATA_URI_LEN: usize = 200;
+// max_tokens of a freshly initialized state
+pub(crate) const DEFAULT_MAX_TOKENS: u32 = 64;
+// withdraw_delay_slots of a freshly initialized state
+pub(crate) const DEFAULT_WITHDRAW_DELAY_SLOTS: u64 = 1000;
+// rate_limit_ops and rate_limit_window_slots of a freshly initialized state
+pub(crate) const DEFAULT_RATE_LIMIT_OPS: u32 = 20;
+pub(crate) const DEFAULT_RATE_LIMIT_WINDOW_SLOTS: u64 = 100;
+// timelock_delay_slots of a freshly initialized state, about an hour
+pub const DEFAULT_TIMELOCK_DELAY_SLOTS: u64 = 9_000;
+// lowest timelock_delay_slots the admin can set, about ten minutes
+pub const MIN_TIMELOCK_DELAY_SLOTS: u64 = 1_500;
+// slots after its eta a queued action can still run, about a day
+pub const TIMELOCK_GRACE_SLOTS: u64 = 216_000;
+
+// pull the balance, stats and slots of every balance account among accounts into the state,
+// each one has to be the PDA of the mint and user it claims to hold
+pub fn load_balances<'a, 'b>(
+    program_id: &Pubkey,
+    accounts: &'a [AccountInfo<'b>],
+    state: &mut ContractState,
+) -> Result<BalanceAccounts<'a, 'b>, ProgramError> {
+    let mut loaded = HashMap::new();
+    for account in accounts {
+        if account.owner != program_id {
+            continue;
+        }
+        let data = account.try_borrow_data()?;
+        if data.first() != Some(&BALANCE_ACCOUNT_TAG) {
+            continue;
+        }
+        let balance = BalanceAccountData::read(&data)?;
+        let address = Pubkey::create_program_address(
+            &[
+                BALANCE_SEED,
+                balance.mint.as_ref(),
+                balance.user.as_ref(),
+                &[balance.bump],
+            ],
+            program_id,
+        )
+        .map_err(|_| ContractError::InvalidBalanceAccount)?;
+        if account.key != &address {
+            return Err(ContractError::InvalidBalanceAccount.into());
+        }
+        let epoch = state.token_epochs.get(&balance.mint).copied().unwrap_or(0);
+        // tokens compare by mint only
+        let token = TokenType {
+            symbol: String::new(),
+            mint: balance.mint,
+        };
+        if let Some(entry) = state.all_token_balances.get_mut(&token) {
+            if balance.epoch() == epoch {
+                let key = (balance.mint, balance.user);
+                entry.balances.insert(balance.user, balance.amount());
+                // older layouts have them in the state
+                if data.len() >= BALANCE_ACCOUNT_SIZE {
+                    insert_nonzero(&mut state.user_stats, key, balance.stats());
+                    insert_nonzero(&mut state.balance_slots, key, balance.slots());
+                }
+            }
+        }
+        loaded.insert((balance.mint, balance.user), account);
+    }
+    Ok(loaded)
+}
+
+// absent reads as the default, so that is left out
+fn insert_nonzero<V: Default + PartialEq>(
+    map: &mut BTreeMap<(Pubkey, Pubkey), V>,
+    key: (Pubkey, Pubkey),
+    value: V,
+) {
+    if value != V::default() {
+        map.insert(key, value);
+    }
+}
+
+pub(crate) fn write_balance_account(
+    account: &AccountInfo,
+    balance: &BalanceAccount,
+) -> ProgramResult {
+    let mut data = account.try_borrow_mut_data()?;
+    let data = data
+        .get_mut(..BALANCE_ACCOUNT_SIZE)
+        .ok_or(ProgramError::AccountDataTooSmall)?;
+    let stored: &mut BalanceAccountData = bytemuck::from_bytes_mut(data);
+    if stored.tag == BALANCE_ACCOUNT_TAG {
+        // an account already in use was loaded, so its mint, user and bump are the ones of
+        // its address, only the balance and the activity change, the nonce is kept
+        stored.set_epoch(balance.epoch);
+        stored.set_amount(balance.amount);
+        stored.set_last_update_slot(balance.last_update_slot);
+        stored.set_stats(&balance.stats);
+        stored.set_slots(&balance.slots);
+    } else {
+        *stored = BalanceAccountData::from(balance);
+    }
+    Ok(())
+}
+
+// schema version and raw payload of the state account, whatever the version
+pub(crate) fn load_versioned_payload(
+    state_account: &AccountInfo,
+) -> Result<(u8, Vec<u8>), ProgramError> {
+    let data = state_account.try_borrow_data()?;
+    let version = *data.first().ok_or(ProgramError::AccountDataTooSmall)?;
+    if version == STATE_UNINITIALIZED {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let header_len = state_header_len(version);
+    if data.len() < header_len {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    if header_len == STATE_HEADER_LEN && data[1..9] != STATE_DISCRIMINATOR {
+        msg!("state account data doesn't start with the state discriminator");
+        return Err(ContractError::InvalidStateAccount.into());
+    }
+    let len = u32::from_le_bytes(data[header_len - 4..header_len].try_into().unwrap()) as usize;
+    let payload = data
+        .get(header_len..header_len + len)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    Ok((version, payload.to_vec()))
+}
+
+// header of a state account of version, the ones before the discriminator had 5 bytes
+fn state_header_len(version: u8) -> usize {
+    if version < STATE_DISCRIMINATOR_VERSION {
+        1 + 4
+    } else {
+        STATE_HEADER_LEN
+    }
+}
+
+// write payload of schema version into data with that version's header
+pub(crate) fn write_state_payload(data: &mut [u8], version: u8, payload: &[u8]) -> ProgramResult {
+    let header_len = state_header_len(version);
+    if data.len() < header_len + payload.len() {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    data[0] = version;
+    if header_len == STATE_HEADER_LEN {
+        data[1..9].copy_from_slice(&STATE_DISCRIMINATOR);
+    }
+    data[header_len - 4..header_len].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    data[header_len..header_len + payload.len()].copy_from_slice(payload);
+    Ok(())
+}
+
+// read ContractState out of the state account, refusing any other schema version
+pub fn load_state(state_account: &AccountInfo) -> Result<ContractState, ProgramError> {
+    let (version, payload) = load_versioned_payload(state_account)?;
+    if version != STATE_VERSION {
+        msg!("state version {}, expected {}", version, STATE_VERSION);
+        return Err(ContractError::UnsupportedStateVersion.into());
+    }
+    ContractState::try_from_slice(&payload).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+// the state an AdminSnapshotState export holds, one of an older schema comes out migrated
+pub fn decode_snapshot(data: &[u8]) -> Result<ContractState, ProgramError> {
+    if data.len() < SNAPSHOT_HEADER_LEN {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    let version = data[0];
+    if version == STATE_UNINITIALIZED {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if version > STATE_VERSION {
+        return Err(ContractError::UnsupportedStateVersion.into());
+    }
+    let len = u32::from_le_bytes(data[9..SNAPSHOT_HEADER_LEN].try_into().unwrap()) as usize;
+    let mut payload = data
+        .get(SNAPSHOT_HEADER_LEN..SNAPSHOT_HEADER_LEN + len)
+        .ok_or(ProgramError::InvalidAccountData)?
+        .to_vec();
+    for step in version..STATE_VERSION {
+        payload = migrate_payload(step, payload)?;
+    }
+    ContractState::try_from_slice(&payload).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+// rewrite a version `version` payload as version + 1, new fields get their defaults
+// fields are only ever appended, so each step appends the borsh of the new defaults
+pub(crate) fn migrate_payload(version: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, ProgramError> {
+    match version {
+        // v2 added nonces
+        1 => {
+            let nonces: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            payload.extend(borsh::to_vec(&nonces).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v3 added eth_links
+        2 => {
+            let eth_links: BTreeMap<[u8; 20], Pubkey> = BTreeMap::new();
+            payload
+                .extend(borsh::to_vec(&eth_links).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v4 added sequence
+        3 => payload.extend(0u64.to_le_bytes()),
+        // v5 added token_epochs
+        4 => {
+            let token_epochs: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&token_epochs).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v6 added holder_counts, the balances of older listings live in balance accounts the
+        // migration can't see, so their counts start at zero and only follow later changes
+        5 => {
+            let holder_counts: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&holder_counts).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v7 added token_metadata, older listings read as the default until the admin sets it
+        6 => {
+            let token_metadata: BTreeMap<Pubkey, TokenMetadata> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&token_metadata).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v8 added display_precisions
+        7 => {
+            let display_precisions: BTreeMap<Pubkey, u8> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&display_precisions).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v9 added reward_pools, reward_points and reward_indexes, all three start empty
+        8 => {
+            let reward_pools: BTreeMap<Pubkey, RewardPool> = BTreeMap::new();
+            let reward_points: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            let reward_indexes: BTreeMap<(Pubkey, Pubkey), u128> = BTreeMap::new();
+            for field in [
+                borsh::to_vec(&reward_pools),
+                borsh::to_vec(&reward_points),
+                borsh::to_vec(&reward_indexes),
+            ] {
+                payload.extend(field.map_err(|_| ProgramError::InvalidAccountData)?);
+            }
+        }
+        // v10 added locked_lots
+        9 => {
+            let locked_lots: BTreeMap<(Pubkey, Pubkey), Vec<LockedLot>> = BTreeMap::new();
+            payload
+                .extend(borsh::to_vec(&locked_lots).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v11 added vesting_schedules
+        10 => {
+            let vesting_schedules: BTreeMap<(Pubkey, Pubkey), Vec<VestingSchedule>> =
+                BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&vesting_schedules).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v12 added referrers and referral_volume
+        11 => {
+            let referrers: BTreeMap<Pubkey, Option<Pubkey>> = BTreeMap::new();
+            let referral_volume: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            for field in [borsh::to_vec(&referrers), borsh::to_vec(&referral_volume)] {
+                payload.extend(field.map_err(|_| ProgramError::InvalidAccountData)?);
+            }
+        }
+        // v13 added audit_log
+        12 => {
+            let audit_log: VecDeque<AuditEntry> = VecDeque::new();
+            payload
+                .extend(borsh::to_vec(&audit_log).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v14 added STATE_DISCRIMINATOR to the header, the payload stays as it is
+        13 => {}
+        // v15 added airdrops
+        14 => {
+            let airdrops: BTreeMap<Pubkey, Airdrop> = BTreeMap::new();
+            payload.extend(borsh::to_vec(&airdrops).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v16 added redeemed_vouchers
+        15 => {
+            let redeemed_vouchers: BTreeMap<u64, i64> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&redeemed_vouchers).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v17 added the rate limit, existing states get the default one
+        16 => {
+            let rate_windows: BTreeMap<Pubkey, RateWindow> = BTreeMap::new();
+            payload.extend(DEFAULT_RATE_LIMIT_OPS.to_le_bytes());
+            payload.extend(DEFAULT_RATE_LIMIT_WINDOW_SLOTS.to_le_bytes());
+            payload.extend(
+                borsh::to_vec(&rate_windows).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v18 added global_total_deposited, the sum of the totals of the all_token_balances
+        // the payload starts with, and global_deposit_cap, none at first
+        17 => {
+            let tokens = BTreeMap::<TokenType, TokenEntry>::deserialize(&mut payload.as_slice())
+                .map_err(|_| ProgramError::InvalidAccountData)?;
+            let total: u128 = tokens
+                .values()
+                .map(|entry| u128::from(entry.total_deposited))
+                .sum();
+            payload.extend(total.to_le_bytes());
+            payload.extend(u128::MAX.to_le_bytes());
+        }
+        // v19 added price_feeds
+        18 => {
+            let price_feeds: BTreeMap<Pubkey, PriceFeed> = BTreeMap::new();
+            payload
+                .extend(borsh::to_vec(&price_feeds).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v20 added swap_rates
+        19 => {
+            let swap_rates: BTreeMap<(Pubkey, Pubkey), SwapRate> = BTreeMap::new();
+            payload
+                .extend(borsh::to_vec(&swap_rates).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v21 added share_supplies
+        20 => {
+            let share_supplies: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&share_supplies).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v22 added timelock_delay_slots and queued_actions
+        21 => {
+            payload.extend(DEFAULT_TIMELOCK_DELAY_SLOTS.to_le_bytes());
+            let queued_actions: BTreeMap<[u8; 32], QueuedAction> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&queued_actions).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v23 added circuit_breakers
+        22 => {
+            let circuit_breakers: BTreeMap<Pubkey, CircuitBreaker> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&circuit_breakers).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v24 added dust_thresholds
+        23 => {
+            let dust_thresholds: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&dust_thresholds).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v25 added user_stats
+        24 => {
+            let user_stats: BTreeMap<(Pubkey, Pubkey), UserStats> = BTreeMap::new();
+            payload
+                .extend(borsh::to_vec(&user_stats).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v26 added balance_slots, older balances read as never deposited to or withdrawn from
+        25 => {
+            let balance_slots: BTreeMap<(Pubkey, Pubkey), BalanceSlots> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&balance_slots).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v27 added withdraw_cooldowns
+        26 => {
+            let withdraw_cooldowns: BTreeMap<Pubkey, WithdrawCooldown> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&withdraw_cooldowns).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v28 added min_withdrawals
+        27 => {
+            let min_withdrawals: BTreeMap<Pubkey, u64> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&min_withdrawals).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v29 added next_receipt_id and receipts, deposits before it have no receipt
+        28 => {
+            let receipts: BTreeMap<Pubkey, Vec<DepositReceipt>> = BTreeMap::new();
+            payload.extend(0u64.to_le_bytes());
+            payload.extend(borsh::to_vec(&receipts).map_err(|_| ProgramError::InvalidAccountData)?);
+        }
+        // v30 added withdraw_queues
+        29 => {
+            let withdraw_queues: BTreeMap<Pubkey, VecDeque<QueuedWithdraw>> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&withdraw_queues).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        // v31 added nonce_floor, no balance account was closed with a nonce before it
+        30 => payload.extend(0u64.to_le_bytes()),
+        // v32 added rate_buckets, windows still running when it runs are carried over later
+        31 => {
+            let rate_buckets: BTreeMap<Pubkey, RateBuckets> = BTreeMap::new();
+            payload.extend(
+                borsh::to_vec(&rate_buckets).map_err(|_| ProgramError::InvalidAccountData)?,
+            );
+        }
+        _ => return Err(ContractError::UnsupportedStateVersion.into()),
+    }
+    Ok(payload)
+}
+
+// write ContractState back into the state account
+pub(crate) fn store_state(state_account: &AccountInfo, state: &ContractState) -> ProgramResult {
+    let payload = borsh::to_vec(state).map_err(|_| ProgramError::InvalidAccountData)?;
+    write_state_payload(
+        &mut state_account.try_borrow_mut_data()?,
+        STATE_VERSION,
+        &payload,
+    )
+}
+
+// symbols are 1..=MAX_SYMBOL_LEN ASCII alphanumerics, public so clients can check before sending
+pub fn validate_token_symbol(symbol: &str) -> Result<(), ContractError> {
+    if symbol.is_empty()
+        || symbol.len() > MAX_SYMBOL_LEN
+        || !symbol.bytes().all(|b| b.is_ascii_alphanumeric())
+    {
+        return Err(ContractError::InvalidTokenSymbol);
+    }
+    Ok(())
+}
+
+// public so clients can check before sending
+pub fn validate_token_metadata(metadata: &TokenMetadata) -> Result<(), ContractError> {
+    if metadata.name.len() > MAX_TOKEN_NAME_LEN {
+        return Err(ContractError::TokenNameTooLong);
+    }
+    if metadata.decimals > MAX_DECIMALS {
+        return Err(ContractError::InvalidDecimals);
+    }
+    if metadata
+        .metadata_uri
+        .as_ref()
+        .is_some_and(|uri| uri.len() > MAX_METADATA_URI_LEN)
+    {
+        return Err(ContractError::MetadataUriTooLong);
+    }
+    Ok(())
+}
+
+// uppercase form symbols are stored and compared in
+pub(crate) fn canonical_symbol(symbol: &str) -> String {
+    symbol.to_ascii_uppercase()
+}
+
+// PDA [VAULT_SEED, mint] owning the vault token account of mint, public so clients can pass it
+pub fn find_vault_authority(program_id: &Pubkey, mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, mint.as_ref()], program_id)
+}
+
+// the vault custodying mint's deposits, the vault authority's associated token account
+// SOL is held by the native mint's vault authority itself
+pub fn find_vault_address(program_id: &Pubkey, mint: &Pubkey) -> Pubkey {
+    find_vault_address_with_program_id(program_id, mint, &spl_token::id())
+}
+
+// find_vault_address of a mint owned by token_program, e.g. a Token-2022 mint
+pub fn find_vault_address_with_program_id(
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    let (vault_authority, _) = find_vault_authority(program_id, mint);
+    if mint == &spl_token::native_mint::id() {
+        return vault_authority;
+    }
+    get_associated_token_address_with_program_id(&vault_authority, mint, token_program)
+}
+
+// address of the account holding user's balance of mint, public so clients can pass it
+pub fn find_balance_address(program_id: &Pubkey, mint: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BALANCE_SEED, mint.as_ref(), user.as_ref()], program_id)
+}
+
+// address of the account keeping user's receipts, public so clients can pass it
+pub fn find_receipts_address(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RECEIPTS_SEED, user.as_ref()], program_id)
+}
@@ -0,0 +1,133 @@
+// wasm_bindgen wrappers of the deposit and withdraw builders, so browser wallets get payloads
+// identical to client.rs' without re-implementing the encoding
+// build with `wasm-pack build --target web --features wasm`, keys go in and out as base58
+// strings, amounts and nonces as BigInt, the token is named by its symbol and mint
+use std::str::FromStr;
+
+use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address;
+use wasm_bindgen::prelude::*;
+
+use crate::client::{deposit_ix, withdraw_ix};
+use crate::state::TokenType;
+
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WasmAccountMeta {
+    pub pubkey: String,
+    #[wasm_bindgen(js_name = isSigner)]
+    pub is_signer: bool,
+    #[wasm_bindgen(js_name = isWritable)]
+    pub is_writable: bool,
+}
+
+// what @solana/web3.js' TransactionInstruction is built from
+#[wasm_bindgen(getter_with_clone)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WasmInstruction {
+    #[wasm_bindgen(js_name = programId)]
+    pub program_id: String,
+    pub accounts: Vec<WasmAccountMeta>,
+    pub data: Vec<u8>,
+}
+
+impl From<Instruction> for WasmInstruction {
+    fn from(instruction: Instruction) -> Self {
+        WasmInstruction {
+            program_id: instruction.program_id.to_string(),
+            accounts: instruction
+                .accounts
+                .into_iter()
+                .map(|account| WasmAccountMeta {
+                    pubkey: account.pubkey.to_string(),
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data: instruction.data,
+        }
+    }
+}
+
+fn pubkey(name: &str, value: &str) -> Result<Pubkey, JsError> {
+    Pubkey::from_str(value).map_err(|_| JsError::new(&format!("invalid {}: {}", name, value)))
+}
+
+// the user's own token account unless one is given, the user itself for native SOL
+fn token_account(
+    token: &TokenType,
+    user: &Pubkey,
+    account: Option<String>,
+) -> Result<Pubkey, JsError> {
+    match account {
+        Some(account) => pubkey("token account", &account),
+        None if token.is_native() => Ok(*user),
+        None => Ok(get_associated_token_address(user, &token.mint)),
+    }
+}
+
+fn token(symbol: &str, mint: &str) -> Result<TokenType, JsError> {
+    Ok(TokenType {
+        symbol: symbol.to_string(),
+        mint: pubkey("mint", mint)?,
+    })
+}
+
+// deposit_ix without lock, referrer, beneficiary or memo, nonce is the user's next one
+#[wasm_bindgen(js_name = buildDepositInstruction)]
+pub fn build_deposit_instruction(
+    program_id: &str,
+    symbol: &str,
+    mint: &str,
+    user: &str,
+    amount: u64,
+    nonce: u64,
+    user_token_account: Option<String>,
+) -> Result<WasmInstruction, JsError> {
+    let token = token(symbol, mint)?;
+    let user = pubkey("user", user)?;
+    let user_token_account = token_account(&token, &user, user_token_account)?;
+    Ok(deposit_ix(
+        &pubkey("program id", program_id)?,
+        &token,
+        &user,
+        &user_token_account,
+        amount,
+        nonce,
+        None,
+        None,
+        None,
+        None,
+    )
+    .into())
+}
+
+// withdraw_ix signed by the user itself to one of its token accounts
+#[wasm_bindgen(js_name = buildWithdrawInstruction)]
+pub fn build_withdraw_instruction(
+    program_id: &str,
+    symbol: &str,
+    mint: &str,
+    user: &str,
+    amount: u64,
+    nonce: u64,
+    user_token_account: Option<String>,
+) -> Result<WasmInstruction, JsError> {
+    let token = token(symbol, mint)?;
+    let user = pubkey("user", user)?;
+    let user_token_account = token_account(&token, &user, user_token_account)?;
+    Ok(withdraw_ix(
+        &pubkey("program id", program_id)?,
+        &token,
+        &user,
+        &user,
+        &user_token_account,
+        amount,
+        nonce,
+        None,
+        None,
+        None,
+    )
+    .into())
+}
@@ -0,0 +1,401 @@
+// the cli's argument parsing and the instructions it builds, without a cluster
+use std::collections::{BTreeMap, BTreeSet};
+
+use hello_world::cli::{
+    build_instructions, format_text, parse, resolve_token, run, ChainData, CliCommand, DEFAULT_URL,
+};
+use hello_world::{
+    client::*, deserialize_instruction, find_vault_address, required_account_size, BalanceAccount,
+    BalanceAccountData, ContractInstruction, ContractState, TokenEntry, TokenMetadata, TokenType,
+};
+use solana_sdk::pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address;
+
+fn entry(deposit_fee_bps: u16) -> TokenEntry {
+    TokenEntry {
+        deposits_enabled: true,
+        withdrawals_enabled: true,
+        deposit_cap: None,
+        total_deposited: 0,
+        max_user_balance: None,
+        min_deposit: 0,
+        daily_withdrawal_limit: 0,
+        withdrawal_windows: BTreeMap::new(),
+        withdrawal_fee_bps: 0,
+        deposit_fee_bps,
+        withdraw_requests: BTreeMap::new(),
+        frozen: BTreeSet::new(),
+        balances: BTreeMap::new(),
+    }
+}
+
+#[test]
+fn test_parse() {
+    let program_id = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+    let id = program_id.to_string();
+
+    let (config, command) = parse(["cli", "--program-id", &id, "list-tokens"]).unwrap();
+    assert_eq!(config.url, DEFAULT_URL);
+    assert_eq!(config.program_id, program_id);
+    assert!(config.keypair.ends_with(".config/solana/id.json"));
+    assert!(!config.json);
+    assert_eq!(command, CliCommand::ListTokens);
+
+    // the global flags go after the subcommand too
+    let (config, command) = parse([
+        "cli",
+        "deposit",
+        "usdc",
+        "150",
+        "--url",
+        "http://x",
+        "--keypair",
+        "k.json",
+        "--program-id",
+        &id,
+        "--json",
+    ])
+    .unwrap();
+    assert_eq!(config.url, "http://x");
+    assert_eq!(config.keypair, "k.json");
+    assert!(config.json);
+    assert_eq!(
+        command,
+        CliCommand::Deposit {
+            token: "usdc".to_string(),
+            amount: 150,
+            token_account: None,
+        }
+    );
+
+    let mint_arg = mint.to_string();
+    let (_, command) = parse([
+        "cli",
+        "--program-id",
+        &id,
+        "add-token",
+        &mint_arg,
+        "usdc",
+        "--decimals",
+        "6",
+        "--name",
+        "USD Coin",
+    ])
+    .unwrap();
+    assert_eq!(
+        command,
+        CliCommand::AddToken {
+            mint,
+            symbol: "usdc".to_string(),
+            name: "USD Coin".to_string(),
+            decimals: 6,
+            metadata_uri: None,
+        }
+    );
+
+    let (_, command) = parse([
+        "cli",
+        "--program-id",
+        &id,
+        "delete-token",
+        "usdc",
+        "--queue",
+        "9000",
+    ])
+    .unwrap();
+    assert_eq!(
+        command,
+        CliCommand::DeleteToken {
+            token: "usdc".to_string(),
+            queue_eta_slot: Some(9_000),
+        }
+    );
+
+    let (_, command) = parse(["cli", "--program-id", &id, "init", "--admin", &mint_arg]).unwrap();
+    assert_eq!(command, CliCommand::Init { admin: Some(mint) });
+
+    let (_, command) =
+        parse(["cli", "--program-id", &id, "account-size", "--users", "100"]).unwrap();
+    assert_eq!(
+        command,
+        CliCommand::AccountSize {
+            max_tokens: 64,
+            users: 100
+        }
+    );
+    // answered without the cluster or the keypair
+    let (config, _) = parse([
+        "cli",
+        "--program-id",
+        &id,
+        "--keypair",
+        "none.json",
+        "list-tokens",
+    ])
+    .unwrap();
+    let output = run(&config, &command).unwrap();
+    assert_eq!(output["bytes"], required_account_size(64, 100));
+    assert_eq!(
+        format_text(&output),
+        format!(
+            "{} bytes for 64 tokens and 100 users",
+            required_account_size(64, 100)
+        )
+    );
+
+    assert!(parse(["cli", "list-tokens"]).is_err());
+    assert!(parse(["cli", "--program-id", &id]).is_err());
+    assert!(parse(["cli", "--program-id", "nope", "list-tokens"]).is_err());
+    assert!(parse(["cli", "--program-id", &id, "withdraw", "usdc", "-1"]).is_err());
+    assert!(parse(["cli", "--program-id", &id, "add-token", &mint_arg, "usdc"]).is_err());
+}
+
+#[test]
+fn test_build_instructions() {
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    let mut state = ContractState::default();
+    state.all_token_balances.insert(token.clone(), entry(0));
+    state
+        .all_token_balances
+        .insert(TokenType::native(), entry(25));
+    state.treasury = Some(treasury);
+    state.nonces.insert(user, 3);
+    let chain = ChainData {
+        state: Some(state),
+        token_program: spl_token::id(),
+        balance: None,
+    };
+    let build =
+        |signer: &Pubkey, command| build_instructions(&program_id, signer, &command, &chain);
+
+    assert_eq!(
+        build(&admin, CliCommand::Init { admin: None }).unwrap(),
+        vec![
+            initialize_state_ix(&program_id, &admin),
+            initialize_config_ix(&program_id, &admin, &admin),
+        ]
+    );
+
+    // the vault is created with the token
+    let add = build(
+        &admin,
+        CliCommand::AddToken {
+            mint: token.mint,
+            symbol: "usdc".to_string(),
+            name: "USD Coin".to_string(),
+            decimals: 6,
+            metadata_uri: None,
+        },
+    )
+    .unwrap();
+    assert_eq!(add.len(), 2);
+    assert_eq!(add[0].program_id, spl_associated_token_account::id());
+    assert_eq!(
+        add[0].accounts[1].pubkey,
+        find_vault_address(&program_id, &token.mint)
+    );
+    assert_eq!(
+        deserialize_instruction(&add[1].data).unwrap(),
+        ContractInstruction::AdminAddSupportedToken {
+            token: token.clone(),
+            metadata: TokenMetadata {
+                name: "USD Coin".to_string(),
+                decimals: 6,
+                metadata_uri: None,
+            },
+        }
+    );
+
+    // tokens are found by symbol or mint, deposits carry the user's next nonce
+    for name in ["usdc".to_string(), token.mint.to_string()] {
+        let deposit = build(
+            &user,
+            CliCommand::Deposit {
+                token: name,
+                amount: 150,
+                token_account: None,
+            },
+        )
+        .unwrap();
+        let ata = get_associated_token_address(&user, &token.mint);
+        assert_eq!(
+            deposit,
+            vec![deposit_ix(
+                &program_id,
+                &token,
+                &user,
+                &ata,
+                150,
+                3,
+                None,
+                None,
+                None,
+                None
+            )]
+        );
+    }
+
+    // SOL charges a deposit fee, so the treasury's balance account is passed
+    let deposit = build(
+        &user,
+        CliCommand::Deposit {
+            token: "SOL".to_string(),
+            amount: 10,
+            token_account: None,
+        },
+    )
+    .unwrap();
+    let native = TokenType::native();
+    assert_eq!(
+        deposit,
+        vec![with_treasury_balance(
+            deposit_ix(
+                &program_id,
+                &native,
+                &user,
+                &user,
+                10,
+                3,
+                None,
+                None,
+                None,
+                None
+            ),
+            &native.mint,
+            &treasury
+        )]
+    );
+
+    let account = Pubkey::new_unique();
+    assert_eq!(
+        build(
+            &user,
+            CliCommand::Withdraw {
+                token: "usdc".to_string(),
+                amount: 5,
+                token_account: Some(account),
+            }
+        )
+        .unwrap(),
+        vec![withdraw_ix(
+            &program_id,
+            &token,
+            &user,
+            &user,
+            &account,
+            5,
+            3,
+            None,
+            None,
+            None
+        )]
+    );
+
+    // a balance account the user has carries its own nonce
+    let mut state = ContractState::default();
+    state.all_token_balances.insert(token.clone(), entry(0));
+    state.nonces.insert(user, 3);
+    let balance = BalanceAccount {
+        mint: token.mint,
+        user,
+        bump: 255,
+        epoch: 0,
+        amount: 5,
+        flags: 0,
+        last_update_slot: 40,
+        nonce: 7,
+        ..Default::default()
+    };
+    let with_balance = ChainData {
+        state: Some(state),
+        token_program: spl_token::id(),
+        balance: Some(bytemuck::bytes_of(&BalanceAccountData::from(&balance)).to_vec()),
+    };
+    let withdraw = CliCommand::Withdraw {
+        token: "usdc".to_string(),
+        amount: 5,
+        token_account: Some(account),
+    };
+    assert_eq!(
+        build_instructions(&program_id, &user, &withdraw, &with_balance).unwrap(),
+        vec![withdraw_ix(
+            &program_id,
+            &token,
+            &user,
+            &user,
+            &account,
+            5,
+            7,
+            None,
+            None,
+            None
+        )]
+    );
+
+    assert_eq!(
+        build(
+            &admin,
+            CliCommand::DeleteToken {
+                token: "usdc".to_string(),
+                queue_eta_slot: Some(9_000),
+            }
+        )
+        .unwrap(),
+        vec![queue_action_ix(
+            &program_id,
+            &admin,
+            &delete_supported_token_ix(&program_id, &admin, &token),
+            9_000
+        )]
+    );
+    assert_eq!(
+        build(
+            &admin,
+            CliCommand::DeleteToken {
+                token: "usdc".to_string(),
+                queue_eta_slot: None,
+            }
+        )
+        .unwrap(),
+        vec![execute_action_ix(
+            &program_id,
+            delete_supported_token_ix(&program_id, &admin, &token)
+        )]
+    );
+    assert!(build(&user, CliCommand::ListTokens).unwrap().is_empty());
+    let size = CliCommand::AccountSize {
+        max_tokens: 8,
+        users: 10,
+    };
+    assert!(build(&user, size).unwrap().is_empty());
+
+    let unknown = CliCommand::DeleteToken {
+        token: "dai".to_string(),
+        queue_eta_slot: None,
+    };
+    assert!(build(&admin, unknown).is_err());
+    assert!(resolve_token(chain.state.as_ref().unwrap(), "SOL")
+        .unwrap()
+        .is_native());
+
+    // nothing but init works before the state exists
+    let uninitialized = ChainData {
+        state: None,
+        token_program: spl_token::id(),
+        balance: None,
+    };
+    let deposit = CliCommand::Deposit {
+        token: "usdc".to_string(),
+        amount: 1,
+        token_account: None,
+    };
+    assert!(build_instructions(&program_id, &user, &deposit, &uninitialized).is_err());
+}
@@ -0,0 +1,909 @@
+// the client builders against deserialize_instruction and the documented account layouts
+use hello_world::{
+    client::*, deserialize_instruction, find_balance_address, find_receipts_address,
+    find_vault_address, find_vault_address_with_program_id, find_vault_authority,
+    ContractInstruction, TokenMetadata, TokenType,
+};
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, system_program};
+
+#[test]
+fn test_builders_round_trip() {
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+    let token_account = Pubkey::new_unique();
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    let metadata = TokenMetadata {
+        name: "USD Coin".to_string(),
+        decimals: 6,
+        metadata_uri: Some("https://example.com/usdc.json".to_string()),
+    };
+    let items = [(token.clone(), 5, token_account)];
+    let cases = vec![
+        (
+            initialize_state_ix(&program_id, &admin),
+            ContractInstruction::InitializeState,
+        ),
+        (
+            migrate_state_ix(&program_id, &admin),
+            ContractInstruction::MigrateState,
+        ),
+        (
+            initialize_config_ix(&program_id, &admin, &other),
+            ContractInstruction::InitializeConfig { admin: other },
+        ),
+        (
+            add_supported_token_ix(&program_id, &admin, &token, &metadata),
+            ContractInstruction::AdminAddSupportedToken {
+                token: token.clone(),
+                metadata: metadata.clone(),
+            },
+        ),
+        (
+            delete_supported_token_ix(&program_id, &admin, &token),
+            ContractInstruction::AdminDeleteSupportedToken {
+                token: token.clone(),
+            },
+        ),
+        (
+            force_delete_token_ix(&program_id, &admin, &token, true),
+            ContractInstruction::AdminForceDeleteToken {
+                token: token.clone(),
+                confirm: true,
+            },
+        ),
+        (
+            deposit_ix(
+                &program_id,
+                &token,
+                &user,
+                &token_account,
+                10,
+                3,
+                Some(1_700_000_000),
+                Some(other),
+                None,
+                None,
+            ),
+            ContractInstruction::UserDeposit {
+                token: token.clone(),
+                user,
+                amount: 10,
+                nonce: 3,
+                lock_until: Some(1_700_000_000),
+                referrer: Some(other),
+                beneficiary: None,
+                memo: None,
+            },
+        ),
+        (
+            withdraw_ix(
+                &program_id,
+                &token,
+                &other,
+                &user,
+                &token_account,
+                10,
+                4,
+                Some(vec![7; 65]),
+                Some(other),
+                Some("invoice 17".to_string()),
+            ),
+            ContractInstruction::UserWithdraw {
+                token: token.clone(),
+                user,
+                amount: 10,
+                nonce: 4,
+                eth_signature: Some(vec![7; 65]),
+                recipient: Some(other),
+                memo: Some("invoice 17".to_string()),
+            },
+        ),
+        (
+            withdraw_all_ix(&program_id, &token, &user, &user, &token_account),
+            ContractInstruction::UserWithdrawAll {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            request_withdraw_ix(&program_id, &token, &user, 6),
+            ContractInstruction::UserRequestWithdraw {
+                token: token.clone(),
+                user,
+                amount: 6,
+            },
+        ),
+        (
+            claim_withdraw_ix(&program_id, &token, &user, &user, &token_account),
+            ContractInstruction::UserClaimWithdraw {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            cancel_withdraw_request_ix(&program_id, &admin, &token, &user),
+            ContractInstruction::AdminCancelWithdrawRequest {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            set_withdraw_delay_ix(&program_id, &admin, 100),
+            ContractInstruction::AdminSetWithdrawDelay { delay_slots: 100 },
+        ),
+        (
+            set_rate_limit_ix(&program_id, &admin, 5, 50),
+            ContractInstruction::AdminSetRateLimit {
+                max_ops: 5,
+                window_slots: 50,
+            },
+        ),
+        (
+            set_global_cap_ix(&program_id, &admin, 1 << 70),
+            ContractInstruction::AdminSetGlobalCap { cap: 1 << 70 },
+        ),
+        (
+            get_global_stats_ix(&program_id),
+            ContractInstruction::GetGlobalStats,
+        ),
+        (
+            set_swap_rate_ix(&program_id, &admin, &token, &TokenType::native(), 1, 2),
+            ContractInstruction::AdminSetSwapRate {
+                from: token.clone(),
+                to: TokenType::native(),
+                numerator: 1,
+                denominator: 2,
+            },
+        ),
+        (
+            enable_share_mode_ix(&program_id, &admin, &token),
+            ContractInstruction::AdminEnableShareMode {
+                token: token.clone(),
+            },
+        ),
+        (
+            donate_yield_ix(&program_id, &admin, &token_account, &token, 40),
+            ContractInstruction::AdminDonateYield {
+                token: token.clone(),
+                amount: 40,
+            },
+        ),
+        (
+            slash_ix(&program_id, &admin, &token, &user, &other, 15, 3),
+            ContractInstruction::AdminSlash {
+                token: token.clone(),
+                user,
+                amount: 15,
+                reason_code: 3,
+            },
+        ),
+        (
+            queue_action_ix(
+                &program_id,
+                &admin,
+                &set_deposit_fee_ix(&program_id, &admin, &token, 30),
+                9_000,
+            ),
+            ContractInstruction::AdminQueueAction {
+                action: Box::new(ContractInstruction::AdminSetDepositFee {
+                    token: token.clone(),
+                    fee_bps: 30,
+                }),
+                eta_slot: 9_000,
+            },
+        ),
+        (
+            execute_action_ix(
+                &program_id,
+                set_deposit_fee_ix(&program_id, &admin, &token, 30),
+            ),
+            ContractInstruction::AdminExecuteAction {
+                action: Box::new(ContractInstruction::AdminSetDepositFee {
+                    token: token.clone(),
+                    fee_bps: 30,
+                }),
+            },
+        ),
+        (
+            set_timelock_delay_ix(&program_id, &admin, 3_000),
+            ContractInstruction::AdminSetTimelockDelay { delay_slots: 3_000 },
+        ),
+        (
+            set_circuit_breaker_ix(&program_id, &admin, &token, 2_000, 150),
+            ContractInstruction::AdminSetCircuitBreaker {
+                token: token.clone(),
+                threshold_bps: 2_000,
+                window_slots: 150,
+            },
+        ),
+        (
+            reset_breaker_ix(&program_id, &admin, &token),
+            ContractInstruction::AdminResetBreaker {
+                token: token.clone(),
+            },
+        ),
+        (
+            set_dust_threshold_ix(&program_id, &admin, &token, 10),
+            ContractInstruction::AdminSetDustThreshold {
+                token: token.clone(),
+                threshold: 10,
+            },
+        ),
+        (
+            is_token_supported_ix(&program_id, &token),
+            ContractInstruction::IsTokenSupported {
+                token: token.clone(),
+            },
+        ),
+        (
+            add_supported_tokens_ix(&program_id, &admin, &[token.clone(), TokenType::native()]),
+            ContractInstruction::AdminAddSupportedTokens {
+                tokens: vec![token.clone(), TokenType::native()],
+            },
+        ),
+        (
+            get_queue_position_ix(&program_id, &token, &user),
+            ContractInstruction::GetQueuePosition {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            enqueue_withdraw_ix(&program_id, &token, &user, 12),
+            ContractInstruction::UserEnqueueWithdraw {
+                token: token.clone(),
+                user,
+                amount: 12,
+            },
+        ),
+        (
+            cancel_queued_withdraw_ix(&program_id, &token, &user),
+            ContractInstruction::UserCancelQueuedWithdraw {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            process_withdraw_queue_ix(&program_id, &admin, &token, &[(user, user), (admin, admin)]),
+            ContractInstruction::ProcessWithdrawQueue {
+                token: token.clone(),
+                max_items: 2,
+            },
+        ),
+        (
+            get_receipt_ix(&program_id, &user, 9),
+            ContractInstruction::GetReceipt {
+                user,
+                receipt_id: 9,
+            },
+        ),
+        (
+            get_user_stats_ix(&program_id, &token, &user),
+            ContractInstruction::GetUserStats {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            set_withdraw_cooldown_ix(&program_id, &admin, &token, 30, true),
+            ContractInstruction::AdminSetWithdrawCooldown {
+                token: token.clone(),
+                withdraw_cooldown_slots: 30,
+                transfers_reset: true,
+            },
+        ),
+        (
+            set_min_withdraw_ix(&program_id, &admin, &token, 15),
+            ContractInstruction::AdminSetMinWithdraw {
+                token: token.clone(),
+                min_withdraw: 15,
+            },
+        ),
+        (
+            set_price_feed_ix(&program_id, &admin, &token, &other, 500, 25),
+            ContractInstruction::AdminSetPriceFeed {
+                token: token.clone(),
+                feed: other,
+                usd_cap: 500,
+                max_age_slots: 25,
+            },
+        ),
+        (
+            set_treasury_ix(&program_id, &admin, &other),
+            ContractInstruction::AdminSetTreasury { treasury: other },
+        ),
+        (
+            set_withdrawal_fee_ix(&program_id, &admin, &token, 25),
+            ContractInstruction::AdminSetWithdrawalFee {
+                token: token.clone(),
+                fee_bps: 25,
+            },
+        ),
+        (
+            set_deposit_fee_ix(&program_id, &admin, &token, 30),
+            ContractInstruction::AdminSetDepositFee {
+                token: token.clone(),
+                fee_bps: 30,
+            },
+        ),
+        (
+            set_fee_exempt_ix(&program_id, &admin, &user, true),
+            ContractInstruction::AdminSetFeeExempt { user, exempt: true },
+        ),
+        (
+            withdraw_treasury_ix(&program_id, &admin, &token_account, &token, &other, 8),
+            ContractInstruction::AdminWithdrawTreasury {
+                token: token.clone(),
+                amount: 8,
+            },
+        ),
+        (
+            set_withdraw_delegate_ix(&program_id, &user, Some(other)),
+            ContractInstruction::UserSetWithdrawDelegate {
+                user,
+                delegate: Some(other),
+            },
+        ),
+        (
+            freeze_user_ix(&program_id, &admin, &token, &user),
+            ContractInstruction::AdminFreezeUser {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            unfreeze_user_ix(&program_id, &admin, &token, &user),
+            ContractInstruction::AdminUnfreezeUser {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            set_blocked_ix(&program_id, &admin, &user, false),
+            ContractInstruction::AdminSetBlocked {
+                user,
+                blocked: false,
+            },
+        ),
+        (
+            set_operator_ix(&program_id, &admin, None),
+            ContractInstruction::AdminSetOperator { operator: None },
+        ),
+        (
+            propose_new_admin_ix(&program_id, &admin, &other),
+            ContractInstruction::AdminProposeNewAdmin { new_admin: other },
+        ),
+        (
+            cancel_proposed_admin_ix(&program_id, &admin),
+            ContractInstruction::AdminCancelProposedAdmin,
+        ),
+        (
+            accept_admin_ix(&program_id, &other),
+            ContractInstruction::AcceptAdmin,
+        ),
+        (
+            pause_ix(&program_id, &admin, true),
+            ContractInstruction::AdminPause {
+                withdrawals_only: true,
+            },
+        ),
+        (
+            unpause_ix(&program_id, &admin),
+            ContractInstruction::AdminUnpause,
+        ),
+        (
+            set_token_flags_ix(&program_id, &admin, &token, false, true),
+            ContractInstruction::AdminSetTokenFlags {
+                token: token.clone(),
+                deposits_enabled: false,
+                withdrawals_enabled: true,
+            },
+        ),
+        (
+            set_max_tokens_ix(&program_id, &admin, 4),
+            ContractInstruction::AdminSetMaxTokens { max_tokens: 4 },
+        ),
+        (
+            set_deposit_cap_ix(&program_id, &admin, &token, Some(1_000)),
+            ContractInstruction::AdminSetDepositCap {
+                token: token.clone(),
+                cap: Some(1_000),
+            },
+        ),
+        (
+            set_max_user_balance_ix(&program_id, &admin, &token, None),
+            ContractInstruction::AdminSetMaxUserBalance {
+                token: token.clone(),
+                max_user_balance: None,
+            },
+        ),
+        (
+            set_min_deposit_ix(&program_id, &admin, &token, 2),
+            ContractInstruction::AdminSetMinDeposit {
+                token: token.clone(),
+                min_deposit: 2,
+            },
+        ),
+        (
+            set_daily_withdrawal_limit_ix(&program_id, &admin, &token, 50),
+            ContractInstruction::AdminSetDailyWithdrawalLimit {
+                token: token.clone(),
+                limit: 50,
+            },
+        ),
+        (
+            batch_deposit_ix(&program_id, &user, &items),
+            ContractInstruction::BatchDeposit {
+                user,
+                items: vec![(token.clone(), 5)],
+            },
+        ),
+        (
+            batch_withdraw_ix(&program_id, &user, &items),
+            ContractInstruction::BatchWithdraw {
+                user,
+                items: vec![(token.clone(), 5)],
+            },
+        ),
+        (
+            transfer_ix(&program_id, &token, &user, &other, 9),
+            ContractInstruction::UserTransfer {
+                token: token.clone(),
+                from: user,
+                to: other,
+                amount: 9,
+            },
+        ),
+        (
+            swap_ix(
+                &program_id,
+                &token,
+                &TokenType::native(),
+                &user,
+                &other,
+                12,
+                6,
+            ),
+            ContractInstruction::UserSwap {
+                from: token.clone(),
+                to: TokenType::native(),
+                user,
+                amount_in: 12,
+                min_amount_out: 6,
+            },
+        ),
+        (
+            approve_ix(&program_id, &token, &user, &other, 11),
+            ContractInstruction::UserApprove {
+                token: token.clone(),
+                owner: user,
+                spender: other,
+                amount: 11,
+            },
+        ),
+        (
+            transfer_from_ix(&program_id, &token, &user, &other, &admin, 12),
+            ContractInstruction::TransferFrom {
+                token: token.clone(),
+                owner: user,
+                spender: other,
+                to: admin,
+                amount: 12,
+            },
+        ),
+        (
+            get_balance_ix(&program_id, &token, &user),
+            ContractInstruction::GetBalance {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            link_eth_address_ix(&program_id, &user, vec![1; 65]),
+            ContractInstruction::LinkEthAddress {
+                user,
+                signature: vec![1; 65],
+            },
+        ),
+        (
+            get_sequence_ix(&program_id),
+            ContractInstruction::GetSequence,
+        ),
+        (
+            get_nonce_ix(&program_id, &token, &user),
+            ContractInstruction::GetNonce {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            list_supported_tokens_ix(&program_id, 32, 16),
+            ContractInstruction::ListSupportedTokens {
+                offset: 32,
+                limit: 16,
+            },
+        ),
+        (
+            get_token_stats_ix(&program_id, &token),
+            ContractInstruction::GetTokenStats {
+                token: token.clone(),
+            },
+        ),
+        (
+            update_token_metadata_ix(&program_id, &admin, &token, &metadata),
+            ContractInstruction::AdminUpdateTokenMetadata {
+                token: token.clone(),
+                metadata: metadata.clone(),
+            },
+        ),
+        (
+            set_display_precision_ix(&program_id, &admin, &token, Some(2)),
+            ContractInstruction::AdminSetDisplayPrecision {
+                token: token.clone(),
+                display_precision: Some(2),
+            },
+        ),
+        (
+            set_reward_rate_ix(&program_id, &admin, &token, 10),
+            ContractInstruction::AdminSetRewardRate {
+                token: token.clone(),
+                points_per_day: 10,
+            },
+        ),
+        (
+            claim_rewards_ix(&program_id, &user, &[token.mint]),
+            ContractInstruction::ClaimRewards { user },
+        ),
+        (
+            get_reward_points_ix(&program_id, &user, &[token.mint]),
+            ContractInstruction::GetRewardPoints { user },
+        ),
+        (
+            deposit_vested_ix(
+                &program_id,
+                &admin,
+                &token_account,
+                &token,
+                &user,
+                100,
+                1_700_000_000,
+                86_400,
+            ),
+            ContractInstruction::AdminDepositVested {
+                token: token.clone(),
+                user,
+                amount: 100,
+                start_ts: 1_700_000_000,
+                duration_secs: 86_400,
+            },
+        ),
+        (
+            get_referral_stats_ix(&program_id, &other),
+            ContractInstruction::GetReferralStats { referrer: other },
+        ),
+        (
+            snapshot_state_ix(&program_id, &admin, &other, &[(token.mint, user)]),
+            ContractInstruction::AdminSnapshotState,
+        ),
+        (
+            get_audit_log_ix(&program_id, 16, 8),
+            ContractInstruction::GetAuditLog {
+                offset: 16,
+                limit: 8,
+            },
+        ),
+        (
+            close_user_balance_ix(&program_id, &admin, &token, &user),
+            ContractInstruction::CloseUserBalance {
+                token: token.clone(),
+                user,
+            },
+        ),
+        (
+            emergency_sweep_ix(&program_id, &admin, &token, &token_account, &[user, other]),
+            ContractInstruction::AdminEmergencySweep {
+                token: token.clone(),
+                destination: token_account,
+            },
+        ),
+        (
+            rename_token_ix(&program_id, &admin, &token, "usdce"),
+            ContractInstruction::AdminRenameToken {
+                old: token.clone(),
+                new_symbol: "usdce".to_string(),
+            },
+        ),
+        (
+            set_merkle_root_ix(&program_id, &admin, &token_account, &token, [7; 32], 900),
+            ContractInstruction::AdminSetMerkleRoot {
+                token: token.clone(),
+                root: [7; 32],
+                total: 900,
+            },
+        ),
+        (
+            claim_airdrop_ix(&program_id, &admin, &token, &user, 300, &[[1; 32], [2; 32]]),
+            ContractInstruction::ClaimAirdrop {
+                token: token.clone(),
+                user,
+                amount: 300,
+                proof: vec![[1; 32], [2; 32]],
+            },
+        ),
+        (
+            redeem_voucher_ix(
+                &program_id,
+                &user,
+                &admin,
+                &token,
+                &user,
+                300,
+                4,
+                1_700_000_000,
+                [5; 64],
+            ),
+            ContractInstruction::RedeemVoucher {
+                token: token.clone(),
+                user,
+                amount: 300,
+                voucher_id: 4,
+                expiry: 1_700_000_000,
+                signature: [5; 64],
+            },
+        ),
+        (
+            multicall_ix(
+                &program_id,
+                &[
+                    set_min_deposit_ix(&program_id, &admin, &token, 2),
+                    close_user_balance_ix(&program_id, &admin, &token, &user),
+                ],
+            ),
+            ContractInstruction::Multicall {
+                calls: vec![
+                    ContractInstruction::AdminSetMinDeposit {
+                        token: token.clone(),
+                        min_deposit: 2,
+                    },
+                    ContractInstruction::CloseUserBalance {
+                        token: token.clone(),
+                        user,
+                    },
+                ],
+            },
+        ),
+    ];
+    for (instruction, expected) in cases {
+        assert_eq!(instruction.program_id, program_id);
+        assert_eq!(
+            instruction.accounts[0].pubkey,
+            find_state_address(&program_id)
+        );
+        assert_eq!(
+            deserialize_instruction(&instruction.data).unwrap(),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_builder_accounts() {
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let token_account = Pubkey::new_unique();
+    let state = find_state_address(&program_id);
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    let (vault_authority, _) = find_vault_authority(&program_id, &token.mint);
+    let (balance, _) = find_balance_address(&program_id, &token.mint, &user);
+    let (receipts, _) = find_receipts_address(&program_id, &user);
+    let deposit = deposit_ix(
+        &program_id,
+        &token,
+        &user,
+        &token_account,
+        1,
+        0,
+        None,
+        None,
+        None,
+        None,
+    );
+    assert_eq!(
+        deposit.accounts,
+        vec![
+            AccountMeta::new(state, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new(token_account, false),
+            AccountMeta::new(find_vault_address(&program_id, &token.mint), false),
+            AccountMeta::new_readonly(vault_authority, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(balance, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(receipts, false),
+        ]
+    );
+
+    // a deposit for someone else carries the beneficiary's balance and receipts accounts
+    let beneficiary = Pubkey::new_unique();
+    let (beneficiary_balance, _) = find_balance_address(&program_id, &token.mint, &beneficiary);
+    let deposit = deposit_ix(
+        &program_id,
+        &token,
+        &user,
+        &token_account,
+        1,
+        0,
+        None,
+        None,
+        Some(beneficiary),
+        None,
+    );
+    assert_eq!(
+        deposit.accounts[6],
+        AccountMeta::new(beneficiary_balance, false)
+    );
+    // followed by the depositor's own, which keeps its nonce
+    let (user_balance, _) = find_balance_address(&program_id, &token.mint, &user);
+    assert_eq!(deposit.accounts[7], AccountMeta::new(user_balance, false));
+    assert_eq!(deposit.accounts[8].pubkey, system_program::id());
+    let (beneficiary_receipts, _) = find_receipts_address(&program_id, &beneficiary);
+    assert_eq!(
+        deposit.accounts[9],
+        AccountMeta::new(beneficiary_receipts, false)
+    );
+
+    // Token-2022 mints have their own vault and pass the mint after the program
+    let deposit = with_token_2022(
+        deposit_ix(
+            &program_id,
+            &token,
+            &user,
+            &token_account,
+            1,
+            0,
+            None,
+            None,
+            None,
+            None,
+        ),
+        &token.mint,
+    );
+    let vault_2022 =
+        find_vault_address_with_program_id(&program_id, &token.mint, &spl_token_2022::id());
+    assert_ne!(vault_2022, find_vault_address(&program_id, &token.mint));
+    assert_eq!(
+        deposit.accounts,
+        vec![
+            AccountMeta::new(state, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new(token_account, false),
+            AccountMeta::new(vault_2022, false),
+            AccountMeta::new_readonly(vault_authority, false),
+            AccountMeta::new_readonly(spl_token_2022::id(), false),
+            AccountMeta::new_readonly(token.mint, false),
+            AccountMeta::new(balance, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(receipts, false),
+        ]
+    );
+
+    // native SOL moves between the user and the vault PDA through the system program
+    let sol = TokenType::native();
+    let (vault, _) = find_vault_authority(&program_id, &sol.mint);
+    let (sol_balance, _) = find_balance_address(&program_id, &sol.mint, &user);
+    let deposit = deposit_ix(
+        &program_id,
+        &sol,
+        &user,
+        &user,
+        1,
+        0,
+        None,
+        None,
+        None,
+        None,
+    );
+    assert_eq!(
+        deposit.accounts,
+        vec![
+            AccountMeta::new(state, false),
+            AccountMeta::new(user, true),
+            AccountMeta::new(user, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(sol_balance, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(receipts, false),
+        ]
+    );
+
+    // the treasury's balance account is appended for fee-charging instructions
+    let treasury = Pubkey::new_unique();
+    let (treasury_balance, _) = find_balance_address(&program_id, &token.mint, &treasury);
+    let withdraw = with_treasury_balance(
+        withdraw_ix(
+            &program_id,
+            &token,
+            &user,
+            &user,
+            &token_account,
+            1,
+            1,
+            None,
+            None,
+            None,
+        ),
+        &token.mint,
+        &treasury,
+    );
+    assert_eq!(
+        withdraw.accounts.last(),
+        Some(&AccountMeta::new(treasury_balance, false))
+    );
+    // an executed action goes with the accounts of the instruction it runs
+    let delete = delete_supported_token_ix(&program_id, &user, &token);
+    assert_eq!(
+        execute_action_ix(&program_id, delete.clone()).accounts,
+        delete.accounts
+    );
+    // and a token's price feed for deposits of it
+    let feed = Pubkey::new_unique();
+    let deposit = with_price_feed(
+        deposit_ix(
+            &program_id,
+            &token,
+            &user,
+            &token_account,
+            1,
+            0,
+            None,
+            None,
+            None,
+            None,
+        ),
+        &feed,
+    );
+    assert_eq!(
+        deposit.accounts.last(),
+        Some(&AccountMeta::new_readonly(feed, false))
+    );
+
+    // a multicall passes every call's accounts in turn
+    let calls = [
+        deposit_ix(
+            &program_id,
+            &token,
+            &user,
+            &token_account,
+            1,
+            0,
+            None,
+            None,
+            None,
+            None,
+        ),
+        close_user_balance_ix(&program_id, &user, &token, &user),
+    ];
+    let multicall = multicall_ix(&program_id, &calls);
+    assert_eq!(
+        multicall.accounts,
+        [calls[0].accounts.clone(), calls[1].accounts.clone()].concat()
+    );
+
+    // queries don't write anything
+    for query in [
+        get_balance_ix(&program_id, &token, &user),
+        get_nonce_ix(&program_id, &token, &user),
+    ] {
+        assert!(query.accounts.iter().all(|account| !account.is_writable));
+    }
+}
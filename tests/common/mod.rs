@@ -0,0 +1,197 @@
+// shared solana-program-test setup
+// every test binary uses its own subset of the helpers
+#![allow(dead_code)]
+
+use hello_world::{
+    client::{find_state_address, initialize_config_ix, initialize_state_ix},
+    find_balance_address, find_receipts_address, find_vault_address, find_vault_authority,
+    process_instruction, ContractInstruction, TokenMetadata,
+};
+use solana_program_test::{processor, BanksClient, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+pub fn pack(instruction: &ContractInstruction) -> Vec<u8> {
+    instruction.pack()
+}
+
+// program with an initialized state whose admin is the payer
+pub async fn setup() -> (Pubkey, Pubkey, BanksClient, Keypair, Hash) {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("hello_world", program_id, processor!(process_instruction));
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let state = initialize(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    (program_id, state, banks_client, payer, recent_blockhash)
+}
+
+// like setup, with the context to warp the slot with, for timelocked instructions
+pub async fn setup_with_context() -> (Pubkey, Pubkey, ProgramTestContext) {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new("hello_world", program_id, processor!(process_instruction));
+    let mut context = program_test.start_with_context().await;
+    let state = initialize(
+        &mut context.banks_client,
+        &context.payer,
+        &program_id,
+        context.last_blockhash,
+    )
+    .await;
+    (program_id, state, context)
+}
+
+// initialize the state and config of program_id with the payer as admin
+pub async fn initialize(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    program_id: &Pubkey,
+    recent_blockhash: Hash,
+) -> Pubkey {
+    let state = find_state_address(program_id);
+    let initialize_state = initialize_state_ix(program_id, &payer.pubkey());
+    let initialize_config = initialize_config_ix(program_id, &payer.pubkey(), &payer.pubkey());
+    let transaction = Transaction::new_signed_with_payer(
+        &[initialize_state, initialize_config],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+    banks_client.process_transaction(transaction).await.unwrap();
+    state
+}
+
+// signers[0] pays
+pub async fn send(
+    banks_client: &mut BanksClient,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+    recent_blockhash: Hash,
+) {
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&signers[0].pubkey()),
+        signers,
+        recent_blockhash,
+    );
+    banks_client.process_transaction(transaction).await.unwrap();
+}
+
+// metadata matching the mints create_mint_and_vault makes
+pub fn mint_metadata() -> TokenMetadata {
+    TokenMetadata {
+        name: "Test Token".to_string(),
+        decimals: 6,
+        metadata_uri: None,
+    }
+}
+
+// mint with the payer as mint authority and the program's empty vault for it
+pub async fn create_mint_and_vault(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    program_id: &Pubkey,
+    recent_blockhash: Hash,
+) -> (Keypair, Pubkey) {
+    let mint = Keypair::new();
+    let (vault_authority, _) = find_vault_authority(program_id, &mint.pubkey());
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            1_000_000_000,
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            6,
+        )
+        .unwrap(),
+        create_associated_token_account(
+            &payer.pubkey(),
+            &vault_authority,
+            &mint.pubkey(),
+            &spl_token::id(),
+        ),
+    ];
+    send(
+        banks_client,
+        &instructions,
+        &[payer, &mint],
+        recent_blockhash,
+    )
+    .await;
+    let vault = find_vault_address(program_id, &mint.pubkey());
+    (mint, vault)
+}
+
+// user with lamports for its balance accounts' rent and amount of mint in its associated
+// token account, returned along with the user
+pub async fn create_user(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    amount: u64,
+    recent_blockhash: Hash,
+) -> (Keypair, Pubkey) {
+    let user = Keypair::new();
+    let token_account = get_associated_token_address(&user.pubkey(), mint);
+    let instructions = [
+        system_instruction::transfer(&payer.pubkey(), &user.pubkey(), 1_000_000_000),
+        create_associated_token_account(&payer.pubkey(), &user.pubkey(), mint, &spl_token::id()),
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            mint,
+            &token_account,
+            &payer.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap(),
+    ];
+    send(banks_client, &instructions, &[payer], recent_blockhash).await;
+    (user, token_account)
+}
+
+// [state, signer, token_account, vault, vault_authority, token_program] followed by the
+// balance accounts of balance_users and the system program
+pub fn token_transfer_accounts(
+    program_id: &Pubkey,
+    state: &Pubkey,
+    mint: &Pubkey,
+    signer: &Pubkey,
+    token_account: &Pubkey,
+    balance_users: &[Pubkey],
+) -> Vec<AccountMeta> {
+    let (vault_authority, _) = find_vault_authority(program_id, mint);
+    let mut accounts = vec![
+        AccountMeta::new(*state, false),
+        AccountMeta::new(*signer, true),
+        AccountMeta::new(*token_account, false),
+        AccountMeta::new(find_vault_address(program_id, mint), false),
+        AccountMeta::new_readonly(vault_authority, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    for user in balance_users {
+        let (balance, _) = find_balance_address(program_id, mint, user);
+        accounts.push(AccountMeta::new(balance, false));
+    }
+    accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    for user in balance_users {
+        let (receipts, _) = find_receipts_address(program_id, user);
+        accounts.push(AccountMeta::new(receipts, false));
+    }
+    accounts
+}
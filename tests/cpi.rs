@@ -0,0 +1,148 @@
+// the contract's queries called by another program, which reads their return data
+use borsh::BorshDeserialize;
+use hello_world::{
+    client::{add_supported_token_ix, is_token_supported_ix, set_token_flags_ix},
+    process_instruction, SupportedToken, TokenType,
+};
+use solana_program_test::{processor, tokio, BanksClient, ProgramTest};
+use solana_sdk::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    program::{get_return_data, invoke, set_return_data},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+
+mod common;
+use common::{create_mint_and_vault, initialize, mint_metadata, send};
+
+// calls the contract, accounts[0], with its own data and the other accounts, then returns what
+// the contract returned as its own return data
+fn process_caller(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let (contract, contract_accounts) = accounts
+        .split_first()
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    let instruction = Instruction::new_with_bytes(
+        *contract.key,
+        data,
+        contract_accounts
+            .iter()
+            .map(|account| AccountMeta::new_readonly(*account.key, false))
+            .collect(),
+    );
+    invoke(&instruction, accounts)?;
+    match get_return_data() {
+        Some((program_id, returned)) if program_id == *contract.key => {
+            set_return_data(&returned);
+            Ok(())
+        }
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+// the caller forwarding instruction, built for the contract, to it
+fn caller_ix(caller_id: &Pubkey, instruction: Instruction) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(instruction.program_id, false)];
+    accounts.extend(instruction.accounts);
+    Instruction::new_with_bytes(*caller_id, &instruction.data, accounts)
+}
+
+// the caller's return data when forwarding instruction, the contract's answer
+async fn call(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    caller_id: &Pubkey,
+    instruction: Instruction,
+    recent_blockhash: Hash,
+) -> Option<SupportedToken> {
+    let transaction = Transaction::new_signed_with_payer(
+        &[caller_ix(caller_id, instruction)],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+    let simulation = banks_client
+        .simulate_transaction(transaction)
+        .await
+        .unwrap();
+    simulation.result.unwrap().unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    assert_eq!(return_data.program_id, *caller_id);
+    Option::<SupportedToken>::try_from_slice(&return_data.data).unwrap()
+}
+
+#[tokio::test]
+async fn test_is_token_supported_cpi() {
+    let program_id = Pubkey::new_unique();
+    let caller_id = Pubkey::new_unique();
+    let mut program_test =
+        ProgramTest::new("hello_world", program_id, processor!(process_instruction));
+    program_test.add_program("caller", caller_id, processor!(process_caller));
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let state = initialize(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    let (mint, _) =
+        create_mint_and_vault(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: mint.pubkey(),
+    };
+    send(
+        &mut banks_client,
+        &[
+            add_supported_token_ix(&program_id, &payer.pubkey(), &token, &mint_metadata()),
+            set_token_flags_ix(&program_id, &payer.pubkey(), &token, true, false),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+
+    let supported = is_token_supported_ix(&program_id, &token);
+    assert_eq!(
+        call(
+            &mut banks_client,
+            &payer,
+            &caller_id,
+            supported.clone(),
+            recent_blockhash
+        )
+        .await,
+        Some(SupportedToken {
+            mint: mint.pubkey(),
+            deposits_enabled: true,
+            withdrawals_enabled: false,
+        })
+    );
+    // an unknown token is an answer too, not an error
+    let unknown = TokenType {
+        symbol: "ray".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    assert_eq!(
+        call(
+            &mut banks_client,
+            &payer,
+            &caller_id,
+            is_token_supported_ix(&program_id, &unknown),
+            recent_blockhash
+        )
+        .await,
+        None
+    );
+
+    // and the state is left as it was, sequence included
+    let before = banks_client.get_account(state).await.unwrap().unwrap();
+    send(
+        &mut banks_client,
+        &[caller_ix(&caller_id, supported)],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+    let after = banks_client.get_account(state).await.unwrap().unwrap();
+    assert_eq!(before.data, after.data);
+}
@@ -0,0 +1,505 @@
+// relaying a payload the user signed off-chain, verified through the ed25519 precompile
+use borsh::BorshDeserialize;
+use hello_world::{
+    client::{add_supported_token_ix, deposit_ix, redeem_voucher_ix, withdraw_ix},
+    find_balance_address, voucher_message, BalanceAccount, ContractError, ContractInstruction,
+    TokenType,
+};
+use solana_program_test::{tokio, BanksClient};
+use solana_sdk::{
+    clock::Clock,
+    ed25519_program,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    sysvar,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+mod common;
+use common::{create_mint_and_vault, create_user, mint_metadata, pack, send, setup};
+
+// precompile instruction with a single signature, pubkey, signature and message all inline
+fn ed25519_instruction(signer: &Keypair, message: &[u8]) -> Instruction {
+    let pubkey_offset: u16 = 16;
+    let signature_offset = pubkey_offset + 32;
+    let message_offset = signature_offset + 64;
+    let mut data = vec![1, 0];
+    for field in [
+        signature_offset,
+        u16::MAX,
+        pubkey_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend(field.to_le_bytes());
+    }
+    data.extend(signer.pubkey().to_bytes());
+    data.extend(signer.sign_message(message).as_ref());
+    data.extend(message);
+    Instruction {
+        program_id: ed25519_program::id(),
+        accounts: vec![],
+        data,
+    }
+}
+
+// a listed token and a user holding 1000 of it in the ledger, the user's next nonce is 1
+async fn relayed_setup() -> (
+    Pubkey,
+    Pubkey,
+    BanksClient,
+    Keypair,
+    TokenType,
+    Keypair,
+    Hash,
+) {
+    let (program_id, state, mut banks_client, payer, recent_blockhash) = setup().await;
+    let (mint, _) =
+        create_mint_and_vault(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: mint.pubkey(),
+    };
+    send(
+        &mut banks_client,
+        &[add_supported_token_ix(
+            &program_id,
+            &payer.pubkey(),
+            &token,
+            &mint_metadata(),
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+    let (user, token_account) = create_user(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        1_000,
+        recent_blockhash,
+    )
+    .await;
+    send(
+        &mut banks_client,
+        &[deposit_ix(
+            &program_id,
+            &token,
+            &user.pubkey(),
+            &token_account,
+            1_000,
+            0,
+            None,
+            None,
+            None,
+            None,
+        )],
+        &[&payer, &user],
+        recent_blockhash,
+    )
+    .await;
+    (
+        program_id,
+        state,
+        banks_client,
+        payer,
+        token,
+        user,
+        recent_blockhash,
+    )
+}
+
+// UserWithdraw of 100 to user's token account at nonce, submitted by the payer with user only
+// as a plain account
+fn relayed_withdraw(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    nonce: u64,
+) -> Instruction {
+    let token_account = get_associated_token_address(user, &token.mint);
+    let mut instruction = withdraw_ix(
+        program_id,
+        token,
+        user,
+        user,
+        &token_account,
+        100,
+        nonce,
+        None,
+        None,
+        None,
+    );
+    instruction.accounts[1].is_signer = false;
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+    instruction
+}
+
+fn relayed_transaction(
+    program_id: &Pubkey,
+    payer: &Keypair,
+    signer: &Keypair,
+    relayed: Instruction,
+    recent_blockhash: Hash,
+) -> Transaction {
+    let message = [program_id.as_ref(), &relayed.data].concat();
+    Transaction::new_signed_with_payer(
+        &[ed25519_instruction(signer, &message), relayed],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    )
+}
+
+#[tokio::test]
+async fn test_relayed_with_precompile() {
+    let (program_id, _, mut banks_client, payer, token, user, recent_blockhash) =
+        relayed_setup().await;
+    let relayed = relayed_withdraw(&program_id, &token, &user.pubkey(), 1);
+    let transaction = relayed_transaction(&program_id, &payer, &user, relayed, recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_ok());
+    assert_eq!(
+        balance_amount(&mut banks_client, &program_id, &token, &user.pubkey()).await,
+        900
+    );
+}
+
+#[tokio::test]
+async fn test_relayed_replay() {
+    let (program_id, _, mut banks_client, payer, token, user, recent_blockhash) =
+        relayed_setup().await;
+    let relayed = relayed_withdraw(&program_id, &token, &user.pubkey(), 1);
+    let transaction = relayed_transaction(
+        &program_id,
+        &payer,
+        &user,
+        relayed.clone(),
+        recent_blockhash,
+    );
+    banks_client.process_transaction(transaction).await.unwrap();
+    // the same signed payload resubmitted by another relayer, the nonce it carries was consumed
+    let (relayer, _) =
+        create_user(&mut banks_client, &payer, &token.mint, 0, recent_blockhash).await;
+    let transaction = relayed_transaction(&program_id, &relayer, &user, relayed, recent_blockhash);
+    let err = banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(ContractError::InvalidNonce as u32)
+        )
+    );
+    assert_eq!(
+        balance_amount(&mut banks_client, &program_id, &token, &user.pubkey()).await,
+        900
+    );
+}
+
+#[tokio::test]
+async fn test_relayed_without_nonce() {
+    let (program_id, state, mut banks_client, payer, recent_blockhash) = setup().await;
+    let user = Keypair::new();
+    // UserSetWithdrawDelegate carries no nonce, its signed payload is not accepted
+    let relayed = Instruction::new_with_bytes(
+        program_id,
+        &pack(&ContractInstruction::UserSetWithdrawDelegate {
+            user: user.pubkey(),
+            delegate: Some(Pubkey::new_unique()),
+        }),
+        vec![
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(user.pubkey(), false),
+            AccountMeta::new_readonly(sysvar::instructions::id(), false),
+        ],
+    );
+    let transaction = relayed_transaction(&program_id, &payer, &user, relayed, recent_blockhash);
+    let err = banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(1, InstructionError::MissingRequiredSignature)
+    );
+}
+
+#[tokio::test]
+async fn test_relayed_without_precompile() {
+    let (program_id, _, mut banks_client, payer, token, user, recent_blockhash) =
+        relayed_setup().await;
+    let transaction = Transaction::new_signed_with_payer(
+        &[relayed_withdraw(&program_id, &token, &user.pubkey(), 1)],
+        Some(&payer.pubkey()),
+        &[&payer],
+        recent_blockhash,
+    );
+    let err = banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        err,
+        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+    );
+}
+
+#[tokio::test]
+async fn test_relayed_with_precompile_over_other_payload() {
+    let (program_id, _, mut banks_client, payer, token, user, recent_blockhash) =
+        relayed_setup().await;
+    let relayed = relayed_withdraw(&program_id, &token, &user.pubkey(), 1);
+    // the same bytes without the program id prefix, or signed by someone else
+    for signed in [
+        ed25519_instruction(&user, &relayed.data),
+        ed25519_instruction(
+            &Keypair::new(),
+            &[program_id.as_ref(), &relayed.data].concat(),
+        ),
+    ] {
+        let transaction = Transaction::new_signed_with_payer(
+            &[signed, relayed.clone()],
+            Some(&payer.pubkey()),
+            &[&payer],
+            recent_blockhash,
+        );
+        let err = banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(
+            err,
+            TransactionError::InstructionError(1, InstructionError::MissingRequiredSignature)
+        );
+    }
+}
+
+// a listed token whose ledger holds 1000 of it for the admin (the payer), what vouchers pay out
+async fn voucher_setup() -> (Pubkey, BanksClient, Keypair, TokenType, Hash) {
+    let (program_id, _, mut banks_client, payer, recent_blockhash) = setup().await;
+    let (mint, _) =
+        create_mint_and_vault(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: mint.pubkey(),
+    };
+    send(
+        &mut banks_client,
+        &[add_supported_token_ix(
+            &program_id,
+            &payer.pubkey(),
+            &token,
+            &mint_metadata(),
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+    let token_account = get_associated_token_address(&payer.pubkey(), &mint.pubkey());
+    send(
+        &mut banks_client,
+        &[
+            create_associated_token_account(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &mint.pubkey(),
+                &spl_token::id(),
+            ),
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &token_account,
+                &payer.pubkey(),
+                &[],
+                1_000,
+            )
+            .unwrap(),
+            deposit_ix(
+                &program_id,
+                &token,
+                &payer.pubkey(),
+                &token_account,
+                1_000,
+                0,
+                None,
+                None,
+                None,
+                None,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+    (program_id, banks_client, payer, token, recent_blockhash)
+}
+
+// the precompile verifying signer's signature over the voucher of amount, followed by
+// RedeemVoucher for redeemed_amount carrying that signature
+#[allow(clippy::too_many_arguments)]
+fn voucher_transaction(
+    program_id: &Pubkey,
+    payer: &Keypair,
+    signer: &Keypair,
+    token: &TokenType,
+    user: &Pubkey,
+    (amount, redeemed_amount): (u64, u64),
+    voucher_id: u64,
+    expiry: i64,
+    recent_blockhash: Hash,
+) -> Transaction {
+    let message = voucher_message(program_id, token, user, amount, voucher_id, expiry);
+    let signature = signer.sign_message(&message).into();
+    Transaction::new_signed_with_payer(
+        &[
+            ed25519_instruction(signer, &message),
+            redeem_voucher_ix(
+                program_id,
+                &payer.pubkey(),
+                &payer.pubkey(),
+                token,
+                user,
+                redeemed_amount,
+                voucher_id,
+                expiry,
+                signature,
+            ),
+        ],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    )
+}
+
+async fn balance_amount(
+    banks_client: &mut BanksClient,
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+) -> u64 {
+    let (balance, _) = find_balance_address(program_id, &token.mint, user);
+    let account = banks_client.get_account(balance).await.unwrap().unwrap();
+    BalanceAccount::try_from_slice(&account.data[1..])
+        .unwrap()
+        .amount
+}
+
+fn voucher_error(error: ContractError) -> TransactionError {
+    TransactionError::InstructionError(1, InstructionError::Custom(error as u32))
+}
+
+#[tokio::test]
+async fn test_redeem_voucher() {
+    let (program_id, mut banks_client, payer, token, recent_blockhash) = voucher_setup().await;
+    let user = Pubkey::new_unique();
+    let clock: Clock = banks_client.get_sysvar().await.unwrap();
+    let expiry = clock.unix_timestamp + 3600;
+    let transaction = voucher_transaction(
+        &program_id,
+        &payer,
+        &payer,
+        &token,
+        &user,
+        (300, 300),
+        7,
+        expiry,
+        recent_blockhash,
+    );
+    banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        balance_amount(&mut banks_client, &program_id, &token, &user).await,
+        300
+    );
+    assert_eq!(
+        balance_amount(&mut banks_client, &program_id, &token, &payer.pubkey()).await,
+        700
+    );
+
+    // another voucher signed with the same id
+    let transaction = voucher_transaction(
+        &program_id,
+        &payer,
+        &payer,
+        &token,
+        &user,
+        (200, 200),
+        7,
+        expiry,
+        recent_blockhash,
+    );
+    let err = banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(err, voucher_error(ContractError::VoucherAlreadyRedeemed));
+    assert_eq!(
+        balance_amount(&mut banks_client, &program_id, &token, &user).await,
+        300
+    );
+}
+
+#[tokio::test]
+async fn test_redeem_expired_voucher() {
+    let (program_id, mut banks_client, payer, token, recent_blockhash) = voucher_setup().await;
+    let clock: Clock = banks_client.get_sysvar().await.unwrap();
+    let transaction = voucher_transaction(
+        &program_id,
+        &payer,
+        &payer,
+        &token,
+        &Pubkey::new_unique(),
+        (300, 300),
+        7,
+        clock.unix_timestamp - 1,
+        recent_blockhash,
+    );
+    let err = banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(err, voucher_error(ContractError::VoucherExpired));
+}
+
+#[tokio::test]
+async fn test_redeem_tampered_voucher() {
+    let (program_id, mut banks_client, payer, token, recent_blockhash) = voucher_setup().await;
+    let clock: Clock = banks_client.get_sysvar().await.unwrap();
+    let expiry = clock.unix_timestamp + 3600;
+    // the admin signed 300 but 900 is redeemed, or a key other than the admin's signed it
+    for (signer, amounts) in [(&payer, (300, 900)), (&Keypair::new(), (300, 300))] {
+        let transaction = voucher_transaction(
+            &program_id,
+            &payer,
+            signer,
+            &token,
+            &Pubkey::new_unique(),
+            amounts,
+            7,
+            expiry,
+            recent_blockhash,
+        );
+        let err = banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(err, voucher_error(ContractError::InvalidVoucherSignature));
+    }
+}
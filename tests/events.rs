@@ -0,0 +1,183 @@
+// events emitted through sol_log_data, decoded as an indexer would
+use std::sync::{Mutex, Once};
+
+use hello_world::{
+    client::add_supported_token_ix,
+    events::{DepositEvent, Event, TokenAddedEvent},
+    ContractInstruction, TokenType,
+};
+use solana_program_test::tokio;
+use solana_sdk::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    signature::Signer,
+};
+
+mod common;
+use common::{
+    create_mint_and_vault, create_user, mint_metadata, pack, send, setup, token_transfer_accounts,
+};
+
+// data fields of every sol_log_data call since the last take_events
+static PROGRAM_DATA: Mutex<Vec<Vec<Vec<u8>>>> = Mutex::new(Vec::new());
+
+// the native runner of solana-program-test drops sol_log_data instead of adding a
+// "Program data:" log, so its syscall stubs are wrapped to record the fields
+struct RecordingStubs(Box<dyn SyscallStubs>);
+
+impl SyscallStubs for RecordingStubs {
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let fields = fields.iter().map(|field| field.to_vec()).collect();
+        PROGRAM_DATA.lock().unwrap().push(fields);
+    }
+    fn sol_log(&self, message: &str) {
+        self.0.sol_log(message)
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        self.0
+            .sol_invoke_signed(instruction, account_infos, signers_seeds)
+    }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_clock_sysvar(var_addr)
+    }
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_schedule_sysvar(var_addr)
+    }
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_epoch_rewards_sysvar(var_addr)
+    }
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_fees_sysvar(var_addr)
+    }
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_rent_sysvar(var_addr)
+    }
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        self.0.sol_get_last_restart_slot(var_addr)
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.0.sol_get_return_data()
+    }
+    fn sol_set_return_data(&self, data: &[u8]) {
+        self.0.sol_set_return_data(data)
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        self.0.sol_get_stack_height()
+    }
+}
+
+// must run after the program test started, which installs the stubs being wrapped
+fn record_program_data() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let stubs = set_syscall_stubs(Box::new(DiscardStubs));
+        set_syscall_stubs(Box::new(RecordingStubs(stubs)));
+    });
+}
+
+// placeholder for the moment between taking the old stubs and installing the wrapper
+struct DiscardStubs;
+impl SyscallStubs for DiscardStubs {}
+
+fn take_events<E: Event>() -> Vec<E> {
+    std::mem::take(&mut *PROGRAM_DATA.lock().unwrap())
+        .iter()
+        .filter_map(|fields| {
+            let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+            E::decode(&fields)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_deposit_event() {
+    let (program_id, state, mut banks_client, payer, recent_blockhash) = setup().await;
+    record_program_data();
+    let (mint, _) =
+        create_mint_and_vault(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    let (user, user_token_account) = create_user(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        100,
+        recent_blockhash,
+    )
+    .await;
+
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: mint.pubkey(),
+    };
+    let add_token = add_supported_token_ix(&program_id, &payer.pubkey(), &token, &mint_metadata());
+    send(&mut banks_client, &[add_token], &[&payer], recent_blockhash).await;
+    assert_eq!(
+        take_events::<TokenAddedEvent>(),
+        vec![TokenAddedEvent {
+            token_symbol: "USDC".to_string(),
+            mint: mint.pubkey(),
+            // after InitializeConfig
+            sequence: 2,
+        }]
+    );
+
+    let deposit = Instruction::new_with_bytes(
+        program_id,
+        &pack(&ContractInstruction::UserDeposit {
+            token,
+            user: user.pubkey(),
+            amount: 40,
+            nonce: 0,
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: Some("invoice 17".to_string()),
+        }),
+        token_transfer_accounts(
+            &program_id,
+            &state,
+            &mint.pubkey(),
+            &user.pubkey(),
+            &user_token_account,
+            &[user.pubkey()],
+        ),
+    );
+    send(
+        &mut banks_client,
+        &[deposit],
+        &[&payer, &user],
+        recent_blockhash,
+    )
+    .await;
+    let program_data = PROGRAM_DATA.lock().unwrap().clone();
+    let slot = banks_client.get_sysvar::<Clock>().await.unwrap().slot;
+    assert_eq!(
+        take_events::<DepositEvent>(),
+        vec![DepositEvent {
+            token_symbol: "USDC".to_string(),
+            user: user.pubkey(),
+            amount: 40,
+            new_balance: 40,
+            sequence: 3,
+            referrer: None,
+            payer: user.pubkey(),
+            memo: Some("invoice 17".to_string()),
+            last_deposit_slot: slot,
+            last_withdraw_slot: 0,
+            receipt_id: 0,
+        }]
+    );
+    // a DepositEvent isn't mistaken for another event
+    assert_eq!(program_data.len(), 1);
+    let fields: Vec<&[u8]> = program_data[0].iter().map(Vec::as_slice).collect();
+    assert_eq!(fields[0], DepositEvent::NAME.as_bytes());
+    assert_eq!(TokenAddedEvent::decode(&fields), None);
+}
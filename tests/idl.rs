@@ -0,0 +1,11 @@
+use hello_world::idl::generate_idl;
+
+#[test]
+fn test_idl_is_up_to_date() {
+    let generated = serde_json::to_string_pretty(&generate_idl()).unwrap() + "\n";
+    assert!(
+        generated == include_str!("../idl/solana_test.json"),
+        "idl/solana_test.json is stale, regenerate it with \
+         `cargo run --features idl --bin idl > idl/solana_test.json`"
+    );
+}
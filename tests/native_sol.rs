@@ -0,0 +1,159 @@
+// native SOL deposits and withdrawals, lamports checked on both sides
+use hello_world::{
+    find_balance_address, find_receipts_address, find_vault_address, ContractInstruction,
+    TokenMetadata, TokenType,
+};
+use solana_program_test::{tokio, BanksClient};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+};
+
+mod common;
+use common::{pack, send, setup};
+
+async fn lamports(banks_client: &mut BanksClient, address: Pubkey) -> u64 {
+    banks_client.get_balance(address).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_native_sol_lamports() {
+    let (program_id, state, mut banks_client, payer, recent_blockhash) = setup().await;
+    let native = TokenType::native();
+    let vault = find_vault_address(&program_id, &native.mint);
+    let rent_floor = banks_client.get_rent().await.unwrap().minimum_balance(0);
+    let user = Keypair::new();
+    let (balance, _) = find_balance_address(&program_id, &native.mint, &user.pubkey());
+    let (receipts, _) = find_receipts_address(&program_id, &user.pubkey());
+
+    let add_token = Instruction::new_with_bytes(
+        program_id,
+        &pack(&ContractInstruction::AdminAddSupportedToken {
+            token: native.clone(),
+            metadata: TokenMetadata {
+                name: "Solana".to_string(),
+                decimals: 9,
+                metadata_uri: None,
+            },
+        }),
+        vec![
+            AccountMeta::new(state, false),
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let fund_user = system_instruction::transfer(&payer.pubkey(), &user.pubkey(), LAMPORTS_PER_SOL);
+    send(
+        &mut banks_client,
+        &[add_token, fund_user],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+    let vault_account = banks_client.get_account(vault).await.unwrap().unwrap();
+    assert_eq!(vault_account.owner, program_id);
+    assert_eq!(vault_account.lamports, rent_floor);
+
+    // the payer covers the fees, so the user's lamports only move with the program
+    let user_accounts = vec![
+        AccountMeta::new(state, false),
+        AccountMeta::new(user.pubkey(), true),
+        AccountMeta::new(user.pubkey(), true),
+        AccountMeta::new(vault, false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(balance, false),
+        AccountMeta::new(receipts, false),
+    ];
+    let deposit = Instruction::new_with_bytes(
+        program_id,
+        &pack(&ContractInstruction::UserDeposit {
+            token: native.clone(),
+            user: user.pubkey(),
+            amount: 400_000_000,
+            nonce: 0,
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        }),
+        user_accounts.clone(),
+    );
+    send(
+        &mut banks_client,
+        &[deposit],
+        &[&payer, &user],
+        recent_blockhash,
+    )
+    .await;
+    // the user also paid the rent of its new balance and receipts accounts
+    let receipts_rent = lamports(&mut banks_client, receipts).await;
+    let rent = lamports(&mut banks_client, balance).await + receipts_rent;
+    assert!(receipts_rent > 0);
+    assert_eq!(
+        lamports(&mut banks_client, user.pubkey()).await,
+        LAMPORTS_PER_SOL - 400_000_000 - rent
+    );
+    assert_eq!(
+        lamports(&mut banks_client, vault).await,
+        rent_floor + 400_000_000
+    );
+
+    let withdraw = Instruction::new_with_bytes(
+        program_id,
+        &pack(&ContractInstruction::UserWithdraw {
+            token: native.clone(),
+            user: user.pubkey(),
+            amount: 100_000_000,
+            nonce: 1,
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        }),
+        user_accounts.clone(),
+    );
+    send(
+        &mut banks_client,
+        &[withdraw],
+        &[&payer, &user],
+        recent_blockhash,
+    )
+    .await;
+    assert_eq!(
+        lamports(&mut banks_client, user.pubkey()).await,
+        LAMPORTS_PER_SOL - 300_000_000 - rent
+    );
+    assert_eq!(
+        lamports(&mut banks_client, vault).await,
+        rent_floor + 300_000_000
+    );
+
+    // withdrawing everything leaves the vault at exactly its rent-exempt minimum and refunds
+    // the balance account
+    let withdraw_all = Instruction::new_with_bytes(
+        program_id,
+        &pack(&ContractInstruction::UserWithdrawAll {
+            token: native,
+            user: user.pubkey(),
+        }),
+        user_accounts,
+    );
+    send(
+        &mut banks_client,
+        &[withdraw_all],
+        &[&payer, &user],
+        recent_blockhash,
+    )
+    .await;
+    // the receipts account stays
+    assert_eq!(
+        lamports(&mut banks_client, user.pubkey()).await,
+        LAMPORTS_PER_SOL - receipts_rent
+    );
+    assert_eq!(lamports(&mut banks_client, vault).await, rent_floor);
+    assert_eq!(lamports(&mut banks_client, balance).await, 0);
+}
@@ -0,0 +1,9264 @@
+// processor tests on host-side accounts, the runtime syscalls the program makes are stubbed
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use hello_world::events::{
+    AirdropClaimedEvent, CircuitBreakerTrippedEvent, DepositEvent, Event, RewardsClaimedEvent,
+    SlashEvent, SweepEvent, TokenAddedEvent, TokenRenamedEvent, WithdrawEvent,
+};
+use hello_world::oracle::{
+    PYTH_ACCOUNT_TYPE_OFFSET, PYTH_ACCOUNT_TYPE_PRICE, PYTH_EXPONENT_OFFSET, PYTH_MAGIC,
+    PYTH_MAGIC_OFFSET, PYTH_PRICE_ACCOUNT_MIN_LEN, PYTH_PRICE_OFFSET, PYTH_PUBLISH_SLOT_OFFSET,
+    PYTH_STATUS_OFFSET, PYTH_STATUS_TRADING, PYTH_VERSION, PYTH_VERSION_OFFSET,
+};
+use hello_world::ContractInstruction::{
+    AcceptAdmin, AdminAddSupportedToken, AdminAddSupportedTokens, AdminCancelProposedAdmin,
+    AdminCancelWithdrawRequest, AdminDeleteSupportedToken, AdminDepositVested, AdminDonateYield,
+    AdminEmergencySweep, AdminEnableShareMode, AdminExecuteAction, AdminForceDeleteToken,
+    AdminFreezeUser, AdminPause, AdminProposeNewAdmin, AdminQueueAction, AdminRenameToken,
+    AdminResetBreaker, AdminSetBlocked, AdminSetCircuitBreaker, AdminSetDailyWithdrawalLimit,
+    AdminSetDepositCap, AdminSetDepositFee, AdminSetDisplayPrecision, AdminSetDustThreshold,
+    AdminSetFeeExempt, AdminSetGlobalCap, AdminSetMaxTokens, AdminSetMaxUserBalance,
+    AdminSetMerkleRoot, AdminSetMinDeposit, AdminSetMinWithdraw, AdminSetOperator,
+    AdminSetPriceFeed, AdminSetRateLimit, AdminSetRewardRate, AdminSetSwapRate,
+    AdminSetTimelockDelay, AdminSetTokenFlags, AdminSetTreasury, AdminSetWithdrawCooldown,
+    AdminSetWithdrawDelay, AdminSetWithdrawalFee, AdminSlash, AdminSnapshotState,
+    AdminUnfreezeUser, AdminUnpause, AdminUpdateTokenMetadata, AdminWithdrawTreasury, BatchDeposit,
+    BatchWithdraw, ClaimAirdrop, ClaimRewards, CloseUserBalance, GetAuditLog, GetBalance,
+    GetGlobalStats, GetNonce, GetQueuePosition, GetReceipt, GetReferralStats, GetRewardPoints,
+    GetSequence, GetTokenStats, GetUserStats, InitializeConfig, InitializeState, IsTokenSupported,
+    LinkEthAddress, ListSupportedTokens, MigrateState, Multicall, ProcessWithdrawQueue,
+    TransferFrom, UserApprove, UserCancelQueuedWithdraw, UserClaimWithdraw, UserDeposit,
+    UserEnqueueWithdraw, UserRequestWithdraw, UserSetWithdrawDelegate, UserSwap, UserTransfer,
+    UserWithdraw, UserWithdrawAll,
+};
+use hello_world::{
+    airdrop_leaf, amount_for_shares, decode_snapshot, deserialize_instruction, eth_link_message,
+    eth_withdraw_message, find_balance_address, find_receipts_address, find_vault_address,
+    find_vault_address_with_program_id, find_vault_authority, load_balances, load_state,
+    merkle_parent, process_instruction, shares_for_amount, token_symbol_hash,
+    validate_token_metadata, validate_token_symbol, AuditPage, BalanceAccount, BalanceAccountData,
+    BalanceSlots, ContractError, ContractInstruction, ContractState, DepositReceipt, GlobalStats,
+    RateBuckets, RateWindow, ReferralStats, SupportedToken, TokenMetadata, TokenPage, TokenStats,
+    TokenType, UserBalance, UserStats, AUDIT_LOG_CAPACITY, BALANCE_ACCOUNT_SIZE,
+    BALANCE_ACCOUNT_TAG, BALANCE_ACCOUNT_V0_SIZE, DEFAULT_TIMELOCK_DELAY_SLOTS,
+    INSTRUCTION_VERSION_BORSH, INSTRUCTION_VERSION_JSON, MAX_AUDIT_ENTRIES_PER_PAGE, MAX_DECIMALS,
+    MAX_INSTRUCTION_DATA_LEN, MAX_LOCKED_LOTS, MAX_METADATA_URI_LEN, MAX_RECEIPTS_PER_USER,
+    MAX_SYMBOL_LEN, MAX_TOKENS_PER_PAGE, MAX_TOKEN_NAME_LEN, MAX_VESTING_SCHEDULES,
+    MIN_TIMELOCK_DELAY_SLOTS, RECEIPTS_ACCOUNT_MAX_SIZE, RECEIPTS_HEADER_LEN, SNAPSHOT_HEADER_LEN,
+    STATE_ACCOUNT_SIZE, STATE_DISCRIMINATOR, STATE_HEADER_LEN, STATE_SEED, STATE_VERSION,
+    TIMELOCK_GRACE_SLOTS,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::Clock;
+use solana_program::entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE, SUCCESS};
+use solana_program::hash::hash;
+use solana_program::instruction::Instruction;
+use solana_program::program::{get_return_data, MAX_RETURN_DATA};
+use solana_program::program_error::ProgramError;
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+use solana_program::program_utils::limited_deserialize;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_program::system_instruction::SystemInstruction;
+use solana_program::system_program;
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token::error::TokenError;
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+use spl_token_2022::extension::non_transferable::NonTransferable;
+use spl_token_2022::extension::transfer_fee::{TransferFee, TransferFeeAmount, TransferFeeConfig};
+use spl_token_2022::extension::{ExtensionType, StateWithExtensions, StateWithExtensionsMut};
+use spl_token_2022::state::{
+    Account as Account2022, AccountState as AccountState2022, Mint as Mint2022,
+};
+use std::cell::{Cell, RefCell};
+use std::str::FromStr;
+use std::sync::Once;
+
+// admin configured by initialized_state_account
+const TEST_ADMIN: Pubkey = Pubkey::new_from_array([7; 32]);
+// lamports of the signer of user_token_accounts
+const USER_LAMPORTS: u64 = 1_000_000_000;
+
+thread_local! {
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    // data fields of every sol_log_data call
+    static PROGRAM_DATA: RefCell<Vec<Vec<Vec<u8>>>> = const { RefCell::new(Vec::new()) };
+    // slot and unix_timestamp served by the Clock sysvar
+    static SLOT: Cell<u64> = const { Cell::new(0) };
+    static UNIX_TIMESTAMP: Cell<i64> = const { Cell::new(0) };
+    // program under test, needed to check PDA signer seeds of CPIs
+    static PROGRAM_ID: Cell<Pubkey> = const { Cell::new(Pubkey::new_from_array([0; 32])) };
+    // balance and receipts PDAs handed out by balance_account and receipts_account, by address
+    static BALANCE_ACCOUNTS: RefCell<HashMap<Pubkey, AccountInfo<'static>>> =
+        RefCell::new(HashMap::new());
+}
+
+// host stand-in for the runtime syscalls the program uses, state is per test thread
+struct TestSyscallStubs;
+
+impl SyscallStubs for TestSyscallStubs {
+    fn sol_log(&self, message: &str) {
+        LOGS.with(|l| l.borrow_mut().push(message.to_string()));
+    }
+
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let fields = fields.iter().map(|field| field.to_vec()).collect();
+        PROGRAM_DATA.with(|d| d.borrow_mut().push(fields));
+    }
+
+    fn sol_set_return_data(&self, data: &[u8]) {
+        RETURN_DATA.with(|r| *r.borrow_mut() = Some((Pubkey::default(), data.to_vec())));
+    }
+
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.with(|r| r.borrow().clone())
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { std::ptr::write(var_addr as *mut Rent, Rent::default()) };
+        SUCCESS
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            slot: SLOT.with(|s| s.get()),
+            unix_timestamp: UNIX_TIMESTAMP.with(|t| t.get()),
+            ..Clock::default()
+        };
+        unsafe { std::ptr::write(var_addr as *mut Clock, clock) };
+        SUCCESS
+    }
+
+    // run CPIs into the SPL token and memo programs in-process
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let program_id = PROGRAM_ID.with(|p| p.get());
+        let pda_signers = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &program_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut cpi_accounts = Vec::new();
+        for meta in &instruction.accounts {
+            let mut info = account_infos
+                .iter()
+                .find(|a| a.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            info.is_signer |= pda_signers.contains(info.key);
+            if meta.is_signer && !info.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            cpi_accounts.push(info);
+        }
+        if instruction.program_id == spl_token::id() {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &cpi_accounts,
+                &instruction.data,
+            )
+        } else if instruction.program_id == spl_token_2022::id() {
+            spl_token_2022::processor::Processor::process(
+                &instruction.program_id,
+                &cpi_accounts,
+                &instruction.data,
+            )
+        } else if instruction.program_id == spl_memo::id() {
+            spl_memo::processor::process_instruction(
+                &instruction.program_id,
+                &cpi_accounts,
+                &instruction.data,
+            )
+        } else if instruction.program_id == system_program::id() {
+            // test accounts are allocated up front and hold no lamports, so creating one
+            // only funds and assigns it
+            match limited_deserialize(&instruction.data, 1024) {
+                Ok(SystemInstruction::CreateAccount {
+                    lamports,
+                    space,
+                    owner,
+                }) => {
+                    let account = &cpi_accounts[1];
+                    if account.lamports() > 0 || account.data_len() != space as usize {
+                        return Err(ProgramError::AccountAlreadyInitialized);
+                    }
+                    **account.lamports.borrow_mut() = lamports;
+                    account.assign(&owner);
+                    Ok(())
+                }
+                Ok(SystemInstruction::Transfer { lamports }) => {
+                    let (from, to) = (&cpi_accounts[0], &cpi_accounts[1]);
+                    if from.owner != &system_program::id() {
+                        return Err(ProgramError::InvalidAccountOwner);
+                    }
+                    let remaining = from
+                        .lamports()
+                        .checked_sub(lamports)
+                        .ok_or(ProgramError::InsufficientFunds)?;
+                    **from.lamports.borrow_mut() = remaining;
+                    **to.lamports.borrow_mut() += lamports;
+                    Ok(())
+                }
+                Ok(SystemInstruction::Assign { owner }) => {
+                    cpi_accounts[0].assign(&owner);
+                    Ok(())
+                }
+                _ => Err(ProgramError::InvalidInstructionData),
+            }
+        } else {
+            Err(ProgramError::IncorrectProgramId)
+        }
+    }
+}
+
+fn install_syscall_stubs() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        set_syscall_stubs(Box::new(TestSyscallStubs));
+    });
+}
+
+fn set_slot(slot: u64) {
+    SLOT.with(|s| s.set(slot));
+}
+
+fn set_unix_timestamp(unix_timestamp: i64) {
+    UNIX_TIMESTAMP.with(|t| t.set(unix_timestamp));
+}
+
+// drain the messages logged on this test thread
+fn take_logs() -> Vec<String> {
+    LOGS.with(|l| l.take())
+}
+
+// events of type E logged since the last call, other events are dropped
+fn take_events<E: Event>() -> Vec<E> {
+    PROGRAM_DATA
+        .with(|d| d.take())
+        .iter()
+        .filter_map(|fields| {
+            let fields: Vec<&[u8]> = fields.iter().map(Vec::as_slice).collect();
+            E::decode(&fields)
+        })
+        .collect()
+}
+
+// fresh program id, registered with the syscall stubs of this test thread
+fn new_program_id() -> Pubkey {
+    install_syscall_stubs();
+    let program_id = Pubkey::new_unique();
+    PROGRAM_ID.with(|p| p.set(program_id));
+    program_id
+}
+
+// AccountInfo borrows everything it points at, leak the backing storage to keep tests short
+fn new_account(
+    key: Pubkey,
+    is_signer: bool,
+    data_len: usize,
+    owner: Pubkey,
+) -> AccountInfo<'static> {
+    AccountInfo::new(
+        Box::leak(Box::new(key)),
+        is_signer,
+        true,
+        Box::leak(Box::new(0)),
+        Box::leak(vec![0u8; data_len].into_boxed_slice()),
+        Box::leak(Box::new(owner)),
+        false,
+        0,
+    )
+}
+
+// rent-exempt for its size, like a state account created by InitializeState
+fn new_state_account(program_id: &Pubkey, data_len: usize) -> AccountInfo<'static> {
+    let (state_key, _) = Pubkey::find_program_address(&[STATE_SEED], program_id);
+    let account = new_account(state_key, false, data_len, *program_id);
+    **account.lamports.borrow_mut() = Rent::default().minimum_balance(data_len);
+    account
+}
+
+// new_state_account laid out the way the runtime serializes a program's accounts, so that
+// AccountInfo::realloc can grow it: the length it started with as a u32 right before the key,
+// the data length as a u64 right before the data and MAX_PERMITTED_DATA_INCREASE bytes to spare
+// after it, the length it started with stays data_len for the whole test
+fn new_growable_state_account(program_id: &Pubkey, data_len: usize) -> AccountInfo<'static> {
+    let (state_key, _) = Pubkey::find_program_address(&[STATE_SEED], program_id);
+    new_growable_account(state_key, program_id, data_len)
+}
+
+fn new_growable_account(key: Pubkey, owner: &Pubkey, data_len: usize) -> AccountInfo<'static> {
+    new_account_growing_to(key, owner, data_len, data_len + MAX_PERMITTED_DATA_INCREASE)
+}
+
+// an account that can be grown to max_len over the whole test, the length it started with is
+// set that far back, where the runtime instead lets each instruction add
+// MAX_PERMITTED_DATA_INCREASE bytes
+fn new_account_growing_to(
+    key: Pubkey,
+    owner: &Pubkey,
+    data_len: usize,
+    max_len: usize,
+) -> AccountInfo<'static> {
+    let key_buffer = Box::leak(vec![0u8; 4 + 32].into_boxed_slice());
+    let original_len = max_len - MAX_PERMITTED_DATA_INCREASE;
+    key_buffer[..4].copy_from_slice(&(original_len as u32).to_le_bytes());
+    key_buffer[4..].copy_from_slice(key.as_ref());
+    let key = unsafe { &*(key_buffer[4..].as_ptr() as *const Pubkey) };
+    // u64 words keep the length in front of the data aligned
+    let words = Box::leak(vec![0u64; 1 + max_len.div_ceil(8)].into_boxed_slice());
+    words[0] = data_len as u64;
+    let data =
+        unsafe { std::slice::from_raw_parts_mut((words.as_mut_ptr() as *mut u8).add(8), data_len) };
+    AccountInfo::new(
+        key,
+        false,
+        true,
+        Box::leak(Box::new(Rent::default().minimum_balance(data_len))),
+        data,
+        Box::leak(Box::new(*owner)),
+        false,
+        0,
+    )
+}
+
+// state account with InitializeState and InitializeConfig { admin: TEST_ADMIN } applied
+fn initialized_state_account(program_id: &Pubkey) -> AccountInfo<'static> {
+    initialize(
+        program_id,
+        new_state_account(program_id, STATE_ACCOUNT_SIZE),
+    )
+}
+
+// for tests filling the audit log while their instructions, unlike the client
+// builders', don't pass the system program to grow the state account
+fn initialized_large_state_account(program_id: &Pubkey) -> AccountInfo<'static> {
+    initialize(
+        program_id,
+        new_state_account(program_id, 4 * STATE_ACCOUNT_SIZE),
+    )
+}
+
+fn initialize(program_id: &Pubkey, state_account: AccountInfo<'static>) -> AccountInfo<'static> {
+    let instruction_data: Vec<u8> = pack(&InitializeState);
+    process_instruction(
+        program_id,
+        std::slice::from_ref(&state_account),
+        &instruction_data,
+    )
+    .unwrap();
+    let instruction_data = pack(&InitializeConfig { admin: TEST_ADMIN });
+    let accounts = [state_account.clone(), admin_account(true)];
+    process_instruction(program_id, &accounts, &instruction_data).unwrap();
+    state_account
+}
+
+// the one balance account of (mint, user) on this test thread, uncreated at first
+fn balance_account(program_id: &Pubkey, mint: &Pubkey, user: &Pubkey) -> AccountInfo<'static> {
+    let (address, _) = find_balance_address(program_id, mint, user);
+    BALANCE_ACCOUNTS.with(|accounts| {
+        accounts
+            .borrow_mut()
+            .entry(address)
+            .or_insert_with(|| {
+                new_account(address, false, BALANCE_ACCOUNT_SIZE, system_program::id())
+            })
+            .clone()
+    })
+}
+
+// the one receipts account of user on this test thread, uncreated at first
+fn receipts_account(program_id: &Pubkey, user: &Pubkey) -> AccountInfo<'static> {
+    let (address, _) = find_receipts_address(program_id, user);
+    BALANCE_ACCOUNTS.with(|accounts| {
+        accounts
+            .borrow_mut()
+            .entry(address)
+            .or_insert_with(|| {
+                let account = new_account_growing_to(
+                    address,
+                    &system_program::id(),
+                    RECEIPTS_HEADER_LEN,
+                    RECEIPTS_ACCOUNT_MAX_SIZE,
+                );
+                **account.lamports.borrow_mut() = 0;
+                account
+            })
+            .clone()
+    })
+}
+
+fn system_program_account() -> AccountInfo<'static> {
+    new_account(system_program::id(), false, 0, Pubkey::default())
+}
+
+// state with the balance of every balance account on this test thread filled in, as the
+// program sees it when all of them are passed
+fn load_full_state(state_account: &AccountInfo) -> Result<ContractState, ProgramError> {
+    let mut state = load_state(state_account)?;
+    let accounts: Vec<_> =
+        BALANCE_ACCOUNTS.with(|accounts| accounts.borrow().values().cloned().collect());
+    load_balances(state_account.owner, &accounts, &mut state)?;
+    Ok(state)
+}
+
+fn admin_account(is_signer: bool) -> AccountInfo<'static> {
+    new_account(TEST_ADMIN, is_signer, 0, Pubkey::default())
+}
+
+// mint of the test token with symbol, every symbol gets its own fixed mint
+fn token(symbol: &str) -> TokenType {
+    TokenType {
+        symbol: symbol.to_string(),
+        mint: Pubkey::new_from_array(hash(symbol.as_bytes()).to_bytes()),
+    }
+}
+
+fn new_mint_account(symbol: &str) -> AccountInfo<'static> {
+    let account = new_account(token(symbol).mint, false, Mint::LEN, spl_token::id());
+    let mint = Mint {
+        mint_authority: COption::None,
+        supply: 0,
+        decimals: 9,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    mint.pack_into_slice(&mut account.data.borrow_mut());
+    account
+}
+
+// Token-2022 mint of the test token with symbol charging fee_bps on every transfer, with the
+// decimals of new_mint_account, non_transferable adds an extension vaults can't support
+fn new_mint_2022_account(
+    symbol: &str,
+    fee_bps: u16,
+    non_transferable: bool,
+) -> AccountInfo<'static> {
+    let mut extensions = vec![ExtensionType::TransferFeeConfig];
+    if non_transferable {
+        extensions.push(ExtensionType::NonTransferable);
+    }
+    let len = ExtensionType::try_calculate_account_len::<Mint2022>(&extensions).unwrap();
+    let account = new_account(token(symbol).mint, false, len, spl_token_2022::id());
+    {
+        let mut data = account.data.borrow_mut();
+        let mut mint = StateWithExtensionsMut::<Mint2022>::unpack_uninitialized(&mut data).unwrap();
+        let fee = TransferFee {
+            epoch: 0.into(),
+            maximum_fee: u64::MAX.into(),
+            transfer_fee_basis_points: fee_bps.into(),
+        };
+        let config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = fee;
+        config.newer_transfer_fee = fee;
+        if non_transferable {
+            mint.init_extension::<NonTransferable>(true).unwrap();
+        }
+        mint.base = Mint2022 {
+            decimals: 9,
+            is_initialized: true,
+            ..Mint2022::default()
+        };
+        mint.pack_base();
+        mint.init_account_type().unwrap();
+    }
+    account
+}
+
+// Token-2022 account of a mint with a transfer fee, it needs room for the withheld fees
+fn new_token_2022_account_at(
+    key: Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> AccountInfo<'static> {
+    let len = ExtensionType::try_calculate_account_len::<Account2022>(&[
+        ExtensionType::TransferFeeAmount,
+    ])
+    .unwrap();
+    let account = new_account(key, false, len, spl_token_2022::id());
+    {
+        let mut data = account.data.borrow_mut();
+        let mut token_account =
+            StateWithExtensionsMut::<Account2022>::unpack_uninitialized(&mut data).unwrap();
+        token_account
+            .init_extension::<TransferFeeAmount>(true)
+            .unwrap();
+        token_account.base = Account2022 {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            state: AccountState2022::Initialized,
+            ..Account2022::default()
+        };
+        token_account.pack_base();
+        token_account.init_account_type().unwrap();
+    }
+    account
+}
+
+fn token_2022_amount(account: &AccountInfo) -> u64 {
+    StateWithExtensions::<Account2022>::unpack(&account.data.borrow())
+        .unwrap()
+        .base
+        .amount
+}
+
+fn new_token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> AccountInfo<'static> {
+    new_token_account_at(Pubkey::new_unique(), mint, owner, amount)
+}
+
+fn new_token_account_at(
+    key: Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> AccountInfo<'static> {
+    let account = new_account(key, false, TokenAccount::LEN, spl_token::id());
+    let token_account = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    token_account.pack_into_slice(&mut account.data.borrow_mut());
+    account
+}
+
+// empty vault of mint at its associated token account address
+fn new_vault_account(program_id: &Pubkey, mint: &Pubkey) -> AccountInfo<'static> {
+    let (vault_authority, _) = find_vault_authority(program_id, mint);
+    new_token_account_at(
+        find_vault_address(program_id, mint),
+        mint,
+        &vault_authority,
+        0,
+    )
+}
+
+fn token_program_account() -> AccountInfo<'static> {
+    new_account(spl_token::id(), false, 0, Pubkey::default())
+}
+
+fn token_amount(account: &AccountInfo) -> u64 {
+    TokenAccount::unpack(&account.data.borrow()).unwrap().amount
+}
+
+// metadata every test token is listed with, the decimals of new_mint_account
+fn metadata() -> TokenMetadata {
+    TokenMetadata {
+        name: "Test Token".to_string(),
+        decimals: 9,
+        metadata_uri: None,
+    }
+}
+
+// register symbol as a supported token backed by a fresh mint, returns (mint, vault)
+fn add_token(
+    program_id: &Pubkey,
+    state_account: &AccountInfo<'static>,
+    symbol: &str,
+) -> (AccountInfo<'static>, AccountInfo<'static>) {
+    let mint = new_mint_account(symbol);
+    let vault = new_vault_account(program_id, mint.key);
+    let instruction_data = pack(&AdminAddSupportedToken {
+        token: token(symbol),
+        metadata: metadata(),
+    });
+    let accounts = [
+        state_account.clone(),
+        admin_account(true),
+        mint.clone(),
+        vault.clone(),
+    ];
+    process_instruction(program_id, &accounts, &instruction_data).unwrap();
+    (mint, vault)
+}
+
+// instruction data in the current wire format
+fn pack(instruction: &ContractInstruction) -> Vec<u8> {
+    instruction.pack()
+}
+
+// run a timelocked instruction the way it has to be: queued by accounts[1], then executed with
+// accounts once the delay passed, the slot is put back afterwards
+fn process_timelocked(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo<'static>],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let action = Box::new(deserialize_instruction(instruction_data)?);
+    let slot = SLOT.with(Cell::get);
+    let eta_slot = slot + load_state(&accounts[0])?.timelock_delay_slots;
+    let queue_data = pack(&AdminQueueAction {
+        action: action.clone(),
+        eta_slot,
+    });
+    // an action that failed to execute stays queued
+    match process_instruction(program_id, &accounts[..2], &queue_data) {
+        Ok(()) => {}
+        Err(error) if error == ContractError::ActionAlreadyQueued.into() => {}
+        Err(error) => return Err(error),
+    }
+    set_slot(eta_slot);
+    let result = process_instruction(program_id, accounts, &pack(&AdminExecuteAction { action }));
+    set_slot(slot);
+    result
+}
+
+// the nonce user's next deposit or withdraw of mint must carry
+fn nonce_of(state_account: &AccountInfo, mint: &Pubkey, user: Pubkey) -> u64 {
+    let state = load_state(state_account).unwrap();
+    let account = balance_account(state_account.owner, mint, &user);
+    let data = account.data.borrow();
+    state.balance_nonce(&user, &data)
+}
+
+fn deposit_data(state_account: &AccountInfo, symbol: &str, user: Pubkey, amount: u64) -> Vec<u8> {
+    pack(&UserDeposit {
+        token: token(symbol),
+        user,
+        amount,
+        nonce: nonce_of(state_account, &token(symbol).mint, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    })
+}
+
+fn withdraw_data(state_account: &AccountInfo, symbol: &str, user: Pubkey, amount: u64) -> Vec<u8> {
+    pack(&UserWithdraw {
+        token: token(symbol),
+        user,
+        amount,
+        nonce: nonce_of(state_account, &token(symbol).mint, user),
+        eth_signature: None,
+        recipient: None,
+        memo: None,
+    })
+}
+
+// user signing, with USER_LAMPORTS to pay for growing their receipts account
+fn user_signer(user: &Pubkey) -> AccountInfo<'static> {
+    let signer = new_account(*user, true, 0, Pubkey::default());
+    **signer.lamports.borrow_mut() = USER_LAMPORTS;
+    signer
+}
+
+// [state, user, user_token_account, vault, vault_authority, token_program] as expected by
+// deposit/withdraw, followed by user's balance account, the system program and user's receipts
+// account
+fn user_token_accounts(
+    state_account: &AccountInfo<'static>,
+    user: &Pubkey,
+    user_token_account: &AccountInfo<'static>,
+    vault: &AccountInfo<'static>,
+) -> Vec<AccountInfo<'static>> {
+    let vault_state = TokenAccount::unpack(&vault.data.borrow()).unwrap();
+    vec![
+        state_account.clone(),
+        user_signer(user),
+        user_token_account.clone(),
+        vault.clone(),
+        new_account(vault_state.owner, false, 0, Pubkey::default()),
+        token_program_account(),
+        balance_account(state_account.owner, &vault_state.mint, user),
+        system_program_account(),
+        receipts_account(state_account.owner, user),
+    ]
+}
+
+#[test]
+fn test_add_delete_deposit_withdraw() {
+    let program_id = new_program_id();
+    let state_account = new_state_account(&program_id, STATE_ACCOUNT_SIZE);
+    let mint = new_mint_account("ray");
+    let vault = new_vault_account(&program_id, mint.key);
+    let accounts = vec![
+        state_account.clone(),
+        admin_account(true),
+        mint.clone(),
+        vault.clone(),
+    ];
+    let delete_accounts = [state_account.clone(), admin_account(true), vault.clone()];
+    let user_token_account = new_token_account(mint.key, &Pubkey::default(), 1000);
+    let user_accounts = user_token_accounts(
+        &state_account,
+        &Pubkey::default(),
+        &user_token_account,
+        &vault,
+    );
+
+    {
+        // state account not initialized yet
+        println!("uninitialized state");
+        let instruction_data = AdminAddSupportedToken {
+            token: token("ray"),
+            metadata: metadata(),
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Err(ProgramError::UninitializedAccount));
+    }
+
+    {
+        // initialize state
+        println!("initialize state");
+        let instruction_data: Vec<u8> = pack(&InitializeState);
+
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Ok(()));
+        assert!(load_full_state(&accounts[0])
+            .unwrap()
+            .all_token_balances
+            .is_empty());
+
+        // second initialization must not wipe the state
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Err(ProgramError::AccountAlreadyInitialized));
+    }
+
+    {
+        // initialize config
+        println!("initialize config");
+        let instruction_data = pack(&InitializeConfig { admin: TEST_ADMIN });
+
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Ok(()));
+    }
+
+    {
+        // legal add
+        println!("legal add");
+        let instruction_data = AdminAddSupportedToken {
+            token: token("ray"),
+            metadata: metadata(),
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Ok(()));
+
+        let state = load_full_state(&accounts[0]).unwrap();
+        let all_token_balances = &state.all_token_balances;
+        let ray = all_token_balances.get(&token("ray"));
+        assert_eq!(ray.map(|entry| &entry.balances), Some(&BTreeMap::new()));
+    }
+
+    {
+        // duplicate add
+        println!("duplicate add");
+        let instruction_data = AdminAddSupportedToken {
+            token: token("ray"),
+            metadata: metadata(),
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        // legal add
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Err(ContractError::MintAlreadyRegistered.into()));
+    }
+
+    {
+        // deposit ray token
+        println!("user ray token deposit");
+        let instruction_data = UserDeposit {
+            token: token("ray"),
+            user: Pubkey::default(),
+            amount: 100,
+            nonce: nonce_of(&state_account, mint.key, Pubkey::default()),
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        let result = process_instruction(&program_id, &user_accounts, &instruction_data);
+        assert_eq!(result, Ok(()));
+
+        let state = load_full_state(&accounts[0]).unwrap();
+        let all_token_balances = &state.all_token_balances;
+        let ray = all_token_balances.get(&token("ray"));
+        assert_eq!(
+            ray.map(|entry| &entry.balances),
+            Some(&BTreeMap::from([(Pubkey::default(), 100)]))
+        );
+        assert_eq!(token_amount(&user_token_account), 900);
+        assert_eq!(token_amount(&vault), 100);
+    }
+
+    {
+        // withdraw ray token
+        println!("user ray token withdraw");
+        let instruction_data = UserWithdraw {
+            token: token("ray"),
+            user: Pubkey::default(),
+            amount: 10,
+            nonce: nonce_of(&state_account, mint.key, Pubkey::default()),
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        let result = process_instruction(&program_id, &user_accounts, &instruction_data);
+        assert_eq!(result, Ok(()));
+
+        let state = load_full_state(&accounts[0]).unwrap();
+        let all_token_balances = &state.all_token_balances;
+        let ray = all_token_balances.get(&token("ray"));
+        assert_eq!(
+            ray.map(|entry| &entry.balances),
+            Some(&BTreeMap::from([(Pubkey::default(), 90)]))
+        );
+        assert_eq!(token_amount(&user_token_account), 910);
+        assert_eq!(token_amount(&vault), 90);
+    }
+
+    {
+        // illegal withdraw ray token
+        println!("illegal user ray token withdraw");
+        let instruction_data = UserWithdraw {
+            token: token("ray"),
+            user: Pubkey::default(),
+            amount: 100,
+            nonce: nonce_of(&state_account, mint.key, Pubkey::default()),
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        let result = process_instruction(&program_id, &user_accounts, &instruction_data);
+        assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    }
+
+    {
+        // illegal delete, user still holds ray
+        println!("delete with outstanding balance");
+        let instruction_del_data = AdminDeleteSupportedToken {
+            token: token("ray"),
+        };
+        let instruction_del_data: Vec<u8> = pack(&instruction_del_data);
+        let result = process_timelocked(&program_id, &delete_accounts, &instruction_del_data);
+        assert_eq!(
+            result,
+            Err(ContractError::TokenHasOutstandingBalances.into())
+        );
+    }
+
+    {
+        // withdraw the rest of ray token
+        println!("user ray token withdraw rest");
+        let instruction_data = UserWithdraw {
+            token: token("ray"),
+            user: Pubkey::default(),
+            amount: 90,
+            nonce: nonce_of(&state_account, mint.key, Pubkey::default()),
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        };
+        let instruction_data: Vec<u8> = pack(&instruction_data);
+
+        let result = process_instruction(&program_id, &user_accounts, &instruction_data);
+        assert_eq!(result, Ok(()));
+    }
+
+    {
+        println!("legal delete");
+        let instruction_del_data = AdminDeleteSupportedToken {
+            token: token("ray"),
+        };
+        let instruction_del_data: Vec<u8> = pack(&instruction_del_data);
+        let result = process_timelocked(&program_id, &delete_accounts, &instruction_del_data);
+        assert_eq!(result, Ok(()));
+
+        let state = load_full_state(&accounts[0]).unwrap();
+        let all_token_balances = &state.all_token_balances;
+        let ray = all_token_balances.get(&token("ray"));
+        assert_eq!(ray, None);
+    }
+
+    {
+        // illegal delete
+        println!("illegal delete");
+        let instruction_del_data = AdminDeleteSupportedToken {
+            token: token("sool"),
+        };
+        let instruction_del_data: Vec<u8> = pack(&instruction_del_data);
+        let result = process_timelocked(&program_id, &delete_accounts, &instruction_del_data);
+        assert_eq!(result, Err(ContractError::TokenNotFound.into()));
+    }
+}
+
+#[test]
+fn test_state_account_too_small() {
+    let program_id = Pubkey::new_unique();
+    let accounts = vec![new_state_account(&program_id, 3)];
+
+    let instruction_data: Vec<u8> = pack(&InitializeState);
+    let result = process_instruction(&program_id, &accounts, &instruction_data);
+    assert_eq!(result, Err(ProgramError::AccountDataTooSmall));
+}
+
+#[test]
+fn test_state_account_growth() {
+    let program_id = new_program_id();
+    let state_account = initialize(
+        &program_id,
+        new_growable_state_account(&program_id, STATE_ACCOUNT_SIZE),
+    );
+    let admin = admin_account(true);
+    **admin.lamports.borrow_mut() = 1_000_000_000;
+    let set_max_data = pack(&AdminSetMaxTokens { max_tokens: 255 });
+    process_instruction(
+        &program_id,
+        &[state_account.clone(), admin.clone()],
+        &set_max_data,
+    )
+    .unwrap();
+    let metadata = TokenMetadata {
+        name: "n".repeat(MAX_TOKEN_NAME_LEN),
+        decimals: 9,
+        metadata_uri: Some("u".repeat(MAX_METADATA_URI_LEN)),
+    };
+    let add = |symbol: &str, with_system_program: bool| {
+        let instruction_data = pack(&AdminAddSupportedToken {
+            token: token(symbol),
+            metadata: metadata.clone(),
+        });
+        let mut accounts = vec![
+            state_account.clone(),
+            admin.clone(),
+            new_mint_account(symbol),
+            new_vault_account(&program_id, &token(symbol).mint),
+        ];
+        if with_system_program {
+            accounts.push(system_program_account());
+        }
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    let payload_len = |state_account: &AccountInfo| {
+        let data = state_account.data.borrow();
+        u32::from_le_bytes(data[9..STATE_HEADER_LEN].try_into().unwrap()) as usize
+    };
+
+    // tokens fitting the account leave it as it is, the first one that doesn't needs the
+    // system program to grow it
+    let mut added = 0;
+    let symbol = loop {
+        let symbol = format!("T{}", added);
+        let before = state_account.data.borrow().to_vec();
+        match add(&symbol, false) {
+            Ok(()) => added += 1,
+            Err(error) => {
+                assert_eq!(error, ContractError::StateAccountFull.into());
+                assert_eq!(state_account.data.borrow()[..], before[..]);
+                break symbol;
+            }
+        }
+    };
+    assert!(added > 1);
+    assert_eq!(state_account.data_len(), STATE_ACCOUNT_SIZE);
+    assert_eq!(admin.lamports(), 1_000_000_000);
+
+    // admin pays exactly the rent of the extra bytes
+    let rent = Rent::default();
+    add(&symbol, true).unwrap();
+    added += 1;
+    let grown = state_account.data_len();
+    assert_eq!(grown, STATE_HEADER_LEN + payload_len(&state_account));
+    assert!(grown > STATE_ACCOUNT_SIZE);
+    assert_eq!(state_account.lamports(), rent.minimum_balance(grown));
+    assert_eq!(
+        admin.lamports(),
+        1_000_000_000 - (rent.minimum_balance(grown) - rent.minimum_balance(STATE_ACCOUNT_SIZE))
+    );
+    assert_eq!(
+        load_full_state(&state_account)
+            .unwrap()
+            .all_token_balances
+            .len(),
+        added
+    );
+
+    // and again for every token after it, up to MAX_PERMITTED_DATA_INCREASE past the length
+    // the account started with
+    loop {
+        let symbol = format!("T{}", added);
+        let len = state_account.data_len();
+        match add(&symbol, true) {
+            Ok(()) => added += 1,
+            Err(error) => {
+                assert_eq!(error, ContractError::StateAccountFull.into());
+                break;
+            }
+        }
+        assert!(state_account.data_len() > len);
+        assert_eq!(
+            state_account.lamports(),
+            rent.minimum_balance(state_account.data_len())
+        );
+    }
+    assert!(state_account.data_len() <= STATE_ACCOUNT_SIZE + MAX_PERMITTED_DATA_INCREASE);
+    assert!(load_full_state(&state_account).is_ok());
+    assert_eq!(
+        admin.lamports(),
+        1_000_000_000
+            - (rent.minimum_balance(state_account.data_len())
+                - rent.minimum_balance(STATE_ACCOUNT_SIZE))
+    );
+}
+
+#[test]
+fn test_signature_verification() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let mint = new_mint_account("ray");
+    let vault = new_vault_account(&program_id, mint.key);
+
+    let add_data = pack(&AdminAddSupportedToken {
+        token: token("ray"),
+        metadata: metadata(),
+    });
+
+    // forged admin: someone else signs the add
+    let forged = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    let accounts = [state_account.clone(), forged, mint.clone(), vault.clone()];
+    let result = process_instruction(&program_id, &accounts, &add_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // admin account passed but not signed
+    let accounts = [
+        state_account.clone(),
+        admin_account(false),
+        mint.clone(),
+        vault.clone(),
+    ];
+    let result = process_instruction(&program_id, &accounts, &add_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // happy path
+    let accounts = [
+        state_account.clone(),
+        admin_account(true),
+        mint.clone(),
+        vault.clone(),
+    ];
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &add_data),
+        Ok(())
+    );
+
+    // deposit where the user didn't sign
+    let user = Pubkey::new_unique();
+    let deposit_data = pack(&UserDeposit {
+        token: token("ray"),
+        user,
+        amount: 100,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let vault = new_vault_account(&program_id, mint.key);
+    let mut accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    accounts[1] = new_account(user, false, 0, Pubkey::default());
+    let result = process_instruction(&program_id, &accounts, &deposit_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // another signer cannot deposit on the user's behalf
+    accounts[1] = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    let result = process_instruction(&program_id, &accounts, &deposit_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let result = process_instruction(&program_id, &accounts, &deposit_data);
+    assert_eq!(result, Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    let ray = state.all_token_balances.get(&token("ray"));
+    assert_eq!(
+        ray.map(|entry| &entry.balances),
+        Some(&BTreeMap::from([(user, 100)]))
+    );
+}
+
+#[test]
+fn test_get_balance() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let user = Pubkey::new_unique();
+
+    let get_balance_data = pack(&GetBalance {
+        token: token("ray"),
+        user,
+    });
+
+    // unknown token
+    let result = process_instruction(
+        &program_id,
+        std::slice::from_ref(&state_account),
+        &get_balance_data,
+    );
+    assert_eq!(result, Err(ContractError::TokenNotSupportedForQuery.into()));
+
+    let (mint, vault) = add_token(&program_id, &state_account, "ray");
+
+    // user never deposited
+    let result = process_instruction(
+        &program_id,
+        std::slice::from_ref(&state_account),
+        &get_balance_data,
+    );
+    assert_eq!(result, Ok(()));
+    let (_, data) = get_return_data().unwrap();
+    assert_eq!(
+        UserBalance::try_from_slice(&data).unwrap(),
+        UserBalance {
+            liquid: 0,
+            locked: 0,
+            last_deposit_slot: 0,
+            last_withdraw_slot: 0,
+        }
+    );
+
+    let deposit_data = pack(&UserDeposit {
+        token: token("ray"),
+        user,
+        amount: 42,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    let user_token_account = new_token_account(mint.key, &user, 42);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(&program_id, &accounts, &deposit_data).unwrap();
+
+    let accounts = [
+        state_account.clone(),
+        balance_account(&program_id, mint.key, &user),
+    ];
+    let result = process_instruction(&program_id, &accounts, &get_balance_data);
+    assert_eq!(result, Ok(()));
+    let (_, data) = get_return_data().unwrap();
+    assert_eq!(
+        UserBalance::try_from_slice(&data).unwrap(),
+        UserBalance {
+            liquid: 42,
+            locked: 0,
+            last_deposit_slot: 0,
+            last_withdraw_slot: 0,
+        }
+    );
+}
+
+#[test]
+fn test_deposit_spl_transfer() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 50);
+    let deposit = |amount: u64| {
+        pack(&UserDeposit {
+            token: token("usdc"),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, user),
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        })
+    };
+    let balance_of = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        state.all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+    };
+
+    // tokens move into the vault and the ledger is credited
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit(30)),
+        Ok(())
+    );
+    assert_eq!(token_amount(&user_token_account), 20);
+    assert_eq!(token_amount(&vault), 30);
+    assert_eq!(balance_of(&user), Some(30));
+
+    // failed transfer leaves the ledger untouched
+    let result = process_instruction(&program_id, &accounts, &deposit(21));
+    assert_eq!(result, Err(TokenError::InsufficientFunds.into()));
+    assert_eq!(token_amount(&user_token_account), 20);
+    assert_eq!(token_amount(&vault), 30);
+    assert_eq!(balance_of(&user), Some(30));
+
+    // source token account of another mint
+    let other_mint = new_mint_account("other");
+    let other_token_account = new_token_account(other_mint.key, &user, 50);
+    let accounts = user_token_accounts(&state_account, &user, &other_token_account, &vault);
+    let result = process_instruction(&program_id, &accounts, &deposit(10));
+    assert_eq!(result, Err(ContractError::TokenAccountMintMismatch.into()));
+
+    // vault not owned by the vault authority
+    let fake_vault = new_token_account(mint.key, &Pubkey::new_unique(), 0);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &fake_vault);
+    let result = process_instruction(&program_id, &accounts, &deposit(10));
+    assert_eq!(result, Err(ContractError::InvalidVaultAuthority.into()));
+    // held by the vault authority but not its associated token account
+    let (vault_authority, _) = find_vault_authority(&program_id, mint.key);
+    let fake_vault = new_token_account(mint.key, &vault_authority, 0);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &fake_vault);
+    let result = process_instruction(&program_id, &accounts, &deposit(10));
+    assert_eq!(result, Err(ContractError::InvalidVaultAccount.into()));
+
+    // token program must be the real one
+    let mut accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    accounts[5] = new_account(Pubkey::new_unique(), false, 0, Pubkey::default());
+    let result = process_instruction(&program_id, &accounts, &deposit(10));
+    assert_eq!(result, Err(ContractError::InvalidProgramId.into()));
+    assert_eq!(balance_of(&user), Some(30));
+}
+
+#[test]
+fn test_withdraw_spl_transfer() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 50);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit_data = pack(&UserDeposit {
+        token: token("usdc"),
+        user,
+        amount: 50,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    process_instruction(&program_id, &accounts, &deposit_data).unwrap();
+    let withdraw = |amount: u64| {
+        pack(&UserWithdraw {
+            token: token("usdc"),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, user),
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        })
+    };
+    let balance_of = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        state.all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+    };
+
+    // vault pays out and the ledger is debited
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw(20)),
+        Ok(())
+    );
+    assert_eq!(token_amount(&user_token_account), 20);
+    assert_eq!(token_amount(&vault), 30);
+    assert_eq!(balance_of(&user), Some(30));
+
+    // vault short of tokens even though the ledger says the user has them
+    let mut vault_state = TokenAccount::unpack(&vault.data.borrow()).unwrap();
+    vault_state.amount = 10;
+    vault_state.pack_into_slice(&mut vault.data.borrow_mut());
+    let result = process_instruction(&program_id, &accounts, &withdraw(20));
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert_eq!(token_amount(&user_token_account), 20);
+    assert_eq!(balance_of(&user), Some(30));
+
+    // wrong vault authority can't sign for the vault
+    let mut bad_accounts = accounts.clone();
+    bad_accounts[4] = new_account(Pubkey::new_unique(), false, 0, Pubkey::default());
+    let result = process_instruction(&program_id, &bad_accounts, &withdraw(5));
+    assert_eq!(result, Err(ContractError::InvalidVaultAuthority.into()));
+    assert_eq!(balance_of(&user), Some(30));
+
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw(10)),
+        Ok(())
+    );
+    assert_eq!(token_amount(&user_token_account), 30);
+    assert_eq!(token_amount(&vault), 0);
+    assert_eq!(balance_of(&user), Some(20));
+}
+
+#[test]
+fn test_initialize_config() {
+    let program_id = new_program_id();
+    let state_account = new_state_account(&program_id, STATE_ACCOUNT_SIZE);
+    let instruction_data: Vec<u8> = pack(&InitializeState);
+    process_instruction(
+        &program_id,
+        std::slice::from_ref(&state_account),
+        &instruction_data,
+    )
+    .unwrap();
+
+    let mint = new_mint_account("ray");
+    let vault = new_vault_account(&program_id, mint.key);
+    let add_data = pack(&AdminAddSupportedToken {
+        token: token("ray"),
+        metadata: metadata(),
+    });
+
+    // no admin configured yet, nobody can add tokens
+    let accounts = [
+        state_account.clone(),
+        admin_account(true),
+        mint.clone(),
+        vault.clone(),
+    ];
+    let result = process_instruction(&program_id, &accounts, &add_data);
+    assert_eq!(result, Err(ContractError::ConfigNotInitialized.into()));
+
+    let admin = Pubkey::new_unique();
+    let config_data = pack(&InitializeConfig { admin });
+    let initializer = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    let accounts = [state_account.clone(), initializer];
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &config_data),
+        Ok(())
+    );
+    assert_eq!(load_full_state(&state_account).unwrap().admin, Some(admin));
+
+    // config can only be set once
+    let config_data = pack(&InitializeConfig {
+        admin: Pubkey::new_unique(),
+    });
+    let result = process_instruction(&program_id, &accounts, &config_data);
+    assert_eq!(result, Err(ContractError::ConfigAlreadyInitialized.into()));
+    assert_eq!(load_full_state(&state_account).unwrap().admin, Some(admin));
+
+    // the previously hardcoded admin key has no special rights anymore
+    let old_admin = Pubkey::from_str("D6gQXdUX7AwrGtdQaCuZ5p1MwyXHaidWvKypdKY9bmkA").unwrap();
+    let old_admin = new_account(old_admin, true, 0, Pubkey::default());
+    let accounts = [
+        state_account.clone(),
+        old_admin,
+        mint.clone(),
+        vault.clone(),
+    ];
+    let result = process_instruction(&program_id, &accounts, &add_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let admin = new_account(admin, true, 0, Pubkey::default());
+    let accounts = [state_account.clone(), admin, mint.clone(), vault.clone()];
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &add_data),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_admin_rotation() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let signed = |key: Pubkey| [state_account.clone(), new_account(key, true, 0, key)];
+    let propose = |new_admin: Pubkey| pack(&AdminProposeNewAdmin { new_admin });
+    let accept_data = pack(&AcceptAdmin);
+    let cancel_data = pack(&AdminCancelProposedAdmin);
+
+    // nothing proposed yet
+    let result = process_instruction(&program_id, &signed(TEST_ADMIN), &accept_data);
+    assert_eq!(result, Err(ContractError::NoPendingAdmin.into()));
+    let result = process_instruction(&program_id, &signed(TEST_ADMIN), &cancel_data);
+    assert_eq!(result, Err(ContractError::NoPendingAdmin.into()));
+
+    // only the admin can propose
+    let typo = Pubkey::new_unique();
+    let result = process_timelocked(&program_id, &signed(typo), &propose(typo));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // propose a typo'd key, then re-propose, the typo can no longer accept
+    let new_admin = Pubkey::new_unique();
+    process_timelocked(&program_id, &signed(TEST_ADMIN), &propose(typo)).unwrap();
+    process_timelocked(&program_id, &signed(TEST_ADMIN), &propose(new_admin)).unwrap();
+    let result = process_instruction(&program_id, &signed(typo), &accept_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // proposal has no effect until accepted
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.admin, Some(TEST_ADMIN));
+    assert_eq!(state.pending_admin, Some(new_admin));
+
+    // unauthorized accept: the current admin can't accept on behalf of the proposed key
+    let result = process_instruction(&program_id, &signed(TEST_ADMIN), &accept_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let result = process_instruction(&program_id, &signed(new_admin), &accept_data);
+    assert_eq!(result, Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.admin, Some(new_admin));
+    assert_eq!(state.pending_admin, None);
+
+    // old admin lost its rights, new admin can cancel its own proposals
+    let result = process_timelocked(&program_id, &signed(TEST_ADMIN), &propose(typo));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    process_timelocked(&program_id, &signed(new_admin), &propose(typo)).unwrap();
+    process_instruction(&program_id, &signed(new_admin), &cancel_data).unwrap();
+    let result = process_instruction(&program_id, &signed(typo), &accept_data);
+    assert_eq!(result, Err(ContractError::NoPendingAdmin.into()));
+}
+
+#[test]
+fn test_deposit_overflow() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, u64::MAX);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit = |amount: u64| {
+        pack(&UserDeposit {
+            token: token("usdc"),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, user),
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        })
+    };
+
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit(u64::MAX)),
+        Ok(())
+    );
+    // refill the user's wallet so only the ledger can fail
+    let mut wallet = TokenAccount::unpack(&user_token_account.data.borrow()).unwrap();
+    wallet.amount = 1;
+    wallet.pack_into_slice(&mut user_token_account.data.borrow_mut());
+
+    let result = process_instruction(&program_id, &accounts, &deposit(1));
+    assert_eq!(result, Err(ContractError::BalanceOverflow.into()));
+    // nothing was transferred
+    assert_eq!(token_amount(&user_token_account), 1);
+    let state = load_full_state(&state_account).unwrap();
+    let usdc = &state.all_token_balances[&token("usdc")];
+    assert_eq!(usdc.balances[&user], u64::MAX);
+}
+
+#[test]
+fn test_delete_token_with_balances() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let usdc = || token("usdc");
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let delete_data = pack(&AdminDeleteSupportedToken { token: usdc() });
+
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let delete_accounts = [state_account.clone(), admin_account(true), vault.clone()];
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit_data = pack(&UserDeposit {
+        token: usdc(),
+        user,
+        amount: 100,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    process_instruction(&program_id, &accounts, &deposit_data).unwrap();
+
+    // user still holds a balance
+    let result = process_timelocked(&program_id, &delete_accounts, &delete_data);
+    assert_eq!(
+        result,
+        Err(ContractError::TokenHasOutstandingBalances.into())
+    );
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.all_token_balances[&usdc()].balances[&user], 100);
+
+    // succeeds once everything is withdrawn
+    let withdraw_data = pack(&UserWithdraw {
+        token: usdc(),
+        user,
+        amount: 100,
+        nonce: nonce_of(&state_account, mint.key, user),
+        eth_signature: None,
+        recipient: None,
+        memo: None,
+    });
+    process_instruction(&program_id, &accounts, &withdraw_data).unwrap();
+    // tokens sent straight to the vault would be stranded
+    let set_vault_amount = |amount: u64| {
+        let mut vault_state = TokenAccount::unpack(&vault.data.borrow()).unwrap();
+        vault_state.amount = amount;
+        vault_state.pack_into_slice(&mut vault.data.borrow_mut());
+    };
+    set_vault_amount(5);
+    let result = process_timelocked(&program_id, &delete_accounts, &delete_data);
+    assert_eq!(result, Err(ContractError::VaultNotEmpty.into()));
+    set_vault_amount(0);
+    // only the token's own vault counts
+    let other_vault =
+        new_token_account(mint.key, &find_vault_authority(&program_id, mint.key).0, 0);
+    let result = process_timelocked(
+        &program_id,
+        &[state_account.clone(), admin_account(true), other_vault],
+        &delete_data,
+    );
+    assert_eq!(result, Err(ContractError::InvalidVaultAccount.into()));
+    let result = process_timelocked(&program_id, &delete_accounts, &delete_data);
+    assert_eq!(result, Ok(()));
+    assert!(!load_full_state(&state_account)
+        .unwrap()
+        .all_token_balances
+        .contains_key(&usdc()));
+
+    // force delete needs the confirmation flag, then destroys balances and logs the total
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user_token_account = new_token_account(mint.key, &user, 70);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit_data = pack(&UserDeposit {
+        token: usdc(),
+        user,
+        amount: 70,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    process_instruction(&program_id, &accounts, &deposit_data).unwrap();
+
+    let force = |confirm: bool| {
+        pack(&AdminForceDeleteToken {
+            token: usdc(),
+            confirm,
+        })
+    };
+    let result = process_timelocked(&program_id, &admin_accounts, &force(false));
+    assert_eq!(result, Err(ContractError::ForceDeleteNotConfirmed.into()));
+
+    take_logs();
+    let result = process_timelocked(&program_id, &admin_accounts, &force(true));
+    assert_eq!(result, Ok(()));
+    assert!(take_logs()
+        .iter()
+        .any(|log| log == "force deleted token usdc, destroyed total balance 70"));
+    assert!(!load_full_state(&state_account)
+        .unwrap()
+        .all_token_balances
+        .contains_key(&usdc()));
+}
+
+#[test]
+fn test_contract_error_codes() {
+    // codes are stable, clients decode them from ProgramError::Custom
+    assert_eq!(
+        ProgramError::from(ContractError::TokenAlreadyExists),
+        ProgramError::Custom(0)
+    );
+    assert_eq!(
+        ProgramError::from(ContractError::TokenNotSupportedForDeposit),
+        ProgramError::Custom(2)
+    );
+    assert_eq!(
+        ProgramError::from(ContractError::ForceDeleteNotConfirmed),
+        ProgramError::Custom(12)
+    );
+    for code in 0..=105 {
+        let error = ContractError::try_from(code).unwrap();
+        assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+    }
+    assert_eq!(
+        ContractError::try_from(106),
+        Err(ProgramError::InvalidArgument)
+    );
+}
+
+#[test]
+fn test_pause_unpause() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let admin_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        new_mint_account("ray"),
+        new_vault_account(&program_id, &token("ray").mint),
+    ];
+    let pause = |withdrawals_only: bool| pack(&AdminPause { withdrawals_only });
+    let unpause_data = pack(&AdminUnpause);
+    let paused: ProgramResult = Err(ContractError::ContractPaused.into());
+
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 50),
+    );
+    assert_eq!(result, Ok(()));
+
+    // only admin can pause
+    let result = process_instruction(&program_id, &accounts[..2], &pause(false));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // full pause mid-session
+    process_instruction(&program_id, &admin_accounts, &pause(false)).unwrap();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, paused);
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, paused);
+
+    // token management keeps working
+    let add_data = pack(&AdminAddSupportedToken {
+        token: token("ray"),
+        metadata: metadata(),
+    });
+    assert_eq!(
+        process_instruction(&program_id, &admin_accounts, &add_data),
+        Ok(())
+    );
+
+    // withdrawals only: users can exit but not enter
+    process_instruction(&program_id, &admin_accounts, &pause(true)).unwrap();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, paused);
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Ok(()));
+
+    // unpause and retry
+    process_instruction(&program_id, &admin_accounts, &unpause_data).unwrap();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert!(!state.paused && !state.withdrawals_only);
+    assert_eq!(state.all_token_balances[&token("usdc")].balances[&user], 50);
+}
+
+#[test]
+fn test_token_flags() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let (ray_mint, ray_vault) = add_token(&program_id, &state_account, "ray");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let user_sol_account = new_token_account(ray_mint.key, &user, 100);
+    let sol_accounts = user_token_accounts(&state_account, &user, &user_sol_account, &ray_vault);
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_flags = |symbol: &str, deposits_enabled: bool, withdrawals_enabled: bool| {
+        pack(&AdminSetTokenFlags {
+            token: token(symbol),
+            deposits_enabled,
+            withdrawals_enabled,
+        })
+    };
+
+    let entry = &load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")];
+    assert!(entry.deposits_enabled && entry.withdrawals_enabled);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 50),
+    )
+    .unwrap();
+
+    // only the admin can change flags, and only of supported tokens
+    let result = process_instruction(&program_id, &accounts[..2], &set_flags("usdc", false, true));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    let result = process_instruction(&program_id, &admin_accounts, &set_flags("btc", false, true));
+    assert_eq!(result, Err(ContractError::TokenNotFound.into()));
+
+    // depegged usdc: no new deposits, users can still exit
+    process_instruction(
+        &program_id,
+        &admin_accounts,
+        &set_flags("usdc", false, true),
+    )
+    .unwrap();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Err(ContractError::DepositsDisabled.into()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 20),
+    );
+    assert_eq!(result, Ok(()));
+
+    // other tokens are unaffected
+    let result = process_instruction(
+        &program_id,
+        &sol_accounts,
+        &deposit_data(&state_account, "ray", user, 10),
+    );
+    assert_eq!(result, Ok(()));
+
+    process_instruction(
+        &program_id,
+        &admin_accounts,
+        &set_flags("usdc", true, false),
+    )
+    .unwrap();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Err(ContractError::WithdrawalsDisabled.into()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Ok(()));
+
+    let state = load_full_state(&state_account).unwrap();
+    let usdc = &state.all_token_balances[&token("usdc")];
+    assert_eq!(usdc.balances[&user], 40);
+    assert!(usdc.deposits_enabled && !usdc.withdrawals_enabled);
+}
+
+#[test]
+fn test_user_transfer() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 100);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 100),
+    )
+    .unwrap();
+    let transfer = |symbol: &str, from: Pubkey, to: Pubkey, amount: u64| {
+        pack(&UserTransfer {
+            token: token(symbol),
+            from,
+            to,
+            amount,
+        })
+    };
+    let balances = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .clone()
+    };
+    // [state, alice], both balance accounts and the system program
+    let alice_accounts = &[
+        accounts[0].clone(),
+        accounts[1].clone(),
+        accounts[6].clone(),
+        balance_account(&program_id, mint.key, &bob),
+        system_program_account(),
+    ];
+
+    // bob has no entry yet, it is created
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("usdc", alice, bob, 30),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(balances(), BTreeMap::from([(alice, 70), (bob, 30)]));
+    // the vault is not touched
+    assert_eq!(token_amount(&vault), 100);
+
+    // from must sign
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("usdc", bob, alice, 10),
+    );
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("usdc", alice, bob, 0),
+    );
+    assert_eq!(result, Err(ContractError::ZeroAmount.into()));
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("usdc", alice, alice, 10),
+    );
+    assert_eq!(result, Err(ContractError::SelfTransfer.into()));
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("usdc", alice, bob, 71),
+    );
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("ray", alice, bob, 10),
+    );
+    assert_eq!(result, Err(ContractError::TokenNotFound.into()));
+
+    // failed transfers changed nothing
+    assert_eq!(balances(), BTreeMap::from([(alice, 70), (bob, 30)]));
+
+    // exact full balance
+    let result = process_instruction(
+        &program_id,
+        alice_accounts,
+        &transfer("usdc", alice, bob, 70),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(balances(), BTreeMap::from([(alice, 0), (bob, 100)]));
+}
+
+#[test]
+fn test_batch_deposit() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let user = Pubkey::new_unique();
+    let (usdc_mint, usdc_vault) = add_token(&program_id, &state_account, "usdc");
+    let (eth_mint, eth_vault) = add_token(&program_id, &state_account, "eth");
+    let usdc_account = new_token_account(usdc_mint.key, &user, 100);
+    let eth_account = new_token_account(eth_mint.key, &user, 100);
+    let usdc_accounts = user_token_accounts(&state_account, &user, &usdc_account, &usdc_vault);
+    let eth_accounts = user_token_accounts(&state_account, &user, &eth_account, &eth_vault);
+    // both items' token accounts, then both balance accounts and the system program
+    let mut accounts = usdc_accounts[..6].to_vec();
+    accounts.extend_from_slice(&eth_accounts[2..6]);
+    accounts.extend_from_slice(&usdc_accounts[6..]);
+    accounts.push(eth_accounts[6].clone());
+    let batch = |items: Vec<(&str, u64)>| {
+        pack(&BatchDeposit {
+            user,
+            items: items
+                .into_iter()
+                .map(|(symbol, amount)| (token(symbol), amount))
+                .collect(),
+        })
+    };
+    let balance = |symbol: &str| {
+        load_full_state(&state_account).unwrap().all_token_balances[&token(symbol)]
+            .balances
+            .get(&user)
+            .copied()
+    };
+
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &batch(vec![("usdc", 30), ("eth", 40)]),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(balance("usdc"), Some(30));
+    assert_eq!(balance("eth"), Some(40));
+    assert_eq!(token_amount(&usdc_vault), 30);
+    assert_eq!(token_amount(&eth_vault), 40);
+    take_logs();
+
+    // second item is not supported, nothing is credited or transferred
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &batch(vec![("usdc", 30), ("ray", 40)]),
+    );
+    assert_eq!(
+        result,
+        Err(ContractError::TokenNotSupportedForDeposit.into())
+    );
+    assert!(take_logs().contains(&"batch deposit item 1 failed".to_string()));
+    assert_eq!(balance("usdc"), Some(30));
+    assert_eq!(token_amount(&usdc_vault), 30);
+    assert_eq!(token_amount(&usdc_account), 70);
+
+    // one set of token accounts per item
+    let result = process_instruction(
+        &program_id,
+        &accounts[..6],
+        &batch(vec![("usdc", 1), ("eth", 1)]),
+    );
+    assert_eq!(result, Err(ProgramError::NotEnoughAccountKeys));
+
+    let result = process_instruction(&program_id, &accounts, &batch(vec![("usdc", 1); 17]));
+    assert_eq!(result, Err(ContractError::BatchTooLarge.into()));
+    assert_eq!(balance("usdc"), Some(30));
+}
+
+#[test]
+fn test_batch_withdraw() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let user = Pubkey::new_unique();
+    let mut accounts = vec![];
+    let mut balance_accounts = vec![];
+    for symbol in ["usdc", "eth", "btc"] {
+        let (mint, vault) = add_token(&program_id, &state_account, symbol);
+        let user_token_account = new_token_account(mint.key, &user, 100);
+        let item_accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+        process_instruction(
+            &program_id,
+            &item_accounts,
+            &deposit_data(&state_account, symbol, user, 50),
+        )
+        .unwrap();
+        if accounts.is_empty() {
+            accounts = item_accounts[..6].to_vec();
+        } else {
+            accounts.extend_from_slice(&item_accounts[2..6]);
+        }
+        balance_accounts.push(item_accounts[6].clone());
+    }
+    let item_accounts_end = accounts.len();
+    accounts.extend(balance_accounts);
+    let batch = |items: Vec<(&str, u64)>| {
+        pack(&BatchWithdraw {
+            user,
+            items: items
+                .into_iter()
+                .map(|(symbol, amount)| (token(symbol), amount))
+                .collect(),
+        })
+    };
+    // (ledger balances, token account amounts) to compare before and after
+    let snapshot = || {
+        let state = load_full_state(&state_account).unwrap();
+        let mut balances: Vec<_> = state
+            .all_token_balances
+            .iter()
+            .map(|(token, entry)| (token.symbol.clone(), entry.balances.clone()))
+            .collect();
+        balances.sort_by(|a, b| a.0.cmp(&b.0));
+        let amounts: Vec<_> = accounts[2..item_accounts_end]
+            .chunks(4)
+            .map(|item| (token_amount(&item[0]), token_amount(&item[1])))
+            .collect();
+        (balances, amounts)
+    };
+
+    // third leg overdraws, the first two must be untouched
+    let before = snapshot();
+    take_logs();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &batch(vec![("usdc", 10), ("eth", 10), ("btc", 51)]),
+    );
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert!(take_logs().contains(&"batch withdraw item 2 failed".to_string()));
+    assert_eq!(snapshot(), before);
+
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &batch(vec![("usdc", 10), ("eth", 20), ("btc", 50)]),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(snapshot().1, vec![(60, 40), (70, 30), (100, 0)]);
+
+    // user signs once, for the whole batch
+    let mut unsigned = accounts.clone();
+    unsigned[1] = new_account(user, false, 0, Pubkey::default());
+    let result = process_instruction(&program_id, &unsigned, &batch(vec![("usdc", 1)]));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let result = process_instruction(&program_id, &accounts, &batch(vec![("usdc", 1); 17]));
+    assert_eq!(result, Err(ContractError::BatchTooLarge.into()));
+}
+
+#[test]
+fn test_withdraw_all() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let token = token("usdc");
+    let withdraw_all = pack(&UserWithdrawAll {
+        token: token.clone(),
+        user,
+    });
+    let balances = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token]
+            .balances
+            .clone()
+    };
+
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 30),
+    )
+    .unwrap();
+    take_logs();
+
+    let result = process_instruction(&program_id, &accounts, &withdraw_all);
+    assert_eq!(result, Ok(()));
+    assert!(take_logs().contains(&"withdrew all 70 of token usdc".to_string()));
+    assert_eq!(token_amount(&user_token_account), 100);
+    assert_eq!(token_amount(&vault), 0);
+    // entry removed, not just zeroed
+    assert!(!balances().contains_key(&user));
+
+    // nothing left, still a success
+    let result = process_instruction(&program_id, &accounts, &withdraw_all);
+    assert_eq!(result, Ok(()));
+    assert!(take_logs().contains(&"withdrew all 0 of token usdc".to_string()));
+    assert!(balances().is_empty());
+}
+
+#[test]
+fn test_balance_accounts() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 100);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let balance = accounts[6].clone();
+    let rent = Rent::default().minimum_balance(BALANCE_ACCOUNT_SIZE);
+
+    // [BALANCE_SEED, mint, user]
+    let (address, _) = Pubkey::find_program_address(
+        &[b"balance", mint.key.as_ref(), alice.as_ref()],
+        &program_id,
+    );
+    assert_eq!(balance.key, &address);
+    assert_eq!(
+        find_balance_address(&program_id, mint.key, &alice).0,
+        address
+    );
+
+    // created on the first deposit, the balance leaves the state account
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 10),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(balance.owner, &program_id);
+    assert_eq!(balance.lamports(), rent);
+    assert!(
+        load_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .is_empty()
+    );
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 25),
+    );
+    assert_eq!(result, Ok(()));
+    // the system program is only needed for creation and by deposits, which grow the receipts
+    // account
+    let result = process_instruction(
+        &program_id,
+        &accounts[..7],
+        &withdraw_data(&state_account, "usdc", alice, 5),
+    );
+    assert_eq!(result, Ok(()));
+
+    // a credit needs the receiving account
+    let transfer = pack(&UserTransfer {
+        token: token("usdc"),
+        from: alice,
+        to: bob,
+        amount: 5,
+    });
+    let mut transfer_accounts = vec![
+        state_account.clone(),
+        accounts[1].clone(),
+        balance.clone(),
+        system_program_account(),
+    ];
+    let missing: ProgramResult = Err(ContractError::MissingBalanceAccount.into());
+    let result = process_instruction(&program_id, &transfer_accounts, &transfer);
+    assert_eq!(result, missing);
+    // someone else's doesn't do
+    transfer_accounts.push(balance_account(
+        &program_id,
+        mint.key,
+        &Pubkey::new_unique(),
+    ));
+    let result = process_instruction(&program_id, &transfer_accounts, &transfer);
+    assert_eq!(result, missing);
+    transfer_accounts.push(balance_account(&program_id, mint.key, &bob));
+    let result = process_instruction(&program_id, &transfer_accounts, &transfer);
+    assert_eq!(result, Ok(()));
+    // a debit without its account sees no balance
+    let result = process_instruction(&program_id, &transfer_accounts[..2], &transfer);
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    let get_balance = pack(&GetBalance {
+        token: token("usdc"),
+        user: alice,
+    });
+    let query = [state_account.clone(), balance.clone()];
+    process_instruction(&program_id, &query, &get_balance).unwrap();
+    let (_, data) = get_return_data().unwrap();
+    assert_eq!(UserBalance::try_from_slice(&data).unwrap().liquid, 25);
+
+    // a copy under another address is rejected wherever it is passed
+    let forged = new_account(
+        Pubkey::new_unique(),
+        false,
+        BALANCE_ACCOUNT_SIZE,
+        program_id,
+    );
+    forged
+        .data
+        .borrow_mut()
+        .copy_from_slice(&balance.data.borrow());
+    let invalid: ProgramResult = Err(ContractError::InvalidBalanceAccount.into());
+    let result = process_instruction(
+        &program_id,
+        &[state_account.clone(), forged.clone()],
+        &get_balance,
+    );
+    assert_eq!(result, invalid);
+    let mut forged_accounts = accounts.clone();
+    forged_accounts[6] = forged;
+    let result = process_instruction(
+        &program_id,
+        &forged_accounts,
+        &withdraw_data(&state_account, "usdc", alice, 10),
+    );
+    assert_eq!(result, invalid);
+
+    // WithdrawAll closes it, the rent goes back to the signer
+    let lamports = accounts[1].lamports();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &pack(&UserWithdrawAll {
+            token: token("usdc"),
+            user: alice,
+        }),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(token_amount(&alice_token_account), 95);
+    assert_eq!(balance.lamports(), 0);
+    assert_eq!(balance.owner, &system_program::id());
+    assert!(balance.data.borrow().iter().all(|byte| *byte == 0));
+    assert_eq!(accounts[1].lamports(), lamports + rent);
+
+    // a force deleted and relisted token starts from scratch
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 40),
+    );
+    assert_eq!(result, Ok(()));
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let result = process_timelocked(
+        &program_id,
+        &admin_accounts,
+        &pack(&AdminForceDeleteToken {
+            token: token("usdc"),
+            confirm: true,
+        }),
+    );
+    assert_eq!(result, Ok(()));
+    add_token(&program_id, &state_account, "usdc");
+    process_instruction(&program_id, &query, &get_balance).unwrap();
+    let (_, data) = get_return_data().unwrap();
+    assert_eq!(UserBalance::try_from_slice(&data).unwrap().liquid, 0);
+}
+
+#[test]
+fn test_token_keyed_by_mint() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let add = |token: TokenType, mint: &AccountInfo<'static>| {
+        let instruction_data = pack(&AdminAddSupportedToken {
+            token,
+            metadata: metadata(),
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            mint.clone(),
+            new_vault_account(&program_id, mint.key),
+        ];
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+
+    // another mint claiming the same symbol
+    let fake_mint = new_mint_account("fake");
+    let fake_usdc = TokenType {
+        symbol: "usdc".to_string(),
+        mint: *fake_mint.key,
+    };
+    assert_eq!(
+        add(fake_usdc, &fake_mint),
+        Err(ContractError::TokenAlreadyExists.into())
+    );
+    // the same mint under another symbol
+    let renamed = TokenType {
+        symbol: "usdce".to_string(),
+        mint: *mint.key,
+    };
+    assert_eq!(
+        add(renamed.clone(), &mint),
+        Err(ContractError::MintAlreadyRegistered.into())
+    );
+    assert!(take_logs().contains(&format!("mint {} is listed as USDC", mint.key)));
+    // mint account must be the token's mint
+    assert_eq!(
+        add(token("eth"), &fake_mint),
+        Err(ContractError::MintAccountMismatch.into())
+    );
+    // the vault has to be created up front, at the associated token account address
+    let eth_mint = new_mint_account("eth");
+    let add_eth = pack(&AdminAddSupportedToken {
+        token: token("eth"),
+        metadata: metadata(),
+    });
+    let add_eth_with = |vault: AccountInfo<'static>| {
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            eth_mint.clone(),
+            vault,
+        ];
+        process_instruction(&program_id, &accounts, &add_eth)
+    };
+    let (eth_vault_authority, _) = find_vault_authority(&program_id, eth_mint.key);
+    let uncreated = new_account(
+        find_vault_address(&program_id, eth_mint.key),
+        false,
+        0,
+        Pubkey::default(),
+    );
+    assert_eq!(
+        add_eth_with(uncreated),
+        Err(ContractError::WrongOwner.into())
+    );
+    let elsewhere = new_token_account(eth_mint.key, &eth_vault_authority, 0);
+    assert_eq!(
+        add_eth_with(elsewhere),
+        Err(ContractError::InvalidVaultAccount.into())
+    );
+    let foreign_owner = new_token_account_at(
+        find_vault_address(&program_id, eth_mint.key),
+        eth_mint.key,
+        &Pubkey::new_unique(),
+        0,
+    );
+    assert_eq!(
+        add_eth_with(foreign_owner),
+        Err(ContractError::InvalidVaultAuthority.into())
+    );
+    assert_eq!(
+        add_eth_with(new_vault_account(&program_id, eth_mint.key)),
+        Ok(())
+    );
+
+    // delisted, the mint can be listed again under a new symbol, on the same vault
+    let delete_data = pack(&AdminDeleteSupportedToken {
+        token: token("eth"),
+    });
+    let delete_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        new_vault_account(&program_id, eth_mint.key),
+    ];
+    process_timelocked(&program_id, &delete_accounts, &delete_data).unwrap();
+    let weth = TokenType {
+        symbol: "weth".to_string(),
+        mint: *eth_mint.key,
+    };
+    assert_eq!(add(weth, &eth_mint), Ok(()));
+    let state = load_state(&state_account).unwrap();
+    let (listed, _) = state
+        .all_token_balances
+        .get_key_value(&token("eth"))
+        .unwrap();
+    assert_eq!(listed.symbol, "WETH");
+    assert_eq!(state.all_token_balances.len(), 2);
+
+    // deposits are matched on mint, the symbol is just a label
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit_data = pack(&UserDeposit {
+        token: renamed,
+        user,
+        amount: 40,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit_data),
+        Ok(())
+    );
+    let state = load_full_state(&state_account).unwrap();
+    let (registered, entry) = state
+        .all_token_balances
+        .get_key_value(&token("usdc"))
+        .unwrap();
+    assert_eq!(registered.symbol, "USDC");
+    assert_eq!(entry.balances, BTreeMap::from([(user, 40)]));
+}
+
+#[test]
+fn test_token_symbol_validation() {
+    let invalid: Result<(), ContractError> = Err(ContractError::InvalidTokenSymbol);
+    assert_eq!(validate_token_symbol(""), invalid);
+    assert_eq!(validate_token_symbol("ABCDEFGHIJKLM"), invalid);
+    assert_eq!(validate_token_symbol("ÜSDC"), invalid);
+    assert_eq!(validate_token_symbol(" USDC"), invalid);
+    assert_eq!(validate_token_symbol("USDC "), invalid);
+    assert_eq!(validate_token_symbol("US\nDC"), invalid);
+    assert_eq!(validate_token_symbol("ABCDEFGHIJK1"), Ok(()));
+
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let add = |symbol: &str| {
+        let instruction_data = pack(&AdminAddSupportedToken {
+            token: token(symbol),
+            metadata: metadata(),
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            new_mint_account(symbol),
+            new_vault_account(&program_id, &token(symbol).mint),
+        ];
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    assert_eq!(add(" ray"), Err(ContractError::InvalidTokenSymbol.into()));
+    assert_eq!(
+        add(&"x".repeat(1024)),
+        Err(ContractError::InvalidTokenSymbol.into())
+    );
+    // longer than any transaction, refused before the symbol is even looked at
+    assert_eq!(
+        add(&"x".repeat(10 * 1024)),
+        Err(ProgramError::InvalidInstructionData)
+    );
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .all_token_balances
+        .is_empty());
+    let max_len_symbol = "A".repeat(MAX_SYMBOL_LEN);
+    assert_eq!(add(&max_len_symbol), Ok(()));
+}
+
+#[test]
+fn test_symbol_case_insensitive() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "Ray");
+    let state = load_full_state(&state_account).unwrap();
+    let registered = state.all_token_balances.keys().next().unwrap();
+    assert_eq!(registered.symbol, "RAY");
+
+    // other mints can't squat on another casing of the symbol
+    for symbol in ["ray", "RAY", "rAy"] {
+        let instruction_data = pack(&AdminAddSupportedToken {
+            token: token(symbol),
+            metadata: metadata(),
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            new_mint_account(symbol),
+            new_vault_account(&program_id, &token(symbol).mint),
+        ];
+        let result = process_instruction(&program_id, &accounts, &instruction_data);
+        assert_eq!(result, Err(ContractError::TokenAlreadyExists.into()));
+    }
+
+    // whatever casing the user sends, the RAY entry is used
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let with_symbol = |symbol: &str| TokenType {
+        symbol: symbol.to_string(),
+        mint: *mint.key,
+    };
+    let deposit_data = pack(&UserDeposit {
+        token: with_symbol("ray"),
+        user,
+        amount: 60,
+        nonce: nonce_of(&state_account, mint.key, user),
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    process_instruction(&program_id, &accounts, &deposit_data).unwrap();
+    let withdraw_data = pack(&UserWithdraw {
+        token: with_symbol("RaY"),
+        user,
+        amount: 60,
+        nonce: nonce_of(&state_account, mint.key, user),
+        eth_signature: None,
+        recipient: None,
+        memo: None,
+    });
+    process_instruction(&program_id, &accounts, &withdraw_data).unwrap();
+    assert_eq!(token_amount(&user_token_account), 100);
+
+    let delete_data = pack(&AdminDeleteSupportedToken {
+        token: with_symbol("ray"),
+    });
+    let accounts = [state_account.clone(), admin_account(true), vault.clone()];
+    assert_eq!(
+        process_timelocked(&program_id, &accounts, &delete_data),
+        Ok(())
+    );
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .all_token_balances
+        .is_empty());
+}
+
+#[test]
+fn test_rename_token() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    add_token(&program_id, &state_account, "eth");
+    let user = Pubkey::new_unique();
+    let spender = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    let approve = pack(&UserApprove {
+        token: token("usdc"),
+        owner: user,
+        spender,
+        amount: 40,
+    });
+    process_instruction(&program_id, &accounts[..2], &approve).unwrap();
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_cap = pack(&AdminSetDepositCap {
+        token: token("usdc"),
+        cap: Some(500),
+    });
+    process_instruction(&program_id, &admin_accounts, &set_cap).unwrap();
+    let rename = |old: TokenType, new_symbol: &str| {
+        let instruction_data = pack(&AdminRenameToken {
+            old,
+            new_symbol: new_symbol.to_string(),
+        });
+        process_instruction(&program_id, &admin_accounts, &instruction_data)
+    };
+
+    assert_eq!(
+        rename(token("btc"), "wbtc"),
+        Err(ContractError::TokenNotFound.into())
+    );
+    // symbols collide whatever their casing
+    for symbol in ["eth", "ETH"] {
+        assert_eq!(
+            rename(token("usdc"), symbol),
+            Err(ContractError::TokenAlreadyExists.into())
+        );
+    }
+    assert_eq!(
+        rename(token("usdc"), "usd-c"),
+        Err(ContractError::InvalidTokenSymbol.into())
+    );
+    assert_eq!(
+        rename(token("usdc"), "sol"),
+        Err(ContractError::ReservedToken.into())
+    );
+    assert_eq!(
+        rename(TokenType::native(), "wsol"),
+        Err(ContractError::ReservedToken.into())
+    );
+    take_events::<TokenRenamedEvent>();
+
+    assert_eq!(rename(token("usdc"), "usdce"), Ok(()));
+    let sequence = load_state(&state_account).unwrap().sequence;
+    assert_eq!(
+        take_events::<TokenRenamedEvent>(),
+        [TokenRenamedEvent {
+            old_symbol: "USDC".to_string(),
+            new_symbol: "USDCE".to_string(),
+            mint: *mint.key,
+            sequence,
+        }]
+    );
+    let state = load_full_state(&state_account).unwrap();
+    let (renamed, entry) = state
+        .all_token_balances
+        .get_key_value(&token("usdc"))
+        .unwrap();
+    assert_eq!(renamed.symbol, "USDCE");
+    assert_eq!(entry.balances, BTreeMap::from([(user, 100)]));
+    assert_eq!(entry.total_deposited, 100);
+    assert_eq!(entry.deposit_cap, Some(500));
+    assert_eq!(state.all_token_balances.len(), 2);
+    let (allowance, amount) = state.allowances.iter().next().unwrap();
+    assert_eq!(allowance.0.symbol, "USDCE");
+    assert_eq!(*amount, 40);
+    // renaming to its own symbol is no collision
+    assert_eq!(rename(token("usdc"), "UsdcE"), Ok(()));
+
+    // the old symbol is free again, the balance is withdrawn under the new one
+    let mut compact =
+        deserialize_instruction(&withdraw_data(&state_account, "usdc", user, 100)).unwrap();
+    if let UserWithdraw { token, .. } = &mut compact {
+        *token = TokenType {
+            symbol: "usdce".to_string(),
+            mint: Pubkey::default(),
+        };
+    }
+    let withdraw = compact.pack_compact().unwrap();
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw),
+        Ok(())
+    );
+    assert_eq!(token_amount(&user_token_account), 100);
+    assert_eq!(rename(token("eth"), "usdc"), Ok(()));
+}
+
+#[test]
+fn test_max_tokens() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    assert_eq!(load_full_state(&state_account).unwrap().max_tokens, 64);
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_max_data = pack(&AdminSetMaxTokens { max_tokens: 3 });
+
+    // only admin can change the cap
+    let user = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    let result = process_instruction(&program_id, &[state_account.clone(), user], &set_max_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    process_instruction(&program_id, &admin_accounts, &set_max_data).unwrap();
+
+    for symbol in ["a", "b", "c"] {
+        add_token(&program_id, &state_account, symbol);
+    }
+    let add = |symbol: &str| {
+        let instruction_data = pack(&AdminAddSupportedToken {
+            token: token(symbol),
+            metadata: metadata(),
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            new_mint_account(symbol),
+            new_vault_account(&program_id, &token(symbol).mint),
+        ];
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    assert_eq!(add("d"), Err(ContractError::TokenLimitReached.into()));
+
+    // deleting frees a slot
+    let delete_data = pack(&AdminDeleteSupportedToken { token: token("b") });
+    let delete_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        new_vault_account(&program_id, &token("b").mint),
+    ];
+    process_timelocked(&program_id, &delete_accounts, &delete_data).unwrap();
+    assert_eq!(add("d"), Ok(()));
+    assert_eq!(add("e"), Err(ContractError::TokenLimitReached.into()));
+}
+
+#[test]
+fn test_deposit_cap() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1000);
+    let bob_token_account = new_token_account(mint.key, &bob, 1000);
+    let alice_accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_cap = |cap: Option<u64>| {
+        let instruction_data = pack(&AdminSetDepositCap {
+            token: token("usdc"),
+            cap,
+        });
+        process_instruction(&program_id, &admin_accounts, &instruction_data)
+    };
+    let total_deposited = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")].total_deposited
+    };
+    let cap_exceeded: ProgramResult = Err(ContractError::DepositCapExceeded.into());
+
+    assert_eq!(set_cap(Some(100)), Ok(()));
+    process_instruction(
+        &program_id,
+        &alice_accounts,
+        &deposit_data(&state_account, "usdc", alice, 60),
+    )
+    .unwrap();
+    // the cap is on the total over all users
+    let result = process_instruction(
+        &program_id,
+        &bob_accounts,
+        &deposit_data(&state_account, "usdc", bob, 41),
+    );
+    assert_eq!(result, cap_exceeded);
+    let result = process_instruction(
+        &program_id,
+        &bob_accounts,
+        &deposit_data(&state_account, "usdc", bob, 40),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(total_deposited(), 100);
+
+    // withdrawals free up room
+    process_instruction(
+        &program_id,
+        &alice_accounts,
+        &withdraw_data(&state_account, "usdc", alice, 10),
+    )
+    .unwrap();
+    assert_eq!(total_deposited(), 90);
+    let result = process_instruction(
+        &program_id,
+        &bob_accounts,
+        &deposit_data(&state_account, "usdc", bob, 10),
+    );
+    assert_eq!(result, Ok(()));
+
+    // cap lowered below the total: balances stay, deposits blocked, withdrawals still work
+    assert_eq!(set_cap(Some(50)), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &alice_accounts,
+        &deposit_data(&state_account, "usdc", alice, 1),
+    );
+    assert_eq!(result, cap_exceeded);
+    let result = process_instruction(
+        &program_id,
+        &alice_accounts,
+        &withdraw_data(&state_account, "usdc", alice, 50),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(total_deposited(), 50);
+
+    // no cap, no limit
+    assert_eq!(set_cap(None), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &alice_accounts,
+        &deposit_data(&state_account, "usdc", alice, 900),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(total_deposited(), 950);
+    assert_eq!(token_amount(&vault), 950);
+
+    let result = process_instruction(
+        &program_id,
+        &admin_accounts,
+        &pack(&AdminSetDepositCap {
+            token: token("eth"),
+            cap: None,
+        }),
+    );
+    assert_eq!(result, Err(ContractError::TokenNotFound.into()));
+}
+
+#[test]
+fn test_max_user_balance() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1000);
+    let bob_token_account = new_token_account(mint.key, &bob, 1000);
+    let alice_accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    let set_max = |max_user_balance: Option<u64>| {
+        let instruction_data = pack(&AdminSetMaxUserBalance {
+            token: token("usdc"),
+            max_user_balance,
+        });
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    let transfer_data = |from: Pubkey, to: Pubkey, amount: u64| {
+        pack(&UserTransfer {
+            token: token("usdc"),
+            from,
+            to,
+            amount,
+        })
+    };
+    let cap_exceeded: ProgramResult = Err(ContractError::UserBalanceCapExceeded.into());
+
+    process_instruction(
+        &program_id,
+        &alice_accounts,
+        &deposit_data(&state_account, "usdc", alice, 300),
+    )
+    .unwrap();
+    assert_eq!(set_max(Some(100)), Ok(()));
+
+    // exactly on the ceiling is fine, one unit over is not
+    let result = process_instruction(
+        &program_id,
+        &bob_accounts,
+        &deposit_data(&state_account, "usdc", bob, 101),
+    );
+    assert_eq!(result, cap_exceeded);
+    let result = process_instruction(
+        &program_id,
+        &bob_accounts,
+        &deposit_data(&state_account, "usdc", bob, 100),
+    );
+    assert_eq!(result, Ok(()));
+
+    // internal transfers are credits too
+    let transfer_accounts = [
+        alice_accounts[0].clone(),
+        alice_accounts[1].clone(),
+        alice_accounts[6].clone(),
+        bob_accounts[6].clone(),
+    ];
+    let result = process_instruction(
+        &program_id,
+        &transfer_accounts,
+        &transfer_data(alice, bob, 1),
+    );
+    assert_eq!(result, cap_exceeded);
+
+    // alice is grandfathered above the ceiling, but can't grow
+    let result = process_instruction(
+        &program_id,
+        &alice_accounts,
+        &deposit_data(&state_account, "usdc", alice, 1),
+    );
+    assert_eq!(result, cap_exceeded);
+    let result = process_instruction(
+        &program_id,
+        &alice_accounts,
+        &withdraw_data(&state_account, "usdc", alice, 250),
+    );
+    assert_eq!(result, Ok(()));
+    let transfer_accounts = [
+        bob_accounts[0].clone(),
+        bob_accounts[1].clone(),
+        bob_accounts[6].clone(),
+        alice_accounts[6].clone(),
+    ];
+    let result = process_instruction(
+        &program_id,
+        &transfer_accounts,
+        &transfer_data(bob, alice, 50),
+    );
+    assert_eq!(result, Ok(()));
+
+    assert_eq!(set_max(None), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &bob_accounts,
+        &deposit_data(&state_account, "usdc", bob, 500),
+    );
+    assert_eq!(result, Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].balances,
+        BTreeMap::from([(alice, 100), (bob, 550)])
+    );
+}
+
+#[test]
+fn test_min_deposit() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 1000);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let set_min = |min_deposit: u64| {
+        let instruction_data = pack(&AdminSetMinDeposit {
+            token: token("usdc"),
+            min_deposit,
+        });
+        let admin_accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &admin_accounts, &instruction_data)
+    };
+    let below_minimum: ProgramResult = Err(ContractError::DepositBelowMinimum.into());
+
+    // default minimum is 0, dust goes through
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 1),
+    );
+    assert_eq!(result, Ok(()));
+
+    assert_eq!(set_min(10), Ok(()));
+    take_logs();
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 9),
+    );
+    assert_eq!(result, below_minimum);
+    assert!(take_logs().contains(&"minimum deposit of token usdc is 10".to_string()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 11),
+    );
+    assert_eq!(result, Ok(()));
+
+    // existing balances are kept when the minimum goes up, and can be withdrawn in dust
+    assert_eq!(set_min(100), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 1),
+    );
+    assert_eq!(result, Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].balances,
+        BTreeMap::from([(user, 21)])
+    );
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 99),
+    );
+    assert_eq!(result, below_minimum);
+}
+
+#[test]
+fn test_daily_withdrawal_limit() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 1000);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let set_limit = |limit: u64| {
+        let instruction_data = pack(&AdminSetDailyWithdrawalLimit {
+            token: token("usdc"),
+            limit,
+        });
+        let admin_accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &admin_accounts, &instruction_data)
+    };
+    let withdraw = |amount: u64| {
+        process_instruction(
+            &program_id,
+            &accounts,
+            &withdraw_data(&state_account, "usdc", user, amount),
+        )
+    };
+    let limit_exceeded: ProgramResult = Err(ContractError::DailyLimitExceeded.into());
+
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 1000),
+    )
+    .unwrap();
+    assert_eq!(set_limit(100), Ok(()));
+
+    // withdrawals accumulate within the window
+    set_unix_timestamp(1_000_000);
+    assert_eq!(withdraw(60), Ok(()));
+    set_unix_timestamp(1_000_000 + 3600);
+    assert_eq!(withdraw(41), limit_exceeded);
+    assert_eq!(withdraw(40), Ok(()));
+    assert_eq!(withdraw(1), limit_exceeded);
+
+    // last second of the window
+    set_unix_timestamp(1_000_000 + 86_399);
+    assert_eq!(withdraw(1), limit_exceeded);
+
+    // window rolled over, the full allowance is back
+    set_unix_timestamp(1_000_000 + 86_400);
+    assert_eq!(withdraw(101), limit_exceeded);
+    assert_eq!(withdraw(100), Ok(()));
+    assert_eq!(token_amount(&user_token_account), 200);
+
+    // 0 means no limit
+    assert_eq!(set_limit(0), Ok(()));
+    assert_eq!(withdraw(500), Ok(()));
+    assert_eq!(token_amount(&user_token_account), 700);
+}
+
+#[test]
+fn test_timelocked_withdraw() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let admin_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        accounts[6].clone(),
+    ];
+    let request_accounts = [
+        accounts[0].clone(),
+        accounts[1].clone(),
+        accounts[6].clone(),
+    ];
+    let request = |amount: u64| {
+        let instruction_data = pack(&UserRequestWithdraw {
+            token: token("usdc"),
+            user,
+            amount,
+        });
+        process_instruction(&program_id, &request_accounts, &instruction_data)
+    };
+    let claim_data = pack(&UserClaimWithdraw {
+        token: token("usdc"),
+        user,
+    });
+    let cancel_data = pack(&AdminCancelWithdrawRequest {
+        token: token("usdc"),
+        user,
+    });
+    let balance = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .get(&user)
+            .copied()
+    };
+
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    assert_eq!(
+        load_full_state(&state_account)
+            .unwrap()
+            .withdraw_delay_slots,
+        1000
+    );
+
+    set_slot(5000);
+    assert_eq!(request(101), Err(ProgramError::InsufficientFunds));
+    assert_eq!(request(70), Ok(()));
+    assert_eq!(balance(), Some(30));
+    // locked amount can't be withdrawn normally or requested again
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 31),
+    );
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert_eq!(
+        request(10),
+        Err(ContractError::WithdrawRequestPending.into())
+    );
+
+    // too early
+    set_slot(5999);
+    let result = process_instruction(&program_id, &accounts, &claim_data);
+    assert_eq!(result, Err(ContractError::WithdrawRequestLocked.into()));
+
+    set_slot(6000);
+    let result = process_instruction(&program_id, &accounts, &claim_data);
+    assert_eq!(result, Ok(()));
+    assert_eq!(token_amount(&user_token_account), 70);
+    assert_eq!(token_amount(&vault), 30);
+    assert_eq!(balance(), Some(30));
+    let result = process_instruction(&program_id, &accounts, &claim_data);
+    assert_eq!(result, Err(ContractError::NoWithdrawRequest.into()));
+
+    // admin cancels, funds are available again
+    assert_eq!(request(30), Ok(()));
+    assert_eq!(balance(), Some(0));
+    let user_signer = &accounts[..2];
+    let result = process_instruction(&program_id, user_signer, &cancel_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    assert_eq!(
+        process_instruction(&program_id, &admin_accounts, &cancel_data),
+        Ok(())
+    );
+    assert_eq!(balance(), Some(30));
+    set_slot(10_000);
+    let result = process_instruction(&program_id, &accounts, &claim_data);
+    assert_eq!(result, Err(ContractError::NoWithdrawRequest.into()));
+    assert_eq!(
+        process_instruction(&program_id, &admin_accounts, &cancel_data),
+        Err(ContractError::NoWithdrawRequest.into())
+    );
+
+    // locked amounts count as outstanding when deleting the token
+    assert_eq!(request(30), Ok(()));
+    let delete_data = pack(&AdminDeleteSupportedToken {
+        token: token("usdc"),
+    });
+    let result = process_timelocked(&program_id, &admin_accounts, &delete_data);
+    assert_eq!(
+        result,
+        Err(ContractError::TokenHasOutstandingBalances.into())
+    );
+
+    // shorter delay applies to pending requests
+    let delay_data = pack(&AdminSetWithdrawDelay { delay_slots: 0 });
+    process_instruction(&program_id, &admin_accounts, &delay_data).unwrap();
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &claim_data),
+        Ok(())
+    );
+    assert_eq!(token_amount(&user_token_account), 100);
+}
+
+#[test]
+fn test_withdrawal_fee() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 1_000_000);
+    let mut accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    accounts.push(balance_account(&program_id, mint.key, &treasury));
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_fee = |fee_bps: u16| {
+        let instruction_data = pack(&AdminSetWithdrawalFee {
+            token: token("usdc"),
+            fee_bps,
+        });
+        process_timelocked(&program_id, &admin_accounts, &instruction_data)
+    };
+    let balance_of = |user: &Pubkey| {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+            .unwrap_or(0)
+    };
+
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 1_000_000),
+    )
+    .unwrap();
+    assert_eq!(set_fee(1001), Err(ContractError::FeeTooHigh.into()));
+    assert_eq!(set_fee(30), Ok(()));
+    // nowhere to put the fee yet
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10_000),
+    );
+    assert_eq!(result, Err(ContractError::TreasuryNotSet.into()));
+    let treasury_data = pack(&AdminSetTreasury { treasury });
+    process_instruction(&program_id, &admin_accounts, &treasury_data).unwrap();
+
+    // payout + fee always equals what the user was debited, tiny amounts round the fee to 0
+    for (amount, fee) in [(10_000, 30), (1, 0), (333, 0), (334, 1), (9_999, 29)] {
+        let user_before = balance_of(&user);
+        let treasury_before = balance_of(&treasury);
+        let received_before = token_amount(&user_token_account);
+        let result = process_instruction(
+            &program_id,
+            &accounts,
+            &withdraw_data(&state_account, "usdc", user, amount),
+        );
+        assert_eq!(result, Ok(()));
+        let debited = user_before - balance_of(&user);
+        let fee_credited = balance_of(&treasury) - treasury_before;
+        let payout = token_amount(&user_token_account) - received_before;
+        assert_eq!(debited, amount);
+        assert_eq!(fee_credited, fee);
+        assert_eq!(payout + fee_credited, debited);
+    }
+    // vault still backs every ledger balance
+    assert_eq!(
+        token_amount(&vault),
+        balance_of(&user) + balance_of(&treasury)
+    );
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].total_deposited,
+        token_amount(&vault)
+    );
+
+    // admin pulls the accumulated fees, without a fee
+    let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, 0);
+    let mut admin_transfer_accounts =
+        user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, &vault);
+    admin_transfer_accounts[1] = admin_account(true);
+    admin_transfer_accounts[6] = balance_account(&program_id, mint.key, &treasury);
+    let pull = |amount: u64| {
+        let instruction_data = pack(&AdminWithdrawTreasury {
+            token: token("usdc"),
+            amount,
+        });
+        process_instruction(&program_id, &admin_transfer_accounts, &instruction_data)
+    };
+    assert_eq!(pull(61), Err(ProgramError::InsufficientFunds));
+    assert_eq!(pull(60), Ok(()));
+    assert_eq!(token_amount(&admin_token_account), 60);
+    assert_eq!(balance_of(&treasury), 0);
+    // users can't pull the treasury
+    let mut forged = admin_transfer_accounts.clone();
+    forged[1] = new_account(user, true, 0, Pubkey::default());
+    let instruction_data = pack(&AdminWithdrawTreasury {
+        token: token("usdc"),
+        amount: 0,
+    });
+    let result = process_instruction(&program_id, &forged, &instruction_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+}
+
+#[test]
+fn test_deposit_fee() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let market_maker = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100_000);
+    let market_maker_token_account = new_token_account(mint.key, &market_maker, 100_000);
+    let treasury_token_account = new_token_account(mint.key, &treasury, 100_000);
+    let treasury_balance_account = balance_account(&program_id, mint.key, &treasury);
+    let mut user_accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    user_accounts.push(treasury_balance_account.clone());
+    let mut market_maker_accounts = user_token_accounts(
+        &state_account,
+        &market_maker,
+        &market_maker_token_account,
+        &vault,
+    );
+    market_maker_accounts.push(treasury_balance_account);
+    let treasury_accounts =
+        user_token_accounts(&state_account, &treasury, &treasury_token_account, &vault);
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let admin = |instruction: &ContractInstruction| {
+        process_instruction(&program_id, &admin_accounts, &pack(instruction))
+    };
+    let set_fee = |fee_bps: u16| {
+        let data = pack(&AdminSetDepositFee {
+            token: token("usdc"),
+            fee_bps,
+        });
+        process_timelocked(&program_id, &admin_accounts, &data)
+    };
+    let balance_of = |user: &Pubkey| {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+            .unwrap_or(0)
+    };
+
+    admin(&AdminSetTreasury { treasury }).unwrap();
+    assert_eq!(set_fee(1001), Err(ContractError::FeeTooHigh.into()));
+    // the maximum fee is 10%
+    assert_eq!(set_fee(1000), Ok(()));
+    process_instruction(
+        &program_id,
+        &user_accounts,
+        &deposit_data(&state_account, "usdc", user, 10_000),
+    )
+    .unwrap();
+    assert_eq!(balance_of(&user), 9_000);
+    assert_eq!(balance_of(&treasury), 1_000);
+
+    assert_eq!(set_fee(50), Ok(()));
+    process_instruction(
+        &program_id,
+        &user_accounts,
+        &deposit_data(&state_account, "usdc", user, 10_000),
+    )
+    .unwrap();
+    assert_eq!(balance_of(&user), 18_950);
+    assert_eq!(balance_of(&treasury), 1_050);
+
+    // exempt users and the treasury itself pay nothing
+    admin(&AdminSetFeeExempt {
+        user: market_maker,
+        exempt: true,
+    })
+    .unwrap();
+    process_instruction(
+        &program_id,
+        &market_maker_accounts,
+        &deposit_data(&state_account, "usdc", market_maker, 10_000),
+    )
+    .unwrap();
+    assert_eq!(balance_of(&market_maker), 10_000);
+    process_instruction(
+        &program_id,
+        &treasury_accounts,
+        &deposit_data(&state_account, "usdc", treasury, 10_000),
+    )
+    .unwrap();
+    assert_eq!(balance_of(&treasury), 11_050);
+    assert_eq!(balance_of(&user), 18_950);
+
+    // exemption revoked
+    admin(&AdminSetFeeExempt {
+        user: market_maker,
+        exempt: false,
+    })
+    .unwrap();
+    process_instruction(
+        &program_id,
+        &market_maker_accounts,
+        &deposit_data(&state_account, "usdc", market_maker, 10_000),
+    )
+    .unwrap();
+    assert_eq!(balance_of(&market_maker), 19_950);
+
+    // no fee
+    assert_eq!(set_fee(0), Ok(()));
+    process_instruction(
+        &program_id,
+        &user_accounts,
+        &deposit_data(&state_account, "usdc", user, 1),
+    )
+    .unwrap();
+    assert_eq!(balance_of(&user), 18_951);
+
+    // ledger sums to what is in the vault
+    assert_eq!(
+        token_amount(&vault),
+        balance_of(&user) + balance_of(&market_maker) + balance_of(&treasury)
+    );
+}
+
+#[test]
+fn test_approve_transfer_from() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let owner = Pubkey::new_unique();
+    let spender = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    let owner_token_account = new_token_account(mint.key, &owner, 100);
+    let accounts = user_token_accounts(&state_account, &owner, &owner_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", owner, 100),
+    )
+    .unwrap();
+    let owner_accounts = &accounts[..2];
+    // [state, spender], the owner's and recipient's balance accounts and the system program
+    let spender_accounts = [
+        state_account.clone(),
+        new_account(spender, true, 0, Pubkey::default()),
+        accounts[6].clone(),
+        balance_account(&program_id, mint.key, &recipient),
+        system_program_account(),
+    ];
+    let approve = |amount: u64| {
+        let instruction_data = pack(&UserApprove {
+            token: token("usdc"),
+            owner,
+            spender,
+            amount,
+        });
+        process_instruction(&program_id, owner_accounts, &instruction_data)
+    };
+    let transfer_from = |amount: u64| {
+        let instruction_data = pack(&TransferFrom {
+            token: token("usdc"),
+            owner,
+            spender,
+            to: recipient,
+            amount,
+        });
+        process_instruction(&program_id, &spender_accounts, &instruction_data)
+    };
+    let balances = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .clone()
+    };
+
+    assert_eq!(transfer_from(10), Err(ContractError::NoAllowance.into()));
+    // only the owner approves
+    let instruction_data = pack(&UserApprove {
+        token: token("usdc"),
+        owner,
+        spender,
+        amount: 10,
+    });
+    let result = process_instruction(&program_id, &spender_accounts, &instruction_data);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    assert_eq!(approve(50), Ok(()));
+    assert_eq!(transfer_from(30), Ok(()));
+    assert_eq!(balances(), BTreeMap::from([(owner, 70), (recipient, 30)]));
+    assert_eq!(
+        transfer_from(21),
+        Err(ContractError::AllowanceExceeded.into())
+    );
+    assert_eq!(transfer_from(20), Ok(()));
+    // allowance used up
+    assert_eq!(transfer_from(1), Err(ContractError::NoAllowance.into()));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .allowances
+        .is_empty());
+
+    // approving 0 revokes
+    assert_eq!(approve(40), Ok(()));
+    assert_eq!(approve(0), Ok(()));
+    assert_eq!(transfer_from(1), Err(ContractError::NoAllowance.into()));
+
+    // allowance larger than what the owner actually holds
+    assert_eq!(approve(1000), Ok(()));
+    assert_eq!(transfer_from(51), Err(ProgramError::InsufficientFunds));
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.allowances[&(token("usdc"), owner, spender)], 1000);
+    assert_eq!(balances(), BTreeMap::from([(owner, 50), (recipient, 50)]));
+}
+
+#[test]
+fn test_withdraw_delegate() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let delegate = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    let signed_by = |signer: Pubkey, destination: &AccountInfo<'static>| {
+        let mut accounts = user_token_accounts(&state_account, &user, destination, &vault);
+        accounts[1] = new_account(signer, true, 0, Pubkey::default());
+        accounts
+    };
+    let set_delegate = |signer: Pubkey, delegate: Option<Pubkey>| {
+        let instruction_data = pack(&UserSetWithdrawDelegate { user, delegate });
+        let accounts = [
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+        ];
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    let withdraw = || withdraw_data(&state_account, "usdc", user, 10);
+
+    // not a delegate yet
+    let result = process_instruction(
+        &program_id,
+        &signed_by(delegate, &user_token_account),
+        &withdraw(),
+    );
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    assert_eq!(set_delegate(user, Some(delegate)), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &signed_by(delegate, &user_token_account),
+        &withdraw(),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(token_amount(&user_token_account), 10);
+
+    // funds only go to the user
+    let delegate_token_account = new_token_account(mint.key, &delegate, 0);
+    let result = process_instruction(
+        &program_id,
+        &signed_by(delegate, &delegate_token_account),
+        &withdraw(),
+    );
+    assert_eq!(result, Err(ContractError::DelegateRecipientNotUser.into()));
+
+    // random key
+    let result = process_instruction(
+        &program_id,
+        &signed_by(Pubkey::new_unique(), &user_token_account),
+        &withdraw(),
+    );
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    // delegate can't replace or remove itself
+    assert_eq!(
+        set_delegate(delegate, Some(Pubkey::new_unique())),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(
+        set_delegate(delegate, None),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+
+    // revoked immediately
+    assert_eq!(set_delegate(user, None), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &signed_by(delegate, &user_token_account),
+        &withdraw(),
+    );
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    assert_eq!(token_amount(&user_token_account), 10);
+}
+
+#[test]
+fn test_deposit_for_beneficiary() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let payer = Pubkey::new_unique();
+    let beneficiary = Pubkey::new_unique();
+    let payer_token_account = new_token_account(mint.key, &payer, 100);
+    let deposit = |amount: u64| {
+        let data = pack(&UserDeposit {
+            token: token("usdc"),
+            user: payer,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, payer),
+            lock_until: None,
+            referrer: None,
+            beneficiary: Some(beneficiary),
+            memo: None,
+        });
+        let mut accounts =
+            user_token_accounts(&state_account, &payer, &payer_token_account, &vault);
+        accounts[6] = balance_account(&program_id, mint.key, &beneficiary);
+        accounts.insert(7, balance_account(&program_id, mint.key, &payer));
+        accounts[9] = receipts_account(&program_id, &beneficiary);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let balance_of = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        state.all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+    };
+
+    // a brand-new beneficiary is credited, the payer's nonce is the one used up, kept in a
+    // balance account of the payer's own with nothing in it
+    take_events::<DepositEvent>();
+    assert_eq!(deposit(40), Ok(()));
+    assert_eq!(token_amount(&payer_token_account), 60);
+    assert_eq!(balance_of(&beneficiary), Some(40));
+    assert_eq!(balance_of(&payer), Some(0));
+    assert_eq!(nonce_of(&state_account, mint.key, payer), 1);
+    assert_eq!(nonce_of(&state_account, mint.key, beneficiary), 0);
+    let events = take_events::<DepositEvent>();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].user, events[0].payer), (beneficiary, payer));
+    assert_eq!(events[0].new_balance, 40);
+
+    // the per-user cap applies to the beneficiary's balance
+    let data = pack(&AdminSetMaxUserBalance {
+        token: token("usdc"),
+        max_user_balance: Some(50),
+    });
+    let accounts = [state_account.clone(), admin_account(true)];
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    assert_eq!(
+        deposit(20),
+        Err(ContractError::UserBalanceCapExceeded.into())
+    );
+
+    // a blocked beneficiary can't be credited
+    let data = pack(&AdminSetBlocked {
+        user: beneficiary,
+        blocked: true,
+    });
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    assert_eq!(deposit(5), Err(ContractError::UserBlocked.into()));
+    assert_eq!(balance_of(&beneficiary), Some(40));
+    assert_eq!(token_amount(&payer_token_account), 60);
+}
+
+#[test]
+fn test_withdraw_to_recipient() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let recipient_token_account = new_token_account(mint.key, &recipient, 0);
+    process_instruction(
+        &program_id,
+        &user_token_accounts(&state_account, &user, &user_token_account, &vault),
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    let withdraw = |destination: &AccountInfo<'static>, recipient: Option<Pubkey>| {
+        let data = pack(&UserWithdraw {
+            token: token("usdc"),
+            user,
+            amount: 30,
+            nonce: nonce_of(&state_account, mint.key, user),
+            eth_signature: None,
+            recipient,
+            memo: None,
+        });
+        let accounts = user_token_accounts(&state_account, &user, destination, &vault);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let balance_of = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        state.all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+    };
+
+    // user's ledger pays, recipient's token account receives
+    take_events::<WithdrawEvent>();
+    assert_eq!(withdraw(&recipient_token_account, Some(recipient)), Ok(()));
+    assert_eq!(token_amount(&recipient_token_account), 30);
+    assert_eq!(balance_of(&user), Some(70));
+    assert_eq!(balance_of(&recipient), None);
+    let events = take_events::<WithdrawEvent>();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].user, events[0].recipient), (user, recipient));
+
+    // the payout account must belong to the named recipient
+    let result = withdraw(&user_token_account, Some(recipient));
+    assert_eq!(result, Err(ContractError::RecipientMismatch.into()));
+    assert_eq!(balance_of(&user), Some(70));
+
+    // without a recipient the event names the payout account's owner
+    assert_eq!(withdraw(&user_token_account, None), Ok(()));
+    let events = take_events::<WithdrawEvent>();
+    assert_eq!(events[0].recipient, user);
+
+    // a delegate still can't pay out to anyone but user
+    let delegate = Pubkey::new_unique();
+    let data = pack(&UserSetWithdrawDelegate {
+        user,
+        delegate: Some(delegate),
+    });
+    let accounts = [
+        state_account.clone(),
+        new_account(user, true, 0, Pubkey::default()),
+    ];
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    let mut accounts = user_token_accounts(&state_account, &user, &recipient_token_account, &vault);
+    accounts[1] = new_account(delegate, true, 0, Pubkey::default());
+    let data = pack(&UserWithdraw {
+        token: token("usdc"),
+        user,
+        amount: 10,
+        nonce: nonce_of(&state_account, mint.key, user),
+        eth_signature: None,
+        recipient: Some(recipient),
+        memo: None,
+    });
+    let result = process_instruction(&program_id, &accounts, &data);
+    assert_eq!(result, Err(ContractError::DelegateRecipientNotUser.into()));
+    assert_eq!(token_amount(&recipient_token_account), 30);
+}
+
+#[test]
+fn test_memo() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let mut memo_accounts = accounts.clone();
+    memo_accounts.push(new_account(spl_memo::id(), false, 0, Pubkey::default()));
+    let deposit = |memo: Option<&str>| {
+        pack(&UserDeposit {
+            token: token("usdc"),
+            user,
+            amount: 10,
+            nonce: nonce_of(&state_account, mint.key, user),
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: memo.map(str::to_string),
+        })
+    };
+    let withdraw = |memo: Option<&str>| {
+        pack(&UserWithdraw {
+            token: token("usdc"),
+            user,
+            amount: 5,
+            nonce: nonce_of(&state_account, mint.key, user),
+            eth_signature: None,
+            recipient: None,
+            memo: memo.map(str::to_string),
+        })
+    };
+
+    // the memo reaches the event as given
+    take_events::<DepositEvent>();
+    let result = process_instruction(&program_id, &accounts, &deposit(Some("invoice 17")));
+    assert_eq!(result, Ok(()));
+    let events = take_events::<DepositEvent>();
+    assert_eq!(events[0].memo.as_deref(), Some("invoice 17"));
+    take_events::<WithdrawEvent>();
+    let result = process_instruction(&program_id, &accounts, &withdraw(Some("ref 9")));
+    assert_eq!(result, Ok(()));
+    let events = take_events::<WithdrawEvent>();
+    assert_eq!(events[0].memo.as_deref(), Some("ref 9"));
+
+    // with the memo program passed it logs the memo too
+    take_logs();
+    let result = process_instruction(&program_id, &memo_accounts, &deposit(Some("invoice 18")));
+    assert_eq!(result, Ok(()));
+    assert!(take_logs()
+        .iter()
+        .any(|log| log == "Memo (len 10): \"invoice 18\""));
+
+    // 64 bytes fit, 65 fail before the nonce is used
+    let longest = "m".repeat(64);
+    let result = process_instruction(&program_id, &memo_accounts, &deposit(Some(&longest)));
+    assert_eq!(result, Ok(()));
+    let nonce = nonce_of(&state_account, mint.key, user);
+    let too_long = "m".repeat(65);
+    for data in [deposit(Some(&too_long)), withdraw(Some(&too_long))] {
+        let result = process_instruction(&program_id, &memo_accounts, &data);
+        assert_eq!(result, Err(ContractError::MemoTooLong.into()));
+    }
+    assert_eq!(nonce_of(&state_account, mint.key, user), nonce);
+    assert_eq!(token_amount(&user_token_account), 75);
+
+    // no memo, nothing is logged for it
+    take_logs();
+    take_events::<WithdrawEvent>();
+    let result = process_instruction(&program_id, &memo_accounts, &withdraw(None));
+    assert_eq!(result, Ok(()));
+    assert!(!take_logs().iter().any(|log| log.starts_with("Memo")));
+    assert_eq!(take_events::<WithdrawEvent>()[0].memo, None);
+    assert_eq!(token_amount(&user_token_account), 80);
+}
+
+#[test]
+fn test_freeze_user() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let admin = |instruction: &ContractInstruction| {
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &pack(instruction))
+    };
+    let freeze = AdminFreezeUser {
+        token: token("usdc"),
+        user,
+    };
+    let unfreeze = AdminUnfreezeUser {
+        token: token("usdc"),
+        user,
+    };
+    let frozen: ProgramResult = Err(ContractError::AccountFrozen.into());
+
+    // pre-emptive freeze, deposits still land
+    assert_eq!(admin(&freeze), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    );
+    assert_eq!(result, Ok(()));
+
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, frozen);
+    let transfer_data = pack(&UserTransfer {
+        token: token("usdc"),
+        from: user,
+        to: Pubkey::new_unique(),
+        amount: 10,
+    });
+    let result = process_instruction(&program_id, &accounts[..2], &transfer_data);
+    assert_eq!(result, frozen);
+    let request_data = pack(&UserRequestWithdraw {
+        token: token("usdc"),
+        user,
+        amount: 10,
+    });
+    let result = process_instruction(&program_id, &accounts[..2], &request_data);
+    assert_eq!(result, frozen);
+
+    // freezing twice is fine, one unfreeze lifts it
+    assert_eq!(admin(&freeze), Ok(()));
+    assert_eq!(admin(&unfreeze), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(token_amount(&user_token_account), 10);
+    assert_eq!(admin(&unfreeze), Ok(()));
+}
+
+#[test]
+fn test_blocklist() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let other_token_account = new_token_account(mint.key, &other, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let other_accounts = user_token_accounts(&state_account, &other, &other_token_account, &vault);
+    let set_blocked = |user: Pubkey, blocked: bool| {
+        let instruction_data = pack(&AdminSetBlocked { user, blocked });
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    let transfer_data = |from: Pubkey, to: Pubkey| {
+        pack(&UserTransfer {
+            token: token("usdc"),
+            from,
+            to,
+            amount: 10,
+        })
+    };
+    let blocked: ProgramResult = Err(ContractError::UserBlocked.into());
+
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 50),
+    )
+    .unwrap();
+    process_instruction(
+        &program_id,
+        &other_accounts,
+        &deposit_data(&state_account, "usdc", other, 50),
+    )
+    .unwrap();
+    assert_eq!(
+        set_blocked(TEST_ADMIN, true),
+        Err(ContractError::CannotBlockAdmin.into())
+    );
+    assert_eq!(set_blocked(user, true), Ok(()));
+    let state_before = load_full_state(&state_account).unwrap();
+
+    // the deposited funds are inert
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, blocked);
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, blocked);
+    let result = process_instruction(&program_id, &accounts[..2], &transfer_data(user, other));
+    assert_eq!(result, blocked);
+    // can't receive either
+    let result = process_instruction(
+        &program_id,
+        &other_accounts[..2],
+        &transfer_data(other, user),
+    );
+    assert_eq!(result, blocked);
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")],
+        state_before.all_token_balances[&token("usdc")]
+    );
+    assert_eq!(token_amount(&user_token_account), 50);
+
+    assert_eq!(set_blocked(user, false), Ok(()));
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 50),
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(token_amount(&user_token_account), 100);
+}
+
+#[test]
+fn test_operator_permissions() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    add_token(&program_id, &state_account, "usdc");
+    let operator = Pubkey::new_unique();
+    let stranger = Pubkey::new_unique();
+    let run = |signer: Pubkey, instruction: &ContractInstruction| {
+        let mut accounts = vec![
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+        ];
+        match instruction {
+            AdminAddSupportedToken { token, .. } => {
+                accounts.push(new_mint_account(&token.symbol));
+                accounts.push(new_vault_account(&program_id, &token.mint));
+            }
+            AdminUpdateTokenMetadata { token, .. } => {
+                accounts.push(new_mint_account(&token.symbol));
+            }
+            AdminDeleteSupportedToken { token } => {
+                accounts.push(new_vault_account(&program_id, &token.mint));
+            }
+            _ => {}
+        }
+        match instruction {
+            AdminDeleteSupportedToken { .. }
+            | AdminProposeNewAdmin { .. }
+            | AdminSetWithdrawalFee { .. }
+            | AdminSetDepositFee { .. } => {
+                process_timelocked(&program_id, &accounts, &pack(instruction))
+            }
+            _ => process_instruction(&program_id, &accounts, &pack(instruction)),
+        }
+    };
+    let set_operator = AdminSetOperator {
+        operator: Some(operator),
+    };
+
+    // only the admin picks the operator
+    assert_eq!(
+        run(stranger, &set_operator),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(run(TEST_ADMIN, &set_operator), Ok(()));
+    assert_eq!(
+        run(operator, &set_operator),
+        Err(ContractError::InsufficientAuthority.into())
+    );
+
+    let denied: ProgramResult = Err(ContractError::InsufficientAuthority.into());
+    let unsigned: ProgramResult = Err(ProgramError::MissingRequiredSignature);
+    // (instruction, operator allowed)
+    let matrix = [
+        (
+            AdminAddSupportedToken {
+                token: token("eth"),
+                metadata: metadata(),
+            },
+            false,
+        ),
+        (
+            AdminDeleteSupportedToken {
+                token: token("eth"),
+            },
+            false,
+        ),
+        (AdminSetMaxTokens { max_tokens: 10 }, false),
+        (
+            AdminSetRateLimit {
+                max_ops: 5,
+                window_slots: 50,
+            },
+            false,
+        ),
+        (
+            AdminSetTreasury {
+                treasury: Pubkey::new_unique(),
+            },
+            false,
+        ),
+        (
+            AdminProposeNewAdmin {
+                new_admin: Pubkey::new_unique(),
+            },
+            false,
+        ),
+        (
+            AdminUpdateTokenMetadata {
+                token: token("usdc"),
+                metadata: metadata(),
+            },
+            false,
+        ),
+        (
+            AdminRenameToken {
+                old: token("usdc"),
+                new_symbol: "usdce".to_string(),
+            },
+            false,
+        ),
+        (
+            AdminPause {
+                withdrawals_only: false,
+            },
+            true,
+        ),
+        (AdminUnpause, true),
+        (
+            AdminSetWithdrawalFee {
+                token: token("usdc"),
+                fee_bps: 10,
+            },
+            true,
+        ),
+        (
+            AdminSetDepositFee {
+                token: token("usdc"),
+                fee_bps: 10,
+            },
+            true,
+        ),
+        (
+            AdminFreezeUser {
+                token: token("usdc"),
+                user: stranger,
+            },
+            true,
+        ),
+        (
+            AdminUnfreezeUser {
+                token: token("usdc"),
+                user: stranger,
+            },
+            true,
+        ),
+    ];
+    for (instruction, operator_allowed) in &matrix {
+        assert_eq!(run(stranger, instruction), unsigned, "{:?}", instruction);
+        let expected = if *operator_allowed {
+            Ok(())
+        } else {
+            denied.clone()
+        };
+        assert_eq!(run(operator, instruction), expected, "{:?}", instruction);
+    }
+    // the admin keeps every permission
+    for (instruction, _) in &matrix {
+        let result = run(TEST_ADMIN, instruction);
+        assert_eq!(result, Ok(()), "{:?}", instruction);
+    }
+
+    // clearing the operator revokes it
+    assert_eq!(
+        run(TEST_ADMIN, &AdminSetOperator { operator: None }),
+        Ok(())
+    );
+    assert_eq!(run(operator, &AdminUnpause), unsigned);
+}
+
+#[test]
+fn test_nonce() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let nonce_of = |symbol: &str, user: Pubkey| {
+        let data = pack(&GetNonce {
+            token: token(symbol),
+            user,
+        });
+        let accounts = [
+            state_account.clone(),
+            balance_account(&program_id, &token(symbol).mint, &user),
+        ];
+        process_instruction(&program_id, &accounts, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        u64::from_le_bytes(data.try_into().unwrap())
+    };
+    let get_nonce = || nonce_of("usdc", user);
+    let deposit = |nonce: u64| {
+        pack(&UserDeposit {
+            token: token("usdc"),
+            user,
+            amount: 10,
+            nonce,
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        })
+    };
+    let invalid_nonce: ProgramResult = Err(ContractError::InvalidNonce.into());
+
+    assert_eq!(get_nonce(), 0);
+    let first = deposit(0);
+    assert_eq!(process_instruction(&program_id, &accounts, &first), Ok(()));
+    assert_eq!(get_nonce(), 1);
+
+    // replaying the same payload
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &first),
+        invalid_nonce
+    );
+    assert!(take_logs().contains(&"expected nonce 1, got 0".to_string()));
+    // skipping ahead
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit(2)),
+        invalid_nonce
+    );
+    assert_eq!(get_nonce(), 1);
+    assert_eq!(token_amount(&user_token_account), 90);
+
+    // withdrawals share the counter
+    let withdraw = |nonce: u64| {
+        pack(&UserWithdraw {
+            token: token("usdc"),
+            user,
+            amount: 10,
+            nonce,
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        })
+    };
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw(0)),
+        invalid_nonce
+    );
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw(1)),
+        Ok(())
+    );
+    assert_eq!(get_nonce(), 2);
+
+    // a failed instruction doesn't consume the nonce
+    let result = process_instruction(&program_id, &accounts, &withdraw(2));
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert_eq!(get_nonce(), 2);
+
+    // the nonce is kept in the balance account, which the withdrawal emptied and closed, one
+    // created again at its address starts where it was, so no old payload becomes valid
+    assert_eq!(accounts[6].owner, &system_program::id());
+    assert_eq!(accounts[6].lamports(), 0);
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &first),
+        invalid_nonce
+    );
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit(2)),
+        Ok(())
+    );
+    assert_eq!(get_nonce(), 3);
+    assert_eq!(nonce_of_account(&accounts[6]), 3);
+
+    // every balance account has its own, a new one starts past those of closed accounts
+    add_token(&program_id, &state_account, "dai");
+    assert_eq!(nonce_of("dai", user), 2);
+    assert_eq!(nonce_of("usdc", Pubkey::new_unique()), 2);
+}
+
+// the nonce field of a balance account, read in place
+fn nonce_of_account(account: &AccountInfo) -> u64 {
+    BalanceAccountData::load(&account.data.borrow())
+        .unwrap()
+        .nonce()
+}
+
+#[test]
+fn test_legacy_balance_account() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+
+    // a balance account written before flags, last_update_slot and nonce, by a program that
+    // kept the user's nonce in the state
+    let (address, bump) = find_balance_address(&program_id, mint.key, &user);
+    let legacy = new_growable_account(address, &program_id, BALANCE_ACCOUNT_V0_SIZE);
+    let mut state = load_state(&state_account).unwrap();
+    let balance = BalanceAccount {
+        mint: *mint.key,
+        user,
+        bump,
+        epoch: state.token_epochs[mint.key],
+        amount: 30,
+        flags: 0,
+        last_update_slot: 0,
+        nonce: 0,
+        stats: UserStats::default(),
+        slots: BalanceSlots::default(),
+    };
+    let mut data = vec![BALANCE_ACCOUNT_TAG];
+    balance.serialize(&mut data).unwrap();
+    legacy
+        .data
+        .borrow_mut()
+        .copy_from_slice(&data[..BALANCE_ACCOUNT_V0_SIZE]);
+    BALANCE_ACCOUNTS.with(|accounts| accounts.borrow_mut().insert(address, legacy.clone()));
+    state.nonces.insert(user, 5);
+    let stats = UserStats {
+        deposit_count: 2,
+        withdraw_count: 0,
+        lifetime_deposited: 30,
+        lifetime_withdrawn: 0,
+    };
+    state.user_stats.insert((*mint.key, user), stats);
+    let payload = borsh::to_vec(&state).unwrap();
+    {
+        let mut data = state_account.data.borrow_mut();
+        data[9..STATE_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        data[STATE_HEADER_LEN..STATE_HEADER_LEN + payload.len()].copy_from_slice(&payload);
+    }
+
+    // its balance reads as it is, its nonce is the user's old one
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.all_token_balances[&token("usdc")].balances[&user], 30);
+    assert_eq!(nonce_of(&state_account, mint.key, user), 5);
+
+    // the first write brings it to the current layout, the signer topping its rent up, and
+    // moves the stats kept in the state into it
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let stale = pack(&UserDeposit {
+        token: token("usdc"),
+        user,
+        amount: 10,
+        nonce: 4,
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &stale),
+        Err(ContractError::InvalidNonce.into())
+    );
+    set_slot(77);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 10),
+    )
+    .unwrap();
+    assert_eq!(legacy.data_len(), BALANCE_ACCOUNT_SIZE);
+    assert_eq!(
+        legacy.lamports(),
+        Rent::default().minimum_balance(BALANCE_ACCOUNT_SIZE)
+    );
+    assert_eq!(
+        BalanceAccount::from(BalanceAccountData::load(&legacy.data.borrow()).unwrap()),
+        BalanceAccount {
+            amount: 40,
+            last_update_slot: 77,
+            nonce: 6,
+            stats: UserStats {
+                deposit_count: 3,
+                lifetime_deposited: 40,
+                ..stats
+            },
+            slots: BalanceSlots {
+                last_deposit_slot: 77,
+                last_withdraw_slot: 0,
+            },
+            ..balance
+        }
+    );
+    assert_eq!(nonce_of(&state_account, mint.key, user), 6);
+    assert!(load_state(&state_account).unwrap().user_stats.is_empty());
+    set_slot(0);
+}
+
+#[test]
+fn test_instruction_versions() {
+    let instructions = [
+        UserDeposit {
+            token: token("usdc"),
+            user: Pubkey::new_unique(),
+            amount: 42,
+            nonce: 7,
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        },
+        BatchWithdraw {
+            user: Pubkey::new_unique(),
+            items: vec![(token("usdc"), 1), (token("ray"), 2)],
+        },
+        UserSetWithdrawDelegate {
+            user: Pubkey::new_unique(),
+            delegate: None,
+        },
+        AdminUnpause,
+    ];
+    for instruction in &instructions {
+        // version 0, the original JSON layout
+        let mut json = vec![INSTRUCTION_VERSION_JSON];
+        json.extend(serde_json::to_vec(instruction).unwrap());
+        assert_eq!(deserialize_instruction(&json).as_ref(), Ok(instruction));
+
+        // version 1, borsh
+        let data = pack(instruction);
+        assert_eq!(data[0], INSTRUCTION_VERSION_BORSH);
+        assert_eq!(deserialize_instruction(&data).as_ref(), Ok(instruction));
+
+        // Anchor-style, the discriminator instead of version and variant index
+        let anchor = instruction.pack_anchor();
+        assert_eq!(anchor[8..], data[2..]);
+        assert_eq!(deserialize_instruction(&anchor).as_ref(), Ok(instruction));
+
+        // the same payload under the other version doesn't decode
+        let mut swapped = data.clone();
+        swapped[0] = INSTRUCTION_VERSION_JSON;
+        assert_eq!(
+            deserialize_instruction(&swapped),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    // version 3 doesn't exist yet
+    let mut data = pack(&AdminUnpause);
+    data[0] = 3;
+    assert_eq!(
+        deserialize_instruction(&data),
+        Err(ProgramError::InvalidInstructionData)
+    );
+    assert!(take_logs().contains(&"unsupported instruction version 3".to_string()));
+    let result = process_instruction(&new_program_id(), &[], &data);
+    assert_eq!(result, Err(ProgramError::InvalidInstructionData));
+
+    assert_eq!(
+        deserialize_instruction(&[]),
+        Err(ProgramError::InvalidInstructionData)
+    );
+}
+
+#[test]
+fn test_compact_instructions() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit = |symbol: &str, amount: u64| {
+        UserDeposit {
+            token: token(symbol),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, &token(symbol).mint, user),
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        }
+        .pack_compact()
+        .unwrap()
+    };
+
+    // the mint is looked up by symbol
+    let data = deposit("usdc", 60);
+    assert_eq!(data.len(), 56);
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(token_amount(&vault), 60);
+    let data = withdraw_data(&state_account, "usdc", user, 20);
+    let compact = deserialize_instruction(&data)
+        .unwrap()
+        .pack_compact()
+        .unwrap();
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &compact),
+        Ok(())
+    );
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.all_token_balances[&token("usdc")].balances[&user], 40);
+
+    // a symbol that isn't listed names no token
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit("dai", 10)),
+        Err(ContractError::TokenNotSupportedForDeposit.into())
+    );
+    assert_eq!(token_amount(&vault), 40);
+}
+
+#[test]
+fn test_malformed_instruction_data() {
+    install_syscall_stubs();
+    take_logs();
+    let json = |payload: &str| [&[INSTRUCTION_VERSION_JSON], payload.as_bytes()].concat();
+    let truncated = json(r#"{"UserDeposit":{"token":{"symbol":"usdc","#);
+    let wrong_shape = json(r#"{"UserDeposit":{"amount":"lots"}}"#);
+    for data in [vec![], truncated, wrong_shape] {
+        assert_eq!(
+            deserialize_instruction(&data),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+    // the decoder's reason is logged
+    let logs = take_logs();
+    assert_eq!(
+        logs.iter()
+            .filter(|log| log.starts_with("invalid instruction data: "))
+            .count(),
+        2
+    );
+
+    // a valid instruction that is too long for a transaction is refused without parsing
+    let oversized = pack(&LinkEthAddress {
+        user: Pubkey::new_unique(),
+        signature: vec![0; MAX_INSTRUCTION_DATA_LEN],
+    });
+    assert!(oversized.len() > MAX_INSTRUCTION_DATA_LEN);
+    assert_eq!(
+        deserialize_instruction(&oversized),
+        Err(ProgramError::InvalidInstructionData)
+    );
+    assert!(take_logs().contains(&format!(
+        "instruction data too long: {} bytes",
+        oversized.len()
+    )));
+    let limit = pack(&LinkEthAddress {
+        user: Pubkey::new_unique(),
+        signature: vec![0; MAX_INSTRUCTION_DATA_LEN - 1 - 1 - 32 - 4],
+    });
+    assert_eq!(limit.len(), MAX_INSTRUCTION_DATA_LEN);
+    assert!(deserialize_instruction(&limit).is_ok());
+}
+
+#[test]
+fn test_migrate_state() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+
+    // rewrite the account in the v1 layout, which is the current one without the trailing
+    // nonces, eth_links, sequence and the per-token and reward maps after them, and kept
+    // balances inline
+    let mut state = load_full_state(&state_account).unwrap();
+    state.nonces.clear();
+    state.sequence = 0;
+    state.token_epochs.clear();
+    state.holder_counts.clear();
+    state.token_metadata.clear();
+    state.display_precisions.clear();
+    state.reward_pools.clear();
+    state.reward_points.clear();
+    state.reward_indexes.clear();
+    state.locked_lots.clear();
+    state.vesting_schedules.clear();
+    state.referrers.clear();
+    state.referral_volume.clear();
+    state.audit_log.clear();
+    state.airdrops.clear();
+    state.redeemed_vouchers.clear();
+    state.rate_limit_ops = 0;
+    state.rate_limit_window_slots = 0;
+    state.rate_windows.clear();
+    state.global_total_deposited = 0;
+    state.global_deposit_cap = 0;
+    state.price_feeds.clear();
+    state.swap_rates.clear();
+    state.share_supplies.clear();
+    state.timelock_delay_slots = 0;
+    state.queued_actions.clear();
+    state.circuit_breakers.clear();
+    state.dust_thresholds.clear();
+    state.user_stats.clear();
+    state.balance_slots.clear();
+    state.withdraw_cooldowns.clear();
+    state.min_withdrawals.clear();
+    state.next_receipt_id = 0;
+    state.receipts.clear();
+    state.withdraw_queues.clear();
+    state.nonce_floor = 0;
+    state.rate_buckets.clear();
+    let mut payload = borsh::to_vec(&state).unwrap();
+    assert_eq!(payload.split_off(payload.len() - 196), vec![0; 196]);
+    {
+        let mut data = state_account.data.borrow_mut();
+        // version 1's header, [version][payload len: u32 LE]
+        data.fill(0);
+        data[0] = 1;
+        data[1..5].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        data[5..5 + payload.len()].copy_from_slice(&payload);
+    }
+    let unsupported: ProgramResult = Err(ContractError::UnsupportedStateVersion.into());
+
+    // nothing else runs until the state is migrated
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &pack(&UserDeposit {
+            token: token("usdc"),
+            user,
+            amount: 1,
+            nonce: 1,
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        }),
+    );
+    assert_eq!(result, unsupported);
+    assert!(take_logs().contains(&format!("state version 1, expected {}", STATE_VERSION)));
+
+    let migrate = |signer: Pubkey| {
+        let accounts = [
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+        ];
+        process_instruction(&program_id, &accounts, &pack(&MigrateState))
+    };
+    assert_eq!(
+        migrate(Pubkey::new_unique()),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    // one version per call, the last one adds the discriminator
+    for version in 2..=STATE_VERSION {
+        assert_eq!(migrate(TEST_ADMIN), Ok(()));
+        assert_eq!(state_account.data.borrow()[0], version);
+    }
+    assert_eq!(state_account.data.borrow()[1..9], STATE_DISCRIMINATOR);
+    assert_eq!(
+        migrate(TEST_ADMIN),
+        Err(ContractError::StateAlreadyCurrent.into())
+    );
+
+    // balances survive, new fields start empty
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].balances[&user],
+        100
+    );
+    assert_eq!(state.admin, Some(TEST_ADMIN));
+    assert!(state.nonces.is_empty());
+    assert!(state.eth_links.is_empty());
+    assert!(state.holder_counts.is_empty());
+    assert!(state.token_metadata.is_empty());
+    assert!(state.display_precisions.is_empty());
+    assert!(state.reward_points.is_empty());
+    assert!(state.locked_lots.is_empty());
+    assert!(state.vesting_schedules.is_empty());
+    assert!(state.referrers.is_empty());
+    assert!(state.audit_log.is_empty());
+    // the rate limit starts out as the default one
+    assert_eq!(
+        (state.rate_limit_ops, state.rate_limit_window_slots),
+        (20, 100)
+    );
+    // the global total starts as the sum of the tokens' totals, without a cap
+    assert_eq!(state.global_total_deposited, 100);
+    assert_eq!(state.global_deposit_cap, u128::MAX);
+    assert!(state.price_feeds.is_empty());
+    assert!(state.swap_rates.is_empty());
+    assert!(state.share_supplies.is_empty());
+    assert_eq!(state.timelock_delay_slots, DEFAULT_TIMELOCK_DELAY_SLOTS);
+    assert!(state.queued_actions.is_empty());
+    assert!(state.circuit_breakers.is_empty());
+    assert!(state.dust_thresholds.is_empty());
+    assert!(state.user_stats.is_empty());
+    assert!(state.balance_slots.is_empty());
+    assert!(state.withdraw_cooldowns.is_empty());
+    assert!(state.min_withdrawals.is_empty());
+    assert_eq!(state.next_receipt_id, 0);
+    assert!(state.receipts.is_empty());
+    assert!(state.withdraw_queues.is_empty());
+    assert_eq!(state.nonce_floor, 0);
+    assert!(state.rate_buckets.is_empty());
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 100),
+    );
+    assert_eq!(result, Ok(()));
+    // the inline balance left the state once it changed, withdrawn in full it needs no
+    // balance account either
+    let state = load_state(&state_account).unwrap();
+    assert!(state.all_token_balances[&token("usdc")].balances.is_empty());
+    assert!(
+        !load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .contains_key(&user)
+    );
+
+    // a version from the future is refused
+    state_account.data.borrow_mut()[0] = STATE_VERSION + 1;
+    let result = process_instruction(&program_id, &accounts, &pack(&AdminUnpause));
+    assert_eq!(result, unsupported);
+    assert_eq!(migrate(TEST_ADMIN), unsupported);
+}
+
+// [r, s, v] signature over keccak256(message), v in Ethereum's 27/28 form
+fn eth_sign(secret_key: &libsecp256k1::SecretKey, message: &[u8]) -> Vec<u8> {
+    let hash = solana_program::keccak::hash(message).0;
+    let (signature, recovery_id) =
+        libsecp256k1::sign(&libsecp256k1::Message::parse(&hash), secret_key);
+    let mut bytes = signature.serialize().to_vec();
+    bytes.push(recovery_id.serialize() + 27);
+    bytes
+}
+
+#[test]
+fn test_eth_withdraw() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let mut accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    // a known key, its address derived the Ethereum way
+    let eth_key = libsecp256k1::SecretKey::parse(&[0x42; 32]).unwrap();
+    let eth_pubkey = libsecp256k1::PublicKey::from_secret_key(&eth_key).serialize();
+    let eth_address: [u8; 20] = solana_program::keccak::hash(&eth_pubkey[1..]).0[12..]
+        .try_into()
+        .unwrap();
+
+    let link = |user: Pubkey, signature: Vec<u8>| {
+        let accounts = [
+            state_account.clone(),
+            new_account(user, true, 0, Pubkey::default()),
+        ];
+        process_instruction(
+            &program_id,
+            &accounts,
+            &pack(&LinkEthAddress { user, signature }),
+        )
+    };
+    let withdraw = |amount: u64, signature: Vec<u8>| {
+        pack(&UserWithdraw {
+            token: token("usdc"),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, user),
+            eth_signature: Some(signature),
+            recipient: None,
+            memo: None,
+        })
+    };
+    let withdraw_message = |amount: u64| {
+        eth_withdraw_message(
+            &program_id,
+            &token("usdc"),
+            &user,
+            amount,
+            nonce_of(&state_account, mint.key, user),
+        )
+    };
+    // relayed, the user doesn't sign
+    accounts[1].is_signer = false;
+
+    // not linked yet
+    let signature = eth_sign(&eth_key, &withdraw_message(10));
+    let result = process_instruction(&program_id, &accounts, &withdraw(10, signature));
+    assert_eq!(result, Err(ContractError::EthAddressNotLinked.into()));
+
+    let link_signature = eth_sign(&eth_key, &eth_link_message(&program_id, &user));
+    assert_eq!(link(user, link_signature.clone()), Ok(()));
+    assert_eq!(
+        load_full_state(&state_account).unwrap().eth_links[&eth_address],
+        user
+    );
+    // taken by user
+    let other = Pubkey::new_unique();
+    let other_signature = eth_sign(&eth_key, &eth_link_message(&program_id, &other));
+    assert_eq!(
+        link(other, other_signature),
+        Err(ContractError::EthAddressAlreadyLinked.into())
+    );
+    // the link signature names user, replayed for someone else it recovers an unrelated address
+    assert_eq!(link(other, link_signature), Ok(()));
+    assert_eq!(
+        load_full_state(&state_account).unwrap().eth_links[&eth_address],
+        user
+    );
+
+    let signature = eth_sign(&eth_key, &withdraw_message(10));
+    let data = withdraw(10, signature.clone());
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(token_amount(&user_token_account), 10);
+    // replay
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &data),
+        Err(ContractError::InvalidNonce.into())
+    );
+    // signature over another amount recovers some other address
+    let result = process_instruction(&program_id, &accounts, &withdraw(20, signature));
+    assert_eq!(result, Err(ContractError::EthAddressNotLinked.into()));
+
+    let invalid: ProgramResult = Err(ContractError::InvalidEthSignature.into());
+    let signature = eth_sign(&eth_key, &withdraw_message(10));
+    // bad recovery id
+    let mut bad_recovery_id = signature.clone();
+    bad_recovery_id[64] = 29;
+    let result = process_instruction(&program_id, &accounts, &withdraw(10, bad_recovery_id));
+    assert_eq!(result, invalid);
+    // the malleated (r, n - s) twin of a valid signature
+    let mut low_s = libsecp256k1::Signature::parse_standard_slice(&signature[..64]).unwrap();
+    low_s.s = -low_s.s;
+    let mut high_s = low_s.serialize().to_vec();
+    high_s.push(signature[64] ^ 1);
+    let result = process_instruction(&program_id, &accounts, &withdraw(10, high_s));
+    assert_eq!(result, invalid);
+    // wrong length
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw(10, signature[..64].to_vec()),
+    );
+    assert_eq!(result, invalid);
+
+    // the payout has to go to the user
+    let relayer_token_account = new_token_account(mint.key, &Pubkey::new_unique(), 0);
+    let mut redirected = user_token_accounts(&state_account, &user, &relayer_token_account, &vault);
+    redirected[1].is_signer = false;
+    let result = process_instruction(&program_id, &redirected, &withdraw(10, signature.clone()));
+    assert_eq!(result, Err(ContractError::DelegateRecipientNotUser.into()));
+
+    // without the signature the unsigned user account is rejected
+    let result = process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", user, 10),
+    );
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw(10, signature)),
+        Ok(())
+    );
+    assert_eq!(token_amount(&user_token_account), 20);
+}
+
+#[test]
+fn test_sequence() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let get_sequence = || {
+        let data = pack(&GetSequence);
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        u64::from_le_bytes(data.try_into().unwrap())
+    };
+
+    // InitializeConfig and AdminAddSupportedToken
+    let n = get_sequence();
+    assert_eq!(n, 2);
+    assert_eq!(load_full_state(&state_account).unwrap().sequence, n);
+
+    let deposit = deposit_data(&state_account, "usdc", user, 100);
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &deposit),
+        Ok(())
+    );
+    assert_eq!(get_sequence(), n + 1);
+
+    // failed instructions and queries don't consume a number
+    let withdraw = withdraw_data(&state_account, "usdc", user, 101);
+    let result = process_instruction(&program_id, &accounts, &withdraw);
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert_eq!(get_sequence(), n + 1);
+
+    let withdraw = withdraw_data(&state_account, "usdc", user, 40);
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &withdraw),
+        Ok(())
+    );
+    assert_eq!(get_sequence(), n + 2);
+}
+
+// [state, user, user, vault, vault, system_program, user's balance account]
+fn native_accounts(
+    state_account: &AccountInfo<'static>,
+    user_account: &AccountInfo<'static>,
+    vault: &AccountInfo<'static>,
+) -> Vec<AccountInfo<'static>> {
+    let mint = spl_token::native_mint::id();
+    vec![
+        state_account.clone(),
+        user_account.clone(),
+        user_account.clone(),
+        vault.clone(),
+        vault.clone(),
+        system_program_account(),
+        balance_account(state_account.owner, &mint, user_account.key),
+        receipts_account(state_account.owner, user_account.key),
+    ]
+}
+
+#[test]
+fn test_native_sol() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let native = TokenType::native();
+    let rent_floor = Rent::default().minimum_balance(0);
+    let vault_key = find_vault_address(&program_id, &native.mint);
+    assert_eq!(vault_key, find_vault_authority(&program_id, &native.mint).0);
+    let vault = new_account(vault_key, false, 0, system_program::id());
+    let admin = admin_account(true);
+    **admin.lamports.borrow_mut() = 10 * rent_floor;
+    let add_data = pack(&AdminAddSupportedToken {
+        token: native.clone(),
+        metadata: metadata(),
+    });
+    let add_accounts = [
+        state_account.clone(),
+        admin.clone(),
+        vault.clone(),
+        system_program_account(),
+    ];
+
+    // the symbol and the native mint only come together
+    for token in [
+        token("sol"),
+        TokenType {
+            symbol: "wsol".to_string(),
+            mint: native.mint,
+        },
+    ] {
+        let instruction_data = pack(&AdminAddSupportedToken {
+            token,
+            metadata: metadata(),
+        });
+        let result = process_instruction(&program_id, &add_accounts, &instruction_data);
+        assert_eq!(result, Err(ContractError::ReservedToken.into()));
+    }
+    let mut wrong_vault = add_accounts.clone();
+    wrong_vault[2] = new_account(Pubkey::new_unique(), false, 0, system_program::id());
+    let result = process_instruction(&program_id, &wrong_vault, &add_data);
+    assert_eq!(result, Err(ContractError::InvalidVaultAccount.into()));
+
+    // the admin makes the vault rent-exempt and the program takes it over
+    assert_eq!(
+        process_instruction(&program_id, &add_accounts, &add_data),
+        Ok(())
+    );
+    assert_eq!(vault.owner, &program_id);
+    assert_eq!(vault.lamports(), rent_floor);
+    assert_eq!(admin.lamports(), 9 * rent_floor);
+
+    let user = Pubkey::new_unique();
+    let user_account = new_account(user, true, 0, system_program::id());
+    // and what the receipts account takes to hold the deposit's receipt, which the test stubs
+    // didn't charge for creating
+    let receipt_rent = Rent::default().minimum_balance(RECEIPTS_HEADER_LEN + DepositReceipt::LEN)
+        - Rent::default().minimum_balance(RECEIPTS_HEADER_LEN);
+    **user_account.lamports.borrow_mut() = 1000 + receipt_rent;
+    let accounts = native_accounts(&state_account, &user_account, &vault);
+    let deposit = |amount: u64, accounts: &[AccountInfo<'static>]| {
+        let instruction_data = pack(&UserDeposit {
+            token: native.clone(),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, &native.mint, user),
+            lock_until: None,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        });
+        process_instruction(&program_id, accounts, &instruction_data)
+    };
+    let withdraw = |amount: u64| {
+        let instruction_data = pack(&UserWithdraw {
+            token: native.clone(),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, &native.mint, user),
+            eth_signature: None,
+            recipient: None,
+            memo: None,
+        });
+        process_instruction(&program_id, &accounts, &instruction_data)
+    };
+    let balance_of = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        let entry = state.all_token_balances.get(&native).unwrap();
+        entry.balances.get(user).copied().unwrap_or(0)
+    };
+
+    // lamports only move through the system program and the real vault
+    let mut wrong_program = accounts.clone();
+    wrong_program[5] = token_program_account();
+    assert_eq!(
+        deposit(100, &wrong_program),
+        Err(ContractError::InvalidProgramId.into())
+    );
+    let mut wrong_vault = accounts.clone();
+    wrong_vault[3] = new_account(Pubkey::new_unique(), false, 0, program_id);
+    assert_eq!(
+        deposit(100, &wrong_vault),
+        Err(ContractError::InvalidVaultAccount.into())
+    );
+
+    assert_eq!(deposit(600, &accounts), Ok(()));
+    assert_eq!(user_account.lamports(), 400);
+    assert_eq!(vault.lamports(), rent_floor + 600);
+    assert_eq!(balance_of(&user), 600);
+    assert_eq!(withdraw(200), Ok(()));
+    assert_eq!(user_account.lamports(), 600);
+    assert_eq!(vault.lamports(), rent_floor + 400);
+    assert_eq!(balance_of(&user), 400);
+
+    // with the vault short of the ledger, payouts stop at the rent floor
+    **vault.lamports.borrow_mut() = rent_floor + 300;
+    assert_eq!(withdraw(301), Err(ProgramError::InsufficientFunds));
+    assert_eq!(vault.lamports(), rent_floor + 300);
+    assert_eq!(balance_of(&user), 400);
+    assert_eq!(withdraw(300), Ok(()));
+    assert_eq!(vault.lamports(), rent_floor);
+    assert_eq!(user_account.lamports(), 900);
+    assert_eq!(withdraw(1), Err(ProgramError::InsufficientFunds));
+
+    // the token can't go while SOL beyond the rent floor is left in the vault
+    let withdraw_all_data = pack(&UserWithdrawAll {
+        token: native.clone(),
+        user,
+    });
+    **vault.lamports.borrow_mut() = rent_floor + 101;
+    process_instruction(&program_id, &accounts, &withdraw_all_data).unwrap();
+    // plus the rent of the closed balance account, which the test stubs didn't charge
+    let balance_rent = Rent::default().minimum_balance(BALANCE_ACCOUNT_SIZE);
+    assert_eq!(user_account.lamports(), 1000 + balance_rent);
+    assert_eq!(balance_of(&user), 0);
+    let delete_data = pack(&AdminDeleteSupportedToken {
+        token: native.clone(),
+    });
+    let delete_accounts = [state_account.clone(), admin_account(true), vault.clone()];
+    let result = process_timelocked(&program_id, &delete_accounts, &delete_data);
+    assert_eq!(result, Err(ContractError::VaultNotEmpty.into()));
+    **vault.lamports.borrow_mut() = rent_floor;
+    assert_eq!(
+        process_timelocked(&program_id, &delete_accounts, &delete_data),
+        Ok(())
+    );
+
+    // adding SOL again reuses the vault, the admin pays nothing
+    assert_eq!(
+        process_instruction(&program_id, &add_accounts, &add_data),
+        Ok(())
+    );
+    assert_eq!(admin.lamports(), 9 * rent_floor);
+}
+
+// account at key owned by owner holding a copy of account's data and lamports
+fn spoofed_copy(account: &AccountInfo, key: Pubkey, owner: Pubkey) -> AccountInfo<'static> {
+    let copy = new_account(key, account.is_signer, account.data_len(), owner);
+    copy.data
+        .borrow_mut()
+        .copy_from_slice(&account.data.borrow());
+    **copy.lamports.borrow_mut() = account.lamports();
+    copy
+}
+
+#[test]
+fn test_account_validation() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let attacker = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 50);
+    let deposit_data = pack(&UserDeposit {
+        token: token("usdc"),
+        user,
+        amount: 10,
+        nonce: 0,
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    });
+    let deposit_with = |accounts: &[AccountInfo<'static>]| {
+        process_instruction(&program_id, accounts, &deposit_data)
+    };
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+
+    // a copy of the state kept by another program, even for a query, or at another address
+    let invalid_state: ProgramResult = Err(ContractError::InvalidStateAccount.into());
+    let mut spoofed = accounts.clone();
+    spoofed[0] = spoofed_copy(&state_account, *state_account.key, attacker);
+    assert_eq!(deposit_with(&spoofed), invalid_state);
+    let result = process_instruction(&program_id, &spoofed[..1], &pack(&GetSequence));
+    assert_eq!(result, invalid_state);
+    spoofed[0] = spoofed_copy(&state_account, Pubkey::new_unique(), program_id);
+    assert_eq!(
+        deposit_with(&spoofed),
+        Err(ContractError::InvalidPda.into())
+    );
+    let migrate_accounts = [spoofed[0].clone(), admin_account(true)];
+    let result = process_instruction(&program_id, &migrate_accounts, &pack(&MigrateState));
+    assert_eq!(result, Err(ContractError::InvalidPda.into()));
+    // the program's own account holding data of another layout
+    spoofed[0] = spoofed_copy(&state_account, *state_account.key, program_id);
+    spoofed[0].data.borrow_mut()[1] ^= 1;
+    assert_eq!(deposit_with(&spoofed), invalid_state);
+    let result = process_instruction(&program_id, &spoofed[..1], &pack(&GetSequence));
+    assert_eq!(result, invalid_state);
+    // the real one passed read-only can only be queried
+    spoofed[0] = spoofed_copy(&state_account, *state_account.key, program_id);
+    spoofed[0].is_writable = false;
+    assert_eq!(deposit_with(&spoofed), invalid_state);
+    let result = process_instruction(&program_id, &spoofed[..1], &pack(&GetSequence));
+    assert_eq!(result, Ok(()));
+    assert!(take_logs().contains(&"state account isn't writable".to_string()));
+    // the real state account drained below rent exemption
+    spoofed[0] = spoofed_copy(&state_account, *state_account.key, program_id);
+    **spoofed[0].lamports.borrow_mut() -= 1;
+    assert_eq!(
+        deposit_with(&spoofed),
+        Err(ContractError::NotRentExempt.into())
+    );
+
+    // token accounts, vault and mint copied into accounts of another program
+    let mut spoofed = accounts.clone();
+    spoofed[2] = spoofed_copy(&user_token_account, *user_token_account.key, attacker);
+    assert_eq!(
+        deposit_with(&spoofed),
+        Err(ContractError::WrongOwner.into())
+    );
+    let mut spoofed = accounts.clone();
+    spoofed[3] = spoofed_copy(&vault, *vault.key, attacker);
+    assert_eq!(
+        deposit_with(&spoofed),
+        Err(ContractError::WrongOwner.into())
+    );
+    let add_data = pack(&AdminAddSupportedToken {
+        token: token("usdt"),
+        metadata: metadata(),
+    });
+    let add_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        spoofed_copy(&new_mint_account("usdt"), token("usdt").mint, attacker),
+        new_vault_account(&program_id, &token("usdt").mint),
+    ];
+    let result = process_instruction(&program_id, &add_accounts, &add_data);
+    assert_eq!(result, Err(ContractError::WrongOwner.into()));
+
+    // programs invoked must be the real ones
+    let mut spoofed = accounts.clone();
+    spoofed[5] = spoofed_copy(&token_program_account(), attacker, Pubkey::default());
+    assert_eq!(
+        deposit_with(&spoofed),
+        Err(ContractError::InvalidProgramId.into())
+    );
+    let native_add_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        new_account(
+            find_vault_address(&program_id, &spl_token::native_mint::id()),
+            false,
+            0,
+            system_program::id(),
+        ),
+        new_account(attacker, false, 0, Pubkey::default()),
+    ];
+    let native_add_data = pack(&AdminAddSupportedToken {
+        token: TokenType::native(),
+        metadata: metadata(),
+    });
+    let result = process_instruction(&program_id, &native_add_accounts, &native_add_data);
+    assert_eq!(result, Err(ContractError::InvalidProgramId.into()));
+    let other_program_id = new_program_id();
+    let init_accounts = [
+        new_state_account(&other_program_id, 0),
+        admin_account(true),
+        new_account(attacker, false, 0, Pubkey::default()),
+    ];
+    let result = process_instruction(&other_program_id, &init_accounts, &pack(&InitializeState));
+    assert_eq!(result, Err(ContractError::InvalidProgramId.into()));
+    let init_accounts = [new_account(
+        Pubkey::new_unique(),
+        false,
+        0,
+        other_program_id,
+    )];
+    let result = process_instruction(&other_program_id, &init_accounts, &pack(&InitializeState));
+    assert_eq!(result, Err(ContractError::InvalidPda.into()));
+
+    // nothing above got through
+    PROGRAM_ID.with(|p| p.set(program_id));
+    assert_eq!(deposit_with(&accounts), Ok(()));
+    assert_eq!(token_amount(&vault), 10);
+}
+
+#[test]
+fn test_list_supported_tokens() {
+    let program_id = new_program_id();
+    let state_account = initialized_large_state_account(&program_id);
+    let list = |offset: u32, limit: u32| {
+        let data = pack(&ListSupportedTokens { offset, limit });
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        assert!(data.len() <= MAX_RETURN_DATA);
+        TokenPage::try_from_slice(&data).unwrap()
+    };
+    let symbols = |page: &TokenPage| -> Vec<String> {
+        page.tokens
+            .iter()
+            .map(|(token, _)| token.symbol.clone())
+            .collect()
+    };
+
+    // empty registry
+    let page = list(0, MAX_TOKENS_PER_PAGE);
+    assert_eq!(page.total, 0);
+    assert!(page.tokens.is_empty());
+
+    // a single page, sorted by the canonical symbol whatever the order of adding
+    for symbol in ["usdc", "btc", "ray"] {
+        add_token(&program_id, &state_account, symbol);
+    }
+    let page = list(0, 10);
+    assert_eq!(page.total, 3);
+    assert_eq!(symbols(&page), ["BTC", "RAY", "USDC"]);
+    assert_eq!(page.tokens[0], (token("btc"), metadata()));
+    assert_eq!(symbols(&list(1, 1)), ["RAY"]);
+    // queries don't take a sequence number
+    assert_eq!(load_state(&state_account).unwrap().sequence, 4);
+
+    // past the end is an empty page, not an error
+    let page = list(3, 10);
+    assert_eq!(page.total, 3);
+    assert!(page.tokens.is_empty());
+    assert!(list(u32::MAX, u32::MAX).tokens.is_empty());
+
+    // enough tokens that a page is cut short by the return data limit, iterating the pages
+    // still yields every token exactly once
+    for i in 0..40 {
+        add_token(&program_id, &state_account, &format!("tok{:02}", i));
+    }
+    let mut expected: Vec<String> = (0..40).map(|i| format!("TOK{:02}", i)).collect();
+    expected.extend(["BTC", "RAY", "USDC"].map(String::from));
+    expected.sort();
+    let first = list(0, u32::MAX);
+    assert!(first.tokens.len() < MAX_TOKENS_PER_PAGE as usize);
+    let mut listed = Vec::new();
+    loop {
+        let page = list(listed.len() as u32, u32::MAX);
+        assert_eq!(page.total, 43);
+        if page.tokens.is_empty() {
+            break;
+        }
+        listed.extend(symbols(&page));
+    }
+    assert_eq!(listed, expected);
+    assert_eq!(list(0, 5).tokens.len(), 5);
+}
+
+#[test]
+fn test_token_stats() {
+    let program_id = new_program_id();
+    let state_account = initialized_large_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let users: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+    let token_accounts: Vec<_> = users
+        .iter()
+        .map(|user| new_token_account(mint.key, user, 1_000_000))
+        .collect();
+    // user's deposit/withdraw accounts followed by every user's balance account
+    let accounts = |i: usize| {
+        let mut accounts =
+            user_token_accounts(&state_account, &users[i], &token_accounts[i], &vault);
+        accounts.extend(
+            users
+                .iter()
+                .map(|user| balance_account(&program_id, mint.key, user)),
+        );
+        accounts
+    };
+    let stats = || {
+        let data = pack(&GetTokenStats {
+            token: token("usdc"),
+        });
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        TokenStats::try_from_slice(&data).unwrap()
+    };
+    assert_eq!(
+        stats(),
+        TokenStats {
+            total_deposited: 0,
+            holder_count: 0,
+            metadata: metadata(),
+        }
+    );
+
+    // far more operations than the rate limit allows
+    let data = pack(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    });
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    process_instruction(&program_id, &admin_accounts, &data).unwrap();
+
+    let mut rng = StdRng::seed_from_u64(54);
+    for _ in 0..500 {
+        let (i, j) = (rng.gen_range(0..4), rng.gen_range(0..4));
+        let amount = rng.gen_range(0..100);
+        let data = match rng.gen_range(0..5) {
+            0 | 1 => deposit_data(&state_account, "usdc", users[i], amount),
+            2 => withdraw_data(&state_account, "usdc", users[i], amount),
+            3 => pack(&UserTransfer {
+                token: token("usdc"),
+                from: users[i],
+                to: users[j],
+                amount,
+            }),
+            _ => pack(&UserWithdrawAll {
+                token: token("usdc"),
+                user: users[i],
+            }),
+        };
+        // plenty of these fail for lack of funds, which must not move the aggregates either
+        let _ = process_instruction(&program_id, &accounts(i), &data);
+
+        // the cached aggregates match the ones recomputed from every balance
+        let state = load_full_state(&state_account).unwrap();
+        let entry = &state.all_token_balances[&token("usdc")];
+        let holders = entry.balances.values().filter(|&&b| b > 0).count() as u64;
+        let total: u64 = entry.balances.values().sum();
+        assert_eq!(
+            stats(),
+            TokenStats {
+                total_deposited: total,
+                holder_count: holders,
+                metadata: metadata(),
+            }
+        );
+    }
+    assert!(stats().holder_count > 0);
+
+    // unlisted tokens have no stats
+    let data = pack(&GetTokenStats {
+        token: token("ray"),
+    });
+    let result = process_instruction(&program_id, std::slice::from_ref(&state_account), &data);
+    assert_eq!(result, Err(ContractError::TokenNotSupportedForQuery.into()));
+
+    // the count goes with the listing
+    let data = pack(&AdminForceDeleteToken {
+        token: token("usdc"),
+        confirm: true,
+    });
+    process_timelocked(&program_id, &admin_accounts, &data).unwrap();
+    assert!(load_state(&state_account).unwrap().holder_counts.is_empty());
+}
+
+#[test]
+fn test_is_token_supported() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, _) = add_token(&program_id, &state_account, "usdc");
+    let supported = |token: TokenType| {
+        let data = pack(&IsTokenSupported { token });
+        let accounts = std::slice::from_ref(&state_account);
+        process_instruction(&program_id, accounts, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        data
+    };
+    let before = state_account.data.borrow().to_vec();
+
+    // by symbol alone too
+    let expected = Some(SupportedToken {
+        mint: *mint.key,
+        deposits_enabled: true,
+        withdrawals_enabled: true,
+    });
+    assert_eq!(supported(token("usdc")), borsh::to_vec(&expected).unwrap());
+    let by_symbol = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::default(),
+    };
+    assert_eq!(supported(by_symbol), borsh::to_vec(&expected).unwrap());
+    // a single byte for an unknown token
+    assert_eq!(supported(token("ray")), [0]);
+    assert_eq!(state_account.data.borrow().to_vec(), before);
+}
+
+#[test]
+fn test_user_stats() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1_000);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let stats = |symbol: &str| {
+        let data = pack(&GetUserStats {
+            token: token(symbol),
+            user: alice,
+        });
+        let accounts = [
+            state_account.clone(),
+            balance_account(&program_id, mint.key, &alice),
+        ];
+        process_instruction(&program_id, &accounts, &data)?;
+        let (_, data) = get_return_data().unwrap();
+        Ok::<_, ProgramError>(UserStats::try_from_slice(&data).unwrap())
+    };
+    let deposit = |amount: u64| {
+        let data = deposit_data(&state_account, "usdc", alice, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", alice, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+
+    // nothing yet
+    assert_eq!(stats("usdc"), Ok(UserStats::default()));
+    assert_eq!(
+        stats("ray"),
+        Err(ContractError::TokenNotSupportedForQuery.into())
+    );
+
+    assert_eq!(deposit(100), Ok(()));
+    assert_eq!(deposit(50), Ok(()));
+    assert_eq!(withdraw(30), Ok(()));
+    // a failed withdrawal isn't counted
+    assert_eq!(withdraw(500), Err(ProgramError::InsufficientFunds));
+    assert_eq!(withdraw(70), Ok(()));
+    assert_eq!(
+        stats("usdc"),
+        Ok(UserStats {
+            deposit_count: 2,
+            withdraw_count: 2,
+            lifetime_deposited: 150,
+            lifetime_withdrawn: 100,
+        })
+    );
+    // kept in the balance account, not the state
+    assert!(load_state(&state_account).unwrap().user_stats.is_empty());
+
+    // a transfer is neither, closing the emptied balance starts the counts over
+    let alice_signer = accounts[1].clone();
+    let bob = Pubkey::new_unique();
+    let transfer_accounts = [
+        state_account.clone(),
+        alice_signer.clone(),
+        balance_account(&program_id, mint.key, &alice),
+        balance_account(&program_id, mint.key, &bob),
+        system_program_account(),
+    ];
+    let data = pack(&UserTransfer {
+        token: token("usdc"),
+        from: alice,
+        to: bob,
+        amount: 50,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &transfer_accounts, &data),
+        Ok(())
+    );
+    assert_eq!(stats("usdc").unwrap().lifetime_withdrawn, 100);
+    let accounts = [
+        state_account.clone(),
+        alice_signer,
+        balance_account(&program_id, mint.key, &alice),
+    ];
+    let data = pack(&CloseUserBalance {
+        token: token("usdc"),
+        user: alice,
+    });
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(stats("usdc"), Ok(UserStats::default()));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .balance_slots
+        .is_empty());
+    assert_eq!(deposit(10), Ok(()));
+    assert_eq!(
+        stats("usdc"),
+        Ok(UserStats {
+            deposit_count: 1,
+            withdraw_count: 0,
+            lifetime_deposited: 10,
+            lifetime_withdrawn: 0,
+        })
+    );
+}
+
+#[test]
+fn test_balance_slots() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1_000);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let slots = || {
+        let data = pack(&GetBalance {
+            token: token("usdc"),
+            user: alice,
+        });
+        let accounts = [
+            state_account.clone(),
+            balance_account(&program_id, mint.key, &alice),
+        ];
+        process_instruction(&program_id, &accounts, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        let balance = UserBalance::try_from_slice(&data).unwrap();
+        (balance.last_deposit_slot, balance.last_withdraw_slot)
+    };
+    let deposit = |amount: u64| {
+        let data = deposit_data(&state_account, "usdc", alice, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", alice, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    assert_eq!(slots(), (0, 0));
+
+    set_slot(10);
+    assert_eq!(deposit(100), Ok(()));
+    assert_eq!(slots(), (10, 0));
+    let events = take_events::<DepositEvent>();
+    assert_eq!(
+        (events[0].last_deposit_slot, events[0].last_withdraw_slot),
+        (10, 0)
+    );
+
+    // a withdrawal doesn't touch last_deposit_slot
+    set_slot(20);
+    assert_eq!(withdraw(30), Ok(()));
+    assert_eq!(slots(), (10, 20));
+    let events = take_events::<WithdrawEvent>();
+    assert_eq!(
+        (events[0].last_deposit_slot, events[0].last_withdraw_slot),
+        (10, 20)
+    );
+
+    // nor does a deposit last_withdraw_slot
+    set_slot(30);
+    assert_eq!(deposit(5), Ok(()));
+    assert_eq!(slots(), (30, 20));
+
+    // a failed withdrawal changes nothing
+    set_slot(40);
+    assert_eq!(withdraw(500), Err(ProgramError::InsufficientFunds));
+    assert_eq!(slots(), (30, 20));
+    set_slot(0);
+}
+
+#[test]
+fn test_withdraw_cooldown() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1_000);
+    let bob_token_account = new_token_account(mint.key, &bob, 0);
+    let alice_accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    let set_cooldown = |signer: Pubkey, withdraw_cooldown_slots: u64, transfers_reset: bool| {
+        let accounts = [
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+        ];
+        let data = pack(&AdminSetWithdrawCooldown {
+            token: token("usdc"),
+            withdraw_cooldown_slots,
+            transfers_reset,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let deposit = |amount: u64| {
+        let data = deposit_data(&state_account, "usdc", alice, amount);
+        process_instruction(&program_id, &alice_accounts, &data)
+    };
+    let withdraw = |user: Pubkey, amount: u64| {
+        let accounts = if user == bob {
+            &bob_accounts
+        } else {
+            &alice_accounts
+        };
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, accounts, &data)
+    };
+    let transfer = |amount: u64| {
+        let accounts = [
+            state_account.clone(),
+            alice_accounts[1].clone(),
+            balance_account(&program_id, mint.key, &alice),
+            balance_account(&program_id, mint.key, &bob),
+            system_program_account(),
+        ];
+        let data = pack(&UserTransfer {
+            token: token("usdc"),
+            from: alice,
+            to: bob,
+            amount,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+
+    // a cooldown of 0 is no cooldown
+    set_slot(100);
+    assert_eq!(set_cooldown(TEST_ADMIN, 0, false), Ok(()));
+    assert_eq!(deposit(100), Ok(()));
+    assert_eq!(withdraw(alice, 10), Ok(()));
+
+    assert_eq!(
+        set_cooldown(Pubkey::new_unique(), 10, false),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(set_cooldown(TEST_ADMIN, 10, false), Ok(()));
+    // counted from the last deposit, which was at 100
+    take_logs();
+    assert_eq!(
+        withdraw(alice, 10),
+        Err(ContractError::CooldownActive.into())
+    );
+    assert!(take_logs()
+        .iter()
+        .any(|log| log == &format!("user {} can withdraw usdc in 10 slots", alice)));
+    set_slot(109);
+    assert_eq!(
+        withdraw(alice, 10),
+        Err(ContractError::CooldownActive.into())
+    );
+    set_slot(110);
+    assert_eq!(withdraw(alice, 10), Ok(()));
+    // a new deposit starts it over
+    assert_eq!(deposit(10), Ok(()));
+    set_slot(115);
+    assert_eq!(
+        withdraw(alice, 10),
+        Err(ContractError::CooldownActive.into())
+    );
+
+    // a transfer received doesn't count as a deposit unless the token says so
+    assert_eq!(transfer(20), Ok(()));
+    assert_eq!(withdraw(bob, 5), Ok(()));
+    assert_eq!(set_cooldown(TEST_ADMIN, 10, true), Ok(()));
+    assert_eq!(transfer(20), Ok(()));
+    set_slot(124);
+    assert_eq!(withdraw(bob, 5), Err(ContractError::CooldownActive.into()));
+    set_slot(125);
+    assert_eq!(withdraw(bob, 5), Ok(()));
+
+    // removing the cooldown lets everyone withdraw at once again
+    assert_eq!(deposit(10), Ok(()));
+    assert_eq!(
+        withdraw(alice, 10),
+        Err(ContractError::CooldownActive.into())
+    );
+    assert_eq!(set_cooldown(TEST_ADMIN, 0, true), Ok(()));
+    assert_eq!(withdraw(alice, 10), Ok(()));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .withdraw_cooldowns
+        .is_empty());
+    set_slot(0);
+}
+
+#[test]
+fn test_min_withdraw() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let set_min = |min_withdraw: u64| {
+        let accounts = [state_account.clone(), admin_account(true)];
+        let data = pack(&AdminSetMinWithdraw {
+            token: token("usdc"),
+            min_withdraw,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let balance = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .get(&user)
+            .copied()
+    };
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+
+    assert_eq!(set_min(30), Ok(()));
+    // below, at and above the minimum
+    assert_eq!(
+        withdraw(29),
+        Err(ContractError::WithdrawBelowMinimum.into())
+    );
+    assert_eq!(withdraw(30), Ok(()));
+    assert_eq!(withdraw(50), Ok(()));
+    assert_eq!(balance(), Some(20));
+
+    // what is left is below the minimum, but all of it can always go
+    assert_eq!(
+        withdraw(10),
+        Err(ContractError::WithdrawBelowMinimum.into())
+    );
+    assert_eq!(withdraw(20), Ok(()));
+    assert_eq!(balance(), None);
+
+    // 0 removes the minimum
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 50),
+    )
+    .unwrap();
+    assert_eq!(set_min(0), Ok(()));
+    assert_eq!(withdraw(1), Ok(()));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .min_withdrawals
+        .is_empty());
+
+    // a leftover the dust threshold sweeps to the treasury makes it a full withdrawal
+    let treasury = Pubkey::new_unique();
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    for instruction in [
+        AdminSetTreasury { treasury },
+        AdminSetDustThreshold {
+            token: token("usdc"),
+            threshold: 25,
+        },
+    ] {
+        process_instruction(&program_id, &admin_accounts, &pack(&instruction)).unwrap();
+    }
+    assert_eq!(set_min(30), Ok(()));
+    let mut accounts = accounts.clone();
+    accounts.push(balance_account(&program_id, mint.key, &treasury));
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    // 29 would be left
+    assert_eq!(
+        withdraw(20),
+        Err(ContractError::WithdrawBelowMinimum.into())
+    );
+    assert_eq!(withdraw(25), Ok(()));
+    assert_eq!(balance(), None);
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].balances[&treasury],
+        24
+    );
+}
+
+#[test]
+fn test_receipts() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1_000);
+    let bob_token_account = new_token_account(mint.key, &bob, 1_000);
+    let alice_accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    // the receipt id the deposit returned
+    let deposit = |user: Pubkey, amount: u64| {
+        let accounts = if user == bob {
+            &bob_accounts
+        } else {
+            &alice_accounts
+        };
+        let data = deposit_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, accounts, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        u64::from_le_bytes(data.try_into().unwrap())
+    };
+    let receipt = |user: Pubkey, receipt_id: u64| {
+        let data = pack(&GetReceipt { user, receipt_id });
+        let accounts = [state_account.clone(), receipts_account(&program_id, &user)];
+        process_instruction(&program_id, &accounts, &data)?;
+        let (_, data) = get_return_data().unwrap();
+        Ok::<_, ProgramError>(DepositReceipt::try_from_slice(&data).unwrap())
+    };
+
+    // ids keep increasing whoever deposits
+    set_slot(7);
+    let ids = [
+        deposit(alice, 10),
+        deposit(bob, 20),
+        deposit(alice, 30),
+        deposit(bob, 40),
+    ];
+    assert_eq!(ids, [0, 1, 2, 3]);
+    let events = take_events::<DepositEvent>();
+    assert_eq!(
+        events
+            .iter()
+            .map(|event| event.receipt_id)
+            .collect::<Vec<_>>(),
+        ids
+    );
+    assert_eq!(
+        receipt(alice, 2),
+        Ok(DepositReceipt {
+            receipt_id: 2,
+            mint: *mint.key,
+            user: alice,
+            amount: 30,
+            slot: 7,
+        })
+    );
+    // a receipt is looked up with its own user
+    assert_eq!(
+        receipt(alice, 1),
+        Err(ContractError::ReceiptNotFound.into())
+    );
+    assert_eq!(receipt(bob, 1).unwrap().amount, 20);
+    assert_eq!(
+        receipt(alice, 4),
+        Err(ContractError::ReceiptNotFound.into())
+    );
+
+    // a full list drops its oldest receipt, the balance keeps every deposit
+    let mut last = 3;
+    for i in 0..MAX_RECEIPTS_PER_USER as u64 - 1 {
+        // a rate limit window apart
+        set_slot(100 * (i + 1));
+        let id = deposit(alice, 1);
+        assert!(id > last);
+        last = id;
+    }
+    assert_eq!(
+        receipt(alice, 0),
+        Err(ContractError::ReceiptNotFound.into())
+    );
+    assert_eq!(receipt(alice, 2).unwrap().amount, 30);
+    assert_eq!(receipt(alice, last).unwrap().amount, 1);
+    // they are kept in the user's receipts account, which stops growing, not in the state
+    let state = load_full_state(&state_account).unwrap();
+    assert!(state.receipts.is_empty());
+    assert_eq!(alice_accounts[8].data_len(), RECEIPTS_ACCOUNT_MAX_SIZE);
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].balances[&alice],
+        10 + 30 + MAX_RECEIPTS_PER_USER as u64 - 1
+    );
+    set_slot(100 * MAX_RECEIPTS_PER_USER as u64);
+    let id = deposit(alice, 1);
+    assert_eq!(alice_accounts[8].data_len(), RECEIPTS_ACCOUNT_MAX_SIZE);
+    assert_eq!(
+        receipt(alice, 2),
+        Err(ContractError::ReceiptNotFound.into())
+    );
+    assert_eq!(receipt(alice, id).unwrap().amount, 1);
+    assert_eq!(deposit(bob, 5), id + 1);
+
+    // a deposit needs the receipts account of the user it credits
+    let data = deposit_data(&state_account, "usdc", bob, 5);
+    assert_eq!(
+        process_instruction(&program_id, &bob_accounts[..8], &data),
+        Err(ContractError::MissingReceiptsAccount.into())
+    );
+
+    // receipts kept in the state before there were receipts accounts are still found
+    let carol = Pubkey::new_unique();
+    let mut state = load_state(&state_account).unwrap();
+    let legacy = DepositReceipt {
+        receipt_id: 0,
+        mint: *mint.key,
+        user: carol,
+        amount: 3,
+        slot: 1,
+    };
+    state.receipts.insert(carol, vec![legacy]);
+    let payload = borsh::to_vec(&state).unwrap();
+    {
+        let mut data = state_account.data.borrow_mut();
+        data[9..STATE_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        data[STATE_HEADER_LEN..STATE_HEADER_LEN + payload.len()].copy_from_slice(&payload);
+    }
+    assert_eq!(receipt(carol, 0), Ok(legacy));
+    set_slot(0);
+}
+
+#[test]
+fn test_global_total() {
+    let program_id = new_program_id();
+    let state_account = initialized_large_state_account(&program_id);
+    let symbols = ["usdc", "ray"];
+    let vaults: Vec<_> = symbols
+        .iter()
+        .map(|symbol| add_token(&program_id, &state_account, symbol))
+        .collect();
+    let users: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let token_accounts: Vec<Vec<_>> = vaults
+        .iter()
+        .map(|(mint, _)| {
+            users
+                .iter()
+                .map(|user| new_token_account(mint.key, user, 1_000_000))
+                .collect()
+        })
+        .collect();
+    // user i's deposit/withdraw accounts of token t followed by every user's balance account
+    let accounts = |t: usize, i: usize| {
+        let (mint, vault) = &vaults[t];
+        let mut accounts =
+            user_token_accounts(&state_account, &users[i], &token_accounts[t][i], vault);
+        accounts.extend(
+            users
+                .iter()
+                .map(|user| balance_account(&program_id, mint.key, user)),
+        );
+        accounts
+    };
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_cap = |cap: u128| {
+        let data = pack(&AdminSetGlobalCap { cap });
+        process_instruction(&program_id, &admin_accounts, &data)
+    };
+    let stats = || {
+        let data = pack(&GetGlobalStats);
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        GlobalStats::try_from_slice(&data).unwrap()
+    };
+    let data = pack(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    });
+    process_instruction(&program_id, &admin_accounts, &data).unwrap();
+
+    // deposits up to the cap go through, the next unit doesn't
+    assert_eq!(set_cap(150), Ok(()));
+    let data = deposit_data(&state_account, "usdc", users[0], 100);
+    assert_eq!(
+        process_instruction(&program_id, &accounts(0, 0), &data),
+        Ok(())
+    );
+    let data = deposit_data(&state_account, "ray", users[1], 51);
+    let result = process_instruction(&program_id, &accounts(1, 1), &data);
+    assert_eq!(result, Err(ContractError::GlobalCapExceeded.into()));
+    let data = deposit_data(&state_account, "ray", users[1], 50);
+    assert_eq!(
+        process_instruction(&program_id, &accounts(1, 1), &data),
+        Ok(())
+    );
+    assert_eq!(
+        stats(),
+        GlobalStats {
+            total_deposited: 150,
+            cap: 150,
+            token_count: 2,
+            holder_count: 2,
+        }
+    );
+    // a cap below the total only blocks deposits, withdrawals still go through
+    assert_eq!(set_cap(100), Ok(()));
+    let data = withdraw_data(&state_account, "ray", users[1], 20);
+    assert_eq!(
+        process_instruction(&program_id, &accounts(1, 1), &data),
+        Ok(())
+    );
+    assert_eq!(stats().total_deposited, 130);
+
+    assert_eq!(set_cap(1_000), Ok(()));
+    let mut rng = StdRng::seed_from_u64(89);
+    for _ in 0..500 {
+        let (t, i, j) = (
+            rng.gen_range(0..2),
+            rng.gen_range(0..3),
+            rng.gen_range(0..3),
+        );
+        let amount = rng.gen_range(0..100);
+        let data = match rng.gen_range(0..5) {
+            0 | 1 => deposit_data(&state_account, symbols[t], users[i], amount),
+            2 => withdraw_data(&state_account, symbols[t], users[i], amount),
+            3 => pack(&UserTransfer {
+                token: token(symbols[t]),
+                from: users[i],
+                to: users[j],
+                amount,
+            }),
+            _ => pack(&UserWithdrawAll {
+                token: token(symbols[t]),
+                user: users[i],
+            }),
+        };
+        // plenty of these fail for lack of funds or on the cap, neither moves the total
+        let _ = process_instruction(&program_id, &accounts(t, i), &data);
+
+        let state = load_full_state(&state_account).unwrap();
+        let total: u128 = state
+            .all_token_balances
+            .values()
+            .map(|entry| u128::from(entry.total_deposited))
+            .sum();
+        assert_eq!(state.global_total_deposited, total);
+        assert!(total <= 1_000);
+    }
+    assert!(stats().total_deposited > 0);
+
+    // what a forced delete destroys leaves the total too
+    let ray_total =
+        load_state(&state_account).unwrap().all_token_balances[&token("ray")].total_deposited;
+    let before = stats().total_deposited;
+    let data = pack(&AdminForceDeleteToken {
+        token: token("ray"),
+        confirm: true,
+    });
+    process_timelocked(&program_id, &admin_accounts, &data).unwrap();
+    let stats = stats();
+    assert_eq!(stats.total_deposited, before - u128::from(ray_total));
+    assert_eq!(stats.token_count, 1);
+}
+
+// Pyth price account trading at price * 10^exponent, published in publish_slot
+fn price_account(price: i64, exponent: i32, publish_slot: u64) -> AccountInfo<'static> {
+    let account = new_account(
+        Pubkey::new_unique(),
+        false,
+        PYTH_PRICE_ACCOUNT_MIN_LEN,
+        Pubkey::new_unique(),
+    );
+    let mut data = account.data.borrow_mut();
+    for (offset, field) in [
+        (PYTH_MAGIC_OFFSET, PYTH_MAGIC.to_le_bytes().to_vec()),
+        (PYTH_VERSION_OFFSET, PYTH_VERSION.to_le_bytes().to_vec()),
+        (
+            PYTH_ACCOUNT_TYPE_OFFSET,
+            PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes().to_vec(),
+        ),
+        (PYTH_EXPONENT_OFFSET, exponent.to_le_bytes().to_vec()),
+        (PYTH_PRICE_OFFSET, price.to_le_bytes().to_vec()),
+        (
+            PYTH_STATUS_OFFSET,
+            PYTH_STATUS_TRADING.to_le_bytes().to_vec(),
+        ),
+        (
+            PYTH_PUBLISH_SLOT_OFFSET,
+            publish_slot.to_le_bytes().to_vec(),
+        ),
+    ] {
+        data[offset..offset + field.len()].copy_from_slice(&field);
+    }
+    drop(data);
+    account
+}
+
+#[test]
+fn test_usd_cap() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 1_000_000_000_000);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let set_feed = |symbol: &str, feed: Pubkey| {
+        let data = pack(&AdminSetPriceFeed {
+            token: token(symbol),
+            feed,
+            usd_cap: 300,
+            max_age_slots: 10,
+        });
+        process_instruction(&program_id, &admin_accounts, &data)
+    };
+    // $2 a token, with the 9 decimals of the test mint
+    let feed = price_account(200_000_000, -8, 45);
+    let deposit = |amount: u64, feed: Option<&AccountInfo<'static>>| {
+        let mut accounts = accounts.clone();
+        accounts.extend(feed.cloned());
+        let data = deposit_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let tokens = |amount: u64| amount * 1_000_000_000;
+    let over_cap: ProgramResult = Err(ContractError::UsdCapExceeded.into());
+    let data = pack(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    });
+    process_instruction(&program_id, &admin_accounts, &data).unwrap();
+    set_slot(50);
+
+    // without a feed only the unit caps apply
+    assert_eq!(deposit(tokens(10), None), Ok(()));
+    assert_eq!(
+        set_feed("ray", *feed.key),
+        Err(ContractError::TokenNotFound.into())
+    );
+    assert_eq!(set_feed("usdc", *feed.key), Ok(()));
+
+    // the feed account is required, another price account doesn't stand in for it
+    assert_eq!(
+        deposit(tokens(1), None),
+        Err(ProgramError::NotEnoughAccountKeys)
+    );
+    let other = price_account(1, 0, 50);
+    assert_eq!(
+        deposit(tokens(1), Some(&other)),
+        Err(ProgramError::NotEnoughAccountKeys)
+    );
+
+    // the whole total is valued, the 10 deposited before the feed included
+    assert_eq!(deposit(tokens(90), Some(&feed)), Ok(()));
+    assert_eq!(deposit(tokens(51), Some(&feed)), over_cap);
+    let data = pack(&BatchDeposit {
+        user,
+        items: vec![(token("usdc"), tokens(51))],
+    });
+    let mut batch_accounts = accounts.clone();
+    batch_accounts.push(feed.clone());
+    assert_eq!(
+        process_instruction(&program_id, &batch_accounts, &data),
+        over_cap
+    );
+    assert_eq!(deposit(tokens(50), Some(&feed)), Ok(()));
+    // the harness keeps the transfers of failed deposits, the ledger is what was credited
+    let total =
+        || load_state(&state_account).unwrap().all_token_balances[&token("usdc")].total_deposited;
+    assert_eq!(total(), tokens(150));
+
+    // a price more than max_age_slots old is refused, so is one not trading
+    let withdraw = withdraw_data(&state_account, "usdc", user, tokens(50));
+    process_instruction(&program_id, &accounts, &withdraw).unwrap();
+    set_slot(56);
+    assert_eq!(
+        deposit(1, Some(&feed)),
+        Err(ContractError::StalePrice.into())
+    );
+    set_slot(55);
+    feed.data.borrow_mut()[PYTH_STATUS_OFFSET] = 0;
+    assert_eq!(
+        deposit(1, Some(&feed)),
+        Err(ContractError::InvalidPriceFeed.into())
+    );
+    feed.data.borrow_mut()[PYTH_STATUS_OFFSET] = PYTH_STATUS_TRADING as u8;
+    assert_eq!(deposit(tokens(50), Some(&feed)), Ok(()));
+
+    // removed, the feed is no longer needed nor checked
+    assert_eq!(set_feed("usdc", Pubkey::default()), Ok(()));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .price_feeds
+        .is_empty());
+    assert_eq!(deposit(tokens(100), None), Ok(()));
+    assert_eq!(total(), tokens(250));
+    set_slot(0);
+}
+
+#[test]
+fn test_swap() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let tokens: Vec<_> = ["usdc", "ray"]
+        .iter()
+        .map(|symbol| add_token(&program_id, &state_account, symbol))
+        .collect();
+    let user = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let admin = |instruction: &ContractInstruction| {
+        process_instruction(&program_id, &admin_accounts, &pack(instruction))
+    };
+    admin(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    })
+    .unwrap();
+    admin(&AdminSetTreasury { treasury }).unwrap();
+    // the user holds 100 usdc, the treasury 100 ray
+    for ((mint, vault), (symbol, owner)) in tokens.iter().zip([("usdc", user), ("ray", treasury)]) {
+        let token_account = new_token_account(mint.key, &owner, 100);
+        let accounts = user_token_accounts(&state_account, &owner, &token_account, vault);
+        let data = deposit_data(&state_account, symbol, owner, 100);
+        process_instruction(&program_id, &accounts, &data).unwrap();
+    }
+    let set_rate = |from: &str, to: &str, numerator: u64, denominator: u64| {
+        admin(&AdminSetSwapRate {
+            from: token(from),
+            to: token(to),
+            numerator,
+            denominator,
+        })
+    };
+    let swap = |from: &str, to: &str, amount_in: u64, min_amount_out: u64| {
+        let mut accounts = vec![
+            state_account.clone(),
+            new_account(user, true, 0, Pubkey::default()),
+        ];
+        for symbol in [from, to] {
+            for owner in [user, treasury] {
+                accounts.push(balance_account(&program_id, &token(symbol).mint, &owner));
+            }
+        }
+        accounts.push(system_program_account());
+        let data = pack(&UserSwap {
+            from: token(from),
+            to: token(to),
+            user,
+            amount_in,
+            min_amount_out,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    // (user's usdc, user's ray, treasury's usdc, treasury's ray)
+    let balances = || {
+        let state = load_full_state(&state_account).unwrap();
+        let balance = |symbol: &str, owner: &Pubkey| {
+            let entry = &state.all_token_balances[&token(symbol)];
+            entry.balances.get(owner).copied().unwrap_or(0)
+        };
+        (
+            balance("usdc", &user),
+            balance("ray", &user),
+            balance("usdc", &treasury),
+            balance("ray", &treasury),
+        )
+    };
+    let no_rate: ProgramResult = Err(ContractError::SwapRateNotSet.into());
+
+    assert_eq!(swap("usdc", "ray", 10, 0), no_rate);
+    assert_eq!(
+        set_rate("usdc", "usdc", 1, 1),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        set_rate("usdc", "ray", 1, 0),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        set_rate("usdc", "sol", 1, 1),
+        Err(ContractError::TokenNotFound.into())
+    );
+
+    // 10 * 3 / 7 is 4 once rounded down, asking for 5 is refused
+    assert_eq!(set_rate("usdc", "ray", 3, 7), Ok(()));
+    assert_eq!(
+        swap("usdc", "ray", 10, 5),
+        Err(ContractError::SlippageExceeded.into())
+    );
+    assert_eq!(swap("usdc", "ray", 10, 4), Ok(()));
+    assert_eq!(balances(), (90, 4, 10, 96));
+    // an amount buying less than one unit is refused rather than taken for nothing
+    assert_eq!(
+        swap("usdc", "ray", 2, 0),
+        Err(ContractError::ZeroAmount.into())
+    );
+    assert_eq!(swap("usdc", "ray", 7, 3), Ok(()));
+    assert_eq!(balances(), (83, 7, 17, 93));
+    // each direction has its own rate
+    assert_eq!(swap("ray", "usdc", 7, 0), no_rate);
+
+    // neither side is overdrawn, nor can the rate overflow what comes out
+    assert_eq!(set_rate("usdc", "ray", 1_000, 1), Ok(()));
+    assert_eq!(
+        swap("usdc", "ray", 1, 0),
+        Err(ProgramError::InsufficientFunds)
+    );
+    assert_eq!(
+        swap("usdc", "ray", u64::MAX, 0),
+        Err(ContractError::BalanceOverflow.into())
+    );
+    assert_eq!(set_rate("usdc", "ray", 1, 1), Ok(()));
+    assert_eq!(
+        swap("usdc", "ray", 84, 0),
+        Err(ProgramError::InsufficientFunds)
+    );
+    assert_eq!(swap("usdc", "ray", 83, 83), Ok(()));
+    assert_eq!(balances(), (0, 90, 100, 10));
+
+    // what the vaults hold is untouched, so are the totals
+    let state = load_state(&state_account).unwrap();
+    for symbol in ["usdc", "ray"] {
+        assert_eq!(
+            state.all_token_balances[&token(symbol)].total_deposited,
+            100
+        );
+    }
+    assert_eq!(state.global_total_deposited, 200);
+
+    assert_eq!(set_rate("usdc", "ray", 0, 1), Ok(()));
+    assert_eq!(swap("usdc", "ray", 1, 0), no_rate);
+}
+
+#[test]
+fn test_swap_share_mode() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let tokens: Vec<_> = ["usdc", "ray"]
+        .iter()
+        .map(|symbol| add_token(&program_id, &state_account, symbol))
+        .collect();
+    let user = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let admin = |instruction: &ContractInstruction| {
+        process_instruction(&program_id, &admin_accounts, &pack(instruction))
+    };
+    admin(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    })
+    .unwrap();
+    admin(&AdminSetTreasury { treasury }).unwrap();
+    admin(&AdminEnableShareMode {
+        token: token("usdc"),
+    })
+    .unwrap();
+    let deposit = |t: usize, owner: Pubkey, amount: u64| {
+        let (mint, vault) = &tokens[t];
+        let token_account = new_token_account(mint.key, &owner, amount);
+        let accounts = user_token_accounts(&state_account, &owner, &token_account, vault);
+        let symbol = ["usdc", "ray"][t];
+        let data = deposit_data(&state_account, symbol, owner, amount);
+        process_instruction(&program_id, &accounts, &data).unwrap();
+    };
+    // 100 usdc shares each, 100 ray for the treasury
+    deposit(0, user, 100);
+    deposit(0, treasury, 100);
+    deposit(1, treasury, 100);
+    // a share is worth 2 usdc from now on
+    let (mint, vault) = &tokens[0];
+    let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, 200);
+    let mut accounts =
+        user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, vault);
+    accounts[1] = admin_account(true);
+    let donate = pack(&AdminDonateYield {
+        token: token("usdc"),
+        amount: 200,
+    });
+    process_instruction(&program_id, &accounts[..6], &donate).unwrap();
+
+    for (from, to) in [("usdc", "ray"), ("ray", "usdc")] {
+        admin(&AdminSetSwapRate {
+            from: token(from),
+            to: token(to),
+            numerator: 1,
+            denominator: 1,
+        })
+        .unwrap();
+    }
+    let swap = |from: &str, to: &str, amount_in: u64, min_amount_out: u64| {
+        let mut accounts = vec![
+            state_account.clone(),
+            new_account(user, true, 0, Pubkey::default()),
+        ];
+        for symbol in [from, to] {
+            for owner in [user, treasury] {
+                accounts.push(balance_account(&program_id, &token(symbol).mint, &owner));
+            }
+        }
+        accounts.push(system_program_account());
+        let data = pack(&UserSwap {
+            from: token(from),
+            to: token(to),
+            user,
+            amount_in,
+            min_amount_out,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    // (user's usdc shares, user's ray, treasury's usdc shares, treasury's ray)
+    let balances = || {
+        let state = load_full_state(&state_account).unwrap();
+        let balance = |symbol: &str, owner: &Pubkey| {
+            let entry = &state.all_token_balances[&token(symbol)];
+            entry.balances.get(owner).copied().unwrap_or(0)
+        };
+        (
+            balance("usdc", &user),
+            balance("ray", &user),
+            balance("usdc", &treasury),
+            balance("ray", &treasury),
+        )
+    };
+
+    // 10 shares are worth 20 usdc, so buy 20 ray
+    assert_eq!(
+        swap("usdc", "ray", 10, 21),
+        Err(ContractError::SlippageExceeded.into())
+    );
+    assert_eq!(swap("usdc", "ray", 10, 20), Ok(()));
+    assert_eq!(balances(), (90, 20, 110, 80));
+    // and 15 ray buy 7 shares, rounded down
+    assert_eq!(swap("ray", "usdc", 15, 7), Ok(()));
+    assert_eq!(balances(), (97, 5, 103, 95));
+    // more shares than there are
+    assert_eq!(
+        swap("usdc", "ray", u64::MAX, 0),
+        Err(ProgramError::InsufficientFunds)
+    );
+    // shares change hands, the pool doesn't
+    let state = load_state(&state_account).unwrap();
+    assert_eq!(state.share_supplies[mint.key], 200);
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].total_deposited,
+        400
+    );
+
+    // the dust threshold is in usdc too, 2 shares left are worth 4
+    admin(&AdminSetDustThreshold {
+        token: token("usdc"),
+        threshold: 3,
+    })
+    .unwrap();
+    let user_token_account = new_token_account(mint.key, &user, 0);
+    let mut accounts = user_token_accounts(&state_account, &user, &user_token_account, vault);
+    accounts.push(balance_account(&program_id, mint.key, &treasury));
+    let withdraw = |shares: u64| {
+        let data = withdraw_data(&state_account, "usdc", user, shares);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    assert_eq!(withdraw(95), Ok(()));
+    assert_eq!(balances().0, 2);
+    // 1 share left is worth 2, swept
+    assert_eq!(withdraw(1), Ok(()));
+    assert_eq!(balances().0, 0);
+    assert_eq!(balances().2, 104);
+}
+
+#[test]
+fn test_share_mode() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let tokens: HashMap<_, _> = ["usdc", "ray"]
+        .into_iter()
+        .map(|symbol| (symbol, add_token(&program_id, &state_account, symbol)))
+        .collect();
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let admin = |instruction: &ContractInstruction| {
+        process_instruction(&program_id, &admin_accounts, &pack(instruction))
+    };
+    admin(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    })
+    .unwrap();
+    let enable = |symbol: &str| {
+        admin(&AdminEnableShareMode {
+            token: token(symbol),
+        })
+    };
+    // admin paying amount of symbol into the vault
+    let admin_transfer = |symbol: &str, amount: u64, instruction: &ContractInstruction| {
+        let (mint, vault) = &tokens[symbol];
+        let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, amount);
+        let mut accounts =
+            user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, vault);
+        accounts[1] = admin_account(true);
+        process_instruction(&program_id, &accounts[..6], &pack(instruction))
+    };
+    let donate = |symbol: &str, amount: u64| {
+        admin_transfer(
+            symbol,
+            amount,
+            &AdminDonateYield {
+                token: token(symbol),
+                amount,
+            },
+        )
+    };
+    // a user of symbol holding amount, with its accounts for deposit and withdraw
+    let new_user = |symbol: &str, amount: u64| {
+        let (mint, vault) = &tokens[symbol];
+        let user = Pubkey::new_unique();
+        let token_account = new_token_account(mint.key, &user, amount);
+        let accounts = user_token_accounts(&state_account, &user, &token_account, vault);
+        (user, accounts)
+    };
+    let deposit = |symbol: &str, (user, accounts): &(Pubkey, Vec<_>), amount: u64| {
+        let data = deposit_data(&state_account, symbol, *user, amount);
+        process_instruction(&program_id, accounts, &data)
+    };
+    let withdraw = |symbol: &str, (user, accounts): &(Pubkey, Vec<_>), shares: u64| {
+        let data = withdraw_data(&state_account, symbol, *user, shares);
+        process_instruction(&program_id, accounts, &data)
+    };
+    let shares_of = |symbol: &str, user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        let entry = &state.all_token_balances[&token(symbol)];
+        entry.balances.get(user).copied().unwrap_or(0)
+    };
+    // (total shares, total deposited)
+    let pool = |symbol: &str| {
+        let state = load_state(&state_account).unwrap();
+        (
+            state.share_supplies[&token(symbol).mint],
+            state.all_token_balances[&token(symbol)].total_deposited,
+        )
+    };
+    let zero_shares: ProgramResult = Err(ContractError::ZeroShares.into());
+
+    // balances already held would be read as shares
+    let ray_user = new_user("ray", 10);
+    assert_eq!(deposit("ray", &ray_user, 10), Ok(()));
+    assert_eq!(
+        enable("ray"),
+        Err(ContractError::TokenHasOutstandingBalances.into())
+    );
+    assert_eq!(withdraw("ray", &ray_user, 10), Ok(()));
+    assert_eq!(enable("sol"), Err(ContractError::TokenNotFound.into()));
+    assert_eq!(enable("usdc"), Ok(()));
+    // nothing to donate to before the first deposit
+    assert_eq!(donate("usdc", 50), Err(ProgramError::InvalidArgument));
+
+    // the first deposit gets a share per unit, a donation raises what each is worth
+    let alice = new_user("usdc", 1_000);
+    let bob = new_user("usdc", 1_000);
+    assert_eq!(deposit("usdc", &alice, 100), Ok(()));
+    assert_eq!(shares_of("usdc", &alice.0), 100);
+    assert_eq!(donate("usdc", 0), Err(ContractError::ZeroAmount.into()));
+    assert_eq!(donate("usdc", 50), Ok(()));
+    assert_eq!(pool("usdc"), (100, 150));
+    assert_eq!(token_amount(&tokens["usdc"].1), 150);
+    // 30 * 100 / 150
+    assert_eq!(deposit("usdc", &bob, 30), Ok(()));
+    assert_eq!(shares_of("usdc", &bob.0), 20);
+    assert_eq!(pool("usdc"), (120, 180));
+
+    // rounding goes the vault's way, 1 buys no share and is refused, 2 buy one worth 1.5
+    assert_eq!(deposit("usdc", &bob, 1), zero_shares);
+    assert_eq!(deposit("usdc", &bob, 2), Ok(()));
+    assert_eq!(pool("usdc"), (121, 182));
+    assert_eq!(shares_for_amount(2, 120, 180), Some(1));
+    assert_eq!(amount_for_shares(1, 121, 182), Some(1));
+
+    // 100 * 182 / 121 is 150.4, alice gets her deposit and the donation
+    assert_eq!(withdraw("usdc", &alice, 100), Ok(()));
+    assert_eq!(token_amount(&alice.1[2]), 1_050);
+    assert_eq!(pool("usdc"), (21, 32));
+    assert_eq!(
+        withdraw("usdc", &bob, 22),
+        Err(ProgramError::InsufficientFunds)
+    );
+    // the last holder gets what is left, the vault backs the ledger throughout
+    assert_eq!(withdraw("usdc", &bob, 21), Ok(()));
+    assert_eq!(token_amount(&bob.1[2]), 1_000);
+    assert_eq!(pool("usdc"), (0, 0));
+    assert_eq!(token_amount(&tokens["usdc"].1), 0);
+
+    // tokens sent straight to the vault aren't assets of the shares, a first depositor of 1
+    // can't inflate what its share is worth with them
+    let attacker = new_user("usdc", 1);
+    let victim = new_user("usdc", 1_000);
+    assert_eq!(deposit("usdc", &attacker, 1), Ok(()));
+    let vault = &tokens["usdc"].1;
+    let mut vault_state = TokenAccount::unpack(&vault.data.borrow()).unwrap();
+    vault_state.amount += 1_000_000;
+    vault_state.pack_into_slice(&mut vault.data.borrow_mut());
+    assert_eq!(deposit("usdc", &victim, 1_000), Ok(()));
+    assert_eq!(shares_of("usdc", &victim.0), 1_000);
+    assert_eq!(withdraw("usdc", &attacker, 1), Ok(()));
+    assert_eq!(token_amount(&attacker.1[2]), 1);
+    assert_eq!(withdraw("usdc", &victim, 1_000), Ok(()));
+    assert_eq!(token_amount(&victim.1[2]), 1_000);
+
+    // a donation after a deposit of 1 makes that share worth 1_001, a deposit buying no share
+    // is refused rather than handed to the one holder
+    assert_eq!(enable("ray"), Ok(()));
+    let first = new_user("ray", 1);
+    let victim = new_user("ray", 3_000);
+    assert_eq!(deposit("ray", &first, 1), Ok(()));
+    assert_eq!(donate("ray", 1_000), Ok(()));
+    assert_eq!(deposit("ray", &victim, 1_000), zero_shares);
+    assert_eq!(deposit("ray", &victim, 2_002), Ok(()));
+    assert_eq!(shares_of("ray", &victim.0), 2);
+    // each gets back exactly what it is owed
+    assert_eq!(withdraw("ray", &victim, 2), Ok(()));
+    assert_eq!(withdraw("ray", &first, 1), Ok(()));
+    assert_eq!(token_amount(&first.1[2]), 1_001);
+    assert_eq!(pool("ray"), (0, 0));
+    let state = load_state(&state_account).unwrap();
+    assert_eq!(state.all_token_balances[&token("ray")].total_deposited, 0);
+
+    // an airdrop would credit amounts, not shares
+    let set_root = AdminSetMerkleRoot {
+        token: token("ray"),
+        root: [1; 32],
+        total: 10,
+    };
+    assert_eq!(
+        admin_transfer("ray", 10, &set_root),
+        Err(ProgramError::InvalidArgument)
+    );
+}
+
+#[test]
+fn test_share_mode_limits() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let admin = |instruction: &ContractInstruction| {
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &pack(instruction))
+    };
+    admin(&AdminSetRateLimit {
+        max_ops: 0,
+        window_slots: 0,
+    })
+    .unwrap();
+    admin(&AdminEnableShareMode {
+        token: token("usdc"),
+    })
+    .unwrap();
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 1_000);
+    let bob_token_account = new_token_account(mint.key, &bob, 1_000);
+    let alice_accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    let deposit = |accounts: &[AccountInfo<'static>], user: Pubkey, amount: u64| {
+        let data = deposit_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, accounts, &data)
+    };
+    let withdraw = |shares: u64| {
+        let data = withdraw_data(&state_account, "usdc", alice, shares);
+        process_instruction(&program_id, &alice_accounts, &data)
+    };
+    let transfer = |shares: u64| {
+        let accounts = [
+            state_account.clone(),
+            alice_accounts[1].clone(),
+            alice_accounts[6].clone(),
+            bob_accounts[6].clone(),
+            system_program_account(),
+        ];
+        let data = pack(&UserTransfer {
+            token: token("usdc"),
+            from: alice,
+            to: bob,
+            amount: shares,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    // (total shares, total deposited)
+    let pool = || {
+        let state = load_state(&state_account).unwrap();
+        (
+            state.share_supplies[mint.key],
+            state.all_token_balances[&token("usdc")].total_deposited,
+        )
+    };
+
+    // a share is worth 2 from here on
+    assert_eq!(deposit(&alice_accounts, alice, 100), Ok(()));
+    let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, 100);
+    let mut donate_accounts =
+        user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, &vault);
+    donate_accounts[1] = admin_account(true);
+    let data = pack(&AdminDonateYield {
+        token: token("usdc"),
+        amount: 100,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &donate_accounts[..6], &data),
+        Ok(())
+    );
+    assert_eq!(pool(), (100, 200));
+
+    // the balance cap is in units, 160 shares are worth 320
+    let cap_exceeded: ProgramResult = Err(ContractError::UserBalanceCapExceeded.into());
+    admin(&AdminSetMaxUserBalance {
+        token: token("usdc"),
+        max_user_balance: Some(300),
+    })
+    .unwrap();
+    assert_eq!(deposit(&bob_accounts, bob, 200), Ok(()));
+    assert_eq!(deposit(&bob_accounts, bob, 120), cap_exceeded);
+    assert_eq!(deposit(&bob_accounts, bob, 100), Ok(()));
+    assert_eq!(pool(), (250, 500));
+    // and the one share more a transfer would give bob takes him to 302
+    assert_eq!(transfer(1), cap_exceeded);
+    admin(&AdminSetMaxUserBalance {
+        token: token("usdc"),
+        max_user_balance: None,
+    })
+    .unwrap();
+    assert_eq!(transfer(1), Ok(()));
+
+    // so is the minimum withdrawal, 24 shares are worth 48
+    admin(&AdminSetMinWithdraw {
+        token: token("usdc"),
+        min_withdraw: 50,
+    })
+    .unwrap();
+    assert_eq!(
+        withdraw(24),
+        Err(ContractError::WithdrawBelowMinimum.into())
+    );
+    assert_eq!(withdraw(25), Ok(()));
+    assert_eq!(token_amount(&alice_token_account), 950);
+    admin(&AdminSetMinWithdraw {
+        token: token("usdc"),
+        min_withdraw: 0,
+    })
+    .unwrap();
+
+    // and the daily withdrawal limit, 50 shares use up 100 of it
+    admin(&AdminSetDailyWithdrawalLimit {
+        token: token("usdc"),
+        limit: 100,
+    })
+    .unwrap();
+    assert_eq!(withdraw(50), Ok(()));
+    assert_eq!(withdraw(1), Err(ContractError::DailyLimitExceeded.into()));
+    assert_eq!(token_amount(&alice_token_account), 1_050);
+
+    // the stats count units too
+    let state = load_full_state(&state_account).unwrap();
+    let stats = state.user_stats[&(*mint.key, alice)];
+    assert_eq!((stats.withdraw_count, stats.lifetime_withdrawn), (2, 150));
+    assert_eq!(state.user_stats[&(*mint.key, bob)].lifetime_deposited, 300);
+}
+
+#[test]
+fn test_slash() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let operator = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    let admin = |instruction: &ContractInstruction| {
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &pack(instruction))
+    };
+    let slash_as = |signer: Pubkey, amount: u64, reason_code: u16| {
+        let accounts = [
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+            balance_account(&program_id, mint.key, &user),
+            balance_account(&program_id, mint.key, &treasury),
+            system_program_account(),
+        ];
+        let data = pack(&AdminSlash {
+            token: token("usdc"),
+            user,
+            amount,
+            reason_code,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let slash = |amount: u64, reason_code: u16| slash_as(TEST_ADMIN, amount, reason_code);
+    // (user's, treasury's)
+    let balances = || {
+        let state = load_full_state(&state_account).unwrap();
+        let entry = &state.all_token_balances[&token("usdc")];
+        let balance = |owner: &Pubkey| entry.balances.get(owner).copied().unwrap_or(0);
+        (balance(&user), balance(&treasury))
+    };
+
+    assert_eq!(slash(10, 1), Err(ContractError::TreasuryNotSet.into()));
+    admin(&AdminSetTreasury { treasury }).unwrap();
+    admin(&AdminSetOperator {
+        operator: Some(operator),
+    })
+    .unwrap();
+    // only the admin slashes
+    assert_eq!(
+        slash_as(operator, 10, 1),
+        Err(ContractError::InsufficientAuthority.into())
+    );
+    assert_eq!(
+        slash_as(user, 10, 1),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(balances(), (100, 0));
+    assert_eq!(slash(0, 1), Err(ContractError::ZeroAmount.into()));
+
+    take_events::<SlashEvent>();
+    assert_eq!(slash(30, 7), Ok(()));
+    assert_eq!(balances(), (70, 30));
+    assert_eq!(
+        take_events::<SlashEvent>(),
+        [SlashEvent {
+            token_symbol: "USDC".to_string(),
+            user,
+            amount: 30,
+            reason_code: 7,
+            user_balance: 70,
+            treasury_balance: 30,
+            sequence: load_state(&state_account).unwrap().sequence,
+        }]
+    );
+
+    // a request for more than would be left has to be cancelled first
+    let data = pack(&UserRequestWithdraw {
+        token: token("usdc"),
+        user,
+        amount: 20,
+    });
+    let request_accounts = [
+        accounts[0].clone(),
+        accounts[1].clone(),
+        accounts[6].clone(),
+    ];
+    process_instruction(&program_id, &request_accounts, &data).unwrap();
+    assert_eq!(balances(), (50, 30));
+    assert_eq!(slash(30, 2), Ok(()));
+    assert_eq!(
+        slash(1, 2),
+        Err(ContractError::WithdrawRequestPending.into())
+    );
+    let data = pack(&AdminCancelWithdrawRequest {
+        token: token("usdc"),
+        user,
+    });
+    let cancel_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        accounts[6].clone(),
+    ];
+    process_instruction(&program_id, &cancel_accounts, &data).unwrap();
+    assert_eq!(balances(), (40, 60));
+
+    // more than the balance takes all of it, the vault still backs the ledger
+    assert_eq!(slash(1_000, 3), Ok(()));
+    assert_eq!(balances(), (0, 100));
+    assert_eq!(take_events::<SlashEvent>()[1].amount, 40);
+    assert_eq!(slash(1, 3), Err(ProgramError::InsufficientFunds));
+    let state = load_full_state(&state_account).unwrap();
+    // the emptied balance is dropped, the treasury is the only holder left
+    assert!(!state.all_token_balances[&token("usdc")]
+        .balances
+        .contains_key(&user));
+    assert_eq!(state.holder_counts[mint.key], 1);
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].total_deposited,
+        100
+    );
+    assert_eq!(token_amount(&vault), 100);
+
+    // of a share-mode token the shares worth amount are taken
+    let (ray_mint, ray_vault) = add_token(&program_id, &state_account, "ray");
+    admin(&AdminEnableShareMode {
+        token: token("ray"),
+    })
+    .unwrap();
+    let ray_token_account = new_token_account(ray_mint.key, &user, 100);
+    let ray_accounts = user_token_accounts(&state_account, &user, &ray_token_account, &ray_vault);
+    process_instruction(
+        &program_id,
+        &ray_accounts,
+        &deposit_data(&state_account, "ray", user, 100),
+    )
+    .unwrap();
+    let admin_token_account = new_token_account(ray_mint.key, &TEST_ADMIN, 100);
+    let mut donate_accounts = user_token_accounts(
+        &state_account,
+        &TEST_ADMIN,
+        &admin_token_account,
+        &ray_vault,
+    );
+    donate_accounts[1] = admin_account(true);
+    let donate = pack(&AdminDonateYield {
+        token: token("ray"),
+        amount: 100,
+    });
+    process_instruction(&program_id, &donate_accounts[..6], &donate).unwrap();
+    // a share is worth 2 ray
+    let slash_ray = |amount: u64| {
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            balance_account(&program_id, ray_mint.key, &user),
+            balance_account(&program_id, ray_mint.key, &treasury),
+            system_program_account(),
+        ];
+        let data = pack(&AdminSlash {
+            token: token("ray"),
+            user,
+            amount,
+            reason_code: 4,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    assert_eq!(slash_ray(1), Err(ContractError::ZeroShares.into()));
+    // 21 ray are 10.5 shares
+    assert_eq!(slash_ray(21), Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    let entry = &state.all_token_balances[&token("ray")];
+    assert_eq!(entry.balances[&user], 90);
+    assert_eq!(entry.balances[&treasury], 10);
+    assert_eq!(slash_ray(u64::MAX), Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    let entry = &state.all_token_balances[&token("ray")];
+    assert!(!entry.balances.contains_key(&user));
+    assert_eq!(entry.balances[&treasury], 100);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let operator = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 1_000);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 1_000),
+    )
+    .unwrap();
+    let signed = |signer: Pubkey, instruction: &ContractInstruction| {
+        let accounts = [
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+        ];
+        process_instruction(&program_id, &accounts, &pack(instruction))
+    };
+    let admin = |instruction: &ContractInstruction| signed(TEST_ADMIN, instruction);
+    admin(&AdminSetOperator {
+        operator: Some(operator),
+    })
+    .unwrap();
+    let set_breaker = |threshold_bps: u16, window_slots: u64| AdminSetCircuitBreaker {
+        token: token("usdc"),
+        threshold_bps,
+        window_slots,
+    };
+    let reset = AdminResetBreaker {
+        token: token("usdc"),
+    };
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let withdrawals_enabled = || {
+        load_state(&state_account).unwrap().all_token_balances[&token("usdc")].withdrawals_enabled
+    };
+    set_slot(10);
+
+    assert_eq!(
+        admin(&set_breaker(10_001, 100)),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        admin(&AdminSetCircuitBreaker {
+            token: token("usdt"),
+            threshold_bps: 2_000,
+            window_slots: 100,
+        }),
+        Err(ContractError::TokenNotFound.into())
+    );
+    assert_eq!(
+        signed(operator, &set_breaker(2_000, 100)),
+        Err(ContractError::InsufficientAuthority.into())
+    );
+    // 20% per 100 slots
+    admin(&set_breaker(2_000, 100)).unwrap();
+
+    // a drain: 200 of 1000 is still within the threshold
+    assert_eq!(withdraw(100), Ok(()));
+    assert_eq!(withdraw(100), Ok(()));
+    assert!(withdrawals_enabled());
+    take_events::<CircuitBreakerTrippedEvent>();
+    // the withdrawal going past it still goes through and trips the breaker
+    assert_eq!(withdraw(1), Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.all_token_balances[&token("usdc")].balances[&user],
+        799
+    );
+    assert!(!withdrawals_enabled());
+    assert_eq!(
+        take_events::<CircuitBreakerTrippedEvent>(),
+        [CircuitBreakerTrippedEvent {
+            token_symbol: "USDC".to_string(),
+            withdrawn: 201,
+            total_deposited: 799,
+            window_start_slot: 10,
+            sequence: load_state(&state_account).unwrap().sequence,
+        }]
+    );
+    assert_eq!(withdraw(1), Err(ContractError::WithdrawalsDisabled.into()));
+    let request = pack(&UserRequestWithdraw {
+        token: token("usdc"),
+        user,
+        amount: 1,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &request),
+        Err(ContractError::WithdrawalsDisabled.into())
+    );
+    // only a reset re-enables withdrawals, and only by the admin
+    let enable = AdminSetTokenFlags {
+        token: token("usdc"),
+        deposits_enabled: true,
+        withdrawals_enabled: true,
+    };
+    let tripped: ProgramResult = Err(ContractError::CircuitBreakerTripped.into());
+    assert_eq!(signed(operator, &enable), tripped);
+    assert_eq!(admin(&set_breaker(0, 100)), tripped);
+    // a new threshold doesn't reset it either
+    admin(&set_breaker(5_000, 100)).unwrap();
+    assert!(!withdrawals_enabled());
+    assert_eq!(
+        signed(operator, &reset),
+        Err(ContractError::InsufficientAuthority.into())
+    );
+    set_slot(20);
+    assert_eq!(admin(&reset), Ok(()));
+    assert!(withdrawals_enabled());
+    assert_eq!(admin(&reset), Err(ProgramError::InvalidArgument));
+    assert!(!load_state(&state_account).unwrap().circuit_breakers[mint.key].tripped);
+
+    // the window starts over with the reset, and again once it expired
+    admin(&set_breaker(2_000, 100)).unwrap();
+    assert_eq!(withdraw(150), Ok(()));
+    set_slot(120);
+    assert_eq!(withdraw(100), Ok(()));
+    assert!(withdrawals_enabled());
+    let breaker = load_state(&state_account).unwrap().circuit_breakers[mint.key];
+    assert_eq!((breaker.window_start_slot, breaker.withdrawn), (120, 100));
+
+    // a threshold of 0 removes the breaker
+    admin(&set_breaker(0, 100)).unwrap();
+    assert!(load_state(&state_account)
+        .unwrap()
+        .circuit_breakers
+        .is_empty());
+    assert_eq!(withdraw(549), Ok(()));
+    assert!(withdrawals_enabled());
+    set_slot(0);
+}
+
+#[test]
+fn test_dust_threshold() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let user = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+    let treasury = Pubkey::new_unique();
+    let operator = Pubkey::new_unique();
+    let accounts_of = |user: &Pubkey, token_account: &AccountInfo<'static>| {
+        let mut accounts = user_token_accounts(&state_account, user, token_account, &vault);
+        accounts.push(balance_account(&program_id, mint.key, &treasury));
+        accounts
+    };
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = accounts_of(&user, &user_token_account);
+    let other_token_account = new_token_account(mint.key, &other, 20);
+    let other_accounts = accounts_of(&other, &other_token_account);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 100),
+    )
+    .unwrap();
+    let signed = |signer: Pubkey, instruction: &ContractInstruction| {
+        let accounts = [
+            state_account.clone(),
+            new_account(signer, true, 0, Pubkey::default()),
+        ];
+        process_instruction(&program_id, &accounts, &pack(instruction))
+    };
+    let admin = |instruction: &ContractInstruction| signed(TEST_ADMIN, instruction);
+    let set_threshold = |threshold: u64| AdminSetDustThreshold {
+        token: token("usdc"),
+        threshold,
+    };
+    let withdraw = |user: Pubkey, amount: u64| {
+        let accounts = if user == other {
+            &other_accounts
+        } else {
+            &accounts
+        };
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, accounts, &data)
+    };
+    // (user's balance, treasury's balance, total_deposited, holder count)
+    let ledger = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        let entry = &state.all_token_balances[&token("usdc")];
+        let balance = |owner: &Pubkey| entry.balances.get(owner).copied();
+        (
+            balance(user),
+            balance(&treasury),
+            entry.total_deposited,
+            state.holder_counts[mint.key],
+        )
+    };
+
+    // the dust needs somewhere to go
+    assert_eq!(
+        admin(&set_threshold(10)),
+        Err(ContractError::TreasuryNotSet.into())
+    );
+    admin(&AdminSetTreasury { treasury }).unwrap();
+    admin(&AdminSetOperator {
+        operator: Some(operator),
+    })
+    .unwrap();
+    assert_eq!(
+        signed(operator, &set_threshold(10)),
+        Err(ContractError::InsufficientAuthority.into())
+    );
+    admin(&set_threshold(10)).unwrap();
+
+    // just above the threshold the balance stays
+    take_events::<WithdrawEvent>();
+    assert_eq!(withdraw(user, 89), Ok(()));
+    assert_eq!(ledger(&user), (Some(11), None, 11, 1));
+    assert_eq!(take_events::<WithdrawEvent>()[0].dust_swept, 0);
+    // at it, what is left goes to the treasury and the user's entry is removed
+    assert_eq!(withdraw(user, 1), Ok(()));
+    assert_eq!(ledger(&user), (None, Some(10), 10, 1));
+    let event = &take_events::<WithdrawEvent>()[0];
+    assert_eq!(
+        (event.amount, event.new_balance, event.dust_swept),
+        (1, 0, 10)
+    );
+    // the dust stays in the vault
+    assert_eq!(token_amount(&user_token_account), 90);
+    assert_eq!(token_amount(&vault), 10);
+
+    // a threshold of 0 keeps any balance
+    admin(&set_threshold(0)).unwrap();
+    assert!(load_state(&state_account)
+        .unwrap()
+        .dust_thresholds
+        .is_empty());
+    process_instruction(
+        &program_id,
+        &other_accounts,
+        &deposit_data(&state_account, "usdc", other, 20),
+    )
+    .unwrap();
+    assert_eq!(withdraw(other, 15), Ok(()));
+    assert_eq!(ledger(&other), (Some(5), Some(10), 15, 2));
+    assert_eq!(take_events::<WithdrawEvent>()[0].dust_swept, 0);
+}
+
+#[test]
+fn test_timelock() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    add_token(&program_id, &state_account, "usdc");
+    let operator = Pubkey::new_unique();
+    let signed = |key: Pubkey| [state_account.clone(), new_account(key, true, 0, key)];
+    let admin = |instruction: &ContractInstruction| {
+        process_instruction(&program_id, &signed(TEST_ADMIN), &pack(instruction))
+    };
+    admin(&AdminSetOperator {
+        operator: Some(operator),
+    })
+    .unwrap();
+    let set_fee = |fee_bps: u16| {
+        Box::new(AdminSetDepositFee {
+            token: token("usdc"),
+            fee_bps,
+        })
+    };
+    let queue = |signer: Pubkey, action: Box<ContractInstruction>, eta_slot: u64| {
+        let data = pack(&AdminQueueAction { action, eta_slot });
+        process_instruction(&program_id, &signed(signer), &data)
+    };
+    let execute = |signer: Pubkey, action: Box<ContractInstruction>| {
+        let data = pack(&AdminExecuteAction { action });
+        process_instruction(&program_id, &signed(signer), &data)
+    };
+    let fee =
+        || load_state(&state_account).unwrap().all_token_balances[&token("usdc")].deposit_fee_bps;
+    let delay = DEFAULT_TIMELOCK_DELAY_SLOTS;
+    set_slot(100);
+
+    // timelocked instructions don't run on their own, not even in a multicall
+    let required: ProgramResult = Err(ContractError::TimelockRequired.into());
+    assert_eq!(admin(&set_fee(10)), required);
+    let calls = vec![*set_fee(10)];
+    assert_eq!(admin(&Multicall { calls }), required);
+    // pausing stays immediate, and can't be queued
+    let pause = AdminPause {
+        withdrawals_only: false,
+    };
+    assert_eq!(
+        queue(TEST_ADMIN, Box::new(pause.clone()), 100 + delay),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(admin(&pause), Ok(()));
+    assert_eq!(admin(&AdminUnpause), Ok(()));
+
+    // the eta has to be the delay away at least
+    assert_eq!(
+        queue(TEST_ADMIN, set_fee(10), 99 + delay),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(
+        execute(TEST_ADMIN, set_fee(10)),
+        Err(ContractError::ActionNotQueued.into())
+    );
+    // whoever may run the action queues it
+    assert_eq!(
+        queue(Pubkey::new_unique(), set_fee(10), 100 + delay),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(queue(operator, set_fee(10), 100 + delay), Ok(()));
+    assert_eq!(
+        queue(TEST_ADMIN, set_fee(10), 200 + delay),
+        Err(ContractError::ActionAlreadyQueued.into())
+    );
+    set_slot(99 + delay);
+    assert_eq!(
+        execute(operator, set_fee(10)),
+        Err(ContractError::TimelockNotElapsed.into())
+    );
+    // the action is exactly the one queued
+    set_slot(100 + delay);
+    assert_eq!(
+        execute(operator, set_fee(11)),
+        Err(ContractError::ActionNotQueued.into())
+    );
+    assert_eq!(fee(), 0);
+    assert_eq!(execute(operator, set_fee(10)), Ok(()));
+    assert_eq!(fee(), 10);
+    assert!(load_state(&state_account)
+        .unwrap()
+        .queued_actions
+        .is_empty());
+    assert_eq!(
+        execute(operator, set_fee(10)),
+        Err(ContractError::ActionNotQueued.into())
+    );
+
+    // past the grace period after its eta the action expired
+    let slot = 100 + delay;
+    assert_eq!(queue(TEST_ADMIN, set_fee(20), slot + delay), Ok(()));
+    set_slot(slot + delay + TIMELOCK_GRACE_SLOTS + 1);
+    assert_eq!(
+        execute(TEST_ADMIN, set_fee(20)),
+        Err(ContractError::ActionExpired.into())
+    );
+    assert_eq!(fee(), 10);
+    // and makes way for queueing it again
+    let slot = slot + delay + TIMELOCK_GRACE_SLOTS + 1;
+    assert_eq!(queue(TEST_ADMIN, set_fee(20), slot + delay), Ok(()));
+
+    // a longer delay also holds back the actions queued before it
+    let set_delay = |delay_slots: u64| Box::new(AdminSetTimelockDelay { delay_slots });
+    assert_eq!(
+        queue(
+            TEST_ADMIN,
+            set_delay(MIN_TIMELOCK_DELAY_SLOTS - 1),
+            slot + delay
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        queue(TEST_ADMIN, set_delay(2 * delay), slot + delay),
+        Ok(())
+    );
+    set_slot(slot + delay);
+    assert_eq!(
+        execute(TEST_ADMIN, set_delay(MIN_TIMELOCK_DELAY_SLOTS - 1)),
+        Err(ProgramError::InvalidArgument)
+    );
+    assert_eq!(execute(TEST_ADMIN, set_delay(2 * delay)), Ok(()));
+    assert_eq!(
+        execute(TEST_ADMIN, set_fee(20)),
+        Err(ContractError::TimelockNotElapsed.into())
+    );
+    set_slot(slot + 2 * delay);
+    assert_eq!(execute(TEST_ADMIN, set_fee(20)), Ok(()));
+    assert_eq!(fee(), 20);
+    set_slot(0);
+}
+
+#[test]
+fn test_token_metadata() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let add = |symbol: &str, metadata: TokenMetadata| {
+        let data = pack(&AdminAddSupportedToken {
+            token: token(symbol),
+            metadata,
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            new_mint_account(symbol),
+            new_vault_account(&program_id, &token(symbol).mint),
+        ];
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let stats = |symbol: &str| {
+        let data = pack(&GetTokenStats {
+            token: token(symbol),
+        });
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        TokenStats::try_from_slice(&data).unwrap()
+    };
+
+    // listed with its metadata, the longest URI accepted
+    let usdc = TokenMetadata {
+        name: "USD Coin".to_string(),
+        decimals: 9,
+        metadata_uri: Some("u".repeat(MAX_METADATA_URI_LEN)),
+    };
+    assert_eq!(add("usdc", usdc.clone()), Ok(()));
+    assert_eq!(stats("usdc").metadata, usdc);
+    let data = pack(&ListSupportedTokens {
+        offset: 0,
+        limit: 10,
+    });
+    process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+    let (_, data) = get_return_data().unwrap();
+    let page = TokenPage::try_from_slice(&data).unwrap();
+    assert_eq!(page.tokens, [(token("usdc"), usdc.clone())]);
+
+    // out of range metadata is refused and lists nothing
+    let invalid = [
+        (
+            TokenMetadata {
+                decimals: MAX_DECIMALS + 1,
+                ..metadata()
+            },
+            ContractError::InvalidDecimals,
+        ),
+        (
+            TokenMetadata {
+                metadata_uri: Some("u".repeat(MAX_METADATA_URI_LEN + 1)),
+                ..metadata()
+            },
+            ContractError::MetadataUriTooLong,
+        ),
+        (
+            TokenMetadata {
+                name: "n".repeat(MAX_TOKEN_NAME_LEN + 1),
+                ..metadata()
+            },
+            ContractError::TokenNameTooLong,
+        ),
+    ];
+    for (metadata, error) in &invalid {
+        assert_eq!(add("ray", metadata.clone()), Err((*error).into()));
+        assert_eq!(validate_token_metadata(metadata), Err(*error));
+    }
+    assert!(!load_state(&state_account)
+        .unwrap()
+        .all_token_balances
+        .contains_key(&token("ray")));
+
+    // updates replace the whole record, checked like on add
+    let update = |symbol: &str, metadata: TokenMetadata| {
+        let data = pack(&AdminUpdateTokenMetadata {
+            token: token(symbol),
+            metadata,
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            new_mint_account(symbol),
+        ];
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let updated = TokenMetadata {
+        name: "USD Coin (bridged)".to_string(),
+        decimals: 9,
+        metadata_uri: None,
+    };
+    assert_eq!(update("usdc", updated.clone()), Ok(()));
+    assert_eq!(stats("usdc").metadata, updated);
+    for (metadata, error) in invalid {
+        assert_eq!(update("usdc", metadata), Err(error.into()));
+    }
+    assert_eq!(stats("usdc").metadata, updated);
+    assert_eq!(
+        update("ray", metadata()),
+        Err(ContractError::TokenNotFound.into())
+    );
+
+    // the metadata goes with the listing
+    let data = pack(&AdminForceDeleteToken {
+        token: token("usdc"),
+        confirm: true,
+    });
+    process_timelocked(&program_id, &admin_accounts, &data).unwrap();
+    assert!(load_state(&state_account)
+        .unwrap()
+        .token_metadata
+        .is_empty());
+}
+
+#[test]
+fn test_token_decimals() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let with_decimals = |decimals| TokenMetadata {
+        decimals,
+        ..metadata()
+    };
+    // the fixture mint has 9 decimals
+    let mint = new_mint_account("usdc");
+    assert_eq!(Mint::unpack(&mint.data.borrow()).unwrap().decimals, 9);
+    let add_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        mint.clone(),
+        new_vault_account(&program_id, mint.key),
+    ];
+    let add = |decimals| {
+        let data = pack(&AdminAddSupportedToken {
+            token: token("usdc"),
+            metadata: with_decimals(decimals),
+        });
+        process_instruction(&program_id, &add_accounts, &data)
+    };
+    let mismatch: ProgramResult = Err(ContractError::DecimalsMismatch.into());
+    take_logs();
+    assert_eq!(add(6), mismatch);
+    assert!(take_logs().contains(&"mint has 9 decimals, not 6".to_string()));
+    assert_eq!(add(9), Ok(()));
+
+    // native SOL has no mint account, its decimals are fixed
+    let native_add_accounts = [
+        state_account.clone(),
+        admin_account(true),
+        new_account(
+            find_vault_address(&program_id, &TokenType::native().mint),
+            false,
+            0,
+            system_program::id(),
+        ),
+        system_program_account(),
+    ];
+    let data = pack(&AdminAddSupportedToken {
+        token: TokenType::native(),
+        metadata: with_decimals(6),
+    });
+    let result = process_instruction(&program_id, &native_add_accounts, &data);
+    assert_eq!(result, mismatch);
+
+    // updates are checked against the mint they pass, which must be the token's
+    let update = |mint_account: &AccountInfo<'static>, decimals| {
+        let data = pack(&AdminUpdateTokenMetadata {
+            token: token("usdc"),
+            metadata: with_decimals(decimals),
+        });
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            mint_account.clone(),
+        ];
+        process_instruction(&program_id, &accounts, &data)
+    };
+    assert_eq!(update(&mint, 6), mismatch);
+    assert_eq!(
+        update(&new_mint_account("ray"), 9),
+        Err(ContractError::MintAccountMismatch.into())
+    );
+    assert_eq!(update(&mint, 9), Ok(()));
+
+    // no precision beyond the token's decimals
+    let set_precision = |display_precision| {
+        let data = pack(&AdminSetDisplayPrecision {
+            token: token("usdc"),
+            display_precision,
+        });
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &data)
+    };
+    assert_eq!(
+        set_precision(Some(10)),
+        Err(ContractError::InvalidDisplayPrecision.into())
+    );
+
+    // with 2 of the 9 decimals shown, deposits are whole multiples of 10^7
+    assert_eq!(set_precision(Some(2)), Ok(()));
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 10_000_000_000);
+    let vault = add_accounts[3].clone();
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit = |amount| {
+        let data = deposit_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let dust: ProgramResult = Err(ContractError::DustDeposit.into());
+    assert_eq!(deposit(10_000_001), dust);
+    assert_eq!(deposit(5), dust);
+    take_logs();
+    assert_eq!(deposit(30_000_000), Ok(()));
+    let data = pack(&BatchDeposit {
+        user,
+        items: vec![(token("usdc"), 1)],
+    });
+    assert_eq!(process_instruction(&program_id, &accounts, &data), dust);
+    assert_eq!(token_amount(&vault), 30_000_000);
+
+    // a precision as high as the decimals lets every amount through, and so does none
+    assert_eq!(set_precision(Some(9)), Ok(()));
+    assert_eq!(deposit(1), Ok(()));
+    assert_eq!(set_precision(Some(0)), Ok(()));
+    assert_eq!(deposit(1), dust);
+    assert_eq!(deposit(1_000_000_000), Ok(()));
+    assert_eq!(set_precision(None), Ok(()));
+    assert_eq!(deposit(3), Ok(()));
+    assert_eq!(token_amount(&vault), 1_030_000_004);
+}
+
+#[test]
+fn test_reward_points() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let start = 1_700_000_000;
+    set_unix_timestamp(start);
+    let set_rate = |points_per_day| {
+        let data = pack(&AdminSetRewardRate {
+            token: token("usdc"),
+            points_per_day,
+        });
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &data)
+    };
+    assert_eq!(set_rate(10), Ok(()));
+    let data = pack(&AdminSetRewardRate {
+        token: token("ray"),
+        points_per_day: 10,
+    });
+    let accounts = [state_account.clone(), admin_account(true)];
+    let result = process_instruction(&program_id, &accounts, &data);
+    assert_eq!(result, Err(ContractError::TokenNotFound.into()));
+
+    // 100 and 300 whole tokens of 9 decimals
+    let users = [Pubkey::new_unique(), Pubkey::new_unique()];
+    for (user, tokens) in users.iter().zip([100, 300]) {
+        let amount = tokens * 1_000_000_000;
+        let user_token_account = new_token_account(mint.key, user, amount);
+        let accounts = user_token_accounts(&state_account, user, &user_token_account, &vault);
+        let data = deposit_data(&state_account, "usdc", *user, amount);
+        process_instruction(&program_id, &accounts, &data).unwrap();
+    }
+    // points are settled for the balance accounts passed, up to now
+    let points = |user: &Pubkey, settle: bool| {
+        let mut accounts = vec![state_account.clone()];
+        if settle {
+            accounts.push(balance_account(&program_id, mint.key, user));
+        }
+        let data = pack(&GetRewardPoints { user: *user });
+        process_instruction(&program_id, &accounts, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        u64::from_le_bytes(data.try_into().unwrap())
+    };
+    assert_eq!(points(&users[0], true), 0);
+
+    // the same period earns in proportion to the balance
+    set_unix_timestamp(start + 2 * 86_400);
+    assert_eq!(points(&users[0], true), 2_000);
+    assert_eq!(points(&users[1], true), 6_000);
+    assert_eq!(points(&users[0], false), 0);
+    // and every second counts
+    set_unix_timestamp(start + 2 * 86_400 + 43_200);
+    assert_eq!(points(&users[0], true), 2_500);
+
+    // a new rate applies from when it's set, what was earned before stays
+    assert_eq!(set_rate(0), Ok(()));
+    set_unix_timestamp(start + 10 * 86_400);
+    assert_eq!(points(&users[0], true), 2_500);
+    assert_eq!(points(&users[1], true), 7_500);
+    assert_eq!(set_rate(20), Ok(()));
+    set_unix_timestamp(start + 11 * 86_400);
+
+    // claiming zeroes the points, including those accrued up to the claim
+    let claim = |user: &Pubkey, is_signer: bool| {
+        let accounts = [
+            state_account.clone(),
+            new_account(*user, is_signer, 0, Pubkey::default()),
+            balance_account(&program_id, mint.key, user),
+        ];
+        process_instruction(&program_id, &accounts, &pack(&ClaimRewards { user: *user }))
+    };
+    assert_eq!(
+        claim(&users[0], false),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    take_events::<RewardsClaimedEvent>();
+    assert_eq!(claim(&users[0], true), Ok(()));
+    let sequence = load_state(&state_account).unwrap().sequence;
+    assert_eq!(
+        take_events::<RewardsClaimedEvent>(),
+        [RewardsClaimedEvent {
+            user: users[0],
+            points: 4_500,
+            sequence,
+        }]
+    );
+    assert_eq!(points(&users[0], true), 0);
+    assert_eq!(points(&users[1], true), 13_500);
+
+    // an emptied balance stops accruing
+    let user_token_account = new_token_account(mint.key, &users[1], 0);
+    let accounts = user_token_accounts(&state_account, &users[1], &user_token_account, &vault);
+    let data = pack(&UserWithdrawAll {
+        token: token("usdc"),
+        user: users[1],
+    });
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    set_unix_timestamp(start + 20 * 86_400);
+    assert_eq!(points(&users[1], true), 13_500);
+    assert!(!load_state(&state_account)
+        .unwrap()
+        .reward_indexes
+        .contains_key(&(*mint.key, users[1])));
+
+    // a whale at the highest rate for a century saturates instead of overflowing
+    let whale = Pubkey::new_unique();
+    let (whale_mint, whale_vault) = add_token(&program_id, &state_account, "whale");
+    let whale_token_account = new_token_account(whale_mint.key, &whale, u64::MAX);
+    let accounts = user_token_accounts(&state_account, &whale, &whale_token_account, &whale_vault);
+    let data = deposit_data(&state_account, "whale", whale, u64::MAX);
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    let data = pack(&AdminSetRewardRate {
+        token: token("whale"),
+        points_per_day: u64::MAX,
+    });
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    process_instruction(&program_id, &admin_accounts, &data).unwrap();
+    set_unix_timestamp(start + 100 * 365 * 86_400);
+    let accounts = [
+        state_account.clone(),
+        balance_account(&program_id, whale_mint.key, &whale),
+    ];
+    let data = pack(&GetRewardPoints { user: whale });
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    let (_, data) = get_return_data().unwrap();
+    assert_eq!(data, u64::MAX.to_le_bytes());
+
+    // the pool goes with the listing
+    let data = pack(&AdminForceDeleteToken {
+        token: token("usdc"),
+        confirm: true,
+    });
+    process_timelocked(&program_id, &admin_accounts, &data).unwrap();
+    let state = load_state(&state_account).unwrap();
+    assert!(!state.reward_pools.contains_key(mint.key));
+    assert!(state.reward_indexes.keys().all(|(m, _)| m != mint.key));
+}
+
+#[test]
+fn test_locked_deposits() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let start = 1_700_000_000;
+    set_unix_timestamp(start);
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 1_000);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let deposit = |amount: u64, lock_until: Option<i64>| {
+        let data = pack(&UserDeposit {
+            token: token("usdc"),
+            user,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, user),
+            lock_until,
+            referrer: None,
+            beneficiary: None,
+            memo: None,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let balance = || {
+        let query = [
+            state_account.clone(),
+            balance_account(&program_id, mint.key, &user),
+        ];
+        let data = pack(&GetBalance {
+            token: token("usdc"),
+            user,
+        });
+        process_instruction(&program_id, &query, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        let balance = UserBalance::try_from_slice(&data).unwrap();
+        (balance.liquid, balance.locked)
+    };
+    let locked: ProgramResult = Err(ContractError::BalanceLocked.into());
+
+    // a lock has to end in the future
+    let invalid: ProgramResult = Err(ContractError::InvalidLockTime.into());
+    assert_eq!(deposit(10, Some(start)), invalid);
+    assert_eq!(deposit(10, Some(start - 1)), invalid);
+
+    // a liquid tranche and two lots with different expiries
+    assert_eq!(deposit(100, None), Ok(()));
+    assert_eq!(deposit(50, Some(start + 100)), Ok(()));
+    assert_eq!(deposit(30, Some(start + 200)), Ok(()));
+    assert_eq!(balance(), (100, 80));
+
+    // only the liquid part can leave, by any instruction
+    assert_eq!(withdraw(101), locked);
+    assert_eq!(withdraw(60), Ok(()));
+    let other = Pubkey::new_unique();
+    let transfer_accounts = [
+        state_account.clone(),
+        accounts[1].clone(),
+        balance_account(&program_id, mint.key, &user),
+        balance_account(&program_id, mint.key, &other),
+        system_program_account(),
+    ];
+    let transfer = pack(&UserTransfer {
+        token: token("usdc"),
+        from: user,
+        to: other,
+        amount: 41,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &transfer_accounts, &transfer),
+        locked
+    );
+    // WithdrawAll takes the liquid part and keeps the balance account for the rest
+    let data = pack(&UserWithdrawAll {
+        token: token("usdc"),
+        user,
+    });
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(token_amount(&user_token_account), 1_000 - 80);
+    assert_eq!(balance(), (0, 80));
+
+    // the first lot unlocks at its expiry, the second one still holds
+    set_unix_timestamp(start + 99);
+    assert_eq!(withdraw(1), locked);
+    set_unix_timestamp(start + 100);
+    assert_eq!(balance(), (50, 30));
+    assert_eq!(withdraw(51), locked);
+    assert_eq!(withdraw(50), Ok(()));
+    let lots = |state_account: &AccountInfo| {
+        load_state(state_account)
+            .unwrap()
+            .locked_lots
+            .get(&(*mint.key, user))
+            .map_or(0, Vec::len)
+    };
+    assert_eq!(lots(&state_account), 1);
+
+    // once everything unlocked the lots are gone and the balance is liquid
+    set_unix_timestamp(start + 200);
+    assert_eq!(balance(), (30, 0));
+    let transfer = pack(&UserTransfer {
+        token: token("usdc"),
+        from: user,
+        to: other,
+        amount: 30,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &transfer_accounts, &transfer),
+        Ok(())
+    );
+    assert_eq!(lots(&state_account), 0);
+
+    // lots of the same expiry are merged, distinct ones are capped
+    for i in 0..MAX_LOCKED_LOTS as i64 {
+        assert_eq!(deposit(1, Some(start + 300 + i)), Ok(()));
+    }
+    assert_eq!(deposit(1, Some(start + 300)), Ok(()));
+    assert_eq!(
+        deposit(1, Some(start + 1_000)),
+        Err(ContractError::TooManyLockedLots.into())
+    );
+    assert_eq!(lots(&state_account), MAX_LOCKED_LOTS);
+    assert_eq!(balance(), (0, MAX_LOCKED_LOTS as u64 + 1));
+
+    // the lots go with the listing
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let data = pack(&AdminForceDeleteToken {
+        token: token("usdc"),
+        confirm: true,
+    });
+    process_timelocked(&program_id, &admin_accounts, &data).unwrap();
+    assert!(load_state(&state_account).unwrap().locked_lots.is_empty());
+}
+
+#[test]
+fn test_vesting_deposits() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let start = 1_700_000_000;
+    set_unix_timestamp(start - 100);
+    let user = Pubkey::new_unique();
+    let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, 2_000);
+    let mut admin_accounts =
+        user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, &vault);
+    admin_accounts[6] = balance_account(&program_id, mint.key, &user);
+    admin_accounts[8] = receipts_account(&program_id, &user);
+    let vest = |accounts: &[AccountInfo<'static>], amount: u64, start_ts: i64, duration_secs| {
+        let data = pack(&AdminDepositVested {
+            token: token("usdc"),
+            user,
+            amount,
+            start_ts,
+            duration_secs,
+        });
+        process_instruction(&program_id, accounts, &data)
+    };
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let withdraw = |amount: u64| {
+        let data = withdraw_data(&state_account, "usdc", user, amount);
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let balance = || {
+        let query = [
+            state_account.clone(),
+            balance_account(&program_id, mint.key, &user),
+        ];
+        let data = pack(&GetBalance {
+            token: token("usdc"),
+            user,
+        });
+        process_instruction(&program_id, &query, &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        let balance = UserBalance::try_from_slice(&data).unwrap();
+        (balance.liquid, balance.locked)
+    };
+    let locked: ProgramResult = Err(ContractError::BalanceLocked.into());
+
+    // only the admin funds a schedule, from its own token account
+    assert_eq!(
+        vest(&accounts, 1_001, start, 1_000),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(vest(&admin_accounts, 1_001, start, 1_000), Ok(()));
+    assert_eq!(token_amount(&admin_token_account), 2_000 - 1_001);
+
+    // nothing vested before the start, a liquid deposit next to it stays withdrawable
+    let data = deposit_data(&state_account, "usdc", user, 100);
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    assert_eq!(balance(), (100, 1_001));
+    assert_eq!(withdraw(101), locked);
+    assert_eq!(withdraw(100), Ok(()));
+    set_unix_timestamp(start);
+    assert_eq!(withdraw(1), locked);
+
+    // half way the vested part is 1001 / 2 rounded down
+    set_unix_timestamp(start + 500);
+    assert_eq!(balance(), (500, 501));
+    assert_eq!(withdraw(501), locked);
+    assert_eq!(withdraw(500), Ok(()));
+    assert_eq!(withdraw(1), locked);
+
+    // a second schedule vests on its own, what was withdrawn counts against both
+    assert_eq!(vest(&admin_accounts, 200, start + 500, 100), Ok(()));
+    set_unix_timestamp(start + 550);
+    assert_eq!(balance(), (150, 551));
+    assert_eq!(withdraw(151), locked);
+
+    // fully vested after the end, the schedules are dropped
+    set_unix_timestamp(start + 1_000);
+    assert_eq!(balance(), (701, 0));
+    assert_eq!(withdraw(701), Ok(()));
+    assert_eq!(token_amount(&user_token_account), 100 + 1_201);
+    assert!(load_state(&state_account)
+        .unwrap()
+        .vesting_schedules
+        .is_empty());
+
+    // distinct schedules are capped
+    for _ in 0..MAX_VESTING_SCHEDULES {
+        assert_eq!(vest(&admin_accounts, 1, start + 2_000, 10), Ok(()));
+    }
+    assert_eq!(
+        vest(&admin_accounts, 1, start + 2_000, 10),
+        Err(ContractError::TooManyVestingSchedules.into())
+    );
+}
+
+#[test]
+fn test_referrals() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let referrer = Pubkey::new_unique();
+    let deposit = |user: &Pubkey, amount: u64, referrer: Option<Pubkey>| {
+        let user_token_account = new_token_account(mint.key, user, amount);
+        let accounts = user_token_accounts(&state_account, user, &user_token_account, &vault);
+        let data = pack(&UserDeposit {
+            token: token("usdc"),
+            user: *user,
+            amount,
+            nonce: nonce_of(&state_account, mint.key, *user),
+            lock_until: None,
+            referrer,
+            beneficiary: None,
+            memo: None,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let stats = |referrer: Pubkey| {
+        let data = pack(&GetReferralStats { referrer });
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        ReferralStats::try_from_slice(&data).unwrap()
+    };
+    let referred = |user: &Pubkey| {
+        take_events::<DepositEvent>()
+            .into_iter()
+            .filter(|event| &event.user == user)
+            .map(|event| event.referrer)
+            .collect::<Vec<_>>()
+    };
+
+    // nobody refers itself
+    let alice = Pubkey::new_unique();
+    assert_eq!(
+        deposit(&alice, 100, Some(alice)),
+        Err(ContractError::SelfReferral.into())
+    );
+
+    // the first deposit binds the referrer, a later one can't change it
+    take_events::<DepositEvent>();
+    assert_eq!(deposit(&alice, 100, Some(referrer)), Ok(()));
+    assert_eq!(deposit(&alice, 50, Some(Pubkey::new_unique())), Ok(()));
+    assert_eq!(deposit(&alice, 20, None), Ok(()));
+    assert_eq!(referred(&alice), [Some(referrer); 3]);
+    assert_eq!(
+        stats(referrer),
+        ReferralStats {
+            volume: 170,
+            referees: 1
+        }
+    );
+
+    // a first deposit without a referrer binds none
+    let bob = Pubkey::new_unique();
+    assert_eq!(deposit(&bob, 10, None), Ok(()));
+    assert_eq!(deposit(&bob, 10, Some(referrer)), Ok(()));
+    assert_eq!(referred(&bob), [None, None]);
+
+    // volume adds up across referees
+    let carol = Pubkey::new_unique();
+    assert_eq!(deposit(&carol, 30, Some(referrer)), Ok(()));
+    assert_eq!(
+        stats(referrer),
+        ReferralStats {
+            volume: 200,
+            referees: 2
+        }
+    );
+    assert_eq!(
+        stats(alice),
+        ReferralStats {
+            volume: 0,
+            referees: 0
+        }
+    );
+}
+
+#[test]
+fn test_snapshot_state() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    set_unix_timestamp(1_700_000_000);
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 150);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    let data = deposit_data(&state_account, "usdc", user, 100);
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    let snapshot = |destination: &AccountInfo<'static>| {
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            destination.clone(),
+            balance_account(&program_id, mint.key, &user),
+        ];
+        process_instruction(&program_id, &accounts, &pack(&AdminSnapshotState))
+    };
+    let invalid: ProgramResult = Err(ContractError::InvalidSnapshotAccount.into());
+
+    // never over the state or a balance account, nor an account the program can't write
+    assert_eq!(snapshot(&state_account), invalid);
+    assert_eq!(
+        snapshot(&balance_account(&program_id, mint.key, &user)),
+        invalid
+    );
+    let foreign = new_account(
+        Pubkey::new_unique(),
+        false,
+        STATE_ACCOUNT_SIZE,
+        Pubkey::new_unique(),
+    );
+    assert_eq!(snapshot(&foreign), Err(ContractError::WrongOwner.into()));
+    let small = new_account(Pubkey::new_unique(), false, 64, program_id);
+    assert_eq!(
+        snapshot(&small),
+        Err(ContractError::SnapshotBufferTooSmall.into())
+    );
+    let destination = new_account(Pubkey::new_unique(), false, STATE_ACCOUNT_SIZE, program_id);
+    // admin only
+    let user_accounts = [
+        state_account.clone(),
+        new_account(user, true, 0, Pubkey::default()),
+        destination.clone(),
+    ];
+    assert_eq!(
+        process_instruction(&program_id, &user_accounts, &pack(&AdminSnapshotState)),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    assert_eq!(snapshot(&destination), Ok(()));
+    let taken = load_full_state(&state_account).unwrap();
+
+    // the live state moves on, the snapshot doesn't
+    let data = deposit_data(&state_account, "usdc", user, 50);
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    let data = pack(&AdminSetMaxTokens { max_tokens: 3 });
+    process_instruction(&program_id, &admin_accounts, &data).unwrap();
+
+    let data = destination.data.borrow();
+    assert_eq!(data[0], STATE_VERSION);
+    assert_eq!(data[1..9], 1_700_000_000i64.to_le_bytes());
+    let decoded = decode_snapshot(&data).unwrap();
+    assert_eq!(
+        decoded.all_token_balances[&token("usdc")].balances[&user],
+        100
+    );
+    assert_eq!(decoded.nonces, taken.nonces);
+    assert_eq!(decoded.max_tokens, taken.max_tokens);
+    assert_ne!(decoded.max_tokens, 3);
+    assert_eq!(
+        decoded.sequence + 2,
+        load_state(&state_account).unwrap().sequence
+    );
+
+    // a truncated export doesn't decode
+    assert!(decode_snapshot(&data[..SNAPSHOT_HEADER_LEN + 10]).is_err());
+    assert!(decode_snapshot(&[]).is_err());
+}
+
+#[test]
+fn test_audit_log() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let audit_log = |offset: u32, limit: u32| {
+        let data = pack(&GetAuditLog { offset, limit });
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        AuditPage::try_from_slice(&data).unwrap()
+    };
+
+    // the newest entry is the deposit, with its signer, token and amount
+    set_slot(42);
+    let user = Pubkey::new_unique();
+    let user_token_account = new_token_account(mint.key, &user, 100);
+    let accounts = user_token_accounts(&state_account, &user, &user_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", user, 60),
+    )
+    .unwrap();
+    let page = audit_log(0, 1);
+    assert_eq!(page.entries.len(), 1);
+    let entry = &page.entries[0];
+    assert_eq!(
+        entry.sequence,
+        load_full_state(&state_account).unwrap().sequence
+    );
+    assert_eq!(entry.instruction_tag, 6);
+    assert_eq!(entry.actor, user);
+    assert_eq!(entry.token_symbol_hash, token_symbol_hash("USDC"));
+    assert_eq!(entry.token_symbol_hash, token_symbol_hash("usdc"));
+    assert_eq!(entry.amount, 60);
+    assert_eq!(entry.slot, 42);
+
+    // a failed instruction isn't logged
+    let total = page.total;
+    assert_eq!(
+        process_instruction(
+            &program_id,
+            &accounts,
+            &deposit_data(&state_account, "usdc", user, 1_000),
+        ),
+        Err(TokenError::InsufficientFunds.into())
+    );
+    assert_eq!(audit_log(0, 1), page);
+    assert_eq!(audit_log(0, 0).total, total);
+
+    // past the capacity the oldest entries are dropped, pages run newest first
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    for max_tokens in 0..AUDIT_LOG_CAPACITY as u32 + 5 {
+        set_slot(100 + u64::from(max_tokens));
+        process_instruction(
+            &program_id,
+            &admin_accounts,
+            &pack(&AdminSetMaxTokens {
+                max_tokens: 10 + max_tokens,
+            }),
+        )
+        .unwrap();
+    }
+    let sequence = load_full_state(&state_account).unwrap().sequence;
+    let mut entries = Vec::new();
+    loop {
+        let page = audit_log(entries.len() as u32, u32::MAX);
+        assert_eq!(page.total, AUDIT_LOG_CAPACITY as u32);
+        assert!(page.entries.len() <= MAX_AUDIT_ENTRIES_PER_PAGE as usize);
+        if page.entries.is_empty() {
+            break;
+        }
+        entries.extend(page.entries);
+    }
+    assert_eq!(entries.len(), AUDIT_LOG_CAPACITY);
+    for (age, entry) in entries.iter().enumerate() {
+        assert_eq!(entry.sequence, sequence - age as u64);
+        assert_eq!(entry.actor, TEST_ADMIN);
+        assert_eq!(entry.token_symbol_hash, 0);
+        assert_eq!(
+            entry.amount,
+            10 + AUDIT_LOG_CAPACITY as u64 + 4 - age as u64
+        );
+    }
+    assert_eq!(entries[0].slot, 100 + AUDIT_LOG_CAPACITY as u64 + 4);
+
+    // queries aren't logged, an offset past the end gives an empty page
+    let page = audit_log(AUDIT_LOG_CAPACITY as u32, 4);
+    assert!(page.entries.is_empty());
+    assert_eq!(audit_log(0, 1).entries[0], entries[0]);
+}
+
+#[test]
+fn test_close_user_balance() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 100);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let alice_balance = accounts[6].clone();
+    let close = |signer: &AccountInfo<'static>, user: Pubkey| {
+        let accounts = [
+            state_account.clone(),
+            signer.clone(),
+            balance_account(&program_id, mint.key, &user),
+        ];
+        process_instruction(
+            &program_id,
+            &accounts,
+            &pack(&CloseUserBalance {
+                token: token("usdc"),
+                user,
+            }),
+        )
+    };
+    let entry = |user: &Pubkey| {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+    };
+    let holders = || load_full_state(&state_account).unwrap().holder_counts[mint.key];
+    let balance_rent = Rent::default().minimum_balance(BALANCE_ACCOUNT_SIZE);
+
+    // withdrawing the whole balance drops the entry and closes the account
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 100),
+    )
+    .unwrap();
+    assert_eq!(holders(), 1);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &withdraw_data(&state_account, "usdc", alice, 100),
+    )
+    .unwrap();
+    assert_eq!(entry(&alice), None);
+    assert_eq!(alice_balance.owner, &system_program::id());
+    assert_eq!(alice_balance.lamports(), 0);
+    assert_eq!(holders(), 0);
+
+    // a later deposit starts over
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 60),
+    )
+    .unwrap();
+    assert_eq!(entry(&alice), Some(60));
+    assert_eq!(alice_balance.owner, &program_id);
+    assert_eq!(holders(), 1);
+
+    // only a zero balance is closed
+    let alice_signer = accounts[1].clone();
+    assert_eq!(
+        close(&alice_signer, alice),
+        Err(ContractError::BalanceNotZero.into())
+    );
+
+    // a transfer of everything leaves a zero entry behind
+    let transfer_accounts = [
+        state_account.clone(),
+        alice_signer.clone(),
+        alice_balance.clone(),
+        balance_account(&program_id, mint.key, &bob),
+        system_program_account(),
+    ];
+    let transfer = pack(&UserTransfer {
+        token: token("usdc"),
+        from: alice,
+        to: bob,
+        amount: 60,
+    });
+    process_instruction(&program_id, &transfer_accounts, &transfer).unwrap();
+    assert_eq!(entry(&alice), Some(0));
+    assert_eq!(holders(), 1);
+
+    // user or admin
+    let stranger = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    assert_eq!(
+        close(&stranger, alice),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    let bob_signer = new_account(bob, true, 0, Pubkey::default());
+    assert_eq!(
+        close(&bob_signer, alice),
+        Err(ProgramError::MissingRequiredSignature)
+    );
+    let lamports = alice_signer.lamports();
+    assert_eq!(close(&alice_signer, alice), Ok(()));
+    assert_eq!(entry(&alice), None);
+    assert_eq!(alice_balance.lamports(), 0);
+    assert_eq!(alice_signer.lamports(), lamports + balance_rent);
+    assert_eq!(holders(), 1);
+    assert_eq!(
+        close(&admin_account(true), bob),
+        Err(ContractError::BalanceNotZero.into())
+    );
+
+    // the balance account has to be passed
+    let accounts = [state_account.clone(), admin_account(true)];
+    let data = pack(&CloseUserBalance {
+        token: token("usdc"),
+        user: alice,
+    });
+    assert_eq!(
+        process_instruction(&program_id, &accounts, &data),
+        Err(ContractError::MissingBalanceAccount.into())
+    );
+
+    // a deposit after closing recreates the account
+    process_instruction(
+        &program_id,
+        &user_token_accounts(&state_account, &alice, &alice_token_account, &vault),
+        &deposit_data(&state_account, "usdc", alice, 40),
+    )
+    .unwrap();
+    assert_eq!(entry(&alice), Some(40));
+    assert_eq!(holders(), 2);
+}
+
+#[test]
+fn test_emergency_sweep() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let users: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let mut transfer_accounts = Vec::new();
+    for (i, user) in users.iter().enumerate() {
+        let user_token_account = new_token_account(mint.key, user, 1_000);
+        let accounts = user_token_accounts(&state_account, user, &user_token_account, &vault);
+        let amount = 100 * (i as u64 + 1);
+        process_instruction(
+            &program_id,
+            &accounts,
+            &deposit_data(&state_account, "usdc", *user, amount),
+        )
+        .unwrap();
+        transfer_accounts = accounts[3..6].to_vec();
+    }
+    // the last user's request is owed too
+    let request_accounts = [
+        state_account.clone(),
+        new_account(users[2], true, 0, Pubkey::default()),
+        balance_account(&program_id, mint.key, &users[2]),
+    ];
+    let data = pack(&UserRequestWithdraw {
+        token: token("usdc"),
+        user: users[2],
+        amount: 50,
+    });
+    process_instruction(&program_id, &request_accounts, &data).unwrap();
+    // tokens sent to the vault directly are swept with the rest
+    let mut vault_state = TokenAccount::unpack(&vault.data.borrow()).unwrap();
+    vault_state.amount += 10;
+    vault_state.pack_into_slice(&mut vault.data.borrow_mut());
+    let rescue = new_token_account(mint.key, &Pubkey::new_unique(), 0);
+    let sweep = |holders: &[Pubkey]| {
+        let mut accounts = vec![state_account.clone(), admin_account(true), rescue.clone()];
+        accounts.extend(transfer_accounts.clone());
+        accounts.extend(
+            holders
+                .iter()
+                .map(|user| balance_account(&program_id, mint.key, user)),
+        );
+        let data = pack(&AdminEmergencySweep {
+            token: token("usdc"),
+            destination: *rescue.key,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+
+    // only while paused
+    assert_eq!(sweep(&users), Err(ContractError::ContractNotPaused.into()));
+    let pause = pack(&AdminPause {
+        withdrawals_only: false,
+    });
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    process_instruction(&program_id, &admin_accounts, &pause).unwrap();
+
+    // every holder has to be passed
+    assert_eq!(
+        sweep(&users[..2]),
+        Err(ContractError::SweepIncomplete.into())
+    );
+    assert_eq!(token_amount(&rescue), 0);
+
+    take_events::<SweepEvent>();
+    assert_eq!(sweep(&users), Ok(()));
+    assert_eq!(token_amount(&vault), 0);
+    assert_eq!(token_amount(&rescue), 610);
+    let state = load_full_state(&state_account).unwrap();
+    let entry = &state.all_token_balances[&token("usdc")];
+    assert!(entry.balances.values().all(|amount| *amount == 0));
+    assert!(entry.withdraw_requests.is_empty());
+    assert_eq!(entry.total_deposited, 0);
+    assert_eq!(state.holder_counts[mint.key], 0);
+    let mut events = take_events::<SweepEvent>();
+    events.sort_by_key(|event| event.amount);
+    let swept: Vec<(Pubkey, u64)> = events
+        .iter()
+        .map(|event| (event.user, event.amount))
+        .collect();
+    assert_eq!(swept, [(users[0], 100), (users[1], 200), (users[2], 300)]);
+    assert!(events
+        .iter()
+        .all(|event| event.destination == *rescue.key && event.token_symbol == "USDC"));
+
+    // the listing stays, emptied
+    assert_eq!(sweep(&[]), Ok(()));
+    assert!(take_events::<SweepEvent>().is_empty());
+}
+
+#[test]
+fn test_token_2022() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    // 1% of every transfer is withheld
+    let mint = new_mint_2022_account("t22", 100, false);
+    let (vault_authority, _) = find_vault_authority(&program_id, mint.key);
+    let vault_address =
+        find_vault_address_with_program_id(&program_id, mint.key, &spl_token_2022::id());
+    let vault = new_token_2022_account_at(vault_address, mint.key, &vault_authority, 0);
+    let add = |symbol: &str, mint: &AccountInfo<'static>, vault: &AccountInfo<'static>| {
+        let accounts = [
+            state_account.clone(),
+            admin_account(true),
+            mint.clone(),
+            vault.clone(),
+        ];
+        let data = pack(&AdminAddSupportedToken {
+            token: token(symbol),
+            metadata: metadata(),
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+
+    // extensions that let tokens move on their own are refused
+    let non_transferable = new_mint_2022_account("nt", 0, true);
+    let nt_vault = new_token_2022_account_at(
+        find_vault_address_with_program_id(
+            &program_id,
+            non_transferable.key,
+            &spl_token_2022::id(),
+        ),
+        non_transferable.key,
+        &find_vault_authority(&program_id, non_transferable.key).0,
+        0,
+    );
+    assert_eq!(
+        add("nt", &non_transferable, &nt_vault),
+        Err(ContractError::UnsupportedMintExtension.into())
+    );
+
+    // the vault is the Token-2022 associated token account
+    let legacy_vault = new_token_2022_account_at(
+        find_vault_address(&program_id, mint.key),
+        mint.key,
+        &vault_authority,
+        0,
+    );
+    assert_eq!(
+        add("t22", &mint, &legacy_vault),
+        Err(ContractError::InvalidVaultAccount.into())
+    );
+    assert_eq!(add("t22", &mint, &vault), Ok(()));
+
+    // the transfer fee never reaches the vault, only what does is credited
+    let user = Pubkey::new_unique();
+    let user_token_account =
+        new_token_2022_account_at(Pubkey::new_unique(), mint.key, &user, 1_000);
+    let accounts = vec![
+        state_account.clone(),
+        user_signer(&user),
+        user_token_account.clone(),
+        vault.clone(),
+        new_account(vault_authority, false, 0, Pubkey::default()),
+        new_account(spl_token_2022::id(), false, 0, Pubkey::default()),
+        mint.clone(),
+        balance_account(&program_id, mint.key, &user),
+        system_program_account(),
+        receipts_account(&program_id, &user),
+    ];
+    let balance = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("t22")].balances[&user]
+    };
+    take_events::<DepositEvent>();
+    let data = deposit_data(&state_account, "t22", user, 1_000);
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(token_2022_amount(&user_token_account), 0);
+    assert_eq!(token_2022_amount(&vault), 990);
+    assert_eq!(balance(), 990);
+    let events = take_events::<DepositEvent>();
+    assert_eq!((events[0].amount, events[0].new_balance), (1_000, 990));
+    let state = load_state(&state_account).unwrap();
+    assert_eq!(state.all_token_balances[&token("t22")].total_deposited, 990);
+
+    // the legacy program can't move the token, nor can transfers skip the mint
+    let mut legacy_accounts = accounts.clone();
+    legacy_accounts[5] = token_program_account();
+    legacy_accounts.remove(6);
+    let data = withdraw_data(&state_account, "t22", user, 100);
+    assert_eq!(
+        process_instruction(&program_id, &legacy_accounts, &data),
+        Err(ContractError::WrongOwner.into())
+    );
+    let mut wrong_mint = accounts.clone();
+    wrong_mint[6] = new_mint_account("usdc");
+    assert_eq!(
+        process_instruction(&program_id, &wrong_mint, &data),
+        Err(ContractError::MintAccountMismatch.into())
+    );
+
+    // the whole amount leaves the ledger and the vault, the fee is withheld from the payout
+    take_logs();
+    let data = withdraw_data(&state_account, "t22", user, 500);
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(balance(), 490);
+    assert_eq!(token_2022_amount(&vault), 490);
+    assert_eq!(token_2022_amount(&user_token_account), 495);
+    assert!(take_logs().contains(&"transfer fee of 5 withheld from the payout".to_string()));
+    let state = load_state(&state_account).unwrap();
+    assert_eq!(state.all_token_balances[&token("t22")].total_deposited, 490);
+}
+
+#[test]
+fn test_multicall() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 100);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    process_instruction(
+        &program_id,
+        &accounts,
+        &deposit_data(&state_account, "usdc", alice, 100),
+    )
+    .unwrap();
+    let admin_accounts = vec![state_account.clone(), admin_account(true)];
+    let alice_accounts = vec![
+        state_account.clone(),
+        accounts[1].clone(),
+        accounts[6].clone(),
+        balance_account(&program_id, mint.key, &bob),
+        system_program_account(),
+    ];
+    let multicall = |calls: Vec<(ContractInstruction, &Vec<AccountInfo<'static>>)>| {
+        let accounts: Vec<AccountInfo> = calls
+            .iter()
+            .flat_map(|(_, accounts)| accounts.iter().cloned())
+            .collect();
+        let calls = calls.into_iter().map(|(call, _)| call).collect();
+        process_instruction(&program_id, &accounts, &pack(&Multicall { calls }))
+    };
+    let min_deposit =
+        || load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")].min_deposit;
+    let balance_of = |user: &Pubkey| {
+        let state = load_full_state(&state_account).unwrap();
+        state.all_token_balances[&token("usdc")]
+            .balances
+            .get(user)
+            .copied()
+    };
+    let sequence = || load_full_state(&state_account).unwrap().sequence;
+    let transfer = |amount: u64| UserTransfer {
+        token: token("usdc"),
+        from: alice,
+        to: bob,
+        amount,
+    };
+
+    // every call is applied and takes its own sequence number
+    let before = sequence();
+    let result = multicall(vec![
+        (
+            AdminSetDepositCap {
+                token: token("usdc"),
+                cap: Some(1000),
+            },
+            &admin_accounts,
+        ),
+        (
+            AdminSetMinDeposit {
+                token: token("usdc"),
+                min_deposit: 5,
+            },
+            &admin_accounts,
+        ),
+        (
+            AdminSetMaxUserBalance {
+                token: token("usdc"),
+                max_user_balance: Some(500),
+            },
+            &admin_accounts,
+        ),
+    ]);
+    assert_eq!(result, Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    let usdc = &state.all_token_balances[&token("usdc")];
+    assert_eq!(
+        (usdc.deposit_cap, usdc.min_deposit, usdc.max_user_balance),
+        (Some(1000), 5, Some(500))
+    );
+    assert_eq!(state.sequence, before + 3);
+    assert_eq!(
+        state
+            .audit_log
+            .iter()
+            .rev()
+            .take(3)
+            .map(|entry| entry.sequence)
+            .collect::<Vec<_>>(),
+        [before + 3, before + 2, before + 1]
+    );
+
+    // the third call fails, the first two are undone with it
+    take_logs();
+    let result = multicall(vec![
+        (
+            AdminSetMinDeposit {
+                token: token("usdc"),
+                min_deposit: 1,
+            },
+            &admin_accounts,
+        ),
+        (transfer(30), &alice_accounts),
+        (transfer(500), &alice_accounts),
+    ]);
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert!(take_logs()
+        .iter()
+        .any(|log| log == "multicall call 2 failed"));
+    assert_eq!(min_deposit(), 5);
+    assert_eq!(balance_of(&alice), Some(100));
+    assert_eq!(balance_of(&bob), None);
+    assert_eq!(sequence(), before + 3);
+
+    // each call is authorized on its own
+    let result = multicall(vec![
+        (transfer(30), &alice_accounts),
+        (
+            AdminSetMinDeposit {
+                token: token("usdc"),
+                min_deposit: 1,
+            },
+            &alice_accounts,
+        ),
+    ]);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    assert_eq!(balance_of(&bob), None);
+
+    // a mixed batch goes through as a whole
+    let result = multicall(vec![
+        (transfer(30), &alice_accounts),
+        (
+            AdminSetMinDeposit {
+                token: token("usdc"),
+                min_deposit: 1,
+            },
+            &admin_accounts,
+        ),
+    ]);
+    assert_eq!(result, Ok(()));
+    assert_eq!(balance_of(&bob), Some(30));
+    assert_eq!(min_deposit(), 1);
+
+    // no nesting, no queries
+    let nested = Multicall { calls: vec![] };
+    for call in [nested, GetSequence] {
+        let result = multicall(vec![(call, &admin_accounts)]);
+        assert_eq!(result, Err(ContractError::CallNotBatchable.into()));
+    }
+
+    // one account list per call, at most 8 calls
+    let result = process_instruction(
+        &program_id,
+        &admin_accounts,
+        &pack(&Multicall {
+            calls: vec![AdminUnpause, AdminUnpause],
+        }),
+    );
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    let calls = (0..9).map(|_| (AdminUnpause, &admin_accounts)).collect();
+    assert_eq!(multicall(calls), Err(ContractError::BatchTooLarge.into()));
+}
+
+#[test]
+fn test_merkle_airdrop() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let users: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+    let amounts = [100, 200, 300, 400];
+    // the tree is built off-chain, two levels over four leaves
+    let leaves: Vec<[u8; 32]> = users
+        .iter()
+        .zip(amounts)
+        .map(|(user, amount)| airdrop_leaf(user, amount))
+        .collect();
+    let nodes = [
+        merkle_parent(&leaves[0], &leaves[1]),
+        merkle_parent(&leaves[2], &leaves[3]),
+    ];
+    let root = merkle_parent(&nodes[0], &nodes[1]);
+    let proofs = [
+        vec![leaves[1], nodes[1]],
+        vec![leaves[0], nodes[1]],
+        vec![leaves[3], nodes[0]],
+        vec![leaves[2], nodes[0]],
+    ];
+    let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, 2_000);
+    let admin_accounts =
+        user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, &vault);
+    let set_root = |total: u64| {
+        let data = pack(&AdminSetMerkleRoot {
+            token: token("usdc"),
+            root,
+            total,
+        });
+        process_instruction(&program_id, &admin_accounts[..6], &data)
+    };
+    let claim = |index: usize, amount: u64, proof: &[[u8; 32]]| {
+        let user = users[index];
+        let accounts = [
+            state_account.clone(),
+            new_account(Pubkey::new_unique(), true, 0, Pubkey::default()),
+            balance_account(&program_id, mint.key, &user),
+            system_program_account(),
+        ];
+        let data = pack(&ClaimAirdrop {
+            token: token("usdc"),
+            user,
+            amount,
+            proof: proof.to_vec(),
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let balance_of = |index: usize| {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")]
+            .balances
+            .get(&users[index])
+            .copied()
+    };
+    let total_deposited = || {
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")].total_deposited
+    };
+
+    assert_eq!(
+        claim(0, 100, &proofs[0]),
+        Err(ContractError::NoAirdrop.into())
+    );
+    // the admin funds the whole airdrop up front
+    assert_eq!(set_root(1_000), Ok(()));
+    assert_eq!(token_amount(&admin_token_account), 1_000);
+    assert_eq!(token_amount(&vault), 1_000);
+    assert_eq!(total_deposited(), 1_000);
+
+    take_events::<AirdropClaimedEvent>();
+    assert_eq!(claim(0, 100, &proofs[0]), Ok(()));
+    let sequence = load_state(&state_account).unwrap().sequence;
+    assert_eq!(
+        take_events::<AirdropClaimedEvent>(),
+        [AirdropClaimedEvent {
+            token_symbol: "USDC".to_string(),
+            user: users[0],
+            amount: 100,
+            new_balance: 100,
+            sequence,
+        }]
+    );
+    assert_eq!(claim(2, 300, &proofs[2]), Ok(()));
+    assert_eq!(balance_of(0), Some(100));
+    assert_eq!(balance_of(2), Some(300));
+    // claims only move the amount from the airdrop to a balance
+    assert_eq!(total_deposited(), 1_000);
+    assert_eq!(
+        claim(0, 100, &proofs[0]),
+        Err(ContractError::AirdropAlreadyClaimed.into())
+    );
+
+    // forged proofs: another amount, someone else's proof, a made-up sibling
+    for result in [
+        claim(1, 2_000, &proofs[1]),
+        claim(1, 200, &proofs[0]),
+        claim(1, 200, &[[7; 32], nodes[1]]),
+    ] {
+        assert_eq!(result, Err(ContractError::InvalidMerkleProof.into()));
+    }
+    assert_eq!(balance_of(1), None);
+
+    // a new airdrop takes over what is left of the old one, 600, and can't be smaller
+    assert_eq!(set_root(500), Err(ProgramError::InvalidArgument));
+    assert_eq!(set_root(700), Ok(()));
+    assert_eq!(token_amount(&admin_token_account), 900);
+    assert_eq!(claim(3, 400, &proofs[3]), Ok(()));
+    assert_eq!(claim(2, 300, &proofs[2]), Ok(()));
+    // claims never add up to more than the total
+    assert_eq!(
+        claim(1, 200, &proofs[1]),
+        Err(ContractError::AirdropExhausted.into())
+    );
+    assert_eq!(balance_of(2), Some(600));
+    assert_eq!(total_deposited(), 1_100);
+
+    // an airdropped balance is withdrawn like any other
+    let user_token_account = new_token_account(mint.key, &users[0], 0);
+    let accounts = user_token_accounts(&state_account, &users[0], &user_token_account, &vault);
+    let data = withdraw_data(&state_account, "usdc", users[0], 100);
+    assert_eq!(process_instruction(&program_id, &accounts, &data), Ok(()));
+    assert_eq!(token_amount(&user_token_account), 100);
+}
+
+#[test]
+fn test_rate_limit() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    // the harness keeps the token transfers of deposits that fail, so alice needs plenty
+    let alice_token_account = new_token_account(mint.key, &alice, 1000);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let set_rate_limit = |max_ops: u32, window_slots: u64| {
+        process_instruction(
+            &program_id,
+            &[state_account.clone(), admin_account(true)],
+            &pack(&AdminSetRateLimit {
+                max_ops,
+                window_slots,
+            }),
+        )
+    };
+    let deposit = || {
+        process_instruction(
+            &program_id,
+            &accounts,
+            &deposit_data(&state_account, "usdc", alice, 10),
+        )
+    };
+    let transfer = || {
+        process_instruction(
+            &program_id,
+            &[
+                accounts[0].clone(),
+                accounts[1].clone(),
+                accounts[6].clone(),
+                balance_account(&program_id, mint.key, &bob),
+                system_program_account(),
+            ],
+            &pack(&UserTransfer {
+                token: token("usdc"),
+                from: alice,
+                to: bob,
+                amount: 1,
+            }),
+        )
+    };
+    let buckets = || load_full_state(&state_account).unwrap().rate_buckets[&alice];
+    let rate_limited: ProgramResult = Err(ContractError::RateLimited.into());
+
+    assert_eq!(set_rate_limit(3, 0), Err(ProgramError::InvalidArgument));
+    assert_eq!(set_rate_limit(3, 10), Ok(()));
+
+    set_slot(100);
+    for _ in 0..2 {
+        assert_eq!(deposit(), Ok(()));
+    }
+    set_slot(105);
+    assert_eq!(transfer(), Ok(()));
+    // the fourth operation of the window fails without a trace
+    let before = borsh::to_vec(&load_full_state(&state_account).unwrap()).unwrap();
+    assert_eq!(deposit(), rate_limited);
+    set_slot(109);
+    assert_eq!(transfer(), rate_limited);
+    assert_eq!(
+        borsh::to_vec(&load_full_state(&state_account).unwrap()).unwrap(),
+        before
+    );
+    assert_eq!(
+        buckets(),
+        RateBuckets {
+            bucket_start: 100,
+            count: 3,
+            previous_count: 0,
+        }
+    );
+
+    // the bucket ended but the window still covers all of it
+    set_slot(110);
+    assert_eq!(deposit(), rate_limited);
+    // half of it, rounded up 2 of its operations
+    set_slot(115);
+    assert_eq!(deposit(), Ok(()));
+    assert_eq!(deposit(), rate_limited);
+    assert_eq!(
+        buckets(),
+        RateBuckets {
+            bucket_start: 110,
+            count: 1,
+            previous_count: 3,
+        }
+    );
+    // a bucket later only the one operation of the last bucket still counts
+    set_slot(127);
+    assert_eq!(deposit(), Ok(()));
+    assert_eq!(transfer(), Ok(()));
+    assert_eq!(deposit(), rate_limited);
+    assert_eq!(
+        buckets(),
+        RateBuckets {
+            bucket_start: 120,
+            count: 2,
+            previous_count: 1,
+        }
+    );
+
+    // the admin is never limited
+    let admin_token_account = new_token_account(mint.key, &TEST_ADMIN, 100);
+    let admin_accounts =
+        user_token_accounts(&state_account, &TEST_ADMIN, &admin_token_account, &vault);
+    for _ in 0..5 {
+        let data = deposit_data(&state_account, "usdc", TEST_ADMIN, 10);
+        assert_eq!(
+            process_instruction(&program_id, &admin_accounts, &data),
+            Ok(())
+        );
+    }
+    assert!(!load_full_state(&state_account)
+        .unwrap()
+        .rate_buckets
+        .contains_key(&TEST_ADMIN));
+
+    // bob's buckets are dropped once the window moved past both, by anyone's operation
+    let bob_token_account = new_token_account(mint.key, &bob, 100);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    let data = deposit_data(&state_account, "usdc", bob, 10);
+    assert_eq!(
+        process_instruction(&program_id, &bob_accounts, &data),
+        Ok(())
+    );
+    set_slot(150);
+    assert_eq!(deposit(), Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.rate_buckets.keys().collect::<Vec<_>>(), vec![&alice]);
+
+    // a window of a state before v32 still running counts into the current bucket
+    let mut state = load_state(&state_account).unwrap();
+    state.rate_windows.insert(
+        alice,
+        RateWindow {
+            start_slot: 145,
+            count: 2,
+        },
+    );
+    state.rate_windows.insert(
+        bob,
+        RateWindow {
+            start_slot: 100,
+            count: 2,
+        },
+    );
+    let payload = borsh::to_vec(&state).unwrap();
+    {
+        let mut data = state_account.data.borrow_mut();
+        data[9..STATE_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        data[STATE_HEADER_LEN..STATE_HEADER_LEN + payload.len()].copy_from_slice(&payload);
+    }
+    set_slot(151);
+    assert_eq!(deposit(), rate_limited);
+    set_slot(152);
+    assert_eq!(transfer(), rate_limited);
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.rate_windows.len(), 2);
+    // and it is gone once counted or ended
+    assert_eq!(set_rate_limit(4, 10), Ok(()));
+    assert_eq!(deposit(), Ok(()));
+    let state = load_full_state(&state_account).unwrap();
+    assert!(state.rate_windows.is_empty());
+    assert_eq!(
+        state.rate_buckets[&alice],
+        RateBuckets {
+            bucket_start: 150,
+            count: 4,
+            previous_count: 0,
+        }
+    );
+
+    // a new window length starts every count over
+    assert_eq!(set_rate_limit(4, 20), Ok(()));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .rate_buckets
+        .is_empty());
+
+    // nor is anyone once it is turned off
+    assert_eq!(set_rate_limit(0, 0), Ok(()));
+    for _ in 0..3 {
+        assert_eq!(deposit(), Ok(()));
+    }
+    set_slot(0);
+}
+
+#[test]
+fn test_rate_limit_boundary_burst() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let alice = Pubkey::new_unique();
+    let alice_token_account = new_token_account(mint.key, &alice, 100);
+    let accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let data = pack(&AdminSetRateLimit {
+        max_ops: 4,
+        window_slots: 100,
+    });
+    let admin_accounts = [state_account.clone(), admin_account(true)];
+    assert_eq!(
+        process_instruction(&program_id, &admin_accounts, &data),
+        Ok(())
+    );
+    let deposit = || {
+        process_instruction(
+            &program_id,
+            &accounts,
+            &deposit_data(&state_account, "usdc", alice, 1),
+        )
+    };
+    let rate_limited: ProgramResult = Err(ContractError::RateLimited.into());
+
+    // a full burst in the last slot of one bucket and another in the first slot of the next,
+    // which fixed windows would let through
+    set_slot(199);
+    for _ in 0..4 {
+        assert_eq!(deposit(), Ok(()));
+    }
+    set_slot(200);
+    assert_eq!(deposit(), rate_limited);
+    // the burst counts until the window slid a quarter past it
+    set_slot(224);
+    assert_eq!(deposit(), rate_limited);
+    set_slot(225);
+    assert_eq!(deposit(), Ok(()));
+    assert_eq!(deposit(), rate_limited);
+    set_slot(275);
+    assert_eq!(deposit(), Ok(()));
+    assert_eq!(deposit(), Ok(()));
+    assert_eq!(deposit(), rate_limited);
+    set_slot(0);
+}
+
+#[test]
+fn test_withdraw_queue() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let users: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let (alice, bob, carol) = (users[0], users[1], users[2]);
+    let token_accounts: Vec<_> = users
+        .iter()
+        .map(|user| new_token_account(mint.key, user, 100))
+        .collect();
+    let user_accounts: Vec<_> = users
+        .iter()
+        .zip(&token_accounts)
+        .map(|(user, token_account)| {
+            user_token_accounts(&state_account, user, token_account, &vault)
+        })
+        .collect();
+    let accounts_of = |user: Pubkey| &user_accounts[users.iter().position(|u| *u == user).unwrap()];
+    for user in &users {
+        let data = deposit_data(&state_account, "usdc", *user, 100);
+        process_instruction(&program_id, accounts_of(*user), &data).unwrap();
+    }
+    let enqueue = |user: Pubkey, amount: u64| {
+        let data = pack(&UserEnqueueWithdraw {
+            token: token("usdc"),
+            user,
+            amount,
+        });
+        process_instruction(&program_id, accounts_of(user), &data)
+    };
+    let cancel = |user: Pubkey| {
+        let data = pack(&UserCancelQueuedWithdraw {
+            token: token("usdc"),
+            user,
+        });
+        process_instruction(&program_id, accounts_of(user), &data)
+    };
+    let position = |user: Pubkey| {
+        let data = pack(&GetQueuePosition {
+            token: token("usdc"),
+            user,
+        });
+        process_instruction(&program_id, std::slice::from_ref(&state_account), &data).unwrap();
+        let (_, data) = get_return_data().unwrap();
+        Option::<u32>::try_from_slice(&data).unwrap()
+    };
+    // the payout accounts and balance account of each of users, in order
+    let payer = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    let crank = |users: &[Pubkey], max_items: u8| {
+        let mut accounts = vec![state_account.clone(), payer.clone()];
+        for user in users {
+            accounts.extend_from_slice(&accounts_of(*user)[2..6]);
+        }
+        for user in users {
+            accounts.push(accounts_of(*user)[6].clone());
+        }
+        accounts.push(system_program_account());
+        for user in users {
+            accounts.push(accounts_of(*user)[1].clone());
+        }
+        let data = pack(&ProcessWithdrawQueue {
+            token: token("usdc"),
+            max_items,
+        });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let set_vault = |amount: u64| {
+        let mut vault_state = TokenAccount::unpack(&vault.data.borrow()).unwrap();
+        vault_state.amount = amount;
+        vault_state.pack_into_slice(&mut vault.data.borrow_mut());
+    };
+    let queued = || {
+        load_full_state(&state_account)
+            .unwrap()
+            .withdraw_queues
+            .get(mint.key)
+            .map(|queue| {
+                queue
+                    .iter()
+                    .map(|queued| (queued.user, queued.amount))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    assert_eq!(enqueue(alice, 0), Err(ContractError::ZeroAmount.into()));
+    assert_eq!(enqueue(alice, 101), Err(ProgramError::InsufficientFunds));
+    set_slot(5);
+    assert_eq!(enqueue(alice, 100), Ok(()));
+    assert_eq!(enqueue(bob, 60), Ok(()));
+    assert_eq!(enqueue(carol, 30), Ok(()));
+    assert_eq!(
+        enqueue(bob, 10),
+        Err(ContractError::WithdrawAlreadyQueued.into())
+    );
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(state.withdraw_queues[mint.key][0].queued_slot, 5);
+    assert_eq!(
+        users.iter().map(|user| position(*user)).collect::<Vec<_>>(),
+        [Some(0), Some(1), Some(2)]
+    );
+    assert_eq!(position(Pubkey::new_unique()), None);
+
+    // cancelling takes out the user's entry only, queueing again goes to the back
+    assert_eq!(cancel(bob), Ok(()));
+    assert_eq!(queued(), [(alice, 100), (carol, 30)]);
+    assert_eq!(cancel(bob), Err(ContractError::NotInWithdrawQueue.into()));
+    assert_eq!(enqueue(bob, 60), Ok(()));
+    assert_eq!(queued(), [(alice, 100), (carol, 30), (bob, 60)]);
+    assert_eq!(position(bob), Some(2));
+    // queueing neither pays out nor reserves the balance
+    assert_eq!(token_amount(&token_accounts[0]), 0);
+    assert_eq!(
+        load_full_state(&state_account).unwrap().all_token_balances[&token("usdc")].balances
+            [&alice],
+        100
+    );
+
+    // entries go in order, the payouts have to be passed for them in that order
+    assert_eq!(
+        crank(&[carol], 1),
+        Err(ContractError::RecipientMismatch.into())
+    );
+    set_vault(150);
+    assert_eq!(crank(&[alice], 1), Ok(()));
+    assert_eq!(token_amount(&token_accounts[0]), 100);
+    assert_eq!(queued(), [(carol, 30), (bob, 60)]);
+    assert_eq!(position(carol), Some(0));
+    // the vault pays carol, then is short for bob, who stays at the front
+    assert_eq!(crank(&[carol, bob], 2), Ok(()));
+    assert_eq!(token_amount(&token_accounts[2]), 30);
+    assert_eq!(token_amount(&token_accounts[1]), 0);
+    assert_eq!(token_amount(&vault), 20);
+    assert_eq!(queued(), [(bob, 60)]);
+    take_logs();
+
+    // an entry the balance can no longer cover is dropped and the next one runs
+    assert_eq!(enqueue(carol, 70), Ok(()));
+    set_vault(200);
+    let data = withdraw_data(&state_account, "usdc", carol, 50);
+    process_instruction(&program_id, accounts_of(carol), &data).unwrap();
+    assert_eq!(queued(), [(bob, 60), (carol, 70)]);
+    // max_items beyond the queue only takes the entries there are
+    assert_eq!(crank(&[bob, carol], 5), Ok(()));
+    assert_eq!(token_amount(&token_accounts[1]), 60);
+    assert_eq!(token_amount(&token_accounts[2]), 80);
+    assert!(take_logs()
+        .iter()
+        .any(|log| log.contains(&format!("queued withdrawal of user {} dropped", carol))));
+    assert!(load_full_state(&state_account)
+        .unwrap()
+        .withdraw_queues
+        .is_empty());
+    assert_eq!(crank(&[], 3), Ok(()));
+    set_slot(0);
+}
+
+#[test]
+fn test_withdraw_queue_stuck_entries() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    let (mint, vault) = add_token(&program_id, &state_account, "usdc");
+    let admin = |instruction: &ContractInstruction| {
+        let accounts = [state_account.clone(), admin_account(true)];
+        process_instruction(&program_id, &accounts, &pack(instruction))
+    };
+    let alice = Pubkey::new_unique();
+    let bob = Pubkey::new_unique();
+    let ata = get_associated_token_address_with_program_id(&alice, mint.key, &spl_token::id());
+    let alice_token_account = new_token_account_at(ata, mint.key, &alice, 100);
+    let alice_accounts = user_token_accounts(&state_account, &alice, &alice_token_account, &vault);
+    let bob_token_account = new_token_account(mint.key, &bob, 100);
+    let bob_accounts = user_token_accounts(&state_account, &bob, &bob_token_account, &vault);
+    let enqueue = |accounts: &[AccountInfo<'static>], user: Pubkey, amount: u64| {
+        let data = pack(&UserEnqueueWithdraw {
+            token: token("usdc"),
+            user,
+            amount,
+        });
+        process_instruction(&program_id, accounts, &data)
+    };
+    set_slot(10);
+    for (user, accounts) in [(alice, &alice_accounts), (bob, &bob_accounts)] {
+        let data = deposit_data(&state_account, "usdc", user, 100);
+        process_instruction(&program_id, accounts, &data).unwrap();
+    }
+    assert_eq!(enqueue(&alice_accounts, alice, 100), Ok(()));
+    assert_eq!(enqueue(&bob_accounts, bob, 40), Ok(()));
+    // [state, payer] then each entry's payout accounts, the balance accounts, the system
+    // program and the users
+    let payer = new_account(Pubkey::new_unique(), true, 0, Pubkey::default());
+    let crank_accounts = |entries: &[(&[AccountInfo<'static>], &AccountInfo<'static>)]| {
+        let mut accounts = vec![state_account.clone(), payer.clone()];
+        for (user_accounts, payout) in entries {
+            accounts.push((*payout).clone());
+            accounts.extend_from_slice(&user_accounts[3..6]);
+        }
+        for (user_accounts, _) in entries {
+            accounts.push(user_accounts[6].clone());
+        }
+        accounts.push(system_program_account());
+        for (user_accounts, _) in entries {
+            accounts.push(user_accounts[1].clone());
+        }
+        accounts
+    };
+    let crank = |accounts: &[AccountInfo<'static>], max_items: u8| {
+        let data = pack(&ProcessWithdrawQueue {
+            token: token("usdc"),
+            max_items,
+        });
+        process_instruction(&program_id, accounts, &data)
+    };
+    let queued = || {
+        load_full_state(&state_account)
+            .unwrap()
+            .withdraw_queues
+            .get(mint.key)
+            .map(|queue| {
+                queue
+                    .iter()
+                    .map(|queued| (queued.user, queued.amount))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+
+    // alice closed her associated token account, her entry moves to the back and bob's runs
+    let closed = new_account(ata, false, 0, system_program::id());
+    let not_alices = new_account(Pubkey::new_unique(), false, 0, system_program::id());
+    let accounts = crank_accounts(&[
+        (&alice_accounts, &not_alices),
+        (&bob_accounts, &bob_token_account),
+    ]);
+    assert!(crank(&accounts, 2).is_err());
+    assert_eq!(queued(), [(alice, 100), (bob, 40)]);
+    let accounts = crank_accounts(&[
+        (&alice_accounts, &closed),
+        (&bob_accounts, &bob_token_account),
+    ]);
+    assert_eq!(crank(&accounts, 2), Ok(()));
+    assert_eq!(token_amount(&bob_token_account), 40);
+    assert_eq!(queued(), [(alice, 100)]);
+
+    // an entry over its daily limit keeps its place and the one behind it runs
+    let reopened = new_token_account_at(ata, mint.key, &alice, 0);
+    admin(&AdminSetDailyWithdrawalLimit {
+        token: token("usdc"),
+        limit: 80,
+    })
+    .unwrap();
+    assert_eq!(enqueue(&bob_accounts, bob, 30), Ok(()));
+    take_logs();
+    let accounts = crank_accounts(&[
+        (&alice_accounts, &reopened),
+        (&bob_accounts, &bob_token_account),
+    ]);
+    assert_eq!(crank(&accounts, 2), Ok(()));
+    assert_eq!(token_amount(&bob_token_account), 70);
+    assert_eq!(queued(), [(alice, 100)]);
+    assert!(take_logs()
+        .iter()
+        .any(|log| log.contains(&format!("queued withdrawal of user {} kept", alice))));
+    // and so does one still cooling down
+    admin(&AdminSetDailyWithdrawalLimit {
+        token: token("usdc"),
+        limit: 0,
+    })
+    .unwrap();
+    admin(&AdminSetWithdrawCooldown {
+        token: token("usdc"),
+        withdraw_cooldown_slots: 50,
+        transfers_reset: false,
+    })
+    .unwrap();
+    let accounts = crank_accounts(&[(&alice_accounts, &reopened)]);
+    assert_eq!(crank(&accounts, 1), Ok(()));
+    assert_eq!(queued(), [(alice, 100)]);
+
+    // the rent of the balance account the payout closes goes to alice, who must be passed
+    set_slot(60);
+    assert_eq!(
+        crank(&accounts[..accounts.len() - 1], 1),
+        Err(ProgramError::NotEnoughAccountKeys)
+    );
+    let rent = alice_accounts[6].lamports();
+    let lamports = alice_accounts[1].lamports();
+    assert!(rent > 0);
+    assert_eq!(crank(&accounts, 1), Ok(()));
+    assert_eq!(token_amount(&reopened), 100);
+    assert!(queued().is_empty());
+    assert_eq!(alice_accounts[6].lamports(), 0);
+    assert_eq!(alice_accounts[1].lamports(), lamports + rent);
+    assert_eq!(payer.lamports(), 0);
+    set_slot(0);
+}
+
+#[test]
+fn test_add_supported_tokens() {
+    let program_id = new_program_id();
+    let state_account = initialized_state_account(&program_id);
+    add_token(&program_id, &state_account, "usdc");
+    take_events::<TokenAddedEvent>();
+    let add = |tokens: Vec<TokenType>| {
+        let mut accounts = vec![state_account.clone(), admin_account(true)];
+        for token in &tokens {
+            accounts.push(new_mint_account(&token.symbol));
+            accounts.push(new_vault_account(&program_id, &token.mint));
+        }
+        let data = pack(&AdminAddSupportedTokens { tokens });
+        process_instruction(&program_id, &accounts, &data)
+    };
+    let listed = || {
+        let state = load_full_state(&state_account).unwrap();
+        state
+            .all_token_balances
+            .keys()
+            .map(|token| token.symbol.clone())
+            .collect::<BTreeSet<_>>()
+    };
+
+    // a clean batch lists every token, named after its symbol with the mint's decimals
+    assert_eq!(add(vec![token("ray"), token("srm"), token("orca")]), Ok(()));
+    assert_eq!(
+        listed(),
+        BTreeSet::from(["ORCA", "RAY", "SRM", "USDC"].map(String::from))
+    );
+    let state = load_full_state(&state_account).unwrap();
+    assert_eq!(
+        state.token_metadata[&token("srm").mint],
+        TokenMetadata {
+            name: "SRM".to_string(),
+            decimals: 9,
+            metadata_uri: None,
+        }
+    );
+    assert_eq!(take_events::<TokenAddedEvent>().len(), 3);
+
+    // the same symbol, whatever its case, or the same mint twice in the batch, nothing listed
+    let renamed = TokenType {
+        symbol: "msol".to_string(),
+        mint: token("mngo").mint,
+    };
+    for batch in [
+        vec![token("mngo"), token("step"), token("MNGO")],
+        vec![token("mngo"), renamed.clone()],
+    ] {
+        assert_eq!(add(batch), Err(ContractError::DuplicateTokenInBatch.into()));
+    }
+    // a symbol or mint listed already is the usual error
+    let listed_symbol = TokenType {
+        symbol: "usdc".to_string(),
+        mint: token("step").mint,
+    };
+    assert_eq!(
+        add(vec![token("mngo"), listed_symbol]),
+        Err(ContractError::TokenAlreadyExists.into())
+    );
+    let listed_mint = TokenType {
+        symbol: "step".to_string(),
+        mint: token("ray").mint,
+    };
+    assert_eq!(
+        add(vec![token("mngo"), listed_mint]),
+        Err(ContractError::MintAlreadyRegistered.into())
+    );
+    assert_eq!(listed().len(), 4);
+
+    // at most MAX_BATCH_ITEMS tokens, and no more than max_tokens in all
+    let batch = |count: usize| (0..count).map(|i| token(&format!("t{}", i))).collect();
+    assert_eq!(add(batch(17)), Err(ContractError::BatchTooLarge.into()));
+    let accounts = [state_account.clone(), admin_account(true)];
+    let data = pack(&AdminSetMaxTokens { max_tokens: 6 });
+    process_instruction(&program_id, &accounts, &data).unwrap();
+    assert_eq!(add(batch(3)), Err(ContractError::TokenLimitReached.into()));
+    assert_eq!(listed().len(), 4);
+    assert_eq!(add(batch(2)), Ok(()));
+    assert_eq!(listed().len(), 6);
+}
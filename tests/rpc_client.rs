@@ -0,0 +1,594 @@
+// ContractClient's retry policy and error decoding against a mocked cluster
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use borsh::BorshSerialize;
+use hello_world::rpc_client::{
+    decode_error, is_blockhash_expired, Cluster, ContractClient, ContractClientConfig,
+    ContractClientError, RetryPolicy,
+};
+use hello_world::{
+    client::*, find_balance_address, BalanceAccount, ContractError, ContractState, TokenEntry,
+    TokenType, BALANCE_ACCOUNT_TAG, STATE_ACCOUNT_SIZE, STATE_DISCRIMINATOR, STATE_HEADER_LEN,
+    STATE_VERSION,
+};
+use solana_client::client_error::ClientError;
+use solana_client::rpc_request::{RpcError, MAX_MULTIPLE_ACCOUNTS};
+use solana_sdk::account::Account;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::program_error::ProgramError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::{Transaction, TransactionError};
+
+// fails sends with its failures in turn, then confirms them
+#[derive(Default)]
+struct MockCluster {
+    failures: Mutex<VecDeque<ClientError>>,
+    sent: Mutex<Vec<Transaction>>,
+    accounts: HashMap<Pubkey, Account>,
+    // how many accounts each call of accounts asked for
+    fetched: Mutex<Vec<usize>>,
+}
+
+#[async_trait]
+impl Cluster for MockCluster {
+    async fn latest_blockhash(&self) -> Result<Hash, ClientError> {
+        Ok(Hash::new_unique())
+    }
+
+    async fn send_and_confirm(&self, transaction: &Transaction) -> Result<Signature, ClientError> {
+        self.sent.lock().unwrap().push(transaction.clone());
+        match self.failures.lock().unwrap().pop_front() {
+            Some(error) => Err(error),
+            None => Ok(transaction.signatures[0]),
+        }
+    }
+
+    async fn account(&self, address: &Pubkey) -> Result<Option<Account>, ClientError> {
+        Ok(self.accounts.get(address).cloned())
+    }
+
+    async fn program_account_keys(
+        &self,
+        program_id: &Pubkey,
+        tag: u8,
+    ) -> Result<Vec<Pubkey>, ClientError> {
+        Ok(self
+            .accounts
+            .iter()
+            .filter(|(_, account)| {
+                account.owner == *program_id && account.data.first() == Some(&tag)
+            })
+            .map(|(address, _)| *address)
+            .collect())
+    }
+
+    async fn accounts(&self, addresses: &[Pubkey]) -> Result<Vec<Option<Account>>, ClientError> {
+        assert!(addresses.len() <= MAX_MULTIPLE_ACCOUNTS);
+        self.fetched.lock().unwrap().push(addresses.len());
+        Ok(addresses
+            .iter()
+            .map(|address| self.accounts.get(address).cloned())
+            .collect())
+    }
+}
+
+fn expired() -> ClientError {
+    RpcError::ForUser("unable to confirm transaction. This can happen ...".to_string()).into()
+}
+
+fn failed(index: u8, error: InstructionError) -> ClientError {
+    TransactionError::InstructionError(index, error).into()
+}
+
+fn config(max_attempts: u32) -> ContractClientConfig {
+    ContractClientConfig {
+        retry: RetryPolicy {
+            max_attempts,
+            backoff: Duration::from_millis(1),
+            backoff_multiplier: 2,
+        },
+        ..ContractClientConfig::default()
+    }
+}
+
+fn new_client(cluster: MockCluster, max_attempts: u32) -> (ContractClient<MockCluster>, Pubkey) {
+    let program_id = Pubkey::new_unique();
+    let client =
+        ContractClient::with_cluster(cluster, program_id, Keypair::new(), config(max_attempts));
+    (client, program_id)
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy {
+        max_attempts: 4,
+        backoff: Duration::from_millis(100),
+        backoff_multiplier: 3,
+    };
+    let backoffs: Vec<_> = (0..6)
+        .map(|attempt| policy.backoff_before(attempt))
+        .collect();
+    assert_eq!(
+        backoffs,
+        [
+            Some(Duration::ZERO),
+            Some(Duration::from_millis(100)),
+            Some(Duration::from_millis(300)),
+            Some(Duration::from_millis(900)),
+            None,
+            None,
+        ]
+    );
+
+    assert!(is_blockhash_expired(&expired()));
+    assert!(is_blockhash_expired(
+        &TransactionError::BlockhashNotFound.into()
+    ));
+    assert!(!is_blockhash_expired(&failed(
+        0,
+        InstructionError::Custom(1)
+    )));
+    assert!(!is_blockhash_expired(
+        &RpcError::ForUser("something else".to_string()).into()
+    ));
+}
+
+#[test]
+fn test_decode_error() {
+    let program_id = Pubkey::new_unique();
+    let payer = Keypair::new();
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1),
+            get_sequence_ix(&program_id),
+        ],
+        Some(&payer.pubkey()),
+        &[&payer],
+        Hash::new_unique(),
+    );
+    let code = ContractError::BalanceLocked as u32;
+
+    assert!(matches!(
+        decode_error(
+            &program_id,
+            &transaction,
+            failed(1, InstructionError::Custom(code))
+        ),
+        ContractClientError::Contract(ContractError::BalanceLocked)
+    ));
+    // the same code from another program is not a ContractError
+    assert!(matches!(
+        decode_error(
+            &program_id,
+            &transaction,
+            failed(0, InstructionError::Custom(code))
+        ),
+        ContractClientError::Instruction {
+            index: 0,
+            error: InstructionError::Custom(_),
+        }
+    ));
+    assert!(matches!(
+        decode_error(
+            &program_id,
+            &transaction,
+            failed(1, InstructionError::Custom(9999))
+        ),
+        ContractClientError::Instruction { index: 1, .. }
+    ));
+    assert!(matches!(
+        decode_error(
+            &program_id,
+            &transaction,
+            failed(1, InstructionError::MissingRequiredSignature)
+        ),
+        ContractClientError::Instruction {
+            index: 1,
+            error: InstructionError::MissingRequiredSignature,
+        }
+    ));
+    assert!(matches!(
+        decode_error(&program_id, &transaction, expired()),
+        ContractClientError::Rpc(_)
+    ));
+}
+
+#[tokio::test]
+async fn test_send_retries_expired() {
+    // signed again with a new blockhash after each expiry
+    let cluster = MockCluster::default();
+    cluster
+        .failures
+        .lock()
+        .unwrap()
+        .extend([expired(), expired()]);
+    let (client, program_id) = new_client(cluster, 3);
+    let signature = client.send(&[get_sequence_ix(&program_id)]).await.unwrap();
+    let sent = client_sent(&client);
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[2].signatures[0], signature);
+    assert_ne!(
+        sent[0].message.recent_blockhash,
+        sent[1].message.recent_blockhash
+    );
+
+    let cluster = MockCluster::default();
+    cluster
+        .failures
+        .lock()
+        .unwrap()
+        .extend((0..3).map(|_| expired()));
+    let (client, program_id) = new_client(cluster, 3);
+    assert!(matches!(
+        client.send(&[get_sequence_ix(&program_id)]).await,
+        Err(ContractClientError::Expired { attempts: 3 })
+    ));
+
+    // any other failure is returned at once
+    let cluster = MockCluster::default();
+    let code = ContractError::ContractPaused as u32;
+    cluster
+        .failures
+        .lock()
+        .unwrap()
+        .push_back(failed(0, InstructionError::Custom(code)));
+    let (client, program_id) = new_client(cluster, 3);
+    assert!(matches!(
+        client.send(&[get_sequence_ix(&program_id)]).await,
+        Err(ContractClientError::Contract(ContractError::ContractPaused))
+    ));
+    assert_eq!(client_sent(&client).len(), 1);
+}
+
+fn client_sent(client: &ContractClient<MockCluster>) -> Vec<Transaction> {
+    client.cluster().sent.lock().unwrap().clone()
+}
+
+fn entry() -> TokenEntry {
+    TokenEntry {
+        deposits_enabled: true,
+        withdrawals_enabled: true,
+        deposit_cap: None,
+        total_deposited: 0,
+        max_user_balance: None,
+        min_deposit: 0,
+        daily_withdrawal_limit: 0,
+        withdrawal_windows: BTreeMap::new(),
+        withdrawal_fee_bps: 0,
+        deposit_fee_bps: 0,
+        withdraw_requests: BTreeMap::new(),
+        frozen: BTreeSet::new(),
+        balances: BTreeMap::new(),
+    }
+}
+
+fn state_account(state: &ContractState) -> Account {
+    let payload = borsh::to_vec(state).unwrap();
+    let mut data = vec![0; STATE_ACCOUNT_SIZE];
+    data[0] = STATE_VERSION;
+    data[1..9].copy_from_slice(&STATE_DISCRIMINATOR);
+    data[9..STATE_HEADER_LEN].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    data[STATE_HEADER_LEN..STATE_HEADER_LEN + payload.len()].copy_from_slice(&payload);
+    Account {
+        data,
+        ..Account::default()
+    }
+}
+
+#[tokio::test]
+async fn test_deposit_and_balance() {
+    let payer = Keypair::new();
+    let user = payer.pubkey();
+    let program_id = Pubkey::new_unique();
+    let token = TokenType::native();
+    let other = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    let mut state = ContractState::default();
+    state.all_token_balances.insert(token.clone(), entry());
+    state.nonces.insert(user, 4);
+    let (balance, bump) = find_balance_address(&program_id, &token.mint, &user);
+    let mut balance_data = vec![BALANCE_ACCOUNT_TAG];
+    BalanceAccount {
+        mint: token.mint,
+        user,
+        bump,
+        epoch: 0,
+        amount: 7,
+        flags: 0,
+        last_update_slot: 3,
+        nonce: 9,
+        ..Default::default()
+    }
+    .serialize(&mut balance_data)
+    .unwrap();
+    let mut cluster = MockCluster::default();
+    cluster
+        .accounts
+        .insert(find_state_address(&program_id), state_account(&state));
+    cluster.accounts.insert(
+        balance,
+        Account {
+            data: balance_data,
+            ..Account::default()
+        },
+    );
+    let client = ContractClient::with_cluster(cluster, program_id, payer, config(1));
+
+    // the deposit carries the nonce of the user's balance account, not the one the user was at
+    // before nonces moved there
+    client.deposit(&token, &user, 10).await.unwrap();
+    let sent = client_sent(&client);
+    assert_eq!(
+        sent[0].message.instructions[0].data,
+        deposit_ix(
+            &program_id,
+            &token,
+            &user,
+            &user,
+            10,
+            9,
+            None,
+            None,
+            None,
+            None
+        )
+        .data
+    );
+    assert_eq!(client.get_balance(&token, &user).await.unwrap(), 7);
+    assert_eq!(
+        client
+            .get_balance(&token, &Pubkey::new_unique())
+            .await
+            .unwrap(),
+        0
+    );
+
+    assert!(matches!(
+        client.withdraw(&other, &user, 10).await,
+        Err(ContractClientError::Contract(
+            ContractError::TokenNotSupportedForWithdraw
+        ))
+    ));
+    assert_eq!(client_sent(&client).len(), 1);
+
+    // nothing to read before the state is initialized
+    let (uninitialized, _) = new_client(MockCluster::default(), 1);
+    assert!(matches!(
+        uninitialized.get_balance(&token, &user).await,
+        Err(ContractClientError::Program(_))
+    ));
+}
+
+// a balance account of user's balance of token, as the program writes it
+fn balance_account(
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    epoch: u64,
+    amount: u64,
+) -> (Pubkey, Account) {
+    let (address, bump) = find_balance_address(program_id, &token.mint, user);
+    let mut data = vec![BALANCE_ACCOUNT_TAG];
+    BalanceAccount {
+        mint: token.mint,
+        user: *user,
+        bump,
+        epoch,
+        amount,
+        flags: 0,
+        last_update_slot: 0,
+        nonce: 0,
+        ..Default::default()
+    }
+    .serialize(&mut data)
+    .unwrap();
+    let account = Account {
+        data,
+        owner: *program_id,
+        ..Account::default()
+    };
+    (address, account)
+}
+
+#[tokio::test]
+async fn test_export_balances() {
+    let program_id = Pubkey::new_unique();
+    let sol = TokenType::native();
+    let usdc = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_from_array([1; 32]),
+    };
+    let delisted = TokenType {
+        symbol: "ray".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    let (alice, bob) = (
+        Pubkey::new_from_array([2; 32]),
+        Pubkey::new_from_array([3; 32]),
+    );
+    let mut state = ContractState::default();
+    state.all_token_balances.insert(sol.clone(), entry());
+    let mut usdc_entry = entry();
+    // still inline in the state
+    usdc_entry.balances.insert(bob, 5);
+    state.all_token_balances.insert(usdc.clone(), usdc_entry);
+    // usdc was listed again since the epoch 0 balance account below was written, so it is stale
+    state.token_epochs.insert(usdc.mint, 1);
+
+    let mut cluster = MockCluster::default();
+    cluster
+        .accounts
+        .insert(find_state_address(&program_id), state_account(&state));
+    let fixtures = [
+        (&sol, alice, 0, 7),
+        (&usdc, alice, 1, 12),
+        (&usdc, Pubkey::new_unique(), 0, 40),
+        (&delisted, alice, 0, 3),
+    ];
+    for (token, user, epoch, amount) in fixtures {
+        let (address, account) = balance_account(&program_id, token, &user, epoch, amount);
+        cluster.accounts.insert(address, account);
+    }
+    // a balance account at another address than its own
+    let (_, misplaced) = balance_account(&program_id, &sol, &bob, 0, 9);
+    let misplaced_address = Pubkey::new_unique();
+    cluster.accounts.insert(misplaced_address, misplaced);
+    // cut short
+    let (truncated_address, mut truncated) = balance_account(&program_id, &sol, &bob, 0, 9);
+    truncated.data.truncate(20);
+    cluster.accounts.insert(truncated_address, truncated);
+    // another program's, not fetched
+    let (other_address, mut other) = balance_account(&program_id, &usdc, &bob, 1, 9);
+    other.owner = Pubkey::new_unique();
+    cluster.accounts.insert(other_address, other);
+    let client = ContractClient::with_cluster(cluster, program_id, Keypair::new(), config(1));
+
+    let export = client.export_balances().await.unwrap();
+    let row = |token: &TokenType, user: Pubkey, balance| BalanceRow {
+        token_symbol: token.symbol.clone(),
+        mint: token.mint,
+        user,
+        balance,
+        shares: None,
+    };
+    assert_eq!(
+        export.rows,
+        // usdc's mint sorts first
+        [
+            row(&usdc, alice, 12),
+            row(&usdc, bob, 5),
+            row(&sol, alice, 7)
+        ]
+    );
+    let mut failed = export.failed.clone();
+    failed.sort_by_key(|(address, _)| *address == truncated_address);
+    assert_eq!(
+        failed,
+        [
+            (
+                misplaced_address,
+                ContractError::InvalidBalanceAccount.into()
+            ),
+            (truncated_address, ProgramError::InvalidAccountData),
+        ]
+    );
+    assert_eq!(*client.cluster().fetched.lock().unwrap(), [6]);
+
+    let mut csv = Vec::new();
+    export.to_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        format!(
+            "token,mint,user,balance,shares\n\
+             usdc,{},{alice},12,\n\
+             usdc,{},{bob},5,\n\
+             SOL,{},{alice},7,\n",
+            usdc.mint, usdc.mint, sol.mint
+        )
+    );
+    let json = serde_json::to_value(&export.rows[1]).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "token_symbol": "usdc",
+            "mint": usdc.mint.to_string(),
+            "user": bob.to_string(),
+            "balance": 5,
+            "shares": null,
+        })
+    );
+    let rows: Vec<BalanceRow> =
+        serde_json::from_str(&serde_json::to_string(&export.rows).unwrap()).unwrap();
+    assert_eq!(rows, export.rows);
+
+    // fetched a chunk at a time
+    let mut cluster = MockCluster::default();
+    cluster
+        .accounts
+        .insert(find_state_address(&program_id), state_account(&state));
+    for amount in 1..=MAX_MULTIPLE_ACCOUNTS as u64 + 30 {
+        let (address, account) =
+            balance_account(&program_id, &sol, &Pubkey::new_unique(), 0, amount);
+        cluster.accounts.insert(address, account);
+    }
+    let client = ContractClient::with_cluster(cluster, program_id, Keypair::new(), config(1));
+    let export = client.export_balances().await.unwrap();
+    // bob's inline usdc balance too
+    assert_eq!(export.rows.len(), MAX_MULTIPLE_ACCOUNTS + 31);
+    assert!(export.failed.is_empty());
+    assert_eq!(
+        *client.cluster().fetched.lock().unwrap(),
+        [MAX_MULTIPLE_ACCOUNTS, 30]
+    );
+
+    // nothing to export before the state is initialized
+    let (uninitialized, _) = new_client(MockCluster::default(), 1);
+    assert!(matches!(
+        uninitialized.export_balances().await,
+        Err(ContractClientError::Program(
+            ProgramError::UninitializedAccount
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn test_export_share_mode_balances() {
+    let program_id = Pubkey::new_unique();
+    let usdc = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_from_array([1; 32]),
+    };
+    let (alice, bob) = (
+        Pubkey::new_from_array([2; 32]),
+        Pubkey::new_from_array([3; 32]),
+    );
+    // 150 shares hold 400, so a share is worth 2.67
+    let mut state = ContractState::default();
+    let mut usdc_entry = entry();
+    usdc_entry.total_deposited = 400;
+    usdc_entry.balances.insert(bob, 50);
+    state.all_token_balances.insert(usdc.clone(), usdc_entry);
+    state.share_supplies.insert(usdc.mint, 150);
+
+    let mut cluster = MockCluster::default();
+    cluster
+        .accounts
+        .insert(find_state_address(&program_id), state_account(&state));
+    let (address, account) = balance_account(&program_id, &usdc, &alice, 0, 100);
+    cluster.accounts.insert(address, account);
+    let client = ContractClient::with_cluster(cluster, program_id, Keypair::new(), config(1));
+
+    // balances are in units, rounded down, next to the shares
+    let export = client.export_balances().await.unwrap();
+    let row = |user: Pubkey, balance, shares| BalanceRow {
+        token_symbol: usdc.symbol.clone(),
+        mint: usdc.mint,
+        user,
+        balance,
+        shares: Some(shares),
+    };
+    assert_eq!(export.rows, [row(alice, 266, 100), row(bob, 133, 50)]);
+    let mut csv = Vec::new();
+    export.to_csv(&mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        format!(
+            "token,mint,user,balance,shares\n\
+             usdc,{},{alice},266,100\n\
+             usdc,{},{bob},133,50\n",
+            usdc.mint, usdc.mint
+        )
+    );
+    assert_eq!(
+        serde_json::to_value(&export.rows[0]).unwrap()["shares"],
+        serde_json::json!(100)
+    );
+}
@@ -0,0 +1,739 @@
+// property tests of the instruction encoding: random instructions must survive encode→decode
+// unchanged and random bytes must decode to an error, never a panic
+// the generators are seeded so a failure reproduces, inputs it finds go to corpus/instructions.txt
+use hello_world::{
+    deserialize_instruction, ContractInstruction, TokenMetadata, TokenType,
+    INSTRUCTION_DISCRIMINATORS, INSTRUCTION_VERSION_BORSH, INSTRUCTION_VERSION_COMPACT,
+    INSTRUCTION_VERSION_JSON, MAX_INSTRUCTION_DATA_LEN, MAX_SYMBOL_LEN,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use solana_sdk::program_error::ProgramError;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+
+const CASES: usize = 2_000;
+
+fn rng() -> StdRng {
+    StdRng::seed_from_u64(0x5eed)
+}
+
+// default and all-ones keys show up as often as random ones
+fn arb_pubkey(rng: &mut StdRng) -> Pubkey {
+    match rng.gen_range(0..4) {
+        0 => Pubkey::default(),
+        1 => Pubkey::new_from_array([0xff; 32]),
+        _ => Pubkey::new_from_array(rng.gen()),
+    }
+}
+
+// edge values as often as random ones
+fn arb_u64(rng: &mut StdRng) -> u64 {
+    match rng.gen_range(0..4) {
+        0 => 0,
+        1 => u64::MAX,
+        _ => rng.gen(),
+    }
+}
+
+fn arb_bytes(rng: &mut StdRng, max_len: usize) -> Vec<u8> {
+    let len = rng.gen_range(0..=max_len);
+    (0..len).map(|_| rng.gen()).collect()
+}
+
+// any string, not only valid symbols: empty, unicode, control characters, overlong
+fn arb_symbol(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(0..40);
+    (0..len)
+        .map(|_| match rng.gen_range(0..3) {
+            0 => rng.gen_range(b'a'..=b'z') as char,
+            1 => rng.gen_range('\0'..='\u{7f}'),
+            _ => rng.gen::<char>(),
+        })
+        .collect()
+}
+
+fn arb_token(rng: &mut StdRng) -> TokenType {
+    if rng.gen_ratio(1, 8) {
+        return TokenType::native();
+    }
+    TokenType {
+        symbol: arb_symbol(rng),
+        mint: arb_pubkey(rng),
+    }
+}
+
+// any name and URI, not only valid ones
+fn arb_metadata(rng: &mut StdRng) -> TokenMetadata {
+    TokenMetadata {
+        name: arb_symbol(rng),
+        decimals: rng.gen(),
+        metadata_uri: arb_option(rng, arb_symbol),
+    }
+}
+
+fn arb_option<T>(rng: &mut StdRng, value: impl FnOnce(&mut StdRng) -> T) -> Option<T> {
+    rng.gen::<bool>().then(|| value(rng))
+}
+
+fn arb_items(rng: &mut StdRng) -> Vec<(TokenType, u64)> {
+    (0..rng.gen_range(0..6))
+        .map(|_| (arb_token(rng), arb_u64(rng)))
+        .collect()
+}
+
+// every variant, keep in sync with ContractInstruction
+fn arb_instruction(r: &mut StdRng) -> ContractInstruction {
+    match r.gen_range(0..86) {
+        0 => ContractInstruction::InitializeState,
+        1 => ContractInstruction::MigrateState,
+        2 => ContractInstruction::InitializeConfig {
+            admin: arb_pubkey(r),
+        },
+        3 => ContractInstruction::AdminAddSupportedToken {
+            token: arb_token(r),
+            metadata: arb_metadata(r),
+        },
+        4 => ContractInstruction::AdminDeleteSupportedToken {
+            token: arb_token(r),
+        },
+        5 => ContractInstruction::AdminForceDeleteToken {
+            token: arb_token(r),
+            confirm: r.gen(),
+        },
+        6 => ContractInstruction::UserDeposit {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: arb_u64(r),
+            nonce: arb_u64(r),
+            lock_until: arb_option(r, |r| r.gen()),
+            referrer: arb_option(r, arb_pubkey),
+            beneficiary: arb_option(r, arb_pubkey),
+            memo: arb_option(r, arb_symbol),
+        },
+        7 => ContractInstruction::UserWithdraw {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: arb_u64(r),
+            nonce: arb_u64(r),
+            eth_signature: arb_option(r, |r| arb_bytes(r, 80)),
+            recipient: arb_option(r, arb_pubkey),
+            memo: arb_option(r, arb_symbol),
+        },
+        8 => ContractInstruction::UserWithdrawAll {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        9 => ContractInstruction::UserRequestWithdraw {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: arb_u64(r),
+        },
+        10 => ContractInstruction::UserClaimWithdraw {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        11 => ContractInstruction::AdminCancelWithdrawRequest {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        12 => ContractInstruction::AdminSetWithdrawDelay {
+            delay_slots: arb_u64(r),
+        },
+        13 => ContractInstruction::AdminSetTreasury {
+            treasury: arb_pubkey(r),
+        },
+        14 => ContractInstruction::AdminSetWithdrawalFee {
+            token: arb_token(r),
+            fee_bps: r.gen(),
+        },
+        15 => ContractInstruction::AdminSetDepositFee {
+            token: arb_token(r),
+            fee_bps: r.gen(),
+        },
+        16 => ContractInstruction::AdminSetFeeExempt {
+            user: arb_pubkey(r),
+            exempt: r.gen(),
+        },
+        17 => ContractInstruction::AdminWithdrawTreasury {
+            token: arb_token(r),
+            amount: arb_u64(r),
+        },
+        18 => ContractInstruction::UserSetWithdrawDelegate {
+            user: arb_pubkey(r),
+            delegate: arb_option(r, arb_pubkey),
+        },
+        19 => ContractInstruction::AdminFreezeUser {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        20 => ContractInstruction::AdminUnfreezeUser {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        21 => ContractInstruction::AdminSetBlocked {
+            user: arb_pubkey(r),
+            blocked: r.gen(),
+        },
+        22 => ContractInstruction::AdminSetOperator {
+            operator: arb_option(r, arb_pubkey),
+        },
+        23 => ContractInstruction::AdminProposeNewAdmin {
+            new_admin: arb_pubkey(r),
+        },
+        24 => ContractInstruction::AdminCancelProposedAdmin,
+        25 => ContractInstruction::AcceptAdmin,
+        26 => ContractInstruction::AdminPause {
+            withdrawals_only: r.gen(),
+        },
+        27 => ContractInstruction::AdminUnpause,
+        28 => ContractInstruction::AdminSetTokenFlags {
+            token: arb_token(r),
+            deposits_enabled: r.gen(),
+            withdrawals_enabled: r.gen(),
+        },
+        29 => ContractInstruction::AdminSetMaxTokens {
+            max_tokens: r.gen(),
+        },
+        30 => ContractInstruction::AdminSetDepositCap {
+            token: arb_token(r),
+            cap: arb_option(r, arb_u64),
+        },
+        31 => ContractInstruction::AdminSetMaxUserBalance {
+            token: arb_token(r),
+            max_user_balance: arb_option(r, arb_u64),
+        },
+        32 => ContractInstruction::AdminSetMinDeposit {
+            token: arb_token(r),
+            min_deposit: arb_u64(r),
+        },
+        33 => ContractInstruction::AdminSetDailyWithdrawalLimit {
+            token: arb_token(r),
+            limit: arb_u64(r),
+        },
+        34 => ContractInstruction::BatchDeposit {
+            user: arb_pubkey(r),
+            items: arb_items(r),
+        },
+        35 => ContractInstruction::BatchWithdraw {
+            user: arb_pubkey(r),
+            items: arb_items(r),
+        },
+        36 => ContractInstruction::UserTransfer {
+            token: arb_token(r),
+            from: arb_pubkey(r),
+            to: arb_pubkey(r),
+            amount: arb_u64(r),
+        },
+        37 => ContractInstruction::UserApprove {
+            token: arb_token(r),
+            owner: arb_pubkey(r),
+            spender: arb_pubkey(r),
+            amount: arb_u64(r),
+        },
+        38 => ContractInstruction::TransferFrom {
+            token: arb_token(r),
+            owner: arb_pubkey(r),
+            spender: arb_pubkey(r),
+            to: arb_pubkey(r),
+            amount: arb_u64(r),
+        },
+        39 => ContractInstruction::GetBalance {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        40 => ContractInstruction::LinkEthAddress {
+            user: arb_pubkey(r),
+            signature: arb_bytes(r, 80),
+        },
+        41 => ContractInstruction::GetSequence,
+        42 => ContractInstruction::GetNonce {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        43 => ContractInstruction::ListSupportedTokens {
+            offset: r.gen(),
+            limit: r.gen(),
+        },
+        44 => ContractInstruction::GetTokenStats {
+            token: arb_token(r),
+        },
+        45 => ContractInstruction::AdminUpdateTokenMetadata {
+            token: arb_token(r),
+            metadata: arb_metadata(r),
+        },
+        46 => ContractInstruction::AdminSetDisplayPrecision {
+            token: arb_token(r),
+            display_precision: arb_option(r, |r| r.gen()),
+        },
+        47 => ContractInstruction::AdminSetRewardRate {
+            token: arb_token(r),
+            points_per_day: arb_u64(r),
+        },
+        48 => ContractInstruction::ClaimRewards {
+            user: arb_pubkey(r),
+        },
+        49 => ContractInstruction::GetRewardPoints {
+            user: arb_pubkey(r),
+        },
+        50 => ContractInstruction::AdminDepositVested {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: arb_u64(r),
+            start_ts: r.gen(),
+            duration_secs: arb_u64(r),
+        },
+        51 => ContractInstruction::GetReferralStats {
+            referrer: arb_pubkey(r),
+        },
+        52 => ContractInstruction::AdminSnapshotState,
+        53 => ContractInstruction::GetAuditLog {
+            offset: r.gen(),
+            limit: r.gen(),
+        },
+        54 => ContractInstruction::CloseUserBalance {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        55 => ContractInstruction::AdminEmergencySweep {
+            token: arb_token(r),
+            destination: arb_pubkey(r),
+        },
+        56 => ContractInstruction::AdminRenameToken {
+            old: arb_token(r),
+            new_symbol: arb_symbol(r),
+        },
+        57 => ContractInstruction::AdminSetMerkleRoot {
+            token: arb_token(r),
+            root: r.gen(),
+            total: r.gen(),
+        },
+        58 => ContractInstruction::ClaimAirdrop {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: r.gen(),
+            proof: (0..r.gen_range(0..8)).map(|_| r.gen()).collect(),
+        },
+        59 => ContractInstruction::RedeemVoucher {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: r.gen(),
+            voucher_id: r.gen(),
+            expiry: r.gen(),
+            signature: {
+                let mut signature = [0; 64];
+                r.fill(&mut signature[..]);
+                signature
+            },
+        },
+        60 => ContractInstruction::AdminSetRateLimit {
+            max_ops: r.gen(),
+            window_slots: r.gen(),
+        },
+        61 => ContractInstruction::AdminSetGlobalCap { cap: r.gen() },
+        62 => ContractInstruction::GetGlobalStats,
+        63 => ContractInstruction::AdminSetPriceFeed {
+            token: arb_token(r),
+            feed: arb_pubkey(r),
+            usd_cap: r.gen(),
+            max_age_slots: r.gen(),
+        },
+        64 => ContractInstruction::AdminSetSwapRate {
+            from: arb_token(r),
+            to: arb_token(r),
+            numerator: r.gen(),
+            denominator: r.gen(),
+        },
+        65 => ContractInstruction::UserSwap {
+            from: arb_token(r),
+            to: arb_token(r),
+            user: arb_pubkey(r),
+            amount_in: r.gen(),
+            min_amount_out: r.gen(),
+        },
+        66 => ContractInstruction::AdminEnableShareMode {
+            token: arb_token(r),
+        },
+        67 => ContractInstruction::AdminDonateYield {
+            token: arb_token(r),
+            amount: r.gen(),
+        },
+        68 => ContractInstruction::AdminSlash {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: r.gen(),
+            reason_code: r.gen(),
+        },
+        69 => ContractInstruction::AdminQueueAction {
+            action: Box::new(ContractInstruction::AdminSetDepositFee {
+                token: arb_token(r),
+                fee_bps: r.gen(),
+            }),
+            eta_slot: r.gen(),
+        },
+        70 => ContractInstruction::AdminExecuteAction {
+            action: Box::new(ContractInstruction::AdminForceDeleteToken {
+                token: arb_token(r),
+                confirm: r.gen(),
+            }),
+        },
+        71 => ContractInstruction::AdminSetTimelockDelay {
+            delay_slots: r.gen(),
+        },
+        72 => ContractInstruction::AdminSetCircuitBreaker {
+            token: arb_token(r),
+            threshold_bps: r.gen(),
+            window_slots: r.gen(),
+        },
+        73 => ContractInstruction::AdminResetBreaker {
+            token: arb_token(r),
+        },
+        74 => ContractInstruction::AdminSetDustThreshold {
+            token: arb_token(r),
+            threshold: r.gen(),
+        },
+        75 => ContractInstruction::IsTokenSupported {
+            token: arb_token(r),
+        },
+        76 => ContractInstruction::GetUserStats {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        77 => ContractInstruction::AdminSetWithdrawCooldown {
+            token: arb_token(r),
+            withdraw_cooldown_slots: r.gen(),
+            transfers_reset: r.gen(),
+        },
+        78 => ContractInstruction::AdminSetMinWithdraw {
+            token: arb_token(r),
+            min_withdraw: r.gen(),
+        },
+        79 => ContractInstruction::GetReceipt {
+            user: arb_pubkey(r),
+            receipt_id: r.gen(),
+        },
+        80 => ContractInstruction::UserEnqueueWithdraw {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+            amount: r.gen(),
+        },
+        81 => ContractInstruction::UserCancelQueuedWithdraw {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        82 => ContractInstruction::ProcessWithdrawQueue {
+            token: arb_token(r),
+            max_items: r.gen(),
+        },
+        83 => ContractInstruction::GetQueuePosition {
+            token: arb_token(r),
+            user: arb_pubkey(r),
+        },
+        84 => ContractInstruction::AdminAddSupportedTokens {
+            tokens: (0..r.gen_range(0..4)).map(|_| arb_token(r)).collect(),
+        },
+        // nested calls are encoded like any other, the processor is what rejects them
+        _ => ContractInstruction::Multicall {
+            calls: (0..r.gen_range(0..4)).map(|_| arb_instruction(r)).collect(),
+        },
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let instruction = arb_instruction(&mut rng);
+        let data = instruction.pack();
+        let decoded = deserialize_instruction(&data).unwrap();
+        assert_eq!(decoded, instruction);
+        // tokens compare by mint only, the bytes catch a changed symbol
+        assert_eq!(decoded.pack(), data);
+        let anchor = instruction.pack_anchor();
+        assert_eq!(anchor[..8], instruction.discriminator());
+        assert_eq!(anchor[8..], data[2..]);
+        let decoded = deserialize_instruction(&anchor).unwrap();
+        assert_eq!(decoded, instruction);
+        assert_eq!(decoded.pack_anchor(), anchor);
+        // the legacy encoding still decodes too, as long as it fits in a transaction
+        let mut json = vec![INSTRUCTION_VERSION_JSON];
+        json.extend(serde_json::to_vec(&instruction).unwrap());
+        if json.len() <= MAX_INSTRUCTION_DATA_LEN {
+            assert_eq!(deserialize_instruction(&json).unwrap(), instruction);
+        }
+    }
+}
+
+// the longest symbol among the tokens the instruction names, the ones it adds included
+fn longest_symbol(instruction: &mut ContractInstruction) -> usize {
+    match instruction {
+        ContractInstruction::AdminAddSupportedToken { token, .. } => token.symbol.len(),
+        ContractInstruction::AdminAddSupportedTokens { tokens } => tokens
+            .iter()
+            .map(|token| token.symbol.len())
+            .max()
+            .unwrap_or(0),
+        ContractInstruction::AdminRenameToken { old, new_symbol } => {
+            old.symbol.len().max(new_symbol.len())
+        }
+        ContractInstruction::Multicall { calls } => {
+            calls.iter_mut().map(longest_symbol).max().unwrap_or(0)
+        }
+        _ => instruction
+            .listed_tokens_mut()
+            .iter()
+            .map(|token| token.symbol.len())
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+#[test]
+fn test_compact_round_trip() {
+    let mut rng = rng();
+    let mut tags = HashSet::new();
+    // enough cases for AdminRenameToken, which only fits with two short symbols, to come up
+    for _ in 0..CASES * 8 {
+        let mut instruction = arb_instruction(&mut rng);
+        let Some(data) = instruction.pack_compact() else {
+            // only ever for a symbol too long for the layout
+            assert!(
+                longest_symbol(&mut instruction) > MAX_SYMBOL_LEN,
+                "{:?}",
+                instruction
+            );
+            continue;
+        };
+        assert!(longest_symbol(&mut instruction) <= MAX_SYMBOL_LEN);
+        tags.insert(instruction.tag());
+        let decoded = deserialize_instruction(&data).unwrap();
+        assert_eq!(decoded.pack_compact().as_ref(), Some(&data));
+        // listed tokens come back without their mint, and tokens compare by mint only, so the
+        // borsh bytes are compared to catch a changed symbol
+        for token in instruction.listed_tokens_mut() {
+            token.mint = Pubkey::default();
+        }
+        assert_eq!(
+            borsh::to_vec(&decoded).unwrap(),
+            borsh::to_vec(&instruction).unwrap()
+        );
+    }
+    // every variant made it through
+    assert_eq!(tags.len(), INSTRUCTION_DISCRIMINATORS.len());
+}
+
+#[test]
+fn test_compact_truncated() {
+    // cut at every field boundary and everywhere between, nor does an extra byte decode
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let Some(mut data) = arb_instruction(&mut rng).pack_compact() else {
+            continue;
+        };
+        for len in 0..data.len() {
+            assert_eq!(
+                ContractInstruction::unpack_compact(&data[..len]),
+                Err(ProgramError::InvalidInstructionData),
+                "{:?}",
+                &data[..len]
+            );
+        }
+        data.push(0);
+        assert!(deserialize_instruction(&data).is_err());
+    }
+}
+
+#[test]
+fn test_compact_layout() {
+    let user = Pubkey::new_unique();
+    let deposit = |symbol: &str| ContractInstruction::UserDeposit {
+        token: TokenType {
+            symbol: symbol.to_string(),
+            mint: Pubkey::new_unique(),
+        },
+        user,
+        amount: 150,
+        nonce: 7,
+        lock_until: None,
+        referrer: None,
+        beneficiary: None,
+        memo: None,
+    };
+    let data = deposit("usdc").pack_compact().unwrap();
+    let tag = deposit("usdc").tag();
+    assert_eq!(
+        data,
+        [
+            &[INSTRUCTION_VERSION_COMPACT, tag, 4][..],
+            b"usdc",
+            user.as_ref(),
+            &150u64.to_le_bytes(),
+            &7u64.to_le_bytes(),
+            // no options
+            &[0],
+        ]
+        .concat()
+    );
+    assert_eq!(data.len(), 56);
+    let longest = "x".repeat(MAX_SYMBOL_LEN);
+    assert_eq!(
+        deposit(&longest[1..]).pack_compact().map(|data| data.len()),
+        Some(63)
+    );
+    assert_eq!(deposit(&(longest.clone() + "x")).pack_compact(), None);
+
+    // options follow the flags in order
+    let referrer = Pubkey::new_unique();
+    let with_options = ContractInstruction::UserDeposit {
+        token: TokenType {
+            symbol: "usdc".to_string(),
+            mint: Pubkey::new_unique(),
+        },
+        user,
+        amount: 150,
+        nonce: 7,
+        lock_until: None,
+        referrer: Some(referrer),
+        beneficiary: None,
+        memo: Some("hi".to_string()),
+    };
+    let with_options = with_options.pack_compact().unwrap();
+    assert_eq!(with_options[..55], data[..55]);
+    assert_eq!(
+        with_options[55..],
+        [&[0b1010][..], referrer.as_ref(), &[2], b"hi"].concat()
+    );
+
+    let decodes = |data: &[u8]| deserialize_instruction(data).is_ok();
+    let with = |at: usize, byte: u8| {
+        let mut changed = data.clone();
+        changed[at] = byte;
+        changed
+    };
+    assert!(decodes(&data));
+    // unknown tag
+    assert!(!decodes(&with(1, INSTRUCTION_DISCRIMINATORS.len() as u8)));
+    // a symbol over MAX_SYMBOL_LEN, or not UTF-8
+    let mut overlong = [
+        &[INSTRUCTION_VERSION_COMPACT, tag, 13][..],
+        longest.as_bytes(),
+        b"x",
+    ]
+    .concat();
+    overlong.extend(&data[7..]);
+    assert!(!decodes(&overlong));
+    assert!(!decodes(&with(3, 0xff)));
+    // UserDeposit has 4 options, and a flagged one has to follow
+    assert!(!decodes(&with(55, 1 << 4)));
+    assert!(!decodes(&with(55, 1)));
+    // bools are 0 or 1
+    let pause = ContractInstruction::AdminPause {
+        withdrawals_only: true,
+    };
+    let mut pause = pause.pack_compact().unwrap();
+    assert_eq!(pause.len(), 3);
+    pause[2] = 2;
+    assert!(!decodes(&pause));
+}
+
+#[test]
+fn test_noise_never_panics() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let mut data = arb_bytes(&mut rng, 200);
+        // mostly a known version byte or discriminator, so the payload decoders get exercised
+        if let Some(version) = data.first_mut() {
+            *version = [0, 1, 1, 1, 2, 2, *version][rng.gen_range(0..7)];
+        }
+        if data.len() >= 8 && rng.gen_range(0..4) == 0 {
+            let (_, discriminator) =
+                INSTRUCTION_DISCRIMINATORS[rng.gen_range(0..INSTRUCTION_DISCRIMINATORS.len())];
+            data[..8].copy_from_slice(&discriminator);
+        }
+        // the rare noise that decodes has to be the canonical encoding of what it decoded to
+        if let Ok(instruction) = deserialize_instruction(&data) {
+            match data[0] {
+                INSTRUCTION_VERSION_JSON => {}
+                INSTRUCTION_VERSION_BORSH => assert_eq!(instruction.pack(), data),
+                INSTRUCTION_VERSION_COMPACT => assert_eq!(instruction.pack_compact(), Some(data)),
+                _ => assert_eq!(instruction.pack_anchor(), data),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_discriminators() {
+    // one per variant, AdminAddSupportedTokens is the last one
+    let last = ContractInstruction::AdminAddSupportedTokens { tokens: Vec::new() };
+    assert_eq!(INSTRUCTION_DISCRIMINATORS.len(), last.tag() as usize + 1);
+    let mut names = HashSet::new();
+    let mut discriminators = HashSet::new();
+    for (name, discriminator) in INSTRUCTION_DISCRIMINATORS {
+        let hash = solana_sdk::hash::hash(format!("global:{}", name).as_bytes());
+        assert_eq!(discriminator, hash.to_bytes()[..8], "{}", name);
+        assert!(names.insert(name), "{}", name);
+        assert!(discriminators.insert(discriminator), "{} collides", name);
+        // the first byte alone tells the encodings apart
+        assert!(
+            ![
+                INSTRUCTION_VERSION_JSON,
+                INSTRUCTION_VERSION_BORSH,
+                INSTRUCTION_VERSION_COMPACT
+            ]
+            .contains(&discriminator[0]),
+            "{}",
+            name
+        );
+    }
+    // names are the variants' in snake_case
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let instruction = arb_instruction(&mut rng);
+        let debug = format!("{:?}", instruction);
+        let variant = debug.split([' ', '(']).next().unwrap();
+        let mut snake_case = String::new();
+        for c in variant.chars() {
+            if c.is_uppercase() && !snake_case.is_empty() {
+                snake_case.push('_');
+            }
+            snake_case.push(c.to_ascii_lowercase());
+        }
+        let (name, _) = INSTRUCTION_DISCRIMINATORS[instruction.tag() as usize];
+        assert_eq!(name, snake_case);
+    }
+}
+
+#[test]
+fn test_mutated_instructions_never_panic() {
+    let mut rng = rng();
+    for _ in 0..CASES {
+        let mut data = arb_instruction(&mut rng).pack();
+        match rng.gen_range(0..3) {
+            0 => data.truncate(rng.gen_range(0..data.len())),
+            1 => {
+                let at = rng.gen_range(1..data.len().max(2)).min(data.len() - 1);
+                data[at] = rng.gen();
+            }
+            _ => data.extend(arb_bytes(&mut rng, 8)),
+        }
+        let _ = deserialize_instruction(&data);
+    }
+}
+
+#[test]
+fn test_regression_corpus() {
+    let corpus = include_str!("corpus/instructions.txt");
+    let inputs: Vec<_> = corpus
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert!(!inputs.is_empty());
+    for hex in inputs {
+        let data: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert!(deserialize_instruction(&data).is_err(), "accepted {}", hex);
+    }
+    assert!(deserialize_instruction(&[]).is_err());
+}
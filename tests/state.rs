@@ -0,0 +1,609 @@
+// account layouts: the state's encoding depends only on its contents, never on the order they
+// were inserted in, balance accounts keep their fields at fixed offsets, and the sizes clients
+// compute match what borsh writes
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::mem::{align_of, offset_of, size_of};
+
+use borsh::BorshSerialize;
+use hello_world::{
+    read_receipts, required_account_size, Airdrop, AuditEntry, BalanceAccount, BalanceAccountData,
+    BalanceSlots, CircuitBreaker, ContractState, DepositReceipt, LockedLot, PriceFeed,
+    QueuedAction, QueuedWithdraw, RateBuckets, RateWindow, ReceiptsHeader, RewardPool, SwapRate,
+    TokenEntry, TokenMetadata, TokenType, UserStats, VestingSchedule, WithdrawCooldown,
+    WithdrawRequest, AUDIT_LOG_CAPACITY, BALANCE_ACCOUNT_SIZE, BALANCE_ACCOUNT_TAG,
+    BALANCE_ACCOUNT_V0_SIZE, BALANCE_ACCOUNT_V1_SIZE, MAX_METADATA_URI_LEN, MAX_RECEIPTS_PER_USER,
+    MAX_SYMBOL_LEN, MAX_TOKEN_NAME_LEN, RECEIPTS_ACCOUNT_MAX_SIZE, RECEIPTS_ACCOUNT_TAG,
+    RECEIPTS_HEADER_LEN, STATE_DISCRIMINATOR, STATE_HEADER_LEN,
+};
+use solana_sdk::hash::hash;
+use solana_sdk::program_error::ProgramError;
+use solana_sdk::pubkey::Pubkey;
+
+fn entry() -> TokenEntry {
+    TokenEntry {
+        deposits_enabled: true,
+        withdrawals_enabled: true,
+        deposit_cap: None,
+        total_deposited: 0,
+        max_user_balance: None,
+        min_deposit: 0,
+        daily_withdrawal_limit: 0,
+        withdrawal_windows: BTreeMap::new(),
+        withdrawal_fee_bps: 0,
+        deposit_fee_bps: 0,
+        withdraw_requests: BTreeMap::new(),
+        frozen: BTreeSet::new(),
+        balances: BTreeMap::new(),
+    }
+}
+
+// every token and user of the same state, added in the order given
+fn build(tokens: &[TokenType], users: &[(Pubkey, u64)]) -> ContractState {
+    let mut state = ContractState::default();
+    for token in tokens {
+        let mut entry = entry();
+        for (user, amount) in users {
+            entry.balances.insert(*user, *amount);
+            entry.withdrawal_windows.insert(*user, (0, *amount));
+            if amount % 2 == 0 {
+                entry.frozen.insert(*user);
+            }
+            entry.total_deposited += amount;
+        }
+        state.all_token_balances.insert(token.clone(), entry);
+        state.token_epochs.insert(token.mint, 1);
+    }
+    let referrer = Pubkey::new_from_array([9; 32]);
+    for (user, amount) in users {
+        state.nonces.insert(*user, *amount);
+        state.referrers.insert(*user, Some(referrer));
+        state.fee_exempt.insert(*user);
+        for token in tokens {
+            state
+                .allowances
+                .insert((token.clone(), *user, referrer), *amount);
+        }
+    }
+    state
+}
+
+#[test]
+fn test_state_serialization_is_canonical() {
+    let tokens: Vec<TokenType> = ["usdc", "ray", "bonk", "SOL"]
+        .iter()
+        .map(|symbol| TokenType {
+            symbol: symbol.to_string(),
+            mint: Pubkey::new_unique(),
+        })
+        .collect();
+    let users: Vec<(Pubkey, u64)> = (1..=50).map(|i| (Pubkey::new_unique(), i)).collect();
+    let mut reversed_tokens = tokens.clone();
+    reversed_tokens.reverse();
+    let mut shuffled_users = users.clone();
+    shuffled_users.reverse();
+    shuffled_users.rotate_left(17);
+
+    let forward = build(&tokens, &users);
+    let backward = build(&reversed_tokens, &shuffled_users);
+    assert_eq!(
+        borsh::to_vec(&forward).unwrap(),
+        borsh::to_vec(&backward).unwrap()
+    );
+    // and handlers walking the state see the same order
+    assert!(forward
+        .all_token_balances
+        .keys()
+        .eq(backward.all_token_balances.keys()));
+    assert!(forward.nonces.keys().eq(backward.nonces.keys()));
+
+    // the layout is the one borsh gave the hash maps before, sorted by key, so accounts written
+    // back then decode unchanged
+    let hashed: HashMap<Pubkey, u64> = shuffled_users.iter().copied().collect();
+    assert_eq!(
+        borsh::to_vec(&hashed).unwrap(),
+        borsh::to_vec(&forward.nonces).unwrap()
+    );
+}
+
+#[test]
+fn test_balance_account_layout() {
+    assert_eq!(offset_of!(BalanceAccountData, tag), 0);
+    assert_eq!(offset_of!(BalanceAccountData, mint), 1);
+    assert_eq!(offset_of!(BalanceAccountData, user), 33);
+    assert_eq!(offset_of!(BalanceAccountData, bump), 65);
+    assert_eq!(offset_of!(BalanceAccountData, epoch), 66);
+    assert_eq!(offset_of!(BalanceAccountData, amount), 74);
+    assert_eq!(offset_of!(BalanceAccountData, flags), 82);
+    assert_eq!(offset_of!(BalanceAccountData, last_update_slot), 83);
+    assert_eq!(offset_of!(BalanceAccountData, nonce), 91);
+    assert_eq!(offset_of!(BalanceAccountData, deposit_count), 99);
+    assert_eq!(offset_of!(BalanceAccountData, withdraw_count), 103);
+    assert_eq!(offset_of!(BalanceAccountData, lifetime_deposited), 107);
+    assert_eq!(offset_of!(BalanceAccountData, lifetime_withdrawn), 123);
+    assert_eq!(offset_of!(BalanceAccountData, last_deposit_slot), 139);
+    assert_eq!(offset_of!(BalanceAccountData, last_withdraw_slot), 147);
+    assert_eq!(size_of::<BalanceAccountData>(), BALANCE_ACCOUNT_SIZE);
+    assert_eq!(BALANCE_ACCOUNT_SIZE, 155);
+    assert_eq!(
+        BALANCE_ACCOUNT_V0_SIZE,
+        offset_of!(BalanceAccountData, flags)
+    );
+    assert_eq!(
+        BALANCE_ACCOUNT_V1_SIZE,
+        offset_of!(BalanceAccountData, deposit_count)
+    );
+    assert_eq!(align_of::<BalanceAccountData>(), 1);
+
+    // the bytes borsh wrote, so accounts written before read in place
+    let balance = BalanceAccount {
+        mint: Pubkey::new_unique(),
+        user: Pubkey::new_unique(),
+        bump: 254,
+        epoch: 3,
+        amount: 0x0102_0304_0506_0708,
+        flags: 0,
+        last_update_slot: 11,
+        nonce: 12,
+        stats: UserStats {
+            deposit_count: 1,
+            withdraw_count: 2,
+            lifetime_deposited: 3,
+            lifetime_withdrawn: 4,
+        },
+        slots: BalanceSlots {
+            last_deposit_slot: 5,
+            last_withdraw_slot: 6,
+        },
+    };
+    let mut data = vec![BALANCE_ACCOUNT_TAG];
+    balance.serialize(&mut data).unwrap();
+    assert_eq!(
+        bytemuck::bytes_of(&BalanceAccountData::from(&balance)),
+        &data[..]
+    );
+    let loaded = BalanceAccountData::load(&data).unwrap();
+    assert_eq!(BalanceAccount::from(loaded), balance);
+    assert_eq!(loaded.amount(), 0x0102_0304_0506_0708);
+    assert_eq!(loaded.epoch(), 3);
+    assert_eq!(loaded.last_update_slot(), 11);
+    assert_eq!(loaded.nonce(), 12);
+
+    // a change touches its own bytes only, at any alignment of the data
+    let mut unaligned = [&[0][..], &data].concat();
+    let stored = BalanceAccountData::load_mut(&mut unaligned[1..]).unwrap();
+    stored.set_amount(9);
+    stored.set_epoch(4);
+    assert_eq!(unaligned[1..67], data[..66]);
+    assert_eq!(unaligned[67..75], 4u64.to_le_bytes());
+    assert_eq!(unaligned[75..83], 9u64.to_le_bytes());
+    assert_eq!(unaligned[83..], data[82..]);
+    let stored = BalanceAccountData::load_mut(&mut unaligned[1..]).unwrap();
+    stored.set_last_update_slot(13);
+    stored.set_nonce(14);
+    assert_eq!(unaligned[83], 0);
+    assert_eq!(unaligned[84..92], 13u64.to_le_bytes());
+    assert_eq!(unaligned[92..100], 14u64.to_le_bytes());
+    assert_eq!(unaligned[100..], data[99..]);
+    let stored = BalanceAccountData::load_mut(&mut unaligned[1..]).unwrap();
+    stored.set_stats(&UserStats {
+        deposit_count: 7,
+        ..balance.stats
+    });
+    stored.set_slots(&BalanceSlots {
+        last_withdraw_slot: 8,
+        ..balance.slots
+    });
+    assert_eq!(unaligned[100..104], 7u32.to_le_bytes());
+    assert_eq!(unaligned[104..148], data[103..147]);
+    assert_eq!(unaligned[148..], 8u64.to_le_bytes());
+
+    // an account of the layout before flags, last_update_slot and nonce reads them as 0, but
+    // only through read, which copies it
+    let legacy = &data[..BALANCE_ACCOUNT_V0_SIZE];
+    assert_eq!(
+        BalanceAccountData::load(legacy),
+        Err(ProgramError::InvalidAccountData)
+    );
+    let read = BalanceAccountData::read(legacy).unwrap();
+    assert_eq!(
+        BalanceAccount::from(&read),
+        BalanceAccount {
+            last_update_slot: 0,
+            nonce: 0,
+            stats: UserStats::default(),
+            slots: BalanceSlots::default(),
+            ..BalanceAccount::from(loaded)
+        }
+    );
+    // and one of the layout before stats and slots reads those as 0
+    let v1 = &data[..BALANCE_ACCOUNT_V1_SIZE];
+    assert_eq!(
+        BalanceAccount::from(&BalanceAccountData::read(v1).unwrap()),
+        BalanceAccount {
+            stats: UserStats::default(),
+            slots: BalanceSlots::default(),
+            ..BalanceAccount::from(loaded)
+        }
+    );
+    assert_eq!(BalanceAccountData::read(&data).unwrap(), *loaded);
+    assert_eq!(
+        BalanceAccountData::read(&data[..BALANCE_ACCOUNT_V0_SIZE - 1]),
+        Err(ProgramError::InvalidAccountData)
+    );
+
+    // the nonce a user is at, a missing or legacy account starts past the legacy per-user nonce
+    // and the nonce of every closed account
+    let mut state = ContractState {
+        nonce_floor: 4,
+        ..ContractState::default()
+    };
+    assert_eq!(state.balance_nonce(&balance.user, &data), 12);
+    assert_eq!(state.balance_nonce(&balance.user, v1), 12);
+    assert_eq!(state.balance_nonce(&balance.user, legacy), 4);
+    assert_eq!(state.balance_nonce(&balance.user, &[]), 4);
+    state.nonces.insert(balance.user, 6);
+    assert_eq!(state.balance_nonce(&balance.user, legacy), 6);
+    assert_eq!(state.first_nonce(&Pubkey::new_unique()), 4);
+
+    // larger accounts are read from the start, shorter or untagged ones not at all
+    data.extend([0; 8]);
+    assert_eq!(
+        BalanceAccountData::load(&data).unwrap().amount(),
+        balance.amount
+    );
+    assert_eq!(
+        BalanceAccountData::load(&data[..BALANCE_ACCOUNT_SIZE - 1]),
+        Err(ProgramError::InvalidAccountData)
+    );
+    data[0] = 0;
+    assert_eq!(
+        BalanceAccountData::load(&data),
+        Err(ProgramError::InvalidAccountData)
+    );
+}
+
+#[test]
+fn test_receipts_account_layout() {
+    assert_eq!(offset_of!(ReceiptsHeader, tag), 0);
+    assert_eq!(offset_of!(ReceiptsHeader, user), 1);
+    assert_eq!(offset_of!(ReceiptsHeader, bump), 33);
+    assert_eq!(offset_of!(ReceiptsHeader, next), 34);
+    assert_eq!(RECEIPTS_HEADER_LEN, 36);
+    assert_eq!(RECEIPTS_ACCOUNT_MAX_SIZE, 36 + 256 * 88);
+
+    let user = Pubkey::new_unique();
+    let receipt = |receipt_id: u64| DepositReceipt {
+        receipt_id,
+        mint: Pubkey::new_unique(),
+        user,
+        amount: 1,
+        slot: 2,
+    };
+    let account = |next: u16, receipts: &[DepositReceipt]| {
+        let mut data = vec![RECEIPTS_ACCOUNT_TAG];
+        data.extend(user.as_ref());
+        data.push(255);
+        data.extend(next.to_le_bytes());
+        for receipt in receipts {
+            receipt.serialize(&mut data).unwrap();
+        }
+        data
+    };
+
+    // filled from the start until full, then the oldest is at next
+    let receipts: Vec<_> = (0..3).map(receipt).collect();
+    assert_eq!(read_receipts(&account(3, &receipts)), Ok(receipts));
+    let mut full: Vec<_> = (0..MAX_RECEIPTS_PER_USER as u64).map(receipt).collect();
+    assert_eq!(read_receipts(&account(0, &full)), Ok(full.clone()));
+    full[0] = receipt(MAX_RECEIPTS_PER_USER as u64);
+    full[1] = receipt(MAX_RECEIPTS_PER_USER as u64 + 1);
+    let read = read_receipts(&account(2, &full)).unwrap();
+    assert_eq!(read[0].receipt_id, 2);
+    assert_eq!(read[MAX_RECEIPTS_PER_USER - 1].receipt_id, 257);
+
+    let mut data = account(0, &[]);
+    assert_eq!(read_receipts(&data), Ok(Vec::new()));
+    assert_eq!(
+        read_receipts(&data[..RECEIPTS_HEADER_LEN - 1]),
+        Err(ProgramError::InvalidAccountData)
+    );
+    data[0] = 0;
+    assert_eq!(read_receipts(&data), Err(ProgramError::InvalidAccountData));
+}
+
+fn borsh_len<T: BorshSerialize>(value: &T) -> usize {
+    borsh::to_vec(value).unwrap().len()
+}
+
+fn longest_metadata() -> TokenMetadata {
+    TokenMetadata {
+        name: "n".repeat(MAX_TOKEN_NAME_LEN),
+        decimals: 9,
+        metadata_uri: Some("u".repeat(MAX_METADATA_URI_LEN)),
+    }
+}
+
+// admin, pending_admin, operator and treasury set, nothing else
+fn state_with_keys() -> ContractState {
+    ContractState {
+        admin: Some(Pubkey::new_unique()),
+        pending_admin: Some(Pubkey::new_unique()),
+        operator: Some(Pubkey::new_unique()),
+        treasury: Some(Pubkey::new_unique()),
+        ..ContractState::default()
+    }
+}
+
+fn audit_entry(sequence: u64) -> AuditEntry {
+    AuditEntry {
+        sequence,
+        instruction_tag: 5,
+        actor: Pubkey::new_unique(),
+        token_symbol_hash: 7,
+        amount: 9,
+        slot: 11,
+    }
+}
+
+#[test]
+fn test_serialized_sizes() {
+    let request = WithdrawRequest {
+        amount: 1,
+        requested_slot: 2,
+        requested_at: 3,
+    };
+    let pool = RewardPool {
+        points_per_day: 1,
+        index: 2,
+        updated_at: 3,
+    };
+    let lot = LockedLot {
+        amount: 1,
+        unlock_at: 2,
+    };
+    let schedule = VestingSchedule {
+        amount: 1,
+        start_ts: 2,
+        duration_secs: 3,
+    };
+    let balance = BalanceAccount {
+        mint: Pubkey::new_unique(),
+        user: Pubkey::new_unique(),
+        bump: 1,
+        epoch: 2,
+        amount: 3,
+        flags: 0,
+        last_update_slot: 4,
+        nonce: 5,
+        stats: UserStats::default(),
+        slots: BalanceSlots::default(),
+    };
+    assert_eq!(borsh_len(&request), WithdrawRequest::LEN);
+    assert_eq!(borsh_len(&pool), RewardPool::LEN);
+    assert_eq!(borsh_len(&lot), LockedLot::LEN);
+    assert_eq!(borsh_len(&schedule), VestingSchedule::LEN);
+    assert_eq!(borsh_len(&audit_entry(1)), AuditEntry::LEN);
+    assert_eq!(borsh_len(&balance), BalanceAccount::LEN);
+    assert_eq!(BALANCE_ACCOUNT_SIZE, 1 + BalanceAccount::LEN);
+
+    let longest = TokenType {
+        symbol: "S".repeat(MAX_SYMBOL_LEN),
+        mint: Pubkey::new_unique(),
+    };
+    assert_eq!(borsh_len(&longest), TokenType::MAX_LEN);
+    for token in [longest, TokenType::native()] {
+        assert_eq!(token.serialized_size(), borsh_len(&token));
+    }
+    assert_eq!(borsh_len(&longest_metadata()), TokenMetadata::MAX_LEN);
+    for metadata in [longest_metadata(), TokenMetadata::default()] {
+        assert_eq!(metadata.serialized_size(), borsh_len(&metadata));
+    }
+
+    let mut token_entry = entry();
+    assert_eq!(token_entry.serialized_size(), borsh_len(&token_entry));
+    token_entry.deposit_cap = Some(1);
+    token_entry.max_user_balance = Some(2);
+    assert_eq!(borsh_len(&token_entry), TokenEntry::BASE_LEN);
+    let user = Pubkey::new_unique();
+    token_entry.withdrawal_windows.insert(user, (1, 2));
+    token_entry.withdraw_requests.insert(user, request);
+    token_entry.frozen.insert(user);
+    token_entry.balances.insert(user, 3);
+    token_entry.max_user_balance = None;
+    assert_eq!(token_entry.serialized_size(), borsh_len(&token_entry));
+
+    let state = ContractState::default();
+    assert_eq!(state.serialized_size(), borsh_len(&state));
+    assert_eq!(borsh_len(&state_with_keys()), ContractState::BASE_LEN);
+    assert_eq!(
+        STATE_DISCRIMINATOR,
+        hash(b"account:ContractState").to_bytes()[..8]
+    );
+
+    // every collection filled in, a field added without its size fails here
+    let tokens: Vec<TokenType> = ["usdc", "ray"]
+        .iter()
+        .map(|symbol| TokenType {
+            symbol: symbol.to_string(),
+            mint: Pubkey::new_unique(),
+        })
+        .collect();
+    let users: Vec<(Pubkey, u64)> = (1..=5).map(|i| (Pubkey::new_unique(), i)).collect();
+    let mut state = build(&tokens, &users);
+    state.pending_admin = Some(Pubkey::new_unique());
+    let (mint, user) = (tokens[0].mint, users[0].0);
+    state.withdraw_delegates.insert(user, Pubkey::new_unique());
+    state.blocked.insert(user);
+    state.eth_links.insert([1; 20], user);
+    state.holder_counts.insert(mint, 5);
+    state.token_metadata.insert(mint, longest_metadata());
+    state
+        .token_metadata
+        .insert(tokens[1].mint, TokenMetadata::default());
+    state.display_precisions.insert(mint, 2);
+    state.reward_pools.insert(mint, pool);
+    state.reward_points.insert(user, 4);
+    state.reward_indexes.insert((mint, user), 5);
+    state
+        .locked_lots
+        .insert((mint, user), vec![lot.clone(), lot]);
+    state.vesting_schedules.insert((mint, user), vec![schedule]);
+    state.referrers.insert(users[1].0, None);
+    state.referral_volume.insert(user, 6);
+    state.audit_log.extend((1..=3).map(audit_entry));
+    let airdrop = Airdrop {
+        root: [1; 32],
+        total: 9,
+        claimed: 4,
+        claimed_leaves: BTreeSet::new(),
+    };
+    assert_eq!(borsh_len(&airdrop), Airdrop::BASE_LEN);
+    state.airdrops.insert(
+        mint,
+        Airdrop {
+            claimed_leaves: BTreeSet::from([[2; 32], [3; 32]]),
+            ..airdrop
+        },
+    );
+    state.redeemed_vouchers.insert(7, 8);
+    let window = RateWindow {
+        start_slot: 9,
+        count: 10,
+    };
+    assert_eq!(borsh_len(&window), RateWindow::LEN);
+    state.rate_windows.insert(user, window);
+    let buckets = RateBuckets {
+        bucket_start: 9,
+        count: 10,
+        previous_count: 11,
+    };
+    assert_eq!(borsh_len(&buckets), RateBuckets::LEN);
+    state.rate_buckets.insert(user, buckets);
+    let feed = PriceFeed {
+        feed: Pubkey::new_unique(),
+        usd_cap: 11,
+        max_age_slots: 12,
+    };
+    assert_eq!(borsh_len(&feed), PriceFeed::LEN);
+    state.price_feeds.insert(mint, feed);
+    let rate = SwapRate {
+        numerator: 13,
+        denominator: 14,
+    };
+    assert_eq!(borsh_len(&rate), SwapRate::LEN);
+    state.swap_rates.insert((mint, user), rate);
+    state.share_supplies.insert(mint, 15);
+    let action = QueuedAction {
+        queued_slot: 16,
+        eta_slot: 17,
+    };
+    assert_eq!(borsh_len(&action), QueuedAction::LEN);
+    state.queued_actions.insert([18; 32], action);
+    let breaker = CircuitBreaker {
+        threshold_bps: 19,
+        window_slots: 20,
+        window_start_slot: 21,
+        withdrawn: 22,
+        tripped: true,
+    };
+    assert_eq!(borsh_len(&breaker), CircuitBreaker::LEN);
+    state.circuit_breakers.insert(mint, breaker);
+    state.dust_thresholds.insert(mint, 23);
+    let stats = UserStats {
+        deposit_count: 24,
+        withdraw_count: 25,
+        lifetime_deposited: 26,
+        lifetime_withdrawn: 27,
+    };
+    assert_eq!(borsh_len(&stats), UserStats::LEN);
+    state.user_stats.insert((mint, user), stats);
+    let slots = BalanceSlots {
+        last_deposit_slot: 28,
+        last_withdraw_slot: 29,
+    };
+    assert_eq!(borsh_len(&slots), BalanceSlots::LEN);
+    state.balance_slots.insert((mint, user), slots);
+    let cooldown = WithdrawCooldown {
+        withdraw_cooldown_slots: 30,
+        transfers_reset: true,
+    };
+    assert_eq!(borsh_len(&cooldown), WithdrawCooldown::LEN);
+    state.withdraw_cooldowns.insert(mint, cooldown);
+    state.min_withdrawals.insert(mint, 31);
+    state.next_receipt_id = 32;
+    let receipt = DepositReceipt {
+        receipt_id: 33,
+        mint,
+        user,
+        amount: 34,
+        slot: 35,
+    };
+    assert_eq!(borsh_len(&receipt), DepositReceipt::LEN);
+    state.receipts.insert(user, vec![receipt; 2]);
+    let queued = QueuedWithdraw {
+        user,
+        amount: 36,
+        queued_slot: 37,
+    };
+    assert_eq!(borsh_len(&queued), QueuedWithdraw::LEN);
+    state
+        .withdraw_queues
+        .insert(mint, VecDeque::from([queued, queued]));
+    state
+        .all_token_balances
+        .get_mut(&tokens[1])
+        .unwrap()
+        .withdraw_requests
+        .insert(
+            user,
+            WithdrawRequest {
+                amount: 1,
+                requested_slot: 2,
+                requested_at: 3,
+            },
+        );
+    assert_eq!(state.serialized_size(), borsh_len(&state));
+}
+
+#[test]
+fn test_required_account_size() {
+    // exactly the state of the tokens and users it assumes
+    for (max_tokens, users) in [(0, 0), (1, 0), (0, 1), (5, 40)] {
+        let mut state = state_with_keys();
+        for _ in 0..max_tokens {
+            let token = TokenType {
+                symbol: "S".repeat(MAX_SYMBOL_LEN),
+                mint: Pubkey::new_unique(),
+            };
+            let mut entry = entry();
+            entry.deposit_cap = Some(1);
+            entry.max_user_balance = Some(1);
+            state.token_epochs.insert(token.mint, 1);
+            state.holder_counts.insert(token.mint, 1);
+            state.token_metadata.insert(token.mint, longest_metadata());
+            state.display_precisions.insert(token.mint, 1);
+            state.reward_pools.insert(
+                token.mint,
+                RewardPool {
+                    points_per_day: 1,
+                    index: 1,
+                    updated_at: 1,
+                },
+            );
+            state.all_token_balances.insert(token, entry);
+        }
+        for _ in 0..users {
+            let user = Pubkey::new_unique();
+            state.referrers.insert(user, Some(Pubkey::new_unique()));
+            state.referral_volume.insert(user, 1);
+            state.reward_points.insert(user, 1);
+        }
+        state
+            .audit_log
+            .extend((0..AUDIT_LOG_CAPACITY as u64).map(audit_entry));
+        assert_eq!(
+            STATE_HEADER_LEN + borsh_len(&state),
+            required_account_size(max_tokens, users)
+        );
+    }
+}
@@ -0,0 +1,255 @@
+// the vault's SPL balance against the internal ledger
+// every instruction comes from the client builders, so their account lists are checked too
+use borsh::BorshDeserialize;
+use hello_world::{
+    client::{
+        add_supported_token_ix, deposit_ix, execute_action_ix, get_balance_ix, queue_action_ix,
+        set_treasury_ix, set_withdrawal_fee_ix, transfer_ix, with_treasury_balance,
+        withdraw_all_ix, withdraw_ix, withdraw_treasury_ix,
+    },
+    TokenType, UserBalance, DEFAULT_TIMELOCK_DELAY_SLOTS,
+};
+use solana_program_test::{tokio, BanksClient};
+use solana_sdk::{
+    clock::Clock,
+    hash::Hash,
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+
+mod common;
+use common::{create_mint_and_vault, create_user, mint_metadata, send, setup_with_context};
+
+async fn token_amount(banks_client: &mut BanksClient, token_account: Pubkey) -> u64 {
+    let account = banks_client
+        .get_account(token_account)
+        .await
+        .unwrap()
+        .unwrap();
+    spl_token::state::Account::unpack(&account.data)
+        .unwrap()
+        .amount
+}
+
+// GetBalance is read-only, simulating it is enough to get the return data
+async fn ledger_balance(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    program_id: &Pubkey,
+    token: &TokenType,
+    user: &Pubkey,
+    recent_blockhash: Hash,
+) -> u64 {
+    let transaction = Transaction::new_signed_with_payer(
+        &[get_balance_ix(program_id, token, user)],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    );
+    let simulation = banks_client
+        .simulate_transaction(transaction)
+        .await
+        .unwrap();
+    simulation.result.unwrap().unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    let balance = UserBalance::try_from_slice(&return_data.data).unwrap();
+    balance.liquid + balance.locked
+}
+
+#[tokio::test]
+async fn test_vault_matches_ledger() {
+    let (program_id, _, mut context) = setup_with_context().await;
+    let mut banks_client = context.banks_client.clone();
+    let payer = context.payer.insecure_clone();
+    let recent_blockhash = context.last_blockhash;
+    let (mint, vault) =
+        create_mint_and_vault(&mut banks_client, &payer, &program_id, recent_blockhash).await;
+    let (alice, alice_token_account) = create_user(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        1_000,
+        recent_blockhash,
+    )
+    .await;
+    let (bob, bob_token_account) = create_user(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        1_000,
+        recent_blockhash,
+    )
+    .await;
+    let admin_token_account = get_associated_token_address(&payer.pubkey(), &mint.pubkey());
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: mint.pubkey(),
+    };
+    // the admin keeps the withdrawal fees, 5%, set once the timelock has passed
+    let set_fee = set_withdrawal_fee_ix(&program_id, &payer.pubkey(), &token, 500);
+    let clock: Clock = banks_client.get_sysvar().await.unwrap();
+    let eta_slot = clock.slot + DEFAULT_TIMELOCK_DELAY_SLOTS;
+    send(
+        &mut banks_client,
+        &[
+            create_associated_token_account(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &mint.pubkey(),
+                &spl_token::id(),
+            ),
+            add_supported_token_ix(&program_id, &payer.pubkey(), &token, &mint_metadata()),
+            set_treasury_ix(&program_id, &payer.pubkey(), &payer.pubkey()),
+            queue_action_ix(&program_id, &payer.pubkey(), &set_fee, eta_slot),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+    context.warp_to_slot(eta_slot).unwrap();
+    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    send(
+        &mut banks_client,
+        &[execute_action_ix(&program_id, set_fee)],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await;
+
+    let treasury = payer.pubkey();
+    let steps: Vec<(Instruction, &Keypair, [u64; 3])> = vec![
+        (
+            deposit_ix(
+                &program_id,
+                &token,
+                &alice.pubkey(),
+                &alice_token_account,
+                600,
+                0,
+                None,
+                None,
+                None,
+                None,
+            ),
+            &alice,
+            [600, 0, 0],
+        ),
+        (
+            deposit_ix(
+                &program_id,
+                &token,
+                &bob.pubkey(),
+                &bob_token_account,
+                300,
+                0,
+                None,
+                None,
+                None,
+                None,
+            ),
+            &bob,
+            [600, 300, 0],
+        ),
+        (
+            with_treasury_balance(
+                withdraw_ix(
+                    &program_id,
+                    &token,
+                    &alice.pubkey(),
+                    &alice.pubkey(),
+                    &alice_token_account,
+                    100,
+                    1,
+                    None,
+                    None,
+                    None,
+                ),
+                &mint.pubkey(),
+                &treasury,
+            ),
+            &alice,
+            [500, 300, 5],
+        ),
+        (
+            transfer_ix(&program_id, &token, &alice.pubkey(), &bob.pubkey(), 200),
+            &alice,
+            [300, 500, 5],
+        ),
+        (
+            with_treasury_balance(
+                withdraw_all_ix(
+                    &program_id,
+                    &token,
+                    &bob.pubkey(),
+                    &bob.pubkey(),
+                    &bob_token_account,
+                ),
+                &mint.pubkey(),
+                &treasury,
+            ),
+            &bob,
+            [300, 0, 30],
+        ),
+        (
+            withdraw_treasury_ix(
+                &program_id,
+                &payer.pubkey(),
+                &admin_token_account,
+                &token,
+                &treasury,
+                30,
+            ),
+            &payer,
+            [300, 0, 0],
+        ),
+    ];
+
+    for (instruction, signer, expected) in steps {
+        send(
+            &mut banks_client,
+            &[instruction],
+            &[&payer, signer],
+            recent_blockhash,
+        )
+        .await;
+        let mut ledger = [0; 3];
+        for (balance, user) in ledger
+            .iter_mut()
+            .zip([alice.pubkey(), bob.pubkey(), treasury])
+        {
+            *balance = ledger_balance(
+                &mut banks_client,
+                &payer,
+                &program_id,
+                &token,
+                &user,
+                recent_blockhash,
+            )
+            .await;
+        }
+        assert_eq!(ledger, expected);
+        assert_eq!(
+            token_amount(&mut banks_client, vault).await,
+            ledger.iter().sum::<u64>()
+        );
+    }
+    // everything paid out left the vault for the owners' token accounts
+    assert_eq!(
+        token_amount(&mut banks_client, alice_token_account).await,
+        495
+    );
+    assert_eq!(
+        token_amount(&mut banks_client, bob_token_account).await,
+        1_175
+    );
+    assert_eq!(
+        token_amount(&mut banks_client, admin_token_account).await,
+        30
+    );
+}
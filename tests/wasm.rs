@@ -0,0 +1,85 @@
+// the wasm wrappers produce the native builders' instructions, run natively since the wrappers
+// don't call into JS on success, JsError has no Debug so results are unwrapped through ok()
+use hello_world::client::{deposit_ix, withdraw_ix};
+use hello_world::wasm::{build_deposit_instruction, build_withdraw_instruction, WasmInstruction};
+use hello_world::TokenType;
+use solana_sdk::pubkey::Pubkey;
+use spl_associated_token_account::get_associated_token_address;
+
+#[test]
+fn test_wasm_builders_match_native() {
+    let program_id = Pubkey::new_unique();
+    let user = Pubkey::new_unique();
+    let token = TokenType {
+        symbol: "usdc".to_string(),
+        mint: Pubkey::new_unique(),
+    };
+    let ata = get_associated_token_address(&user, &token.mint);
+    let native = TokenType::native();
+    let other = Pubkey::new_unique();
+    let (id, mint, user_arg) = (
+        program_id.to_string(),
+        token.mint.to_string(),
+        user.to_string(),
+    );
+
+    let deposit = build_deposit_instruction(&id, "usdc", &mint, &user_arg, 150, 3, None)
+        .ok()
+        .unwrap();
+    assert_eq!(
+        deposit,
+        WasmInstruction::from(deposit_ix(
+            &program_id,
+            &token,
+            &user,
+            &ata,
+            150,
+            3,
+            None,
+            None,
+            None,
+            None
+        ))
+    );
+    let native_mint = native.mint.to_string();
+    let deposit = build_deposit_instruction(&id, "SOL", &native_mint, &user_arg, u64::MAX, 0, None)
+        .ok()
+        .unwrap();
+    assert_eq!(
+        deposit,
+        WasmInstruction::from(deposit_ix(
+            &program_id,
+            &native,
+            &user,
+            &user,
+            u64::MAX,
+            0,
+            None,
+            None,
+            None,
+            None
+        ))
+    );
+
+    let withdraw =
+        build_withdraw_instruction(&id, "usdc", &mint, &user_arg, 5, 4, Some(other.to_string()))
+            .ok()
+            .unwrap();
+    let expected = withdraw_ix(
+        &program_id,
+        &token,
+        &user,
+        &user,
+        &other,
+        5,
+        4,
+        None,
+        None,
+        None,
+    );
+    assert_eq!(withdraw.data, expected.data);
+    assert_eq!(withdraw.program_id, id);
+    assert_eq!(withdraw, WasmInstruction::from(expected));
+    assert!(withdraw.accounts[1].is_signer);
+    assert_eq!(withdraw.accounts[1].pubkey, user_arg);
+}